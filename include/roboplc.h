@@ -0,0 +1,136 @@
+#ifndef ROBOPLC_H
+#define ROBOPLC_H
+
+/* Generated with cbindgen, do not edit manually */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * The operation is successful
+ */
+#define ROBOPLC_OK 0
+
+/**
+ * The bridge is not installed
+ */
+#define ROBOPLC_ERR_NOT_INSTALLED -1
+
+/**
+ * Invalid arguments or unregistered message kind
+ */
+#define ROBOPLC_ERR_INVALID -2
+
+/**
+ * The channel is closed
+ */
+#define ROBOPLC_ERR_CLOSED -3
+
+/**
+ * The channel is empty
+ */
+#define ROBOPLC_ERR_EMPTY -4
+
+/**
+ * The buffer is too small for the message
+ */
+#define ROBOPLC_ERR_BUF -5
+
+/**
+ * All other errors
+ */
+#define ROBOPLC_ERR_FAILED -99
+
+/**
+ * Opaque hub client handle
+ */
+typedef struct RoboplcClient RoboplcClient;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Sends a message to the hub
+ *
+ * # Safety
+ *
+ * `data` MUST point to a valid memory block of `len` bytes
+ */
+int32_t roboplc_hub_send(uint32_t kind, const void *data, size_t len);
+
+/**
+ * Registers a hub client which receives messages of the given kinds. If the capacity is zero,
+ * the hub default is used. Returns NULL on errors
+ *
+ * # Safety
+ *
+ * `name` MUST be a valid NUL-terminated string, `kinds` MUST point to `kinds_len` values
+ */
+struct RoboplcClient *roboplc_hub_register(const char *name,
+                                           const uint32_t *kinds,
+                                           size_t kinds_len,
+                                           size_t capacity);
+
+/**
+ * Receives a message, blocks until a message is available
+ *
+ * # Safety
+ *
+ * `client` MUST be a valid client handle, `buf` MUST point to a writable memory block of
+ * `buf_len` bytes, `kind` and `len` MUST be valid pointers
+ */
+int32_t roboplc_client_recv(const struct RoboplcClient *client,
+                            uint32_t *kind,
+                            void *buf,
+                            size_t buf_len,
+                            size_t *len);
+
+/**
+ * Receives a message if available, returns `ROBOPLC_ERR_EMPTY` otherwise
+ *
+ * # Safety
+ *
+ * The same as for [`roboplc_client_recv()`]
+ */
+int32_t roboplc_client_try_recv(const struct RoboplcClient *client,
+                                uint32_t *kind,
+                                void *buf,
+                                size_t buf_len,
+                                size_t *len);
+
+/**
+ * Unregisters and frees a hub client
+ *
+ * # Safety
+ *
+ * `client` MUST be a valid client handle or NULL and MUST NOT be used after the call
+ */
+void roboplc_client_free(struct RoboplcClient *client);
+
+/**
+ * Returns the registered message size or a negative error code
+ */
+intptr_t roboplc_message_size(uint32_t kind);
+
+/**
+ * Returns the controller state (see `ControllerStateKind`), -128 if the bridge is not installed
+ */
+int8_t roboplc_controller_state(void);
+
+/**
+ * Returns true if the controller is online
+ */
+bool roboplc_controller_is_online(void);
+
+/**
+ * Returns the controller operation mode (0 - normal, 1 - degraded, 2 - maintenance)
+ */
+uint8_t roboplc_controller_operation_mode(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* ROBOPLC_H */
@@ -2,6 +2,8 @@
 
 use clap::Parser;
 
+use roboplc_client::Slot;
+
 #[derive(Parser)]
 #[clap(author = "Bohemia Automation (https://bma.ai)",
     version = env!("CARGO_PKG_VERSION"),
@@ -44,8 +46,29 @@ pub enum SubCommand {
     Restart,
     #[clap(name = "flash", about = "Flash program")]
     Flash(FlashCommand),
+    #[clap(
+        name = "promote",
+        about = "Activate the program flashed into the staging slot"
+    )]
+    Promote(PromoteCommand),
     #[clap(name = "purge", about = "Purge program data directory")]
     Purge,
+    #[clap(name = "doctor", about = "Check remote real-time environment")]
+    Doctor,
+    #[clap(name = "bundle", about = "Offline bundles for air-gapped sites")]
+    Bundle(BundleCommand),
+    #[clap(
+        name = "import-tags",
+        about = "Generate Rust structures from a classic PLC tag list"
+    )]
+    ImportTags(ImportTagsCommand),
+    #[clap(
+        name = "install",
+        about = "Install the program on a self-managed host (no manager) over SSH"
+    )]
+    Install(InstallCommand),
+    #[clap(name = "snapshot-diff", about = "Compare two program state snapshots")]
+    SnapshotDiff(SnapshotDiffCommand),
 }
 
 #[derive(Parser)]
@@ -54,6 +77,11 @@ pub struct NewCommand {
     pub name: String,
     #[clap(long, help = "RoboPLC crate features")]
     pub features: Vec<String>,
+    #[clap(
+        long,
+        help = "Create a standalone project, ignore the cargo workspace of the current directory"
+    )]
+    pub standalone: bool,
     #[clap(last(true), help = "extra cargo arguments")]
     pub extras: Vec<String>,
 }
@@ -76,4 +104,167 @@ pub struct FlashCommand {
     pub force: bool,
     #[clap(short = 'r', long, help = "Put remote in RUN mode after flashing")]
     pub run: bool,
+    #[clap(
+        long,
+        value_enum,
+        help = "Flash into a staging slot, activate the program with `promote`"
+    )]
+    pub slot: Option<Slot>,
+    #[clap(long, help = "Skip pre-flash checks, configured in robo.toml")]
+    pub skip_checks: bool,
+    #[clap(
+        short = 'e',
+        long = "env",
+        help = "Program environment variable (KEY=VALUE), overrides robo.toml"
+    )]
+    pub env: Vec<String>,
+    #[clap(
+        last(true),
+        help = "Program arguments, override robo.toml [remote.<name>.exec]"
+    )]
+    pub program_args: Vec<String>,
+}
+
+#[derive(Parser)]
+pub struct PromoteCommand {
+    #[clap(
+        long,
+        default_value = "30",
+        help = "Seconds to wait for the program to reach RUNNING state"
+    )]
+    pub wait: u64,
+    #[clap(long, help = "Do not roll back if the program fails to start")]
+    pub no_rollback: bool,
+}
+
+#[derive(Parser)]
+pub struct BundleCommand {
+    #[clap(subcommand)]
+    pub subcmd: BundleSubCommand,
+}
+
+#[derive(Parser)]
+pub enum BundleSubCommand {
+    #[clap(name = "create", about = "Compile the program and create a bundle")]
+    Create(BundleCreateCommand),
+    #[clap(name = "flash", about = "Verify and flash a bundle")]
+    Flash(BundleFlashCommand),
+}
+
+#[derive(Parser)]
+pub struct BundleCreateCommand {
+    #[clap(long, env = "CARGO", help = "cargo/cross binary path")]
+    pub cargo: Option<PathBuf>,
+    #[clap(long, help = "Override remote cargo target")]
+    pub cargo_target: Option<String>,
+    #[clap(long, help = "Extra cargo arguments")]
+    pub cargo_args: Option<String>,
+    #[clap(long, help = "Do not compile a Rust project, use a file instead")]
+    pub file: Option<PathBuf>,
+    #[clap(long = "asset", help = "Asset file to include into the bundle")]
+    pub assets: Vec<PathBuf>,
+    #[clap(short = 'o', long, help = "Output file")]
+    pub output: Option<PathBuf>,
+    #[clap(long, env = "ROBOPLC_BUNDLE_KEY", help = "Bundle signing key")]
+    pub sign_key: Option<String>,
+}
+
+#[derive(Parser)]
+pub struct BundleFlashCommand {
+    #[clap(help = "Bundle file")]
+    pub bundle: PathBuf,
+    #[clap(
+        long,
+        env = "ROBOPLC_BUNDLE_KEY",
+        help = "Bundle signature verification key"
+    )]
+    pub sign_key: Option<String>,
+    #[clap(long, help = "Extract bundle assets into the directory")]
+    pub extract_assets: Option<PathBuf>,
+    #[clap(
+        short = 'f',
+        long,
+        help = "Force flash (automatically put remote in CONFIG mode)"
+    )]
+    pub force: bool,
+    #[clap(short = 'r', long, help = "Put remote in RUN mode after flashing")]
+    pub run: bool,
+}
+
+#[derive(Parser)]
+pub struct InstallCommand {
+    #[clap(long, help = "Install the program as a systemd service")]
+    pub systemd: bool,
+    #[clap(
+        long,
+        help = "SSH destination ([user@]host), overrides robo.toml [install]"
+    )]
+    pub host: Option<String>,
+    #[clap(long, help = "Remote binary directory (default: /usr/local/bin)")]
+    pub path: Option<String>,
+    #[clap(long, help = "Service name (default: the program name)")]
+    pub service: Option<String>,
+    #[clap(long, help = "Service user (default: root)")]
+    pub user: Option<String>,
+    #[clap(long, help = "Service CPU affinity, e.g. 2-3")]
+    pub cpus: Option<String>,
+    #[clap(long, help = "Service restart policy (default: on-failure)")]
+    pub restart: Option<String>,
+    #[clap(long, help = "Run remote commands with sudo")]
+    pub sudo: bool,
+    #[clap(long, env = "CARGO", help = "cargo/cross binary path")]
+    pub cargo: Option<PathBuf>,
+    #[clap(long, help = "Override cargo target (default: detected on the host)")]
+    pub cargo_target: Option<String>,
+    #[clap(long, help = "Extra cargo arguments")]
+    pub cargo_args: Option<String>,
+    #[clap(long, help = "Do not compile a Rust project, use a file instead")]
+    pub file: Option<PathBuf>,
+    #[clap(long, help = "Write the unit file only, do not install")]
+    pub unit_file: Option<PathBuf>,
+    #[clap(long, help = "Do not (re)start the service")]
+    pub no_start: bool,
+    #[clap(
+        long,
+        help = "Save the install settings into robo.toml (the file is rewritten)"
+    )]
+    pub save: bool,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Eq, PartialEq)]
+pub enum TagFormat {
+    /// Siemens TIA Portal PLC tag table (CSV)
+    Tia,
+    /// Allen-Bradley L5X export (controller/program base tags)
+    L5x,
+}
+
+#[derive(Parser)]
+pub struct SnapshotDiffCommand {
+    #[clap(help = "First (good) snapshot file")]
+    pub first: PathBuf,
+    #[clap(help = "Second (bad) snapshot file")]
+    pub second: PathBuf,
+}
+
+#[derive(Parser)]
+pub struct ImportTagsCommand {
+    #[clap(help = "Tag list file")]
+    pub file: PathBuf,
+    #[clap(long, value_enum, help = "Tag list format")]
+    pub format: TagFormat,
+    #[clap(short = 'o', long, help = "Output file (default: stdout)")]
+    pub output: Option<PathBuf>,
+    #[clap(
+        long,
+        default_value = "Tags",
+        help = "Structure name (for formats without addresses)"
+    )]
+    pub struct_name: String,
+    #[clap(
+        long,
+        default_value = "h0",
+        help = "Modbus base register (for formats without addresses)"
+    )]
+    pub base_register: String,
 }
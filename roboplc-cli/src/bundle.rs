@@ -0,0 +1,251 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use colored::Colorize as _;
+use hmac::{Hmac, Mac};
+use roboplc_client::Client;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    arguments::{BundleCreateCommand, BundleFlashCommand},
+    common::report_ok,
+    config,
+    flashing::{self, BuildOptions},
+};
+
+const BUNDLE_FORMAT: u32 = 1;
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+const SIGNATURE_FILE_NAME: &str = "manifest.sig";
+const PROGRAM_FILE_NAME: &str = "program";
+const ASSETS_DIR: &str = "assets";
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    format: u32,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+    created: u64,
+    // file path inside the bundle -> sha256 hex digest
+    files: BTreeMap<String, String>,
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+fn sign(data: &[u8], key: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())?;
+    mac.update(data);
+    Ok(hex::encode(mac.finalize().into_bytes()))
+}
+
+fn verify_signature(
+    data: &[u8],
+    key: &str,
+    signature: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())?;
+    mac.update(data);
+    mac.verify_slice(&hex::decode(signature.trim())?)
+        .map_err(|_| "Bundle signature verification failed".into())
+}
+
+fn append_file<W: std::io::Write>(
+    builder: &mut tar::Builder<W>,
+    path: &str,
+    data: &[u8],
+    mode: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(u64::try_from(data.len())?);
+    header.set_mode(mode);
+    header.set_cksum();
+    builder.append_data(&mut header, path, data)?;
+    Ok(())
+}
+
+fn package_version() -> Option<String> {
+    let contents = fs::read_to_string("Cargo.toml").ok()?;
+    let value = contents.parse::<toml::Value>().ok()?;
+    value
+        .get("package")?
+        .get("version")?
+        .as_str()
+        .map(String::from)
+}
+
+pub fn create(
+    remote: Option<&Client>,
+    opts: &BundleCreateCommand,
+    build_config: config::Build,
+    build_custom: config::BuildCustom,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let program = if let Some(ref file) = opts.file {
+        file.clone()
+    } else {
+        flashing::compile(
+            remote,
+            BuildOptions {
+                cargo: opts.cargo.clone(),
+                cargo_target: opts.cargo_target.clone(),
+                cargo_args: opts.cargo_args.clone(),
+            },
+            build_config,
+            build_custom,
+        )?
+    };
+    let name = program
+        .file_name()
+        .ok_or("Invalid program file name")?
+        .to_string_lossy()
+        .to_string();
+    let output = opts
+        .output
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(format!("{}.robo.tar", name)));
+    println!("Creating bundle: {}", output.display().to_string().yellow());
+    let mut files = BTreeMap::new();
+    let mut contents = Vec::new();
+    let program_data =
+        fs::read(&program).map_err(|e| format!("Unable to read {}: {}", program.display(), e))?;
+    files.insert(PROGRAM_FILE_NAME.to_owned(), sha256_hex(&program_data));
+    contents.push((PROGRAM_FILE_NAME.to_owned(), program_data, 0o755));
+    for asset in &opts.assets {
+        let asset_name = asset
+            .file_name()
+            .ok_or_else(|| format!("Invalid asset file name: {}", asset.display()))?
+            .to_string_lossy();
+        let path = format!("{}/{}", ASSETS_DIR, asset_name);
+        if files.contains_key(&path) {
+            return Err(format!("Duplicate asset: {}", asset_name).into());
+        }
+        let data =
+            fs::read(asset).map_err(|e| format!("Unable to read {}: {}", asset.display(), e))?;
+        println!("Asset: {}", asset.display());
+        files.insert(path.clone(), sha256_hex(&data));
+        contents.push((path, data, 0o644));
+    }
+    let manifest = Manifest {
+        format: BUNDLE_FORMAT,
+        name,
+        version: package_version(),
+        created: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        files,
+    };
+    let manifest_data = serde_json::to_vec_pretty(&manifest)?;
+    let mut builder = tar::Builder::new(fs::File::create(&output)?);
+    append_file(&mut builder, MANIFEST_FILE_NAME, &manifest_data, 0o644)?;
+    if let Some(ref key) = opts.sign_key {
+        let signature = sign(&manifest_data, key)?;
+        append_file(
+            &mut builder,
+            SIGNATURE_FILE_NAME,
+            signature.as_bytes(),
+            0o644,
+        )?;
+    } else {
+        println!("{}", "Bundle is not signed".yellow());
+    }
+    for (path, data, mode) in contents {
+        append_file(&mut builder, &path, &data, mode)?;
+    }
+    builder.into_inner()?;
+    report_ok()
+}
+
+fn extract(bundle: &Path) -> Result<BTreeMap<String, Vec<u8>>, Box<dyn std::error::Error>> {
+    let mut archive = tar::Archive::new(fs::File::open(bundle)?);
+    let mut result = BTreeMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().to_string();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        result.insert(path, data);
+    }
+    Ok(result)
+}
+
+pub fn flash(
+    client: &Client,
+    opts: &BundleFlashCommand,
+    exec: &config::Exec,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Remote: {}", client.url().yellow());
+    println!("Bundle: {}", opts.bundle.display().to_string().yellow());
+    let mut contents = extract(&opts.bundle)?;
+    let manifest_data = contents
+        .remove(MANIFEST_FILE_NAME)
+        .ok_or("Bundle manifest not found")?;
+    let manifest: Manifest = serde_json::from_slice(&manifest_data)?;
+    if manifest.format != BUNDLE_FORMAT {
+        return Err(format!("Unsupported bundle format: {}", manifest.format).into());
+    }
+    match (contents.remove(SIGNATURE_FILE_NAME), opts.sign_key.as_ref()) {
+        (Some(signature), Some(sign_key)) => {
+            verify_signature(&manifest_data, sign_key, &String::from_utf8(signature)?)?;
+            println!("Signature: {}", "OK".green());
+        }
+        (None, Some(_)) => return Err("Bundle is not signed".into()),
+        (Some(_), None) => {
+            println!(
+                "{}",
+                "Signing key not specified, signature not verified".yellow()
+            );
+        }
+        (None, None) => {}
+    }
+    if contents.len() != manifest.files.len() {
+        return Err("Bundle contains files not listed in the manifest".into());
+    }
+    for (path, checksum) in &manifest.files {
+        let data = contents
+            .get(path)
+            .ok_or_else(|| format!("File not found in the bundle: {}", path))?;
+        if &sha256_hex(data) != checksum {
+            return Err(format!("Checksum mismatch: {}", path).into());
+        }
+    }
+    println!("Checksums: {}", "OK".green());
+    println!(
+        "Program: {} {}",
+        manifest.name.yellow(),
+        manifest.version.as_deref().unwrap_or_default()
+    );
+    if let Some(ref dir) = opts.extract_assets {
+        let prefix = format!("{}/", ASSETS_DIR);
+        for (path, data) in &contents {
+            if let Some(asset_name) = path.strip_prefix(&prefix) {
+                let target = dir.join(asset_name);
+                println!("Extracting asset: {}", target.display());
+                fs::create_dir_all(dir)?;
+                fs::write(target, data)?;
+            }
+        }
+    }
+    let tmp_dir = std::env::temp_dir().join(format!("robo-bundle-{}", std::process::id()));
+    fs::create_dir_all(&tmp_dir)?;
+    let program = tmp_dir.join(&manifest.name);
+    fs::write(
+        &program,
+        contents
+            .get(PROGRAM_FILE_NAME)
+            .ok_or("Program not found in the bundle")?,
+    )?;
+    println!("Flashing...");
+    flashing::print_exec(exec);
+    let result = client.flash(
+        &program,
+        &flashing::flash_params(exec).force(opts.force).run(opts.run),
+    );
+    let _ = fs::remove_dir_all(&tmp_dir);
+    result?;
+    report_ok()
+}
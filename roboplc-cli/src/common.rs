@@ -1,8 +1,10 @@
-use core::fmt;
-use std::{env, path::PathBuf};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
 
 use colored::Colorize;
-use serde::{Deserialize, Serialize};
+use roboplc_client::{Mode, State};
 
 pub const CONFIG_FILE_NAME: &str = "robo.toml";
 pub const GLOBAL_CONFIG_FILE_NAME: &str = ".robo-global.toml";
@@ -11,61 +13,38 @@ pub fn print_err(msg: &str) {
     eprintln!("{}", msg.red());
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct State {
-    pid: Option<u32>,
-    mode: Mode,
-    memory_used: Option<u64>,
-    run_time: Option<u64>,
-}
-
-impl State {
-    pub fn print_std(&self) {
-        let mode_colored = match self.mode {
-            Mode::Run => format!("{}", self.mode).green(),
-            Mode::Config => format!("{}", self.mode).yellow(),
-            Mode::Unknown => format!("{}", self.mode).red(),
-        };
-        println!("Mode {}", mode_colored);
-        if let Some(pid) = self.pid {
-            println!("PID  {}", pid);
-        }
-        if let Some(memory) = self.memory_used {
-            println!("Mem  {}", memory);
-        }
-        if let Some(run_time) = self.run_time {
-            println!("Up   {}", run_time);
-        }
+pub fn print_state(state: &State) {
+    let mode_colored = match state.mode {
+        Mode::Run => format!("{}", state.mode).green(),
+        Mode::Config => format!("{}", state.mode).yellow(),
+        Mode::Unknown => format!("{}", state.mode).red(),
+    };
+    println!("Mode {}", mode_colored);
+    if let Some(pid) = state.pid {
+        println!("PID  {}", pid);
     }
-}
-
-#[derive(Deserialize)]
-pub struct KernelInfo {
-    machine: String,
-}
-
-impl KernelInfo {
-    pub fn to_machine_cargo_target(&self) -> String {
-        format!("{}-unknown-linux-gnu", self.machine)
+    if let Some(memory) = state.memory_used {
+        println!("Mem  {}", memory);
     }
-}
-
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
-#[serde(rename_all = "lowercase")]
-pub enum Mode {
-    Run,
-    Config,
-    Unknown,
-}
-
-impl fmt::Display for Mode {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Mode::Run => write!(f, "RUN"),
-            Mode::Config => write!(f, "CONFIG"),
-            Mode::Unknown => write!(f, "UNKNOWN"),
+    if let Some(run_time) = state.run_time {
+        println!("Up   {}", run_time);
+    }
+    if let Some(ref controller_state) = state.state {
+        println!("Stat {}", controller_state);
+    }
+    if let Some(ref status) = state.status {
+        if let Some(progress) = state.progress {
+            println!("Info {} [{:.0}%]", status, progress);
+        } else {
+            println!("Info {}", status);
         }
     }
+    if let Some(slot) = state.slot {
+        println!("Slot {}", slot);
+    }
+    if let Some(staging_slot) = state.staging_slot {
+        println!("Stag {}", staging_slot);
+    }
 }
 
 pub fn find_robo_toml() -> Option<PathBuf> {
@@ -91,6 +70,37 @@ pub fn find_robo_toml() -> Option<PathBuf> {
     None
 }
 
+/// Finds the root of the cargo workspace, the given directory belongs to (the directory itself
+/// is checked as well)
+pub fn find_workspace_root(dir: &Path) -> Option<PathBuf> {
+    let mut current_dir = dir.to_path_buf();
+    loop {
+        let cargo_toml_path = current_dir.join("Cargo.toml");
+        if let Ok(contents) = fs::read_to_string(&cargo_toml_path) {
+            if let Ok(value) = contents.parse::<toml::Value>() {
+                if value.get("workspace").is_some() {
+                    return Some(current_dir);
+                }
+            }
+        }
+        if !current_dir.pop() {
+            return None;
+        }
+    }
+}
+
+/// The cargo target directory of the current crate: `CARGO_TARGET_DIR` if set, the workspace
+/// one for workspace members, `target` otherwise
+pub fn target_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("CARGO_TARGET_DIR") {
+        return dir.into();
+    }
+    env::current_dir()
+        .ok()
+        .and_then(|dir| find_workspace_root(&dir))
+        .map_or_else(|| PathBuf::from("target"), |root| root.join("target"))
+}
+
 #[allow(clippy::unnecessary_wraps)]
 pub fn report_ok() -> Result<(), Box<dyn std::error::Error>> {
     println!("{}", "OK".green());
@@ -12,6 +12,10 @@ pub struct Config {
     pub build: Build,
     #[serde(default, rename = "build-custom")]
     pub build_custom: BuildCustom,
+    #[serde(default)]
+    pub checks: Checks,
+    #[serde(default, skip_serializing_if = "Install::is_empty")]
+    pub install: Install,
 }
 
 #[derive(Deserialize, Serialize, Default, Debug)]
@@ -22,6 +26,78 @@ pub struct Remote {
     pub key: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub timeout: Option<u64>,
+    /// Default program arguments/environment for the remote
+    #[serde(default, skip_serializing_if = "Exec::is_empty")]
+    pub exec: Exec,
+    /// Per-remote sections (`[remote.<name>.exec]`), the name is the one given with `-U`
+    #[serde(flatten, default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, RemoteProfile>,
+}
+
+impl Config {
+    /// Fills missing remote and build settings of a workspace member from the workspace
+    /// robo.toml. Per-remote program arguments/environment are not inherited
+    pub fn inherit(&mut self, workspace: Config) {
+        let remote = &mut self.remote;
+        if remote.url.is_none() {
+            remote.url = workspace.remote.url;
+        }
+        if remote.key.is_none() {
+            remote.key = workspace.remote.key;
+        }
+        if remote.timeout.is_none() {
+            remote.timeout = workspace.remote.timeout;
+        }
+        let build = &mut self.build;
+        if build.cargo.is_none() {
+            build.cargo = workspace.build.cargo;
+        }
+        if build.target.is_none() {
+            build.target = workspace.build.target;
+        }
+        if build.cargo_args.is_none() {
+            build.cargo_args = workspace.build.cargo_args;
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub struct RemoteProfile {
+    #[serde(default)]
+    pub exec: Exec,
+}
+
+/// Program arguments and environment variables, recorded at flash time
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+pub struct Exec {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+}
+
+impl Exec {
+    pub fn is_empty(&self) -> bool {
+        self.args.is_empty() && self.env.is_empty()
+    }
+    /// Applies command-line overrides: `KEY=VALUE` environment variables are merged, arguments
+    /// replace the profile ones if specified
+    pub fn apply_overrides(
+        &mut self,
+        env: &[String],
+        args: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for e in env {
+            let (k, v) = e
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid environment variable: {} (KEY=VALUE)", e))?;
+            self.env.insert(k.to_owned(), v.to_owned());
+        }
+        if !args.is_empty() {
+            self.args = args.to_vec();
+        }
+        Ok(())
+    }
 }
 
 #[derive(Deserialize, Serialize, Default, Debug)]
@@ -42,6 +118,58 @@ pub struct BuildCustom {
     pub file: Option<PathBuf>,
 }
 
+/// Commands which are run before compiling the release binary for flashing
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub struct Checks {
+    /// Run `cargo test`
+    #[serde(default)]
+    pub test: bool,
+    /// Run `cargo clippy`, warnings are denied
+    #[serde(default)]
+    pub clippy: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cargo_args: Option<String>,
+    /// Custom commands, executed with `sh -c`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<String>,
+}
+
+/// Self-managed host (no manager) installation settings, used by `robo install`
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+pub struct Install {
+    /// SSH destination, `[user@]host`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// Remote binary directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// CPU affinity of the service, e.g. `2-3`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<String>,
+    /// systemd restart policy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restart: Option<String>,
+    /// Run remote commands with sudo
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sudo: bool,
+}
+
+impl Install {
+    pub fn is_empty(&self) -> bool {
+        self.host.is_none()
+            && self.path.is_none()
+            && self.service.is_none()
+            && self.user.is_none()
+            && self.cpus.is_none()
+            && self.restart.is_none()
+            && !self.sudo
+    }
+}
+
 #[derive(Deserialize, Debug)]
 struct GlobalConfig {
     remote: BTreeMap<String, Remote>,
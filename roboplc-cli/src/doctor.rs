@@ -0,0 +1,169 @@
+use colored::Colorize as _;
+use roboplc_client::Client;
+use serde::Deserialize;
+
+#[derive(Deserialize, Default)]
+struct SystemInfo {
+    sched_rt_runtime_us: Option<i64>,
+    #[serde(default)]
+    isolated_cpus: Vec<usize>,
+    #[serde(default)]
+    cpu_governors: Vec<String>,
+    dpkg_version: Option<String>,
+    dpkg_zstd: Option<bool>,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+struct Report {
+    warnings: usize,
+    failures: usize,
+}
+
+impl Report {
+    fn new() -> Self {
+        Self {
+            warnings: 0,
+            failures: 0,
+        }
+    }
+    fn item(&mut self, status: Status, name: &str, value: &str, hint: Option<&str>) {
+        let label = match status {
+            Status::Pass => "PASS".green(),
+            Status::Warn => {
+                self.warnings += 1;
+                "WARN".yellow()
+            }
+            Status::Fail => {
+                self.failures += 1;
+                "FAIL".red()
+            }
+        };
+        println!("[{}] {}: {}", label, name, value);
+        if status != Status::Pass {
+            if let Some(hint) = hint {
+                println!("       {}", hint.dimmed());
+            }
+        }
+    }
+}
+
+pub fn doctor(client: &Client) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Remote: {}", client.url().yellow());
+    let mut report = Report::new();
+    let kernel = client.kernel_info()?;
+    report.item(Status::Pass, "Machine", &kernel.machine, None);
+    let release = kernel.release.as_deref().unwrap_or_default();
+    let version = kernel.version.as_deref().unwrap_or_default();
+    if release.is_empty() && version.is_empty() {
+        report.item(
+            Status::Warn,
+            "Kernel",
+            "unknown",
+            Some("The manager does not report the kernel version, consider upgrading it"),
+        );
+    } else {
+        let is_rt = version.contains("PREEMPT_RT")
+            || version.contains("PREEMPT RT")
+            || release.contains("-rt");
+        report.item(
+            if is_rt { Status::Pass } else { Status::Fail },
+            "PREEMPT_RT kernel",
+            release,
+            Some("Install a PREEMPT_RT kernel to get deterministic real-time scheduling"),
+        );
+    }
+    let system: SystemInfo = match client.call("query.info.system") {
+        Ok(v) => v,
+        Err(e) => {
+            report.item(
+                Status::Warn,
+                "System info",
+                &e.to_string(),
+                Some("The manager does not provide system info, consider upgrading it"),
+            );
+            SystemInfo::default()
+        }
+    };
+    if let Some(rt_runtime) = system.sched_rt_runtime_us {
+        report.item(
+            if rt_runtime == -1 {
+                Status::Pass
+            } else {
+                Status::Warn
+            },
+            "RT throttling (sched_rt_runtime_us)",
+            &rt_runtime.to_string(),
+            Some(
+                "Real-time tasks are throttled by the kernel. Set kernel.sched_rt_runtime_us=-1 \
+                or use roboplc::thread_rt::SystemConfig",
+            ),
+        );
+    }
+    if system.isolated_cpus.is_empty() {
+        report.item(
+            Status::Warn,
+            "CPU isolation",
+            "none",
+            Some("Isolate CPUs for real-time workers with isolcpus= kernel parameter"),
+        );
+    } else {
+        report.item(
+            Status::Pass,
+            "CPU isolation",
+            &system
+                .isolated_cpus
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+            None,
+        );
+    }
+    if !system.cpu_governors.is_empty() {
+        let all_performance = system.cpu_governors.iter().all(|g| g == "performance");
+        report.item(
+            if all_performance {
+                Status::Pass
+            } else {
+                Status::Warn
+            },
+            "CPU governors",
+            &system.cpu_governors.join(","),
+            Some(
+                "CPU frequency scaling increases latencies. Set the performance governor or use \
+                roboplc::thread_rt::CpuGovernor",
+            ),
+        );
+    }
+    if let Some(zstd) = system.dpkg_zstd {
+        report.item(
+            if zstd { Status::Pass } else { Status::Fail },
+            "dpkg zstd support",
+            system.dpkg_version.as_deref().unwrap_or("unknown"),
+            Some(
+                "dpkg is unable to install zstd-compressed packages (e.g. Debian Bullseye on \
+                RevPi), upgrade dpkg from backports or use xz-compressed packages",
+            ),
+        );
+    }
+    println!();
+    if report.failures > 0 {
+        return Err(format!(
+            "{} check(s) failed, {} warning(s)",
+            report.failures, report.warnings
+        )
+        .into());
+    }
+    if report.warnings > 0 {
+        println!("{} {} warning(s)", "OK".yellow(), report.warnings);
+    } else {
+        println!("{}", "OK".green());
+    }
+    Ok(())
+}
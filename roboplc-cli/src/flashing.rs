@@ -4,62 +4,23 @@
 };
 
 use colored::Colorize as _;
-use serde_json::json;
-use ureq::Agent;
-use ureq_multipart::MultipartBuilder;
+use roboplc_client::{Client, FlashParams};
 use which::which;
 
 use crate::{
     arguments::FlashCommand,
-    common::{report_ok, KernelInfo},
+    common::{report_ok, target_dir},
     config,
-    ureq_err::PrintErr,
-    API_PREFIX,
 };
 
-fn flash_file(
-    url: &str,
-    key: &str,
-    agent: Agent,
-    file: &Path,
-    force: bool,
-    run: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    if !file.exists() {
-        return Err(format!("File not found: {}", file.display()).into());
-    }
-    let (content_type, data) = MultipartBuilder::new()
-        .add_file("file", file)?
-        .add_text(
-            "params",
-            &serde_json::to_string(&json! {
-                {
-                    "force": force,
-                    "run": run,
-                }
-
-            })?,
-        )?
-        .finish()?;
-    agent
-        .post(&format!("{}{}/flash", url, API_PREFIX))
-        .set("x-auth-key", key)
-        .set("content-type", &content_type)
-        .send_bytes(&data)
-        .process_error()?;
-    Ok(())
+/// Flash parameters with program arguments/environment, recorded by the manager
+pub fn flash_params(exec: &config::Exec) -> FlashParams {
+    FlashParams::new()
+        .args(exec.args.clone())
+        .env(exec.env.clone())
 }
 
-fn run_build_custom(
-    url: &str,
-    key: &str,
-    agent: Agent,
-    force: bool,
-    run: bool,
-    cmd: &str,
-    file: &Path,
-) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Remote: {}", url.yellow());
+fn run_build_custom(cmd: &str, file: &Path) -> Result<(), Box<dyn std::error::Error>> {
     println!("Build command line: {}", cmd.yellow());
     println!("Binary: {}", file.display().to_string().yellow());
     println!("Compiling...");
@@ -69,106 +30,197 @@ fn run_build_custom(
     if !result.success() {
         return Err("Compilation failed".into());
     }
-    println!("Flashing...");
     if !file.exists() {
         return Err(format!("File not found: {}", file.display()).into());
     }
-    flash_file(url, key, agent, file, force, run)?;
+    Ok(())
+}
+
+/// Compilation options, override ones from robo.toml
+pub struct BuildOptions {
+    pub cargo: Option<PathBuf>,
+    pub cargo_target: Option<String>,
+    pub cargo_args: Option<String>,
+}
+
+impl From<&FlashCommand> for BuildOptions {
+    fn from(opts: &FlashCommand) -> Self {
+        Self {
+            cargo: opts.cargo.clone(),
+            cargo_target: opts.cargo_target.clone(),
+            cargo_args: opts.cargo_args.clone(),
+        }
+    }
+}
+
+/// Compiles the program and returns the binary path. The remote is used to detect the cargo
+/// target if not specified
+pub fn compile(
+    remote: Option<&Client>,
+    opts: BuildOptions,
+    build_config: config::Build,
+    build_custom: config::BuildCustom,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    if let Some(custom_cmd) = build_custom.command {
+        let file = build_custom
+            .file
+            .ok_or("Custom build command requires a file")?;
+        run_build_custom(&custom_cmd, &file)?;
+        return Ok(file);
+    }
+    let mut cargo_target: Option<String> = None;
+    if let Some(c) = opts.cargo_target {
+        cargo_target.replace(c);
+    }
+    if cargo_target.is_none() {
+        cargo_target = build_config.target;
+    }
+    if cargo_target.is_none() {
+        let client = remote.ok_or("Cargo target not specified")?;
+        let info = client.kernel_info()?;
+        cargo_target.replace(info.to_machine_cargo_target());
+    }
+    let mut cargo: Option<PathBuf> = None;
+    if let Some(c) = opts.cargo {
+        cargo.replace(c);
+    }
+    if cargo.is_none() {
+        cargo = build_config.cargo;
+    }
+    if cargo.is_none() {
+        cargo = which("cross").ok();
+    }
+    let cargo_target = cargo_target.unwrap();
+    let cargo = cargo.unwrap_or_else(|| "cargo".into());
+    let Some(name) = find_name_and_chdir() else {
+        return Err("Could not find Cargo.toml/binary name".into());
+    };
+    let mut cargo_args = None;
+    if let Some(args) = opts.cargo_args {
+        cargo_args.replace(args);
+    } else {
+        cargo_args = build_config.cargo_args;
+    }
+    // workspace members share the workspace target directory
+    let binary_name = target_dir().join(&cargo_target).join("release").join(name);
+    let mut args: Vec<String> = vec![
+        "build".into(),
+        "--release".into(),
+        "--target".into(),
+        cargo_target.clone(),
+    ];
+    if let Some(extra) = cargo_args {
+        args.extend(shlex::split(&extra).expect("Invalid cargo args"));
+    }
+    println!(
+        "Cargo command line: {} {}",
+        cargo.display().to_string().yellow(),
+        args.join(" ").yellow()
+    );
+    println!("Cargo target: {}", cargo_target.yellow());
+    println!("Binary: {}", binary_name.display().to_string().yellow());
+    println!("Compiling...");
+    let result = std::process::Command::new(cargo).args(args).status()?;
+    if !result.success() {
+        return Err("Compilation failed".into());
+    }
+    Ok(binary_name)
+}
+
+fn run_check(program: &str, args: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Check: {} {}", program.yellow(), args.join(" ").yellow());
+    let result = std::process::Command::new(program).args(args).status()?;
+    if !result.success() {
+        return Err(format!("Check failed: {} {}", program, args.join(" ")).into());
+    }
+    Ok(())
+}
+
+/// Runs pre-flash checks, configured in robo.toml. Aborts on the first failed check
+pub fn run_checks(checks: &config::Checks) -> Result<(), Box<dyn std::error::Error>> {
+    if !checks.test && !checks.clippy && checks.commands.is_empty() {
+        return Ok(());
+    }
+    if find_name_and_chdir().is_none() {
+        return Err("Could not find Cargo.toml".into());
+    }
+    let extra = checks
+        .cargo_args
+        .as_deref()
+        .map(|a| shlex::split(a).ok_or("Invalid checks cargo args"))
+        .transpose()?
+        .unwrap_or_default();
+    let extra: Vec<&str> = extra.iter().map(String::as_str).collect();
+    if checks.test {
+        let mut args = vec!["test"];
+        args.extend(&extra);
+        run_check("cargo", &args)?;
+    }
+    if checks.clippy {
+        let mut args = vec!["clippy"];
+        args.extend(&extra);
+        args.extend(["--", "-D", "warnings"]);
+        run_check("cargo", &args)?;
+    }
+    for cmd in &checks.commands {
+        run_check("sh", &["-c", cmd])?;
+    }
+    println!("{}", "Checks passed".green());
     Ok(())
 }
 
 pub fn flash(
-    url: &str,
-    key: &str,
-    agent: Agent,
+    client: &Client,
     opts: FlashCommand,
     build_config: config::Build,
     build_custom: config::BuildCustom,
+    checks: config::Checks,
+    mut exec: config::Exec,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    if let Some(file) = opts.file {
-        flash_file(url, key, agent, &file, opts.force, opts.run)?;
-    } else if let Some(custom_cmd) = build_custom.command {
-        run_build_custom(
-            url,
-            key,
-            agent,
-            opts.force,
-            opts.run,
-            &custom_cmd,
-            &build_custom
-                .file
-                .ok_or("Custom build command requires a file")?,
-        )?;
+    exec.apply_overrides(&opts.env, &opts.program_args)?;
+    print_exec(&exec);
+    let params = flash_params(&exec)
+        .force(opts.force)
+        .run(opts.run)
+        .slot(opts.slot);
+    if let Some(ref file) = opts.file {
+        client.flash(file, &params)?;
     } else {
-        let mut cargo_target: Option<String> = None;
-        if let Some(c) = opts.cargo_target {
-            cargo_target.replace(c);
-        }
-        if cargo_target.is_none() {
-            cargo_target = build_config.target;
-        }
-        if cargo_target.is_none() {
-            let resp = agent
-                .post(&format!("{}{}/query.info.kernel", url, API_PREFIX))
-                .set("x-auth-key", key)
-                .call()?;
-            let info: KernelInfo = resp.into_json()?;
-            cargo_target.replace(info.to_machine_cargo_target());
-        }
-        let mut cargo: Option<PathBuf> = None;
-        if let Some(c) = opts.cargo {
-            cargo.replace(c);
-        }
-        if cargo.is_none() {
-            cargo = build_config.cargo;
-        }
-        if cargo.is_none() {
-            cargo = which("cross").ok();
-        }
-        let cargo_target = cargo_target.unwrap();
-        let cargo = cargo.unwrap_or_else(|| "cargo".into());
-        let Some(name) = find_name_and_chdir() else {
-            return Err("Could not find Cargo.toml/binary name".into());
-        };
-        let mut cargo_args = None;
-        if let Some(args) = opts.cargo_args {
-            cargo_args.replace(args);
+        println!("Remote: {}", client.url().yellow());
+        if opts.skip_checks {
+            println!("{}", "Pre-flash checks skipped".yellow());
         } else {
-            cargo_args = build_config.cargo_args;
-        }
-        let binary_name = Path::new("target")
-            .join(&cargo_target)
-            .join("release")
-            .join(name);
-        let mut args: Vec<String> = vec![
-            "build".into(),
-            "--release".into(),
-            "--target".into(),
-            cargo_target.clone(),
-        ];
-        if let Some(extra) = cargo_args {
-            args.extend(shlex::split(&extra).expect("Invalid cargo args"));
+            run_checks(&checks)?;
         }
-        println!("Remote: {}", url.yellow());
+        let binary = compile(
+            Some(client),
+            BuildOptions::from(&opts),
+            build_config,
+            build_custom,
+        )?;
+        println!("Flashing...");
+        client.flash(&binary, &params)?;
+    }
+    if let Some(slot) = opts.slot {
         println!(
-            "Cargo command line: {} {}",
-            cargo.display().to_string().yellow(),
-            args.join(" ").yellow()
+            "Flashed into the staging slot {}, use `{}` to activate",
+            slot.to_string().yellow(),
+            "robo promote".yellow()
         );
-        println!("Cargo target: {}", cargo_target.yellow());
-        println!("Binary: {}", binary_name.display().to_string().yellow());
-        println!("Compiling...");
-        let result = std::process::Command::new(cargo).args(args).status()?;
-        if !result.success() {
-            return Err("Compilation failed".into());
-        }
-        println!("Flashing...");
-        flash_file(url, key, agent, &binary_name, opts.force, opts.run)?;
     }
     report_ok()
 }
 
-fn find_name_and_chdir() -> Option<String> {
+pub fn print_exec(exec: &config::Exec) {
+    if !exec.args.is_empty() {
+        println!("Program args: {}", exec.args.join(" ").yellow());
+    }
+    for (k, v) in &exec.env {
+        println!("Program env: {}={}", k.yellow(), v.yellow());
+    }
+}
+
+pub fn find_name_and_chdir() -> Option<String> {
     let mut current_dir = env::current_dir().ok()?;
     loop {
         let mut cargo_toml_path = current_dir.clone();
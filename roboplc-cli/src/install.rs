@@ -0,0 +1,302 @@
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use colored::Colorize as _;
+
+use crate::{
+    arguments::InstallCommand,
+    common::{find_robo_toml, report_ok, CONFIG_FILE_NAME},
+    config::{self, Config},
+    flashing::{self, find_name_and_chdir, print_exec, BuildOptions},
+};
+
+const DEFAULT_PATH: &str = "/usr/local/bin";
+const DEFAULT_USER: &str = "root";
+const DEFAULT_RESTART: &str = "on-failure";
+const RESTART_POLICIES: &[&str] = &[
+    "no",
+    "always",
+    "on-success",
+    "on-failure",
+    "on-abnormal",
+    "on-abort",
+    "on-watchdog",
+];
+
+/// Merged install settings
+struct Settings {
+    name: String,
+    service: String,
+    path: String,
+    user: String,
+    cpus: Option<String>,
+    restart: String,
+}
+
+impl Settings {
+    fn binary_path(&self) -> String {
+        format!("{}/{}", self.path.trim_end_matches('/'), self.name)
+    }
+}
+
+// systemd command line/environment quoting, specifiers and variables are escaped as well
+fn systemd_quote(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    if !escaped.is_empty() && !escaped.contains(|c: char| c.is_whitespace() || c == '\'') {
+        escaped
+    } else {
+        format!("\"{}\"", escaped)
+    }
+}
+
+fn generate_unit(settings: &Settings, exec: &config::Exec) -> String {
+    let mut exec_start = systemd_quote(&settings.binary_path());
+    for arg in &exec.args {
+        exec_start.push(' ');
+        exec_start.push_str(&systemd_quote(arg));
+    }
+    let mut unit = format!(
+        "[Unit]
+Description=RoboPLC program {name}
+After=network-online.target
+Wants=network-online.target
+StartLimitIntervalSec=0
+
+[Service]
+Type=simple
+User={user}
+ExecStart={exec_start}
+WorkingDirectory=/var/lib/{service}
+StateDirectory={service}
+Restart={restart}
+RestartSec=1
+KillSignal=SIGTERM
+TimeoutStopSec=30
+# real-time scheduling and memory locking
+LimitRTPRIO=99
+LimitMEMLOCK=infinity
+",
+        name = settings.name,
+        user = settings.user,
+        exec_start = exec_start,
+        service = settings.service,
+        restart = settings.restart,
+    );
+    if let Some(ref cpus) = settings.cpus {
+        writeln!(unit, "CPUAffinity={}", cpus.replace(',', " ")).unwrap();
+    }
+    for (k, v) in &exec.env {
+        writeln!(
+            unit,
+            "Environment={}",
+            systemd_quote(&format!("{}={}", k, v))
+        )
+        .unwrap();
+    }
+    unit.push_str(
+        "# hardening
+NoNewPrivileges=yes
+ProtectSystem=full
+ProtectHome=yes
+PrivateTmp=yes
+ProtectKernelModules=yes
+ProtectControlGroups=yes
+RestrictSUIDSGID=yes
+LockPersonality=yes
+
+[Install]
+WantedBy=multi-user.target
+",
+    );
+    unit
+}
+
+fn ssh(host: &str, cmd: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let output = Command::new("ssh")
+        .arg(host)
+        .arg(cmd)
+        .output()
+        .map_err(|e| format!("Unable to run ssh: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "Remote command failed: {}: {}",
+            cmd,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+fn scp(file: &Path, host: &str, remote_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let result = Command::new("scp")
+        .arg("-q")
+        .arg(file)
+        .arg(format!("{}:{}", host, remote_path))
+        .status()
+        .map_err(|e| format!("Unable to run scp: {}", e))?;
+    if !result.success() {
+        return Err(format!("Unable to copy {} to {}", file.display(), host).into());
+    }
+    Ok(())
+}
+
+fn save_settings(install: config::Install) -> Result<(), Box<dyn std::error::Error>> {
+    let (path, mut robo_toml) = if let Some(path) = find_robo_toml() {
+        let config: Config = toml::from_str(&fs::read_to_string(&path)?)?;
+        (path, config)
+    } else {
+        (
+            PathBuf::from(CONFIG_FILE_NAME),
+            Config {
+                remote: <_>::default(),
+                build: <_>::default(),
+                build_custom: <_>::default(),
+                checks: <_>::default(),
+                install: <_>::default(),
+            },
+        )
+    };
+    robo_toml.install = install;
+    fs::write(&path, toml::to_string_pretty(&robo_toml)?)?;
+    println!("Settings saved: {}", path.display().to_string().yellow());
+    Ok(())
+}
+
+pub fn install(
+    opts: &InstallCommand,
+    install_config: config::Install,
+    exec: &config::Exec,
+    build_config: config::Build,
+    build_custom: config::BuildCustom,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !opts.systemd {
+        return Err("Only systemd installations are supported, specify --systemd".into());
+    }
+    let install = config::Install {
+        host: opts.host.clone().or(install_config.host),
+        path: opts.path.clone().or(install_config.path),
+        service: opts.service.clone().or(install_config.service),
+        user: opts.user.clone().or(install_config.user),
+        cpus: opts.cpus.clone().or(install_config.cpus),
+        restart: opts.restart.clone().or(install_config.restart),
+        sudo: opts.sudo || install_config.sudo,
+    };
+    let binary = opts
+        .file
+        .clone()
+        .or_else(|| build_custom.command.as_ref().and(build_custom.file.clone()));
+    let name = if let Some(ref file) = binary {
+        file.file_name()
+            .ok_or("Invalid program file name")?
+            .to_string_lossy()
+            .to_string()
+    } else {
+        find_name_and_chdir().ok_or("Could not find Cargo.toml/binary name")?
+    };
+    let restart = install
+        .restart
+        .clone()
+        .unwrap_or_else(|| DEFAULT_RESTART.to_owned());
+    if !RESTART_POLICIES.contains(&restart.as_str()) {
+        return Err(format!(
+            "Invalid restart policy: {} (valid: {})",
+            restart,
+            RESTART_POLICIES.join(", ")
+        )
+        .into());
+    }
+    let settings = Settings {
+        service: install.service.clone().unwrap_or_else(|| name.clone()),
+        path: install
+            .path
+            .clone()
+            .unwrap_or_else(|| DEFAULT_PATH.to_owned()),
+        user: install
+            .user
+            .clone()
+            .unwrap_or_else(|| DEFAULT_USER.to_owned()),
+        cpus: install.cpus.clone(),
+        restart,
+        name,
+    };
+    let unit = generate_unit(&settings, exec);
+    if opts.save {
+        save_settings(install.clone())?;
+    }
+    if let Some(ref unit_file) = opts.unit_file {
+        fs::write(unit_file, unit)?;
+        println!("Unit file: {}", unit_file.display().to_string().yellow());
+        return report_ok();
+    }
+    let host = install
+        .host
+        .as_deref()
+        .ok_or("Host not specified (--host or robo.toml [install] host)")?;
+    println!("Host: {}", host.yellow());
+    println!("Service: {}", settings.service.yellow());
+    println!("Binary: {}", settings.binary_path().yellow());
+    print_exec(exec);
+    let program = if let Some(file) = binary.filter(|_| opts.file.is_some()) {
+        file
+    } else {
+        let mut cargo_target = opts
+            .cargo_target
+            .clone()
+            .or_else(|| build_config.target.clone());
+        if cargo_target.is_none() && build_custom.command.is_none() {
+            let machine = ssh(host, "uname -m")?;
+            cargo_target = Some(format!("{}-unknown-linux-gnu", machine));
+        }
+        flashing::compile(
+            None,
+            BuildOptions {
+                cargo: opts.cargo.clone(),
+                cargo_target,
+                cargo_args: opts.cargo_args.clone(),
+            },
+            build_config,
+            build_custom,
+        )?
+    };
+    let sudo = if install.sudo { "sudo " } else { "" };
+    let tmp_dir = ssh(host, "mktemp -d")?;
+    println!("Uploading...");
+    let unit_path = std::env::temp_dir().join(format!("{}.service", settings.service));
+    fs::write(&unit_path, unit)?;
+    let uploaded = scp(&program, host, &format!("{}/program", tmp_dir))
+        .and_then(|()| scp(&unit_path, host, &format!("{}/unit", tmp_dir)));
+    let _ = fs::remove_file(&unit_path);
+    if let Err(e) = uploaded {
+        let _ = ssh(host, &format!("rm -rf {}", tmp_dir));
+        return Err(e);
+    }
+    println!("Installing...");
+    let binary = shlex::try_quote(&settings.binary_path())?.into_owned();
+    let service = shlex::try_quote(&settings.service)?.into_owned();
+    let mut script = format!(
+        "set -e; \
+        {sudo}install -m 755 {tmp}/program {binary}; \
+        {sudo}install -m 644 {tmp}/unit /etc/systemd/system/{service}.service; \
+        rm -rf {tmp}; \
+        {sudo}systemctl daemon-reload; \
+        {sudo}systemctl enable {service}",
+        sudo = sudo,
+        tmp = tmp_dir,
+        binary = binary,
+        service = service,
+    );
+    if !opts.no_start {
+        write!(script, "; {}systemctl restart {}", sudo, service)?;
+    }
+    ssh(host, &script)?;
+    report_ok()
+}
@@ -1,34 +1,49 @@
-use std::{fs, time::Duration};
+use std::{env, fs, time::Duration};
 
-use arguments::{Args, SubCommand};
+use arguments::{Args, BundleCommand, BundleSubCommand, SubCommand};
 use clap::Parser;
-use common::{find_robo_toml, Mode};
-use ureq::Agent;
+use common::{find_robo_toml, find_workspace_root, CONFIG_FILE_NAME};
+use roboplc_client::{Client, Mode};
 
 use crate::config::Config;
 
-const API_PREFIX: &str = "/roboplc/api";
 const DEFAULT_TIMEOUT: u64 = 60;
 const TPL_DEFAULT_RS: &str = include_str!("../tpl/default.rs");
 
 mod arguments;
+mod bundle;
 mod common;
 mod config;
+mod doctor;
 mod flashing;
+mod install;
 mod project;
 mod remote;
-mod ureq_err;
+mod snapshot;
+mod tags;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() {
     #[cfg(target_os = "windows")]
     let _ansi_enabled = ansi_term::enable_ansi_support();
+    if let Err(e) = run() {
+        common::print_err(&format!("Error: {}", e));
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
     let mut maybe_url = args.url;
     let mut maybe_key = args.key;
+    // the remote name (if specified instead of URL), used to select the exec profile
+    let mut remote_name = None;
+    let mut global_exec = None;
     if let Some(ref u) = maybe_url {
         if !u.starts_with("http://") && !u.starts_with("https://") {
+            remote_name = Some(u.clone());
             // try to get url from global config
             if let Some(remote) = config::get_global_remote(u) {
+                global_exec = Some(remote.exec);
                 if let Some(url) = remote.url {
                     maybe_url = Some(url);
                 }
@@ -41,11 +56,35 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut maybe_timeout = args.timeout;
     let mut build_config = None;
     let mut build_custom = None;
+    let mut checks = None;
+    let mut install_config = None;
+    let mut exec = None;
+    if let SubCommand::ImportTags(ref opts) = args.subcmd {
+        tags::import(opts)?;
+        return Ok(());
+    }
+    if let SubCommand::SnapshotDiff(ref opts) = args.subcmd {
+        snapshot::diff(opts)?;
+        return Ok(());
+    }
     if let SubCommand::New(_) = args.subcmd {
         // do not parse robo.toml for `new` command
     } else if let Some(robo_toml_path) = find_robo_toml() {
-        let contents = fs::read_to_string(robo_toml_path)?;
-        let robo_toml: Config = toml::from_str(&contents)?;
+        let contents = fs::read_to_string(&robo_toml_path)?;
+        let mut robo_toml: Config = toml::from_str(&contents)?;
+        // workspace members inherit missing settings from the workspace robo.toml
+        if let Some(workspace_toml_path) = env::current_dir()
+            .ok()
+            .and_then(|dir| find_workspace_root(&dir))
+            .map(|root| root.join(CONFIG_FILE_NAME))
+            .filter(|path| {
+                path.exists()
+                    && fs::canonicalize(path).ok() != fs::canonicalize(&robo_toml_path).ok()
+            })
+        {
+            let contents = fs::read_to_string(workspace_toml_path)?;
+            robo_toml.inherit(toml::from_str(&contents)?);
+        }
         if maybe_url.is_none() {
             maybe_url = robo_toml.remote.url;
         }
@@ -57,6 +96,16 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
         build_config = Some(robo_toml.build);
         build_custom = Some(robo_toml.build_custom);
+        checks = Some(robo_toml.checks);
+        install_config = Some(robo_toml.install);
+        // the project profile for the remote, then the global remote one, then the defaults
+        exec = remote_name
+            .as_deref()
+            .and_then(|name| robo_toml.remote.profiles.remove(name))
+            .map(|profile| profile.exec)
+            .filter(|e| !e.is_empty())
+            .or_else(|| global_exec.take().filter(|e| !e.is_empty()))
+            .or(Some(robo_toml.remote.exec));
     }
     maybe_url = maybe_url.map(|v| {
         let mut u = v.trim_end_matches('/').to_owned();
@@ -69,43 +118,85 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         project::create(maybe_url, maybe_key, maybe_timeout, &opts)?;
         return Ok(());
     }
+    let timeout = maybe_timeout.unwrap_or(DEFAULT_TIMEOUT);
+    if let SubCommand::Bundle(BundleCommand {
+        subcmd: BundleSubCommand::Create(ref opts),
+    }) = args.subcmd
+    {
+        // the remote is optional, used to detect the cargo target only
+        let remote = maybe_url
+            .as_deref()
+            .zip(maybe_key.as_deref())
+            .map(|(url, key)| Client::new(url, key).timeout(Duration::from_secs(timeout)));
+        bundle::create(
+            remote.as_ref(),
+            opts,
+            build_config.unwrap_or_default(),
+            build_custom.unwrap_or_default(),
+        )?;
+        return Ok(());
+    }
+    if let SubCommand::Install(ref opts) = args.subcmd {
+        // self-managed hosts are accessed over SSH, the manager is not required
+        install::install(
+            opts,
+            install_config.unwrap_or_default(),
+            &exec.or(global_exec).unwrap_or_default(),
+            build_config.unwrap_or_default(),
+            build_custom.unwrap_or_default(),
+        )?;
+        return Ok(());
+    }
     let url = maybe_url.ok_or("URL not specified")?;
     let key = maybe_key.ok_or("Key not specified")?;
-    let timeout = maybe_timeout.unwrap_or(DEFAULT_TIMEOUT);
-    let agent: Agent = ureq::AgentBuilder::new()
-        .timeout_read(Duration::from_secs(timeout))
-        .timeout_write(Duration::from_secs(timeout))
-        .build();
+    let client = Client::new(&url, &key).timeout(Duration::from_secs(timeout));
     match args.subcmd {
-        SubCommand::New(_) => {
+        SubCommand::New(_)
+        | SubCommand::ImportTags(_)
+        | SubCommand::Install(_)
+        | SubCommand::SnapshotDiff(_) => {
             panic!("BUG");
         }
         SubCommand::Stat => {
-            remote::stat(&url, &key, agent)?;
+            remote::stat(&client)?;
         }
         SubCommand::Config => {
-            remote::set_mode(&url, &key, &agent, Mode::Config, true)?;
+            remote::set_mode(&client, Mode::Config, true)?;
         }
         SubCommand::Run => {
-            remote::set_mode(&url, &key, &agent, Mode::Run, true)?;
+            remote::set_mode(&client, Mode::Run, true)?;
         }
         SubCommand::Restart => {
-            remote::set_mode(&url, &key, &agent, Mode::Config, false)?;
-            remote::set_mode(&url, &key, &agent, Mode::Run, true)?;
+            remote::set_mode(&client, Mode::Config, false)?;
+            remote::set_mode(&client, Mode::Run, true)?;
         }
         SubCommand::Flash(opts) => {
             flashing::flash(
-                &url,
-                &key,
-                agent,
+                &client,
                 opts,
                 build_config.unwrap_or_default(),
                 build_custom.unwrap_or_default(),
+                checks.unwrap_or_default(),
+                exec.or(global_exec).unwrap_or_default(),
             )?;
         }
+        SubCommand::Promote(opts) => {
+            remote::promote(&client, &opts)?;
+        }
         SubCommand::Purge => {
-            remote::purge(&url, &key, agent)?;
+            remote::purge(&client)?;
+        }
+        SubCommand::Doctor => {
+            doctor::doctor(&client)?;
         }
+        SubCommand::Bundle(opts) => match opts.subcmd {
+            BundleSubCommand::Create(_) => {
+                panic!("BUG");
+            }
+            BundleSubCommand::Flash(opts) => {
+                bundle::flash(&client, &opts, &exec.or(global_exec).unwrap_or_default())?;
+            }
+        },
     }
     Ok(())
 }
@@ -1,10 +1,13 @@
-use std::env;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
 
 use colored::Colorize as _;
 
 use crate::{
     arguments::NewCommand,
-    common::CONFIG_FILE_NAME,
+    common::{find_workspace_root, CONFIG_FILE_NAME},
     config::{self, Config},
     TPL_DEFAULT_RS,
 };
@@ -16,6 +19,14 @@ pub fn create(
     opts: &NewCommand,
 ) -> Result<(), Box<dyn std::error::Error>> {
     println!("Creating new project: {}", opts.name.green().bold());
+    let workspace_root = if opts.standalone {
+        None
+    } else {
+        find_workspace_root(&env::current_dir()?)
+    };
+    if let Some(ref root) = workspace_root {
+        println!("Workspace: {}", root.display().to_string().yellow());
+    }
     let mut cmd = std::process::Command::new("cargo");
     cmd.arg("-q").arg("new").arg(&opts.name);
     if !opts.extras.is_empty() {
@@ -27,6 +38,9 @@ pub fn create(
     }
     let mut current_dir = env::current_dir()?;
     current_dir.push(&opts.name);
+    if let Some(ref root) = workspace_root {
+        add_workspace_member(root, &current_dir)?;
+    }
     env::set_current_dir(&current_dir)?;
     let mut robo_features: Vec<&str> = Vec::new();
     for feature in &opts.features {
@@ -41,9 +55,13 @@ pub fn create(
             key: maybe_key,
             url: maybe_url,
             timeout: maybe_timeout,
+            exec: <_>::default(),
+            profiles: <_>::default(),
         },
         build: <_>::default(),
         build_custom: <_>::default(),
+        checks: <_>::default(),
+        install: <_>::default(),
     };
     std::fs::write(CONFIG_FILE_NAME, toml::to_string_pretty(&robo_toml)?)?;
     std::fs::write("src/main.rs", prepare_main(TPL_DEFAULT_RS, &robo_features))?;
@@ -51,6 +69,80 @@ pub fn create(
     Ok(())
 }
 
+// cargo adds new crates to workspace members since 1.75, older versions only print a warning
+fn add_workspace_member(root: &Path, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let member = path
+        .strip_prefix(root)
+        .map_err(|_| "The project is outside of the workspace")?
+        .to_string_lossy()
+        .replace('\\', "/");
+    let cargo_toml_path = root.join("Cargo.toml");
+    let contents = fs::read_to_string(&cargo_toml_path)?;
+    let value = contents.parse::<toml::Value>()?;
+    let members: Vec<&str> = value["workspace"]
+        .get("members")
+        .and_then(toml::Value::as_array)
+        .map(|m| m.iter().filter_map(toml::Value::as_str).collect())
+        .unwrap_or_default();
+    if members.iter().any(|m| member_matches(m, &member)) {
+        return Ok(());
+    }
+    println!("Adding workspace member: {}", member.green().bold());
+    fs::write(&cargo_toml_path, insert_member(&contents, &member)?)?;
+    Ok(())
+}
+
+// supports trailing-asterisk globs only (e.g. `crates/*`), which covers the common layouts
+fn member_matches(pattern: &str, member: &str) -> bool {
+    let pattern = pattern.trim_end_matches('/');
+    if let Some(prefix) = pattern.strip_suffix("/*") {
+        PathBuf::from(member).parent() == Some(Path::new(prefix))
+    } else {
+        pattern == member
+    }
+}
+
+// inserts the member into the workspace manifest, keeping the formatting and comments
+fn insert_member(contents: &str, member: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let mut out = String::with_capacity(contents.len() + member.len() + 16);
+    let mut in_workspace = false;
+    let mut inserted = false;
+    for line in contents.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') && !trimmed.starts_with("[[") {
+            if in_workspace && !inserted {
+                out.push_str(&format!("members = [\"{}\"]\n", member));
+                inserted = true;
+            }
+            in_workspace = trimmed == "[workspace]";
+        }
+        out.push_str(line);
+        out.push('\n');
+        if in_workspace && !inserted && trimmed.starts_with("members") {
+            let Some(pos) = line.find('[') else {
+                return Err("Unable to parse workspace members".into());
+            };
+            out.truncate(out.len() - line.len() - 1);
+            out.push_str(&line[..=pos]);
+            out.push_str(&format!("\n    \"{}\",", member));
+            let rest = &line[pos + 1..];
+            if !rest.trim().is_empty() {
+                out.push_str("\n    ");
+                out.push_str(rest.trim_start());
+            }
+            out.push('\n');
+            inserted = true;
+        }
+    }
+    if !inserted {
+        if !in_workspace {
+            return Err("Workspace section not found".into());
+        }
+        out.push_str(&format!("members = [\"{}\"]\n", member));
+    }
+    Ok(out)
+}
+
 fn add_dependency(name: &str, features: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
     println!("Adding dependency: {}", name.green().bold());
     let mut cmd = std::process::Command::new("cargo");
@@ -1,48 +1,72 @@
-use ureq::Agent;
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use colored::Colorize as _;
+use roboplc_client::{Client, Mode};
 
 use crate::{
-    common::{report_ok, Mode, State},
-    ureq_err::{self, PrintErr},
-    API_PREFIX,
+    arguments::PromoteCommand,
+    common::{print_state, report_ok},
 };
 
-pub fn stat(url: &str, key: &str, agent: Agent) -> Result<(), Box<dyn std::error::Error>> {
-    let resp = agent
-        .post(&format!("{}{}/query.stats.program", url, API_PREFIX))
-        .set("x-auth-key", key)
-        .call()
-        .process_error()?;
-    let stats: State = resp.into_json()?;
-    stats.print_std();
+const PROMOTE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub fn stat(client: &Client) -> Result<(), Box<dyn std::error::Error>> {
+    print_state(&client.stat()?);
     Ok(())
 }
 
+/// Activates the staging slot and waits for the program to reach RUNNING state. If the program
+/// fails to start in time, the previous slot is activated back (unless disabled)
+pub fn promote(client: &Client, opts: &PromoteCommand) -> Result<(), Box<dyn std::error::Error>> {
+    client.promote_slot()?;
+    let wait = Duration::from_secs(opts.wait);
+    println!(
+        "Waiting up to {} sec for the program to reach RUNNING state...",
+        opts.wait
+    );
+    let started = Instant::now();
+    loop {
+        // errors are ignored, the manager may be busy during the program restart
+        if let Ok(state) = client.stat() {
+            if state.is_running() {
+                if let Some(slot) = state.slot {
+                    println!("Active slot: {}", slot.to_string().green());
+                }
+                return report_ok();
+            }
+        }
+        if started.elapsed() >= wait {
+            break;
+        }
+        thread::sleep(PROMOTE_POLL_INTERVAL);
+    }
+    if opts.no_rollback {
+        return Err("The program has not reached RUNNING state".into());
+    }
+    println!(
+        "{}",
+        "The program has not reached RUNNING state, rolling back".red()
+    );
+    client.rollback_slot()?;
+    Err("Promotion failed, rolled back to the previous slot".into())
+}
+
 pub fn set_mode(
-    url: &str,
-    key: &str,
-    agent: &Agent,
+    client: &Client,
     mode: Mode,
     report: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    agent
-        .post(&format!("{}{}/set.program.mode", url, API_PREFIX))
-        .set("x-auth-key", key)
-        .send_json(ureq::json!({
-             "mode": mode,
-        }))
-        .process_error()?;
+    client.set_mode(mode)?;
     if report {
         report_ok()?;
     }
     Ok(())
 }
 
-pub fn purge(url: &str, key: &str, agent: Agent) -> Result<(), Box<dyn std::error::Error>> {
-    ureq_err::PrintErr::process_error(
-        agent
-            .post(&format!("{}{}/purge.program.data", url, API_PREFIX))
-            .set("x-auth-key", key)
-            .call(),
-    )?;
+pub fn purge(client: &Client) -> Result<(), Box<dyn std::error::Error>> {
+    client.purge()?;
     report_ok()
 }
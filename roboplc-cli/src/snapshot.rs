@@ -0,0 +1,86 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use colored::Colorize as _;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::arguments::SnapshotDiffCommand;
+
+#[derive(Deserialize)]
+struct Section {
+    time: String,
+    data: Value,
+}
+
+#[derive(Deserialize)]
+struct Snapshot {
+    label: Option<String>,
+    time: String,
+    sections: BTreeMap<String, Section>,
+}
+
+fn load(path: &Path) -> Result<Snapshot, Box<dyn std::error::Error>> {
+    serde_json::from_slice(&fs::read(path)?)
+        .map_err(|e| format!("Invalid snapshot {}: {}", path.display(), e).into())
+}
+
+// objects are flattened, other values (including arrays) are compared as-is
+fn flatten<'a>(
+    path: &str,
+    value: &'a Value,
+    time: &'a str,
+    out: &mut BTreeMap<String, (&'a Value, &'a str)>,
+) {
+    if let Value::Object(map) = value {
+        for (k, v) in map {
+            flatten(&format!("{}.{}", path, k), v, time, out);
+        }
+    } else {
+        out.insert(path.to_owned(), (value, time));
+    }
+}
+
+fn values(snapshot: &Snapshot) -> BTreeMap<String, (&Value, &str)> {
+    let mut values = BTreeMap::new();
+    for (name, section) in &snapshot.sections {
+        flatten(name, &section.data, &section.time, &mut values);
+    }
+    values
+}
+
+pub fn diff(opts: &SnapshotDiffCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let first = load(&opts.first)?;
+    let second = load(&opts.second)?;
+    for (sign, snapshot) in [("-".red(), &first), ("+".green(), &second)] {
+        println!(
+            "{} {} {}",
+            sign,
+            snapshot.label.as_deref().unwrap_or("-").bold(),
+            snapshot.time
+        );
+    }
+    let before = values(&first);
+    let after = values(&second);
+    let mut paths: Vec<&String> = before.keys().chain(after.keys()).collect();
+    paths.sort();
+    paths.dedup();
+    let mut changed = 0;
+    for path in paths {
+        let b = before.get(path);
+        let a = after.get(path);
+        if b.map(|v| v.0) == a.map(|v| v.0) {
+            continue;
+        }
+        changed += 1;
+        println!("{}", path.yellow());
+        if let Some((value, time)) = b {
+            println!("  {} {} ({})", "-".red(), value.to_string().red(), time);
+        }
+        if let Some((value, time)) = a {
+            println!("  {} {} ({})", "+".green(), value.to_string().green(), time);
+        }
+    }
+    println!();
+    println!("Changed values: {}", changed.to_string().bold());
+    Ok(())
+}
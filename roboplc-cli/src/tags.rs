@@ -0,0 +1,493 @@
+use std::{collections::BTreeSet, fmt::Write as _, fs};
+
+use colored::Colorize as _;
+
+use crate::arguments::{ImportTagsCommand, TagFormat};
+
+// Rust keywords which can not be used as field names
+const KEYWORDS: &[&str] = &[
+    "as", "break", "const", "continue", "crate", "else", "enum", "extern", "false", "fn", "for",
+    "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return",
+    "self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use", "where",
+    "while", "async", "await", "dyn", "abstract", "become", "box", "do", "final", "macro",
+    "override", "priv", "typeof", "unsized", "virtual", "yield", "try",
+];
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Area {
+    Input,
+    Output,
+    Memory,
+}
+
+impl Area {
+    fn struct_name(self) -> &'static str {
+        match self {
+            Area::Input => "Inputs",
+            Area::Output => "Outputs",
+            Area::Memory => "Memory",
+        }
+    }
+    // the default Modbus register kind the area is usually exposed as
+    fn register_kind(self) -> char {
+        match self {
+            Area::Input => 'i',
+            Area::Output | Area::Memory => 'h',
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Address {
+    area: Area,
+    byte: u32,
+    bit: Option<u8>,
+}
+
+#[derive(Debug, Clone)]
+struct Tag {
+    name: String,
+    data_type: String,
+    address: Option<Address>,
+    // array dimension (L5X)
+    dimension: Option<u32>,
+    comment: Option<String>,
+}
+
+/// Rust type and size in bytes
+fn rust_type(data_type: &str) -> Option<(&'static str, u32)> {
+    let t = match data_type.to_uppercase().as_str() {
+        "BYTE" | "USINT" | "CHAR" => ("u8", 1),
+        "SINT" => ("i8", 1),
+        "WORD" | "UINT" => ("u16", 2),
+        "INT" => ("i16", 2),
+        "DWORD" | "UDINT" => ("u32", 4),
+        "DINT" | "TIME" => ("i32", 4),
+        "LWORD" | "ULINT" => ("u64", 8),
+        "LINT" => ("i64", 8),
+        "REAL" => ("f32", 4),
+        "LREAL" => ("f64", 8),
+        _ => return None,
+    };
+    Some(t)
+}
+
+fn is_bool(data_type: &str) -> bool {
+    data_type.eq_ignore_ascii_case("bool")
+}
+
+pub fn import(opts: &ImportTagsCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let source = fs::read_to_string(&opts.file)?;
+    let tags = match opts.format {
+        TagFormat::Tia => parse_tia_csv(&source)?,
+        TagFormat::L5x => parse_l5x(&source)?,
+    };
+    if tags.is_empty() {
+        return Err("No tags found".into());
+    }
+    let mut skipped = Vec::new();
+    let code = match opts.format {
+        TagFormat::Tia => generate_addressed(&tags, &mut skipped),
+        TagFormat::L5x => generate_sequential(&tags, opts, &mut skipped)?,
+    };
+    for (tag, reason) in skipped {
+        eprintln!(
+            "{} {} ({}): {}",
+            "Skipped".yellow(),
+            tag.name,
+            tag.data_type,
+            reason
+        );
+    }
+    if let Some(ref output) = opts.output {
+        fs::write(output, code)?;
+    } else {
+        print!("{}", code);
+    }
+    Ok(())
+}
+
+fn parse_address(s: &str) -> Option<Address> {
+    let s = s.trim().trim_start_matches('%').to_uppercase();
+    let mut chars = s.chars();
+    let area = match chars.next()? {
+        'I' | 'E' => Area::Input,
+        'Q' | 'A' => Area::Output,
+        'M' => Area::Memory,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    // skip the size letter (B, W, D, X)
+    let rest = rest.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+    let (byte, bit) = if let Some((byte, bit)) = rest.split_once('.') {
+        (byte.parse().ok()?, Some(bit.parse().ok()?))
+    } else {
+        (rest.parse().ok()?, None)
+    };
+    Some(Address { area, byte, bit })
+}
+
+fn split_csv_line(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Siemens TIA Portal PLC tag table, exported as CSV
+fn parse_tia_csv(source: &str) -> Result<Vec<Tag>, Box<dyn std::error::Error>> {
+    let mut lines = source
+        .trim_start_matches('\u{feff}')
+        .lines()
+        .filter(|l| !l.trim().is_empty());
+    let header = lines.next().ok_or("Empty tag table")?;
+    let delimiter = [';', ',', '\t']
+        .into_iter()
+        .max_by_key(|d| header.matches(*d).count())
+        .unwrap();
+    let columns: Vec<String> = split_csv_line(header, delimiter)
+        .into_iter()
+        .map(|c| c.trim().to_lowercase())
+        .collect();
+    let column = |names: &[&str]| columns.iter().position(|c| names.contains(&c.as_str()));
+    let col_name = column(&["name"]).ok_or("Column \"Name\" not found")?;
+    let col_type = column(&["data type", "datatype"]).ok_or("Column \"Data Type\" not found")?;
+    let col_address =
+        column(&["logical address", "address"]).ok_or("Column \"Logical Address\" not found")?;
+    let col_comment = column(&["comment"]);
+    let mut tags = Vec::new();
+    for line in lines {
+        let fields = split_csv_line(line, delimiter);
+        let field = |i: usize| fields.get(i).map(|v| v.trim()).unwrap_or_default();
+        let name = field(col_name);
+        if name.is_empty() {
+            continue;
+        }
+        tags.push(Tag {
+            name: name.to_owned(),
+            data_type: field(col_type).to_owned(),
+            address: parse_address(field(col_address)),
+            dimension: None,
+            comment: col_comment
+                .map(field)
+                .filter(|c| !c.is_empty())
+                .map(ToOwned::to_owned),
+        });
+    }
+    Ok(tags)
+}
+
+fn xml_attr(element: &str, name: &str) -> Option<String> {
+    let mut pos = 0;
+    let pattern = format!("{}=\"", name);
+    while let Some(found) = element[pos..].find(&pattern) {
+        let start = pos + found;
+        let value_start = start + pattern.len();
+        if start == 0 || element[..start].ends_with(char::is_whitespace) {
+            let value_end = value_start + element[value_start..].find('"')?;
+            return Some(xml_unescape(&element[value_start..value_end]));
+        }
+        pos = value_start;
+    }
+    None
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Allen-Bradley L5X export, controller and program base tags of atomic types
+fn parse_l5x(source: &str) -> Result<Vec<Tag>, Box<dyn std::error::Error>> {
+    if !source.contains("<RSLogix5000Content") {
+        return Err("Not a L5X file".into());
+    }
+    let mut tags = Vec::new();
+    let mut pos = 0;
+    while let Some(found) = source[pos..].find("<Tag ") {
+        let start = pos + found;
+        let end = start + source[start..].find('>').ok_or("Invalid L5X file")?;
+        let element = &source[start..end];
+        pos = end;
+        if xml_attr(element, "TagType").map_or(false, |t| t != "Base") {
+            continue;
+        }
+        let name = xml_attr(element, "Name").ok_or("Tag name not found")?;
+        let data_type = xml_attr(element, "DataType").unwrap_or_default();
+        let dimension = xml_attr(element, "Dimensions")
+            .map(|d| d.trim().parse::<u32>())
+            .transpose()
+            .map_err(|_| format!("Unsupported tag dimensions: {}", name))?
+            .filter(|d| *d > 0);
+        let mut comment = None;
+        if !element.ends_with('/') {
+            let body_end = source[end..]
+                .find("</Tag>")
+                .map_or(source.len(), |e| end + e);
+            let body = &source[end..body_end];
+            if let Some(d) = body.find("<Description>") {
+                let d = &body[d + "<Description>".len()..];
+                if let Some(d_end) = d.find("</Description>") {
+                    let d = d[..d_end].trim();
+                    let d = d
+                        .strip_prefix("<![CDATA[")
+                        .and_then(|d| d.strip_suffix("]]>"))
+                        .map_or_else(|| xml_unescape(d), ToOwned::to_owned);
+                    comment = Some(d.trim().to_owned()).filter(|d| !d.is_empty());
+                }
+            }
+            pos = body_end;
+        }
+        tags.push(Tag {
+            name,
+            data_type,
+            address: None,
+            dimension,
+            comment,
+        });
+    }
+    Ok(tags)
+}
+
+fn to_snake_case(name: &str) -> String {
+    let mut result = String::new();
+    let mut prev_lower = false;
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            if c.is_ascii_uppercase() && prev_lower {
+                result.push('_');
+            }
+            prev_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+            result.push(c.to_ascii_lowercase());
+        } else {
+            if !result.ends_with('_') {
+                result.push('_');
+            }
+            prev_lower = false;
+        }
+    }
+    let mut result = result.trim_matches('_').to_owned();
+    if result.is_empty() || result.starts_with(|c: char| c.is_ascii_digit()) {
+        result.insert(0, '_');
+    }
+    if KEYWORDS.contains(&result.as_str()) {
+        result.push('_');
+    }
+    result
+}
+
+fn unique_ident(name: &str, used: &mut BTreeSet<String>) -> String {
+    let base = to_snake_case(name);
+    let mut ident = base.clone();
+    let mut n = 1;
+    while !used.insert(ident.clone()) {
+        n += 1;
+        ident = format!("{}_{}", base, n);
+    }
+    ident
+}
+
+fn write_header(code: &mut String, source: &str) {
+    writeln!(
+        code,
+        "// Generated by `robo import-tags` from {}, review before use\n",
+        source
+    )
+    .unwrap();
+    writeln!(code, "#![allow(dead_code)]\n").unwrap();
+    writeln!(code, "use binrw::binrw;\n").unwrap();
+}
+
+fn write_comment(code: &mut String, tag: &Tag) {
+    let address = tag.address.as_ref().map(|a| match a.bit {
+        Some(bit) => format!("{}.{}", a.byte, bit),
+        None => a.byte.to_string(),
+    });
+    let mut doc = tag.name.clone();
+    if let Some(ref comment) = tag.comment {
+        write!(doc, ": {}", comment).unwrap();
+    }
+    if let Some(address) = address {
+        write!(doc, " ({})", address).unwrap();
+    }
+    writeln!(code, "    /// {}", doc).unwrap();
+}
+
+/// TIA tags: a structure per memory area, fields are placed by their byte addresses, bits are
+/// grouped into bytes with accessor methods
+fn generate_addressed<'a>(tags: &'a [Tag], skipped: &mut Vec<(&'a Tag, &'static str)>) -> String {
+    let mut code = String::new();
+    write_header(&mut code, "TIA Portal tag table");
+    for area in [Area::Input, Area::Output, Area::Memory] {
+        // (byte, size, tag)
+        let mut fields: Vec<(u32, u32, &Tag)> = Vec::new();
+        for tag in tags {
+            let Some(ref address) = tag.address else {
+                if area == Area::Input {
+                    skipped.push((tag, "no address"));
+                }
+                continue;
+            };
+            if address.area != area {
+                continue;
+            }
+            let size = if is_bool(&tag.data_type) {
+                1
+            } else if let Some((_, size)) = rust_type(&tag.data_type) {
+                size
+            } else {
+                skipped.push((tag, "unsupported data type"));
+                continue;
+            };
+            fields.push((address.byte, size, tag));
+        }
+        if fields.is_empty() {
+            continue;
+        }
+        fields.sort_by_key(|(byte, _, tag)| (*byte, tag.address.as_ref().unwrap().bit));
+        let start = fields[0].0;
+        let struct_name = area.struct_name();
+        let mut used = BTreeSet::new();
+        let mut accessors = String::new();
+        writeln!(
+            code,
+            "#[binrw]\n#[brw(big)]\n#[derive(Clone, Debug, Default)]"
+        )
+        .unwrap();
+        writeln!(code, "pub struct {} {{", struct_name).unwrap();
+        // registers are 16-bit
+        let mut offset = start - start % 2;
+        let mut last_field_pos = 0;
+        let mut i = 0;
+        while i < fields.len() {
+            let (byte, size, tag) = fields[i];
+            if byte < offset {
+                skipped.push((tag, "overlaps the previous tag"));
+                i += 1;
+                continue;
+            }
+            last_field_pos = code.len();
+            if byte > offset {
+                writeln!(code, "    #[brw(pad_before = {})]", byte - offset).unwrap();
+            }
+            if is_bool(&tag.data_type) {
+                // all bits of the byte
+                let ident = format!("bits_{}", byte);
+                used.insert(ident.clone());
+                while i < fields.len() && fields[i].0 == byte && is_bool(&fields[i].2.data_type) {
+                    let bit_tag = fields[i].2;
+                    let bit = bit_tag.address.as_ref().unwrap().bit.unwrap_or(0);
+                    write_comment(&mut code, bit_tag);
+                    let name = unique_ident(&bit_tag.name, &mut used);
+                    writeln!(
+                        accessors,
+                        "    pub fn {name}(&self) -> bool {{\n        self.{ident} & (1 << {bit}) != 0\n    }}\n    pub fn set_{name}(&mut self, value: bool) {{\n        if value {{\n            self.{ident} |= 1 << {bit};\n        }} else {{\n            self.{ident} &= !(1 << {bit});\n        }}\n    }}",
+                    )
+                    .unwrap();
+                    i += 1;
+                }
+                writeln!(code, "    pub {}: u8,", ident).unwrap();
+            } else {
+                let (t, _) = rust_type(&tag.data_type).unwrap();
+                write_comment(&mut code, tag);
+                writeln!(
+                    code,
+                    "    pub {}: {},",
+                    unique_ident(&tag.name, &mut used),
+                    t
+                )
+                .unwrap();
+                i += 1;
+            }
+            offset = byte + size;
+        }
+        if offset % 2 != 0 {
+            code.insert_str(last_field_pos, "    #[brw(pad_after = 1)]\n");
+        }
+        writeln!(code, "}}\n").unwrap();
+        if !accessors.is_empty() {
+            writeln!(code, "impl {} {{\n{}}}\n", struct_name, accessors).unwrap();
+        }
+        let upper = struct_name.to_uppercase();
+        writeln!(
+            code,
+            "// the area is assumed to be exposed via Modbus with the same word offsets\npub const {}_REGISTER: &str = \"{}{}\";\npub const {}_COUNT: u16 = {};\n",
+            upper,
+            area.register_kind(),
+            start / 2,
+            upper,
+            (offset + 1) / 2 - start / 2
+        )
+        .unwrap();
+    }
+    code
+}
+
+/// L5X tags: a single structure with tags in the declaration order, each tag is aligned to 16-bit
+/// Modbus registers
+fn generate_sequential<'a>(
+    tags: &'a [Tag],
+    opts: &ImportTagsCommand,
+    skipped: &mut Vec<(&'a Tag, &'static str)>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut code = String::new();
+    write_header(&mut code, "L5X export");
+    let mut used = BTreeSet::new();
+    let mut registers = 0u32;
+    writeln!(
+        code,
+        "#[binrw]\n#[brw(big)]\n#[derive(Clone, Debug, Default)]"
+    )
+    .unwrap();
+    writeln!(code, "pub struct {} {{", opts.struct_name).unwrap();
+    for tag in tags {
+        let (t, size) = if is_bool(&tag.data_type) {
+            // a boolean occupies a whole register
+            ("u16", 2)
+        } else if let Some(t) = rust_type(&tag.data_type) {
+            t
+        } else {
+            skipped.push((tag, "unsupported data type"));
+            continue;
+        };
+        let count = tag.dimension.unwrap_or(1);
+        let bytes = size * count;
+        write_comment(&mut code, tag);
+        if bytes % 2 != 0 {
+            writeln!(code, "    #[brw(pad_after = 1)]").unwrap();
+        }
+        let ident = unique_ident(&tag.name, &mut used);
+        if let Some(dim) = tag.dimension {
+            writeln!(code, "    pub {}: [{}; {}],", ident, t, dim).unwrap();
+        } else {
+            writeln!(code, "    pub {}: {},", ident, t).unwrap();
+        }
+        registers += (bytes + 1) / 2;
+    }
+    writeln!(code, "}}\n").unwrap();
+    let upper = to_snake_case(&opts.struct_name).to_uppercase();
+    let register_count = u16::try_from(registers).map_err(|_| "Too many registers")?;
+    writeln!(
+        code,
+        "pub const {}_REGISTER: &str = \"{}\";\npub const {}_COUNT: u16 = {};",
+        upper, opts.base_register, upper, register_count
+    )
+    .unwrap();
+    Ok(code)
+}
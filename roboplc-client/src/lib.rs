@@ -0,0 +1,284 @@
+#![ doc = include_str!( concat!( env!( "CARGO_MANIFEST_DIR" ), "/", "README.md" ) ) ]
+use core::fmt;
+use std::{collections::BTreeMap, path::Path, time::Duration};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+use ureq::Agent;
+use ureq_multipart::MultipartBuilder;
+
+const API_PREFIX: &str = "/roboplc/api";
+
+/// The default API timeout
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The client error type
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// The manager has returned an error
+    #[error("{message} ({status})")]
+    Api { status: u16, message: String },
+    /// Transport (network, TLS) errors
+    #[error("transport error: {0}")]
+    Transport(String),
+    /// I/O errors (e.g. unable to read a file to flash)
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    /// Invalid data received / parameters provided
+    #[error("invalid data: {0}")]
+    InvalidData(String),
+}
+
+impl From<ureq::Error> for Error {
+    fn from(err: ureq::Error) -> Self {
+        match err {
+            ureq::Error::Status(status, response) => Error::Api {
+                status,
+                message: response.into_string().unwrap_or_default(),
+            },
+            ureq::Error::Transport(e) => Error::Transport(e.to_string()),
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::InvalidData(err.to_string())
+    }
+}
+
+/// Program mode
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    Run,
+    Config,
+    Unknown,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mode::Run => write!(f, "RUN"),
+            Mode::Config => write!(f, "CONFIG"),
+            Mode::Unknown => write!(f, "UNKNOWN"),
+        }
+    }
+}
+
+/// Program slot for canary/AB deployments
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Slot {
+    A,
+    B,
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Slot::A => write!(f, "a"),
+            Slot::B => write!(f, "b"),
+        }
+    }
+}
+
+/// Program state
+#[derive(Debug, Serialize, Deserialize)]
+pub struct State {
+    pub pid: Option<u32>,
+    pub mode: Mode,
+    pub memory_used: Option<u64>,
+    /// Program uptime in seconds
+    pub run_time: Option<u64>,
+    /// Controller state, as reported by the program state beacon
+    #[serde(default)]
+    pub state: Option<String>,
+    #[serde(default)]
+    pub slot: Option<Slot>,
+    #[serde(default)]
+    pub staging_slot: Option<Slot>,
+    /// Status annotation, pushed by the program
+    #[serde(default)]
+    pub status: Option<String>,
+    /// Progress (percents), pushed by the program
+    #[serde(default)]
+    pub progress: Option<f32>,
+}
+
+impl State {
+    /// The program is in RUN mode and the controller reports RUNNING state
+    pub fn is_running(&self) -> bool {
+        self.mode == Mode::Run
+            && self.pid.is_some()
+            && self
+                .state
+                .as_deref()
+                .map_or(false, |s| s.eq_ignore_ascii_case("running"))
+    }
+}
+
+/// Remote kernel information
+#[derive(Deserialize, Debug)]
+pub struct KernelInfo {
+    pub machine: String,
+    #[serde(default)]
+    pub release: Option<String>,
+    #[serde(default)]
+    pub version: Option<String>,
+}
+
+impl KernelInfo {
+    /// Returns the cargo target for the remote machine
+    pub fn to_machine_cargo_target(&self) -> String {
+        format!("{}-unknown-linux-gnu", self.machine)
+    }
+}
+
+/// Flash parameters
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct FlashParams {
+    force: bool,
+    run: bool,
+    slot: Option<Slot>,
+    // not sent if empty to keep compatibility with older managers
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    args: Vec<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    env: BTreeMap<String, String>,
+}
+
+impl FlashParams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Automatically put the remote in CONFIG mode
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+    /// Put the remote in RUN mode after flashing
+    pub fn run(mut self, run: bool) -> Self {
+        self.run = run;
+        self
+    }
+    /// Flash into a staging slot, activate the program with [`Client::promote_slot()`]
+    pub fn slot(mut self, slot: Option<Slot>) -> Self {
+        self.slot = slot;
+        self
+    }
+    /// Program arguments, recorded by the manager
+    pub fn args(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+    /// Program environment variables, recorded by the manager
+    pub fn env(mut self, env: BTreeMap<String, String>) -> Self {
+        self.env = env;
+        self
+    }
+}
+
+/// Manager API client
+#[derive(Clone)]
+pub struct Client {
+    url: String,
+    key: String,
+    agent: Agent,
+}
+
+impl Client {
+    /// Creates a new client. If the URL has no scheme, `http://` is used
+    pub fn new(url: &str, key: &str) -> Self {
+        let mut url = url.trim_end_matches('/').to_owned();
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            url = format!("http://{}", url);
+        }
+        Self {
+            url,
+            key: key.to_owned(),
+            agent: agent(DEFAULT_TIMEOUT),
+        }
+    }
+    /// Sets API read/write timeout (the default is 60 seconds)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.agent = agent(timeout);
+        self
+    }
+    /// Returns the manager URL
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+    fn request(&self, method: &str) -> ureq::Request {
+        self.agent
+            .post(&format!("{}{}/{}", self.url, API_PREFIX, method))
+            .set("x-auth-key", &self.key)
+    }
+    /// Calls an API method without parameters
+    pub fn call<T: DeserializeOwned>(&self, method: &str) -> Result<T> {
+        Ok(self.request(method).call()?.into_json()?)
+    }
+    /// Calls an API method, the result is ignored
+    pub fn call0(&self, method: &str) -> Result<()> {
+        self.request(method).call()?;
+        Ok(())
+    }
+    /// Gets the program state
+    pub fn stat(&self) -> Result<State> {
+        self.call("query.stats.program")
+    }
+    /// Gets the remote kernel information
+    pub fn kernel_info(&self) -> Result<KernelInfo> {
+        self.call("query.info.kernel")
+    }
+    /// Switches the program mode
+    pub fn set_mode(&self, mode: Mode) -> Result<()> {
+        self.request("set.program.mode")
+            .send_json(json!({ "mode": mode }))?;
+        Ok(())
+    }
+    /// Switches the program to CONFIG mode and back to RUN
+    pub fn restart(&self) -> Result<()> {
+        self.set_mode(Mode::Config)?;
+        self.set_mode(Mode::Run)
+    }
+    /// Flashes a program file
+    pub fn flash(&self, file: &Path, params: &FlashParams) -> Result<()> {
+        if !file.exists() {
+            return Err(Error::InvalidData(format!(
+                "file not found: {}",
+                file.display()
+            )));
+        }
+        let (content_type, data) = MultipartBuilder::new()
+            .add_file("file", file)?
+            .add_text("params", &serde_json::to_string(params)?)?
+            .finish()?;
+        self.request("flash")
+            .set("content-type", &content_type)
+            .send_bytes(&data)?;
+        Ok(())
+    }
+    /// Activates the program flashed into the staging slot
+    pub fn promote_slot(&self) -> Result<()> {
+        self.call0("promote.program.slot")
+    }
+    /// Activates the previous program slot back
+    pub fn rollback_slot(&self) -> Result<()> {
+        self.call0("rollback.program.slot")
+    }
+    /// Purges the program data directory
+    pub fn purge(&self) -> Result<()> {
+        self.call0("purge.program.data")
+    }
+}
+
+fn agent(timeout: Duration) -> Agent {
+    ureq::AgentBuilder::new()
+        .timeout_read(timeout)
+        .timeout_write(timeout)
+        .build()
+}
@@ -25,7 +25,8 @@ fn lowercase_first_letter(s: &str) -> String {
 ///
 /// * `name` - Specifies the name of the worker. The value must be a quoted string. The name must
 /// be unique and must be 15 characters or less. If not specified, the default is the the structure
-/// name with the first letter in lowercase
+/// name with the first letter in lowercase. Duplicate names across the crate can be detected at
+/// compile time with `roboplc::build::check_worker_names()` in the build script
 ///
 /// * `stack_size` - Specifies the stack size for the worker
 ///
@@ -43,6 +44,18 @@ fn lowercase_first_letter(s: &str) -> String {
 /// * `cpu` - Specifies the CPU affinity for the worker. The value can be a single CPU number or a
 /// range of CPUs separated by a dash. The value can be a quoted string or an integer
 ///
+/// * `pause_in` - Specifies controller operation modes the worker is paused in. The value must be
+/// a quoted string with comma-separated modes: `normal`, `degraded`, `maintenance`
+///
+/// * `commands` - Specifies the capacity of the worker's command channel. If not specified, the
+/// worker has got no command channel
+///
+/// * `importance` - Specifies the worker importance class for load shedding: `low`, `normal`,
+/// `high` or `critical`. If not specified, the default is `normal`
+///
+/// * `restart` - Specifies the worker restart policy: `never`, `on_failure` or `always`. If not
+/// specified, the default is `never`
+///
 /// Example:
 ///
 /// ```rust
@@ -59,6 +72,12 @@ fn lowercase_first_letter(s: &str) -> String {
 /// struct MyWorker2 {
 ///  // some fields
 /// }
+///
+/// #[derive(WorkerOpts)]
+/// #[worker_opts(name = "logger", pause_in = "degraded,maintenance", restart = "on_failure")]
+/// struct Logger {
+///  // some fields
+/// }
 /// ```
 ///
 ///
@@ -77,6 +96,10 @@ pub fn worker_opts_derive(input: TokenStream) -> TokenStream {
     let mut priority = None;
     let mut cpus = Vec::new();
     let mut blocking = false;
+    let mut pause_in = Vec::new();
+    let mut commands = None;
+    let mut importance = None;
+    let mut restart = None;
 
     for attr in input.attrs {
         if attr.path.is_ident("worker_opts") {
@@ -132,6 +155,32 @@ pub fn worker_opts_derive(input: TokenStream) -> TokenStream {
                                     panic!("Invalid cpu value: {}", value);
                                 }
                             }
+                        } else if path.is_ident("commands") {
+                            if let Lit::Int(lit_int) = lit {
+                                commands = Some(lit_int.base10_parse::<usize>().unwrap());
+                            } else {
+                                panic!("worker commands must be usize");
+                            }
+                        } else if path.is_ident("importance") {
+                            if let Lit::Str(lit_str) = lit {
+                                importance = Some(parse_importance(&lit_str.value()));
+                            } else {
+                                panic!("worker importance must be a quoted string");
+                            }
+                        } else if path.is_ident("restart") {
+                            if let Lit::Str(lit_str) = lit {
+                                restart = Some(parse_restart_policy(&lit_str.value()));
+                            } else {
+                                panic!("worker restart must be a quoted string");
+                            }
+                        } else if path.is_ident("pause_in") {
+                            if let Lit::Str(lit_str) = lit {
+                                for mode in lit_str.value().split(',') {
+                                    pause_in.push(parse_operation_mode(mode.trim()));
+                                }
+                            } else {
+                                panic!("worker pause_in must be a quoted string");
+                            }
                         } else {
                             panic!("Unknown attribute: {:?}", path);
                         }
@@ -208,6 +257,42 @@ fn worker_is_blocking(&self) -> bool {
     } else {
         quote! {}
     };
+    let pause_in_impl = if pause_in.is_empty() {
+        quote! {}
+    } else {
+        quote! {
+            fn worker_paused_in(&self) -> &[::roboplc::controller::OperationMode] {
+                &[#(#pause_in),*]
+            }
+        }
+    };
+    let commands_impl = if let Some(c) = commands {
+        quote! {
+            fn worker_command_capacity(&self) -> Option<usize> {
+                Some(#c)
+            }
+        }
+    } else {
+        quote! {}
+    };
+    let importance_impl = if let Some(i) = importance {
+        quote! {
+            fn worker_importance(&self) -> ::roboplc::controller::Importance {
+                #i
+            }
+        }
+    } else {
+        quote! {}
+    };
+    let restart_impl = if let Some(r) = restart {
+        quote! {
+            fn worker_restart_policy(&self) -> ::roboplc::controller::RestartPolicy {
+                #r
+            }
+        }
+    } else {
+        quote! {}
+    };
     let expanded = quote! {
         impl ::roboplc::controller::WorkerOptions for #name {
             fn worker_name(&self) -> &str {
@@ -219,6 +304,10 @@ fn worker_name(&self) -> &str {
             #priority_impl
             #cpus_impl
             #blocking_impl
+            #pause_in_impl
+            #commands_impl
+            #importance_impl
+            #restart_impl
 
         }
     };
@@ -226,6 +315,34 @@ fn worker_name(&self) -> &str {
     expanded.into()
 }
 
+fn parse_operation_mode(mode: &str) -> proc_macro2::TokenStream {
+    match mode.to_lowercase().as_str() {
+        "normal" => quote! { ::roboplc::controller::OperationMode::Normal },
+        "degraded" => quote! { ::roboplc::controller::OperationMode::Degraded },
+        "maintenance" => quote! { ::roboplc::controller::OperationMode::Maintenance },
+        v => panic!("Unknown operation mode: {}", v),
+    }
+}
+
+fn parse_importance(importance: &str) -> proc_macro2::TokenStream {
+    match importance.to_lowercase().as_str() {
+        "low" => quote! { ::roboplc::controller::Importance::Low },
+        "normal" => quote! { ::roboplc::controller::Importance::Normal },
+        "high" => quote! { ::roboplc::controller::Importance::High },
+        "critical" => quote! { ::roboplc::controller::Importance::Critical },
+        v => panic!("Unknown worker importance: {}", v),
+    }
+}
+
+fn parse_restart_policy(policy: &str) -> proc_macro2::TokenStream {
+    match policy.to_lowercase().as_str() {
+        "never" => quote! { ::roboplc::controller::RestartPolicy::Never },
+        "on_failure" => quote! { ::roboplc::controller::RestartPolicy::OnFailure },
+        "always" => quote! { ::roboplc::controller::RestartPolicy::Always },
+        v => panic!("Unknown worker restart policy: {}", v),
+    }
+}
+
 fn parse_scheduling(lit: &Lit) -> String {
     match lit {
         Lit::Str(lit_str) => lit_str.value(),
@@ -233,3 +350,492 @@ fn parse_scheduling(lit: &Lit) -> String {
         _ => "other".to_string(),
     }
 }
+
+/// Automatically implements the `Schema` trait (requires `schema` crate feature)
+///
+/// Field descriptions are taken from doc comments. The attribute `schema` can be used to specify
+/// additional field properties:
+///
+/// * `unit` - Specifies the field unit (a quoted string)
+///
+/// * `access` - Specifies the field access mode: `r` (default), `w` or `rw`
+///
+/// * `description` - Overrides the field description
+///
+/// * `skip` - Excludes the field from the schema
+///
+/// Example:
+///
+/// ```rust,ignore
+/// use roboplc::schema::Schema;
+///
+/// #[derive(Schema)]
+/// struct Variables {
+///     /// Tank level
+///     #[schema(unit = "m", access = "r")]
+///     level: f32,
+///     #[schema(skip)]
+///     counter: u64,
+/// }
+/// ```
+///
+/// # Panics
+///
+/// Will panic on invalid attributes or if the macro is used for unions
+#[proc_macro_derive(Schema, attributes(schema))]
+pub fn schema_derive(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    let name_str = name.to_string();
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let schema = match &input.data {
+        syn::Data::Struct(data) => {
+            let fields = schema_fields(&data.fields);
+            quote! {
+                ::roboplc::schema::TypeSchema::Struct {
+                    name: #name_str.to_owned(),
+                    fields: vec![#(#fields),*],
+                }
+            }
+        }
+        syn::Data::Enum(data) => {
+            let variants = data.variants.iter().map(|variant| {
+                let variant_name = variant.ident.to_string();
+                let fields = schema_fields(&variant.fields);
+                let description = doc_description(&variant.attrs)
+                    .map(|d| quote! { .description(#d) })
+                    .unwrap_or_default();
+                quote! {
+                    ::roboplc::schema::Variant::new(#variant_name, vec![#(#fields),*])
+                        #description
+                }
+            });
+            quote! {
+                ::roboplc::schema::TypeSchema::Enum {
+                    name: #name_str.to_owned(),
+                    variants: vec![#(#variants),*],
+                }
+            }
+        }
+        syn::Data::Union(_) => panic!("Schema can not be derived for unions"),
+    };
+    let expanded = quote! {
+        impl #impl_generics ::roboplc::schema::Schema for #name #ty_generics #where_clause {
+            fn schema() -> ::roboplc::schema::TypeSchema {
+                #schema
+            }
+        }
+    };
+    expanded.into()
+}
+
+fn doc_description(attrs: &[syn::Attribute]) -> Option<String> {
+    let lines: Vec<String> = attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("doc"))
+        .filter_map(|attr| {
+            if let Ok(Meta::NameValue(MetaNameValue {
+                lit: Lit::Str(lit_str),
+                ..
+            })) = attr.parse_meta()
+            {
+                Some(lit_str.value().trim().to_owned())
+            } else {
+                None
+            }
+        })
+        .collect();
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join(" "))
+    }
+}
+
+fn schema_fields(fields: &syn::Fields) -> Vec<proc_macro2::TokenStream> {
+    let mut result = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let field_name = field
+            .ident
+            .as_ref()
+            .map_or_else(|| i.to_string(), ToString::to_string);
+        let ty = &field.ty;
+        let type_name = quote!(#ty).to_string().replace(' ', "");
+        let mut unit = None;
+        let mut access = None;
+        let mut description = doc_description(&field.attrs);
+        let mut skip = false;
+        for attr in &field.attrs {
+            if !attr.path.is_ident("schema") {
+                continue;
+            }
+            let Ok(Meta::List(meta_list)) = attr.parse_meta() else {
+                panic!("unable to parse schema attribute");
+            };
+            for meta in &meta_list.nested {
+                match meta {
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("skip") => {
+                        skip = true;
+                    }
+                    NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                        path,
+                        lit: Lit::Str(lit_str),
+                        ..
+                    })) => {
+                        if path.is_ident("unit") {
+                            unit = Some(lit_str.value());
+                        } else if path.is_ident("access") {
+                            access = Some(match lit_str.value().as_str() {
+                                "r" => quote! { ::roboplc::schema::Access::Read },
+                                "w" => quote! { ::roboplc::schema::Access::Write },
+                                "rw" => quote! { ::roboplc::schema::Access::ReadWrite },
+                                v => panic!("Unknown access mode: {}", v),
+                            });
+                        } else if path.is_ident("description") {
+                            description = Some(lit_str.value());
+                        } else {
+                            panic!("Unknown attribute: {:?}", path);
+                        }
+                    }
+                    _ => panic!("invalid schema attribute"),
+                }
+            }
+        }
+        if skip {
+            continue;
+        }
+        let unit = unit.map(|u| quote! { .unit(#u) }).unwrap_or_default();
+        let access = access.map(|a| quote! { .access(#a) }).unwrap_or_default();
+        let description = description
+            .map(|d| quote! { .description(#d) })
+            .unwrap_or_default();
+        result.push(quote! {
+            ::roboplc::schema::Field::new(#field_name, #type_name)
+                #unit
+                #access
+                #description
+        });
+    }
+    result
+}
+
+/// Derives `roboplc::frame::Frame` for fixed-size binary frames (structs with named fields), which
+/// produces a human-readable frame specification (offsets, sizes, types) as const data
+///
+/// Field attributes:
+///
+/// * `unit` - field unit
+///
+/// * `description` - field description (the doc comment is used by default)
+///
+/// Struct attributes:
+///
+/// * `name` - frame name (the struct name is used by default)
+///
+/// * `endian` - "big" or "little" (taken from `#[brw(...)]`/`#[br(...)]` by default)
+///
+/// Field types must implement `Frame` as well (primitive numbers, arrays and other frames).
+///
+/// ```rust,ignore
+/// use roboplc::frame::Frame;
+///
+/// #[binrw]
+/// #[brw(big)]
+/// #[derive(Frame)]
+/// struct Telemetry {
+///     /// Motor speed
+///     #[frame(unit = "rpm")]
+///     speed: u32,
+///     currents: [f32; 3],
+/// }
+/// ```
+///
+/// # Panics
+///
+/// Will panic on invalid attributes, on field-level binrw attributes (as they change the layout)
+/// or if the macro is used for generic types, enums, unions or tuple structs
+#[proc_macro_derive(Frame, attributes(frame))]
+pub fn frame_derive(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    if !input.generics.params.is_empty() {
+        panic!("Frame can not be derived for generic types");
+    }
+    let syn::Data::Struct(syn::DataStruct {
+        fields: syn::Fields::Named(fields),
+        ..
+    }) = &input.data
+    else {
+        panic!("Frame can be derived for structs with named fields only");
+    };
+    let mut frame_name = name.to_string();
+    let mut endian: Option<String> = None;
+    for attr in &input.attrs {
+        if attr.path.is_ident("brw") || attr.path.is_ident("br") || attr.path.is_ident("bw") {
+            // other binrw options are ignored
+            if let Ok(Meta::List(meta_list)) = attr.parse_meta() {
+                for meta in &meta_list.nested {
+                    if let NestedMeta::Meta(Meta::Path(path)) = meta {
+                        if path.is_ident("big") {
+                            set_frame_endian(&mut endian, "big");
+                        } else if path.is_ident("little") {
+                            set_frame_endian(&mut endian, "little");
+                        }
+                    }
+                }
+            }
+        } else if attr.path.is_ident("frame") {
+            for (path, value) in frame_attr_values(attr) {
+                if path.is_ident("name") {
+                    frame_name = value;
+                } else if path.is_ident("endian") {
+                    set_frame_endian(&mut endian, &value);
+                } else {
+                    panic!("Unknown attribute: {:?}", path);
+                }
+            }
+        }
+    }
+    let endianness = match endian.as_deref() {
+        Some("big") => quote! { ::roboplc::frame::Endianness::Big },
+        Some("little") => quote! { ::roboplc::frame::Endianness::Little },
+        None => quote! { ::roboplc::frame::Endianness::Unspecified },
+        Some(v) => panic!("Unknown endianness: {}", v),
+    };
+    let mut sizes = Vec::new();
+    let mut frame_fields = Vec::new();
+    for field in &fields.named {
+        let field_name = field.ident.as_ref().unwrap().to_string();
+        let ty = &field.ty;
+        let type_name = quote!(#ty).to_string().replace(' ', "");
+        let mut unit = None;
+        let mut description = doc_description(&field.attrs);
+        for attr in &field.attrs {
+            if attr.path.is_ident("brw") || attr.path.is_ident("br") || attr.path.is_ident("bw") {
+                panic!(
+                    "binrw field attributes are not supported by Frame (field {})",
+                    field_name
+                );
+            }
+            if !attr.path.is_ident("frame") {
+                continue;
+            }
+            for (path, value) in frame_attr_values(attr) {
+                if path.is_ident("unit") {
+                    unit = Some(value);
+                } else if path.is_ident("description") {
+                    description = Some(value);
+                } else {
+                    panic!("Unknown attribute: {:?}", path);
+                }
+            }
+        }
+        let size = quote! { <#ty as ::roboplc::frame::Frame>::SIZE };
+        let unit = unit.map_or_else(|| quote! { None }, |u| quote! { Some(#u) });
+        let description = description.map_or_else(|| quote! { None }, |d| quote! { Some(#d) });
+        frame_fields.push(quote! {
+            ::roboplc::frame::FrameField {
+                name: #field_name,
+                type_name: #type_name,
+                offset: 0usize #(+ #sizes)*,
+                size: #size,
+                unit: #unit,
+                description: #description,
+            }
+        });
+        sizes.push(size);
+    }
+    let expanded = quote! {
+        impl ::roboplc::frame::Frame for #name {
+            const NAME: &'static str = #frame_name;
+            const SIZE: usize = 0usize #(+ #sizes)*;
+            const ENDIANNESS: ::roboplc::frame::Endianness = #endianness;
+            const FIELDS: &'static [::roboplc::frame::FrameField] = &[#(#frame_fields),*];
+        }
+    };
+    expanded.into()
+}
+
+fn set_frame_endian(endian: &mut Option<String>, value: &str) {
+    if let Some(current) = endian {
+        if current != value {
+            panic!("conflicting frame endianness: {} and {}", current, value);
+        }
+    }
+    endian.replace(value.to_owned());
+}
+
+fn frame_attr_values(attr: &syn::Attribute) -> Vec<(syn::Path, String)> {
+    let Ok(Meta::List(meta_list)) = attr.parse_meta() else {
+        panic!("unable to parse frame attribute");
+    };
+    meta_list
+        .nested
+        .iter()
+        .map(|meta| match meta {
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                path,
+                lit: Lit::Str(lit_str),
+                ..
+            })) => (path.clone(), lit_str.value()),
+            _ => panic!("invalid frame attribute"),
+        })
+        .collect()
+}
+
+/// Derives a device facade for a struct of I/O mappings (`roboplc::io::LockedIoMapping`, e.g.
+/// Modbus mappings), which reads/writes all the mappings with a single call under a single client
+/// lock. Mappings are executed in the declaration order and must share the same client
+///
+/// Struct attributes:
+///
+/// * `read` - a data struct, `read_all()` method returns. Must have fields with the same names as
+///   the readable mappings
+///
+/// * `write` - a data struct, `write_all()` method accepts. Must have fields with the same names
+///   as the writable mappings (other fields are ignored)
+///
+/// Field attributes: `read` and/or `write`. Fields without the attribute are ignored.
+///
+/// ```rust,ignore
+/// use roboplc::io::MappingGroup;
+///
+/// #[derive(MappingGroup)]
+/// #[mapping_group(read = "VfdStatus", write = "VfdControl")]
+/// struct Vfd {
+///     #[mapping_group(read)]
+///     state: ModbusMapping,
+///     #[mapping_group(read)]
+///     speed: ModbusMapping,
+///     #[mapping_group(write)]
+///     command: ModbusMapping,
+/// }
+/// ```
+///
+/// # Panics
+///
+/// Will panic on invalid attributes or if the macro is used for enums, unions or tuple structs
+#[proc_macro_derive(MappingGroup, attributes(mapping_group))]
+pub fn mapping_group_derive(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let syn::Data::Struct(syn::DataStruct {
+        fields: syn::Fields::Named(fields),
+        ..
+    }) = &input.data
+    else {
+        panic!("MappingGroup can be derived for structs with named fields only");
+    };
+    let mut read_ty: Option<syn::Path> = None;
+    let mut write_ty: Option<syn::Path> = None;
+    for attr in &input.attrs {
+        if !attr.path.is_ident("mapping_group") {
+            continue;
+        }
+        let Ok(Meta::List(meta_list)) = attr.parse_meta() else {
+            panic!("unable to parse mapping_group attribute");
+        };
+        for meta in &meta_list.nested {
+            let NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                path,
+                lit: Lit::Str(lit_str),
+                ..
+            })) = meta
+            else {
+                panic!("invalid mapping_group attribute");
+            };
+            let ty: syn::Path = lit_str.parse().expect("invalid type");
+            if path.is_ident("read") {
+                read_ty = Some(ty);
+            } else if path.is_ident("write") {
+                write_ty = Some(ty);
+            } else {
+                panic!("Unknown attribute: {:?}", path);
+            }
+        }
+    }
+    let mut all = Vec::new();
+    let mut readable = Vec::new();
+    let mut writable = Vec::new();
+    for field in &fields.named {
+        let ident = field.ident.as_ref().unwrap();
+        let mut member = false;
+        for attr in &field.attrs {
+            if !attr.path.is_ident("mapping_group") {
+                continue;
+            }
+            let Ok(Meta::List(meta_list)) = attr.parse_meta() else {
+                panic!("unable to parse mapping_group attribute");
+            };
+            for meta in &meta_list.nested {
+                match meta {
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("read") => {
+                        readable.push(ident.clone());
+                    }
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("write") => {
+                        writable.push(ident.clone());
+                    }
+                    _ => panic!("invalid mapping_group attribute"),
+                }
+                member = true;
+            }
+        }
+        if member {
+            all.push(ident.clone());
+        }
+    }
+    let Some(first) = all.first() else {
+        panic!("MappingGroup requires at least one mapping");
+    };
+    let lock = quote! {
+        let client = ::roboplc::io::LockedIoMapping::client(&self.#first).clone();
+        #(
+            if !client.is_same(::roboplc::io::LockedIoMapping::client(&self.#all)) {
+                return Err(::roboplc::Error::invalid_data(
+                    "mappings of the group must share the same client",
+                ));
+            }
+        )*
+        let _lock = client.lock();
+    };
+    let read_all = match read_ty {
+        Some(ty) => quote! {
+            /// Reads all readable mappings under a single client lock
+            pub fn read_all(&mut self) -> ::roboplc::Result<#ty> {
+                #lock
+                Ok(#ty {
+                    #(
+                        #readable: ::roboplc::io::LockedIoMapping::read_locked(
+                            &mut self.#readable,
+                        )?,
+                    )*
+                })
+            }
+        },
+        None if readable.is_empty() => quote! {},
+        None => panic!("read data type is not specified"),
+    };
+    let write_all = match write_ty {
+        Some(ty) => quote! {
+            /// Writes all writable mappings under a single client lock
+            pub fn write_all(&mut self, data: #ty) -> ::roboplc::Result<()> {
+                #lock
+                let #ty { #(#writable,)* .. } = data;
+                #(
+                    ::roboplc::io::LockedIoMapping::write_locked(&mut self.#writable, #writable)?;
+                )*
+                Ok(())
+            }
+        },
+        None if writable.is_empty() => quote! {},
+        None => panic!("write data type is not specified"),
+    };
+    let expanded = quote! {
+        impl #impl_generics #name #ty_generics #where_clause {
+            #read_all
+            #write_all
+        }
+    };
+    expanded.into()
+}
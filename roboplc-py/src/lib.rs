@@ -0,0 +1,274 @@
+//! Python bindings for RoboPLC: in-process hub, TTL cells and Modbus mappings. The GIL is
+//! released during all blocking operations.
+use std::io::{Read, Seek, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ::roboplc::comm;
+use ::roboplc::hub::{self, ClientOptions};
+use ::roboplc::io::modbus::{ModbusMapping as RModbusMapping, ModbusRegister, ModbusRegisterKind};
+use ::roboplc::io::IoMapping;
+use ::roboplc::{DataDeliveryPolicy, Error};
+use binrw::{BinRead, BinResult, BinWrite, Endian};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+
+// receive polling step when a timeout is specified
+const RECV_POLL_STEP: Duration = Duration::from_millis(1);
+
+fn to_py_err(e: Error) -> PyErr {
+    PyRuntimeError::new_err(e.to_string())
+}
+
+fn duration(secs: f64) -> PyResult<Duration> {
+    Duration::try_from_secs_f64(secs).map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+#[derive(Clone)]
+struct Message {
+    topic: String,
+    payload: PyObject,
+}
+
+impl DataDeliveryPolicy for Message {}
+
+/// In-process data hub, messages are (topic, payload) pairs
+#[pyclass]
+struct Hub {
+    hub: hub::Hub<Message>,
+}
+
+#[pymethods]
+impl Hub {
+    #[new]
+    #[pyo3(signature = (capacity=None))]
+    fn new(capacity: Option<usize>) -> Self {
+        let mut hub = hub::Hub::new();
+        if let Some(capacity) = capacity {
+            hub = hub.set_default_channel_capacity(capacity);
+        }
+        Self { hub }
+    }
+    /// Publishes a message
+    fn publish(&self, topic: &str, payload: PyObject) {
+        self.hub.send(Message {
+            topic: topic.to_owned(),
+            payload,
+        });
+    }
+    /// Registers a client which receives messages with topics starting with any of the given
+    /// prefixes (all messages if no prefixes specified)
+    #[pyo3(signature = (name, topics=Vec::new(), capacity=None))]
+    fn register(
+        &self,
+        name: &str,
+        topics: Vec<String>,
+        capacity: Option<usize>,
+    ) -> PyResult<HubClient> {
+        let condition = move |message: &Message| {
+            topics.is_empty() || topics.iter().any(|t| message.topic.starts_with(t))
+        };
+        let mut options = ClientOptions::new(name, condition);
+        if let Some(capacity) = capacity {
+            options = options.capacity(capacity);
+        }
+        let client = self.hub.register_with_options(options).map_err(to_py_err)?;
+        Ok(HubClient { client })
+    }
+}
+
+/// Hub client
+#[pyclass]
+struct HubClient {
+    client: hub::Client<Message>,
+}
+
+#[pymethods]
+impl HubClient {
+    /// Receives a message. Returns None if the timeout is reached
+    #[pyo3(signature = (timeout=None))]
+    fn recv(&self, py: Python<'_>, timeout: Option<f64>) -> PyResult<Option<(String, PyObject)>> {
+        let message = if let Some(timeout) = timeout {
+            let deadline = Instant::now() + duration(timeout)?;
+            py.allow_threads(|| loop {
+                match self.client.try_recv() {
+                    Ok(message) => break Ok(Some(message)),
+                    Err(Error::ChannelEmpty) => {
+                        if Instant::now() >= deadline {
+                            break Ok(None);
+                        }
+                        thread::sleep(RECV_POLL_STEP);
+                    }
+                    Err(e) => break Err(e),
+                }
+            })
+        } else {
+            py.allow_threads(|| self.client.recv().map(Some))
+        }
+        .map_err(to_py_err)?;
+        Ok(message.map(|m| (m.topic, m.payload)))
+    }
+    /// Receives a message if available
+    fn try_recv(&self) -> PyResult<Option<(String, PyObject)>> {
+        match self.client.try_recv() {
+            Ok(m) => Ok(Some((m.topic, m.payload))),
+            Err(Error::ChannelEmpty) => Ok(None),
+            Err(e) => Err(to_py_err(e)),
+        }
+    }
+}
+
+/// A value with time-to-live
+#[pyclass]
+struct TtlCell {
+    ttl: Duration,
+    value: Option<(PyObject, Instant)>,
+}
+
+#[pymethods]
+impl TtlCell {
+    #[new]
+    fn new(ttl: f64) -> PyResult<Self> {
+        Ok(Self {
+            ttl: duration(ttl)?,
+            value: None,
+        })
+    }
+    fn set(&mut self, value: PyObject) {
+        self.value = Some((value, Instant::now()));
+    }
+    /// Returns the value or None if not set or expired
+    fn get(&self, py: Python<'_>) -> Option<PyObject> {
+        self.value
+            .as_ref()
+            .filter(|(_, set_at)| set_at.elapsed() <= self.ttl)
+            .map(|(v, _)| v.clone_ref(py))
+    }
+    /// Takes the value, the cell becomes empty
+    fn take(&mut self) -> Option<PyObject> {
+        let (value, set_at) = self.value.take()?;
+        (set_at.elapsed() <= self.ttl).then_some(value)
+    }
+    fn clear(&mut self) {
+        self.value = None;
+    }
+    #[getter]
+    fn is_expired(&self) -> bool {
+        self.value
+            .as_ref()
+            .map_or(true, |(_, set_at)| set_at.elapsed() > self.ttl)
+    }
+}
+
+// raw mapping data
+struct Raw(Vec<u8>);
+
+impl BinRead for Raw {
+    type Args<'a> = ();
+
+    fn read_options<R: Read + Seek>(reader: &mut R, _endian: Endian, _args: ()) -> BinResult<Self> {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        Ok(Raw(buf))
+    }
+}
+
+impl BinWrite for Raw {
+    type Args<'a> = ();
+
+    fn write_options<W: Write + Seek>(
+        &self,
+        writer: &mut W,
+        _endian: Endian,
+        _args: (),
+    ) -> BinResult<()> {
+        writer.write_all(&self.0)?;
+        Ok(())
+    }
+}
+
+/// Modbus client mapping. Coils and discretes are read as lists of bools, inputs and holdings as
+/// lists of 16-bit unsigned integers
+#[pyclass]
+struct ModbusMapping {
+    mapping: RModbusMapping,
+    kind: ModbusRegisterKind,
+    count: u16,
+}
+
+impl ModbusMapping {
+    fn create(client: &comm::Client, unit: u8, register: &str, count: u16) -> PyResult<Self> {
+        let register: ModbusRegister = register.parse().map_err(to_py_err)?;
+        let kind = register.kind;
+        let mapping = RModbusMapping::create(client, unit, register, count).map_err(to_py_err)?;
+        Ok(Self {
+            mapping,
+            kind,
+            count,
+        })
+    }
+}
+
+#[pymethods]
+impl ModbusMapping {
+    /// Creates a mapping for Modbus/TCP, the register is specified as "h@0", "c@10" etc.
+    #[staticmethod]
+    #[pyo3(signature = (addr, unit, register, count, timeout=1.0))]
+    fn tcp(addr: &str, unit: u8, register: &str, count: u16, timeout: f64) -> PyResult<Self> {
+        let client = comm::tcp::connect(addr, duration(timeout)?).map_err(to_py_err)?;
+        Self::create(&client, unit, register, count)
+    }
+    /// Creates a mapping for Modbus/RTU, the path is specified as "/dev/ttyS0:9600:8:N:1"
+    #[staticmethod]
+    #[pyo3(signature = (path, unit, register, count, timeout=1.0))]
+    fn rtu(path: &str, unit: u8, register: &str, count: u16, timeout: f64) -> PyResult<Self> {
+        let client = comm::serial::connect_rtu(path, duration(timeout)?).map_err(to_py_err)?;
+        Self::create(&client, unit, register, count)
+    }
+    fn read(&mut self, py: Python<'_>) -> PyResult<PyObject> {
+        let mapping = &mut self.mapping;
+        let Raw(data) = py
+            .allow_threads(|| mapping.read::<Raw>())
+            .map_err(to_py_err)?;
+        Ok(match self.kind {
+            ModbusRegisterKind::Coil | ModbusRegisterKind::Discrete => data
+                .iter()
+                .take(usize::from(self.count))
+                .map(|v| *v != 0)
+                .collect::<Vec<bool>>()
+                .into_py(py),
+            ModbusRegisterKind::Input | ModbusRegisterKind::Holding => data
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect::<Vec<u16>>()
+                .into_py(py),
+        })
+    }
+    /// Writes values (bools for coils, integers for holdings)
+    fn write(&mut self, py: Python<'_>, values: Vec<u16>) -> PyResult<()> {
+        if values.len() > usize::from(self.count) {
+            return Err(PyValueError::new_err("too many values"));
+        }
+        let data: Vec<u8> = match self.kind {
+            ModbusRegisterKind::Coil | ModbusRegisterKind::Discrete => {
+                values.iter().map(|v| u8::from(*v != 0)).collect()
+            }
+            ModbusRegisterKind::Input | ModbusRegisterKind::Holding => {
+                values.iter().flat_map(|v| v.to_be_bytes()).collect()
+            }
+        };
+        let mapping = &mut self.mapping;
+        py.allow_threads(|| mapping.write(Raw(data)))
+            .map_err(to_py_err)
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "roboplc")]
+fn roboplc_py(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add_class::<Hub>()?;
+    m.add_class::<HubClient>()?;
+    m.add_class::<TtlCell>()?;
+    m.add_class::<ModbusMapping>()?;
+    Ok(())
+}
@@ -0,0 +1,224 @@
+//!
+//! Build script helpers.
+//!
+//! Duplicate worker names are detected by the controller at spawn time only, which may happen on
+//! the target machine. [`check_worker_names()`] scans project sources for `WorkerOpts` derives and
+//! fails the build if several workers have the same name:
+//!
+//! ```rust,no_run
+//! // build.rs, requires roboplc in [build-dependencies]
+//! fn main() {
+//!     roboplc::build::check_worker_names("src").unwrap();
+//! }
+//! ```
+//!
+//! The scan is text-based: workers with names set in `worker_opts` attributes and workers with
+//! default names (the structure name with the first letter in lowercase) are detected, workers
+//! generated by macros or with manually implemented `WorkerOptions` are not.
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{Error, Result};
+
+/// A worker declaration, found in sources
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct WorkerDeclaration {
+    pub name: String,
+    pub struct_name: String,
+    pub path: PathBuf,
+    pub line: usize,
+}
+
+/// Scans `.rs` files in the directory (recursively) for `WorkerOpts` derives, prints
+/// `cargo:rerun-if-changed` for the directory and returns an error if duplicate worker names are
+/// found
+pub fn check_worker_names<P: AsRef<Path>>(dir: P) -> Result<Vec<WorkerDeclaration>> {
+    let dir = dir.as_ref();
+    println!("cargo:rerun-if-changed={}", dir.display());
+    let mut workers = Vec::new();
+    scan_dir(dir, &mut workers)?;
+    let mut by_name: BTreeMap<&str, Vec<&WorkerDeclaration>> = BTreeMap::new();
+    for worker in &workers {
+        by_name.entry(&worker.name).or_default().push(worker);
+    }
+    let mut errors = Vec::new();
+    for (name, declarations) in by_name {
+        if declarations.len() > 1 {
+            let locations: Vec<String> = declarations
+                .iter()
+                .map(|d| format!("{} ({}:{})", d.struct_name, d.path.display(), d.line))
+                .collect();
+            errors.push(format!(
+                "duplicate worker name `{}`: {}",
+                name,
+                locations.join(", ")
+            ));
+        }
+    }
+    if errors.is_empty() {
+        Ok(workers)
+    } else {
+        Err(Error::invalid_data(errors.join("; ")))
+    }
+}
+
+fn scan_dir(dir: &Path, workers: &mut Vec<WorkerDeclaration>) -> Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(fs::DirEntry::path);
+    for entry in entries {
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            scan_dir(&path, workers)?;
+        } else if path.extension().map_or(false, |ext| ext == "rs") {
+            let source = fs::read_to_string(&path)?;
+            for (name, struct_name, line) in scan_source(&source) {
+                workers.push(WorkerDeclaration {
+                    name,
+                    struct_name,
+                    path: path.clone(),
+                    line,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+// returns (worker name, struct name, line)
+fn scan_source(source: &str) -> Vec<(String, String, usize)> {
+    let mut result = Vec::new();
+    let mut pos = 0;
+    while let Some(found) = source[pos..].find("WorkerOpts") {
+        let start = pos + found;
+        pos = start + "WorkerOpts".len();
+        // must be inside a derive attribute
+        let attr_start = source[..start].rfind("#[").unwrap_or(0);
+        if !source[attr_start..start]
+            .trim_start_matches("#[")
+            .trim_start()
+            .starts_with("derive")
+        {
+            continue;
+        }
+        if source[attr_start..start].contains(']') {
+            continue;
+        }
+        let Some(struct_pos) = find_keyword(&source[pos..], "struct") else {
+            break;
+        };
+        let decl = &source[pos..pos + struct_pos];
+        let struct_name: String = source[pos + struct_pos + "struct".len()..]
+            .trim_start()
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || *c == '_')
+            .collect();
+        if struct_name.is_empty() {
+            continue;
+        }
+        let name = worker_name_attr(decl).unwrap_or_else(|| lowercase_first_letter(&struct_name));
+        let line = source[..start].matches('\n').count() + 1;
+        result.push((name, struct_name, line));
+        pos += struct_pos;
+    }
+    result
+}
+
+fn find_keyword(s: &str, keyword: &str) -> Option<usize> {
+    let mut pos = 0;
+    while let Some(found) = s[pos..].find(keyword) {
+        let start = pos + found;
+        let end = start + keyword.len();
+        let before_ok = s[..start]
+            .chars()
+            .next_back()
+            .map_or(true, |c| !c.is_alphanumeric() && c != '_');
+        let after_ok = s[end..].chars().next().map_or(false, char::is_whitespace);
+        if before_ok && after_ok {
+            return Some(start);
+        }
+        pos = end;
+    }
+    None
+}
+
+fn worker_name_attr(decl: &str) -> Option<String> {
+    let mut pos = 0;
+    while let Some(found) = decl[pos..].find("worker_opts") {
+        pos += found + "worker_opts".len();
+        let rest = decl[pos..].trim_start().strip_prefix('(')?;
+        let end = rest.find(")]")?;
+        let args = &rest[..end];
+        if let Some(name_pos) = find_arg(args, "name") {
+            let value = args[name_pos..]
+                .trim_start()
+                .strip_prefix('=')?
+                .trim_start();
+            let value = value.strip_prefix('"')?;
+            return Some(value[..value.find('"')?].to_owned());
+        }
+    }
+    None
+}
+
+fn find_arg(args: &str, arg: &str) -> Option<usize> {
+    let mut pos = 0;
+    while let Some(found) = args[pos..].find(arg) {
+        let start = pos + found;
+        let end = start + arg.len();
+        let before_ok = args[..start]
+            .trim_end()
+            .chars()
+            .next_back()
+            .map_or(true, |c| c == ',');
+        if before_ok && args[end..].trim_start().starts_with('=') {
+            return Some(end);
+        }
+        pos = end;
+    }
+    None
+}
+
+fn lowercase_first_letter(s: &str) -> String {
+    let mut chars = s.chars();
+    chars.next().map_or_else(String::new, |c| {
+        c.to_lowercase().collect::<String>() + chars.as_str()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::scan_source;
+
+    #[test]
+    fn test_scan_source() {
+        let source = r#"
+#[derive(WorkerOpts)]
+#[worker_opts(cpu = 1, name = "reader", priority = 80)]
+struct Reader {}
+
+#[derive(Default, WorkerOpts)]
+#[worker_opts(blocking = true)]
+pub struct ModbusWorker;
+
+// WorkerOpts is not derived here
+struct Other;
+
+#[derive(WorkerOpts)]
+#[worker_opts(stack_size = 8192)]
+#[worker_opts(scheduling = "fifo", name = "reader")]
+struct Reader2 {}
+"#;
+        let workers = scan_source(source);
+        assert_eq!(
+            workers,
+            vec![
+                ("reader".to_owned(), "Reader".to_owned(), 2),
+                ("modbusWorker".to_owned(), "ModbusWorker".to_owned(), 6),
+                ("reader".to_owned(), "Reader2".to_owned(), 13),
+            ]
+        );
+    }
+}
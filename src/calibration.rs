@@ -0,0 +1,252 @@
+//!
+//! Linearization and calibration of analog values.
+//!
+//! * [`Calibration::Linear`] - scale/offset, can be calculated from two reference points (see
+//! [`Calibration::two_point()`]), e.g. during field calibration of a sensor
+//!
+//! * [`Calibration::Table`] - multi-point linearization table with linear interpolation (e.g.
+//! thermocouple or tank volume tables)
+//!
+//! * [`Calibration::Polynomial`] - polynomial correction
+//!
+//! Calibrations can be serialized, so they are loaded from configuration files or the key-value
+//! store (see [`Calibration::load()`] and [`Calibration::save()`], requires `kv` crate feature)
+//! and applied inline in I/O read paths with [`CalibratedMapping`].
+//!
+//! Example:
+//!
+//! ```rust
+//! use roboplc::calibration::Calibration;
+//!
+//! // 4-20mA input to 0-10 bar
+//! let c = Calibration::two_point((4.0, 0.0), (20.0, 10.0)).unwrap();
+//! assert_eq!(c.apply(12.0), 5.0);
+//! let c = Calibration::table(vec![(0.0, 0.0), (50.0, 200.0), (100.0, 300.0)]).unwrap();
+//! assert_eq!(c.apply(75.0), 250.0);
+//! ```
+use binrw::BinRead;
+use serde::{Deserialize, Serialize};
+
+use crate::{io::IoMapping, Error, Result};
+
+/// Calibration of an analog value
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Calibration {
+    /// `value * scale + offset`
+    Linear { scale: f64, offset: f64 },
+    /// Linearization table, points are sorted by raw values. Values out of the table range are
+    /// extrapolated with the first/last segment if `extrapolate` is true, otherwise clamped
+    Table {
+        points: Vec<(f64, f64)>,
+        #[serde(default)]
+        extrapolate: bool,
+    },
+    /// Polynomial, coefficients are ordered from the constant term: `c0 + c1*x + c2*x^2 + ...`
+    Polynomial { coefficients: Vec<f64> },
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Calibration::Linear {
+            scale: 1.0,
+            offset: 0.0,
+        }
+    }
+}
+
+impl Calibration {
+    /// Creates a linear calibration from two reference points `(raw, value)`
+    #[allow(clippy::float_cmp)]
+    pub fn two_point(p1: (f64, f64), p2: (f64, f64)) -> Result<Self> {
+        if p1.0 == p2.0 {
+            return Err(Error::invalid_data(
+                "calibration points must have different raw values",
+            ));
+        }
+        let scale = (p2.1 - p1.1) / (p2.0 - p1.0);
+        Ok(Calibration::Linear {
+            scale,
+            offset: p1.1 - p1.0 * scale,
+        })
+    }
+    /// Creates a linearization table (values out of range are clamped). The points are sorted,
+    /// at least two points with unique raw values are required
+    #[allow(clippy::float_cmp)]
+    pub fn table(mut points: Vec<(f64, f64)>) -> Result<Self> {
+        if points.len() < 2 {
+            return Err(Error::invalid_data(
+                "linearization table must have at least two points",
+            ));
+        }
+        if points.iter().any(|(x, y)| !x.is_finite() || !y.is_finite()) {
+            return Err(Error::invalid_data(
+                "linearization table points must be finite",
+            ));
+        }
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        if points.windows(2).any(|w| w[0].0 == w[1].0) {
+            return Err(Error::invalid_data(
+                "linearization table raw values must be unique",
+            ));
+        }
+        Ok(Calibration::Table {
+            points,
+            extrapolate: false,
+        })
+    }
+    /// Creates a polynomial calibration
+    pub fn polynomial(coefficients: Vec<f64>) -> Result<Self> {
+        if coefficients.is_empty() {
+            return Err(Error::invalid_data(
+                "polynomial must have at least one coefficient",
+            ));
+        }
+        Ok(Calibration::Polynomial { coefficients })
+    }
+    /// Enables extrapolation for linearization tables, does nothing for other kinds
+    pub fn extrapolate(mut self, value: bool) -> Self {
+        if let Calibration::Table {
+            ref mut extrapolate,
+            ..
+        } = self
+        {
+            *extrapolate = value;
+        }
+        self
+    }
+    /// Validates a calibration, e.g. loaded from a configuration file
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Calibration::Linear { .. } => Ok(()),
+            Calibration::Table { points, .. } => {
+                Calibration::table(points.clone()).map(|_| ())?;
+                if points.windows(2).any(|w| w[0].0 > w[1].0) {
+                    return Err(Error::invalid_data(
+                        "linearization table points must be sorted",
+                    ));
+                }
+                Ok(())
+            }
+            Calibration::Polynomial { coefficients } => {
+                Calibration::polynomial(coefficients.clone()).map(|_| ())
+            }
+        }
+    }
+    /// Applies the calibration to a raw value
+    pub fn apply(&self, raw: f64) -> f64 {
+        match self {
+            Calibration::Linear { scale, offset } => raw * scale + offset,
+            Calibration::Table {
+                points,
+                extrapolate,
+            } => interpolate(points, raw, *extrapolate),
+            Calibration::Polynomial { coefficients } => coefficients
+                .iter()
+                .rev()
+                .fold(0.0, |acc, c| acc.mul_add(raw, *c)),
+        }
+    }
+    /// Loads a calibration from the key-value store
+    #[cfg(feature = "kv")]
+    pub fn load(store: &crate::kv::KvStore, key: &str) -> Result<Option<Self>> {
+        let Some(calibration) = store.get::<Self>(key)? else {
+            return Ok(None);
+        };
+        calibration.validate()?;
+        Ok(Some(calibration))
+    }
+    /// Saves the calibration (e.g. a field-calibrated one) into the key-value store
+    #[cfg(feature = "kv")]
+    pub fn save(&self, store: &crate::kv::KvStore, key: &str) -> Result<()> {
+        store.set(key, self)
+    }
+}
+
+fn interpolate(points: &[(f64, f64)], x: f64, extrapolate: bool) -> f64 {
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return x;
+    };
+    if points.len() == 1 {
+        return first.1;
+    }
+    if !extrapolate {
+        if x <= first.0 {
+            return first.1;
+        }
+        if x >= last.0 {
+            return last.1;
+        }
+    }
+    // the segment, which contains x (or the first/last one for extrapolation)
+    let i = points
+        .partition_point(|p| p.0 <= x)
+        .clamp(1, points.len() - 1);
+    let (x0, y0) = points[i - 1];
+    let (x1, y1) = points[i];
+    y0 + (x - x0) * (y1 - y0) / (x1 - x0)
+}
+
+/// I/O mapping wrapper, which reads numeric values and applies the calibration
+pub struct CalibratedMapping<M: IoMapping> {
+    mapping: M,
+    calibration: Calibration,
+}
+
+impl<M: IoMapping> CalibratedMapping<M> {
+    pub fn new(mapping: M, calibration: Calibration) -> Self {
+        Self {
+            mapping,
+            calibration,
+        }
+    }
+    /// Reads a raw value and returns the calibrated one
+    pub fn read_calibrated<T>(&mut self) -> Result<f64>
+    where
+        T: for<'a> BinRead<Args<'a> = ()> + Into<f64>,
+    {
+        let raw: T = self.mapping.read()?;
+        Ok(self.calibration.apply(raw.into()))
+    }
+    /// Replaces the calibration, e.g. after field calibration
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+    }
+    pub fn calibration(&self) -> &Calibration {
+        &self.calibration
+    }
+    pub fn inner_mut(&mut self) -> &mut M {
+        &mut self.mapping
+    }
+    pub fn into_inner(self) -> M {
+        self.mapping
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Calibration;
+
+    #[test]
+    fn test_calibrations() {
+        let c = Calibration::two_point((4.0, 0.0), (20.0, 100.0)).unwrap();
+        assert!((c.apply(8.0) - 25.0).abs() < 1e-9);
+        assert!(Calibration::two_point((1.0, 0.0), (1.0, 1.0)).is_err());
+        let c = Calibration::table(vec![(10.0, 100.0), (0.0, 0.0), (20.0, 150.0)]).unwrap();
+        assert!((c.apply(5.0) - 50.0).abs() < 1e-9);
+        assert!((c.apply(15.0) - 125.0).abs() < 1e-9);
+        assert!((c.apply(-5.0) - 0.0).abs() < 1e-9);
+        assert!((c.apply(30.0) - 150.0).abs() < 1e-9);
+        let c = c.extrapolate(true);
+        assert!((c.apply(-5.0) + 50.0).abs() < 1e-9);
+        assert!((c.apply(30.0) - 200.0).abs() < 1e-9);
+        assert!(Calibration::table(vec![(0.0, 0.0), (0.0, 1.0)]).is_err());
+        let c = Calibration::polynomial(vec![1.0, 2.0, 3.0]).unwrap();
+        assert!((c.apply(2.0) - 17.0).abs() < 1e-9);
+        let unsorted = Calibration::Table {
+            points: vec![(1.0, 0.0), (0.0, 1.0)],
+            extrapolate: false,
+        };
+        assert!(unsorted.validate().is_err());
+    }
+}
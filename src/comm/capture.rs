@@ -0,0 +1,420 @@
+use crate::Result;
+
+use super::{Client, Communicator, Protocol};
+use parking_lot_rt::{Mutex, MutexGuard};
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+// pcapng block types
+const PCAPNG_SHB: u32 = 0x0A0D_0D0A;
+const PCAPNG_IDB: u32 = 0x0000_0001;
+const PCAPNG_EPB: u32 = 0x0000_0006;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+// LINKTYPE_USER0, raw application-level data
+const PCAPNG_LINKTYPE: u16 = 147;
+const PCAPNG_OPT_COMMENT: u16 = 1;
+const PCAPNG_OPT_EPB_FLAGS: u16 = 2;
+
+/// Capture file format
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum CaptureFormat {
+    /// Text file with timestamped hex dumps
+    #[default]
+    HexDump,
+    /// pcapng file, can be opened with Wireshark. Frames are written with the user-defined link
+    /// type (USER0), the direction is stored in packet flags, session markers are written as empty
+    /// packets with comments
+    Pcapng,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Direction {
+    In,
+    Out,
+}
+
+/// Wire capture, writes all client traffic to a rotating file. Should be attached to a client
+/// with [`Client::with_capture()`]. Can be cloned and enabled/disabled at runtime.
+///
+/// The capture writes data to the file synchronously, so it is a debugging tool which should not
+/// be used in production real-time workers.
+#[derive(Clone)]
+pub struct WireCapture {
+    inner: Arc<CaptureInner>,
+}
+
+struct CaptureInner {
+    enabled: AtomicBool,
+    path: PathBuf,
+    format: CaptureFormat,
+    max_file_size: u64,
+    max_files: usize,
+    file: Mutex<Option<CaptureFile>>,
+}
+
+struct CaptureFile {
+    writer: BufWriter<File>,
+    size: u64,
+}
+
+impl WireCapture {
+    /// Creates a new capture with the file path. The file is created at the first write
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            inner: Arc::new(CaptureInner {
+                enabled: AtomicBool::new(true),
+                path: path.as_ref().to_owned(),
+                format: CaptureFormat::default(),
+                max_file_size: 10_000_000,
+                max_files: 5,
+                file: <_>::default(),
+            }),
+        }
+    }
+    fn inner_mut(&mut self) -> &mut CaptureInner {
+        Arc::get_mut(&mut self.inner).expect("capture is already shared")
+    }
+    /// Sets the capture file format (the default is hex dump)
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the capture is already cloned
+    pub fn format(mut self, format: CaptureFormat) -> Self {
+        self.inner_mut().format = format;
+        self
+    }
+    /// Sets the maximum file size after which the file is rotated (the default is 10 MB)
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the capture is already cloned
+    pub fn max_file_size(mut self, max_file_size: u64) -> Self {
+        self.inner_mut().max_file_size = max_file_size;
+        self
+    }
+    /// Sets the number of kept files, including the current one (the default is 5). Rotated files
+    /// are renamed to `PATH.1`, `PATH.2` etc.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the capture is already cloned
+    pub fn max_files(mut self, max_files: usize) -> Self {
+        self.inner_mut().max_files = max_files.max(1);
+        self
+    }
+    /// Enables the capture
+    pub fn enable(&self) {
+        self.inner.enabled.store(true, Ordering::Release);
+    }
+    /// Disables the capture, the current file is flushed
+    pub fn disable(&self) {
+        self.inner.enabled.store(false, Ordering::Release);
+        if let Some(f) = self.inner.file.lock().as_mut() {
+            let _ = f.writer.flush();
+        }
+    }
+    pub fn is_enabled(&self) -> bool {
+        self.inner.enabled.load(Ordering::Acquire)
+    }
+    fn record_data(&self, direction: Direction, session_id: usize, data: &[u8]) {
+        if self.is_enabled() {
+            let record = match self.inner.format {
+                CaptureFormat::HexDump => hex_record(timestamp(), direction, session_id, data),
+                CaptureFormat::Pcapng => pcapng_epb(timestamp(), Some(direction), data, None),
+            };
+            self.write_record(&record);
+        }
+    }
+    fn record_marker(&self, session_id: usize, marker: &str) {
+        if self.is_enabled() {
+            let record = match self.inner.format {
+                CaptureFormat::HexDump => format!(
+                    "{} session={} # {}\n",
+                    timestamp_str(timestamp()),
+                    session_id,
+                    marker
+                )
+                .into_bytes(),
+                CaptureFormat::Pcapng => pcapng_epb(
+                    timestamp(),
+                    None,
+                    &[],
+                    Some(&format!("session={} {}", session_id, marker)),
+                ),
+            };
+            self.write_record(&record);
+        }
+    }
+    fn write_record(&self, record: &[u8]) {
+        if let Err(error) = self.inner.write_record(record) {
+            error!(%error, path=%self.inner.path.display(), "wire capture failed, disabling");
+            self.inner.enabled.store(false, Ordering::Release);
+            self.inner.file.lock().take();
+        }
+    }
+}
+
+impl CaptureInner {
+    fn write_record(&self, record: &[u8]) -> Result<()> {
+        let mut file = self.file.lock();
+        if file
+            .as_ref()
+            .map_or(false, |f| f.size + record.len() as u64 > self.max_file_size)
+        {
+            if let Some(mut f) = file.take() {
+                f.writer.flush()?;
+            }
+            self.rotate()?;
+        }
+        if file.is_none() {
+            file.replace(self.create_file()?);
+        }
+        let f = file.as_mut().unwrap();
+        f.writer.write_all(record)?;
+        f.writer.flush()?;
+        f.size += record.len() as u64;
+        Ok(())
+    }
+    fn create_file(&self) -> Result<CaptureFile> {
+        let mut writer = BufWriter::new(File::create(&self.path)?);
+        let mut size = 0;
+        if self.format == CaptureFormat::Pcapng {
+            let header = pcapng_header();
+            writer.write_all(&header)?;
+            size = header.len() as u64;
+        }
+        Ok(CaptureFile { writer, size })
+    }
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut p = self.path.as_os_str().to_owned();
+        p.push(format!(".{}", n));
+        p.into()
+    }
+    fn rotate(&self) -> Result<()> {
+        if self.max_files < 2 {
+            return Ok(());
+        }
+        for n in (1..self.max_files - 1).rev() {
+            let from = self.rotated_path(n);
+            if from.exists() {
+                fs::rename(from, self.rotated_path(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+        Ok(())
+    }
+}
+
+/// Creates a client which writes all traffic of the given client to the capture
+pub(super) fn wrap(client: Client, capture: WireCapture) -> Client {
+    let session_id = client.session_id();
+    Client(Arc::new(Captured {
+        client,
+        capture,
+        session_id: AtomicUsize::new(session_id),
+    }))
+}
+
+struct Captured {
+    client: Client,
+    capture: WireCapture,
+    session_id: AtomicUsize,
+}
+
+impl Captured {
+    fn check_session(&self) -> usize {
+        let session_id = self.client.session_id();
+        if self.session_id.swap(session_id, Ordering::AcqRel) != session_id {
+            self.capture.record_marker(session_id, "session started");
+        }
+        session_id
+    }
+}
+
+impl Communicator for Captured {
+    fn lock(&self) -> MutexGuard<()> {
+        self.client.lock()
+    }
+    fn reconnect(&self) {
+        self.client.reconnect();
+        self.capture
+            .record_marker(self.client.session_id(), "reconnect requested");
+    }
+    fn write(&self, buf: &[u8]) -> Result<()> {
+        let result = self.client.write(buf);
+        let session_id = self.check_session();
+        match result {
+            Ok(()) => self.capture.record_data(Direction::Out, session_id, buf),
+            Err(ref e) => self
+                .capture
+                .record_marker(session_id, &format!("write error: {}", e)),
+        }
+        result
+    }
+    fn read_exact(&self, buf: &mut [u8]) -> Result<()> {
+        let result = self.client.read_exact(buf);
+        let session_id = self.check_session();
+        match result {
+            Ok(()) => self.capture.record_data(Direction::In, session_id, buf),
+            Err(ref e) => self
+                .capture
+                .record_marker(session_id, &format!("read error: {}", e)),
+        }
+        result
+    }
+    fn protocol(&self) -> Protocol {
+        self.client.protocol()
+    }
+    fn session_id(&self) -> usize {
+        self.client.session_id()
+    }
+    fn local_ip_addr(&self) -> Result<Option<SocketAddr>> {
+        self.client.local_ip_addr()
+    }
+    fn lock_session(&self) -> Result<usize> {
+        self.client.0.lock_session()
+    }
+    fn unlock_session(&self) {
+        self.client.0.unlock_session();
+    }
+}
+
+// microseconds since the UNIX epoch
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_micros()).unwrap_or(u64::MAX))
+}
+
+fn timestamp_str(ts: u64) -> String {
+    format!("{}.{:06}", ts / 1_000_000, ts % 1_000_000)
+}
+
+fn hex_record(ts: u64, direction: Direction, session_id: usize, data: &[u8]) -> Vec<u8> {
+    let mut s = String::with_capacity(data.len() * 3 + 64);
+    let _ = write!(
+        s,
+        "{} session={} {} {}:",
+        timestamp_str(ts),
+        session_id,
+        match direction {
+            Direction::In => "<",
+            Direction::Out => ">",
+        },
+        data.len()
+    );
+    for byte in data {
+        let _ = write!(s, " {:02x}", byte);
+    }
+    s.push('\n');
+    s.into_bytes()
+}
+
+fn pad4(len: usize) -> usize {
+    (4 - len % 4) % 4
+}
+
+// section header and interface description blocks
+fn pcapng_header() -> Vec<u8> {
+    let mut buf = Vec::with_capacity(48);
+    buf.extend(PCAPNG_SHB.to_le_bytes());
+    buf.extend(28u32.to_le_bytes());
+    buf.extend(PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+    buf.extend(1u16.to_le_bytes());
+    buf.extend(0u16.to_le_bytes());
+    // section length is not specified
+    buf.extend((-1i64).to_le_bytes());
+    buf.extend(28u32.to_le_bytes());
+    buf.extend(PCAPNG_IDB.to_le_bytes());
+    buf.extend(20u32.to_le_bytes());
+    buf.extend(PCAPNG_LINKTYPE.to_le_bytes());
+    buf.extend(0u16.to_le_bytes());
+    // snap length, unlimited
+    buf.extend(0u32.to_le_bytes());
+    buf.extend(20u32.to_le_bytes());
+    buf
+}
+
+// enhanced packet block, timestamps are in microseconds (the default resolution)
+#[allow(clippy::cast_possible_truncation)]
+fn pcapng_epb(
+    ts: u64,
+    direction: Option<Direction>,
+    data: &[u8],
+    comment: Option<&str>,
+) -> Vec<u8> {
+    let mut options = Vec::new();
+    if let Some(direction) = direction {
+        options.extend(PCAPNG_OPT_EPB_FLAGS.to_le_bytes());
+        options.extend(4u16.to_le_bytes());
+        let flags: u32 = match direction {
+            Direction::In => 1,
+            Direction::Out => 2,
+        };
+        options.extend(flags.to_le_bytes());
+    }
+    if let Some(comment) = comment {
+        let comment = &comment.as_bytes()[..comment.len().min(usize::from(u16::MAX))];
+        options.extend(PCAPNG_OPT_COMMENT.to_le_bytes());
+        options.extend((comment.len() as u16).to_le_bytes());
+        options.extend(comment);
+        options.extend(std::iter::repeat(0).take(pad4(comment.len())));
+    }
+    if !options.is_empty() {
+        // end of options
+        options.extend([0u8; 4]);
+    }
+    let total_len = 32 + data.len() + pad4(data.len()) + options.len();
+    let mut buf = Vec::with_capacity(total_len);
+    buf.extend(PCAPNG_EPB.to_le_bytes());
+    buf.extend((total_len as u32).to_le_bytes());
+    // interface id
+    buf.extend(0u32.to_le_bytes());
+    buf.extend(((ts >> 32) as u32).to_le_bytes());
+    buf.extend((ts as u32).to_le_bytes());
+    buf.extend((data.len() as u32).to_le_bytes());
+    buf.extend((data.len() as u32).to_le_bytes());
+    buf.extend(data);
+    buf.extend(std::iter::repeat(0).take(pad4(data.len())));
+    buf.extend(options);
+    buf.extend((total_len as u32).to_le_bytes());
+    buf
+}
+
+#[cfg(test)]
+mod test {
+    use super::{hex_record, pcapng_epb, pcapng_header, Direction};
+
+    #[test]
+    fn test_hex_record() {
+        let record = hex_record(1_700_000_000_000_123, Direction::Out, 2, &[0x01, 0xab]);
+        assert_eq!(
+            std::str::from_utf8(&record).unwrap(),
+            "1700000000.000123 session=2 > 2: 01 ab\n"
+        );
+    }
+
+    #[test]
+    fn test_pcapng_blocks() {
+        let header = pcapng_header();
+        assert_eq!(header.len(), 48);
+        for (data, comment) in [
+            (&[1u8, 2, 3][..], None),
+            (&[][..], Some("session=1 session started")),
+        ] {
+            let block = pcapng_epb(1, Some(Direction::In), data, comment);
+            assert_eq!(block.len() % 4, 0);
+            let len = u32::from_le_bytes(block[4..8].try_into().unwrap());
+            let trailing_len = u32::from_le_bytes(block[block.len() - 4..].try_into().unwrap());
+            assert_eq!(len as usize, block.len());
+            assert_eq!(len, trailing_len);
+        }
+    }
+}
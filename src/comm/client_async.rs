@@ -0,0 +1,101 @@
+use std::{future::Future, net::SocketAddr, pin::Pin, sync::Arc, time::Duration};
+
+use tokio::sync::MutexGuard;
+
+use super::Protocol;
+use crate::{Error, Result};
+
+pub(super) type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A versatile (TCP/serial) asynchronous client, an async counterpart of [`super::Client`]
+#[derive(Clone)]
+pub struct AsyncClient(pub(super) Arc<dyn AsyncCommunicator + Send + Sync>);
+
+impl AsyncClient {
+    /// Lock the client for exclusive access
+    pub async fn lock(&self) -> MutexGuard<()> {
+        self.0.lock().await
+    }
+    /// Returns true if both clients share the same connection
+    pub fn is_same(&self, other: &AsyncClient) -> bool {
+        Arc::as_ptr(&self.0).cast::<()>() == Arc::as_ptr(&other.0).cast::<()>()
+    }
+    /// Reconnect the client in case of read/write problems
+    pub async fn reconnect(&self) {
+        self.0.reconnect().await;
+    }
+    /// Write data to the client
+    pub async fn write(&self, buf: &[u8]) -> Result<()> {
+        self.0.write(buf).await
+    }
+    /// Read data from the client
+    pub async fn read_exact(&self, buf: &mut [u8]) -> Result<()> {
+        self.0.read_exact(buf).await
+    }
+    /// Get the protocol of the client
+    pub fn protocol(&self) -> Protocol {
+        self.0.protocol()
+    }
+    /// Get local IP address (for TCP/IP)
+    pub async fn local_ip_addr(&self) -> Result<Option<SocketAddr>> {
+        self.0.local_ip_addr().await
+    }
+    /// Get the current session id
+    pub fn session_id(&self) -> usize {
+        self.0.session_id()
+    }
+    /// lock the current session (disable reconnects)
+    pub async fn lock_session(&self) -> Result<AsyncSessionGuard> {
+        let session_id = self.0.lock_session().await?;
+        Ok(AsyncSessionGuard {
+            client: self.clone(),
+            session_id,
+        })
+    }
+}
+
+pub struct AsyncSessionGuard {
+    client: AsyncClient,
+    session_id: usize,
+}
+
+impl AsyncSessionGuard {
+    pub fn session_id(&self) -> usize {
+        self.session_id
+    }
+}
+
+impl Drop for AsyncSessionGuard {
+    fn drop(&mut self) {
+        self.client.0.unlock_session();
+    }
+}
+
+pub(super) trait AsyncCommunicator {
+    fn lock(&self) -> BoxFuture<'_, MutexGuard<'_, ()>>;
+    fn reconnect(&self) -> BoxFuture<'_, ()>;
+    fn write<'a>(&'a self, buf: &'a [u8]) -> BoxFuture<'a, Result<()>>;
+    fn read_exact<'a>(&'a self, buf: &'a mut [u8]) -> BoxFuture<'a, Result<()>>;
+    fn protocol(&self) -> Protocol;
+    fn session_id(&self) -> usize;
+    fn local_ip_addr(&self) -> BoxFuture<'_, Result<Option<SocketAddr>>> {
+        Box::pin(async { Ok(None) })
+    }
+    fn lock_session(&self) -> BoxFuture<'_, Result<usize>>;
+    fn unlock_session(&self);
+}
+
+/// Runs the future with the timeout, zero means no timeout
+pub(super) async fn with_timeout<T, F>(timeout: Duration, f: F) -> Result<T>
+where
+    F: Future<Output = std::io::Result<T>>,
+{
+    if timeout > Duration::from_secs(0) {
+        tokio::time::timeout(timeout, f)
+            .await
+            .map_err(|_| Error::Timeout)?
+            .map_err(Into::into)
+    } else {
+        f.await.map_err(Into::into)
+    }
+}
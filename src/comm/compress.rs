@@ -0,0 +1,470 @@
+//! Transparent per-connection compression for high-volume links (hub bridges, telemetry and
+//! recording uploads over cellular uplinks).
+//!
+//! [`CompressedStream`] wraps any [`Read`] + [`Write`] stream (e.g. [`std::net::TcpStream`]),
+//! splits outgoing data into frames and compresses each frame with the algorithm, negotiated with
+//! the peer. Frames which do not benefit from compression are sent as-is. The raw
+//! [`Client`](super::Client) path is not affected and should still be used for latency-critical
+//! device traffic.
+//!
+//! ```rust,no_run
+//! use roboplc::comm::compress::{CompressedStream, Compression};
+//! use std::io::Write;
+//! use std::net::TcpStream;
+//!
+//! let stream = TcpStream::connect("10.0.0.1:7777").unwrap();
+//! // both peers must negotiate
+//! let mut stream = CompressedStream::negotiate(stream, &[Compression::Lz4]).unwrap();
+//! stream.write_all(b"telemetry data").unwrap();
+//! // the data is sent when the frame is full or on flush
+//! stream.flush().unwrap();
+//! let stats = stream.stats();
+//! println!("{}, ratio: {:.2}", stream.compression(), stats.ratio_out());
+//! ```
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+/// The default frame size (uncompressed bytes)
+pub const DEFAULT_FRAME_SIZE: usize = 65_536;
+
+/// The maximum frame size (uncompressed bytes), larger frames are rejected by readers
+pub const MAX_FRAME_SIZE: usize = 16 * 1_048_576;
+
+const HELLO_MAGIC: &[u8; 4] = b"RPCZ";
+const HELLO_VERSION: u8 = 1;
+const FRAME_HEADER_SIZE: usize = 9;
+const FRAME_STORED: u8 = 0;
+const FRAME_COMPRESSED: u8 = 1;
+
+/// Compression algorithm
+#[derive(Default, Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// No compression, data is framed only
+    #[default]
+    None,
+    /// LZ4 block compression (fast, moderate ratio)
+    Lz4,
+    /// Zstandard compression (slower, better ratio, requires `comm-compression-zstd` feature)
+    Zstd,
+}
+
+impl Compression {
+    // negotiation preference, the best ratio first
+    const PREFERENCE: [Compression; 3] = [Compression::Zstd, Compression::Lz4, Compression::None];
+
+    /// Returns true if the algorithm is supported by the current build
+    pub fn is_supported(self) -> bool {
+        match self {
+            Compression::None | Compression::Lz4 => true,
+            Compression::Zstd => cfg!(feature = "comm-compression-zstd"),
+        }
+    }
+    fn bit(self) -> u8 {
+        match self {
+            Compression::None => 1,
+            Compression::Lz4 => 1 << 1,
+            Compression::Zstd => 1 << 2,
+        }
+    }
+    // capability mask of the allowed algorithms, supported by the current build
+    fn capabilities(allowed: &[Compression]) -> u8 {
+        allowed
+            .iter()
+            .filter(|c| c.is_supported())
+            .fold(0, |mask, c| mask | c.bit())
+    }
+    // both peers select the same algorithm from the common capabilities
+    fn select(capabilities: u8) -> Option<Self> {
+        Self::PREFERENCE
+            .into_iter()
+            .find(|c| capabilities & c.bit() != 0)
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Lz4 => write!(f, "lz4"),
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "lz4" => Ok(Compression::Lz4),
+            "zstd" => Ok(Compression::Zstd),
+            v => Err(Error::invalid_data(format!("invalid compression: {}", v))),
+        }
+    }
+}
+
+/// Compression statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompressionStats {
+    /// Uncompressed bytes written
+    pub raw_bytes_out: u64,
+    /// Bytes sent to the peer, including frame headers
+    pub wire_bytes_out: u64,
+    /// Uncompressed bytes received
+    pub raw_bytes_in: u64,
+    /// Bytes received from the peer, including frame headers
+    pub wire_bytes_in: u64,
+    /// Frames sent as-is, as compression did not reduce their size
+    pub stored_frames: u64,
+    /// Time spent on compression
+    pub compress_time: Duration,
+    /// Time spent on decompression
+    pub decompress_time: Duration,
+}
+
+impl CompressionStats {
+    /// Outgoing compression ratio (wire bytes / raw bytes, 1.0 if no data has been sent)
+    pub fn ratio_out(&self) -> f64 {
+        ratio(self.wire_bytes_out, self.raw_bytes_out)
+    }
+    /// Incoming compression ratio (wire bytes / raw bytes, 1.0 if no data has been received)
+    pub fn ratio_in(&self) -> f64 {
+        ratio(self.wire_bytes_in, self.raw_bytes_in)
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn ratio(wire: u64, raw: u64) -> f64 {
+    if raw == 0 {
+        1.0
+    } else {
+        wire as f64 / raw as f64
+    }
+}
+
+/// A compressed stream. Written data is buffered until a frame is full or the stream is flushed,
+/// so [`Write::flush()`] must be called after each message (the buffered data is lost if the
+/// stream is dropped without flushing)
+pub struct CompressedStream<S> {
+    inner: S,
+    compression: Compression,
+    zstd_level: i32,
+    frame_size: usize,
+    write_buf: Vec<u8>,
+    read_buf: Vec<u8>,
+    read_pos: usize,
+    frame_buf: Vec<u8>,
+    stats: CompressionStats,
+}
+
+impl<S> CompressedStream<S>
+where
+    S: Read + Write,
+{
+    /// Creates a compressed stream with a pre-configured algorithm (both peers must use the
+    /// same one). Use [`CompressedStream::negotiate()`] to select the algorithm automatically
+    pub fn new(inner: S, compression: Compression) -> Result<Self> {
+        if !compression.is_supported() {
+            return Err(Error::invalid_data(format!(
+                "compression {} is not supported by the build",
+                compression
+            )));
+        }
+        Ok(Self {
+            inner,
+            compression,
+            zstd_level: 3,
+            frame_size: DEFAULT_FRAME_SIZE,
+            write_buf: Vec::new(),
+            read_buf: Vec::new(),
+            read_pos: 0,
+            frame_buf: Vec::new(),
+            stats: CompressionStats::default(),
+        })
+    }
+    /// Exchanges compression capabilities with the peer and creates a compressed stream with the
+    /// best algorithm, allowed by both. Both peers must call the method on connect, before any
+    /// other data is sent (e.g. before `SchemaVersion::negotiate()` of the schema module). An error
+    /// is returned if the peers have got no common algorithms (add [`Compression::None`] to
+    /// the allowed list to fall back to uncompressed frames)
+    pub fn negotiate(mut inner: S, allowed: &[Compression]) -> Result<Self> {
+        let local = Compression::capabilities(allowed);
+        let mut hello = [0u8; 6];
+        hello[..4].copy_from_slice(HELLO_MAGIC);
+        hello[4] = HELLO_VERSION;
+        hello[5] = local;
+        inner.write_all(&hello)?;
+        inner.flush()?;
+        inner.read_exact(&mut hello)?;
+        if &hello[..4] != HELLO_MAGIC {
+            return Err(Error::invalid_data(
+                "the peer does not support compressed streams",
+            ));
+        }
+        if hello[4] != HELLO_VERSION {
+            return Err(Error::invalid_data(format!(
+                "unsupported compressed stream version: {}",
+                hello[4]
+            )));
+        }
+        let Some(compression) = Compression::select(local & hello[5]) else {
+            return Err(Error::invalid_data(
+                "no common compression algorithms with the peer",
+            ));
+        };
+        Self::new(inner, compression)
+    }
+    /// Zstandard compression level (the default is 3)
+    pub fn zstd_level(mut self, level: i32) -> Self {
+        self.zstd_level = level;
+        self
+    }
+    /// The maximum number of uncompressed bytes per frame (the default is
+    /// [`DEFAULT_FRAME_SIZE`], limited to [`MAX_FRAME_SIZE`]). Larger frames give better ratios
+    /// but increase the memory usage
+    pub fn frame_size(mut self, size: usize) -> Self {
+        self.frame_size = size.clamp(1, MAX_FRAME_SIZE);
+        self
+    }
+    /// The selected compression algorithm
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+    /// Compression statistics
+    pub fn stats(&self) -> CompressionStats {
+        self.stats
+    }
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+    /// Returns the inner stream. Buffered data is discarded, so the stream must be flushed first
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+    fn write_frame(&mut self) -> io::Result<()> {
+        if self.write_buf.is_empty() {
+            return Ok(());
+        }
+        let compressed = if self.compression == Compression::None {
+            None
+        } else {
+            let started = Instant::now();
+            let compressed = compress(self.compression, self.zstd_level, &self.write_buf)?;
+            self.stats.compress_time += started.elapsed();
+            Some(compressed).filter(|c| c.len() < self.write_buf.len())
+        };
+        let (kind, payload) = if let Some(ref c) = compressed {
+            (FRAME_COMPRESSED, c.as_slice())
+        } else {
+            if self.compression != Compression::None {
+                self.stats.stored_frames += 1;
+            }
+            (FRAME_STORED, self.write_buf.as_slice())
+        };
+        // the frame size is limited by MAX_FRAME_SIZE, can not overflow
+        let mut header = [0u8; FRAME_HEADER_SIZE];
+        header[0] = kind;
+        header[1..5].copy_from_slice(&u32::try_from(self.write_buf.len()).unwrap().to_le_bytes());
+        header[5..].copy_from_slice(&u32::try_from(payload.len()).unwrap().to_le_bytes());
+        self.inner.write_all(&header)?;
+        self.inner.write_all(payload)?;
+        self.stats.raw_bytes_out += self.write_buf.len() as u64;
+        self.stats.wire_bytes_out += (FRAME_HEADER_SIZE + payload.len()) as u64;
+        self.write_buf.clear();
+        Ok(())
+    }
+    // returns false on EOF
+    fn read_frame(&mut self) -> io::Result<bool> {
+        let mut header = [0u8; FRAME_HEADER_SIZE];
+        let mut pos = 0;
+        while pos < FRAME_HEADER_SIZE {
+            match self.inner.read(&mut header[pos..]) {
+                Ok(0) if pos == 0 => return Ok(false),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => pos += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let raw_len = u32::from_le_bytes(header[1..5].try_into().unwrap()) as usize;
+        let len = u32::from_le_bytes(header[5..].try_into().unwrap()) as usize;
+        let valid = match header[0] {
+            FRAME_STORED => len == raw_len,
+            FRAME_COMPRESSED => len < raw_len && self.compression != Compression::None,
+            _ => false,
+        };
+        if !valid || raw_len > MAX_FRAME_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid compressed stream frame",
+            ));
+        }
+        self.frame_buf.resize(len, 0);
+        self.inner.read_exact(&mut self.frame_buf)?;
+        if header[0] == FRAME_COMPRESSED {
+            let started = Instant::now();
+            self.read_buf = decompress(self.compression, &self.frame_buf, raw_len)?;
+            self.stats.decompress_time += started.elapsed();
+            if self.read_buf.len() != raw_len {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "compressed stream frame size mismatch",
+                ));
+            }
+        } else {
+            std::mem::swap(&mut self.read_buf, &mut self.frame_buf);
+        }
+        self.read_pos = 0;
+        self.stats.raw_bytes_in += raw_len as u64;
+        self.stats.wire_bytes_in += (FRAME_HEADER_SIZE + len) as u64;
+        Ok(true)
+    }
+}
+
+impl<S> Read for CompressedStream<S>
+where
+    S: Read + Write,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // empty frames are skipped
+        while self.read_pos == self.read_buf.len() {
+            if !self.read_frame()? {
+                return Ok(0);
+            }
+        }
+        let len = buf.len().min(self.read_buf.len() - self.read_pos);
+        buf[..len].copy_from_slice(&self.read_buf[self.read_pos..self.read_pos + len]);
+        self.read_pos += len;
+        Ok(len)
+    }
+}
+
+impl<S> Write for CompressedStream<S>
+where
+    S: Read + Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.frame_size - self.write_buf.len());
+        self.write_buf.extend_from_slice(&buf[..len]);
+        if self.write_buf.len() == self.frame_size {
+            self.write_frame()?;
+        }
+        Ok(len)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_frame()?;
+        self.inner.flush()
+    }
+}
+
+#[allow(unused_variables)]
+fn compress(compression: Compression, level: i32, data: &[u8]) -> io::Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(data.to_vec()),
+        Compression::Lz4 => Ok(lz4_flex::block::compress(data)),
+        #[cfg(feature = "comm-compression-zstd")]
+        Compression::Zstd => zstd::bulk::compress(data, level),
+        #[cfg(not(feature = "comm-compression-zstd"))]
+        Compression::Zstd => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "zstd compression is not supported by the build",
+        )),
+    }
+}
+
+fn decompress(compression: Compression, data: &[u8], raw_len: usize) -> io::Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(data.to_vec()),
+        Compression::Lz4 => lz4_flex::block::decompress(data, raw_len)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        #[cfg(feature = "comm-compression-zstd")]
+        Compression::Zstd => zstd::bulk::decompress(data, raw_len),
+        #[cfg(not(feature = "comm-compression-zstd"))]
+        Compression::Zstd => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "zstd compression is not supported by the build",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CompressedStream, Compression};
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+
+    fn negotiate(
+        a: &'static [Compression],
+        b: &'static [Compression],
+    ) -> (
+        crate::Result<CompressedStream<UnixStream>>,
+        crate::Result<CompressedStream<UnixStream>>,
+    ) {
+        let (sa, sb) = UnixStream::pair().unwrap();
+        let peer = thread::spawn(move || CompressedStream::negotiate(sb, b));
+        let local = CompressedStream::negotiate(sa, a);
+        (local, peer.join().unwrap())
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let (a, b) = negotiate(&[Compression::Lz4, Compression::None], &[Compression::Lz4]);
+        assert_eq!(a.unwrap().compression(), Compression::Lz4);
+        assert_eq!(b.unwrap().compression(), Compression::Lz4);
+        let (a, b) = negotiate(&[Compression::Lz4, Compression::None], &[Compression::None]);
+        assert_eq!(a.unwrap().compression(), Compression::None);
+        assert_eq!(b.unwrap().compression(), Compression::None);
+        let (a, b) = negotiate(&[Compression::Lz4], &[Compression::None]);
+        assert!(a.is_err());
+        assert!(b.is_err());
+    }
+
+    #[test]
+    fn test_stream() {
+        let (a, b) = negotiate(&[Compression::Lz4], &[Compression::Lz4]);
+        let mut a = a.unwrap().frame_size(1000);
+        let mut b = b.unwrap();
+        let data: Vec<u8> = b"temperature=25.0;".repeat(200);
+        let noise: Vec<u8> = (0..500u32)
+            .map(|i| i.wrapping_mul(2_654_435_761).to_le_bytes()[2])
+            .collect();
+        let reader = thread::spawn(move || {
+            let mut buf = vec![0u8; 3400 + 500];
+            b.read_exact(&mut buf).unwrap();
+            // EOF at the frame boundary
+            let mut rest = Vec::new();
+            b.read_to_end(&mut rest).unwrap();
+            assert!(rest.is_empty());
+            (buf, b.stats())
+        });
+        a.write_all(&data).unwrap();
+        a.flush().unwrap();
+        a.write_all(&noise).unwrap();
+        a.flush().unwrap();
+        let stats = a.stats();
+        drop(a);
+        let (received, b_stats) = reader.join().unwrap();
+        assert_eq!(&received[..3400], data);
+        assert_eq!(&received[3400..], noise);
+        assert_eq!(stats.raw_bytes_out, 3900);
+        assert_eq!(stats.wire_bytes_out, b_stats.wire_bytes_in);
+        assert_eq!(b_stats.raw_bytes_in, 3900);
+        // incompressible data is stored as-is
+        assert_eq!(stats.stored_frames, 1);
+        assert!(stats.ratio_out() < 0.5);
+    }
+}
@@ -2,17 +2,34 @@
 use rtsc::data_policy::DataDeliveryPolicy;
 use std::{
     io::{Read, Write},
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
     sync::Arc,
     time::Duration,
 };
 
 use crate::Result;
 
+pub mod capture; // Wire capture
+#[cfg(feature = "comm-async")]
+mod client_async;
+#[cfg(feature = "comm-compression")]
+pub mod compress; // Compressed streams for high-volume links
+pub mod pool; // TCP connection pool
+pub mod redundant; // Redundant communication paths
 pub mod serial; // Serial communications
+#[cfg(feature = "comm-async")]
+pub mod serial_async; // Serial communications, asynchronous edition
+pub mod shaper; // Traffic shaping for shared uplinks
+mod sockopt; // Socket QoS options
 pub mod tcp; // TCP communications
+#[cfg(feature = "comm-async")]
+pub mod tcp_async; // TCP communications, asynchronous edition
+pub mod udp; // UDP communications
 
-/// A versatile (TCP/serial) client
+#[cfg(feature = "comm-async")]
+pub use client_async::{AsyncClient, AsyncSessionGuard};
+
+/// A versatile (TCP/UDP/serial) client
 #[derive(Clone)]
 pub struct Client(Arc<dyn Communicator + Send + Sync>);
 
@@ -21,6 +38,10 @@ impl Client {
     pub fn lock(&self) -> MutexGuard<()> {
         self.0.lock()
     }
+    /// Returns true if both clients share the same connection
+    pub fn is_same(&self, other: &Client) -> bool {
+        Arc::as_ptr(&self.0).cast::<()>() == Arc::as_ptr(&other.0).cast::<()>()
+    }
     /// Reconnect the client in case of read/write problems
     pub fn reconnect(&self) {
         self.0.reconnect();
@@ -45,6 +66,20 @@ pub fn local_ip_addr(&self) -> Result<Option<SocketAddr>> {
     pub fn session_id(&self) -> usize {
         self.0.session_id()
     }
+    /// Create a client which writes all traffic to the wire capture. The original client can be
+    /// still used directly, bypassing the capture
+    pub fn with_capture(&self, capture: capture::WireCapture) -> Client {
+        capture::wrap(self.clone(), capture)
+    }
+    /// Create a client which outgoing data is shaped by the traffic shaper with the given
+    /// priority. The original client can be still used directly, bypassing the shaper
+    pub fn with_shaper(
+        &self,
+        shaper: shaper::TrafficShaper,
+        priority: shaper::TrafficPriority,
+    ) -> Client {
+        shaper::wrap(self.clone(), shaper, priority)
+    }
     /// lock the current session (disable reconnects)
     pub fn lock_session(&self) -> Result<SessionGuard> {
         let session_id = self.0.lock_session()?;
@@ -74,6 +109,7 @@ fn drop(&mut self) {
 
 pub enum Protocol {
     Tcp,
+    Udp,
     Serial,
 }
 
@@ -130,15 +166,26 @@ pub fn none() -> Self {
     }
 }
 
+const DEFAULT_RETRANSMITS: usize = 2;
+
 pub type ChatFn = dyn Fn(&mut dyn Stream) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>>
     + Send
     + Sync;
 
 /// Connection Options
+///
+/// Socket options (source interface/address, DSCP, priority) are applied to TCP connections only.
+/// DSCP marks are used by managed switches and routers to classify control traffic. The socket
+/// priority is a local one: it selects the queue of the egress interface (e.g. with `mqprio` or
+/// `prio` qdisc, configured with `tc`) and, for VLAN interfaces, is mapped to the 802.1p PCP
+/// field with `egress-qos-map` (`ip link add link eth0 name eth0.10 type vlan id 10 egress-qos-map
+/// 6:6`). Setting the priority above 6 requires `CAP_NET_ADMIN`.
 pub struct ConnectionOptions {
     with_reader: bool,
     chat: Option<Box<ChatFn>>,
     timeouts: Timeouts,
+    socket: sockopt::SocketSettings,
+    retransmits: usize,
 }
 
 impl ConnectionOptions {
@@ -152,6 +199,8 @@ pub fn new(timeout: Duration) -> Self {
                 read: timeout,
                 write: timeout,
             },
+            socket: <_>::default(),
+            retransmits: DEFAULT_RETRANSMITS,
         }
     }
     /// Enable the reader channel. The reader channel allows the client to receive a clone of the
@@ -193,4 +242,42 @@ pub fn write_timeout(mut self, timeout: Duration) -> Self {
         self.timeouts.write = timeout;
         self
     }
+    /// Bind connections to a local source IP address (e.g. the address of a VLAN interface)
+    pub fn source_addr(mut self, addr: IpAddr) -> Self {
+        self.socket.source_addr = Some(addr);
+        self
+    }
+    /// Bind connections to a network interface (`SO_BINDTODEVICE`, e.g. `eth0.10` for VLAN 10).
+    /// Requires `CAP_NET_RAW` on older kernels (before 5.7)
+    pub fn interface(mut self, interface: &str) -> Self {
+        self.socket.interface = Some(interface.to_owned());
+        self
+    }
+    /// Set the DSCP value of outgoing packets (0-63, e.g. 46 for expedited forwarding). Sets the
+    /// TOS byte (IPv4) or the traffic class (IPv6)
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the value is greater than 63
+    pub fn dscp(mut self, dscp: u8) -> Self {
+        assert!(dscp < 64, "DSCP must be in range 0-63");
+        self.socket.tos = Some(dscp << 2);
+        self
+    }
+    /// Set the raw TOS byte (IPv4) or the traffic class (IPv6), overrides [`Self::dscp()`]
+    pub fn tos(mut self, tos: u8) -> Self {
+        self.socket.tos = Some(tos);
+        self
+    }
+    /// Set the socket priority (`SO_PRIORITY`), see the struct documentation
+    pub fn socket_priority(mut self, priority: u32) -> Self {
+        self.socket.priority = Some(priority);
+        self
+    }
+    /// Set the number of request retransmits if no response is received within the read timeout
+    /// (UDP clients only, the default is 2). Requests must be idempotent
+    pub fn retransmits(mut self, retransmits: usize) -> Self {
+        self.retransmits = retransmits;
+        self
+    }
 }
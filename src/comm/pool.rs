@@ -0,0 +1,408 @@
+use crate::pchannel;
+use crate::{Error, Result};
+
+use super::tcp::{Tcp, TcpClient};
+use super::{Client, CommReader, ConnectionOptions};
+use core::fmt;
+use parking_lot_rt::{Condvar, Mutex};
+use std::collections::BTreeMap;
+use std::net::ToSocketAddrs;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{trace, warn};
+
+// how often idle connections are checked during regular operations
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Connection pool options
+pub struct PoolOptions {
+    max_concurrent_connects: usize,
+    max_open: usize,
+    idle_timeout: Duration,
+    connect_rate: u32,
+    backoff_min: Duration,
+    backoff_max: Duration,
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        Self {
+            max_concurrent_connects: 4,
+            max_open: 0,
+            idle_timeout: Duration::from_secs(60),
+            connect_rate: 20,
+            backoff_min: Duration::from_millis(500),
+            backoff_max: Duration::from_secs(30),
+        }
+    }
+}
+
+impl PoolOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Maximum number of connection attempts in progress at the same time (the default is 4)
+    pub fn max_concurrent_connects(mut self, max: usize) -> Self {
+        self.max_concurrent_connects = max.max(1);
+        self
+    }
+    /// Maximum number of open connections. If exceeded, the least recently used connections are
+    /// closed (the default is 0, unlimited)
+    pub fn max_open(mut self, max: usize) -> Self {
+        self.max_open = max;
+        self
+    }
+    /// Connections which are not used longer than the timeout are closed (the default is 60
+    /// seconds, zero to disable). The connections are automatically re-established on the next
+    /// request
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+    /// Maximum number of connection attempts per second for the whole pool (the default is 20, 0
+    /// for unlimited)
+    pub fn connect_rate(mut self, rate: u32) -> Self {
+        self.connect_rate = rate;
+        self
+    }
+    /// Reconnect backoff for a failed device: the delay is doubled after each failed attempt, from
+    /// `min` to `max` (the defaults are 500 milliseconds and 30 seconds). The actual delay is
+    /// randomized by ±25% to stagger reconnects of many devices
+    pub fn reconnect_backoff(mut self, min: Duration, max: Duration) -> Self {
+        self.backoff_min = min;
+        self.backoff_max = max.max(min);
+        self
+    }
+}
+
+/// Connection pool for polling many TCP devices. The pool limits concurrent and per-second
+/// connection attempts, staggers reconnects of failed devices and closes idle/least recently
+/// used connections. Clients, created by the pool, are regular [`Client`]s and can be used with
+/// any mapping.
+#[derive(Clone)]
+#[allow(clippy::module_name_repetitions)]
+pub struct ConnectionPool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    options: PoolOptions,
+    state: Mutex<PoolState>,
+    connect_slot: Condvar,
+}
+
+struct PoolState {
+    connecting: usize,
+    next_connect: Instant,
+    last_maintenance: Instant,
+    members: BTreeMap<usize, Member>,
+    next_id: usize,
+    rng: u64,
+}
+
+struct Member {
+    tcp: Weak<Tcp>,
+    connected: bool,
+    last_used: Instant,
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+impl ConnectionPool {
+    pub fn new(options: PoolOptions) -> Self {
+        #[allow(clippy::cast_possible_truncation)]
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        let now = Instant::now();
+        Self {
+            inner: Arc::new(PoolInner {
+                options,
+                state: Mutex::new(PoolState {
+                    connecting: 0,
+                    next_connect: now,
+                    last_maintenance: now,
+                    members: <_>::default(),
+                    next_id: 0,
+                    rng: seed | 1,
+                }),
+                connect_slot: Condvar::new(),
+            }),
+        }
+    }
+    /// Creates a new pooled TCP client (see [`super::tcp::connect()`])
+    pub fn connect<A: ToSocketAddrs + fmt::Debug>(
+        &self,
+        addr: A,
+        timeout: Duration,
+    ) -> Result<Client> {
+        Ok(self
+            .connect_with_options(addr, ConnectionOptions::new(timeout))?
+            .0)
+    }
+    /// Creates a new pooled TCP client with options (see [`super::tcp::connect_with_options()`])
+    pub fn connect_with_options<A: ToSocketAddrs + fmt::Debug>(
+        &self,
+        addr: A,
+        options: ConnectionOptions,
+    ) -> Result<(Client, Option<pchannel::Receiver<CommReader>>)> {
+        let member = {
+            let mut state = self.inner.state.lock();
+            let id = state.next_id;
+            state.next_id += 1;
+            PoolMember {
+                pool: self.clone(),
+                id,
+            }
+        };
+        let (tcp, maybe_rx) = Tcp::create_pooled(addr, options, member.clone())?;
+        member.register(&tcp);
+        Ok((Client(tcp), maybe_rx))
+    }
+    /// Number of clients in the pool
+    pub fn len(&self) -> usize {
+        self.inner.state.lock().members.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Number of open connections
+    pub fn open_connections(&self) -> usize {
+        self.inner
+            .state
+            .lock()
+            .members
+            .values()
+            .filter(|m| m.connected)
+            .count()
+    }
+    /// Closes idle and least recently used connections. Called automatically on pool operations,
+    /// may be called manually if the pool is not used for a long time
+    pub fn close_idle(&self) {
+        let victims = {
+            let mut state = self.inner.state.lock();
+            self.collect_victims(&mut state, None)
+        };
+        close_victims(victims);
+    }
+    // the connections are closed outside of the pool lock
+    fn collect_victims(&self, state: &mut PoolState, except: Option<usize>) -> Vec<TcpClient> {
+        let now = Instant::now();
+        state.last_maintenance = now;
+        let idle_timeout = self.inner.options.idle_timeout;
+        let mut victims = Vec::new();
+        let mut open: Vec<(Instant, usize)> = Vec::new();
+        for (id, member) in &mut state.members {
+            if !member.connected || Some(*id) == except {
+                continue;
+            }
+            if idle_timeout > Duration::ZERO && now.duration_since(member.last_used) >= idle_timeout
+            {
+                if let Some(tcp) = member.tcp.upgrade() {
+                    victims.push(tcp);
+                }
+                member.connected = false;
+            } else {
+                open.push((member.last_used, *id));
+            }
+        }
+        let max_open = self.inner.options.max_open;
+        if max_open > 0 {
+            // the excepted member is a fresh connection and is counted as open
+            let total = open.len() + usize::from(except.is_some());
+            if total > max_open {
+                open.sort();
+                for (_, id) in open.into_iter().take(total - max_open) {
+                    let member = state.members.get_mut(&id).unwrap();
+                    if let Some(tcp) = member.tcp.upgrade() {
+                        victims.push(tcp);
+                    }
+                    member.connected = false;
+                }
+            }
+        }
+        victims
+    }
+}
+
+fn close_victims(victims: Vec<TcpClient>) {
+    for tcp in victims {
+        if tcp.close_if_idle() {
+            trace!(addr=%tcp.addr(), "idle pooled connection closed");
+        }
+    }
+}
+
+impl fmt::Debug for ConnectionPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionPool")
+            .field("clients", &self.len())
+            .field("open", &self.open_connections())
+            .finish()
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct PoolMember {
+    pool: ConnectionPool,
+    id: usize,
+}
+
+pub(super) struct ConnectPermit<'a> {
+    pool: &'a ConnectionPool,
+}
+
+impl Drop for ConnectPermit<'_> {
+    fn drop(&mut self) {
+        let mut state = self.pool.inner.state.lock();
+        state.connecting -= 1;
+        self.pool.inner.connect_slot.notify_one();
+    }
+}
+
+impl PoolMember {
+    fn register(&self, tcp: &TcpClient) {
+        self.pool.inner.state.lock().members.insert(
+            self.id,
+            Member {
+                tcp: Arc::downgrade(tcp),
+                connected: false,
+                last_used: Instant::now(),
+                failures: 0,
+                retry_at: None,
+            },
+        );
+    }
+    /// Waits for a connection slot. Returns an error if the device is in the reconnect backoff
+    /// or the slot is not available in time
+    pub(super) fn acquire(&self, timeout: Duration) -> Result<ConnectPermit<'_>> {
+        let inner = &self.pool.inner;
+        let deadline = (timeout > Duration::ZERO).then(|| Instant::now() + timeout);
+        let mut state = inner.state.lock();
+        if let Some(retry_at) = state.members.get(&self.id).and_then(|m| m.retry_at) {
+            let now = Instant::now();
+            if retry_at > now {
+                return Err(Error::io(format!(
+                    "reconnect backoff, next attempt in {:?}",
+                    retry_at - now
+                )));
+            }
+        }
+        loop {
+            let now = Instant::now();
+            if state.connecting < inner.options.max_concurrent_connects && state.next_connect <= now
+            {
+                break;
+            }
+            let mut wake_at = deadline;
+            if state.connecting < inner.options.max_concurrent_connects {
+                wake_at = Some(wake_at.map_or(state.next_connect, |d| d.min(state.next_connect)));
+            }
+            if let Some(wake_at) = wake_at {
+                if deadline.map_or(false, |d| now >= d) {
+                    return Err(Error::Timeout);
+                }
+                inner
+                    .connect_slot
+                    .wait_for(&mut state, wake_at.saturating_duration_since(now));
+            } else {
+                inner.connect_slot.wait(&mut state);
+            }
+        }
+        state.connecting += 1;
+        if inner.options.connect_rate > 0 {
+            let step = Duration::from_secs(1) / inner.options.connect_rate;
+            state.next_connect = state.next_connect.max(Instant::now()) + step;
+        }
+        Ok(ConnectPermit { pool: &self.pool })
+    }
+    pub(super) fn connected(&self) {
+        let victims = {
+            let mut state = self.pool.inner.state.lock();
+            if let Some(member) = state.members.get_mut(&self.id) {
+                member.connected = true;
+                member.last_used = Instant::now();
+                member.failures = 0;
+                member.retry_at = None;
+            }
+            self.pool.collect_victims(&mut state, Some(self.id))
+        };
+        close_victims(victims);
+    }
+    pub(super) fn failed(&self) {
+        let mut state = self.pool.inner.state.lock();
+        let jitter = next_jitter(&mut state.rng);
+        let options = &self.pool.inner.options;
+        if let Some(member) = state.members.get_mut(&self.id) {
+            member.connected = false;
+            member.failures += 1;
+            let delay = backoff_delay(
+                options.backoff_min,
+                options.backoff_max,
+                member.failures,
+                jitter,
+            );
+            warn!(failures = member.failures, delay=?delay, "pooled connection failed");
+            member.retry_at = Some(Instant::now() + delay);
+        }
+    }
+    pub(super) fn touch(&self) {
+        let victims = {
+            let mut state = self.pool.inner.state.lock();
+            let now = Instant::now();
+            if let Some(member) = state.members.get_mut(&self.id) {
+                member.connected = true;
+                member.last_used = now;
+            }
+            if now.duration_since(state.last_maintenance) < MAINTENANCE_INTERVAL {
+                return;
+            }
+            self.pool.collect_victims(&mut state, Some(self.id))
+        };
+        close_victims(victims);
+    }
+    pub(super) fn disconnected(&self) {
+        if let Some(member) = self.pool.inner.state.lock().members.get_mut(&self.id) {
+            member.connected = false;
+        }
+    }
+    pub(super) fn unregister(&self) {
+        self.pool.inner.state.lock().members.remove(&self.id);
+    }
+}
+
+// returns a factor in range 0.75..1.25
+fn next_jitter(rng: &mut u64) -> f64 {
+    // xorshift64
+    *rng ^= *rng << 13;
+    *rng ^= *rng >> 7;
+    *rng ^= *rng << 17;
+    #[allow(clippy::cast_precision_loss)]
+    let unit = (*rng >> 11) as f64 / (1u64 << 53) as f64;
+    0.75 + unit * 0.5
+}
+
+fn backoff_delay(min: Duration, max: Duration, failures: u32, jitter: f64) -> Duration {
+    let factor = 2u32.saturating_pow(failures.saturating_sub(1).min(31));
+    min.saturating_mul(factor).min(max).mul_f64(jitter)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{backoff_delay, next_jitter};
+    use std::time::Duration;
+
+    #[test]
+    fn test_backoff() {
+        let min = Duration::from_millis(500);
+        let max = Duration::from_secs(30);
+        assert_eq!(backoff_delay(min, max, 1, 1.0), min);
+        assert_eq!(backoff_delay(min, max, 3, 1.0), Duration::from_secs(2));
+        assert_eq!(backoff_delay(min, max, 100, 1.0), max);
+        let mut rng = 42;
+        for _ in 0..1000 {
+            let jitter = next_jitter(&mut rng);
+            assert!((0.75..1.25).contains(&jitter));
+        }
+    }
+}
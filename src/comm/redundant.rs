@@ -0,0 +1,407 @@
+use crate::{Error, Result};
+
+use super::{Client, Communicator, Protocol};
+use parking_lot_rt::{Mutex, MutexGuard};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+type HealthCheckFn = dyn Fn(&Client) -> bool + Send + Sync;
+
+/// Create a new redundant client which uses two communication paths to the same device. The
+/// primary path is used by default, if it fails, the client automatically switches to the
+/// secondary one and periodically checks the primary path to switch back.
+///
+/// Returns a regular client which can be used with any mapping and an object to monitor/control
+/// the active path.
+pub fn connect(
+    primary: Client,
+    secondary: Client,
+    options: RedundancyOptions,
+) -> (Client, RedundantClient) {
+    let redundant: RedundantClient = Redundant {
+        paths: [primary, secondary],
+        active: AtomicUsize::new(Path::Primary as usize),
+        busy: <_>::default(),
+        state: <_>::default(),
+        switches: <_>::default(),
+        session_locked: AtomicBool::new(false),
+        options,
+    }
+    .into();
+    (Client(redundant.clone()), redundant)
+}
+
+/// Communication path
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(usize)]
+pub enum Path {
+    Primary = 0,
+    Secondary = 1,
+}
+
+impl Path {
+    fn other(self) -> Self {
+        match self {
+            Path::Primary => Path::Secondary,
+            Path::Secondary => Path::Primary,
+        }
+    }
+}
+
+impl From<usize> for Path {
+    fn from(value: usize) -> Self {
+        if value == 0 {
+            Path::Primary
+        } else {
+            Path::Secondary
+        }
+    }
+}
+
+/// Redundancy options
+pub struct RedundancyOptions {
+    failover_threshold: u32,
+    failback_check_interval: Duration,
+    failback_hysteresis: u32,
+    health_check: Box<HealthCheckFn>,
+}
+
+impl Default for RedundancyOptions {
+    fn default() -> Self {
+        Self {
+            failover_threshold: 2,
+            failback_check_interval: Duration::from_secs(5),
+            failback_hysteresis: 3,
+            health_check: Box::new(|client| client.lock_session().is_ok()),
+        }
+    }
+}
+
+impl RedundancyOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Number of failed read/write operations in a row after which the client switches to the
+    /// other path (the default is 2)
+    pub fn failover_threshold(mut self, threshold: u32) -> Self {
+        self.failover_threshold = threshold.max(1);
+        self
+    }
+    /// How often the primary path is checked while the secondary one is active (the default is 5
+    /// seconds). The check is performed in the thread which starts a new transaction, so the
+    /// interval should be larger than the primary client connect timeout
+    pub fn failback_check_interval(mut self, interval: Duration) -> Self {
+        self.failback_check_interval = interval;
+        self
+    }
+    /// Number of successful primary path checks in a row required to switch back (the default is
+    /// 3)
+    pub fn failback_hysteresis(mut self, checks: u32) -> Self {
+        self.failback_hysteresis = checks.max(1);
+        self
+    }
+    /// Overrides the path health check function. The default function establishes a connection
+    /// if required (see [`Client::lock_session()`])
+    pub fn health_check<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Client) -> bool + Send + Sync + 'static,
+    {
+        self.health_check = Box::new(f);
+        self
+    }
+}
+
+#[derive(Default)]
+struct RedundancyState {
+    failures: u32,
+    failback_checks: u32,
+    last_failback_check: Option<Instant>,
+    // a manual switch, requested while a transaction is in progress
+    requested: Option<Path>,
+}
+
+#[allow(clippy::module_name_repetitions)]
+pub struct Redundant {
+    paths: [Client; 2],
+    active: AtomicUsize,
+    busy: Mutex<()>,
+    // paths are switched and sessions are locked under the state lock only
+    state: Mutex<RedundancyState>,
+    switches: AtomicUsize,
+    session_locked: AtomicBool,
+    options: RedundancyOptions,
+}
+
+#[allow(clippy::module_name_repetitions)]
+pub type RedundantClient = Arc<Redundant>;
+
+impl Redundant {
+    /// The currently active path
+    pub fn active_path(&self) -> Path {
+        self.active.load(Ordering::Acquire).into()
+    }
+    /// The client of the given path
+    pub fn path_client(&self, path: Path) -> &Client {
+        &self.paths[path as usize]
+    }
+    /// Number of path switches since the client has been created
+    pub fn switches(&self) -> usize {
+        self.switches.load(Ordering::Acquire)
+    }
+    /// Manually switches the active path. If a transaction is in progress (including the one of
+    /// the calling thread, which holds [`Client::lock()`]), the path is switched when the next
+    /// transaction starts. Returns an error if the current session is locked
+    pub fn switch_to(&self, path: Path) -> Result<()> {
+        let busy = self.busy.try_lock();
+        let mut state = self.state.lock();
+        if self.session_locked.load(Ordering::Acquire) {
+            return Err(Error::failed("session locked, path switching not allowed"));
+        }
+        if busy.is_some() {
+            state.requested = None;
+            self.set_active(&mut state, path);
+        } else {
+            state.requested = Some(path);
+        }
+        Ok(())
+    }
+    fn active_client(&self) -> &Client {
+        &self.paths[self.active.load(Ordering::Acquire)]
+    }
+    fn set_active(&self, state: &mut RedundancyState, path: Path) {
+        if self.active.swap(path as usize, Ordering::AcqRel) != path as usize {
+            self.switches.fetch_add(1, Ordering::AcqRel);
+            state.failures = 0;
+            state.failback_checks = 0;
+            state.last_failback_check = None;
+        }
+    }
+    fn handle_result<T>(&self, result: Result<T>) -> Result<T> {
+        let mut state = self.state.lock();
+        if result.is_ok() {
+            state.failures = 0;
+        } else if !self.session_locked.load(Ordering::Acquire) {
+            state.failures += 1;
+            if state.failures >= self.options.failover_threshold {
+                let path = self.active_path();
+                warn!(from=?path, to=?path.other(), "communication path failed, switching");
+                self.set_active(&mut state, path.other());
+            }
+        }
+        result
+    }
+    // applies a requested manual switch, called at the start of a transaction
+    fn apply_requested(&self) {
+        let mut state = self.state.lock();
+        if let Some(path) = state.requested.take() {
+            if !self.session_locked.load(Ordering::Acquire) {
+                self.set_active(&mut state, path);
+            }
+        }
+    }
+    fn check_failback(&self) {
+        if self.active_path() == Path::Primary || self.session_locked.load(Ordering::Acquire) {
+            return;
+        }
+        {
+            let mut state = self.state.lock();
+            if state.last_failback_check.map_or(false, |t| {
+                t.elapsed() < self.options.failback_check_interval
+            }) {
+                return;
+            }
+            state.last_failback_check = Some(Instant::now());
+        }
+        let healthy = (self.options.health_check)(&self.paths[Path::Primary as usize]);
+        let mut state = self.state.lock();
+        if healthy {
+            state.failback_checks += 1;
+            if state.failback_checks >= self.options.failback_hysteresis
+                && !self.session_locked.load(Ordering::Acquire)
+            {
+                warn!("primary communication path restored, switching back");
+                self.set_active(&mut state, Path::Primary);
+            }
+        } else {
+            state.failback_checks = 0;
+        }
+    }
+}
+
+impl Communicator for Redundant {
+    fn lock(&self) -> MutexGuard<()> {
+        let lock = self.busy.lock();
+        self.apply_requested();
+        self.check_failback();
+        lock
+    }
+    fn reconnect(&self) {
+        self.active_client().reconnect();
+    }
+    fn write(&self, buf: &[u8]) -> Result<()> {
+        self.handle_result(self.active_client().write(buf))
+    }
+    fn read_exact(&self, buf: &mut [u8]) -> Result<()> {
+        self.handle_result(self.active_client().read_exact(buf))
+    }
+    fn protocol(&self) -> Protocol {
+        self.active_client().protocol()
+    }
+    fn session_id(&self) -> usize {
+        // changed either if any of the paths is reconnected or the active path is switched
+        self.paths[0]
+            .session_id()
+            .wrapping_add(self.paths[1].session_id())
+            .wrapping_add(self.switches())
+    }
+    fn local_ip_addr(&self) -> Result<Option<SocketAddr>> {
+        self.active_client().local_ip_addr()
+    }
+    // the busy lock is not taken, so the session can be locked inside a transaction
+    fn lock_session(&self) -> Result<usize> {
+        {
+            // no path switches after the flag is set
+            let _state = self.state.lock();
+            self.session_locked.store(true, Ordering::Release);
+        }
+        if let Err(e) = self.active_client().0.lock_session() {
+            self.session_locked.store(false, Ordering::Release);
+            return Err(e);
+        }
+        Ok(self.session_id())
+    }
+    fn unlock_session(&self) {
+        // the active path can not be switched while the session is locked
+        self.active_client().0.unlock_session();
+        self.session_locked.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{connect, Path, RedundancyOptions, RedundantClient};
+    use crate::comm::{Client, Communicator, Protocol};
+    use crate::{Error, Result};
+    use parking_lot_rt::{Mutex, MutexGuard};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct Mock {
+        busy: Mutex<()>,
+        failed: Arc<AtomicBool>,
+    }
+
+    impl Communicator for Mock {
+        fn lock(&self) -> MutexGuard<()> {
+            self.busy.lock()
+        }
+        fn reconnect(&self) {}
+        fn write(&self, _buf: &[u8]) -> Result<()> {
+            if self.failed.load(Ordering::SeqCst) {
+                Err(Error::io("path failed"))
+            } else {
+                Ok(())
+            }
+        }
+        fn read_exact(&self, _buf: &mut [u8]) -> Result<()> {
+            self.write(&[])
+        }
+        fn protocol(&self) -> Protocol {
+            Protocol::Tcp
+        }
+        fn session_id(&self) -> usize {
+            0
+        }
+        fn lock_session(&self) -> Result<usize> {
+            Ok(0)
+        }
+        fn unlock_session(&self) {}
+    }
+
+    // returns the client, the redundant handle and the primary path failure flag
+    fn create(options: RedundancyOptions) -> (Client, RedundantClient, Arc<AtomicBool>) {
+        let primary = Mock::default();
+        let failed = primary.failed.clone();
+        let (client, redundant) = connect(
+            Client(Arc::new(primary)),
+            Client(Arc::new(Mock::default())),
+            options,
+        );
+        (client, redundant, failed)
+    }
+
+    #[test]
+    fn test_failover_threshold() {
+        let (client, redundant, failed) = create(RedundancyOptions::new().failover_threshold(3));
+        failed.store(true, Ordering::SeqCst);
+        for _ in 0..2 {
+            let _lock = client.lock();
+            assert!(client.write(&[]).is_err());
+        }
+        assert_eq!(redundant.active_path(), Path::Primary);
+        // a successful operation resets the failure counter
+        failed.store(false, Ordering::SeqCst);
+        client.write(&[]).unwrap();
+        failed.store(true, Ordering::SeqCst);
+        for _ in 0..2 {
+            assert!(client.write(&[]).is_err());
+        }
+        assert_eq!(redundant.active_path(), Path::Primary);
+        assert!(client.write(&[]).is_err());
+        assert_eq!(redundant.active_path(), Path::Secondary);
+        assert_eq!(redundant.switches(), 1);
+        client.write(&[]).unwrap();
+    }
+
+    #[test]
+    fn test_failback_hysteresis() {
+        let healthy = Arc::new(AtomicBool::new(false));
+        let h = healthy.clone();
+        let options = RedundancyOptions::new()
+            .failover_threshold(1)
+            .failback_check_interval(Duration::ZERO)
+            .failback_hysteresis(3)
+            .health_check(move |_| h.load(Ordering::SeqCst));
+        let (client, redundant, failed) = create(options);
+        failed.store(true, Ordering::SeqCst);
+        assert!(client.write(&[]).is_err());
+        assert_eq!(redundant.active_path(), Path::Secondary);
+        drop(client.lock());
+        assert_eq!(redundant.active_path(), Path::Secondary);
+        healthy.store(true, Ordering::SeqCst);
+        drop(client.lock());
+        drop(client.lock());
+        // a failed check resets the hysteresis counter
+        healthy.store(false, Ordering::SeqCst);
+        drop(client.lock());
+        healthy.store(true, Ordering::SeqCst);
+        drop(client.lock());
+        drop(client.lock());
+        assert_eq!(redundant.active_path(), Path::Secondary);
+        drop(client.lock());
+        assert_eq!(redundant.active_path(), Path::Primary);
+        assert_eq!(redundant.switches(), 2);
+    }
+
+    #[test]
+    fn test_switch_in_transaction() {
+        let (client, redundant, _) = create(RedundancyOptions::new());
+        {
+            let _lock = client.lock();
+            redundant.switch_to(Path::Secondary).unwrap();
+            assert_eq!(redundant.active_path(), Path::Primary);
+            let session = client.lock_session().unwrap();
+            assert!(redundant.switch_to(Path::Primary).is_err());
+            drop(session);
+        }
+        drop(client.lock());
+        assert_eq!(redundant.active_path(), Path::Secondary);
+        redundant.switch_to(Path::Primary).unwrap();
+        assert_eq!(redundant.active_path(), Path::Primary);
+    }
+}
@@ -6,15 +6,17 @@
 use parking_lot_rt::{Mutex, MutexGuard};
 use serial::prelude::*;
 use serial::SystemPort;
+use std::fs;
 use std::io;
 use std::io::{Read, Write};
+use std::path::Path;
 use std::str::FromStr;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tracing::trace;
+use tracing::{info, trace};
 
 /// Create a new serial client. The client will attempt to connect to the given address at the time
 /// of the first request. The client will automatically reconnect if the connection is lost.
@@ -22,6 +24,98 @@ pub fn connect(path: &str, timeout: Duration, frame_delay: Duration) -> Result<C
     Ok(Client(Serial::create(path, timeout, frame_delay)?))
 }
 
+/// Create a new serial client with the inter-frame delay automatically calculated from the port
+/// parameters (Modbus RTU T3.5 silent interval, see [`Parameters::inter_frame_delay()`]). To
+/// override the calculated value, use [`connect()`] instead.
+pub fn connect_rtu(path: &str, timeout: Duration) -> Result<Client> {
+    let frame_delay = parse_path(path)?.inter_frame_delay();
+    Ok(Client(Serial::create(path, timeout, frame_delay)?))
+}
+
+// Modbus over serial line specification: for baud rates higher than 19200 fixed values are used
+const RTU_FIXED_TIMING_BAUD_RATE: usize = 19200;
+const RTU_FIXED_INTER_FRAME_DELAY: Duration = Duration::from_micros(1750);
+const RTU_FIXED_INTER_CHAR_TIMEOUT: Duration = Duration::from_micros(750);
+// Modbus RTU timings are defined for 11-bit characters (8 data bits with either parity or the
+// second stop bit)
+const RTU_CHAR_BITS: u32 = 11;
+
+// port device prefix for USB adapter ids
+const USB_ID_PREFIX: &str = "usb=";
+
+const SYS_CLASS_TTY: &str = "/sys/class/tty";
+
+/// USB serial adapter id: vendor id, product id (hex) and optionally the serial number of the
+/// adapter, e.g. `0403-6001-A10K7XZQ`.
+///
+/// Can be used as the port device in serial paths with the `usb=` prefix (e.g.
+/// `usb=0403-6001-A10K7XZQ:9600:8:N:1`). The port is looked up among connected USB serial
+/// adapters (`ttyUSB*`, `ttyACM*`) at every (re)connect, so adapters, re-enumerated after
+/// glitches, are found again. If several ports match (e.g. a multi-port adapter), the first one
+/// (by the device name) is used.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UsbDeviceId {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub serial: Option<String>,
+}
+
+impl FromStr for UsbDeviceId {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let err = || Error::invalid_data(format!("invalid USB device id: {}", s));
+        let mut sp = s.splitn(3, '-');
+        let vendor_id = u16::from_str_radix(sp.next().ok_or_else(err)?, 16).map_err(|_| err())?;
+        let product_id = u16::from_str_radix(sp.next().ok_or_else(err)?, 16).map_err(|_| err())?;
+        Ok(Self {
+            vendor_id,
+            product_id,
+            serial: sp.next().map(ToOwned::to_owned),
+        })
+    }
+}
+
+impl UsbDeviceId {
+    fn matches(&self, usb_dev: &Path) -> bool {
+        let attr = |name: &str| {
+            fs::read_to_string(usb_dev.join(name))
+                .ok()
+                .map(|v| v.trim().to_owned())
+        };
+        let id = |name: &str| attr(name).and_then(|v| u16::from_str_radix(&v, 16).ok());
+        id("idVendor") == Some(self.vendor_id)
+            && id("idProduct") == Some(self.product_id)
+            && (self.serial.is_none() || attr("serial") == self.serial)
+    }
+}
+
+// finds a tty device of a USB adapter in sysfs
+fn find_usb_tty(sys_class_tty: &Path, id: &UsbDeviceId) -> Result<Option<String>> {
+    let mut names: Vec<String> = fs::read_dir(sys_class_tty)?
+        .filter_map(|e| e.ok().and_then(|e| e.file_name().into_string().ok()))
+        .filter(|n| n.starts_with("ttyUSB") || n.starts_with("ttyACM"))
+        .collect();
+    names.sort();
+    for name in names {
+        let Ok(mut dev) = fs::canonicalize(sys_class_tty.join(&name).join("device")) else {
+            continue;
+        };
+        // tty port -> interface -> USB device
+        for _ in 0..4 {
+            if dev.join("idVendor").exists() {
+                if id.matches(&dev) {
+                    return Ok(Some(format!("/dev/{}", name)));
+                }
+                break;
+            }
+            if !dev.pop() {
+                break;
+            }
+        }
+    }
+    Ok(None)
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Parameters {
     pub port_dev: String,
@@ -31,6 +125,82 @@ pub struct Parameters {
     pub stop_bits: serial::StopBits,
 }
 
+impl Parameters {
+    /// Resolves the port device. USB adapter ids (see [`UsbDeviceId`]) are looked up among
+    /// connected adapters, symbolic links (e.g. `/dev/serial/by-id/...`) are resolved to the
+    /// actual devices. Called at every (re)connect
+    pub fn resolve_port_dev(&self) -> Result<String> {
+        if let Some(id) = self.port_dev.strip_prefix(USB_ID_PREFIX) {
+            let id: UsbDeviceId = id.parse()?;
+            find_usb_tty(Path::new(SYS_CLASS_TTY), &id)?
+                .ok_or_else(|| Error::io(format!("USB serial device not found: {}", self.port_dev)))
+        } else if self.port_dev.starts_with('/') {
+            fs::canonicalize(&self.port_dev)
+                .map(|p| p.to_string_lossy().into_owned())
+                .map_err(|e| Error::io(format!("serial port {}: {}", self.port_dev, e)))
+        } else {
+            Ok(self.port_dev.clone())
+        }
+    }
+    /// Number of bits transmitted per a single character (including start, parity and stop bits)
+    pub fn bits_per_char(&self) -> u32 {
+        let data_bits = match self.char_size {
+            serial::Bits5 => 5,
+            serial::Bits6 => 6,
+            serial::Bits7 => 7,
+            serial::Bits8 => 8,
+        };
+        let parity_bits = match self.parity {
+            serial::ParityNone => 0,
+            serial::ParityOdd | serial::ParityEven => 1,
+        };
+        let stop_bits = match self.stop_bits {
+            serial::Stop1 => 1,
+            serial::Stop2 => 2,
+        };
+        1 + data_bits + parity_bits + stop_bits
+    }
+    /// Time required to transmit a single character
+    pub fn char_time(&self) -> Duration {
+        self.chars_time(1)
+    }
+    /// Time required to transmit the given number of characters
+    pub fn chars_time(&self, chars: usize) -> Duration {
+        let speed = u64::try_from(self.baud_rate.speed())
+            .unwrap_or(u64::MAX)
+            .max(1);
+        let bits = u64::from(self.bits_per_char()) * u64::try_from(chars).unwrap_or(u64::MAX);
+        Duration::from_nanos(bits.saturating_mul(1_000_000_000) / speed)
+    }
+    /// Modbus RTU inter-frame delay (T3.5, 3.5 character times of at least 11 bits, as defined by
+    /// the Modbus RTU specification). For baud rates higher than 19200 the fixed value of 1.75ms
+    /// is used
+    pub fn inter_frame_delay(&self) -> Duration {
+        if self.baud_rate.speed() > RTU_FIXED_TIMING_BAUD_RATE {
+            RTU_FIXED_INTER_FRAME_DELAY
+        } else {
+            self.rtu_chars_time(7) / 2
+        }
+    }
+    /// Modbus RTU inter-character timeout (T1.5, 1.5 character times of at least 11 bits, as
+    /// defined by the Modbus RTU specification). For baud rates higher than 19200 the fixed value
+    /// of 750us is used
+    pub fn inter_char_timeout(&self) -> Duration {
+        if self.baud_rate.speed() > RTU_FIXED_TIMING_BAUD_RATE {
+            RTU_FIXED_INTER_CHAR_TIMEOUT
+        } else {
+            self.rtu_chars_time(3) / 2
+        }
+    }
+    fn rtu_chars_time(&self, chars: u64) -> Duration {
+        let speed = u64::try_from(self.baud_rate.speed())
+            .unwrap_or(u64::MAX)
+            .max(1);
+        let bits = u64::from(self.bits_per_char().max(RTU_CHAR_BITS)) * chars;
+        Duration::from_nanos(bits * 1_000_000_000 / speed)
+    }
+}
+
 impl FromStr for Parameters {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self> {
@@ -109,8 +279,13 @@ fn parse_path(path: &str) -> Result<Parameters> {
     })
 }
 
+/// Opens a serial port, the port device is resolved with [`Parameters::resolve_port_dev()`]
 pub fn open(params: &Parameters, timeout: Duration) -> Result<SystemPort> {
-    let mut port = serial::open(&params.port_dev).map_err(Error::io)?;
+    open_device(&params.resolve_port_dev()?, params, timeout)
+}
+
+fn open_device(dev: &str, params: &Parameters, timeout: Duration) -> Result<SystemPort> {
+    let mut port = serial::open(dev).map_err(Error::io)?;
     port.reconfigure(&|settings| {
         settings.set_baud_rate(params.baud_rate)?;
         settings.set_char_size(params.char_size);
@@ -141,6 +316,8 @@ pub struct Serial {
 struct SPort {
     system_port: Option<SystemPort>,
     last_frame: Option<Instant>,
+    // the resolved device of the last connection
+    device: Option<String>,
 }
 
 #[allow(clippy::module_name_repetitions)]
@@ -163,7 +340,7 @@ fn write(&self, buf: &[u8]) -> Result<()> {
             .get_port()
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
         if let Some(last_frame) = port.last_frame {
-            let el = last_frame.elapsed();
+            let el = Instant::now().saturating_duration_since(last_frame);
             if el < self.frame_delay {
                 std::thread::sleep(self.frame_delay - el);
             }
@@ -178,7 +355,10 @@ fn write(&self, buf: &[u8]) -> Result<()> {
                 e
             });
         if result.is_ok() {
-            port.last_frame.replace(Instant::now());
+            // the data may be still being transmitted by the UART, count the silent interval from
+            // the expected end of the transmission
+            port.last_frame
+                .replace(Instant::now() + self.params.chars_time(buf.len()));
         }
         result.map_err(Into::into)
     }
@@ -186,15 +366,20 @@ fn read_exact(&self, buf: &mut [u8]) -> Result<()> {
         let mut port = self
             .get_port()
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        port.system_port
+        let result = port
+            .system_port
             .as_mut()
             .unwrap()
             .read_exact(buf)
             .map_err(|e| {
                 self.reconnect();
                 e
-            })
-            .map_err(Into::into)
+            });
+        if result.is_ok() {
+            // the silent interval must be kept after a response frame as well
+            port.last_frame.replace(Instant::now());
+        }
+        result.map_err(Into::into)
     }
     fn protocol(&self) -> Protocol {
         Protocol::Serial
@@ -213,6 +398,10 @@ fn unlock_session(&self) {
 }
 
 impl Serial {
+    /// The inter-frame delay used by the client
+    pub fn frame_delay(&self) -> Duration {
+        self.frame_delay
+    }
     pub fn create(path: &str, timeout: Duration, frame_delay: Duration) -> Result<Arc<Self>> {
         let params = parse_path(path)?;
         Ok(Self {
@@ -233,7 +422,12 @@ fn get_port(&self) -> Result<MutexGuard<SPort>> {
                 return Err(Error::io("not connected but reconnects not allowed"));
             }
             trace!(dev=%self.params.port_dev, "creating new serial connection");
-            let port = open(&self.params, self.timeout)?;
+            let dev = self.params.resolve_port_dev()?;
+            if lock.device.as_ref().map_or(false, |d| *d != dev) {
+                info!(port=%self.params.port_dev, %dev, "serial device changed");
+            }
+            let port = open_device(&dev, &self.params, self.timeout)?;
+            lock.device.replace(dev);
             lock.system_port.replace(port);
             lock.last_frame.take();
             self.session_id.fetch_add(1, Ordering::Release);
@@ -242,3 +436,75 @@ fn get_port(&self) -> Result<MutexGuard<SPort>> {
         Ok(lock)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{find_usb_tty, Parameters, UsbDeviceId};
+    use std::time::Duration;
+
+    #[test]
+    fn test_timings() {
+        let params = |s: &str| s.parse::<Parameters>().unwrap();
+        let p_8n1 = params("/dev/ttyS0:9600:8:N:1");
+        let p_8e1 = params("/dev/ttyS0:9600:8:E:1");
+        let p_8n2 = params("/dev/ttyS0:9600:8:N:2");
+        assert_eq!(p_8n1.bits_per_char(), 10);
+        assert_eq!(p_8e1.bits_per_char(), 11);
+        assert_eq!(p_8n2.bits_per_char(), 11);
+        assert_eq!(p_8n1.chars_time(96), Duration::from_millis(100));
+        assert_eq!(p_8e1.char_time(), Duration::from_nanos(1_145_833));
+        // T3.5 = 3.5 * 11 bits / 9600 baud, for 8N1 as well
+        for p in [&p_8n1, &p_8e1, &p_8n2] {
+            assert_eq!(p.inter_frame_delay(), Duration::from_nanos(4_010_416));
+            assert_eq!(p.inter_char_timeout(), Duration::from_nanos(1_718_750));
+        }
+        let p_19200 = params("/dev/ttyS0:19200:8:E:1");
+        assert_eq!(p_19200.inter_frame_delay(), Duration::from_nanos(2_005_208));
+        for baud_rate in [38400, 57600, 115_200] {
+            let p = params(&format!("/dev/ttyS0:{}:8:N:1", baud_rate));
+            assert_eq!(p.inter_frame_delay(), Duration::from_micros(1750));
+            assert_eq!(p.inter_char_timeout(), Duration::from_micros(750));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_usb_tty() {
+        use std::fs;
+        use std::os::unix::fs::symlink;
+
+        let root = std::env::temp_dir().join(format!("roboplc-test-usb-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let class = root.join("class/tty");
+        fs::create_dir_all(&class).unwrap();
+        for (usb, tty, vendor, product, serial) in [
+            ("1-1", "ttyUSB0", "10c4", "ea60", "0001"),
+            ("1-2", "ttyUSB3", "0403", "6001", "A10K7XZQ"),
+        ] {
+            let usb_dev = root.join("devices").join(usb);
+            let port = usb_dev.join(format!("{}:1.0", usb)).join(tty);
+            fs::create_dir_all(&port).unwrap();
+            fs::write(usb_dev.join("idVendor"), format!("{}\n", vendor)).unwrap();
+            fs::write(usb_dev.join("idProduct"), format!("{}\n", product)).unwrap();
+            fs::write(usb_dev.join("serial"), format!("{}\n", serial)).unwrap();
+            fs::create_dir_all(class.join(tty)).unwrap();
+            symlink(&port, class.join(tty).join("device")).unwrap();
+        }
+        fs::create_dir_all(class.join("ttyS0")).unwrap();
+        let id: UsbDeviceId = "0403-6001-A10K7XZQ".parse().unwrap();
+        assert_eq!(id.vendor_id, 0x0403);
+        assert_eq!(
+            find_usb_tty(&class, &id).unwrap().as_deref(),
+            Some("/dev/ttyUSB3")
+        );
+        let id: UsbDeviceId = "10c4-ea60".parse().unwrap();
+        assert_eq!(
+            find_usb_tty(&class, &id).unwrap().as_deref(),
+            Some("/dev/ttyUSB0")
+        );
+        let id: UsbDeviceId = "0403-6001-OTHER".parse().unwrap();
+        assert!(find_usb_tty(&class, &id).unwrap().is_none());
+        assert!("0403".parse::<UsbDeviceId>().is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{Error, Result};
+
+use super::client_async::{with_timeout, AsyncClient, AsyncCommunicator, BoxFuture};
+use super::serial::Parameters;
+use super::Protocol;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::sync::{Mutex, MutexGuard};
+use tokio_serial::{SerialPortBuilderExt as _, SerialStream};
+use tracing::trace;
+
+/// Create a new asynchronous serial client. The client will attempt to connect to the given
+/// address at the time of the first request. The client will automatically reconnect if the
+/// connection is lost.
+pub fn connect(path: &str, timeout: Duration, frame_delay: Duration) -> Result<AsyncClient> {
+    Ok(AsyncClient(SerialAsync::create(
+        path,
+        timeout,
+        frame_delay,
+    )?))
+}
+
+/// Create a new asynchronous serial client with the inter-frame delay automatically calculated
+/// from the port parameters (see [`Parameters::inter_frame_delay()`])
+pub fn connect_rtu(path: &str, timeout: Duration) -> Result<AsyncClient> {
+    let frame_delay = path.parse::<Parameters>()?.inter_frame_delay();
+    Ok(AsyncClient(SerialAsync::create(
+        path,
+        timeout,
+        frame_delay,
+    )?))
+}
+
+/// Opens a serial port, registered in the current tokio runtime. The port device is resolved with
+/// [`Parameters::resolve_port_dev()`]
+pub fn open(params: &Parameters) -> Result<SerialStream> {
+    let speed = u32::try_from(params.baud_rate.speed()).map_err(Error::invalid_data)?;
+    let data_bits = match params.char_size {
+        serial::Bits5 => tokio_serial::DataBits::Five,
+        serial::Bits6 => tokio_serial::DataBits::Six,
+        serial::Bits7 => tokio_serial::DataBits::Seven,
+        serial::Bits8 => tokio_serial::DataBits::Eight,
+    };
+    let parity = match params.parity {
+        serial::ParityNone => tokio_serial::Parity::None,
+        serial::ParityOdd => tokio_serial::Parity::Odd,
+        serial::ParityEven => tokio_serial::Parity::Even,
+    };
+    let stop_bits = match params.stop_bits {
+        serial::Stop1 => tokio_serial::StopBits::One,
+        serial::Stop2 => tokio_serial::StopBits::Two,
+    };
+    tokio_serial::new(params.resolve_port_dev()?, speed)
+        .data_bits(data_bits)
+        .parity(parity)
+        .stop_bits(stop_bits)
+        .flow_control(tokio_serial::FlowControl::None)
+        .open_native_async()
+        .map_err(Error::io)
+}
+
+#[allow(clippy::module_name_repetitions)]
+pub struct SerialAsync {
+    port: Mutex<SPort>,
+    timeout: Duration,
+    frame_delay: Duration,
+    busy: Mutex<()>,
+    params: Parameters,
+    session_id: AtomicUsize,
+    allow_reconnect: AtomicBool,
+}
+
+#[derive(Default)]
+struct SPort {
+    stream: Option<SerialStream>,
+    last_frame: Option<Instant>,
+}
+
+impl AsyncCommunicator for SerialAsync {
+    fn lock(&self) -> BoxFuture<'_, MutexGuard<'_, ()>> {
+        Box::pin(self.busy.lock())
+    }
+    fn session_id(&self) -> usize {
+        self.session_id.load(Ordering::Acquire)
+    }
+    fn reconnect(&self) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            let mut port = self.port.lock().await;
+            port.stream.take();
+            port.last_frame.take();
+        })
+    }
+    fn write<'a>(&'a self, buf: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut port = self.get_port().await?;
+            if let Some(last_frame) = port.last_frame {
+                let el = Instant::now().saturating_duration_since(last_frame);
+                if el < self.frame_delay {
+                    tokio::time::sleep(self.frame_delay - el).await;
+                }
+            }
+            let result =
+                with_timeout(self.timeout, port.stream.as_mut().unwrap().write_all(buf)).await;
+            if result.is_ok() {
+                // the data may be still being transmitted by the UART, count the silent interval
+                // from the expected end of the transmission
+                port.last_frame
+                    .replace(Instant::now() + self.params.chars_time(buf.len()));
+            } else {
+                port.stream.take();
+                port.last_frame.take();
+            }
+            result
+        })
+    }
+    fn read_exact<'a>(&'a self, buf: &'a mut [u8]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut port = self.get_port().await?;
+            let result = with_timeout(self.timeout, port.stream.as_mut().unwrap().read_exact(buf))
+                .await
+                .map(|_| ());
+            if result.is_ok() {
+                // the silent interval must be kept after a response frame as well
+                port.last_frame.replace(Instant::now());
+            } else {
+                port.stream.take();
+                port.last_frame.take();
+            }
+            result
+        })
+    }
+    fn protocol(&self) -> Protocol {
+        Protocol::Serial
+    }
+    fn lock_session(&self) -> BoxFuture<'_, Result<usize>> {
+        Box::pin(async move {
+            let _lock = self.busy.lock().await;
+            let _s = self.get_port().await?;
+            self.allow_reconnect.store(false, Ordering::Release);
+            Ok(self.session_id())
+        })
+    }
+    fn unlock_session(&self) {
+        self.allow_reconnect.store(true, Ordering::Release);
+    }
+}
+
+impl SerialAsync {
+    /// The inter-frame delay used by the client
+    pub fn frame_delay(&self) -> Duration {
+        self.frame_delay
+    }
+    fn create(path: &str, timeout: Duration, frame_delay: Duration) -> Result<Arc<Self>> {
+        Ok(Self {
+            port: <_>::default(),
+            timeout,
+            frame_delay,
+            busy: <_>::default(),
+            params: path.parse()?,
+            session_id: <_>::default(),
+            allow_reconnect: AtomicBool::new(true),
+        }
+        .into())
+    }
+    async fn get_port(&self) -> Result<MutexGuard<'_, SPort>> {
+        let mut lock = self.port.lock().await;
+        if lock.stream.is_none() {
+            if !self.allow_reconnect.load(Ordering::Acquire) {
+                return Err(Error::io("not connected but reconnects not allowed"));
+            }
+            trace!(dev=%self.params.port_dev, "creating new serial connection");
+            lock.stream.replace(open(&self.params)?);
+            lock.last_frame.take();
+            self.session_id.fetch_add(1, Ordering::Release);
+            trace!(dev=%self.params.port_dev, session_id=self.session_id(), "serial connection started");
+        }
+        Ok(lock)
+    }
+}
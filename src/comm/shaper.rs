@@ -0,0 +1,276 @@
+use crate::{Error, Result};
+
+use super::{Client, Communicator, Protocol};
+use parking_lot_rt::{Condvar, Mutex, MutexGuard};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// max condvar wait, limits the reaction time on runtime config changes
+const MAX_WAIT_STEP: Duration = Duration::from_millis(100);
+
+/// Traffic priority of a shaped client. Data of higher priorities is always sent first, data of
+/// lower priorities waits until there are no higher-priority writers
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum TrafficPriority {
+    /// Bulk transfers (e.g. log uploads, recordings)
+    Bulk = 0,
+    /// Telemetry
+    Telemetry = 1,
+    /// Control traffic
+    Control = 2,
+}
+
+const PRIORITIES: usize = 3;
+
+/// Traffic shaper statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShaperStats {
+    /// Bytes sent without delays
+    pub passed_bytes: u64,
+    /// Bytes, delayed by the shaper
+    pub shaped_bytes: u64,
+    /// Bytes, dropped because the max delay was exceeded
+    pub dropped_bytes: u64,
+}
+
+/// Token-bucket traffic shaper for outgoing data of clients, which share the same uplink (e.g. a
+/// cellular modem). Should be attached to clients with [`Client::with_shaper()`], each client
+/// with its own priority. Can be cloned and reconfigured at runtime.
+///
+/// Writes are blocked until the bucket has got enough tokens. Writes larger than the burst size
+/// are allowed when the bucket is full, putting the bucket into debt. If a write of a priority
+/// with max delay set can not be performed in time, the data is dropped and
+/// [`Error::Timeout`] is returned, so the client must be reconnected as for any other write
+/// timeout.
+#[derive(Clone)]
+pub struct TrafficShaper {
+    inner: Arc<ShaperInner>,
+}
+
+struct ShaperInner {
+    name: String,
+    bucket: Mutex<Bucket>,
+    changed: Condvar,
+    passed_bytes: AtomicU64,
+    shaped_bytes: AtomicU64,
+    dropped_bytes: AtomicU64,
+}
+
+struct Bucket {
+    // bytes per second, zero = unlimited
+    rate: u64,
+    burst: u64,
+    tokens: f64,
+    refilled: Instant,
+    waiting: [usize; PRIORITIES],
+    max_delay: [Option<Duration>; PRIORITIES],
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        #[allow(clippy::cast_precision_loss)]
+        let (rate, burst) = (self.rate as f64, self.burst as f64);
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.refilled = now;
+    }
+    fn has_higher_waiting(&self, priority: TrafficPriority) -> bool {
+        self.waiting[priority as usize + 1..].iter().any(|w| *w > 0)
+    }
+    // time, required to get the tokens
+    fn deficit_time(&self, required: f64) -> Duration {
+        #[allow(clippy::cast_precision_loss)]
+        let rate = self.rate as f64;
+        if self.tokens >= required || rate == 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((required - self.tokens) / rate)
+        }
+    }
+}
+
+impl TrafficShaper {
+    /// Creates a new shaper with the rate (bytes per second, zero means unlimited) and the burst
+    /// size (bytes)
+    pub fn new(name: &str, rate: u64, burst: u64) -> Self {
+        Self {
+            inner: Arc::new(ShaperInner {
+                name: name.to_owned(),
+                bucket: Mutex::new(Bucket {
+                    rate,
+                    burst: burst.max(1),
+                    #[allow(clippy::cast_precision_loss)]
+                    tokens: burst.max(1) as f64,
+                    refilled: Instant::now(),
+                    waiting: [0; PRIORITIES],
+                    max_delay: [None; PRIORITIES],
+                }),
+                changed: Condvar::new(),
+                passed_bytes: <_>::default(),
+                shaped_bytes: <_>::default(),
+                dropped_bytes: <_>::default(),
+            }),
+        }
+    }
+    /// Changes the rate (bytes per second, zero means unlimited) and the burst size at runtime
+    pub fn set_rate(&self, rate: u64, burst: u64) {
+        let mut bucket = self.inner.bucket.lock();
+        bucket.refill(Instant::now());
+        bucket.rate = rate;
+        bucket.burst = burst.max(1);
+        #[allow(clippy::cast_precision_loss)]
+        let burst = bucket.burst as f64;
+        bucket.tokens = bucket.tokens.min(burst);
+        self.inner.changed.notify_all();
+    }
+    /// Sets the max delay for data of the priority, after which the data is dropped (the default
+    /// is `None`, the data is never dropped)
+    pub fn set_max_delay(&self, priority: TrafficPriority, max_delay: Option<Duration>) {
+        self.inner.bucket.lock().max_delay[priority as usize] = max_delay;
+        self.inner.changed.notify_all();
+    }
+    /// Shaper statistics
+    pub fn stats(&self) -> ShaperStats {
+        ShaperStats {
+            passed_bytes: self.inner.passed_bytes.load(Ordering::Relaxed),
+            shaped_bytes: self.inner.shaped_bytes.load(Ordering::Relaxed),
+            dropped_bytes: self.inner.dropped_bytes.load(Ordering::Relaxed),
+        }
+    }
+    /// Waits until the data of the given size and priority can be sent
+    pub fn acquire(&self, priority: TrafficPriority, len: usize) -> Result<()> {
+        let started = Instant::now();
+        let mut bucket = self.inner.bucket.lock();
+        bucket.waiting[priority as usize] += 1;
+        let mut shaped = false;
+        #[allow(clippy::cast_precision_loss)]
+        let len_f = len as f64;
+        let result = loop {
+            let now = Instant::now();
+            bucket.refill(now);
+            if bucket.rate == 0 {
+                break Ok(());
+            }
+            #[allow(clippy::cast_precision_loss)]
+            let required = len_f.min(bucket.burst as f64);
+            let higher_waiting = bucket.has_higher_waiting(priority);
+            if !higher_waiting && bucket.tokens >= required {
+                bucket.tokens -= len_f;
+                break Ok(());
+            }
+            let deadline = bucket.max_delay[priority as usize].map(|d| started + d);
+            if deadline.map_or(false, |d| now >= d) {
+                break Err(Error::Timeout);
+            }
+            shaped = true;
+            let mut wait = if higher_waiting {
+                MAX_WAIT_STEP
+            } else {
+                bucket.deficit_time(required).min(MAX_WAIT_STEP)
+            };
+            if let Some(d) = deadline {
+                wait = wait.min(d.saturating_duration_since(now));
+            }
+            self.inner.changed.wait_for(&mut bucket, wait);
+        };
+        bucket.waiting[priority as usize] -= 1;
+        drop(bucket);
+        // lower-priority writers may proceed
+        self.inner.changed.notify_all();
+        let len = len as u64;
+        match result {
+            Ok(()) if shaped => self.record(&self.inner.shaped_bytes, "shaped", len),
+            Ok(()) => self.record(&self.inner.passed_bytes, "passed", len),
+            Err(_) => self.record(&self.inner.dropped_bytes, "dropped", len),
+        }
+        result
+    }
+    #[allow(unused_variables)]
+    fn record(&self, counter: &AtomicU64, kind: &'static str, len: u64) {
+        counter.fetch_add(len, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!("roboplc_shaper_bytes", "shaper" => self.inner.name.clone(), "kind" => kind)
+            .increment(len);
+    }
+    /// Shaper name
+    pub fn name(&self) -> &str {
+        &self.inner.name
+    }
+}
+
+pub(super) fn wrap(client: Client, shaper: TrafficShaper, priority: TrafficPriority) -> Client {
+    Client(Arc::new(Shaped {
+        client,
+        shaper,
+        priority,
+    }))
+}
+
+struct Shaped {
+    client: Client,
+    shaper: TrafficShaper,
+    priority: TrafficPriority,
+}
+
+impl Communicator for Shaped {
+    fn lock(&self) -> MutexGuard<()> {
+        self.client.lock()
+    }
+    fn reconnect(&self) {
+        self.client.reconnect();
+    }
+    fn write(&self, buf: &[u8]) -> Result<()> {
+        self.shaper.acquire(self.priority, buf.len())?;
+        self.client.write(buf)
+    }
+    fn read_exact(&self, buf: &mut [u8]) -> Result<()> {
+        self.client.read_exact(buf)
+    }
+    fn protocol(&self) -> Protocol {
+        self.client.protocol()
+    }
+    fn session_id(&self) -> usize {
+        self.client.session_id()
+    }
+    fn local_ip_addr(&self) -> Result<Option<SocketAddr>> {
+        self.client.local_ip_addr()
+    }
+    fn lock_session(&self) -> Result<usize> {
+        self.client.0.lock_session()
+    }
+    fn unlock_session(&self) {
+        self.client.0.unlock_session();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{TrafficPriority, TrafficShaper};
+    use crate::Error;
+
+    #[test]
+    fn test_shaper() {
+        let shaper = TrafficShaper::new("uplink", 10_000, 100);
+        shaper.acquire(TrafficPriority::Control, 100).unwrap();
+        let started = Instant::now();
+        shaper.acquire(TrafficPriority::Control, 100).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(8));
+        shaper.set_max_delay(TrafficPriority::Bulk, Some(Duration::from_millis(10)));
+        // the bucket is put into debt for 100ms
+        shaper.acquire(TrafficPriority::Telemetry, 1_000).unwrap();
+        assert_eq!(
+            shaper.acquire(TrafficPriority::Bulk, 100),
+            Err(Error::Timeout)
+        );
+        let stats = shaper.stats();
+        assert_eq!(stats.passed_bytes, 100);
+        assert_eq!(stats.shaped_bytes, 1_100);
+        assert_eq!(stats.dropped_bytes, 100);
+        shaper.set_rate(0, 0);
+        shaper.acquire(TrafficPriority::Bulk, 1_000_000).unwrap();
+    }
+}
@@ -0,0 +1,209 @@
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+use crate::{Error, Result};
+
+/// Socket-level options, applied before the connection is established
+#[derive(Default, Clone, Debug)]
+pub(super) struct SocketSettings {
+    pub(super) source_addr: Option<IpAddr>,
+    pub(super) interface: Option<String>,
+    pub(super) tos: Option<u8>,
+    pub(super) priority: Option<u32>,
+}
+
+impl SocketSettings {
+    pub(super) fn is_empty(&self) -> bool {
+        self.source_addr.is_none()
+            && self.interface.is_none()
+            && self.tos.is_none()
+            && self.priority.is_none()
+    }
+    #[cfg(not(target_os = "linux"))]
+    pub(super) fn connect(&self, _addr: &SocketAddr, _timeout: Duration) -> Result<TcpStream> {
+        Err(Error::Unimplemented)
+    }
+    /// Creates a socket, applies the settings, binds it (if required) and connects
+    #[cfg(target_os = "linux")]
+    pub(super) fn connect(&self, addr: &SocketAddr, timeout: Duration) -> Result<TcpStream> {
+        use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+        let domain = if addr.is_ipv4() {
+            libc::AF_INET
+        } else {
+            libc::AF_INET6
+        };
+        // SAFETY: the returned fd is checked and owned
+        let fd = unsafe { libc::socket(domain, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let raw = fd.as_raw_fd();
+        if let Some(ref interface) = self.interface {
+            let name = interface.as_bytes();
+            setsockopt_raw(
+                raw,
+                libc::SOL_SOCKET,
+                libc::SO_BINDTODEVICE,
+                name.as_ptr().cast(),
+                name.len(),
+            )?;
+        }
+        if let Some(tos) = self.tos {
+            let tos = libc::c_int::from(tos);
+            if addr.is_ipv4() {
+                setsockopt_int(raw, libc::IPPROTO_IP, libc::IP_TOS, tos)?;
+            } else {
+                setsockopt_int(raw, libc::IPPROTO_IPV6, libc::IPV6_TCLASS, tos)?;
+            }
+        }
+        if let Some(priority) = self.priority {
+            let priority = libc::c_int::try_from(priority).map_err(Error::invalid_data)?;
+            setsockopt_int(raw, libc::SOL_SOCKET, libc::SO_PRIORITY, priority)?;
+        }
+        if let Some(source_addr) = self.source_addr {
+            if source_addr.is_ipv4() != addr.is_ipv4() {
+                return Err(Error::invalid_data(format!(
+                    "source address {} does not match the address family of {}",
+                    source_addr, addr
+                )));
+            }
+            let (sa, len) = sockaddr(&SocketAddr::new(source_addr, 0));
+            // SAFETY: sa is a valid sockaddr of the given length
+            if unsafe { libc::bind(raw, std::ptr::addr_of!(sa).cast(), len) } < 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+        let stream = TcpStream::from(fd);
+        let zero_to = Duration::from_secs(0);
+        if timeout > zero_to {
+            stream.set_nonblocking(true)?;
+        }
+        let (sa, len) = sockaddr(addr);
+        // SAFETY: sa is a valid sockaddr of the given length
+        if unsafe { libc::connect(raw, std::ptr::addr_of!(sa).cast(), len) } < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EINPROGRESS) {
+                return Err(err.into());
+            }
+            let mut pfd = libc::pollfd {
+                fd: raw,
+                events: libc::POLLOUT,
+                revents: 0,
+            };
+            let timeout_ms = libc::c_int::try_from(timeout.as_millis()).unwrap_or(libc::c_int::MAX);
+            // SAFETY: pfd is a valid pollfd
+            match unsafe { libc::poll(&mut pfd, 1, timeout_ms.max(1)) } {
+                0 => return Err(Error::Timeout),
+                v if v < 0 => return Err(std::io::Error::last_os_error().into()),
+                _ => {}
+            }
+            if let Some(err) = stream.take_error()? {
+                return Err(err.into());
+            }
+        }
+        stream.set_nonblocking(false)?;
+        Ok(stream)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn setsockopt_int(
+    fd: libc::c_int,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> Result<()> {
+    setsockopt_raw(
+        fd,
+        level,
+        name,
+        std::ptr::addr_of!(value).cast(),
+        std::mem::size_of::<libc::c_int>(),
+    )
+}
+
+#[cfg(target_os = "linux")]
+fn setsockopt_raw(
+    fd: libc::c_int,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: *const libc::c_void,
+    len: usize,
+) -> Result<()> {
+    let len = libc::socklen_t::try_from(len).map_err(Error::invalid_data)?;
+    // SAFETY: the value pointer is valid for the given length
+    if unsafe { libc::setsockopt(fd, level, name, value, len) } < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[allow(clippy::cast_possible_truncation)]
+fn sockaddr(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    // SAFETY: sockaddr_storage is a plain C struct, large enough for both address families
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(a) => {
+            let sa = libc::sockaddr_in {
+                sin_family: libc::AF_INET as libc::sa_family_t,
+                sin_port: a.port().to_be(),
+                sin_addr: libc::in_addr {
+                    s_addr: u32::from_ne_bytes(a.ip().octets()),
+                },
+                sin_zero: [0; 8],
+            };
+            unsafe { std::ptr::write(std::ptr::addr_of_mut!(storage).cast(), sa) };
+            std::mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(a) => {
+            let sa = libc::sockaddr_in6 {
+                sin6_family: libc::AF_INET6 as libc::sa_family_t,
+                sin6_port: a.port().to_be(),
+                sin6_flowinfo: a.flowinfo(),
+                sin6_addr: libc::in6_addr {
+                    s6_addr: a.ip().octets(),
+                },
+                sin6_scope_id: a.scope_id(),
+            };
+            unsafe { std::ptr::write(std::ptr::addr_of_mut!(storage).cast(), sa) };
+            std::mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use std::io::{Read, Write};
+    use std::net::{IpAddr, Ipv4Addr, TcpListener};
+    use std::time::Duration;
+
+    use super::SocketSettings;
+
+    #[test]
+    fn test_connect_with_settings() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let settings = SocketSettings {
+            source_addr: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            // EF (expedited forwarding)
+            tos: Some(46 << 2),
+            priority: Some(6),
+            ..SocketSettings::default()
+        };
+        let mut stream = settings.connect(&addr, Duration::from_secs(1)).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        stream.write_all(b"test").unwrap();
+        let mut buf = [0u8; 4];
+        peer.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"test");
+        let settings = SocketSettings {
+            source_addr: Some("::1".parse().unwrap()),
+            ..SocketSettings::default()
+        };
+        assert!(settings.connect(&addr, Duration::from_secs(1)).is_err());
+    }
+}
@@ -1,6 +1,8 @@
 use crate::pchannel;
 use crate::{Error, Result};
 
+use super::pool::PoolMember;
+use super::sockopt::SocketSettings;
 use super::{
     ChatFn, Client, CommReader, Communicator, ConnectionOptions, Protocol, Stream, Timeouts,
 };
@@ -42,11 +44,13 @@ pub struct Tcp {
     addr: SocketAddr,
     stream: Mutex<Option<TcpStream>>,
     timeouts: Timeouts,
+    socket: SocketSettings,
     busy: Mutex<()>,
     session_id: AtomicUsize,
     allow_reconnect: AtomicBool,
     reader_tx: Option<pchannel::Sender<CommReader>>,
     chat: Option<Box<ChatFn>>,
+    pool: Option<PoolMember>,
 }
 
 #[allow(clippy::module_name_repetitions)]
@@ -73,6 +77,9 @@ fn reconnect(&self) {
             .lock()
             .take()
             .map(|s| s.shutdown(net::Shutdown::Both));
+        if let Some(ref pool) = self.pool {
+            pool.disconnected();
+        }
     }
     fn write(&self, buf: &[u8]) -> Result<()> {
         let mut stream = self.get_stream()?;
@@ -118,6 +125,20 @@ impl Tcp {
     fn create<A: ToSocketAddrs + fmt::Debug>(
         addr: A,
         options: ConnectionOptions,
+    ) -> Result<(TcpClient, Option<pchannel::Receiver<CommReader>>)> {
+        Self::create_with_pool(addr, options, None)
+    }
+    pub(super) fn create_pooled<A: ToSocketAddrs + fmt::Debug>(
+        addr: A,
+        options: ConnectionOptions,
+        pool: PoolMember,
+    ) -> Result<(TcpClient, Option<pchannel::Receiver<CommReader>>)> {
+        Self::create_with_pool(addr, options, Some(pool))
+    }
+    fn create_with_pool<A: ToSocketAddrs + fmt::Debug>(
+        addr: A,
+        options: ConnectionOptions,
+        pool: Option<PoolMember>,
     ) -> Result<(TcpClient, Option<pchannel::Receiver<CommReader>>)> {
         let (tx, rx) = if options.with_reader {
             let (tx, rx) = pchannel::bounded(READER_CHANNEL_CAPACITY);
@@ -133,10 +154,12 @@ fn create<A: ToSocketAddrs + fmt::Debug>(
             stream: <_>::default(),
             busy: <_>::default(),
             timeouts: options.timeouts,
+            socket: options.socket,
             session_id: <_>::default(),
             allow_reconnect: AtomicBool::new(true),
             reader_tx: tx,
             chat: options.chat,
+            pool,
         };
         Ok((client.into(), rx))
     }
@@ -146,24 +169,23 @@ fn get_stream(&self) -> Result<MutexGuard<Option<TcpStream>>> {
             if !self.allow_reconnect.load(Ordering::Acquire) {
                 return Err(Error::io("not connected but reconnects not allowed"));
             }
-            trace!(addr=%self.addr, "creating new TCP stream");
-            let zero_to = Duration::from_secs(0);
-            let mut stream = if self.timeouts.connect > zero_to {
-                TcpStream::connect_timeout(&self.addr, self.timeouts.connect)?
+            let stream = if let Some(ref pool) = self.pool {
+                let permit = pool.acquire(self.timeouts.connect)?;
+                let result = self.open_stream();
+                drop(permit);
+                match result {
+                    Ok(stream) => {
+                        pool.connected();
+                        stream
+                    }
+                    Err(e) => {
+                        pool.failed();
+                        return Err(e);
+                    }
+                }
             } else {
-                TcpStream::connect(self.addr)?
+                self.open_stream()?
             };
-            if self.timeouts.read > zero_to {
-                stream.set_read_timeout(Some(self.timeouts.read))?;
-            }
-            if self.timeouts.write > zero_to {
-                stream.set_write_timeout(Some(self.timeouts.write))?;
-            }
-            stream.set_nodelay(true)?;
-            if let Some(ref chat) = self.chat {
-                trace!("chatting with the server");
-                chat(&mut stream).map_err(Error::io)?;
-            }
             self.session_id.fetch_add(1, Ordering::Release);
             trace!(addr=%self.addr, session_id=self.session_id(), "TCP session started");
             if let Some(ref tx) = self.reader_tx {
@@ -172,9 +194,51 @@ fn get_stream(&self) -> Result<MutexGuard<Option<TcpStream>>> {
                 })?;
             }
             lock.replace(stream);
+        } else if let Some(ref pool) = self.pool {
+            pool.touch();
         }
         Ok(lock)
     }
+    fn open_stream(&self) -> Result<TcpStream> {
+        trace!(addr=%self.addr, "creating new TCP stream");
+        let zero_to = Duration::from_secs(0);
+        let mut stream = if !self.socket.is_empty() {
+            self.socket.connect(&self.addr, self.timeouts.connect)?
+        } else if self.timeouts.connect > zero_to {
+            TcpStream::connect_timeout(&self.addr, self.timeouts.connect)?
+        } else {
+            TcpStream::connect(self.addr)?
+        };
+        if self.timeouts.read > zero_to {
+            stream.set_read_timeout(Some(self.timeouts.read))?;
+        }
+        if self.timeouts.write > zero_to {
+            stream.set_write_timeout(Some(self.timeouts.write))?;
+        }
+        stream.set_nodelay(true)?;
+        if let Some(ref chat) = self.chat {
+            trace!("chatting with the server");
+            chat(&mut stream).map_err(Error::io)?;
+        }
+        Ok(stream)
+    }
+    pub(super) fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+    /// Closes the connection if it is not used at the moment and the session is not locked.
+    /// Returns true if the connection has been closed
+    pub(super) fn close_if_idle(&self) -> bool {
+        if !self.allow_reconnect.load(Ordering::Acquire) {
+            return false;
+        }
+        let Some(mut stream) = self.stream.try_lock() else {
+            return false;
+        };
+        stream
+            .take()
+            .map(|s| s.shutdown(net::Shutdown::Both))
+            .is_some()
+    }
 }
 
 impl Drop for Tcp {
@@ -183,5 +247,8 @@ fn drop(&mut self) {
             .lock()
             .take()
             .map(|s| s.shutdown(net::Shutdown::Both));
+        if let Some(ref pool) = self.pool {
+            pool.unregister();
+        }
     }
 }
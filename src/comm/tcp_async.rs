@@ -0,0 +1,164 @@
+use crate::{Error, Result};
+
+use super::client_async::{with_timeout, AsyncClient, AsyncCommunicator, BoxFuture};
+use super::{Protocol, Timeouts};
+use core::fmt;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::net::TcpStream;
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::trace;
+
+/// Create a new asynchronous TCP client. The client will attempt to connect to the given address
+/// at the time of the first request. The client will automatically reconnect if the connection is
+/// lost.
+pub fn connect<A: ToSocketAddrs + fmt::Debug>(addr: A, timeout: Duration) -> Result<AsyncClient> {
+    connect_with_timeouts(addr, Timeouts::new(timeout))
+}
+
+/// Create a new asynchronous TCP client with custom timeouts (zero means no timeout)
+pub fn connect_with_timeouts<A: ToSocketAddrs + fmt::Debug>(
+    addr: A,
+    timeouts: Timeouts,
+) -> Result<AsyncClient> {
+    Ok(AsyncClient(Arc::new(TcpAsync {
+        addr: addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| Error::invalid_data(format!("Invalid address: {:?}", addr)))?,
+        stream: <_>::default(),
+        timeouts,
+        busy: <_>::default(),
+        session_id: <_>::default(),
+        allow_reconnect: AtomicBool::new(true),
+    })))
+}
+
+#[allow(clippy::module_name_repetitions)]
+pub struct TcpAsync {
+    addr: SocketAddr,
+    stream: Mutex<Option<TcpStream>>,
+    timeouts: Timeouts,
+    busy: Mutex<()>,
+    session_id: AtomicUsize,
+    allow_reconnect: AtomicBool,
+}
+
+impl AsyncCommunicator for TcpAsync {
+    fn lock(&self) -> BoxFuture<'_, MutexGuard<'_, ()>> {
+        Box::pin(self.busy.lock())
+    }
+    fn session_id(&self) -> usize {
+        self.session_id.load(Ordering::Acquire)
+    }
+    fn reconnect(&self) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            self.stream.lock().await.take();
+        })
+    }
+    fn write<'a>(&'a self, buf: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut stream = self.get_stream().await?;
+            let result =
+                with_timeout(self.timeouts.write, stream.as_mut().unwrap().write_all(buf)).await;
+            if result.is_err() {
+                stream.take();
+            }
+            result
+        })
+    }
+    fn read_exact<'a>(&'a self, buf: &'a mut [u8]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut stream = self.get_stream().await?;
+            let result = with_timeout(self.timeouts.read, stream.as_mut().unwrap().read_exact(buf))
+                .await
+                .map(|_| ());
+            // the stream state is unknown if the read has been cancelled by the timeout
+            if result == Err(Error::Timeout) {
+                stream.take();
+            }
+            result
+        })
+    }
+    fn local_ip_addr(&self) -> BoxFuture<'_, Result<Option<SocketAddr>>> {
+        Box::pin(async move {
+            let stream = self.get_stream().await?;
+            Ok(Some(stream.as_ref().unwrap().local_addr()?))
+        })
+    }
+    fn protocol(&self) -> Protocol {
+        Protocol::Tcp
+    }
+    fn lock_session(&self) -> BoxFuture<'_, Result<usize>> {
+        Box::pin(async move {
+            let _lock = self.busy.lock().await;
+            let _s = self.get_stream().await?;
+            self.allow_reconnect.store(false, Ordering::Release);
+            Ok(self.session_id())
+        })
+    }
+    fn unlock_session(&self) {
+        self.allow_reconnect.store(true, Ordering::Release);
+    }
+}
+
+impl TcpAsync {
+    async fn get_stream(&self) -> Result<MutexGuard<'_, Option<TcpStream>>> {
+        let mut lock = self.stream.lock().await;
+        if lock.is_none() {
+            if !self.allow_reconnect.load(Ordering::Acquire) {
+                return Err(Error::io("not connected but reconnects not allowed"));
+            }
+            trace!(addr=%self.addr, "creating new TCP stream");
+            let stream = with_timeout(self.timeouts.connect, TcpStream::connect(self.addr)).await?;
+            stream.set_nodelay(true)?;
+            self.session_id.fetch_add(1, Ordering::Release);
+            trace!(addr=%self.addr, session_id=self.session_id(), "TCP session started");
+            lock.replace(stream);
+        }
+        Ok(lock)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            // echo a single frame per connection, then close it
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 4];
+                if stream.read_exact(&mut buf).await.is_ok() {
+                    stream.write_all(&buf).await.unwrap();
+                }
+            }
+        });
+        let client = super::connect(addr, Duration::from_secs(1)).unwrap();
+        let mut buf = [0u8; 4];
+        for session in 1..=2 {
+            let _lock = client.lock().await;
+            client.write(b"ping").await.unwrap();
+            client.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"ping");
+            assert_eq!(client.session_id(), session);
+            client.reconnect().await;
+        }
+        let guard = client.lock_session().await.unwrap();
+        assert_eq!(guard.session_id(), 3);
+        client.reconnect().await;
+        assert!(client.write(b"ping").await.is_err());
+        drop(guard);
+        client.write(b"ping").await.unwrap();
+        assert_eq!(client.session_id(), 4);
+    }
+}
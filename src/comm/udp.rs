@@ -0,0 +1,251 @@
+use crate::{Error, Result};
+
+use super::{Client, Communicator, ConnectionOptions, Protocol, Timeouts};
+use core::fmt;
+use parking_lot_rt::{Mutex, MutexGuard};
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::trace;
+
+/// The maximum UDP payload size
+pub(crate) const MAX_DATAGRAM_SIZE: usize = 65_507;
+
+/// Create a new UDP client. The socket is bound at the time of the first request, datagrams are
+/// accepted from the given address only.
+///
+/// Each write is sent as a single datagram, reads consume the last received one. If no datagram
+/// is received within the read timeout, the last written one is retransmitted (see
+/// [`ConnectionOptions::retransmits()`]). After errors, a new socket (with a new local port) is
+/// bound, so late responses to failed requests are dropped.
+pub fn connect<A: ToSocketAddrs + fmt::Debug>(addr: A, timeout: Duration) -> Result<Client> {
+    connect_with_options(addr, ConnectionOptions::new(timeout))
+}
+
+/// Create a new UDP client with options. Only timeouts and retransmits are used, other options
+/// are ignored
+pub fn connect_with_options<A: ToSocketAddrs + fmt::Debug>(
+    addr: A,
+    options: ConnectionOptions,
+) -> Result<Client> {
+    Ok(Client(Udp::create(addr, options)?))
+}
+
+#[allow(clippy::module_name_repetitions)]
+pub struct Udp {
+    addr: SocketAddr,
+    state: Mutex<State>,
+    timeouts: Timeouts,
+    retransmits: usize,
+    busy: Mutex<()>,
+    session_id: AtomicUsize,
+    allow_reconnect: AtomicBool,
+}
+
+#[allow(clippy::module_name_repetitions)]
+pub type UdpClient = Arc<Udp>;
+
+#[derive(Default)]
+struct State {
+    socket: Option<UdpSocket>,
+    // the last written datagram, kept for retransmits
+    request: Vec<u8>,
+    // the last received datagram and the read position
+    datagram: Vec<u8>,
+    pos: usize,
+}
+
+impl State {
+    fn reset(&mut self) {
+        self.socket.take();
+        self.datagram.clear();
+        self.pos = 0;
+    }
+}
+
+impl Communicator for Udp {
+    fn lock(&self) -> MutexGuard<()> {
+        self.busy.lock()
+    }
+    fn session_id(&self) -> usize {
+        self.session_id.load(Ordering::Acquire)
+    }
+    fn reconnect(&self) {
+        self.state.lock().reset();
+    }
+    fn write(&self, buf: &[u8]) -> Result<()> {
+        let mut state = self.get_state()?;
+        state.datagram.clear();
+        state.pos = 0;
+        if let Err(e) = state.socket.as_ref().unwrap().send(buf) {
+            state.reset();
+            return Err(e.into());
+        }
+        state.request.clear();
+        state.request.extend(buf);
+        Ok(())
+    }
+    fn read_exact(&self, buf: &mut [u8]) -> Result<()> {
+        let mut state = self.get_state()?;
+        if state.pos == state.datagram.len() {
+            if let Err(e) = self.receive(&mut state) {
+                state.reset();
+                return Err(e);
+            }
+        }
+        let (pos, end) = (state.pos, state.pos + buf.len());
+        if end > state.datagram.len() {
+            // the rest of the datagram is discarded
+            state.pos = state.datagram.len();
+            return Err(Error::io("UDP datagram is too short"));
+        }
+        buf.copy_from_slice(&state.datagram[pos..end]);
+        state.pos = end;
+        Ok(())
+    }
+    fn local_ip_addr(&self) -> Result<Option<SocketAddr>> {
+        let state = self.get_state()?;
+        state
+            .socket
+            .as_ref()
+            .unwrap()
+            .local_addr()
+            .map(Some)
+            .map_err(Into::into)
+    }
+    fn protocol(&self) -> Protocol {
+        Protocol::Udp
+    }
+    fn lock_session(&self) -> Result<usize> {
+        let _lock = self.lock();
+        let _s = self.get_state()?;
+        self.allow_reconnect.store(false, Ordering::Release);
+        Ok(self.session_id())
+    }
+    fn unlock_session(&self) {
+        self.allow_reconnect.store(true, Ordering::Release);
+    }
+}
+
+impl Udp {
+    fn create<A: ToSocketAddrs + fmt::Debug>(
+        addr: A,
+        options: ConnectionOptions,
+    ) -> Result<UdpClient> {
+        let client = Self {
+            addr: addr
+                .to_socket_addrs()?
+                .next()
+                .ok_or_else(|| Error::invalid_data(format!("Invalid address: {:?}", addr)))?,
+            state: <_>::default(),
+            timeouts: options.timeouts,
+            retransmits: options.retransmits,
+            busy: <_>::default(),
+            session_id: <_>::default(),
+            allow_reconnect: AtomicBool::new(true),
+        };
+        Ok(client.into())
+    }
+    // returns the state with the socket bound
+    fn get_state(&self) -> Result<MutexGuard<State>> {
+        let mut state = self.state.lock();
+        if state.socket.is_none() {
+            if !self.allow_reconnect.load(Ordering::Acquire) {
+                return Err(Error::io("not connected but reconnects not allowed"));
+            }
+            let socket = self.open_socket()?;
+            self.session_id.fetch_add(1, Ordering::Release);
+            trace!(addr=%self.addr, session_id=self.session_id(), "UDP session started");
+            state.socket.replace(socket);
+        }
+        Ok(state)
+    }
+    fn open_socket(&self) -> Result<UdpSocket> {
+        trace!(addr=%self.addr, "creating new UDP socket");
+        let socket = if self.addr.is_ipv4() {
+            UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?
+        } else {
+            UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?
+        };
+        socket.connect(self.addr)?;
+        let zero_to = Duration::from_secs(0);
+        if self.timeouts.read > zero_to {
+            socket.set_read_timeout(Some(self.timeouts.read))?;
+        }
+        if self.timeouts.write > zero_to {
+            socket.set_write_timeout(Some(self.timeouts.write))?;
+        }
+        Ok(socket)
+    }
+    // receives the next non-empty datagram, retransmits the last request on timeouts
+    fn receive(&self, state: &mut State) -> Result<()> {
+        let State {
+            ref socket,
+            ref request,
+            ref mut datagram,
+            ref mut pos,
+        } = *state;
+        let socket = socket.as_ref().unwrap();
+        let mut retransmits = 0;
+        loop {
+            datagram.resize(MAX_DATAGRAM_SIZE, 0);
+            match socket.recv(datagram) {
+                Ok(len) => {
+                    datagram.truncate(len);
+                    *pos = 0;
+                    if len > 0 {
+                        return Ok(());
+                    }
+                }
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) && retransmits < self.retransmits
+                        && !request.is_empty() =>
+                {
+                    retransmits += 1;
+                    trace!(addr=%self.addr, retransmits, "retransmitting UDP datagram");
+                    socket.send(request)?;
+                }
+                Err(e) => {
+                    datagram.clear();
+                    *pos = 0;
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::connect_with_options;
+    use crate::comm::ConnectionOptions;
+    use std::net::UdpSocket;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_retransmit() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut buf = [0u8; 16];
+            // the first request is lost
+            server.recv_from(&mut buf).unwrap();
+            let (len, peer) = server.recv_from(&mut buf).unwrap();
+            server.send_to(&buf[..len], peer).unwrap();
+        });
+        let options = ConnectionOptions::new(Duration::from_millis(200)).retransmits(1);
+        let client = connect_with_options(addr, options).unwrap();
+        client.write(&[1, 2, 3]).unwrap();
+        let mut buf = [0u8; 2];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2]);
+        assert!(client.read_exact(&mut buf).is_err());
+        server.join().unwrap();
+    }
+}
@@ -1,31 +1,51 @@
 use std::{
+    any::{type_name, Any, TypeId},
+    collections::{btree_map, BTreeMap},
+    fmt,
+    future::Future,
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    str::FromStr,
     sync::{
-        atomic::{AtomicI8, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicI8, AtomicU32, AtomicU64, AtomicU8, Ordering},
+        Arc, Weak,
     },
+    task::{Poll, Waker},
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use crate::{
     critical,
+    environment::EnvironmentReport,
+    flags::Flags,
     hub::Hub,
-    suicide,
+    pchannel::{self, Receiver, Sender},
+    placement::{PlacementReport, TaskPlacement},
+    profiling::{self, ProfileSpan},
+    safety::safe_state::{SafeState, SafeStateReport},
+    shutdown, suicide_with_flush,
     supervisor::Supervisor,
     thread_rt::{Builder, RTParams, Scheduling},
     Error, Result,
 };
-use parking_lot_rt::RwLock;
+use parking_lot_rt::{Condvar, Mutex, RwLock};
 pub use roboplc_derive::WorkerOpts;
 use rtsc::data_policy::DataDeliveryPolicy;
+use serde::{Deserialize, Serialize};
 use signal_hook::{
     consts::{SIGINT, SIGTERM},
     iterator::Signals,
 };
-use tracing::error;
+use tracing::{error, info, warn};
 
 pub mod prelude {
-    pub use super::{Context, Controller, WResult, Worker, WorkerOptions};
+    pub use super::{
+        Context, Controller, ControllerCommand, Importance, OperationMode, RestartPolicy,
+        StartMode, WResult, Worker, WorkerCatalog, WorkerHandle, WorkerOptions, WorkerTaskStatus,
+    };
     pub use roboplc_derive::WorkerOpts;
 }
 
@@ -34,21 +54,119 @@ pub mod prelude {
 
 pub const SLEEP_STEP: Duration = Duration::from_millis(100);
 
+/// The delay before a worker is restarted according to its [`RestartPolicy`]
+pub const WORKER_RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// The name of the hub client and the task of [`Controller::spawn_command_processor()`]
+pub const COMMAND_PROCESSOR_NAME: &str = "ctl.commands";
+
+type ServiceMap = BTreeMap<TypeId, Arc<dyn Any + Send + Sync>>;
+
 /// Controller state beacon. Can be cloned and shared with no limitations.
 #[derive(Clone)]
 pub struct State {
     state: Arc<AtomicI8>,
+    mode: Arc<ModeBeacon>,
+}
+
+#[derive(Default)]
+struct ModeBeacon {
+    mode: AtomicU8,
+    // the highest importance class being shed, 0 = no load shedding
+    shed: AtomicU8,
+    lock: Mutex<()>,
+    changed: Condvar,
+    // controller state change notifications for non-condvar waiters
+    eventfds: Mutex<Vec<Weak<OwnedFd>>>,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl ModeBeacon {
+    fn notify_state_waiters(&self) {
+        self.eventfds.lock().retain(|fd| {
+            if let Some(fd) = fd.upgrade() {
+                signal_eventfd(fd.as_raw_fd());
+                true
+            } else {
+                false
+            }
+        });
+        for waker in self.wakers.lock().drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+fn signal_eventfd(fd: RawFd) {
+    let value: u64 = 1;
+    // SAFETY: the fd is owned by a live eventfd object, the buffer is 8 bytes
+    unsafe {
+        libc::write(fd, std::ptr::addr_of!(value).cast(), 8);
+    }
 }
 
 impl State {
     pub fn new() -> Self {
         Self {
             state: AtomicI8::new(ControllerStateKind::Starting as i8).into(),
+            mode: <_>::default(),
         }
     }
-    /// Set controller state
+    /// Set controller state and notify all waiters
     pub fn set(&self, state: ControllerStateKind) {
-        self.state.store(state as i8, Ordering::SeqCst);
+        let lock = self.mode.lock.lock();
+        let prev = self.state.swap(state as i8, Ordering::SeqCst);
+        if prev != state as i8 {
+            self.mode.changed.notify_all();
+            drop(lock);
+            self.mode.notify_state_waiters();
+        }
+    }
+    /// Blocks until the controller gets into the given state or the timeout is reached. Returns
+    /// true if the state has been reached
+    pub fn wait_for(&self, state: ControllerStateKind, timeout: Duration) -> bool {
+        self.wait_until(|s| s == state, timeout)
+    }
+    /// Blocks until the controller goes offline (stopping or stopped) or the timeout is reached.
+    /// Returns true if the controller is offline
+    pub fn wait_offline(&self, timeout: Duration) -> bool {
+        self.wait_until(|s| s < ControllerStateKind::Starting, timeout)
+    }
+    fn wait_until<F: Fn(ControllerStateKind) -> bool>(&self, f: F, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut lock = self.mode.lock.lock();
+        loop {
+            if f(self.get()) {
+                return true;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            self.mode.changed.wait_for(&mut lock, deadline - now);
+        }
+    }
+    /// Creates an eventfd, which is signaled on each controller state change. Can be added to
+    /// epoll/poll sets of blocking workers (e.g. socket listeners), so they wake up promptly on
+    /// termination. If the controller is already offline, the eventfd is signaled immediately
+    pub fn eventfd(&self) -> Result<StateEventFd> {
+        // SAFETY: the returned fd is checked and owned
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let fd = Arc::new(unsafe { OwnedFd::from_raw_fd(fd) });
+        self.mode.eventfds.lock().push(Arc::downgrade(&fd));
+        if !self.is_online() {
+            signal_eventfd(fd.as_raw_fd());
+        }
+        Ok(StateEventFd { fd })
+    }
+    /// Returns a future, which is resolved when the controller goes offline (for async tasks)
+    pub fn offline(&self) -> StateOffline {
+        StateOffline {
+            state: self.clone(),
+        }
     }
     /// Get controller state
     pub fn get(&self) -> ControllerStateKind {
@@ -58,6 +176,56 @@ pub fn get(&self) -> ControllerStateKind {
     pub fn is_online(&self) -> bool {
         self.get() >= ControllerStateKind::Starting
     }
+    /// Get controller operation mode
+    pub fn operation_mode(&self) -> OperationMode {
+        OperationMode::from(self.mode.mode.load(Ordering::SeqCst))
+    }
+    /// Set controller operation mode and notify all waiters
+    pub fn set_operation_mode(&self, mode: OperationMode) {
+        let _lock = self.mode.lock.lock();
+        let prev = self.mode.mode.swap(mode as u8, Ordering::SeqCst);
+        if prev != mode as u8 {
+            info!(from=%OperationMode::from(prev), to=%mode, "operation mode changed");
+            self.mode.changed.notify_all();
+        }
+    }
+    /// Blocks until the operation mode is changed from the given one or the timeout is reached.
+    /// Returns the current operation mode
+    pub fn wait_operation_mode_change(
+        &self,
+        current: OperationMode,
+        timeout: Duration,
+    ) -> OperationMode {
+        let mut lock = self.mode.lock.lock();
+        let mode = self.operation_mode();
+        if mode != current {
+            return mode;
+        }
+        self.mode.changed.wait_for(&mut lock, timeout);
+        self.operation_mode()
+    }
+    /// Get the current load shedding level: workers of this importance class and below must
+    /// reduce their load. `None` means there is no load shedding
+    pub fn load_shedding(&self) -> Option<Importance> {
+        Importance::from_level(self.mode.shed.load(Ordering::SeqCst))
+    }
+    /// Set the load shedding level and notify all waiters. Critical workers are never shed, so
+    /// the level is limited to [`Importance::High`]
+    pub fn set_load_shedding(&self, level: Option<Importance>) {
+        let level = level.map(|l| l.min(Importance::High));
+        let _lock = self.mode.lock.lock();
+        let prev = self
+            .mode
+            .shed
+            .swap(level.map_or(0, |l| l as u8), Ordering::SeqCst);
+        if prev != level.map_or(0, |l| l as u8) {
+            match level {
+                Some(l) => warn!(level=%l, "load shedding"),
+                None => info!("load shedding stopped"),
+            }
+            self.mode.changed.notify_all();
+        }
+    }
 }
 
 impl Default for State {
@@ -66,6 +234,55 @@ fn default() -> Self {
     }
 }
 
+/// Controller state change eventfd (see [`State::eventfd()`]). The eventfd is non-blocking
+pub struct StateEventFd {
+    fd: Arc<OwnedFd>,
+}
+
+impl StateEventFd {
+    /// Resets the eventfd counter after a notification has been processed
+    pub fn reset(&self) {
+        let mut value: u64 = 0;
+        // SAFETY: the fd is owned, the buffer is 8 bytes
+        unsafe {
+            libc::read(self.fd.as_raw_fd(), std::ptr::addr_of_mut!(value).cast(), 8);
+        }
+    }
+}
+
+impl AsRawFd for StateEventFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+/// A future, which is resolved when the controller goes offline (see [`State::offline()`])
+pub struct StateOffline {
+    state: State,
+}
+
+impl Future for StateOffline {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<()> {
+        if !self.state.is_online() {
+            return Poll::Ready(());
+        }
+        {
+            let mut wakers = self.state.mode.wakers.lock();
+            if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+        }
+        // the state may be changed before the waker has been registered
+        if self.state.is_online() {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }
+}
+
 /// Controller state kind
 #[derive(Default, Eq, PartialEq, Clone, Copy, Ord, PartialOrd)]
 #[repr(i8)]
@@ -86,12 +303,283 @@ fn from(v: i8) -> Self {
             0 => ControllerStateKind::Starting,
             1 => ControllerStateKind::Active,
             2 => ControllerStateKind::Running,
+            -1 => ControllerStateKind::Stopping,
             -100 => ControllerStateKind::Stopped,
             _ => ControllerStateKind::Unknown,
         }
     }
 }
 
+/// Controller operation mode. The mode is shared between all workers, which may change their
+/// behavior accordingly (see [`WorkerOptions::worker_paused_in()`]). The mode can be switched by
+/// workers, by remote commands (see [`ControllerCommand::SetOperationMode`]) and via EAPI RPC
+/// (`mode.get`/`mode.set` methods, requires `eapi` crate feature)
+#[derive(Default, Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum OperationMode {
+    /// Normal operation
+    #[default]
+    Normal = 0,
+    /// Degraded operation, non-critical workers should shed their load
+    Degraded = 1,
+    /// Maintenance, the process is being serviced
+    Maintenance = 2,
+}
+
+impl From<u8> for OperationMode {
+    fn from(v: u8) -> Self {
+        match v {
+            1 => OperationMode::Degraded,
+            2 => OperationMode::Maintenance,
+            _ => OperationMode::Normal,
+        }
+    }
+}
+
+impl fmt::Display for OperationMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OperationMode::Normal => write!(f, "normal"),
+            OperationMode::Degraded => write!(f, "degraded"),
+            OperationMode::Maintenance => write!(f, "maintenance"),
+        }
+    }
+}
+
+impl FromStr for OperationMode {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "normal" => Ok(OperationMode::Normal),
+            "degraded" => Ok(OperationMode::Degraded),
+            "maintenance" => Ok(OperationMode::Maintenance),
+            v => Err(Error::invalid_data(format!(
+                "invalid operation mode: {}",
+                v
+            ))),
+        }
+    }
+}
+
+/// Worker importance class for load shedding (see [`WorkerOptions::worker_importance()`]). Under
+/// sustained overload, less important workers are instructed to reduce their rates or pause first,
+/// critical workers are never shed
+#[derive(Default, Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum Importance {
+    /// Optional workers (e.g. diagnostics, statistics)
+    Low = 1,
+    /// Regular workers
+    #[default]
+    Normal = 2,
+    /// Important workers, shed on heavy overload only
+    High = 3,
+    /// Workers, required for the process safety, never shed
+    Critical = 4,
+}
+
+impl Importance {
+    fn from_level(level: u8) -> Option<Self> {
+        match level {
+            1 => Some(Importance::Low),
+            2 => Some(Importance::Normal),
+            3 => Some(Importance::High),
+            4 => Some(Importance::Critical),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Importance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Importance::Low => write!(f, "low"),
+            Importance::Normal => write!(f, "normal"),
+            Importance::High => write!(f, "high"),
+            Importance::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+impl FromStr for Importance {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "low" => Ok(Importance::Low),
+            "normal" => Ok(Importance::Normal),
+            "high" => Ok(Importance::High),
+            "critical" => Ok(Importance::Critical),
+            v => Err(Error::invalid_data(format!("invalid importance: {}", v))),
+        }
+    }
+}
+
+/// Worker restart policy (see [`WorkerOptions::worker_restart_policy()`]). Workers are restarted
+/// in the same thread with the same instance, so a restartable worker should reset its state at
+/// the beginning of [`Worker::run()`]
+#[derive(Default, Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartPolicy {
+    /// The worker is never restarted, a worker error terminates the process
+    #[default]
+    Never,
+    /// The worker is restarted if it returns an error or panics (panics are not caught if the
+    /// process panic handler is set with [`crate::setup_panic()`])
+    OnFailure,
+    /// The worker is restarted whenever it exits, unless the controller goes offline or the
+    /// worker is stopped with [`WorkerCatalog::stop()`]
+    Always,
+}
+
+impl RestartPolicy {
+    fn restart_required(self, failed: bool) -> bool {
+        match self {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => failed,
+            RestartPolicy::Always => true,
+        }
+    }
+}
+
+impl fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RestartPolicy::Never => write!(f, "never"),
+            RestartPolicy::OnFailure => write!(f, "on_failure"),
+            RestartPolicy::Always => write!(f, "always"),
+        }
+    }
+}
+
+impl FromStr for RestartPolicy {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "never" => Ok(RestartPolicy::Never),
+            "on_failure" => Ok(RestartPolicy::OnFailure),
+            "always" => Ok(RestartPolicy::Always),
+            v => Err(Error::invalid_data(format!(
+                "invalid restart policy: {}",
+                v
+            ))),
+        }
+    }
+}
+
+/// Controller commands, which can be received from remote (e.g. a hub message, produced by a
+/// bridge or a management interface worker) and executed with [`WorkerCatalog::execute()`] or by
+/// the processor, spawned with [`Controller::spawn_command_processor()`]
+///
+/// The text form is `<command> <argument>`, e.g. `start diag`, `stop diag`, `mode degraded`
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControllerCommand {
+    /// Starts a dormant worker
+    StartWorker(String),
+    /// Requests a dormant worker to stop
+    StopWorker(String),
+    /// Switches the controller operation mode
+    SetOperationMode(OperationMode),
+}
+
+impl fmt::Display for ControllerCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ControllerCommand::StartWorker(name) => write!(f, "start {}", name),
+            ControllerCommand::StopWorker(name) => write!(f, "stop {}", name),
+            ControllerCommand::SetOperationMode(mode) => write!(f, "mode {}", mode),
+        }
+    }
+}
+
+impl FromStr for ControllerCommand {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let mut sp = s.split_whitespace();
+        let (Some(command), Some(arg), None) = (sp.next(), sp.next(), sp.next()) else {
+            return Err(Error::invalid_data(format!(
+                "invalid controller command: {}",
+                s
+            )));
+        };
+        match command.to_lowercase().as_str() {
+            "start" => Ok(ControllerCommand::StartWorker(arg.to_owned())),
+            "stop" => Ok(ControllerCommand::StopWorker(arg.to_owned())),
+            "mode" => Ok(ControllerCommand::SetOperationMode(arg.parse()?)),
+            v => Err(Error::invalid_data(format!(
+                "invalid controller command: {}",
+                v
+            ))),
+        }
+    }
+}
+
+/// Environment variable, which selects the controller start mode (`cold`, `warm` or `hot`)
+pub const ENV_START_MODE: &str = "ROBOPLC_START_MODE";
+
+/// Controller start mode, selected by the operator/manager at the program start
+///
+/// * Cold start: all variables are set to their defaults, retained data is not restored
+/// * Warm start: retained variables are restored, the process is restarted from the beginning
+/// * Hot start: all variables are restored and workers resume from the state they were stopped
+///   in (e.g. a sequence continues from the last step)
+///
+/// Restoring the data is up to the program (see [`Controller::restore_variables()`]), workers can
+/// query the start mode with [`Context::start_mode()`]
+#[derive(Default, Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StartMode {
+    /// Clear the state
+    Cold,
+    /// Retain variables
+    #[default]
+    Warm,
+    /// Resume the process
+    Hot,
+}
+
+impl StartMode {
+    /// Loads the start mode from [`ENV_START_MODE`] environment variable, the default is warm
+    pub fn from_env() -> Result<Self> {
+        std::env::var(ENV_START_MODE).map_or(Ok(StartMode::default()), |v| v.parse())
+    }
+    /// Returns true if retained data must be restored (warm and hot starts)
+    pub fn is_retaining(self) -> bool {
+        self != StartMode::Cold
+    }
+}
+
+impl fmt::Display for StartMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StartMode::Cold => write!(f, "cold"),
+            StartMode::Warm => write!(f, "warm"),
+            StartMode::Hot => write!(f, "hot"),
+        }
+    }
+}
+
+impl FromStr for StartMode {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "cold" => Ok(StartMode::Cold),
+            "warm" => Ok(StartMode::Warm),
+            "hot" => Ok(StartMode::Hot),
+            v => Err(Error::invalid_data(format!("invalid start mode: {}", v))),
+        }
+    }
+}
+
+fn start_mode_from_env() -> StartMode {
+    StartMode::from_env().unwrap_or_else(|e| {
+        error!(%e, "{}, using the default start mode", ENV_START_MODE);
+        StartMode::default()
+    })
+}
+
 /// Controller, used to manage workers and their context
 ///
 /// Generic parameter `D` is the message type for the controller's [`Hub`] messages.
@@ -107,6 +595,14 @@ pub struct Controller<D, V>
     hub: Hub<D>,
     state: State,
     variables: Arc<RwLock<V>>,
+    services: Arc<ServiceMap>,
+    flags: Flags,
+    start_mode: StartMode,
+    safe_state: SafeState,
+    catalog: WorkerCatalog<D, V>,
+    handles: BTreeMap<String, WorkerHandle<D>>,
+    #[cfg(feature = "kv")]
+    kv: Option<crate::kv::KvStore>,
 }
 
 impl<D, V> Controller<D, V>
@@ -124,6 +620,14 @@ pub fn new() -> Self
             hub: <_>::default(),
             state: State::new(),
             variables: <_>::default(),
+            services: <_>::default(),
+            flags: Flags::from_env(),
+            start_mode: start_mode_from_env(),
+            safe_state: <_>::default(),
+            catalog: <_>::default(),
+            handles: <_>::default(),
+            #[cfg(feature = "kv")]
+            kv: None,
         }
     }
     /// Creates a new controller instance with a pre-defined variables object
@@ -133,38 +637,167 @@ pub fn new_with_variables(variables: V) -> Self {
             hub: <_>::default(),
             state: State::new(),
             variables: Arc::new(RwLock::new(variables)),
+            services: <_>::default(),
+            flags: Flags::from_env(),
+            start_mode: start_mode_from_env(),
+            safe_state: <_>::default(),
+            catalog: <_>::default(),
+            handles: <_>::default(),
+            #[cfg(feature = "kv")]
+            kv: None,
+        }
+    }
+    /// Sets the key-value store, which is available for workers via [`Context::kv()`]. Must be
+    /// set before workers are spawned
+    #[cfg(feature = "kv")]
+    pub fn set_kv_store(&mut self, store: crate::kv::KvStore) {
+        self.kv = Some(store);
+    }
+    /// Sets the feature flag set, which is available for workers via [`Context::flags()`]. Must be
+    /// set before workers are spawned. By default, flags are loaded from environment variables
+    /// (see [`Flags::from_env()`])
+    pub fn set_flags(&mut self, flags: Flags) {
+        self.flags = flags;
+    }
+    /// Controller feature flags
+    pub fn flags(&self) -> &Flags {
+        &self.flags
+    }
+    /// Sets the start mode, e.g. if selected with program command-line arguments. Must be set
+    /// before workers are spawned. By default, the start mode is loaded from the environment (see
+    /// [`StartMode::from_env()`])
+    pub fn set_start_mode(&mut self, mode: StartMode) {
+        self.start_mode = mode;
+    }
+    /// Controller start mode
+    pub fn start_mode(&self) -> StartMode {
+        self.start_mode
+    }
+    /// Safe-state action registry (see [`crate::safety::safe_state`])
+    pub fn safe_state(&self) -> &SafeState {
+        &self.safe_state
+    }
+    /// Executes registered safe-state actions within the time budget and records the outcome.
+    /// Can be called e.g. from the shutdown handler (see
+    /// [`Controller::register_signals_with_shutdown_handler()`])
+    pub fn enter_safe_state(&self, reason: &str) -> SafeStateReport {
+        self.safe_state.enter(reason)
+    }
+    /// A hook point to restore retained variables. The function is called with the shared
+    /// variables locked for writing, unless the start mode is cold. Should be called before
+    /// workers are spawned
+    pub fn restore_variables<F>(&self, restore: F) -> Result<()>
+    where
+        F: FnOnce(&mut V, StartMode) -> Result<()>,
+    {
+        if !self.start_mode.is_retaining() {
+            info!("cold start, retained variables are not restored");
+            return Ok(());
         }
+        info!(start_mode = %self.start_mode, "restoring retained variables");
+        restore(&mut *self.variables.write(), self.start_mode)
     }
-    /// Spawns a worker
+    /// Adds a shared service object (e.g. a mapping pool or a recorder), which is available for
+    /// workers via [`Context::service()`]. Only one service of each type can be added.
+    ///
+    /// Services must be added before workers are spawned and signals are registered.
+    pub fn add_service<T: Send + Sync + 'static>(&mut self, service: T) -> Result<()> {
+        let services = Arc::get_mut(&mut self.services)
+            .ok_or_else(|| Error::failed("services must be added before workers are spawned"))?;
+        match services.entry(TypeId::of::<T>()) {
+            btree_map::Entry::Occupied(_) => Err(Error::ServiceAlreadyRegistered(type_name::<T>())),
+            btree_map::Entry::Vacant(e) => {
+                e.insert(Arc::new(service));
+                Ok(())
+            }
+        }
+    }
+    /// Returns a shared service object (see [`Controller::add_service()`])
+    pub fn service<T: Send + Sync + 'static>(&self) -> Result<&T> {
+        get_service(&self.services)
+    }
+    /// Validates worker names before spawning: names must be unique (including already spawned
+    /// workers and tasks) and must be 15 characters or less. Allows to detect name collisions
+    /// before any worker is started.
+    pub fn validate_workers(&self, workers: &[&dyn WorkerOptions]) -> Result<()> {
+        let mut names = std::collections::BTreeSet::new();
+        for worker in workers {
+            let name = worker.worker_name();
+            if name.len() > 15 {
+                return Err(Error::invalid_data(format!(
+                    "worker name must be 15 characters or less: {}",
+                    name
+                )));
+            }
+            if !names.insert(name) || self.supervisor.get_task(name).is_some() {
+                return Err(Error::SupervisorDuplicateTask(name.to_owned()));
+            }
+        }
+        Ok(())
+    }
+    /// Spawns a worker. The returned handle can be used to address the worker later (also
+    /// available via [`Controller::worker_handle()`])
     pub fn spawn_worker<W: Worker<D, V> + WorkerOptions + 'static>(
         &mut self,
-        mut worker: W,
-    ) -> Result<()> {
-        let context = self.context();
-        let mut rt_params = RTParams::new().set_scheduling(worker.worker_scheduling());
-        if let Some(priority) = worker.worker_priority() {
-            rt_params = rt_params.set_priority(priority);
-        }
-        if let Some(cpu_ids) = worker.worker_cpu_ids() {
-            rt_params = rt_params.set_cpu_ids(cpu_ids);
-        }
-        let mut builder = Builder::new()
-            .name(worker.worker_name())
-            .rt_params(rt_params)
-            .blocking(worker.worker_is_blocking());
-        if let Some(stack_size) = worker.worker_stack_size() {
-            builder = builder.stack_size(stack_size);
-        }
-        self.supervisor.spawn(builder, move || {
-            if let Err(e) = worker.run(&context) {
-                error!(worker=worker.worker_name(), error=%e, "worker terminated");
-                critical(&format!(
-                    "Worker {} terminated: {}",
-                    worker.worker_name(),
-                    e
-                ));
-            }
+        worker: W,
+    ) -> Result<WorkerHandle<D>> {
+        let handle = spawn_worker_in(&mut self.supervisor, self.context(), <_>::default(), worker)?;
+        self.handles
+            .insert(handle.name().to_owned(), handle.clone());
+        Ok(handle)
+    }
+    /// Returns a handle of a worker, spawned with [`Controller::spawn_worker()`]
+    pub fn worker_handle(&self, name: &str) -> Option<&WorkerHandle<D>> {
+        self.handles.get(name)
+    }
+    /// Registers a dormant worker in the controller's [`WorkerCatalog`]. The worker is not
+    /// started until [`WorkerCatalog::start()`] is called, the factory is called each time the
+    /// worker is started. The name MUST match the name of workers, created by the factory.
+    pub fn add_dormant_worker<W, F>(&mut self, name: &str, factory: F) -> Result<()>
+    where
+        W: Worker<D, V> + WorkerOptions + 'static,
+        F: Fn() -> W + Send + Sync + 'static,
+    {
+        if self.supervisor.get_task(name).is_some() {
+            return Err(Error::SupervisorDuplicateTask(name.to_owned()));
+        }
+        self.catalog.add(name, factory)
+    }
+    /// Returns the controller's [`WorkerCatalog`], which can be used to start/stop dormant
+    /// workers at runtime (e.g. from a worker which processes remote commands)
+    pub fn worker_catalog(&self) -> WorkerCatalog<D, V> {
+        self.catalog.set_context(|| self.context());
+        self.catalog.clone()
+    }
+    /// Spawns a task, which receives controller commands from the hub and executes them (see
+    /// [`WorkerCatalog::execute()`]). The function extracts commands from hub messages, e.g. ones
+    /// received from a bridge or a management interface worker. The hub client and the task are
+    /// named [`COMMAND_PROCESSOR_NAME`]
+    pub fn spawn_command_processor<F>(&mut self, extract: F) -> Result<()>
+    where
+        F: Fn(&D) -> Option<ControllerCommand> + Send + Sync + 'static,
+    {
+        let extract = Arc::new(extract);
+        let catalog = self.worker_catalog();
+        let client = self.hub.register(COMMAND_PROCESSOR_NAME, {
+            let extract = extract.clone();
+            move |message| extract(message).is_some()
         })?;
+        self.supervisor.spawn(
+            Builder::new().name(COMMAND_PROCESSOR_NAME).blocking(true),
+            move || {
+                while let Ok(message) = client.recv() {
+                    let Some(command) = extract(&message) else {
+                        continue;
+                    };
+                    if let Err(e) = catalog.execute(&command) {
+                        error!(%command, error=%e, "controller command failed");
+                    } else {
+                        info!(%command, "controller command executed");
+                    }
+                }
+            },
+        )?;
         Ok(())
     }
     /// Spawns a task thread (non-real-time) with the default options
@@ -217,7 +850,11 @@ macro_rules! sig_handler {
                     if let Some(sig) = signals.forever().next() {
                         match sig {
                             SIGTERM | SIGINT => {
-                                suicide(shutdown_timeout, true);
+                                suicide_with_flush(
+                                    shutdown_timeout,
+                                    shutdown::DEFAULT_FLUSH_TIMEOUT,
+                                    true,
+                                );
                                 $handler(&context);
                                 context.terminate();
                             }
@@ -245,11 +882,57 @@ fn context(&self) -> Context<D, V> {
             hub: self.hub.clone(),
             state: self.state.clone(),
             variables: self.variables.clone(),
+            paused_in: Vec::new().into(),
+            importance: Importance::default(),
+            stop: <_>::default(),
+            commands: None,
+            status: <_>::default(),
+            services: self.services.clone(),
+            flags: self.flags.clone(),
+            start_mode: self.start_mode,
+            safe_state: self.safe_state.clone(),
+            #[cfg(target_os = "linux")]
+            script: None,
+            #[cfg(feature = "kv")]
+            kv: self.kv.clone(),
         }
     }
-    /// Blocks until all tasks/workers are finished
+    // a context with scripted hub traffic and a cycle limit (see crate::testing::WorkerHarness)
+    #[cfg(target_os = "linux")]
+    pub(crate) fn scripted_context(&self, script: Arc<crate::testing::Script<D>>) -> Context<D, V> {
+        let mut context = self.context();
+        context.script = Some(script);
+        context
+    }
+    /// Logs the startup banner (see [`crate::announce()`]), including placements of all spawned
+    /// tasks/workers. Should be called after all workers have been spawned
+    pub fn announce(&self) {
+        let tasks = self
+            .supervisor
+            .tasks()
+            .map(|task| TaskPlacement::new(task.name(), task.rt_params()))
+            .collect();
+        EnvironmentReport::collect().tasks(tasks).log();
+    }
+    /// Cross-checks CPU affinities and priorities of all spawned tasks/workers against each other
+    /// and against isolated CPUs. Conflicts are logged as warnings. Should be called after all
+    /// workers have been spawned
+    pub fn validate_placement(&self) -> Result<PlacementReport> {
+        let tasks = self
+            .supervisor
+            .tasks()
+            .map(|task| TaskPlacement::new(task.name(), task.rt_params()))
+            .collect();
+        let report = PlacementReport::detect(tasks)?;
+        report.log_conflicts();
+        Ok(report)
+    }
+    /// Blocks until all tasks/workers are finished, then calls flush hooks (see
+    /// [`crate::shutdown`])
     pub fn block(&mut self) {
         self.supervisor.join_all();
+        self.catalog.join_all();
+        let _ = shutdown::flush_all(shutdown::DEFAULT_FLUSH_TIMEOUT);
         self.state.set(ControllerStateKind::Stopped);
     }
     /// Blocks until the controller goes into stopping/stopped
@@ -271,6 +954,14 @@ pub fn terminate(&mut self) {
     pub fn state(&self) -> &State {
         &self.state
     }
+    /// Controller operation mode
+    pub fn operation_mode(&self) -> OperationMode {
+        self.state.operation_mode()
+    }
+    /// Sets controller operation mode, all workers are notified
+    pub fn set_operation_mode(&self, mode: OperationMode) {
+        self.state.set_operation_mode(mode);
+    }
     /// Controller [`Hub`] instance
     pub fn hub(&self) -> &Hub<D> {
         &self.hub
@@ -295,6 +986,282 @@ fn default() -> Self {
     }
 }
 
+fn spawn_worker_in<D, V, W>(
+    supervisor: &mut Supervisor<()>,
+    mut context: Context<D, V>,
+    status: Arc<WorkerStatus>,
+    mut worker: W,
+) -> Result<WorkerHandle<D>>
+where
+    D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+    W: Worker<D, V> + WorkerOptions + 'static,
+{
+    context.paused_in = worker.worker_paused_in().into();
+    context.importance = worker.worker_importance();
+    let (tx, rx) = worker
+        .worker_command_capacity()
+        .map(pchannel::bounded)
+        .unzip();
+    context.commands = rx.map(Arc::new);
+    context.status = status.clone();
+    let mut rt_params = RTParams::new().set_scheduling(worker.worker_scheduling());
+    if let Some(priority) = worker.worker_priority() {
+        rt_params = rt_params.set_priority(priority);
+    }
+    if let Some(cpu_ids) = worker.worker_cpu_ids() {
+        rt_params = rt_params.set_cpu_ids(cpu_ids);
+    }
+    let mut builder = Builder::new()
+        .name(worker.worker_name())
+        .rt_params(rt_params)
+        .blocking(worker.worker_is_blocking());
+    if let Some(stack_size) = worker.worker_stack_size() {
+        builder = builder.stack_size(stack_size);
+    }
+    let name = worker.worker_name().to_owned();
+    let restart_policy = worker.worker_restart_policy();
+    status.set_task_status(WorkerTaskStatus::Running);
+    let finished = FinishedGuard(status.clone());
+    let worker_status = status.clone();
+    supervisor.spawn(builder, move || {
+        let _finished = finished;
+        crate::memory::attach_current_thread(worker.worker_name());
+        let _span = profiling::worker_span(worker.worker_name());
+        if restart_policy == RestartPolicy::Never {
+            if let Err(e) = worker.run(&context) {
+                worker_status.set_task_status(WorkerTaskStatus::Failed);
+                error!(worker=worker.worker_name(), error=%e, "worker terminated");
+                critical(&format!(
+                    "Worker {} terminated: {}",
+                    worker.worker_name(),
+                    e
+                ));
+            }
+            return;
+        }
+        loop {
+            let failed = match panic::catch_unwind(AssertUnwindSafe(|| worker.run(&context))) {
+                Ok(Ok(())) => false,
+                Ok(Err(e)) => {
+                    error!(worker=worker.worker_name(), error=%e, "worker failed");
+                    true
+                }
+                Err(_) => {
+                    error!(worker = worker.worker_name(), "worker panicked");
+                    true
+                }
+            };
+            if !restart_policy.restart_required(failed) || !context.is_online() {
+                if failed {
+                    worker_status.set_task_status(WorkerTaskStatus::Failed);
+                }
+                break;
+            }
+            worker_status.set_task_status(WorkerTaskStatus::Restarting);
+            let restart_at = Instant::now() + WORKER_RESTART_DELAY;
+            while Instant::now() < restart_at {
+                if !context.is_online() {
+                    return;
+                }
+                thread::sleep(SLEEP_STEP.min(restart_at.saturating_duration_since(Instant::now())));
+            }
+            worker_status.restarts.fetch_add(1, Ordering::SeqCst);
+            worker_status.set_task_status(WorkerTaskStatus::Running);
+            warn!(worker = worker.worker_name(), policy = %restart_policy, "restarting worker");
+        }
+    })?;
+    Ok(WorkerHandle {
+        name: name.into(),
+        status,
+        commands: tx,
+    })
+}
+
+/// Worker task status (see [`WorkerHandle::status()`])
+#[derive(Default, Debug, Eq, PartialEq, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkerTaskStatus {
+    /// The worker is running
+    #[default]
+    Running,
+    /// The worker has exited and is going to be restarted (see [`RestartPolicy`])
+    Restarting,
+    /// The worker thread is finished
+    Finished,
+    /// The worker thread is finished after an error or a panic
+    Failed,
+}
+
+impl WorkerTaskStatus {
+    /// Returns true if the worker thread is finished
+    pub fn is_finished(self) -> bool {
+        matches!(self, WorkerTaskStatus::Finished | WorkerTaskStatus::Failed)
+    }
+}
+
+impl fmt::Display for WorkerTaskStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WorkerTaskStatus::Running => write!(f, "running"),
+            WorkerTaskStatus::Restarting => write!(f, "restarting"),
+            WorkerTaskStatus::Finished => write!(f, "finished"),
+            WorkerTaskStatus::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// Shared worker status, updated by the worker thread and [`Context::heartbeat()`]
+#[derive(Default)]
+struct WorkerStatus {
+    task_status: Mutex<WorkerTaskStatus>,
+    task_status_changed: Condvar,
+    last_heartbeat: Mutex<Option<Instant>>,
+    restarts: AtomicU32,
+    cycles: AtomicU64,
+}
+
+impl WorkerStatus {
+    fn set_task_status(&self, status: WorkerTaskStatus) {
+        *self.task_status.lock() = status;
+        self.task_status_changed.notify_all();
+    }
+}
+
+// marks the worker finished when the thread exits (including panics)
+struct FinishedGuard(Arc<WorkerStatus>);
+
+impl Drop for FinishedGuard {
+    fn drop(&mut self) {
+        let mut status = self.0.task_status.lock();
+        if thread::panicking() {
+            *status = WorkerTaskStatus::Failed;
+        } else if *status != WorkerTaskStatus::Failed {
+            *status = WorkerTaskStatus::Finished;
+        }
+        self.0.task_status_changed.notify_all();
+    }
+}
+
+/// A handle of a spawned worker, which allows to address the worker directly: send commands to
+/// its own channel (if the worker has got one, see [`WorkerOptions::worker_command_capacity()`]),
+/// query its status and wait for its task to finish. Can be cloned and shared with no
+/// limitations
+///
+/// The task object of the worker (real-time parameters, the join handle) is owned by the
+/// controller supervisor and is available with [`Controller::supervisor()`] by the worker name
+/// (dormant workers are supervised by [`WorkerCatalog`])
+pub struct WorkerHandle<D>
+where
+    D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
+{
+    name: Arc<str>,
+    status: Arc<WorkerStatus>,
+    commands: Option<Sender<D>>,
+}
+
+impl<D> Clone for WorkerHandle<D>
+where
+    D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
+{
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            status: self.status.clone(),
+            commands: self.commands.clone(),
+        }
+    }
+}
+
+impl<D> WorkerHandle<D>
+where
+    D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
+{
+    /// Worker (and its task) name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Worker task status
+    pub fn status(&self) -> WorkerTaskStatus {
+        *self.status.task_status.lock()
+    }
+    /// Returns true if the worker thread is finished
+    pub fn is_finished(&self) -> bool {
+        self.status().is_finished()
+    }
+    /// Blocks until the worker thread is finished. Returns the final task status
+    pub fn join(&self) -> WorkerTaskStatus {
+        let mut status = self.status.task_status.lock();
+        while !status.is_finished() {
+            self.status.task_status_changed.wait(&mut status);
+        }
+        *status
+    }
+    /// Blocks until the worker thread is finished or the timeout is reached. Returns the task
+    /// status
+    pub fn join_timeout(&self, timeout: Duration) -> WorkerTaskStatus {
+        let until = Instant::now() + timeout;
+        let mut status = self.status.task_status.lock();
+        while !status.is_finished() {
+            if self
+                .status
+                .task_status_changed
+                .wait_until(&mut status, until)
+                .timed_out()
+            {
+                break;
+            }
+        }
+        *status
+    }
+    /// The time of the last [`Context::heartbeat()`] call of the worker
+    pub fn last_heartbeat(&self) -> Option<Instant> {
+        *self.status.last_heartbeat.lock()
+    }
+    /// How many times the worker has been restarted, either automatically, according to its
+    /// [`RestartPolicy`], or with [`WorkerCatalog::start()`] (dormant workers only)
+    pub fn restarts(&self) -> u32 {
+        self.status.restarts.load(Ordering::SeqCst)
+    }
+    /// Returns true if the worker has got a command channel
+    pub fn has_commands(&self) -> bool {
+        self.commands.is_some()
+    }
+    /// Sends a command to the worker (blocking)
+    pub fn send(&self, command: D) -> Result<()> {
+        self.command_tx()?.send(command).map_err(Into::into)
+    }
+    /// Sends a command to the worker (non-blocking)
+    pub fn try_send(&self, command: D) -> Result<()> {
+        self.command_tx()?.try_send(command).map_err(Into::into)
+    }
+    fn command_tx(&self) -> Result<&Sender<D>> {
+        self.commands.as_ref().ok_or_else(|| {
+            Error::failed(format!("worker {} has got no command channel", self.name))
+        })
+    }
+}
+
+impl<D> fmt::Debug for WorkerHandle<D>
+where
+    D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WorkerHandle")
+            .field("name", &self.name)
+            .field("status", &self.status())
+            .field("restarts", &self.restarts())
+            .finish_non_exhaustive()
+    }
+}
+
+fn get_service<T: Send + Sync + 'static>(services: &ServiceMap) -> Result<&T> {
+    services
+        .get(&TypeId::of::<T>())
+        .and_then(|s| s.downcast_ref::<T>())
+        .ok_or(Error::ServiceNotRegistered(type_name::<T>()))
+}
+
 /// The context type is used to give workers access to the controller's hub, state, and shared
 /// variables.
 pub struct Context<D, V>
@@ -305,6 +1272,22 @@ pub struct Context<D, V>
     hub: Hub<D>,
     state: State,
     variables: Arc<RwLock<V>>,
+    // operation modes the worker is paused in
+    paused_in: Arc<[OperationMode]>,
+    importance: Importance,
+    // stop flag for dynamically started workers
+    stop: Arc<AtomicBool>,
+    commands: Option<Arc<Receiver<D>>>,
+    status: Arc<WorkerStatus>,
+    services: Arc<ServiceMap>,
+    flags: Flags,
+    start_mode: StartMode,
+    safe_state: SafeState,
+    // scripted hub traffic and the cycle limit for tests
+    #[cfg(target_os = "linux")]
+    script: Option<Arc<crate::testing::Script<D>>>,
+    #[cfg(feature = "kv")]
+    kv: Option<crate::kv::KvStore>,
 }
 
 impl<D, V> Clone for Context<D, V>
@@ -317,6 +1300,19 @@ fn clone(&self) -> Self {
             hub: self.hub.clone(),
             state: self.state.clone(),
             variables: self.variables.clone(),
+            paused_in: self.paused_in.clone(),
+            importance: self.importance,
+            stop: self.stop.clone(),
+            commands: self.commands.clone(),
+            status: self.status.clone(),
+            services: self.services.clone(),
+            flags: self.flags.clone(),
+            start_mode: self.start_mode,
+            safe_state: self.safe_state.clone(),
+            #[cfg(target_os = "linux")]
+            script: self.script.clone(),
+            #[cfg(feature = "kv")]
+            kv: self.kv.clone(),
         }
     }
 }
@@ -334,6 +1330,59 @@ pub fn hub(&self) -> &Hub<D> {
     pub fn variables(&self) -> &Arc<RwLock<V>> {
         &self.variables
     }
+    /// Returns a shared service object (see [`Controller::add_service()`]). Workers should get
+    /// required services at startup to fail early if a service is missing
+    pub fn service<T: Send + Sync + 'static>(&self) -> Result<&T> {
+        get_service(&self.services)
+    }
+    /// The worker's command channel (see [`WorkerOptions::worker_command_capacity()`])
+    pub fn commands(&self) -> Option<&Receiver<D>> {
+        self.commands.as_deref()
+    }
+    /// Reports the worker is alive, the time is available via [`WorkerHandle::last_heartbeat()`]
+    pub fn heartbeat(&self) {
+        self.status.last_heartbeat.lock().replace(Instant::now());
+    }
+    /// Starts a new cycle span (see [`crate::profiling`]), the span is closed when the guard is
+    /// dropped. Should be called at the beginning of each worker cycle
+    pub fn cycle_span(&self) -> ProfileSpan {
+        let cycle = self.status.cycles.fetch_add(1, Ordering::Relaxed);
+        #[cfg(target_os = "linux")]
+        if let Some(ref script) = self.script {
+            if script.on_cycle(cycle, &self.hub) {
+                self.stop.store(true, Ordering::SeqCst);
+            }
+        }
+        profiling::cycle_span(cycle)
+    }
+    // the number of cycles started
+    #[cfg(target_os = "linux")]
+    pub(crate) fn cycles(&self) -> u64 {
+        self.status.cycles.load(Ordering::Relaxed)
+    }
+    /// Controller's feature flags (see [`Controller::set_flags()`])
+    pub fn flags(&self) -> &Flags {
+        &self.flags
+    }
+    /// Controller's start mode, workers which keep their own state (e.g. sequences) should resume
+    /// it on hot starts only
+    pub fn start_mode(&self) -> StartMode {
+        self.start_mode
+    }
+    /// Controller's safe-state action registry, workers register their safe-state actions at
+    /// startup
+    pub fn safe_state(&self) -> &SafeState {
+        &self.safe_state
+    }
+    /// Executes registered safe-state actions (see [`Controller::enter_safe_state()`])
+    pub fn enter_safe_state(&self, reason: &str) -> SafeStateReport {
+        self.safe_state.enter(reason)
+    }
+    /// Controller's key-value store (see [`Controller::set_kv_store()`])
+    #[cfg(feature = "kv")]
+    pub fn kv(&self) -> Option<&crate::kv::KvStore> {
+        self.kv.as_ref()
+    }
     /// Controller's state
     pub fn get_state(&self) -> ControllerStateKind {
         self.state.get()
@@ -342,14 +1391,284 @@ pub fn get_state(&self) -> ControllerStateKind {
     pub fn set_state(&self, state: ControllerStateKind) {
         self.state.set(state);
     }
-    /// Is the controller online (starting or running)
+    /// Controller's state beacon, e.g. to wait for termination (see [`State::wait_offline()`],
+    /// [`State::eventfd()`] and [`State::offline()`])
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+    /// Is the controller online (starting or running). For workers, started with
+    /// [`WorkerCatalog::start()`], returns false also if the worker is requested to stop
     pub fn is_online(&self) -> bool {
-        self.state.is_online()
+        self.state.is_online() && !self.stop.load(Ordering::SeqCst)
     }
     /// Sets controller state to Stopping
     pub fn terminate(&self) {
         self.state.set(ControllerStateKind::Stopping);
     }
+    /// Controller's operation mode
+    pub fn operation_mode(&self) -> OperationMode {
+        self.state.operation_mode()
+    }
+    /// Sets controller's operation mode, all workers are notified
+    pub fn set_operation_mode(&self, mode: OperationMode) {
+        self.state.set_operation_mode(mode);
+    }
+    /// Blocks until the operation mode is changed from the given one or the timeout is reached.
+    /// Returns the current operation mode
+    pub fn wait_operation_mode_change(
+        &self,
+        current: OperationMode,
+        timeout: Duration,
+    ) -> OperationMode {
+        self.state.wait_operation_mode_change(current, timeout)
+    }
+    /// Returns true if the worker is paused in the current operation mode (see
+    /// [`WorkerOptions::worker_paused_in()`])
+    pub fn is_paused(&self) -> bool {
+        self.paused_in.contains(&self.operation_mode())
+    }
+    /// Blocks while the worker is paused in the current operation mode. Returns false if the
+    /// controller has gone offline
+    pub fn wait_while_paused(&self) -> bool {
+        loop {
+            if !self.is_online() {
+                return false;
+            }
+            let mode = self.operation_mode();
+            if !self.paused_in.contains(&mode) {
+                return true;
+            }
+            self.state.wait_operation_mode_change(mode, SLEEP_STEP);
+        }
+    }
+    /// The worker importance class (see [`WorkerOptions::worker_importance()`])
+    pub fn importance(&self) -> Importance {
+        self.importance
+    }
+    /// Returns true if the worker must reduce its load (e.g. lower the polling rate or pause) as
+    /// the controller is overloaded
+    pub fn is_load_shed(&self) -> bool {
+        self.state
+            .load_shedding()
+            .map_or(false, |level| self.importance <= level)
+    }
+    /// Blocks while the worker is being shed. Returns false if the controller has gone offline
+    pub fn wait_while_shed(&self) -> bool {
+        loop {
+            if !self.is_online() {
+                return false;
+            }
+            if !self.is_load_shed() {
+                return true;
+            }
+            // load shedding changes are notified the same way as operation mode changes
+            self.state
+                .wait_operation_mode_change(self.operation_mode(), SLEEP_STEP);
+        }
+    }
+}
+
+type WorkerFactory<D, V> = Box<
+    dyn Fn(&mut Supervisor<()>, Context<D, V>, Arc<WorkerStatus>) -> Result<WorkerHandle<D>>
+        + Send
+        + Sync,
+>;
+
+/// A catalog of dormant workers, which are registered with [`Controller::add_dormant_worker()`]
+/// and can be started/stopped at runtime, e.g. to toggle optional diagnostics pollers on a live
+/// machine by remote commands, received via the hub or a management interface.
+///
+/// Stopping is cooperative: a stop request makes [`Context::is_online()`] return false for the
+/// worker, which must exit its main loop. A stopped worker can be started again as soon as its
+/// thread is finished. Worker restart policies (see [`WorkerOptions::worker_restart_policy()`])
+/// are honored, a stopped worker is never restarted automatically.
+///
+/// Remote commands are executed with [`WorkerCatalog::execute()`], hub commands can be
+/// processed automatically with [`Controller::spawn_command_processor()`].
+pub struct WorkerCatalog<D, V>
+where
+    D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
+    V: Send,
+{
+    inner: Arc<Mutex<CatalogInner<D, V>>>,
+}
+
+struct CatalogInner<D, V>
+where
+    D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
+    V: Send,
+{
+    factories: BTreeMap<String, WorkerFactory<D, V>>,
+    stop_flags: BTreeMap<String, Arc<AtomicBool>>,
+    handles: BTreeMap<String, WorkerHandle<D>>,
+    supervisor: Supervisor<()>,
+    context: Option<Context<D, V>>,
+}
+
+impl<D, V> Default for WorkerCatalog<D, V>
+where
+    D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
+    V: Send,
+{
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(CatalogInner {
+                factories: <_>::default(),
+                stop_flags: <_>::default(),
+                handles: <_>::default(),
+                supervisor: <_>::default(),
+                context: None,
+            })),
+        }
+    }
+}
+
+impl<D, V> Clone for WorkerCatalog<D, V>
+where
+    D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
+    V: Send,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<D, V> WorkerCatalog<D, V>
+where
+    D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    fn add<W, F>(&self, name: &str, factory: F) -> Result<()>
+    where
+        W: Worker<D, V> + WorkerOptions + 'static,
+        F: Fn() -> W + Send + Sync + 'static,
+    {
+        let mut inner = self.inner.lock();
+        let btree_map::Entry::Vacant(entry) = inner.factories.entry(name.to_owned()) else {
+            return Err(Error::SupervisorDuplicateTask(name.to_owned()));
+        };
+        let expected_name = name.to_owned();
+        entry.insert(Box::new(move |supervisor, context, status| {
+            let worker = factory();
+            if worker.worker_name() != expected_name {
+                return Err(Error::invalid_data(format!(
+                    "worker name mismatch: expected {}, got {}",
+                    expected_name,
+                    worker.worker_name()
+                )));
+            }
+            spawn_worker_in(supervisor, context, status, worker)
+        }));
+        Ok(())
+    }
+    fn set_context<F: FnOnce() -> Context<D, V>>(&self, f: F) {
+        let mut inner = self.inner.lock();
+        if inner.context.is_none() {
+            inner.context = Some(f());
+        }
+    }
+    /// Starts a dormant worker. Handles of restarted workers share the status (e.g. the restart
+    /// count) with the previous ones
+    pub fn start(&self, name: &str) -> Result<WorkerHandle<D>> {
+        let mut inner = self.inner.lock();
+        let inner = &mut *inner;
+        inner.supervisor.purge();
+        if inner.supervisor.get_task(name).is_some() {
+            return Err(Error::SupervisorDuplicateTask(name.to_owned()));
+        }
+        let factory = inner
+            .factories
+            .get(name)
+            .ok_or(Error::SupervisorTaskNotFound)?;
+        let mut context = inner
+            .context
+            .clone()
+            .ok_or_else(|| Error::failed("the catalog is not attached to a controller"))?;
+        let stop = Arc::new(AtomicBool::new(false));
+        context.stop = stop.clone();
+        let previous = inner.handles.get(name).map(|handle| handle.status.clone());
+        let restarted = previous.is_some();
+        let handle = factory(&mut inner.supervisor, context, previous.unwrap_or_default())?;
+        if restarted {
+            handle.status.restarts.fetch_add(1, Ordering::SeqCst);
+        }
+        inner.stop_flags.insert(name.to_owned(), stop);
+        inner.handles.insert(name.to_owned(), handle.clone());
+        info!(worker = name, "dormant worker started");
+        Ok(handle)
+    }
+    /// Returns a handle of a dormant worker, which has been started at least once
+    pub fn handle(&self, name: &str) -> Option<WorkerHandle<D>> {
+        self.inner.lock().handles.get(name).cloned()
+    }
+    /// Requests a running dormant worker to stop
+    pub fn stop(&self, name: &str) -> Result<()> {
+        let mut inner = self.inner.lock();
+        inner.supervisor.purge();
+        if inner.supervisor.get_task(name).is_none() {
+            return Err(Error::SupervisorTaskNotFound);
+        }
+        if let Some(stop) = inner.stop_flags.get(name) {
+            stop.store(true, Ordering::SeqCst);
+        }
+        info!(worker = name, "dormant worker stop requested");
+        Ok(())
+    }
+    /// Returns true if the worker is running
+    pub fn is_running(&self, name: &str) -> bool {
+        self.inner
+            .lock()
+            .supervisor
+            .get_task(name)
+            .map_or(false, |task| !task.is_finished())
+    }
+    /// Names of registered dormant workers
+    pub fn workers(&self) -> Vec<String> {
+        self.inner.lock().factories.keys().cloned().collect()
+    }
+    /// Executes a controller command
+    pub fn execute(&self, command: &ControllerCommand) -> Result<()> {
+        match command {
+            ControllerCommand::StartWorker(name) => self.start(name).map(|_| ()),
+            ControllerCommand::StopWorker(name) => self.stop(name),
+            ControllerCommand::SetOperationMode(mode) => {
+                self.inner
+                    .lock()
+                    .context
+                    .as_ref()
+                    .ok_or_else(|| Error::failed("the catalog is not attached to a controller"))?
+                    .set_operation_mode(*mode);
+                Ok(())
+            }
+        }
+    }
+    // the lock is not held while joining, so workers can be started/stopped meanwhile
+    fn join_all(&self) {
+        loop {
+            let tasks: Vec<_> = {
+                let mut inner = self.inner.lock();
+                let names: Vec<String> = inner
+                    .supervisor
+                    .task_names()
+                    .map(ToOwned::to_owned)
+                    .collect();
+                names
+                    .iter()
+                    .filter_map(|name| inner.supervisor.take_task(name))
+                    .collect()
+            };
+            if tasks.is_empty() {
+                break;
+            }
+            for task in tasks {
+                if !task.is_blocking() {
+                    let _r = task.join();
+                }
+            }
+        }
+    }
 }
 
 /// The trait which MUST be implemented by all workers
@@ -387,4 +1706,26 @@ fn worker_cpu_ids(&self) -> Option<&[usize]> {
     fn worker_is_blocking(&self) -> bool {
         false
     }
+    /// Operation modes the worker should be paused in. The worker must periodically call
+    /// [`Context::wait_while_paused()`] or check [`Context::is_paused()`] to follow the declared
+    /// behavior
+    fn worker_paused_in(&self) -> &[OperationMode] {
+        &[]
+    }
+    /// The capacity of the worker's command channel, which allows to send commands directly to
+    /// the worker with [`WorkerHandle::send()`], the worker receives them with
+    /// [`Context::commands()`]. If not specified, the worker has got no command channel
+    fn worker_command_capacity(&self) -> Option<usize> {
+        None
+    }
+    /// The worker importance class for load shedding. Workers must check
+    /// [`Context::is_load_shed()`] and reduce their load when instructed
+    fn worker_importance(&self) -> Importance {
+        Importance::Normal
+    }
+    /// The worker restart policy. Workers with a restart policy other than
+    /// [`RestartPolicy::Never`] do not terminate the process on errors and panics
+    fn worker_restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::Never
+    }
 }
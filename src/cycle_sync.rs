@@ -0,0 +1,387 @@
+//!
+//! Cycle phase synchronization across nodes, e.g. for two controllers driving halves of one
+//! machine. [`CycleSync`] wraps [`Interval`] and aligns its time grid with a reference:
+//!
+//! * system time epochs ([`CycleSync::system_time()`]): ticks happen when the system time is a
+//!   multiple of the period (plus an optional offset). Nodes with the system time synchronized
+//!   with PTP (e.g. `ptp4l` + `phc2sys`) get phase-aligned cycles with no extra communication
+//!
+//! * sync packets from a master node ([`CycleSync::master()`]): the master sends a UDP packet
+//!   with [`SyncSender::send()`] on each tick, the slave nodes receive them with [`SyncReceiver`]
+//!   and align their ticks with the packet arrival times (minus the configured link delay)
+//!
+//! The first measured phase error is corrected immediately, then the grid is slewed with
+//! limited steps, so cycles are never shortened/extended abruptly. The measured phase error is
+//! available with [`CycleSync::phase_error()`].
+//!
+//! ```rust,no_run
+//! use roboplc::cycle_sync::CycleSync;
+//! use std::time::Duration;
+//!
+//! let mut cycle = CycleSync::system_time(Duration::from_millis(1));
+//! loop {
+//!     cycle.tick();
+//!     // the cycle logic
+//!     if !cycle.is_locked(Duration::from_micros(50)) {
+//!         // the phase is not aligned yet
+//!     }
+//! }
+//! ```
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot_rt::Mutex;
+use tracing::warn;
+
+use crate::time::{self, CatchUpPolicy, Interval, Tick};
+use crate::{Error, Result};
+
+const PACKET_MAGIC: &[u8; 4] = b"RPCS";
+const PACKET_VERSION: u8 = 1;
+const PACKET_SIZE: usize = 21;
+
+/// Cycle sync packet sender for the master node
+pub struct SyncSender {
+    socket: UdpSocket,
+    peers: Vec<SocketAddr>,
+    period: Duration,
+    seq: u64,
+    buf: Vec<u8>,
+}
+
+impl SyncSender {
+    /// Binds a socket to the local address, the packets are sent to all the peers
+    pub fn bind<A: ToSocketAddrs>(
+        local_addr: A,
+        peers: &[SocketAddr],
+        period: Duration,
+    ) -> Result<Self> {
+        Ok(Self {
+            socket: UdpSocket::bind(local_addr)?,
+            peers: peers.to_vec(),
+            period,
+            seq: 0,
+            buf: Vec::with_capacity(PACKET_SIZE),
+        })
+    }
+    /// Sends a sync packet, must be called right after the master's tick
+    pub fn send(&mut self) -> Result<()> {
+        self.seq = self.seq.wrapping_add(1);
+        encode_packet(self.seq, self.period, &mut self.buf);
+        for peer in &self.peers {
+            self.socket.send_to(&self.buf, peer)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SyncPacket {
+    seq: u64,
+    period: Duration,
+    received: Instant,
+}
+
+/// Cycle sync packet receiver for slave nodes. Can be cloned and shared with no limitations.
+/// Must be run in a separate thread with [`SyncReceiver::run()`], it is highly recommended to
+/// run the thread with a real-time priority, higher than the cycle one, as packet arrival times
+/// are taken when the thread receives them
+#[derive(Clone)]
+pub struct SyncReceiver {
+    socket: Arc<UdpSocket>,
+    master_addr: Option<SocketAddr>,
+    last: Arc<Mutex<Option<SyncPacket>>>,
+}
+
+impl SyncReceiver {
+    /// Binds a socket to the local address
+    pub fn bind<A: ToSocketAddrs>(local_addr: A) -> Result<Self> {
+        Ok(Self {
+            socket: UdpSocket::bind(local_addr)?.into(),
+            master_addr: None,
+            last: <_>::default(),
+        })
+    }
+    /// Accepts packets from the given master address only (the default is any)
+    pub fn master_addr(mut self, addr: SocketAddr) -> Self {
+        self.master_addr = Some(addr);
+        self
+    }
+    /// Receives sync packets
+    pub fn run(&self) -> Result<()> {
+        let mut buf = [0u8; PACKET_SIZE];
+        loop {
+            let (len, addr) = match self.socket.recv_from(&mut buf) {
+                Ok(v) => v,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let received = time::now();
+            if self.master_addr.map_or(false, |a| a != addr) {
+                warn!(%addr, "cycle sync packet from unknown address");
+                continue;
+            }
+            match decode_packet(&buf[..len]) {
+                Ok((seq, period)) => {
+                    self.last.lock().replace(SyncPacket {
+                        seq,
+                        period,
+                        received,
+                    });
+                }
+                Err(error) => warn!(%addr, %error, "invalid cycle sync packet"),
+            }
+        }
+    }
+    fn last(&self) -> Option<SyncPacket> {
+        *self.last.lock()
+    }
+}
+
+// magic, version, sequence (u64), period in nanoseconds (u64)
+fn encode_packet(seq: u64, period: Duration, buf: &mut Vec<u8>) {
+    buf.clear();
+    buf.extend(PACKET_MAGIC);
+    buf.push(PACKET_VERSION);
+    buf.extend(seq.to_be_bytes());
+    buf.extend(
+        u64::try_from(period.as_nanos())
+            .unwrap_or(u64::MAX)
+            .to_be_bytes(),
+    );
+}
+
+fn decode_packet(buf: &[u8]) -> Result<(u64, Duration)> {
+    if buf.len() != PACKET_SIZE || &buf[..4] != PACKET_MAGIC || buf[4] != PACKET_VERSION {
+        return Err(Error::invalid_data("invalid cycle sync packet"));
+    }
+    let seq = u64::from_be_bytes(buf[5..13].try_into().unwrap());
+    let period = Duration::from_nanos(u64::from_be_bytes(buf[13..21].try_into().unwrap()));
+    Ok((seq, period))
+}
+
+enum Reference {
+    SystemTime {
+        offset: Duration,
+    },
+    Master {
+        receiver: SyncReceiver,
+        link_delay: Duration,
+        last_seq: Option<u64>,
+    },
+}
+
+/// Interval with the phase aligned to an external reference
+pub struct CycleSync {
+    interval: Interval,
+    reference: Reference,
+    gain: f64,
+    max_step: Duration,
+    phase_error: Option<i64>,
+    aligned: bool,
+}
+
+impl CycleSync {
+    fn new(period: Duration, reference: Reference) -> Self {
+        Self {
+            interval: Interval::new(period).catch_up_policy(CatchUpPolicy::PhaseLock),
+            reference,
+            gain: 0.2,
+            max_step: period / 20,
+            phase_error: None,
+            aligned: false,
+        }
+    }
+    /// Aligns ticks with system time epochs (multiples of the period)
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the period is zero
+    pub fn system_time(period: Duration) -> Self {
+        Self::new(
+            period,
+            Reference::SystemTime {
+                offset: Duration::ZERO,
+            },
+        )
+    }
+    /// Aligns ticks with sync packets of a master node
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the period is zero
+    pub fn master(period: Duration, receiver: SyncReceiver) -> Self {
+        Self::new(
+            period,
+            Reference::Master {
+                receiver,
+                link_delay: Duration::ZERO,
+                last_seq: None,
+            },
+        )
+    }
+    /// Phase offset from system time epochs, e.g. to shift cycles of different nodes (system
+    /// time reference only, the default is zero)
+    pub fn offset(mut self, offset: Duration) -> Self {
+        if let Reference::SystemTime { offset: ref mut o } = self.reference {
+            *o = offset;
+        }
+        self
+    }
+    /// One-way link delay between the master and the node, which is subtracted from sync packet
+    /// arrival times (master reference only, the default is zero)
+    pub fn link_delay(mut self, delay: Duration) -> Self {
+        if let Reference::Master {
+            link_delay: ref mut d,
+            ..
+        } = self.reference
+        {
+            *d = delay;
+        }
+        self
+    }
+    /// Part of the phase error, corrected on each tick (the default is 0.2)
+    pub fn gain(mut self, gain: f64) -> Self {
+        self.gain = gain.clamp(0.0, 1.0);
+        self
+    }
+    /// Max phase correction per tick (the default is 1/20 of the period)
+    pub fn max_step(mut self, step: Duration) -> Self {
+        self.max_step = step;
+        self
+    }
+    /// Catch-up policy of the underlying interval (the default is [`CatchUpPolicy::PhaseLock`])
+    pub fn catch_up_policy(mut self, policy: CatchUpPolicy) -> Self {
+        self.interval.set_catch_up_policy(policy);
+        self
+    }
+    pub fn period(&self) -> Duration {
+        self.interval.period()
+    }
+    /// Waits for the next tick, measures the phase error and corrects the time grid
+    pub fn tick(&mut self) -> Tick {
+        let tick = self.interval.tick_info();
+        if let Some(error) = self.measure(tick.scheduled) {
+            self.phase_error = Some(error);
+            let correction = if self.aligned {
+                #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+                let c = (-(error as f64) * self.gain) as i64;
+                let max = i64::try_from(self.max_step.as_nanos()).unwrap_or(i64::MAX);
+                c.clamp(-max, max)
+            } else {
+                // the initial alignment
+                self.aligned = true;
+                -error
+            };
+            self.interval.shift_phase(correction);
+        }
+        tick
+    }
+    /// The last measured phase error in nanoseconds (the local tick time minus the reference
+    /// one), `None` if not measured yet
+    pub fn phase_error(&self) -> Option<i64> {
+        self.phase_error
+    }
+    /// Returns true if the last measured phase error is within the tolerance
+    pub fn is_locked(&self, tolerance: Duration) -> bool {
+        self.phase_error.map_or(false, |e| {
+            u128::from(e.unsigned_abs()) <= tolerance.as_nanos()
+        })
+    }
+    /// Resets the alignment, the next measured phase error is corrected immediately
+    pub fn reset(&mut self) {
+        self.aligned = false;
+        self.phase_error = None;
+    }
+    fn measure(&mut self, scheduled: Instant) -> Option<i64> {
+        let period = self.interval.period();
+        match self.reference {
+            Reference::SystemTime { offset } => {
+                let now = time::now();
+                let real: u128 = time::timestamp_now().as_nanos().into();
+                let real_scheduled =
+                    real.checked_sub(now.saturating_duration_since(scheduled).as_nanos())?;
+                Some(wrap_phase(
+                    i128::try_from(real_scheduled).ok()?
+                        - i128::try_from(offset.as_nanos()).ok()?,
+                    period,
+                ))
+            }
+            Reference::Master {
+                ref receiver,
+                link_delay,
+                ref mut last_seq,
+            } => {
+                let packet = receiver.last()?;
+                if *last_seq == Some(packet.seq) {
+                    return None;
+                }
+                last_seq.replace(packet.seq);
+                if packet.period != period {
+                    warn!(
+                        master = ?packet.period,
+                        local = ?period,
+                        "cycle sync period mismatch"
+                    );
+                    return None;
+                }
+                let master_tick = packet.received.checked_sub(link_delay)?;
+                let diff = if scheduled >= master_tick {
+                    i128::try_from(scheduled.duration_since(master_tick).as_nanos()).ok()?
+                } else {
+                    -i128::try_from(master_tick.duration_since(scheduled).as_nanos()).ok()?
+                };
+                Some(wrap_phase(diff, period))
+            }
+        }
+    }
+}
+
+// the phase of a time difference, in (-period/2, period/2]
+fn wrap_phase(diff: i128, period: Duration) -> i64 {
+    let period = i128::try_from(period.as_nanos()).unwrap_or(i128::MAX);
+    let mut phase = diff.rem_euclid(period);
+    if phase > period / 2 {
+        phase -= period;
+    }
+    i64::try_from(phase).unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{decode_packet, encode_packet, wrap_phase, CycleSync};
+
+    #[test]
+    fn test_wrap_phase() {
+        let period = Duration::from_millis(1);
+        assert_eq!(wrap_phase(100, period), 100);
+        assert_eq!(wrap_phase(-100, period), -100);
+        assert_eq!(wrap_phase(999_900, period), -100);
+        assert_eq!(wrap_phase(5_000_100, period), 100);
+        assert_eq!(wrap_phase(500_000, period), 500_000);
+    }
+
+    #[test]
+    fn test_packet() {
+        let mut buf = Vec::new();
+        encode_packet(42, Duration::from_millis(1), &mut buf);
+        assert_eq!(decode_packet(&buf).unwrap(), (42, Duration::from_millis(1)));
+        buf[0] = 0;
+        assert!(decode_packet(&buf).is_err());
+    }
+
+    #[test]
+    fn test_system_time_alignment() {
+        let mut cycle = CycleSync::system_time(Duration::from_millis(10));
+        for _ in 0..3 {
+            cycle.tick();
+        }
+        assert!(cycle.is_locked(Duration::from_micros(100)));
+    }
+}
@@ -0,0 +1,213 @@
+//!
+//! Report-by-exception filters for analog values.
+//!
+//! Noisy analog channels (e.g. temperatures, pressures) produce a new value every cycle while most
+//! of the changes are insignificant. A [`Deadband`] filter passes only values, which differ from
+//! the last reported one by more than the configured deadbands, plus periodic reports to let
+//! upstream systems know the channel is alive.
+//!
+//! A value is reported if any of the following is true:
+//!
+//! * the value is the first one (or the filter has been reset)
+//! * the absolute difference with the last reported value exceeds the absolute deadband
+//! * the difference in percents of the last reported value exceeds the percent deadband
+//! * the rate of change since the last report (units per second) exceeds the rate deadband
+//! * the max report interval is elapsed since the last report
+//!
+//! If no deadbands are configured, any change is reported.
+//!
+//! ```rust
+//! use roboplc::deadband::Deadband;
+//! use std::time::Duration;
+//!
+//! let mut filter = Deadband::new()
+//!     .absolute(0.5)
+//!     .max_interval(Duration::from_secs(10));
+//! assert_eq!(filter.process(20.0), Some(20.0));
+//! assert_eq!(filter.process(20.2), None);
+//! assert_eq!(filter.process(20.6), Some(20.6));
+//! ```
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+/// Deadband (report-by-exception) filter for a single analog channel. Cloned filters keep the
+/// configuration and the state, use [`Deadband::reset()`] to clear the state
+#[derive(Clone, Debug, Default)]
+pub struct Deadband {
+    absolute: Option<f64>,
+    percent: Option<f64>,
+    rate: Option<f64>,
+    max_interval: Option<Duration>,
+    last: Option<(f64, Instant)>,
+}
+
+impl Deadband {
+    /// Creates a new filter, which reports any change
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Absolute deadband, in value units
+    pub fn absolute(mut self, deadband: f64) -> Self {
+        self.absolute = Some(deadband.abs());
+        self
+    }
+    /// Percent deadband, relative to the last reported value
+    pub fn percent(mut self, deadband: f64) -> Self {
+        self.percent = Some(deadband.abs());
+        self
+    }
+    /// Rate deadband, in value units per second
+    pub fn rate(mut self, deadband: f64) -> Self {
+        self.rate = Some(deadband.abs());
+        self
+    }
+    /// Reports the value if there were no reports within the interval, even if the value is not
+    /// changed (no periodic reports by default)
+    pub fn max_interval(mut self, interval: Duration) -> Self {
+        self.max_interval = Some(interval);
+        self
+    }
+    /// The last reported value
+    pub fn last_reported(&self) -> Option<f64> {
+        self.last.map(|(v, _)| v)
+    }
+    /// Clears the filter state, the next value is always reported
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+    /// Processes a value, returns it back if it must be reported
+    pub fn process(&mut self, value: f64) -> Option<f64> {
+        self.check(value, Instant::now()).then_some(value)
+    }
+    /// Returns true if the value must be reported at the given time. Reported values are
+    /// remembered as the last ones
+    pub fn check(&mut self, value: f64, now: Instant) -> bool {
+        if self.is_significant(value, now) {
+            self.last = Some((value, now));
+            true
+        } else {
+            false
+        }
+    }
+    fn is_significant(&self, value: f64, now: Instant) -> bool {
+        let Some((last, t)) = self.last else {
+            return true;
+        };
+        let elapsed = now.saturating_duration_since(t);
+        if self.max_interval.map_or(false, |i| elapsed >= i) {
+            return true;
+        }
+        if value.is_nan() || last.is_nan() {
+            return value.is_nan() != last.is_nan();
+        }
+        let diff = (value - last).abs();
+        if self.absolute.is_none() && self.percent.is_none() && self.rate.is_none() {
+            return diff > 0.0;
+        }
+        if self.absolute.map_or(false, |d| diff > d) {
+            return true;
+        }
+        if let Some(d) = self.percent {
+            if last == 0.0 {
+                if diff > 0.0 {
+                    return true;
+                }
+            } else if diff / last.abs() * 100.0 > d {
+                return true;
+            }
+        }
+        if let Some(d) = self.rate {
+            let secs = elapsed.as_secs_f64();
+            if secs > 0.0 && diff / secs > d {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// A set of deadband filters for multiple channels, sharing the same configuration. Filters for
+/// new channels are created from the template on demand
+#[derive(Clone, Debug)]
+pub struct DeadbandSet<K: Ord> {
+    template: Deadband,
+    filters: BTreeMap<K, Deadband>,
+}
+
+impl<K: Ord> DeadbandSet<K> {
+    /// Creates a new set, the template state is ignored
+    pub fn new(mut template: Deadband) -> Self {
+        template.reset();
+        Self {
+            template,
+            filters: BTreeMap::new(),
+        }
+    }
+    /// Processes a channel value, returns it back if it must be reported
+    pub fn process(&mut self, channel: K, value: f64) -> Option<f64> {
+        self.check(channel, value, Instant::now()).then_some(value)
+    }
+    /// Returns true if the channel value must be reported at the given time
+    pub fn check(&mut self, channel: K, value: f64, now: Instant) -> bool {
+        self.filters
+            .entry(channel)
+            .or_insert_with(|| self.template.clone())
+            .check(value, now)
+    }
+    /// Clears the state of a channel, the next value is always reported
+    pub fn remove(&mut self, channel: &K) {
+        self.filters.remove(channel);
+    }
+    /// Clears the state of all channels
+    pub fn reset(&mut self) {
+        self.filters.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{Deadband, DeadbandSet};
+
+    #[test]
+    fn test_deadbands() {
+        let t = Instant::now();
+        let s = Duration::from_secs(1);
+        let mut f = Deadband::new();
+        assert!(f.check(1.0, t));
+        assert!(!f.check(1.0, t + s));
+        assert!(f.check(1.1, t + s));
+        let mut f = Deadband::new().absolute(0.5);
+        assert!(f.check(10.0, t));
+        assert!(!f.check(10.5, t));
+        assert!(f.check(9.4, t));
+        let mut f = Deadband::new().percent(10.0);
+        assert!(f.check(200.0, t));
+        assert!(!f.check(219.0, t));
+        assert!(f.check(221.0, t));
+        assert_eq!(f.last_reported(), Some(221.0));
+        let mut f = Deadband::new().absolute(100.0).rate(1.0);
+        assert!(f.check(0.0, t));
+        assert!(!f.check(0.5, t + s));
+        assert!(f.check(3.0, t + s * 2));
+        let mut f = Deadband::new().absolute(1.0).max_interval(s * 5);
+        assert!(f.check(0.0, t));
+        assert!(!f.check(0.0, t + s * 4));
+        assert!(f.check(0.0, t + s * 5));
+        assert!(f.check(f64::NAN, t + s * 5));
+        assert!(!f.check(f64::NAN, t + s * 6));
+    }
+
+    #[test]
+    fn test_deadband_set() {
+        let t = Instant::now();
+        let mut set = DeadbandSet::new(Deadband::new().absolute(1.0));
+        assert!(set.check("a", 0.0, t));
+        assert!(set.check("b", 0.5, t));
+        assert!(!set.check("a", 0.5, t));
+        assert!(set.check("b", 2.0, t));
+    }
+}
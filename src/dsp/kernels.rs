@@ -0,0 +1,185 @@
+//! Filter kernels. All implementations MUST perform floating point operations in the same order
+//! (no fused multiply-add) to produce bit-identical results.
+use super::{BiquadCoeffs, Lane};
+
+pub(super) mod scalar {
+    use super::{BiquadCoeffs, Lane};
+
+    pub fn biquad(
+        frames: &mut [Lane],
+        lanes_per_frame: usize,
+        c: &BiquadCoeffs,
+        z1: &mut [Lane],
+        z2: &mut [Lane],
+    ) {
+        for frame in frames.chunks_exact_mut(lanes_per_frame) {
+            for ((lane, s1), s2) in frame.iter_mut().zip(z1.iter_mut()).zip(z2.iter_mut()) {
+                for i in 0..lane.0.len() {
+                    let x = lane.0[i];
+                    let y = x * c.b0 + s1.0[i];
+                    s1.0[i] = x * c.b1 - y * c.a1 + s2.0[i];
+                    s2.0[i] = x * c.b2 - y * c.a2;
+                    lane.0[i] = y;
+                }
+            }
+        }
+    }
+
+    pub fn fir(
+        frames: &mut [Lane],
+        lanes_per_frame: usize,
+        taps: &[f32],
+        delay: &mut [Lane],
+        pos: &mut usize,
+    ) {
+        let len = taps.len();
+        for frame in frames.chunks_exact_mut(lanes_per_frame) {
+            let base = *pos * lanes_per_frame;
+            delay[base..base + lanes_per_frame].copy_from_slice(frame);
+            for (l, lane) in frame.iter_mut().enumerate() {
+                let mut acc = Lane::default();
+                let mut idx = *pos;
+                for tap in taps {
+                    let x = &delay[idx * lanes_per_frame + l];
+                    for i in 0..acc.0.len() {
+                        acc.0[i] = acc.0[i] + x.0[i] * tap;
+                    }
+                    idx = if idx == 0 { len - 1 } else { idx - 1 };
+                }
+                *lane = acc;
+            }
+            *pos = (*pos + 1) % len;
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+pub(super) mod avx {
+    use super::{BiquadCoeffs, Lane};
+    #[allow(clippy::wildcard_imports)]
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "avx")]
+    pub unsafe fn biquad(
+        frames: &mut [Lane],
+        lanes_per_frame: usize,
+        c: &BiquadCoeffs,
+        z1: &mut [Lane],
+        z2: &mut [Lane],
+    ) {
+        let b0 = _mm256_set1_ps(c.b0);
+        let b1 = _mm256_set1_ps(c.b1);
+        let b2 = _mm256_set1_ps(c.b2);
+        let a1 = _mm256_set1_ps(c.a1);
+        let a2 = _mm256_set1_ps(c.a2);
+        for frame in frames.chunks_exact_mut(lanes_per_frame) {
+            for ((lane, s1), s2) in frame.iter_mut().zip(z1.iter_mut()).zip(z2.iter_mut()) {
+                let x = _mm256_load_ps(lane.0.as_ptr());
+                let y = _mm256_add_ps(_mm256_mul_ps(x, b0), _mm256_load_ps(s1.0.as_ptr()));
+                let n1 = _mm256_add_ps(
+                    _mm256_sub_ps(_mm256_mul_ps(x, b1), _mm256_mul_ps(y, a1)),
+                    _mm256_load_ps(s2.0.as_ptr()),
+                );
+                let n2 = _mm256_sub_ps(_mm256_mul_ps(x, b2), _mm256_mul_ps(y, a2));
+                _mm256_store_ps(s1.0.as_mut_ptr(), n1);
+                _mm256_store_ps(s2.0.as_mut_ptr(), n2);
+                _mm256_store_ps(lane.0.as_mut_ptr(), y);
+            }
+        }
+    }
+
+    #[target_feature(enable = "avx")]
+    pub unsafe fn fir(
+        frames: &mut [Lane],
+        lanes_per_frame: usize,
+        taps: &[f32],
+        delay: &mut [Lane],
+        pos: &mut usize,
+    ) {
+        let len = taps.len();
+        for frame in frames.chunks_exact_mut(lanes_per_frame) {
+            let base = *pos * lanes_per_frame;
+            delay[base..base + lanes_per_frame].copy_from_slice(frame);
+            for (l, lane) in frame.iter_mut().enumerate() {
+                let mut acc = _mm256_setzero_ps();
+                let mut idx = *pos;
+                for tap in taps {
+                    let x = _mm256_load_ps(delay[idx * lanes_per_frame + l].0.as_ptr());
+                    acc = _mm256_add_ps(acc, _mm256_mul_ps(x, _mm256_set1_ps(*tap)));
+                    idx = if idx == 0 { len - 1 } else { idx - 1 };
+                }
+                _mm256_store_ps(lane.0.as_mut_ptr(), acc);
+            }
+            *pos = (*pos + 1) % len;
+        }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+pub(super) mod neon {
+    use super::{BiquadCoeffs, Lane};
+    #[allow(clippy::wildcard_imports)]
+    use std::arch::aarch64::*;
+
+    // a lane is processed as two 128-bit vectors
+    const HALF: usize = 4;
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn biquad(
+        frames: &mut [Lane],
+        lanes_per_frame: usize,
+        c: &BiquadCoeffs,
+        z1: &mut [Lane],
+        z2: &mut [Lane],
+    ) {
+        let b0 = vdupq_n_f32(c.b0);
+        let b1 = vdupq_n_f32(c.b1);
+        let b2 = vdupq_n_f32(c.b2);
+        let a1 = vdupq_n_f32(c.a1);
+        let a2 = vdupq_n_f32(c.a2);
+        for frame in frames.chunks_exact_mut(lanes_per_frame) {
+            for ((lane, s1), s2) in frame.iter_mut().zip(z1.iter_mut()).zip(z2.iter_mut()) {
+                for h in [0, HALF] {
+                    let x = vld1q_f32(lane.0.as_ptr().add(h));
+                    let y = vaddq_f32(vmulq_f32(x, b0), vld1q_f32(s1.0.as_ptr().add(h)));
+                    let n1 = vaddq_f32(
+                        vsubq_f32(vmulq_f32(x, b1), vmulq_f32(y, a1)),
+                        vld1q_f32(s2.0.as_ptr().add(h)),
+                    );
+                    let n2 = vsubq_f32(vmulq_f32(x, b2), vmulq_f32(y, a2));
+                    vst1q_f32(s1.0.as_mut_ptr().add(h), n1);
+                    vst1q_f32(s2.0.as_mut_ptr().add(h), n2);
+                    vst1q_f32(lane.0.as_mut_ptr().add(h), y);
+                }
+            }
+        }
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn fir(
+        frames: &mut [Lane],
+        lanes_per_frame: usize,
+        taps: &[f32],
+        delay: &mut [Lane],
+        pos: &mut usize,
+    ) {
+        let len = taps.len();
+        for frame in frames.chunks_exact_mut(lanes_per_frame) {
+            let base = *pos * lanes_per_frame;
+            delay[base..base + lanes_per_frame].copy_from_slice(frame);
+            for (l, lane) in frame.iter_mut().enumerate() {
+                for h in [0, HALF] {
+                    let mut acc = vdupq_n_f32(0.0);
+                    let mut idx = *pos;
+                    for tap in taps {
+                        let x = vld1q_f32(delay[idx * lanes_per_frame + l].0.as_ptr().add(h));
+                        acc = vaddq_f32(acc, vmulq_f32(x, vdupq_n_f32(*tap)));
+                        idx = if idx == 0 { len - 1 } else { idx - 1 };
+                    }
+                    vst1q_f32(lane.0.as_mut_ptr().add(h), acc);
+                }
+            }
+            *pos = (*pos + 1) % len;
+        }
+    }
+}
@@ -0,0 +1,544 @@
+//!
+//! Batch filtering of multi-channel analog inputs. Samples are stored in [`Block`]s, frame by
+//! frame, each frame is padded to a multiple of [`LANE_WIDTH`] channels and aligned, so the same
+//! filter is applied to all channels at once with SIMD instructions (AVX on x86_64, NEON on
+//! aarch64). The scalar backend is used if SIMD is not available and produces bit-identical
+//! results.
+//!
+//! Filters keep their own per-channel state and process blocks in-place, no allocations are
+//! performed after a filter is created, so they can be safely used in real-time threads.
+use crate::{Error, Result};
+
+mod kernels;
+
+/// Number of channels processed by a single SIMD operation
+pub const LANE_WIDTH: usize = 8;
+
+/// A group of [`LANE_WIDTH`] channel samples, aligned for SIMD operations
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+#[repr(C, align(32))]
+pub struct Lane(pub [f32; LANE_WIDTH]);
+
+/// Filter computation backend
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Backend {
+    /// Portable scalar code
+    Scalar,
+    /// x86_64 AVX
+    Avx,
+    /// aarch64 NEON
+    Neon,
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Self::detect()
+    }
+}
+
+impl Backend {
+    /// Detects the best backend available on the current CPU
+    pub fn detect() -> Self {
+        if Backend::Avx.is_supported() {
+            Backend::Avx
+        } else if Backend::Neon.is_supported() {
+            Backend::Neon
+        } else {
+            Backend::Scalar
+        }
+    }
+    /// Returns true if the backend is supported on the current CPU
+    pub fn is_supported(self) -> bool {
+        match self {
+            Backend::Scalar => true,
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx => is_x86_feature_detected!("avx"),
+            #[cfg(target_arch = "aarch64")]
+            Backend::Neon => std::arch::is_aarch64_feature_detected!("neon"),
+            #[allow(unreachable_patterns)]
+            _ => false,
+        }
+    }
+    fn checked(self) -> Self {
+        if self.is_supported() {
+            self
+        } else {
+            Backend::Scalar
+        }
+    }
+}
+
+fn lanes_for(channels: usize) -> usize {
+    (channels + LANE_WIDTH - 1) / LANE_WIDTH
+}
+
+/// Multi-channel sample block. Channel data is stored frame by frame (all channel samples of
+/// the first sample period, then all channel samples of the second one etc.)
+#[derive(Clone, Debug)]
+pub struct Block {
+    channels: usize,
+    lanes_per_frame: usize,
+    len: usize,
+    data: Vec<Lane>,
+}
+
+impl Block {
+    /// Creates a new empty block with the given number of channels and frame capacity
+    pub fn new(channels: usize, capacity: usize) -> Self {
+        let lanes_per_frame = lanes_for(channels);
+        Self {
+            channels,
+            lanes_per_frame,
+            len: 0,
+            data: vec![Lane::default(); lanes_per_frame * capacity],
+        }
+    }
+    /// Number of channels
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+    /// Maximum number of frames
+    pub fn capacity(&self) -> usize {
+        self.data.len() / self.lanes_per_frame.max(1)
+    }
+    /// Number of frames stored
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    pub fn is_full(&self) -> bool {
+        self.len == self.capacity()
+    }
+    /// Removes all frames, the capacity is kept
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+    /// Appends a frame. The frame MUST contain a sample for each channel
+    pub fn push_frame(&mut self, samples: &[f32]) -> Result<()> {
+        if samples.len() != self.channels {
+            return Err(Error::invalid_data(format!(
+                "frame size mismatch: {} samples, {} channels",
+                samples.len(),
+                self.channels
+            )));
+        }
+        if self.is_full() {
+            return Err(Error::invalid_data("block full"));
+        }
+        self.len += 1;
+        self.frame_mut(self.len - 1).copy_from_slice(samples);
+        Ok(())
+    }
+    /// Returns channel samples of a frame
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the frame is out of bounds
+    pub fn frame(&self, frame: usize) -> &[f32] {
+        assert!(frame < self.len, "frame out of bounds");
+        let lanes = &self.data[frame * self.lanes_per_frame..(frame + 1) * self.lanes_per_frame];
+        // lanes are repr(C) arrays of f32 with no padding
+        unsafe { std::slice::from_raw_parts(lanes.as_ptr().cast::<f32>(), self.channels) }
+    }
+    /// Returns mutable channel samples of a frame
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the frame is out of bounds
+    pub fn frame_mut(&mut self, frame: usize) -> &mut [f32] {
+        assert!(frame < self.len, "frame out of bounds");
+        let lanes =
+            &mut self.data[frame * self.lanes_per_frame..(frame + 1) * self.lanes_per_frame];
+        unsafe { std::slice::from_raw_parts_mut(lanes.as_mut_ptr().cast::<f32>(), self.channels) }
+    }
+    /// Returns a sample
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the frame or the channel is out of bounds
+    pub fn get(&self, frame: usize, channel: usize) -> f32 {
+        self.frame(frame)[channel]
+    }
+    /// Sets a sample
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the frame or the channel is out of bounds
+    pub fn set(&mut self, frame: usize, channel: usize, value: f32) {
+        self.frame_mut(frame)[channel] = value;
+    }
+    /// Iterates over samples of a single channel
+    pub fn channel(&self, channel: usize) -> impl Iterator<Item = f32> + '_ {
+        (0..self.len).map(move |frame| self.get(frame, channel))
+    }
+    fn lanes_mut(&mut self) -> &mut [Lane] {
+        &mut self.data[..self.len * self.lanes_per_frame]
+    }
+    fn check_channels(&self, channels: usize) -> Result<()> {
+        if self.channels == channels {
+            Ok(())
+        } else {
+            Err(Error::invalid_data(format!(
+                "channel count mismatch: block {}, filter {}",
+                self.channels, channels
+            )))
+        }
+    }
+}
+
+/// Normalized biquad (second-order IIR section) coefficients
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BiquadCoeffs {
+    pub b0: f32,
+    pub b1: f32,
+    pub b2: f32,
+    pub a1: f32,
+    pub a2: f32,
+}
+
+impl BiquadCoeffs {
+    /// Creates coefficients from raw values, normalizing them by `a0`
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(b0: f64, b1: f64, b2: f64, a0: f64, a1: f64, a2: f64) -> Self {
+        Self {
+            b0: (b0 / a0) as f32,
+            b1: (b1 / a0) as f32,
+            b2: (b2 / a0) as f32,
+            a1: (a1 / a0) as f32,
+            a2: (a2 / a0) as f32,
+        }
+    }
+    /// Low-pass filter (RBJ cookbook), `q` = 0.7071 for Butterworth response
+    pub fn lowpass(sample_rate: f64, cutoff: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::prepare(sample_rate, cutoff, q);
+        Self::new(
+            (1.0 - cos) / 2.0,
+            1.0 - cos,
+            (1.0 - cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+    /// High-pass filter (RBJ cookbook), `q` = 0.7071 for Butterworth response
+    pub fn highpass(sample_rate: f64, cutoff: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::prepare(sample_rate, cutoff, q);
+        Self::new(
+            (1.0 + cos) / 2.0,
+            -(1.0 + cos),
+            (1.0 + cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+    /// Notch filter (RBJ cookbook), e.g. to remove mains hum
+    pub fn notch(sample_rate: f64, frequency: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::prepare(sample_rate, frequency, q);
+        Self::new(1.0, -2.0 * cos, 1.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha)
+    }
+    fn prepare(sample_rate: f64, frequency: f64, q: f64) -> (f64, f64) {
+        let w0 = 2.0 * std::f64::consts::PI * frequency / sample_rate;
+        (w0.cos(), w0.sin() / (2.0 * q))
+    }
+}
+
+struct Section {
+    coeffs: BiquadCoeffs,
+    z1: Vec<Lane>,
+    z2: Vec<Lane>,
+}
+
+/// IIR filter, a cascade of biquad sections (direct form II transposed)
+pub struct Iir {
+    channels: usize,
+    lanes_per_frame: usize,
+    sections: Vec<Section>,
+    backend: Backend,
+}
+
+impl Iir {
+    /// Creates a new filter for the given number of channels
+    pub fn new(channels: usize, sections: &[BiquadCoeffs]) -> Self {
+        let lanes_per_frame = lanes_for(channels);
+        Self {
+            channels,
+            lanes_per_frame,
+            sections: sections
+                .iter()
+                .map(|coeffs| Section {
+                    coeffs: *coeffs,
+                    z1: vec![Lane::default(); lanes_per_frame],
+                    z2: vec![Lane::default(); lanes_per_frame],
+                })
+                .collect(),
+            backend: Backend::detect(),
+        }
+    }
+    /// Sets the computation backend (the default is auto-detected). If the backend is not
+    /// supported by the current CPU, the scalar one is used
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend.checked();
+        self
+    }
+    /// Resets the filter state
+    pub fn reset(&mut self) {
+        for section in &mut self.sections {
+            section.z1.fill(Lane::default());
+            section.z2.fill(Lane::default());
+        }
+    }
+    /// Filters the block in-place
+    pub fn process(&mut self, block: &mut Block) -> Result<()> {
+        block.check_channels(self.channels)?;
+        let lanes_per_frame = self.lanes_per_frame;
+        if lanes_per_frame == 0 {
+            return Ok(());
+        }
+        let frames = block.lanes_mut();
+        for s in &mut self.sections {
+            match self.backend {
+                #[cfg(target_arch = "x86_64")]
+                Backend::Avx => unsafe {
+                    kernels::avx::biquad(frames, lanes_per_frame, &s.coeffs, &mut s.z1, &mut s.z2);
+                },
+                #[cfg(target_arch = "aarch64")]
+                Backend::Neon => unsafe {
+                    kernels::neon::biquad(frames, lanes_per_frame, &s.coeffs, &mut s.z1, &mut s.z2);
+                },
+                _ => {
+                    kernels::scalar::biquad(
+                        frames,
+                        lanes_per_frame,
+                        &s.coeffs,
+                        &mut s.z1,
+                        &mut s.z2,
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// FIR filter
+pub struct Fir {
+    channels: usize,
+    lanes_per_frame: usize,
+    taps: Vec<f32>,
+    delay: Vec<Lane>,
+    pos: usize,
+    backend: Backend,
+}
+
+impl Fir {
+    /// Creates a new filter for the given number of channels
+    ///
+    /// # Panics
+    ///
+    /// Will panic if no taps are provided
+    pub fn new(channels: usize, taps: &[f32]) -> Self {
+        assert!(!taps.is_empty(), "FIR filter requires at least one tap");
+        let lanes_per_frame = lanes_for(channels);
+        Self {
+            channels,
+            lanes_per_frame,
+            taps: taps.to_vec(),
+            delay: vec![Lane::default(); lanes_per_frame * taps.len()],
+            pos: 0,
+            backend: Backend::detect(),
+        }
+    }
+    /// Creates a moving average filter
+    pub fn moving_average(channels: usize, len: usize) -> Self {
+        #[allow(clippy::cast_precision_loss)]
+        let tap = 1.0 / len.max(1) as f32;
+        Self::new(channels, &vec![tap; len.max(1)])
+    }
+    /// Sets the computation backend (the default is auto-detected). If the backend is not
+    /// supported by the current CPU, the scalar one is used
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend.checked();
+        self
+    }
+    /// Resets the filter state
+    pub fn reset(&mut self) {
+        self.delay.fill(Lane::default());
+        self.pos = 0;
+    }
+    /// Filters the block in-place
+    pub fn process(&mut self, block: &mut Block) -> Result<()> {
+        block.check_channels(self.channels)?;
+        let lanes_per_frame = self.lanes_per_frame;
+        if lanes_per_frame == 0 {
+            return Ok(());
+        }
+        let frames = block.lanes_mut();
+        match self.backend {
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx => unsafe {
+                kernels::avx::fir(
+                    frames,
+                    lanes_per_frame,
+                    &self.taps,
+                    &mut self.delay,
+                    &mut self.pos,
+                );
+            },
+            #[cfg(target_arch = "aarch64")]
+            Backend::Neon => unsafe {
+                kernels::neon::fir(
+                    frames,
+                    lanes_per_frame,
+                    &self.taps,
+                    &mut self.delay,
+                    &mut self.pos,
+                );
+            },
+            _ => kernels::scalar::fir(
+                frames,
+                lanes_per_frame,
+                &self.taps,
+                &mut self.delay,
+                &mut self.pos,
+            ),
+        }
+        Ok(())
+    }
+}
+
+/// Decimator, keeps every N-th frame. The input SHOULD be low-pass filtered before decimation
+/// to avoid aliasing. The phase is kept between blocks, so blocks of any size can be processed
+pub struct Decimator {
+    factor: usize,
+    phase: usize,
+}
+
+impl Decimator {
+    /// # Panics
+    ///
+    /// Will panic if the factor is zero
+    pub fn new(factor: usize) -> Self {
+        assert!(factor > 0, "decimation factor must be positive");
+        Self { factor, phase: 0 }
+    }
+    /// Decimates the input block, appending frames to the output one. Returns the number of
+    /// frames written
+    pub fn process(&mut self, input: &Block, output: &mut Block) -> Result<usize> {
+        output.check_channels(input.channels)?;
+        let mut written = 0;
+        for frame in 0..input.len() {
+            if self.phase == 0 {
+                output.push_frame(input.frame(frame))?;
+                written += 1;
+            }
+            self.phase = (self.phase + 1) % self.factor;
+        }
+        Ok(written)
+    }
+    /// Resets the decimation phase
+    pub fn reset(&mut self) {
+        self.phase = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Backend, BiquadCoeffs, Block, Decimator, Fir, Iir};
+
+    const CHANNELS: usize = 13;
+
+    #[allow(clippy::cast_precision_loss)]
+    fn signal(frames: usize) -> Block {
+        let mut block = Block::new(CHANNELS, frames);
+        for f in 0..frames {
+            let frame: Vec<f32> = (0..CHANNELS)
+                .map(|c| ((f * 31 + c * 17) % 97) as f32 / 97.0 - 0.5)
+                .collect();
+            block.push_frame(&frame).unwrap();
+        }
+        block
+    }
+
+    fn backends() -> Vec<Backend> {
+        [Backend::Scalar, Backend::Avx, Backend::Neon]
+            .into_iter()
+            .filter(|b| b.is_supported())
+            .collect()
+    }
+
+    #[test]
+    fn test_iir_backends() {
+        let coeffs = [
+            BiquadCoeffs::lowpass(10_000.0, 500.0, 0.7071),
+            BiquadCoeffs::notch(10_000.0, 50.0, 10.0),
+        ];
+        let mut reference = signal(256);
+        Iir::new(CHANNELS, &coeffs)
+            .backend(Backend::Scalar)
+            .process(&mut reference)
+            .unwrap();
+        for backend in backends() {
+            let mut filter = Iir::new(CHANNELS, &coeffs).backend(backend);
+            // process in two blocks to check the state is kept
+            let input = signal(256);
+            for half in 0..2 {
+                let mut block = Block::new(CHANNELS, 128);
+                for f in half * 128..(half + 1) * 128 {
+                    block.push_frame(input.frame(f)).unwrap();
+                }
+                filter.process(&mut block).unwrap();
+                for f in 0..128 {
+                    assert_eq!(
+                        block.frame(f),
+                        reference.frame(half * 128 + f),
+                        "{:?}",
+                        backend
+                    );
+                }
+            }
+        }
+        // DC gain of the low-pass filter
+        let mut filter = Iir::new(1, &coeffs[..1]);
+        let mut block = Block::new(1, 1000);
+        for _ in 0..1000 {
+            block.push_frame(&[1.0]).unwrap();
+        }
+        filter.process(&mut block).unwrap();
+        assert!((block.get(999, 0) - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_fir_backends() {
+        let mut reference = signal(100);
+        Fir::moving_average(CHANNELS, 5)
+            .backend(Backend::Scalar)
+            .process(&mut reference)
+            .unwrap();
+        let input = signal(100);
+        let expected: f32 = (0..5).map(|f| input.get(95 + f, 3)).sum::<f32>() / 5.0;
+        assert!((reference.get(99, 3) - expected).abs() < 1e-6);
+        for backend in backends() {
+            let mut block = signal(100);
+            Fir::moving_average(CHANNELS, 5)
+                .backend(backend)
+                .process(&mut block)
+                .unwrap();
+            for f in 0..100 {
+                assert_eq!(block.frame(f), reference.frame(f), "{:?}", backend);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decimator() {
+        let input = signal(10);
+        let mut output = Block::new(CHANNELS, 10);
+        let mut decimator = Decimator::new(3);
+        assert_eq!(decimator.process(&input, &mut output).unwrap(), 4);
+        assert_eq!(decimator.process(&input, &mut output).unwrap(), 3);
+        assert_eq!(output.frame(4), input.frame(2));
+    }
+}
@@ -0,0 +1,498 @@
+//!
+//! Encoder and pulse-counting utilities.
+//!
+//! * [`Counter`] - rollover-safe accumulation of hardware counters of any width (e.g. 16/32-bit
+//! fieldbus counter registers or 24-bit counter modules)
+//!
+//! * [`QuadratureDecoder`] - x4 decoding of A/B signals, read from GPIO
+//!
+//! * [`VelocityEstimator`] - velocity estimation with configurable filtering
+//!
+//! * [`Encoder`] - a counter scaled to position units with velocity estimation
+//!
+//! * [`Homing`] - homing state machine (reference switch and optional index pulse)
+//!
+//! Example:
+//!
+//! ```rust
+//! use roboplc::encoder::Counter;
+//!
+//! let mut counter = Counter::new(16);
+//! counter.update(65_530);
+//! // the counter has wrapped around
+//! assert_eq!(counter.update(4), 10);
+//! assert_eq!(counter.position(), 10);
+//! ```
+use std::{collections::VecDeque, time::Instant};
+
+use crate::{Error, Result};
+
+/// Accumulates a hardware counter value of the given width, handling rollovers. The counter must
+/// be read at least twice per its half-range to detect the direction correctly
+#[derive(Clone, Debug)]
+pub struct Counter {
+    bits: u32,
+    last: Option<u64>,
+    position: i64,
+}
+
+impl Counter {
+    /// Creates a new counter of the given width (1..=64 bits)
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the width is out of range
+    pub fn new(bits: u32) -> Self {
+        assert!(
+            (1..=64).contains(&bits),
+            "counter width must be 1..=64 bits"
+        );
+        Self {
+            bits,
+            last: None,
+            position: 0,
+        }
+    }
+    /// Updates the counter with a raw value (bits above the counter width are ignored). Returns
+    /// the signed delta since the previous update (zero for the first one)
+    pub fn update(&mut self, raw: u64) -> i64 {
+        let raw = raw & mask(self.bits);
+        let delta = self
+            .last
+            .map_or(0, |last| counter_delta(last, raw, self.bits));
+        self.last = Some(raw);
+        self.position = self.position.wrapping_add(delta);
+        delta
+    }
+    /// Accumulated position in counts
+    pub fn position(&self) -> i64 {
+        self.position
+    }
+    /// Sets the accumulated position
+    pub fn set_position(&mut self, position: i64) {
+        self.position = position;
+    }
+    /// Resets the counter, the next update is considered as the first one
+    pub fn reset(&mut self) {
+        self.last = None;
+        self.position = 0;
+    }
+}
+
+fn mask(bits: u32) -> u64 {
+    if bits >= 64 {
+        u64::MAX
+    } else {
+        (1 << bits) - 1
+    }
+}
+
+/// Returns the signed delta between two raw values of a counter of the given width, assuming the
+/// counter has moved less than half of its range
+#[allow(clippy::cast_possible_wrap)]
+pub fn counter_delta(prev: u64, current: u64, bits: u32) -> i64 {
+    let diff = current.wrapping_sub(prev) & mask(bits);
+    if bits >= 64 {
+        return diff as i64;
+    }
+    // sign-extend
+    let shift = 64 - bits;
+    ((diff << shift) as i64) >> shift
+}
+
+/// Quadrature (A/B) x4 decoder. Each valid signal edge is counted, invalid transitions (both
+/// signals changed, e.g. because of missed samples) are counted as errors
+#[derive(Clone, Debug, Default)]
+pub struct QuadratureDecoder {
+    state: Option<u8>,
+    count: i64,
+    errors: u64,
+    inverted: bool,
+}
+
+impl QuadratureDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Inverts the counting direction
+    pub fn inverted(mut self, inverted: bool) -> Self {
+        self.inverted = inverted;
+        self
+    }
+    /// Updates the decoder with the current signal states. Returns the count change (-1, 0 or 1)
+    pub fn update(&mut self, a: bool, b: bool) -> i8 {
+        let state = (u8::from(a) << 1) | u8::from(b);
+        let Some(prev) = self.state.replace(state) else {
+            return 0;
+        };
+        // gray code sequence: 00 -> 01 -> 11 -> 10 -> 00 is the forward direction
+        let step = match (prev, state) {
+            (0b00, 0b01) | (0b01, 0b11) | (0b11, 0b10) | (0b10, 0b00) => 1,
+            (0b00, 0b10) | (0b10, 0b11) | (0b11, 0b01) | (0b01, 0b00) => -1,
+            (p, s) if p == s => 0,
+            _ => {
+                self.errors += 1;
+                0
+            }
+        };
+        let step = if self.inverted { -step } else { step };
+        self.count += i64::from(step);
+        step
+    }
+    /// Counted edges
+    pub fn count(&self) -> i64 {
+        self.count
+    }
+    pub fn set_count(&mut self, count: i64) {
+        self.count = count;
+    }
+    /// Number of invalid transitions
+    pub fn errors(&self) -> u64 {
+        self.errors
+    }
+}
+
+/// Velocity filtering
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VelocityFilter {
+    /// No filtering, the velocity is calculated between two last samples
+    None,
+    /// First-order low-pass filter with the given time constant (seconds)
+    LowPass(f64),
+    /// The velocity is calculated over the given number of last samples (moving window), which
+    /// gives better resolution at low speeds
+    Window(usize),
+}
+
+/// Estimates velocity from position samples
+#[derive(Clone, Debug)]
+pub struct VelocityEstimator {
+    filter: VelocityFilter,
+    samples: VecDeque<(f64, Instant)>,
+    velocity: f64,
+}
+
+impl VelocityEstimator {
+    pub fn new(filter: VelocityFilter) -> Self {
+        let capacity = match filter {
+            VelocityFilter::Window(n) => n.max(1) + 1,
+            _ => 2,
+        };
+        Self {
+            filter,
+            samples: VecDeque::with_capacity(capacity),
+            velocity: 0.0,
+        }
+    }
+    /// Adds a position sample and returns the estimated velocity (units per second)
+    #[allow(clippy::float_cmp)]
+    pub fn update(&mut self, position: f64, at: Instant) -> f64 {
+        let window = match self.filter {
+            VelocityFilter::Window(n) => n.max(1),
+            _ => 1,
+        };
+        if self.samples.len() > window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((position, at));
+        let (Some(first), Some(last)) = (self.samples.front(), self.samples.back()) else {
+            return self.velocity;
+        };
+        let dt = last.1.saturating_duration_since(first.1).as_secs_f64();
+        if dt == 0.0 {
+            return self.velocity;
+        }
+        let raw = (last.0 - first.0) / dt;
+        self.velocity = match self.filter {
+            VelocityFilter::LowPass(tau) if tau > 0.0 => {
+                // the step between two last samples
+                let step = if self.samples.len() > 1 {
+                    let prev = self.samples[self.samples.len() - 2].1;
+                    last.1.saturating_duration_since(prev).as_secs_f64()
+                } else {
+                    dt
+                };
+                let alpha = step / (tau + step);
+                self.velocity + alpha * (raw - self.velocity)
+            }
+            _ => raw,
+        };
+        self.velocity
+    }
+    /// The last estimated velocity
+    pub fn velocity(&self) -> f64 {
+        self.velocity
+    }
+    pub fn reset(&mut self) {
+        self.samples.clear();
+        self.velocity = 0.0;
+    }
+}
+
+/// A hardware counter, scaled to position units, with velocity estimation
+#[derive(Clone, Debug)]
+pub struct Encoder {
+    counter: Counter,
+    counts_per_unit: f64,
+    offset: f64,
+    estimator: VelocityEstimator,
+}
+
+impl Encoder {
+    /// Creates a new encoder for a counter of the given width and resolution (counts per
+    /// position unit, negative to invert the direction)
+    #[allow(clippy::float_cmp)]
+    pub fn new(bits: u32, counts_per_unit: f64) -> Result<Self> {
+        if !(1..=64).contains(&bits) {
+            return Err(Error::invalid_data("counter width must be 1..=64 bits"));
+        }
+        if counts_per_unit == 0.0 || !counts_per_unit.is_finite() {
+            return Err(Error::invalid_data("invalid encoder resolution"));
+        }
+        Ok(Self {
+            counter: Counter::new(bits),
+            counts_per_unit,
+            offset: 0.0,
+            estimator: VelocityEstimator::new(VelocityFilter::None),
+        })
+    }
+    /// Sets the velocity filter (the default is [`VelocityFilter::None`])
+    pub fn velocity_filter(mut self, filter: VelocityFilter) -> Self {
+        self.estimator = VelocityEstimator::new(filter);
+        self
+    }
+    /// Updates the encoder with a raw counter value, read at the given time. Returns the
+    /// position
+    pub fn update(&mut self, raw: u64, at: Instant) -> f64 {
+        self.counter.update(raw);
+        let position = self.position();
+        self.estimator.update(position, at);
+        position
+    }
+    /// Position in units
+    #[allow(clippy::cast_precision_loss)]
+    pub fn position(&self) -> f64 {
+        self.counter.position() as f64 / self.counts_per_unit + self.offset
+    }
+    /// Estimated velocity in units per second
+    pub fn velocity(&self) -> f64 {
+        self.estimator.velocity()
+    }
+    /// Sets the current position (e.g. after homing)
+    pub fn set_position(&mut self, position: f64) {
+        self.counter.set_position(0);
+        self.offset = position;
+        self.estimator.reset();
+    }
+    /// The underlying counter
+    pub fn counter(&self) -> &Counter {
+        &self.counter
+    }
+}
+
+/// Homing method
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HomingMethod {
+    /// The current position is the home one
+    CurrentPosition,
+    /// Move to the reference switch, back off at the creep velocity, the home position is the
+    /// switch release point
+    Switch,
+    /// As [`HomingMethod::Switch`], the home position is the first index pulse after the switch
+    /// release
+    SwitchAndIndex,
+}
+
+/// Homing state
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HomingState {
+    Idle,
+    /// Moving towards the switch
+    Searching,
+    /// Moving off the switch
+    BackingOff,
+    /// Waiting for the index pulse
+    WaitingIndex,
+    /// Finished, contains the position (in the encoder units) where the home event happened
+    Done(f64),
+    /// The switch or the index pulse has not been found within the maximum travel
+    Failed,
+}
+
+/// Homing state machine. Produces velocity commands (e.g. for [`crate::motion::Jog`]) from the
+/// switch/index inputs and the current position, must be called every cycle while homing
+#[derive(Clone, Debug)]
+pub struct Homing {
+    method: HomingMethod,
+    search_velocity: f64,
+    creep_velocity: f64,
+    max_travel: Option<f64>,
+    state: HomingState,
+    start_position: f64,
+}
+
+impl Homing {
+    /// Creates a new homing state machine. The sign of the search velocity defines the search
+    /// direction, the axis backs off in the opposite one at the creep velocity
+    pub fn new(method: HomingMethod, search_velocity: f64, creep_velocity: f64) -> Self {
+        Self {
+            method,
+            search_velocity,
+            creep_velocity: creep_velocity.abs(),
+            max_travel: None,
+            state: HomingState::Idle,
+            start_position: 0.0,
+        }
+    }
+    /// Sets the maximum travel distance, homing fails if it is exceeded (no limit by default)
+    pub fn max_travel(mut self, distance: f64) -> Self {
+        self.max_travel = Some(distance.abs());
+        self
+    }
+    /// Starts homing from the given position
+    pub fn start(&mut self, position: f64) {
+        self.start_position = position;
+        self.state = if self.method == HomingMethod::CurrentPosition {
+            HomingState::Done(position)
+        } else {
+            HomingState::Searching
+        };
+    }
+    /// Aborts homing
+    pub fn abort(&mut self) {
+        self.state = HomingState::Idle;
+    }
+    pub fn state(&self) -> HomingState {
+        self.state
+    }
+    /// Returns true if homing is in progress
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self.state,
+            HomingState::Searching | HomingState::BackingOff | HomingState::WaitingIndex
+        )
+    }
+    /// Processes inputs and returns the velocity command (zero when homing is not active)
+    pub fn step(&mut self, switch: bool, index: bool, position: f64) -> f64 {
+        if let Some(max) = self.max_travel {
+            if self.is_active() && (position - self.start_position).abs() > max {
+                self.state = HomingState::Failed;
+            }
+        }
+        let back_off = -self.search_velocity.signum() * self.creep_velocity;
+        match self.state {
+            HomingState::Searching => {
+                if switch {
+                    self.state = HomingState::BackingOff;
+                    back_off
+                } else {
+                    self.search_velocity
+                }
+            }
+            HomingState::BackingOff => {
+                if switch {
+                    back_off
+                } else if self.method == HomingMethod::SwitchAndIndex {
+                    self.state = HomingState::WaitingIndex;
+                    back_off
+                } else {
+                    self.state = HomingState::Done(position);
+                    0.0
+                }
+            }
+            HomingState::WaitingIndex => {
+                if index {
+                    self.state = HomingState::Done(position);
+                    0.0
+                } else {
+                    back_off
+                }
+            }
+            HomingState::Idle | HomingState::Done(_) | HomingState::Failed => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::float_cmp)]
+mod test {
+    use super::{
+        counter_delta, Counter, Homing, HomingMethod, HomingState, QuadratureDecoder,
+        VelocityEstimator, VelocityFilter,
+    };
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_counter() {
+        assert_eq!(counter_delta(65_535, 0, 16), 1);
+        assert_eq!(counter_delta(0, 65_535, 16), -1);
+        assert_eq!(counter_delta(u64::from(u32::MAX) - 9, 10, 32), 20);
+        assert_eq!(counter_delta(0x00_0010, 0xff_fff0, 24), -32);
+        assert_eq!(counter_delta(u64::MAX, 1, 64), 2);
+        let mut counter = Counter::new(16);
+        assert_eq!(counter.update(100), 0);
+        for raw in (100..=200_000u64).step_by(1000) {
+            counter.update(raw);
+        }
+        assert_eq!(counter.position(), 199_000);
+        for raw in (0..=199_000u64).rev().step_by(1000) {
+            counter.update(raw);
+        }
+        assert_eq!(counter.position(), -100);
+    }
+
+    #[test]
+    fn test_quadrature() {
+        let mut decoder = QuadratureDecoder::new();
+        let forward = [(false, false), (false, true), (true, true), (true, false)];
+        for _ in 0..3 {
+            for (a, b) in forward {
+                decoder.update(a, b);
+            }
+        }
+        assert_eq!(decoder.count(), 11);
+        for (a, b) in forward.iter().rev() {
+            decoder.update(*a, *b);
+        }
+        assert_eq!(decoder.count(), 8);
+        // both signals changed
+        decoder.update(true, true);
+        assert_eq!(decoder.errors(), 1);
+    }
+
+    #[test]
+    fn test_velocity() {
+        let now = Instant::now();
+        let mut estimator = VelocityEstimator::new(VelocityFilter::Window(4));
+        for i in 0..10u32 {
+            estimator.update(
+                f64::from(i) * 0.5,
+                now + Duration::from_millis(u64::from(i) * 10),
+            );
+        }
+        assert!((estimator.velocity() - 50.0).abs() < 1e-6);
+        let mut estimator = VelocityEstimator::new(VelocityFilter::LowPass(0.05));
+        let mut v = 0.0;
+        for i in 0..1000u32 {
+            v = estimator.update(f64::from(i), now + Duration::from_millis(u64::from(i)));
+        }
+        assert!((v - 1000.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_homing() {
+        let mut homing = Homing::new(HomingMethod::SwitchAndIndex, -10.0, 1.0).max_travel(100.0);
+        homing.start(0.0);
+        assert_eq!(homing.step(false, false, -5.0), -10.0);
+        assert_eq!(homing.step(true, false, -20.0), 1.0);
+        assert_eq!(homing.step(true, false, -19.5), 1.0);
+        assert_eq!(homing.step(false, false, -19.0), 1.0);
+        assert_eq!(homing.state(), HomingState::WaitingIndex);
+        assert_eq!(homing.step(false, true, -18.7), 0.0);
+        assert_eq!(homing.state(), HomingState::Done(-18.7));
+        let mut homing = Homing::new(HomingMethod::Switch, 10.0, 1.0).max_travel(5.0);
+        homing.start(0.0);
+        homing.step(false, false, 6.0);
+        assert_eq!(homing.state(), HomingState::Failed);
+    }
+}
@@ -0,0 +1,297 @@
+//!
+//! Startup banner and environment report.
+//!
+//! [`crate::announce()`] (or [`crate::controller::Controller::announce()`], which includes
+//! placements of spawned tasks) logs the crate/build info, the run mode, a real-time preflight
+//! summary and the CPU topology, so every log (and every support ticket) starts with the same
+//! context. The report is serializable and can be attached as JSON as well:
+//!
+//! ```rust,no_run
+//! use roboplc::environment::EnvironmentReport;
+//!
+//! let report = EnvironmentReport::collect();
+//! report.log();
+//! if !report.preflight.warnings().is_empty() {
+//!     // e.g. refuse to start in production
+//! }
+//! ```
+use std::{env, fs, mem};
+
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::placement::{isolated_cpus, parse_cpu_list, TaskPlacement};
+use crate::thread_rt::{is_realtime, num_cpus};
+
+const ONLINE_CPUS_PATH: &str = "/sys/devices/system/cpu/online";
+const PREEMPT_RT_PATH: &str = "/sys/kernel/realtime";
+const OS_RELEASE_PATH: &str = "/proc/sys/kernel/osrelease";
+const RT_RUNTIME_PATH: &str = "/proc/sys/kernel/sched_rt_runtime_us";
+const LIMITS_PATH: &str = "/proc/self/limits";
+
+const FEATURES: &[(&str, bool)] = &[
+    ("comm-async", cfg!(feature = "comm-async")),
+    ("dlms", cfg!(feature = "dlms")),
+    ("eapi", cfg!(feature = "eapi")),
+    ("enip", cfg!(feature = "enip")),
+    ("ffi", cfg!(feature = "ffi")),
+    ("hub-executor", cfg!(feature = "hub-executor")),
+    ("kv", cfg!(feature = "kv")),
+    ("manager-api", cfg!(feature = "manager-api")),
+    ("metrics", cfg!(feature = "metrics")),
+    ("modbus", cfg!(feature = "modbus")),
+    ("modbus-tls", cfg!(feature = "modbus-tls")),
+    ("opcua", cfg!(feature = "opcua")),
+    ("outbox", cfg!(feature = "outbox")),
+    ("pipe", cfg!(feature = "pipe")),
+    ("rvideo", cfg!(feature = "rvideo")),
+    ("scheduler", cfg!(feature = "scheduler")),
+    ("schema", cfg!(feature = "schema")),
+    ("snapshot", cfg!(feature = "snapshot")),
+    ("soak", cfg!(feature = "soak")),
+];
+
+/// Real-time preflight checks
+#[derive(Debug, Clone, Serialize)]
+pub struct Preflight {
+    /// Kernel release
+    pub kernel: Option<String>,
+    pub preempt_rt: bool,
+    pub root: bool,
+    /// `RLIMIT_RTPRIO` soft limit, None if unlimited
+    pub rtprio_limit: Option<u64>,
+    /// `RLIMIT_MEMLOCK` soft limit (bytes), None if unlimited
+    pub memlock_limit: Option<u64>,
+    /// `sched_rt_runtime_us`, -1 if real-time throttling is disabled
+    pub rt_runtime_us: Option<i64>,
+}
+
+impl Preflight {
+    fn collect() -> Self {
+        let limits = fs::read_to_string(LIMITS_PATH).unwrap_or_default();
+        Self {
+            kernel: read_trimmed(OS_RELEASE_PATH),
+            preempt_rt: read_trimmed(PREEMPT_RT_PATH).map_or(false, |v| v == "1"),
+            root: nix::unistd::geteuid().is_root(),
+            rtprio_limit: soft_limit(&limits, "Max realtime priority"),
+            memlock_limit: soft_limit(&limits, "Max locked memory"),
+            rt_runtime_us: read_trimmed(RT_RUNTIME_PATH).and_then(|v| v.parse().ok()),
+        }
+    }
+    /// Returns problems which may affect real-time behavior
+    pub fn warnings(&self) -> Vec<&'static str> {
+        let mut warnings = Vec::new();
+        if !self.preempt_rt {
+            warnings.push("the kernel is not PREEMPT_RT");
+        }
+        if !self.root && self.rtprio_limit == Some(0) {
+            warnings.push("real-time priorities are not permitted (RLIMIT_RTPRIO is 0)");
+        }
+        if !self.root && self.memlock_limit.is_some() {
+            warnings.push("memory locking is limited (RLIMIT_MEMLOCK)");
+        }
+        if self.rt_runtime_us.map_or(false, |v| v >= 0) {
+            warnings.push("real-time throttling is enabled (sched_rt_runtime_us)");
+        }
+        warnings
+    }
+}
+
+/// CPU topology and the process affinity
+#[derive(Debug, Clone, Serialize)]
+pub struct CpuTopology {
+    /// The absolute number of CPUs, including isolated
+    pub total: usize,
+    pub online: Vec<usize>,
+    pub isolated: Vec<usize>,
+    /// CPUs the process is allowed to run on
+    pub process_affinity: Vec<usize>,
+}
+
+impl CpuTopology {
+    fn collect() -> Self {
+        Self {
+            total: num_cpus().unwrap_or_default(),
+            online: read_trimmed(ONLINE_CPUS_PATH)
+                .and_then(|v| parse_cpu_list(&v).ok())
+                .unwrap_or_default(),
+            isolated: isolated_cpus().unwrap_or_default(),
+            process_affinity: process_affinity(),
+        }
+    }
+}
+
+/// Environment report
+#[derive(Debug, Clone, Serialize)]
+pub struct EnvironmentReport {
+    pub crate_version: &'static str,
+    pub program: String,
+    pub pid: u32,
+    /// `debug` or `release`
+    pub profile: &'static str,
+    pub target: String,
+    /// Enabled crate features
+    pub features: Vec<&'static str>,
+    /// Locking implementation
+    pub locking: &'static str,
+    /// Started as a systemd unit (see [`crate::is_production()`])
+    pub production: bool,
+    /// Real-time functions are disabled (see [`crate::thread_rt::set_simulated()`])
+    pub simulated: bool,
+    pub preflight: Preflight,
+    pub cpus: CpuTopology,
+    /// Placements of spawned tasks
+    pub tasks: Vec<TaskPlacement>,
+}
+
+impl EnvironmentReport {
+    /// Collects the report. Values which can not be obtained are reported as empty
+    pub fn collect() -> Self {
+        Self {
+            crate_version: env!("CARGO_PKG_VERSION"),
+            program: env::args().next().unwrap_or_default(),
+            pid: std::process::id(),
+            profile: if cfg!(debug_assertions) {
+                "debug"
+            } else {
+                "release"
+            },
+            target: format!("{}-{}", env::consts::ARCH, env::consts::OS),
+            features: FEATURES
+                .iter()
+                .filter_map(|(name, enabled)| enabled.then_some(*name))
+                .collect(),
+            locking: "parking_lot_rt",
+            production: crate::is_production(),
+            simulated: !is_realtime(),
+            preflight: Preflight::collect(),
+            cpus: CpuTopology::collect(),
+            tasks: Vec::new(),
+        }
+    }
+    /// Sets task placements to report
+    pub fn tasks(mut self, tasks: Vec<TaskPlacement>) -> Self {
+        self.tasks = tasks;
+        self
+    }
+    /// Logs the report. Preflight problems are logged as warnings, unless started in simulated
+    /// mode
+    pub fn log(&self) {
+        info!(
+            version = self.crate_version,
+            program = %self.program,
+            pid = self.pid,
+            profile = self.profile,
+            target = %self.target,
+            features = %self.features.join(","),
+            locking = self.locking,
+            "RoboPLC"
+        );
+        let mode = match (self.production, self.simulated) {
+            (_, true) => "simulated",
+            (true, false) => "production",
+            (false, false) => "development",
+        };
+        let p = &self.preflight;
+        info!(
+            mode,
+            kernel = p.kernel.as_deref().unwrap_or("-"),
+            preempt_rt = p.preempt_rt,
+            root = p.root,
+            rtprio_limit = %format_limit(p.rtprio_limit),
+            memlock_limit = %format_limit(p.memlock_limit),
+            rt_runtime_us = p.rt_runtime_us.unwrap_or_default(),
+            "environment"
+        );
+        if !self.simulated {
+            for warning in p.warnings() {
+                warn!("preflight: {}", warning);
+            }
+        }
+        info!(
+            total = self.cpus.total,
+            online = %format_cpus(&self.cpus.online),
+            isolated = %format_cpus(&self.cpus.isolated),
+            process_affinity = %format_cpus(&self.cpus.process_affinity),
+            "CPUs"
+        );
+        for task in &self.tasks {
+            info!(
+                task = %task.name,
+                scheduling = ?task.scheduling,
+                priority = task.priority.unwrap_or_default(),
+                cpus = %format_cpus(&task.cpu_ids),
+                "task placement"
+            );
+        }
+    }
+}
+
+fn format_limit(limit: Option<u64>) -> String {
+    limit.map_or_else(|| "unlimited".to_owned(), |v| v.to_string())
+}
+
+fn format_cpus(cpus: &[usize]) -> String {
+    if cpus.is_empty() {
+        return "-".to_owned();
+    }
+    cpus.iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn read_trimmed(path: &str) -> Option<String> {
+    fs::read_to_string(path).ok().map(|v| v.trim().to_owned())
+}
+
+// parses a soft limit from /proc/self/limits, None if unlimited or not found
+fn soft_limit(limits: &str, name: &str) -> Option<u64> {
+    limits
+        .lines()
+        .find_map(|line| line.strip_prefix(name))
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(|v| v.parse().ok())
+}
+
+fn process_affinity() -> Vec<usize> {
+    unsafe {
+        let mut cpuset: libc::cpu_set_t = mem::zeroed();
+        if libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut cpuset) != 0 {
+            return Vec::new();
+        }
+        (0..usize::try_from(libc::CPU_SETSIZE).unwrap_or_default())
+            .filter(|cpu| libc::CPU_ISSET(*cpu, &cpuset))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_cpus, soft_limit, Preflight};
+
+    #[test]
+    fn test_preflight_warnings() {
+        let mut preflight = Preflight {
+            kernel: Some("6.6.0-rt15".to_owned()),
+            preempt_rt: true,
+            root: true,
+            rtprio_limit: Some(0),
+            memlock_limit: Some(65536),
+            rt_runtime_us: Some(-1),
+        };
+        assert!(preflight.warnings().is_empty());
+        preflight.root = false;
+        preflight.rt_runtime_us = Some(950_000);
+        assert_eq!(preflight.warnings().len(), 3);
+        assert_eq!(format_cpus(&[0, 2, 3]), "0,2,3");
+        assert_eq!(format_cpus(&[]), "-");
+        let limits = "Limit                     Soft Limit           Hard Limit           Units
+Max locked memory         8388608              8388608              bytes
+Max realtime priority     unlimited            unlimited
+";
+        assert_eq!(soft_limit(limits, "Max locked memory"), Some(8_388_608));
+        assert_eq!(soft_limit(limits, "Max realtime priority"), None);
+        assert_eq!(soft_limit(limits, "Max nice priority"), None);
+    }
+}
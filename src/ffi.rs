@@ -0,0 +1,396 @@
+//!
+//! C ABI for in-process data exchange with C/C++ code (e.g. legacy vision libraries linked into
+//! the program). C code can register hub clients, send and receive POD messages and read the
+//! controller state. The header file is located at `include/roboplc.h` and is generated with
+//! [cbindgen](https://crates.io/crates/cbindgen) (see `cbindgen.toml`).
+//!
+//! Hub messages are exchanged as raw bytes of `repr(C)` structures, each structure is registered
+//! in a [`Bridge`] with a numeric kind and functions to convert it from/to the hub message type.
+//!
+//! Example:
+//!
+//! ```rust,ignore
+//! #[repr(C)]
+//! #[derive(Clone, Copy)]
+//! struct Frame { id: u32, x: f32, y: f32 }
+//!
+//! let bridge = unsafe {
+//!     ffi::Bridge::new(controller.hub(), controller.state())
+//!         .register_pod::<Frame>(1, Message::Frame, |m| match m {
+//!             Message::Frame(f) => Some(*f),
+//!             _ => None,
+//!         })
+//! };
+//! bridge.install()?;
+//! ```
+use std::collections::BTreeMap;
+use std::ffi::{c_char, c_void, CStr};
+use std::mem;
+use std::sync::{Arc, RwLock};
+
+use crate::controller::{ControllerStateKind, State};
+use crate::hub::{Client, ClientOptions, Hub};
+use crate::{DataDeliveryPolicy, Error, Result};
+
+/// The operation is successful
+pub const ROBOPLC_OK: i32 = 0;
+/// The bridge is not installed
+pub const ROBOPLC_ERR_NOT_INSTALLED: i32 = -1;
+/// Invalid arguments or unregistered message kind
+pub const ROBOPLC_ERR_INVALID: i32 = -2;
+/// The channel is closed
+pub const ROBOPLC_ERR_CLOSED: i32 = -3;
+/// The channel is empty
+pub const ROBOPLC_ERR_EMPTY: i32 = -4;
+/// The buffer is too small for the message
+pub const ROBOPLC_ERR_BUF: i32 = -5;
+/// All other errors
+pub const ROBOPLC_ERR_FAILED: i32 = -99;
+
+// a set-once global, std lock is used as parking_lot_rt locks can not be created in const context
+static BRIDGE: RwLock<Option<Box<dyn BridgeApi>>> = RwLock::new(None);
+
+type DecodeFn<D> = Box<dyn Fn(&[u8]) -> D + Send + Sync>;
+type EncodeFn<D> = Box<dyn Fn(&D, &mut [u8]) -> bool + Send + Sync>;
+type MatchFn<D> = Box<dyn Fn(&D) -> bool + Send + Sync>;
+
+struct Layout<D> {
+    size: usize,
+    decode: DecodeFn<D>,
+    encode: EncodeFn<D>,
+    matches: MatchFn<D>,
+}
+
+/// Hub bridge for C code
+pub struct Bridge<D>
+where
+    D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
+{
+    hub: Hub<D>,
+    state: State,
+    layouts: BTreeMap<u32, Layout<D>>,
+}
+
+impl<D> Bridge<D>
+where
+    D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
+{
+    pub fn new(hub: &Hub<D>, state: &State) -> Self {
+        Self {
+            hub: hub.clone(),
+            state: state.clone(),
+            layouts: <_>::default(),
+        }
+    }
+    /// Registers a POD message layout
+    ///
+    /// # Safety
+    ///
+    /// The type MUST be `repr(C)`, contain no pointers/references and be valid for any bit
+    /// pattern, as values are copied from/to C memory as-is
+    pub unsafe fn register_pod<T>(
+        mut self,
+        kind: u32,
+        into: fn(T) -> D,
+        from: fn(&D) -> Option<T>,
+    ) -> Self
+    where
+        T: Copy + Send + Sync + 'static,
+    {
+        let size = mem::size_of::<T>();
+        self.layouts.insert(
+            kind,
+            Layout {
+                size,
+                decode: Box::new(move |data| {
+                    // the size is checked by the caller
+                    into(unsafe { data.as_ptr().cast::<T>().read_unaligned() })
+                }),
+                encode: Box::new(move |message, buf| {
+                    let Some(value) = from(message) else {
+                        return false;
+                    };
+                    unsafe { buf.as_mut_ptr().cast::<T>().write_unaligned(value) };
+                    true
+                }),
+                matches: Box::new(move |message| from(message).is_some()),
+            },
+        );
+        self
+    }
+    /// Installs the bridge globally. Only one bridge can be installed
+    pub fn install(self) -> Result<()> {
+        let mut bridge = BRIDGE
+            .write()
+            .map_err(|_| Error::failed("bridge lock poisoned"))?;
+        if bridge.is_some() {
+            return Err(Error::failed("FFI bridge already installed"));
+        }
+        bridge.replace(Box::new(BridgeInner {
+            hub: self.hub,
+            state: self.state,
+            layouts: Arc::new(self.layouts),
+        }));
+        Ok(())
+    }
+}
+
+trait BridgeApi: Send + Sync {
+    fn send(&self, kind: u32, data: &[u8]) -> i32;
+    fn register(&self, name: &str, kinds: Vec<u32>, capacity: usize) -> Result<Box<dyn ClientApi>>;
+    fn message_size(&self, kind: u32) -> Option<usize>;
+    fn state(&self) -> &State;
+}
+
+trait ClientApi: Send {
+    fn recv(&self, blocking: bool, kind: &mut u32, buf: &mut [u8], len: &mut usize) -> i32;
+}
+
+struct BridgeInner<D>
+where
+    D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
+{
+    hub: Hub<D>,
+    state: State,
+    layouts: Arc<BTreeMap<u32, Layout<D>>>,
+}
+
+impl<D> BridgeApi for BridgeInner<D>
+where
+    D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
+{
+    fn send(&self, kind: u32, data: &[u8]) -> i32 {
+        let Some(layout) = self.layouts.get(&kind) else {
+            return ROBOPLC_ERR_INVALID;
+        };
+        if data.len() != layout.size {
+            return ROBOPLC_ERR_INVALID;
+        }
+        self.hub.send((layout.decode)(data));
+        ROBOPLC_OK
+    }
+    fn register(&self, name: &str, kinds: Vec<u32>, capacity: usize) -> Result<Box<dyn ClientApi>> {
+        if kinds.iter().any(|k| !self.layouts.contains_key(k)) {
+            return Err(Error::invalid_data("unregistered message kind"));
+        }
+        let layouts = self.layouts.clone();
+        let condition_kinds = kinds.clone();
+        let condition = move |message: &D| {
+            condition_kinds
+                .iter()
+                .any(|kind| (layouts[kind].matches)(message))
+        };
+        let mut options = ClientOptions::new(name, condition);
+        if capacity > 0 {
+            options = options.capacity(capacity);
+        }
+        let client = self.hub.register_with_options(options)?;
+        Ok(Box::new(ClientInner {
+            client,
+            kinds,
+            layouts: self.layouts.clone(),
+        }))
+    }
+    fn message_size(&self, kind: u32) -> Option<usize> {
+        self.layouts.get(&kind).map(|l| l.size)
+    }
+    fn state(&self) -> &State {
+        &self.state
+    }
+}
+
+struct ClientInner<D>
+where
+    D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
+{
+    client: Client<D>,
+    kinds: Vec<u32>,
+    layouts: Arc<BTreeMap<u32, Layout<D>>>,
+}
+
+impl<D> ClientApi for ClientInner<D>
+where
+    D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
+{
+    fn recv(&self, blocking: bool, kind: &mut u32, buf: &mut [u8], len: &mut usize) -> i32 {
+        let result = if blocking {
+            self.client.recv()
+        } else {
+            self.client.try_recv()
+        };
+        let message = match result {
+            Ok(v) => v,
+            Err(Error::ChannelClosed) => return ROBOPLC_ERR_CLOSED,
+            Err(Error::ChannelEmpty) => return ROBOPLC_ERR_EMPTY,
+            Err(_) => return ROBOPLC_ERR_FAILED,
+        };
+        for k in &self.kinds {
+            let layout = &self.layouts[k];
+            if !(layout.matches)(&message) {
+                continue;
+            }
+            if layout.size > buf.len() {
+                return ROBOPLC_ERR_BUF;
+            }
+            if (layout.encode)(&message, &mut buf[..layout.size]) {
+                *kind = *k;
+                *len = layout.size;
+                return ROBOPLC_OK;
+            }
+        }
+        ROBOPLC_ERR_FAILED
+    }
+}
+
+/// Opaque hub client handle
+pub struct RoboplcClient(Box<dyn ClientApi>);
+
+fn with_bridge<R, F: FnOnce(&dyn BridgeApi) -> R>(f: F, default: R) -> R {
+    let Ok(bridge) = BRIDGE.read() else {
+        return default;
+    };
+    bridge.as_deref().map_or(default, f)
+}
+
+/// Sends a message to the hub
+///
+/// # Safety
+///
+/// `data` MUST point to a valid memory block of `len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn roboplc_hub_send(kind: u32, data: *const c_void, len: usize) -> i32 {
+    if data.is_null() {
+        return ROBOPLC_ERR_INVALID;
+    }
+    let data = std::slice::from_raw_parts(data.cast::<u8>(), len);
+    with_bridge(|bridge| bridge.send(kind, data), ROBOPLC_ERR_NOT_INSTALLED)
+}
+
+/// Registers a hub client which receives messages of the given kinds. If the capacity is zero,
+/// the hub default is used. Returns NULL on errors
+///
+/// # Safety
+///
+/// `name` MUST be a valid NUL-terminated string, `kinds` MUST point to `kinds_len` values
+#[no_mangle]
+pub unsafe extern "C" fn roboplc_hub_register(
+    name: *const c_char,
+    kinds: *const u32,
+    kinds_len: usize,
+    capacity: usize,
+) -> *mut RoboplcClient {
+    if name.is_null() || (kinds.is_null() && kinds_len > 0) {
+        return std::ptr::null_mut();
+    }
+    let Ok(name) = CStr::from_ptr(name).to_str() else {
+        return std::ptr::null_mut();
+    };
+    let kinds = if kinds_len == 0 {
+        Vec::new()
+    } else {
+        std::slice::from_raw_parts(kinds, kinds_len).to_vec()
+    };
+    with_bridge(
+        |bridge| match bridge.register(name, kinds, capacity) {
+            Ok(client) => Box::into_raw(Box::new(RoboplcClient(client))),
+            Err(_) => std::ptr::null_mut(),
+        },
+        std::ptr::null_mut(),
+    )
+}
+
+unsafe fn client_recv(
+    blocking: bool,
+    client: *const RoboplcClient,
+    kind: *mut u32,
+    buf: *mut c_void,
+    buf_len: usize,
+    len: *mut usize,
+) -> i32 {
+    if client.is_null() || kind.is_null() || buf.is_null() || len.is_null() {
+        return ROBOPLC_ERR_INVALID;
+    }
+    let buf = std::slice::from_raw_parts_mut(buf.cast::<u8>(), buf_len);
+    (*client).0.recv(blocking, &mut *kind, buf, &mut *len)
+}
+
+/// Receives a message, blocks until a message is available
+///
+/// # Safety
+///
+/// `client` MUST be a valid client handle, `buf` MUST point to a writable memory block of
+/// `buf_len` bytes, `kind` and `len` MUST be valid pointers
+#[no_mangle]
+pub unsafe extern "C" fn roboplc_client_recv(
+    client: *const RoboplcClient,
+    kind: *mut u32,
+    buf: *mut c_void,
+    buf_len: usize,
+    len: *mut usize,
+) -> i32 {
+    client_recv(true, client, kind, buf, buf_len, len)
+}
+
+/// Receives a message if available, returns `ROBOPLC_ERR_EMPTY` otherwise
+///
+/// # Safety
+///
+/// The same as for [`roboplc_client_recv()`]
+#[no_mangle]
+pub unsafe extern "C" fn roboplc_client_try_recv(
+    client: *const RoboplcClient,
+    kind: *mut u32,
+    buf: *mut c_void,
+    buf_len: usize,
+    len: *mut usize,
+) -> i32 {
+    client_recv(false, client, kind, buf, buf_len, len)
+}
+
+/// Unregisters and frees a hub client
+///
+/// # Safety
+///
+/// `client` MUST be a valid client handle or NULL and MUST NOT be used after the call
+#[no_mangle]
+pub unsafe extern "C" fn roboplc_client_free(client: *mut RoboplcClient) {
+    if !client.is_null() {
+        drop(Box::from_raw(client));
+    }
+}
+
+/// Returns the registered message size or a negative error code
+#[allow(clippy::cast_possible_truncation)]
+#[no_mangle]
+pub extern "C" fn roboplc_message_size(kind: u32) -> isize {
+    with_bridge(
+        |bridge| {
+            bridge
+                .message_size(kind)
+                .and_then(|s| isize::try_from(s).ok())
+                .unwrap_or(ROBOPLC_ERR_INVALID as isize)
+        },
+        ROBOPLC_ERR_NOT_INSTALLED as isize,
+    )
+}
+
+/// Returns the controller state (see `ControllerStateKind`), -128 if the bridge is not installed
+#[no_mangle]
+pub extern "C" fn roboplc_controller_state() -> i8 {
+    with_bridge(
+        |bridge| bridge.state().get() as i8,
+        ControllerStateKind::Unknown as i8,
+    )
+}
+
+/// Returns true if the controller is online
+#[no_mangle]
+pub extern "C" fn roboplc_controller_is_online() -> bool {
+    with_bridge(|bridge| bridge.state().is_online(), false)
+}
+
+/// Returns the controller operation mode (0 - normal, 1 - degraded, 2 - maintenance)
+#[no_mangle]
+pub extern "C" fn roboplc_controller_operation_mode() -> u8 {
+    with_bridge(|bridge| bridge.state().operation_mode() as u8, 0)
+}
@@ -0,0 +1,298 @@
+//!
+//! Per-deployment feature flags.
+//!
+//! Flags are named values, which allow the same binary to enable site-specific behavior (e.g.
+//! extra diagnostics pollers) without recompiling. Flags are loaded from environment variables
+//! (`ROBOPLC_FLAG_<NAME>`) and/or a flag file and may be overridden at runtime, e.g. by a manager
+//! API handler. Workers access flags via [`crate::controller::Context::flags()`].
+//!
+//! The flag file contains `name = value` lines, empty lines and lines starting with `#` are
+//! ignored, values may be quoted:
+//!
+//! ```text
+//! # site-specific flags
+//! extra_diagnostics = true
+//! poll_interval_ms = 250
+//! line_name = "packaging-2"
+//! ```
+//!
+//! Flag names are case-insensitive, dashes are treated as underscores. Runtime overrides have the
+//! highest priority, then the flag file, then environment variables (the flag file is usually
+//! deployed per site, while environment variables are set for the whole program).
+//!
+//! Example:
+//!
+//! ```rust
+//! use roboplc::flags::Flags;
+//!
+//! let flags = Flags::new();
+//! flags.set("extra_diagnostics", true);
+//! assert!(flags.is_enabled("extra-diagnostics"));
+//! assert_eq!(flags.get_or("poll_interval_ms", 500u64), 500);
+//! ```
+use std::{
+    collections::BTreeMap,
+    env, fmt, fs,
+    path::Path,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use parking_lot_rt::{Condvar, Mutex};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{Error, Result};
+
+/// Environment variable prefix for flags
+pub const ENV_PREFIX: &str = "ROBOPLC_FLAG_";
+
+/// Flag value source
+#[derive(Serialize, Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub enum FlagSource {
+    Environment,
+    File,
+    Override,
+}
+
+/// A flag value with its source
+#[derive(Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct FlagValue {
+    pub value: String,
+    pub source: FlagSource,
+}
+
+/// Feature flag set. Can be cloned and shared with no limitations, all clones share the same
+/// values
+#[derive(Clone, Default)]
+pub struct Flags {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    // values by source, the highest source wins
+    values: Mutex<BTreeMap<String, BTreeMap<FlagSource, String>>>,
+    generation: AtomicU64,
+    changed: Condvar,
+}
+
+impl Flags {
+    /// Creates an empty flag set
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Creates a flag set with values loaded from environment variables
+    pub fn from_env() -> Self {
+        let flags = Self::new();
+        flags.load_env();
+        flags
+    }
+    /// Loads flags from `ROBOPLC_FLAG_<NAME>` environment variables
+    pub fn load_env(&self) {
+        let values = env::vars()
+            .filter_map(|(k, v)| k.strip_prefix(ENV_PREFIX).map(|name| (name.to_owned(), v)))
+            .collect::<Vec<_>>();
+        self.replace_source(FlagSource::Environment, values);
+    }
+    /// Loads flags from a file (see the module documentation for the format). Values, previously
+    /// loaded from a file, are replaced
+    pub fn load_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let contents = fs::read_to_string(path)?;
+        let values = parse_flag_file(&contents)?;
+        self.replace_source(FlagSource::File, values);
+        Ok(())
+    }
+    /// Overrides a flag value at runtime
+    pub fn set<T: fmt::Display>(&self, name: &str, value: T) {
+        let name = normalize_name(name);
+        let value = value.to_string();
+        info!(flag = %name, value = %value, "flag overridden");
+        self.inner
+            .values
+            .lock()
+            .entry(name)
+            .or_default()
+            .insert(FlagSource::Override, value);
+        self.notify();
+    }
+    /// Removes a runtime override, the flag falls back to the file/environment value
+    pub fn reset(&self, name: &str) {
+        let name = normalize_name(name);
+        let mut values = self.inner.values.lock();
+        if let Some(sources) = values.get_mut(&name) {
+            if sources.remove(&FlagSource::Override).is_some() {
+                if sources.is_empty() {
+                    values.remove(&name);
+                }
+                drop(values);
+                info!(flag = %name, "flag override removed");
+                self.notify();
+            }
+        }
+    }
+    /// Returns the raw flag value
+    pub fn value(&self, name: &str) -> Option<FlagValue> {
+        self.inner
+            .values
+            .lock()
+            .get(&normalize_name(name))
+            .and_then(effective)
+    }
+    /// Returns a typed flag value. An error is returned if the value can not be parsed
+    pub fn get<T>(&self, name: &str) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        self.value(name)
+            .map(|v| {
+                v.value.parse::<T>().map_err(|e| {
+                    Error::invalid_data(format!("invalid value of flag {}: {}", name, e))
+                })
+            })
+            .transpose()
+    }
+    /// Returns a typed flag value or the default one if the flag is not set or the value is
+    /// invalid (a warning is logged)
+    pub fn get_or<T>(&self, name: &str, default: T) -> T
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        match self.get(name) {
+            Ok(v) => v.unwrap_or(default),
+            Err(error) => {
+                warn!(%error, "using the default flag value");
+                default
+            }
+        }
+    }
+    /// Returns true if a boolean flag is set and enabled (`true`, `1`, `yes` and `on` values
+    /// are accepted)
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.value(name).map_or(false, |v| parse_bool(&v.value))
+    }
+    /// Returns all flags with their effective values and sources (e.g. for manager API)
+    pub fn list(&self) -> BTreeMap<String, FlagValue> {
+        self.inner
+            .values
+            .lock()
+            .iter()
+            .filter_map(|(name, sources)| effective(sources).map(|v| (name.clone(), v)))
+            .collect()
+    }
+    /// Flag set generation, which is increased on every change
+    pub fn generation(&self) -> u64 {
+        self.inner.generation.load(Ordering::SeqCst)
+    }
+    /// Blocks until flags are changed since the given generation or the timeout is reached.
+    /// Returns the current generation
+    pub fn wait_change(&self, generation: u64, timeout: Duration) -> u64 {
+        let mut values = self.inner.values.lock();
+        let current = self.generation();
+        if current != generation {
+            return current;
+        }
+        self.inner.changed.wait_for(&mut values, timeout);
+        self.generation()
+    }
+    fn replace_source(&self, source: FlagSource, new_values: Vec<(String, String)>) {
+        let mut values = self.inner.values.lock();
+        for sources in values.values_mut() {
+            sources.remove(&source);
+        }
+        for (name, value) in new_values {
+            values
+                .entry(normalize_name(&name))
+                .or_default()
+                .insert(source, value);
+        }
+        values.retain(|_, sources| !sources.is_empty());
+        drop(values);
+        self.notify();
+    }
+    fn notify(&self) {
+        let _values = self.inner.values.lock();
+        self.inner.generation.fetch_add(1, Ordering::SeqCst);
+        self.inner.changed.notify_all();
+    }
+}
+
+impl fmt::Debug for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.list()).finish()
+    }
+}
+
+fn effective(sources: &BTreeMap<FlagSource, String>) -> Option<FlagValue> {
+    sources.iter().next_back().map(|(source, value)| FlagValue {
+        value: value.clone(),
+        source: *source,
+    })
+}
+
+fn normalize_name(name: &str) -> String {
+    name.trim().to_lowercase().replace('-', "_")
+}
+
+fn parse_bool(value: &str) -> bool {
+    matches!(
+        value.trim().to_lowercase().as_str(),
+        "true" | "1" | "yes" | "on"
+    )
+}
+
+fn parse_flag_file(contents: &str) -> Result<Vec<(String, String)>> {
+    let mut values = Vec::new();
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| Error::invalid_data(format!("invalid flag file line {}", n + 1)))?;
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+        values.push((name.trim().to_owned(), value.to_owned()));
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_flag_file, FlagSource, Flags};
+
+    #[test]
+    fn test_flags() {
+        let flags = Flags::new();
+        let values = parse_flag_file(
+            "# comment\n\nextra-diagnostics = yes\npoll_interval_ms=250\nline = \"packaging-2\"\n",
+        )
+        .unwrap();
+        flags.replace_source(FlagSource::File, values);
+        assert!(flags.is_enabled("EXTRA_DIAGNOSTICS"));
+        assert_eq!(flags.get::<u64>("poll_interval_ms").unwrap(), Some(250));
+        assert_eq!(flags.get::<String>("line").unwrap().unwrap(), "packaging-2");
+        assert!(flags.get::<u64>("line").is_err());
+        let generation = flags.generation();
+        flags.set("extra_diagnostics", false);
+        assert!(!flags.is_enabled("extra_diagnostics"));
+        assert!(flags.generation() > generation);
+        flags.reset("extra_diagnostics");
+        assert_eq!(
+            flags.value("extra_diagnostics").unwrap().source,
+            FlagSource::File
+        );
+        assert!(parse_flag_file("invalid line").is_err());
+    }
+}
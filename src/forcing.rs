@@ -0,0 +1,429 @@
+//!
+//! Forcing of process values for commissioning.
+//!
+//! A force fixes an input or an output at the given value regardless of the field state and the
+//! logic. Forces are kept in a shared [`Forces`] table, which is usually registered as a
+//! controller service (see [`crate::controller::Controller::add_service()`]) and modified by
+//! manager/HMI command handlers by point names. String values are parsed according to the point
+//! type, so handlers do not need to know it.
+//!
+//! IO workers register typed [`ForcePoint`]s and apply them in the process image/IO mapping path:
+//! inputs are forced after being read from the field (before the logic sees them), outputs are
+//! forced before being written to the field (after the logic has calculated them). The result is
+//! a dual-port [`ProcessValue`], which contains both the raw and the effective value, with the
+//! forced state flagged in the [`Quality`] bits. Applying a point is lock-free and
+//! allocation-free.
+//!
+//! All forces and releases are logged as warnings/info (journal) and all forces can be listed or
+//! cleared at once, e.g. at the end of commissioning.
+//!
+//! ```rust
+//! use roboplc::forcing::Forces;
+//! use roboplc::process_image::image;
+//!
+//! #[derive(Clone, Default)]
+//! struct Inputs {
+//!     level: roboplc::forcing::ProcessValue<f32>,
+//! }
+//!
+//! let forces = Forces::new();
+//! let level = forces.register::<f32>("tank1.level").unwrap();
+//! let (inputs_writer, mut inputs_reader) = image(Inputs::default());
+//! // manager/HMI command handler
+//! forces.force("tank1.level", "75.5").unwrap();
+//! // IO worker
+//! let raw_level = 42.0;
+//! inputs_writer.update(|i| i.level = level.apply(raw_level));
+//! // logic
+//! let inputs = inputs_reader.snapshot();
+//! assert_eq!(inputs.level.value, 75.5);
+//! assert!(inputs.level.quality.is_forced());
+//! assert_eq!(forces.clear(), 1);
+//! ```
+use std::{
+    collections::BTreeMap,
+    fmt,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use binrw::{BinRead, BinWrite};
+use parking_lot_rt::Mutex;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::io::IoMapping;
+use crate::{Error, Result};
+
+/// Process value quality bits
+#[derive(Serialize, Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+#[serde(transparent)]
+pub struct Quality(u8);
+
+impl Quality {
+    /// The value is good
+    pub const GOOD: Self = Self(0);
+    /// The value is forced
+    pub const FORCED: Self = Self(0b1);
+
+    /// Raw quality bits
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+    pub fn is_good(self) -> bool {
+        self == Self::GOOD
+    }
+    pub fn is_forced(self) -> bool {
+        self.0 & Self::FORCED.0 != 0
+    }
+}
+
+/// A dual-port process value: the raw value (read from the field for inputs, calculated by the
+/// logic for outputs) and the effective one, which is replaced with the force value if forced
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct ProcessValue<T> {
+    pub raw: T,
+    pub value: T,
+    pub quality: Quality,
+}
+
+impl<T: Copy> ProcessValue<T> {
+    /// A good (not forced) value
+    pub fn new(value: T) -> Self {
+        Self {
+            raw: value,
+            value,
+            quality: Quality::GOOD,
+        }
+    }
+}
+
+/// Types which can be forced. Implemented for `bool` and primitive numbers
+pub trait Forceable: Copy + fmt::Display + Send + Sync + 'static {
+    /// Type name, reported by [`Forces::list()`]
+    const KIND: &'static str;
+    fn to_bits(self) -> u64;
+    fn from_bits(bits: u64) -> Self;
+    /// Parses a value, received from manager/HMI commands
+    fn parse_value(s: &str) -> Option<Self>;
+}
+
+impl Forceable for bool {
+    const KIND: &'static str = "bool";
+    fn to_bits(self) -> u64 {
+        u64::from(self)
+    }
+    fn from_bits(bits: u64) -> Self {
+        bits != 0
+    }
+    fn parse_value(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "true" | "1" | "yes" | "on" => Some(true),
+            "false" | "0" | "no" | "off" => Some(false),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! impl_forceable_int {
+    ($($t: ty),*) => {
+        $(
+            impl Forceable for $t {
+                const KIND: &'static str = stringify!($t);
+                #[allow(clippy::cast_sign_loss, clippy::cast_lossless)]
+                fn to_bits(self) -> u64 {
+                    self as u64
+                }
+                #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+                fn from_bits(bits: u64) -> Self {
+                    bits as $t
+                }
+                fn parse_value(s: &str) -> Option<Self> {
+                    s.trim().parse().ok()
+                }
+            }
+        )*
+    };
+}
+
+impl_forceable_int!(u8, i8, u16, i16, u32, i32, u64, i64);
+
+impl Forceable for f32 {
+    const KIND: &'static str = "f32";
+    fn to_bits(self) -> u64 {
+        u64::from(f32::to_bits(self))
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    fn from_bits(bits: u64) -> Self {
+        f32::from_bits(bits as u32)
+    }
+    fn parse_value(s: &str) -> Option<Self> {
+        s.trim().parse().ok()
+    }
+}
+
+impl Forceable for f64 {
+    const KIND: &'static str = "f64";
+    fn to_bits(self) -> u64 {
+        f64::to_bits(self)
+    }
+    fn from_bits(bits: u64) -> Self {
+        f64::from_bits(bits)
+    }
+    fn parse_value(s: &str) -> Option<Self> {
+        s.trim().parse().ok()
+    }
+}
+
+struct Point {
+    kind: &'static str,
+    forced: AtomicBool,
+    bits: AtomicU64,
+    parse: fn(&str) -> Option<u64>,
+    format: fn(u64) -> String,
+}
+
+/// A forced value, returned by [`Forces::list()`]
+#[derive(Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct ForceInfo {
+    /// Point type
+    pub kind: &'static str,
+    pub value: String,
+}
+
+/// Force table. Can be cloned and shared with no limitations, all clones share the same forces
+#[derive(Clone, Default)]
+pub struct Forces {
+    points: Arc<Mutex<BTreeMap<String, Arc<Point>>>>,
+}
+
+impl Forces {
+    /// Creates an empty force table
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Registers a force point. If the point is already registered with the same type, the
+    /// existing one is returned (e.g. for inputs, which are both read and displayed by different
+    /// workers)
+    pub fn register<T: Forceable>(&self, name: &str) -> Result<ForcePoint<T>> {
+        let mut points = self.points.lock();
+        let point = points
+            .entry(name.to_owned())
+            .or_insert_with(|| {
+                Arc::new(Point {
+                    kind: T::KIND,
+                    forced: AtomicBool::new(false),
+                    bits: AtomicU64::new(0),
+                    parse: |s| T::parse_value(s).map(T::to_bits),
+                    format: |bits| T::from_bits(bits).to_string(),
+                })
+            })
+            .clone();
+        if point.kind != T::KIND {
+            return Err(Error::invalid_data(format!(
+                "force point {} is already registered as {}",
+                name, point.kind
+            )));
+        }
+        Ok(ForcePoint {
+            point,
+            _t: PhantomData,
+        })
+    }
+    /// Forces a point, the value is parsed according to the point type (for manager/HMI command
+    /// handlers)
+    pub fn force(&self, name: &str, value: &str) -> Result<()> {
+        let point = self.get(name)?;
+        let bits = (point.parse)(value).ok_or_else(|| {
+            Error::invalid_data(format!(
+                "invalid {} value for force point {}: {}",
+                point.kind, name, value
+            ))
+        })?;
+        Self::set(name, &point, bits);
+        Ok(())
+    }
+    /// Forces a point with a typed value
+    pub fn force_value<T: Forceable>(&self, name: &str, value: T) -> Result<()> {
+        let point = self.get(name)?;
+        if point.kind != T::KIND {
+            return Err(Error::invalid_data(format!(
+                "force point {} is {}, not {}",
+                name,
+                point.kind,
+                T::KIND
+            )));
+        }
+        Self::set(name, &point, value.to_bits());
+        Ok(())
+    }
+    /// Releases a force. Returns false if the point has not been forced
+    pub fn unforce(&self, name: &str) -> Result<bool> {
+        let point = self.get(name)?;
+        let released = point.forced.swap(false, Ordering::SeqCst);
+        if released {
+            info!(point = name, "force released");
+        }
+        Ok(released)
+    }
+    /// Returns all forced points
+    pub fn list(&self) -> BTreeMap<String, ForceInfo> {
+        self.points
+            .lock()
+            .iter()
+            .filter(|(_, point)| point.forced.load(Ordering::SeqCst))
+            .map(|(name, point)| {
+                (
+                    name.clone(),
+                    ForceInfo {
+                        kind: point.kind,
+                        value: (point.format)(point.bits.load(Ordering::SeqCst)),
+                    },
+                )
+            })
+            .collect()
+    }
+    /// Returns true if any point is forced
+    pub fn is_any_forced(&self) -> bool {
+        self.points
+            .lock()
+            .values()
+            .any(|point| point.forced.load(Ordering::SeqCst))
+    }
+    /// Releases all forces. Returns the number of released ones
+    pub fn clear(&self) -> usize {
+        let released = self
+            .points
+            .lock()
+            .values()
+            .filter(|point| point.forced.swap(false, Ordering::SeqCst))
+            .count();
+        if released > 0 {
+            warn!(released, "all forces released");
+        }
+        released
+    }
+    fn get(&self, name: &str) -> Result<Arc<Point>> {
+        self.points
+            .lock()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::invalid_data(format!("force point {} not registered", name)))
+    }
+    fn set(name: &str, point: &Point, bits: u64) {
+        point.bits.store(bits, Ordering::SeqCst);
+        point.forced.store(true, Ordering::SeqCst);
+        warn!(point = name, value = %(point.format)(bits), "value forced");
+    }
+}
+
+impl fmt::Debug for Forces {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.list()).finish()
+    }
+}
+
+/// A typed force point, registered with [`Forces::register()`]
+pub struct ForcePoint<T> {
+    point: Arc<Point>,
+    _t: PhantomData<T>,
+}
+
+impl<T> Clone for ForcePoint<T> {
+    fn clone(&self) -> Self {
+        Self {
+            point: self.point.clone(),
+            _t: PhantomData,
+        }
+    }
+}
+
+impl<T: Forceable> ForcePoint<T> {
+    pub fn is_forced(&self) -> bool {
+        self.point.forced.load(Ordering::Acquire)
+    }
+    /// Applies the force to the raw value
+    pub fn apply(&self, raw: T) -> ProcessValue<T> {
+        // the value is stored before the flag is set
+        if self.is_forced() {
+            ProcessValue {
+                raw,
+                value: T::from_bits(self.point.bits.load(Ordering::Acquire)),
+                quality: Quality::FORCED,
+            }
+        } else {
+            ProcessValue::new(raw)
+        }
+    }
+}
+
+/// An IO mapping of a single forceable value. Reads apply the force to the field value (input
+/// forcing), writes replace the value from the logic with the force one (output forcing)
+pub struct ForcedMapping<M, T> {
+    mapping: M,
+    point: ForcePoint<T>,
+}
+
+impl<M, T> ForcedMapping<M, T>
+where
+    M: IoMapping,
+    T: Forceable + for<'a> BinRead<Args<'a> = ()> + for<'a> BinWrite<Args<'a> = ()>,
+{
+    pub fn new(mapping: M, point: ForcePoint<T>) -> Self {
+        Self { mapping, point }
+    }
+    /// Reads the value from the field and applies the force
+    pub fn read(&mut self) -> Result<ProcessValue<T>> {
+        let raw = self.mapping.read::<T>()?;
+        Ok(self.point.apply(raw))
+    }
+    /// Writes the value to the field or the force value if forced. Returns the written process
+    /// value
+    pub fn write(&mut self, value: T) -> Result<ProcessValue<T>> {
+        let pv = self.point.apply(value);
+        self.mapping.write(pv.value)?;
+        Ok(pv)
+    }
+    pub fn point(&self) -> &ForcePoint<T> {
+        &self.point
+    }
+    /// The underlying mapping
+    pub fn mapping_mut(&mut self) -> &mut M {
+        &mut self.mapping
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Forces, ProcessValue};
+
+    #[test]
+    fn test_forces() {
+        let forces = Forces::new();
+        let pump = forces.register::<bool>("pump").unwrap();
+        let level = forces.register::<i16>("level").unwrap();
+        assert!(forces.register::<u16>("level").is_err());
+        assert_eq!(level.apply(-5), ProcessValue::new(-5));
+        forces.force("pump", "on").unwrap();
+        forces.force_value("level", -100i16).unwrap();
+        assert!(forces.force("level", "x").is_err());
+        assert!(forces.force_value("level", 1u8).is_err());
+        assert!(forces.force("missing", "1").is_err());
+        let pv = level.apply(5);
+        assert_eq!((pv.raw, pv.value), (5, -100));
+        assert!(pv.quality.is_forced());
+        assert!(pump.apply(false).value);
+        let list = forces.list();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list["level"].value, "-100");
+        assert!(forces.unforce("pump").unwrap());
+        assert!(!forces.unforce("pump").unwrap());
+        assert!(!pump.apply(false).value);
+        assert_eq!(forces.clear(), 1);
+        assert!(!forces.is_any_forced());
+        assert!(level.apply(5).quality.is_good());
+    }
+}
@@ -0,0 +1,192 @@
+//!
+//! Binary frame specifications for raw UDP and custom protocols.
+//!
+//! The [`Frame`] derive macro generates a human-readable specification (field offsets, sizes,
+//! types, units) of a fixed-size binary frame as const data. The binary layout itself is still
+//! handled by [binrw](https://docs.rs/binrw), the specification is consumed by telemetry viewers,
+//! documentation generators and peers, written in other languages.
+//!
+//! ```rust,ignore
+//! use binrw::binrw;
+//! use roboplc::frame::Frame;
+//!
+//! #[binrw]
+//! #[brw(little)]
+//! #[derive(Frame)]
+//! struct EnvData {
+//!     /// Ambient temperature
+//!     #[frame(unit = "°C")]
+//!     temp: f64,
+//!     #[frame(unit = "%")]
+//!     hum: f64,
+//!     pressure: f64,
+//!     flags: [u8; 4],
+//! }
+//!
+//! assert_eq!(EnvData::SIZE, 28);
+//! println!("{}", EnvData::spec());
+//! ```
+//!
+//! Field-level binrw attributes, which change the layout (padding, magic, conditional fields
+//! etc.), are not supported. If the derive macro can not see the struct-level endianness (e.g.
+//! `#[binrw]` is placed below the derive), it can be specified with `#[frame(endian = "big")]`.
+use std::fmt;
+
+use serde::Serialize;
+
+pub use roboplc_derive::Frame;
+
+/// Frame byte order
+#[derive(Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Endianness {
+    Big,
+    Little,
+    /// Not specified (native for binrw)
+    Unspecified,
+}
+
+impl fmt::Display for Endianness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endianness::Big => write!(f, "big-endian"),
+            Endianness::Little => write!(f, "little-endian"),
+            Endianness::Unspecified => write!(f, "native byte order"),
+        }
+    }
+}
+
+/// Frame field specification
+#[derive(Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FrameField {
+    pub name: &'static str,
+    pub type_name: &'static str,
+    /// Offset in bytes from the frame start
+    pub offset: usize,
+    /// Size in bytes
+    pub size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<&'static str>,
+}
+
+/// Fixed-size binary frames, usually implemented with the derive macro. Implemented for
+/// primitive numeric types and arrays to be used as frame fields
+pub trait Frame {
+    const NAME: &'static str;
+    /// Frame size in bytes
+    const SIZE: usize;
+    const ENDIANNESS: Endianness = Endianness::Unspecified;
+    /// Frame fields, empty for primitives
+    const FIELDS: &'static [FrameField] = &[];
+    /// Frame specification
+    fn spec() -> FrameSpec {
+        FrameSpec {
+            name: Self::NAME,
+            size: Self::SIZE,
+            endianness: Self::ENDIANNESS,
+            fields: Self::FIELDS,
+        }
+    }
+}
+
+macro_rules! impl_frame_primitive {
+    ($($t: ty),*) => {
+        $(
+            impl Frame for $t {
+                const NAME: &'static str = stringify!($t);
+                const SIZE: usize = std::mem::size_of::<$t>();
+            }
+        )*
+    };
+}
+
+impl_frame_primitive!(u8, i8, u16, i16, u32, i32, u64, i64, u128, i128, f32, f64);
+
+impl<T: Frame, const N: usize> Frame for [T; N] {
+    const NAME: &'static str = "array";
+    const SIZE: usize = T::SIZE * N;
+}
+
+/// Frame specification, can be serialized (e.g. to JSON) or displayed as a text table
+#[derive(Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FrameSpec {
+    pub name: &'static str,
+    pub size: usize,
+    pub endianness: Endianness,
+    pub fields: &'static [FrameField],
+}
+
+impl fmt::Display for FrameSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} ({} bytes, {})",
+            self.name, self.size, self.endianness
+        )?;
+        writeln!(f, "| Offset | Size | Field | Type | Unit | Description |")?;
+        writeln!(f, "|---|---|---|---|---|---|")?;
+        for field in self.fields {
+            writeln!(
+                f,
+                "| {} | {} | {} | {} | {} | {} |",
+                field.offset,
+                field.size,
+                field.name,
+                field.type_name,
+                field.unit.unwrap_or_default(),
+                field.description.unwrap_or_default()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Endianness, Frame, FrameField};
+
+    // a manual implementation, equal to the derive macro output
+    struct Data;
+
+    impl Frame for Data {
+        const NAME: &'static str = "Data";
+        const SIZE: usize = <u16 as Frame>::SIZE + <[f32; 3] as Frame>::SIZE;
+        const ENDIANNESS: Endianness = Endianness::Big;
+        const FIELDS: &'static [FrameField] = &[
+            FrameField {
+                name: "id",
+                type_name: "u16",
+                offset: 0,
+                size: <u16 as Frame>::SIZE,
+                unit: None,
+                description: None,
+            },
+            FrameField {
+                name: "values",
+                type_name: "[f32;3]",
+                offset: <u16 as Frame>::SIZE,
+                size: <[f32; 3] as Frame>::SIZE,
+                unit: Some("V"),
+                description: Some("Phase voltages"),
+            },
+        ];
+    }
+
+    #[test]
+    fn test_frame_spec() {
+        assert_eq!(<[u64; 2] as Frame>::SIZE, 16);
+        let spec = Data::spec();
+        assert_eq!(spec.size, 14);
+        assert_eq!(spec.fields[1].offset, 2);
+        assert_eq!(spec.fields[1].size, 12);
+        let table = spec.to_string();
+        let mut lines = table.lines();
+        assert_eq!(lines.next().unwrap(), "Data (14 bytes, big-endian)");
+        assert_eq!(
+            lines.last().unwrap(),
+            "| 2 | 12 | values | [f32;3] | V | Phase voltages |"
+        );
+    }
+}
@@ -1,8 +1,12 @@
+use std::collections::VecDeque;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use parking_lot_rt::Mutex;
-use rtsc::data_policy::DataDeliveryPolicy;
+use parking_lot_rt::{Condvar, Mutex};
+use rtsc::data_policy::{DataDeliveryPolicy, DeliveryPolicy};
+use tracing::warn;
 
+use crate::deadband::Deadband;
 use crate::pchannel::{self, Receiver, Sender};
 use crate::{Error, Result};
 
@@ -10,6 +14,8 @@
 
 type ConditionFunction<T> = Box<dyn Fn(&T) -> bool + Send + Sync>;
 
+type AckQueue = Arc<Mutex<VecDeque<Arc<BroadcastBarrier>>>>;
+
 pub mod prelude {
     pub use super::Hub;
     pub use crate::event_matches;
@@ -58,9 +64,21 @@ pub fn set_default_channel_capacity(self, capacity: usize) -> Self {
     ///
     /// Should not panic
     pub fn send(&self, message: T) {
+        self.send_delivered(message);
+    }
+    /// Sends a message to subscribed clients, ignores send errors. Returns the number of clients
+    /// the message has been delivered to
+    ///
+    /// # Panics
+    ///
+    /// Should not panic
+    pub fn send_delivered(&self, message: T) -> usize {
+        let mut delivered = 0;
         macro_rules! send {
             ($sub: expr, $msg: expr) => {
-                let _r = $sub.tx.send($msg);
+                if $sub.tx.send($msg).is_ok() {
+                    delivered += 1;
+                }
             };
         }
         // clones matching subscribers to keep the internal mutex unlocked and avoid deadlocks
@@ -73,7 +91,7 @@ macro_rules! send {
             .cloned()
             .collect();
         if targets.is_empty() {
-            return;
+            return 0;
         }
         for sub in targets.iter().take(targets.len() - 1) {
             if (sub.condition)(&message) {
@@ -84,6 +102,39 @@ macro_rules! send {
         if (sub.condition)(&message) {
             send!(sub, message);
         }
+        delivered
+    }
+    /// Sends a message to subscribed clients and keeps it as a retained one. The retained message
+    /// replaces the previous one with the same key (e.g. a topic or a message variant name) and is
+    /// delivered to clients immediately at their registration (if not disabled in
+    /// [`ClientOptions`])
+    pub fn send_retained(&self, key: &str, message: T) {
+        {
+            let mut inner = self.inner.lock();
+            if let Some((_, m)) = inner.retained.iter_mut().find(|(k, _)| &**k == key) {
+                *m = message.clone();
+            } else {
+                inner.retained.push((key.into(), message.clone()));
+            }
+        }
+        self.send(message);
+    }
+    /// Sends a message, created from an analog value, only if the value is significant for the
+    /// deadband filter (report-by-exception). Returns true if the message has been sent
+    pub fn send_by_exception<F>(&self, deadband: &mut Deadband, value: f64, message: F) -> bool
+    where
+        F: FnOnce(f64) -> T,
+    {
+        if let Some(value) = deadband.process(value) {
+            self.send(message(value));
+            true
+        } else {
+            false
+        }
+    }
+    /// Removes all retained messages
+    pub fn clear_retained(&self) {
+        self.inner.lock().retained.clear();
     }
     /// Sends a message to subscribed clients, calls an error handlers function in case of errors
     /// with some subsciber
@@ -129,6 +180,84 @@ macro_rules! send_checked {
         }
         Ok(())
     }
+    /// Sends a message to all members of the client group (regardless of their subscription
+    /// conditions) and blocks until every member acknowledges it with [`Client::ack()`] or the
+    /// timeout is reached (in this case [`Error::Timeout`] is returned and members which have not
+    /// acknowledged the message are logged). Returns immediately if the group has no members.
+    ///
+    /// Acknowledgements are matched in the order of delivery, so:
+    ///
+    /// * group members should not use channel priority ordering;
+    ///
+    /// * the message must have [`DeliveryPolicy::Always`] delivery policy and must not expire,
+    ///   as merged, replaced, skipped or dropped messages would never be acknowledged (messages
+    ///   with other policies are refused with [`Error::InvalidData`]);
+    ///
+    /// * a member must acknowledge every broadcast message it receives, including ones which
+    ///   have been received after the broadcast has timed out, otherwise its acknowledgements
+    ///   are shifted for all following broadcasts.
+    ///
+    /// Can be used e.g. for mode changes, where every worker must confirm the transition before
+    /// outputs are switched.
+    pub fn broadcast_sync(&self, group: &str, message: T, timeout: Duration) -> Result<()> {
+        if !matches!(message.delivery_policy(), DeliveryPolicy::Always) {
+            return Err(Error::invalid_data(
+                "group broadcasts require the Always delivery policy",
+            ));
+        }
+        let deadline = Instant::now() + timeout;
+        let targets: Vec<Arc<Subscription<T>>> = self
+            .inner
+            .lock()
+            .subscriptions
+            .iter()
+            .filter(|c| c.groups.iter().any(|g| &**g == group))
+            .cloned()
+            .collect();
+        if targets.is_empty() {
+            return Ok(());
+        }
+        let barrier = Arc::new(BroadcastBarrier {
+            pending: Mutex::new(targets.iter().map(|t| t.name.clone()).collect()),
+            acked: Condvar::new(),
+        });
+        for sub in &targets {
+            // the barrier is queued before sending, so an early ack is not lost
+            sub.acks.lock().push_back(barrier.clone());
+            if let Err(e) = sub.tx.send(message.clone()) {
+                // the message has not been delivered and is never acknowledged by the member.
+                // Members, which have already received it, acknowledge it as usual
+                sub.acks.lock().retain(|b| !Arc::ptr_eq(b, &barrier));
+                let err: Error = e.into();
+                return Err(Error::HubSend(err.into()));
+            }
+        }
+        let mut pending = barrier.pending.lock();
+        while !pending.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                // the barrier is kept in the member queues and is popped by late acks
+                warn!(
+                    group,
+                    members = ?pending,
+                    "broadcast not acknowledged by group members"
+                );
+                return Err(Error::Timeout);
+            }
+            barrier.acked.wait_for(&mut pending, deadline - now);
+        }
+        Ok(())
+    }
+    /// Returns names of the group members
+    pub fn group_members(&self, group: &str) -> Vec<String> {
+        self.inner
+            .lock()
+            .subscriptions
+            .iter()
+            .filter(|c| c.groups.iter().any(|g| &**g == group))
+            .map(|c| c.name.to_string())
+            .collect()
+    }
     /// Registers a sender-only client with no subscriptions
     ///
     /// If attempting to receive a message from such client, [`Error::ChannelClosed`] is returned
@@ -138,6 +267,7 @@ pub fn sender(&self) -> Client<T> {
             name: "".into(),
             hub: self.clone(),
             rx,
+            acks: <_>::default(),
         }
     }
     /// Registers a regular client. The condition function is used to check which kinds of
@@ -163,9 +293,17 @@ pub fn register_with_options(&self, client_options: ClientOptions<T>) -> Result<
         } else {
             pchannel::bounded(capacity)
         };
-        inner
-            .subscriptions
-            .push(client_options.into_subscription(tx).into());
+        let replay_retained = client_options.replay_retained;
+        let subscription = client_options.into_subscription(tx);
+        let acks = subscription.acks.clone();
+        if replay_retained {
+            for (_, message) in &inner.retained {
+                if (subscription.condition)(message) {
+                    let _r = subscription.tx.try_send(message.clone());
+                }
+            }
+        }
+        inner.subscriptions.push(subscription.into());
         inner
             .subscriptions
             .sort_by(|a, b| a.priority.cmp(&b.priority));
@@ -173,6 +311,7 @@ pub fn register_with_options(&self, client_options: ClientOptions<T>) -> Result<
             name,
             hub: self.clone(),
             rx,
+            acks,
         })
     }
     fn unregister(&self, name: &str) {
@@ -186,6 +325,8 @@ fn unregister(&self, name: &str) {
 struct HubInner<T: DataDeliveryPolicy + Clone> {
     default_channel_capacity: usize,
     subscriptions: Vec<Arc<Subscription<T>>>,
+    // retained messages with their keys
+    retained: Vec<(Arc<str>, T)>,
 }
 
 impl<T> Default for HubInner<T>
@@ -196,6 +337,7 @@ fn default() -> Self {
         Self {
             default_channel_capacity: DEFAULT_CHANNEL_CAPACITY,
             subscriptions: <_>::default(),
+            retained: <_>::default(),
         }
     }
 }
@@ -242,6 +384,7 @@ pub struct Client<T: DataDeliveryPolicy + Clone> {
     name: Arc<str>,
     hub: Hub<T>,
     rx: Receiver<T>,
+    acks: AckQueue,
 }
 
 impl<T> Iterator for Client<T>
@@ -259,6 +402,11 @@ impl<T: DataDeliveryPolicy + Clone> Client<T> {
     pub fn send(&self, message: T) {
         self.hub.send(message);
     }
+    /// Sends a message to hub-subscribed clients and keeps it as a retained one, ignores send
+    /// errors
+    pub fn send_retained(&self, key: &str, message: T) {
+        self.hub.send_retained(key, message);
+    }
     /// Sends a message to subscribed clients, calls an error handlers function in case of errors
     /// with some subsciber
     ///
@@ -277,11 +425,24 @@ pub fn recv(&self) -> Result<T> {
     pub fn try_recv(&self) -> Result<T> {
         self.rx.try_recv().map_err(Into::into)
     }
+    /// Acknowledges the oldest not acknowledged group broadcast message (see
+    /// [`Hub::broadcast_sync()`]). Should be called after the message has been processed, for
+    /// every received broadcast message (including ones with timed-out broadcasts). Returns false
+    /// if there are no broadcasts to acknowledge
+    pub fn ack(&self) -> bool {
+        let Some(barrier) = self.acks.lock().pop_front() else {
+            return false;
+        };
+        barrier.ack(&self.name);
+        true
+    }
 }
 
 impl<T: DataDeliveryPolicy + Clone> Drop for Client<T> {
     fn drop(&mut self) {
         self.hub.unregister(&self.name);
+        // unregistered clients do not block broadcasts
+        while self.ack() {}
     }
 }
 
@@ -290,6 +451,8 @@ pub struct ClientOptions<T: DataDeliveryPolicy + Clone> {
     priority: usize,
     capacity: Option<usize>,
     ordering: bool,
+    replay_retained: bool,
+    groups: Vec<Arc<str>>,
     condition: ConditionFunction<T>,
 }
 
@@ -303,6 +466,8 @@ pub fn new<F>(name: &str, condition: F) -> Self
             priority: DEFAULT_PRIORITY,
             capacity: None,
             ordering: false,
+            replay_retained: true,
+            groups: Vec::new(),
             condition: Box::new(condition),
         }
     }
@@ -316,16 +481,29 @@ pub fn priority(mut self, priority: usize) -> Self {
         self.priority = priority;
         self
     }
+    /// Enables/disables delivery of retained messages at the client registration (enabled by
+    /// default)
+    pub fn replay_retained(mut self, replay_retained: bool) -> Self {
+        self.replay_retained = replay_retained;
+        self
+    }
     /// Overrides the default hub client channel capacity
     pub fn capacity(mut self, capacity: usize) -> Self {
         self.capacity = Some(capacity);
         self
     }
+    /// Adds the client to a group (see [`Hub::broadcast_sync()`]), can be called multiple times
+    pub fn group(mut self, group: &str) -> Self {
+        self.groups.push(group.into());
+        self
+    }
     fn into_subscription(self, tx: Sender<T>) -> Subscription<T> {
         Subscription {
             name: self.name,
             tx,
             priority: self.priority,
+            groups: self.groups,
+            acks: <_>::default(),
             condition: self.condition,
         }
     }
@@ -357,25 +535,54 @@ struct Subscription<T: DataDeliveryPolicy + Clone> {
     name: Arc<str>,
     tx: Sender<T>,
     priority: usize,
+    groups: Vec<Arc<str>>,
+    acks: AckQueue,
     condition: ConditionFunction<T>,
 }
 
+struct BroadcastBarrier {
+    // members which have not acknowledged the message yet
+    pending: Mutex<Vec<Arc<str>>>,
+    acked: Condvar,
+}
+
+impl BroadcastBarrier {
+    fn ack(&self, name: &str) {
+        let mut pending = self.pending.lock();
+        pending.retain(|n| &**n != name);
+        if pending.is_empty() {
+            self.acked.notify_all();
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use rtsc::data_policy::DataDeliveryPolicy;
+    use rtsc::data_policy::{DataDeliveryPolicy, DeliveryPolicy};
+
+    use std::thread;
+    use std::time::Duration;
 
-    use crate::event_matches;
+    use crate::{event_matches, Error};
 
-    use super::Hub;
+    use super::{ClientOptions, Hub};
 
     #[derive(Clone, Debug)]
     enum Message {
         Temperature(f64),
         Humidity(f64),
+        Setpoint(f64),
         Test,
     }
 
-    impl DataDeliveryPolicy for Message {}
+    impl DataDeliveryPolicy for Message {
+        fn delivery_policy(&self) -> DeliveryPolicy {
+            match self {
+                Message::Setpoint(_) => DeliveryPolicy::Single,
+                _ => DeliveryPolicy::Always,
+            }
+        }
+    }
 
     #[test]
     fn test_hub() {
@@ -399,4 +606,100 @@ fn test_hub() {
         insta::assert_snapshot!(messages.len(), @"6");
         insta::assert_debug_snapshot!(messages);
     }
+
+    #[test]
+    fn test_hub_retained() {
+        let hub = Hub::<Message>::new().set_default_channel_capacity(20);
+        let sender = hub.sender();
+        sender.send_retained("temperature", Message::Temperature(1.0));
+        sender.send_retained("humidity", Message::Humidity(3.0));
+        sender.send_retained("temperature", Message::Temperature(2.0));
+        sender.send(Message::Humidity(4.0));
+        let recv = hub
+            .register("test_recv", event_matches!(Message::Temperature(_)))
+            .unwrap();
+        let mut messages = Vec::with_capacity(20);
+        while let Ok(msg) = recv.try_recv() {
+            messages.push(msg);
+        }
+        assert_eq!(format!("{:?}", messages), "[Temperature(2.0)]");
+        drop(recv);
+        // a late subscriber gets the retained messages of all keys
+        let recv = hub
+            .register(
+                "test_recv",
+                event_matches!(Message::Temperature(_) | Message::Humidity(_)),
+            )
+            .unwrap();
+        let mut messages = Vec::with_capacity(20);
+        while let Ok(msg) = recv.try_recv() {
+            messages.push(msg);
+        }
+        assert_eq!(
+            format!("{:?}", messages),
+            "[Temperature(2.0), Humidity(3.0)]"
+        );
+        drop(recv);
+        let recv = hub
+            .register_with_options(
+                ClientOptions::new("test_recv", event_matches!(Message::Temperature(_)))
+                    .replay_retained(false),
+            )
+            .unwrap();
+        assert!(recv.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_hub_broadcast_sync() {
+        let hub = Hub::<Message>::new();
+        let workers: Vec<_> = ["w1", "w2"]
+            .into_iter()
+            .map(|name| {
+                hub.register_with_options(
+                    ClientOptions::new(name, event_matches!(Message::Temperature(_))).group("mode"),
+                )
+                .unwrap()
+            })
+            .collect();
+        assert_eq!(hub.group_members("mode"), ["w1", "w2"]);
+        let sender = hub.clone();
+        let t = thread::spawn(move || {
+            sender.broadcast_sync("mode", Message::Test, Duration::from_secs(5))
+        });
+        for worker in &workers {
+            assert!(matches!(worker.recv().unwrap(), Message::Test));
+            assert!(worker.ack());
+        }
+        t.join().unwrap().unwrap();
+        assert!(!workers[0].ack());
+        // w2 does not acknowledge
+        let sender = hub.clone();
+        let t = thread::spawn(move || {
+            sender.broadcast_sync("mode", Message::Test, Duration::from_millis(100))
+        });
+        workers[0].recv().unwrap();
+        workers[0].ack();
+        assert_eq!(t.join().unwrap().unwrap_err(), Error::Timeout);
+        // the next broadcast succeeds as w2 acknowledges the timed-out one as well
+        let sender = hub.clone();
+        let t = thread::spawn(move || {
+            sender.broadcast_sync("mode", Message::Test, Duration::from_secs(5))
+        });
+        workers[0].recv().unwrap();
+        assert!(workers[0].ack());
+        for _ in 0..2 {
+            assert!(matches!(workers[1].recv().unwrap(), Message::Test));
+            assert!(workers[1].ack());
+        }
+        t.join().unwrap().unwrap();
+        assert!(!workers[1].ack());
+        // merged messages would never be acknowledged
+        assert!(matches!(
+            hub.broadcast_sync("mode", Message::Setpoint(1.0), Duration::from_millis(100)),
+            Err(Error::InvalidData(_))
+        ));
+        assert!(hub
+            .broadcast_sync("other", Message::Test, Duration::from_millis(100))
+            .is_ok());
+    }
 }
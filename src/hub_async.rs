@@ -76,6 +76,25 @@ macro_rules! send {
             send!(sub, message);
         }
     }
+    /// Sends a message to subscribed clients and keeps it as a retained one. The retained message
+    /// replaces the previous one with the same key (e.g. a topic or a message variant name) and is
+    /// delivered to clients immediately at their registration (if not disabled in
+    /// [`ClientOptions`])
+    pub async fn send_retained(&self, key: &str, message: T) {
+        {
+            let mut inner = self.inner.lock();
+            if let Some((_, m)) = inner.retained.iter_mut().find(|(k, _)| &**k == key) {
+                *m = message.clone();
+            } else {
+                inner.retained.push((key.into(), message.clone()));
+            }
+        }
+        self.send(message).await;
+    }
+    /// Removes all retained messages
+    pub fn clear_retained(&self) {
+        self.inner.lock().retained.clear();
+    }
     /// Sends a message to subscribed clients, calls an error handlers function in case of errors
     /// with some subsciber
     ///
@@ -129,6 +148,7 @@ pub fn sender(&self) -> Client<T> {
             name: "".into(),
             hub: self.clone(),
             rx,
+            high_priority: false,
         }
     }
     /// Registers a regular client. The condition function is used to check which kinds of
@@ -154,9 +174,17 @@ pub fn register_with_options(&self, client_options: ClientOptions<T>) -> Result<
         } else {
             pchannel_async::bounded(capacity)
         };
-        inner
-            .subscriptions
-            .push(client_options.into_subscription(tx).into());
+        let replay_retained = client_options.replay_retained;
+        let high_priority = client_options.high_priority;
+        let subscription = client_options.into_subscription(tx);
+        if replay_retained {
+            for (_, message) in &inner.retained {
+                if (subscription.condition)(message) {
+                    let _r = subscription.tx.try_send(message.clone());
+                }
+            }
+        }
+        inner.subscriptions.push(subscription.into());
         inner
             .subscriptions
             .sort_by(|a, b| a.priority.cmp(&b.priority));
@@ -164,6 +192,7 @@ pub fn register_with_options(&self, client_options: ClientOptions<T>) -> Result<
             name,
             hub: self.clone(),
             rx,
+            high_priority,
         })
     }
     fn unregister(&self, name: &str) {
@@ -177,6 +206,8 @@ fn unregister(&self, name: &str) {
 struct HubInner<T: DataDeliveryPolicy + Clone> {
     default_channel_capacity: usize,
     subscriptions: Vec<Arc<Subscription<T>>>,
+    // retained messages with their keys
+    retained: Vec<(Arc<str>, T)>,
 }
 
 impl<T> Default for HubInner<T>
@@ -187,6 +218,7 @@ fn default() -> Self {
         Self {
             default_channel_capacity: DEFAULT_CHANNEL_CAPACITY,
             subscriptions: <_>::default(),
+            retained: <_>::default(),
         }
     }
 }
@@ -195,13 +227,28 @@ pub struct Client<T: DataDeliveryPolicy + Clone> {
     name: Arc<str>,
     hub: Hub<T>,
     rx: Receiver<T>,
+    high_priority: bool,
 }
 
 impl<T: DataDeliveryPolicy + Clone> Client<T> {
+    /// Client name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Returns true if the client is marked as high-priority (see
+    /// [`ClientOptions::high_priority()`])
+    pub fn is_high_priority(&self) -> bool {
+        self.high_priority
+    }
     /// Sends a message to hub-subscribed clients, ignores send errors
     pub fn send(&self, message: T) -> impl Future<Output = ()> + '_ {
         self.hub.send(message)
     }
+    /// Sends a message to hub-subscribed clients and keeps it as a retained one, ignores send
+    /// errors
+    pub fn send_retained<'a>(&'a self, key: &'a str, message: T) -> impl Future<Output = ()> + 'a {
+        self.hub.send_retained(key, message)
+    }
     /// Sends a message to subscribed clients, calls an error handlers function in case of errors
     /// with some subsciber
     ///
@@ -237,6 +284,8 @@ pub struct ClientOptions<T: DataDeliveryPolicy + Clone> {
     priority: usize,
     capacity: Option<usize>,
     ordering: bool,
+    replay_retained: bool,
+    high_priority: bool,
     condition: ConditionFunction<T>,
 }
 
@@ -250,6 +299,8 @@ pub fn new<F>(name: &str, condition: F) -> Self
             priority: DEFAULT_PRIORITY,
             capacity: None,
             ordering: false,
+            replay_retained: true,
+            high_priority: false,
             condition: Box::new(condition),
         }
     }
@@ -263,11 +314,23 @@ pub fn priority(mut self, priority: usize) -> Self {
         self.priority = priority;
         self
     }
+    /// Enables/disables delivery of retained messages at the client registration (enabled by
+    /// default)
+    pub fn replay_retained(mut self, replay_retained: bool) -> Self {
+        self.replay_retained = replay_retained;
+        self
+    }
     /// Overrides the default hub client channel capacity
     pub fn capacity(mut self, capacity: usize) -> Self {
         self.capacity = Some(capacity);
         self
     }
+    /// Marks the client as high-priority (latency-sensitive). Tasks of such clients are run by
+    /// the dedicated runtime of `crate::hub_executor::HubExecutor` (the default is false)
+    pub fn high_priority(mut self, high_priority: bool) -> Self {
+        self.high_priority = high_priority;
+        self
+    }
     fn into_subscription(self, tx: Sender<T>) -> Subscription<T> {
         Subscription {
             name: self.name,
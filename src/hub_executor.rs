@@ -0,0 +1,192 @@
+//!
+//! Priority-aware executor for async hub consumers.
+//!
+//! Tasks of high-priority clients (see [`ClientOptions::high_priority()`]) are run by a dedicated
+//! single-threaded tokio runtime. The runtime thread is started with the given real-time
+//! parameters, usually pinned to housekeeping cores, so latency-sensitive consumers (e.g. EAPI
+//! pushes) stay responsive when the main runtime is busy with bulk processing. Tasks of other
+//! clients are run by the main runtime.
+//!
+//! ```rust,no_run
+//! use roboplc::hub_async::{ClientOptions, Hub};
+//! use roboplc::hub_executor::HubExecutor;
+//! use roboplc::thread_rt::RTParams;
+//! use roboplc::DataDeliveryPolicy;
+//!
+//! #[derive(Clone)]
+//! enum Message {
+//!     Push(u32),
+//! }
+//!
+//! impl DataDeliveryPolicy for Message {}
+//!
+//! # async fn run() {
+//! let hub: Hub<Message> = Hub::new();
+//! let executor = HubExecutor::new(RTParams::new().set_cpu_ids(&[0])).unwrap();
+//! let client = hub
+//!     .register_with_options(ClientOptions::new("eapi", |_| true).high_priority(true))
+//!     .unwrap();
+//! executor
+//!     .spawn_client(client, |client| async move {
+//!         while let Ok(_message) = client.recv().await {
+//!             // push
+//!         }
+//!     })
+//!     .unwrap();
+//! # }
+//! ```
+//!
+//! [`ClientOptions::high_priority()`]: crate::hub_async::ClientOptions::high_priority
+use std::future::Future;
+use std::sync::{mpsc, Arc};
+
+use tokio::runtime::Handle;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tracing::trace;
+
+use crate::hub_async::Client;
+use crate::thread_rt::{self, RTParams, Task};
+use crate::{DataDeliveryPolicy, Error, Result};
+
+/// Executor which runs high-priority hub client tasks on a dedicated runtime
+pub struct HubExecutor {
+    high: Handle,
+    normal: Option<Handle>,
+    shutdown: Arc<Notify>,
+    task: Option<Task<()>>,
+}
+
+impl HubExecutor {
+    /// Starts the dedicated runtime thread with the given real-time parameters
+    pub fn new(rt_params: RTParams) -> Result<Self> {
+        let shutdown = Arc::new(Notify::new());
+        let shutdown_c = shutdown.clone();
+        let (tx, rx) = mpsc::sync_channel(1);
+        let task = thread_rt::Builder::new()
+            .name("RoboPLCHubExec")
+            .rt_params(rt_params)
+            .spawn(move || {
+                let runtime = match tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                {
+                    Ok(v) => v,
+                    Err(e) => {
+                        let _ = tx.send(Err(Error::io(e)));
+                        return;
+                    }
+                };
+                let _ = tx.send(Ok(runtime.handle().clone()));
+                runtime.block_on(shutdown_c.notified());
+            })?;
+        let high = rx.recv().map_err(Error::failed)??;
+        Ok(Self {
+            high,
+            normal: None,
+            shutdown,
+            task: Some(task),
+        })
+    }
+    /// Sets the runtime for normal tasks (the default is the current runtime at the moment of
+    /// spawning)
+    pub fn normal_runtime(mut self, handle: Handle) -> Self {
+        self.normal = Some(handle);
+        self
+    }
+    /// Spawns a high-priority task
+    pub fn spawn_high<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.high.spawn(future)
+    }
+    /// Spawns a normal task. Must be called from the runtime context if the runtime for normal
+    /// tasks is not set
+    pub fn spawn<F>(&self, future: F) -> Result<JoinHandle<F::Output>>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let handle = if let Some(ref handle) = self.normal {
+            handle.clone()
+        } else {
+            Handle::try_current().map_err(Error::failed)?
+        };
+        Ok(handle.spawn(future))
+    }
+    /// Spawns a hub client consumer task. The task is run by the dedicated runtime if the client
+    /// is high-priority, by the normal runtime otherwise
+    pub fn spawn_client<T, F, Fut>(&self, client: Client<T>, f: F) -> Result<JoinHandle<()>>
+    where
+        T: DataDeliveryPolicy + Clone + Send + 'static,
+        F: FnOnce(Client<T>) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        trace!(
+            client = client.name(),
+            high_priority = client.is_high_priority(),
+            "spawning hub client task"
+        );
+        if client.is_high_priority() {
+            Ok(self.spawn_high(f(client)))
+        } else {
+            self.spawn(f(client))
+        }
+    }
+}
+
+impl Drop for HubExecutor {
+    fn drop(&mut self) {
+        // tasks, which are still running, are cancelled
+        self.shutdown.notify_one();
+        if let Some(task) = self.task.take() {
+            let _ = task.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::HubExecutor;
+    use crate::hub_async::{ClientOptions, Hub};
+    use crate::thread_rt::RTParams;
+    use crate::DataDeliveryPolicy;
+
+    #[derive(Clone)]
+    struct Message;
+
+    impl DataDeliveryPolicy for Message {}
+
+    #[tokio::test]
+    async fn test_executor() {
+        let hub: Hub<Message> = Hub::new();
+        let executor = HubExecutor::new(RTParams::new()).unwrap();
+        let mut tasks = Vec::new();
+        for (name, high_priority) in [("eapi", true), ("bulk", false)] {
+            let client = hub
+                .register_with_options(
+                    ClientOptions::new(name, |_| true).high_priority(high_priority),
+                )
+                .unwrap();
+            tasks.push(
+                executor
+                    .spawn_client(client, |client| async move {
+                        client.recv().await.unwrap();
+                        assert_eq!(
+                            thread::current().name() == Some("RoboPLCHubExec"),
+                            client.is_high_priority()
+                        );
+                    })
+                    .unwrap(),
+            );
+        }
+        hub.send(Message).await;
+        for task in tasks {
+            task.await.unwrap();
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//!
+//! Persistence of undelivered critical hub messages (e.g. operator commands).
+//!
+//! Critical messages are sent via [`CriticalQueue::send()`]. If no subscribed client is present
+//! (e.g. workers are not started yet or are already stopped during a live-update restart), the
+//! message is kept in the queue and written into the [`KvStore`], so it is not lost silently. The
+//! store is saved with its own flush policy and by the shutdown flush hooks.
+//!
+//! On the next start, [`CriticalQueue::restore()`] loads the saved messages, messages older than
+//! the age limit are dropped. The pending messages are delivered by
+//! [`CriticalQueue::deliver_pending()`] (should be called after the workers are started) and
+//! before every new critical message, so the order is kept.
+//!
+//! ```rust,no_run
+//! use roboplc::hub::prelude::*;
+//! use roboplc::hub_persistence::CriticalQueue;
+//! use roboplc::kv::KvStore;
+//! use serde::{Deserialize, Serialize};
+//! use std::time::Duration;
+//!
+//! #[derive(Clone, Serialize, Deserialize)]
+//! enum Message {
+//!     Stop,
+//! }
+//!
+//! impl DataDeliveryPolicy for Message {}
+//!
+//! let hub: Hub<Message> = Hub::new();
+//! let store = KvStore::open("/var/roboplc/data/kv.dat").unwrap();
+//! let commands = CriticalQueue::new(&hub, store, "hub.commands")
+//!     .max_age(Duration::from_secs(30));
+//! commands.restore().unwrap();
+//! // start workers
+//! commands.deliver_pending();
+//! commands.send(Message::Stop);
+//! ```
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot_rt::Mutex;
+use rtsc::data_policy::DataDeliveryPolicy;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::hub::Hub;
+use crate::kv::KvStore;
+use crate::Result;
+
+/// The default age limit for pending messages
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Pending<T> {
+    // unix time, milliseconds
+    time: u64,
+    message: T,
+}
+
+struct Inner<T: DataDeliveryPolicy + Clone> {
+    hub: Hub<T>,
+    store: KvStore,
+    key: String,
+    max_age: Duration,
+    pending: Mutex<Vec<Pending<T>>>,
+}
+
+/// Persistent queue of critical messages. Can be cloned and shared between workers with no
+/// limitations
+pub struct CriticalQueue<T>
+where
+    T: DataDeliveryPolicy + Clone,
+{
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Clone for CriticalQueue<T>
+where
+    T: DataDeliveryPolicy + Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> CriticalQueue<T>
+where
+    T: DataDeliveryPolicy + Clone + Serialize + DeserializeOwned,
+{
+    /// Creates a new queue for the hub. Pending messages are stored in the key-value store under
+    /// the specified key
+    pub fn new(hub: &Hub<T>, store: KvStore, key: &str) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                hub: hub.clone(),
+                store,
+                key: key.to_owned(),
+                max_age: DEFAULT_MAX_AGE,
+                pending: <_>::default(),
+            }),
+        }
+    }
+    /// Sets the age limit for pending messages (the default is 60 seconds). Must be called before
+    /// the queue is cloned
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the queue is already cloned
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        Arc::get_mut(&mut self.inner)
+            .expect("the queue is already cloned")
+            .max_age = max_age;
+        self
+    }
+    /// Loads pending messages saved by the previous process instance, messages older than the
+    /// age limit are dropped. Returns the number of restored messages
+    pub fn restore(&self) -> Result<usize> {
+        let saved: Vec<Pending<T>> = self.inner.store.get(&self.inner.key)?.unwrap_or_default();
+        let mut pending = self.inner.pending.lock();
+        let now = now_ms();
+        let total = saved.len();
+        let mut restored = 0;
+        for p in saved {
+            if !self.inner.is_expired(&p, now) {
+                pending.push(p);
+                restored += 1;
+            }
+        }
+        if restored < total {
+            warn!(
+                key = %self.inner.key,
+                expired = total - restored,
+                "expired critical messages dropped"
+            );
+        }
+        self.inner.save(&pending);
+        Ok(restored)
+    }
+    /// Sends a critical message. Pending messages are delivered first. If no client is
+    /// subscribed to the message, it is kept in the queue and saved. Returns true if the message
+    /// has been delivered
+    pub fn send(&self, message: T) -> bool {
+        let mut pending = self.inner.pending.lock();
+        self.inner.deliver(&mut pending);
+        if pending.is_empty() && self.inner.hub.send_delivered(message.clone()) > 0 {
+            return true;
+        }
+        warn!(key = %self.inner.key, "critical message not delivered, queued");
+        pending.push(Pending {
+            time: now_ms(),
+            message,
+        });
+        self.inner.save(&pending);
+        false
+    }
+    /// Tries to deliver pending messages, expired ones are dropped. Returns the number of
+    /// messages left in the queue
+    pub fn deliver_pending(&self) -> usize {
+        let mut pending = self.inner.pending.lock();
+        self.inner.deliver(&mut pending);
+        pending.len()
+    }
+    /// Returns the number of pending messages
+    pub fn pending(&self) -> usize {
+        self.inner.pending.lock().len()
+    }
+}
+
+impl<T> Inner<T>
+where
+    T: DataDeliveryPolicy + Clone + Serialize,
+{
+    fn is_expired(&self, pending: &Pending<T>, now: u64) -> bool {
+        u128::from(now.saturating_sub(pending.time)) > self.max_age.as_millis()
+    }
+    // delivers pending messages in order, stops at the first one which has no subscribers
+    fn deliver(&self, pending: &mut Vec<Pending<T>>) {
+        if pending.is_empty() {
+            return;
+        }
+        let now = now_ms();
+        let mut processed = 0;
+        let mut expired = 0;
+        for p in pending.iter() {
+            if self.is_expired(p, now) {
+                expired += 1;
+            } else if self.hub.send_delivered(p.message.clone()) == 0 {
+                break;
+            }
+            processed += 1;
+        }
+        if expired > 0 {
+            warn!(key = %self.key, expired, "expired critical messages dropped");
+        }
+        if processed > 0 {
+            pending.drain(..processed);
+            self.save(pending);
+        }
+    }
+    fn save(&self, pending: &[Pending<T>]) {
+        let result = if pending.is_empty() {
+            self.store.remove(&self.key);
+            Ok(())
+        } else {
+            self.store.set(&self.key, &pending)
+        };
+        if let Err(error) = result {
+            error!(%error, key = %self.key, "unable to save critical messages");
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use rtsc::data_policy::DataDeliveryPolicy;
+    use serde::{Deserialize, Serialize};
+
+    use super::{now_ms, CriticalQueue, Pending};
+    use crate::hub::Hub;
+    use crate::kv::KvStore;
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    enum Message {
+        Command(u32),
+    }
+
+    impl DataDeliveryPolicy for Message {}
+
+    #[test]
+    fn test_critical_queue() {
+        let dir = std::env::temp_dir().join(format!("roboplc-test-hubp-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("kv.dat");
+        {
+            let hub: Hub<Message> = Hub::new();
+            let store = KvStore::open(&path).unwrap();
+            let queue = CriticalQueue::new(&hub, store.clone(), "commands");
+            assert!(!queue.send(Message::Command(1)));
+            assert!(!queue.send(Message::Command(2)));
+            assert_eq!(queue.pending(), 2);
+            // a message left by an older instance
+            let mut saved = vec![Pending {
+                time: now_ms() - 120_000,
+                message: Message::Command(0),
+            }];
+            saved.extend(
+                store
+                    .get::<Vec<Pending<Message>>>("commands")
+                    .unwrap()
+                    .unwrap(),
+            );
+            store.set("commands", &saved).unwrap();
+            store.flush().unwrap();
+        }
+        let hub: Hub<Message> = Hub::new();
+        let store = KvStore::open(&path).unwrap();
+        let queue =
+            CriticalQueue::new(&hub, store.clone(), "commands").max_age(Duration::from_secs(60));
+        assert_eq!(queue.restore().unwrap(), 2);
+        assert_eq!(queue.deliver_pending(), 2);
+        let client = hub.register("worker", |_| true).unwrap();
+        assert!(queue.send(Message::Command(3)));
+        assert_eq!(queue.pending(), 0);
+        let received: Vec<Message> = std::iter::from_fn(|| client.try_recv().ok()).collect();
+        assert_eq!(
+            received,
+            [
+                Message::Command(1),
+                Message::Command(2),
+                Message::Command(3)
+            ]
+        );
+        store.flush().unwrap();
+        assert!(!KvStore::open(&path).unwrap().contains_key("commands"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+use serde::Serialize;
+
+use crate::{Error, Result};
+
+/// COSEM data value (A-XDR encoded)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum DlmsValue {
+    Null,
+    Array(Vec<DlmsValue>),
+    Structure(Vec<DlmsValue>),
+    Bool(bool),
+    BitString(Vec<u8>),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    Enum(u8),
+    F32(f32),
+    F64(f64),
+    OctetString(Vec<u8>),
+    VisibleString(String),
+    Bcd(u8),
+    /// Raw date-time (12 bytes), date (5 bytes) or time (4 bytes)
+    DateTime(Vec<u8>),
+}
+
+impl DlmsValue {
+    /// Decodes a value
+    pub fn decode(buf: &[u8]) -> Result<Self> {
+        let (value, _) = decode_value(buf)?;
+        Ok(value)
+    }
+    /// Returns a numeric value as f64
+    #[allow(clippy::cast_precision_loss)]
+    pub fn as_f64(&self) -> Option<f64> {
+        Some(match *self {
+            DlmsValue::I8(v) => f64::from(v),
+            DlmsValue::I16(v) => f64::from(v),
+            DlmsValue::I32(v) => f64::from(v),
+            DlmsValue::I64(v) => v as f64,
+            DlmsValue::U8(v) | DlmsValue::Enum(v) | DlmsValue::Bcd(v) => f64::from(v),
+            DlmsValue::U16(v) => f64::from(v),
+            DlmsValue::U32(v) => f64::from(v),
+            DlmsValue::U64(v) => v as f64,
+            DlmsValue::F32(v) => f64::from(v),
+            DlmsValue::F64(v) => v,
+            _ => return None,
+        })
+    }
+    /// Returns (scaler, unit) for scaler_unit register attributes
+    pub fn as_scaler_unit(&self) -> Option<(i8, u8)> {
+        if let DlmsValue::Structure(items) = self {
+            if let [DlmsValue::I8(scaler), DlmsValue::Enum(unit)] = items.as_slice() {
+                return Some((*scaler, *unit));
+            }
+        }
+        None
+    }
+}
+
+/// Decodes a BER-style length, returns the length and the number of bytes consumed
+pub(super) fn decode_length(buf: &[u8]) -> Result<(usize, usize)> {
+    let first = *buf
+        .first()
+        .ok_or_else(|| Error::invalid_data("length missing"))?;
+    if first & 0x80 == 0 {
+        return Ok((usize::from(first), 1));
+    }
+    let n = usize::from(first & 0x7f);
+    if n == 0 || n > 4 {
+        return Err(Error::invalid_data("unsupported length encoding"));
+    }
+    let bytes = buf
+        .get(1..=n)
+        .ok_or_else(|| Error::invalid_data("length truncated"))?;
+    let len = bytes
+        .iter()
+        .fold(0usize, |acc, &b| (acc << 8) | usize::from(b));
+    Ok((len, n + 1))
+}
+
+fn take<const N: usize>(buf: &[u8]) -> Result<[u8; N]> {
+    buf.get(..N)
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| Error::invalid_data("data truncated"))
+}
+
+fn take_vec(buf: &[u8], len: usize) -> Result<&[u8]> {
+    buf.get(..len)
+        .ok_or_else(|| Error::invalid_data("data truncated"))
+}
+
+// returns the value and the number of bytes consumed
+fn decode_value(buf: &[u8]) -> Result<(DlmsValue, usize)> {
+    let tag = *buf
+        .first()
+        .ok_or_else(|| Error::invalid_data("data missing"))?;
+    let data = &buf[1..];
+    let (value, len) = match tag {
+        0 => (DlmsValue::Null, 0),
+        1 | 2 => {
+            let (count, mut pos) = decode_length(data)?;
+            let mut items = Vec::with_capacity(count.min(256));
+            for _ in 0..count {
+                let (item, n) = decode_value(&data[pos..])?;
+                items.push(item);
+                pos += n;
+            }
+            if tag == 1 {
+                (DlmsValue::Array(items), pos)
+            } else {
+                (DlmsValue::Structure(items), pos)
+            }
+        }
+        3 => (DlmsValue::Bool(take::<1>(data)?[0] != 0), 1),
+        4 => {
+            let (bits, pos) = decode_length(data)?;
+            let bytes = take_vec(&data[pos..], (bits + 7) / 8)?;
+            (DlmsValue::BitString(bytes.to_vec()), pos + bytes.len())
+        }
+        5 => (DlmsValue::I32(i32::from_be_bytes(take(data)?)), 4),
+        6 => (DlmsValue::U32(u32::from_be_bytes(take(data)?)), 4),
+        9 | 10 | 12 => {
+            let (len, pos) = decode_length(data)?;
+            let bytes = take_vec(&data[pos..], len)?;
+            let value = if tag == 9 {
+                DlmsValue::OctetString(bytes.to_vec())
+            } else {
+                DlmsValue::VisibleString(String::from_utf8_lossy(bytes).into_owned())
+            };
+            (value, pos + len)
+        }
+        13 => (DlmsValue::Bcd(take::<1>(data)?[0]), 1),
+        15 => (DlmsValue::I8(i8::from_be_bytes(take(data)?)), 1),
+        16 => (DlmsValue::I16(i16::from_be_bytes(take(data)?)), 2),
+        17 => (DlmsValue::U8(take::<1>(data)?[0]), 1),
+        18 => (DlmsValue::U16(u16::from_be_bytes(take(data)?)), 2),
+        20 => (DlmsValue::I64(i64::from_be_bytes(take(data)?)), 8),
+        21 => (DlmsValue::U64(u64::from_be_bytes(take(data)?)), 8),
+        22 => (DlmsValue::Enum(take::<1>(data)?[0]), 1),
+        23 => (DlmsValue::F32(f32::from_be_bytes(take(data)?)), 4),
+        24 => (DlmsValue::F64(f64::from_be_bytes(take(data)?)), 8),
+        25 | 26 | 27 => {
+            let len = match tag {
+                25 => 12,
+                26 => 5,
+                _ => 4,
+            };
+            (DlmsValue::DateTime(take_vec(data, len)?.to_vec()), len)
+        }
+        _ => {
+            return Err(Error::invalid_data(format!(
+                "unsupported data type: {}",
+                tag
+            )))
+        }
+    };
+    Ok((value, len + 1))
+}
@@ -0,0 +1,177 @@
+use crate::comm::Client;
+use crate::{Error, Result};
+
+pub(super) const FLAG: u8 = 0x7e;
+
+pub(super) const SNRM: u8 = 0x93;
+pub(super) const UA: u8 = 0x73;
+pub(super) const DISC: u8 = 0x53;
+pub(super) const DM: u8 = 0x1f;
+
+// frame format type 3
+const FORMAT_TYPE: u8 = 0xa0;
+const SEGMENTED: u8 = 0x08;
+
+pub(super) const LLC_REQUEST: [u8; 3] = [0xe6, 0xe6, 0x00];
+const LLC_RESPONSE: [u8; 3] = [0xe6, 0xe7, 0x00];
+
+/// HDLC addressing
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Addresses {
+    pub(super) client: u8,
+    pub(super) server_upper: u16,
+    pub(super) server_lower: u16,
+}
+
+impl Addresses {
+    #[allow(clippy::cast_possible_truncation)]
+    fn encode_server(&self, buf: &mut Vec<u8>) {
+        let (upper, lower) = (self.server_upper, self.server_lower);
+        if lower == 0 {
+            buf.push(((upper as u8) << 1) | 1);
+        } else if upper < 0x80 && lower < 0x80 {
+            buf.push((upper as u8) << 1);
+            buf.push(((lower as u8) << 1) | 1);
+        } else {
+            buf.push(((upper >> 7) as u8) << 1);
+            buf.push(((upper & 0x7f) as u8) << 1);
+            buf.push(((lower >> 7) as u8) << 1);
+            buf.push((((lower & 0x7f) as u8) << 1) | 1);
+        }
+    }
+}
+
+/// A received frame
+#[derive(Debug)]
+pub(super) struct Frame {
+    pub(super) control: u8,
+    pub(super) segmented: bool,
+    // LLC header is stripped
+    pub(super) info: Vec<u8>,
+}
+
+impl Frame {
+    pub(super) fn is_information(&self) -> bool {
+        self.control & 0x01 == 0
+    }
+}
+
+/// Builds a frame (the client is the source, the server is the destination)
+#[allow(clippy::cast_possible_truncation)]
+pub(super) fn build_frame(addresses: &Addresses, control: u8, info: &[u8]) -> Vec<u8> {
+    let mut frame = vec![FLAG, 0, 0];
+    addresses.encode_server(&mut frame);
+    frame.push((addresses.client << 1) | 1);
+    frame.push(control);
+    // format + addresses + control + HCS (+ info + FCS)
+    let mut len = frame.len() - 1 + 2;
+    if !info.is_empty() {
+        len += info.len() + 2;
+    }
+    frame[1] = FORMAT_TYPE | ((len >> 8) as u8 & 0x07);
+    frame[2] = (len & 0xff) as u8;
+    let hcs = crc16(&frame[1..]);
+    frame.extend(hcs.to_le_bytes());
+    if !info.is_empty() {
+        frame.extend(info);
+        let fcs = crc16(&frame[1..]);
+        frame.extend(fcs.to_le_bytes());
+    }
+    frame.push(FLAG);
+    frame
+}
+
+/// Reads a frame from the client, skipping garbage and inter-frame flags
+pub(super) fn read_frame(client: &Client) -> Result<Frame> {
+    let mut byte = [0u8; 1];
+    loop {
+        client.read_exact(&mut byte)?;
+        if byte[0] == FLAG {
+            break;
+        }
+    }
+    let mut head = [FLAG; 2];
+    // consecutive frames may share a flag or be separated with several flags
+    while head[0] == FLAG {
+        client.read_exact(&mut head[..1])?;
+    }
+    client.read_exact(&mut head[1..])?;
+    let len = (usize::from(head[0] & 0x07) << 8) | usize::from(head[1]);
+    if head[0] & 0xf0 != FORMAT_TYPE || len < 7 {
+        return Err(Error::invalid_data("invalid HDLC frame format"));
+    }
+    // the rest of the frame and the closing flag
+    let mut data = vec![0u8; len - 1];
+    client.read_exact(&mut data)?;
+    if data.pop() != Some(FLAG) {
+        return Err(Error::invalid_data("HDLC closing flag missing"));
+    }
+    let mut frame = head.to_vec();
+    frame.extend(data);
+    parse_frame(&frame)
+}
+
+// parses a frame without flags
+pub(super) fn parse_frame(frame: &[u8]) -> Result<Frame> {
+    let segmented = frame[0] & SEGMENTED != 0;
+    let mut pos = 2;
+    // destination and source addresses, the last address byte has the lowest bit set
+    for _ in 0..2 {
+        loop {
+            let b = *frame
+                .get(pos)
+                .ok_or_else(|| Error::invalid_data("HDLC frame too short"))?;
+            pos += 1;
+            if b & 1 == 1 {
+                break;
+            }
+        }
+    }
+    let control = *frame
+        .get(pos)
+        .ok_or_else(|| Error::invalid_data("HDLC frame too short"))?;
+    pos += 1;
+    if frame.len() < pos + 2 {
+        return Err(Error::invalid_data("HDLC frame too short"));
+    }
+    if crc16(&frame[..pos]).to_le_bytes() != frame[pos..pos + 2] {
+        return Err(Error::invalid_data("HDLC header checksum mismatch"));
+    }
+    pos += 2;
+    let mut info = Vec::new();
+    if frame.len() > pos {
+        if frame.len() < pos + 2 {
+            return Err(Error::invalid_data("HDLC frame too short"));
+        }
+        let fcs_pos = frame.len() - 2;
+        if crc16(&frame[..fcs_pos]).to_le_bytes() != frame[fcs_pos..] {
+            return Err(Error::invalid_data("HDLC frame checksum mismatch"));
+        }
+        info.extend(&frame[pos..fcs_pos]);
+        // the LLC header is present in the first segment only
+        if info.starts_with(&LLC_RESPONSE) {
+            info.drain(..LLC_RESPONSE.len());
+        }
+    }
+    Ok(Frame {
+        control,
+        segmented,
+        info,
+    })
+}
+
+/// CRC-16/X.25, used for HCS and FCS
+pub(super) fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xffff;
+    for &b in data {
+        crc ^= u16::from(b);
+        for _ in 0..8 {
+            if crc & 1 == 1 {
+                crc = (crc >> 1) ^ 0x8408;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    !crc
+}
@@ -0,0 +1,506 @@
+//!
+//! IEC 62056 DLMS/COSEM client for energy meters.
+//!
+//! The client uses HDLC framing (IEC 62056-46) over any [`Client`] (serial or TCP), logical name
+//! referencing and no or low-level (password) authentication, which covers reading values from
+//! the majority of smart meters. Ciphered connections are not supported.
+//!
+//! Example:
+//!
+//! ```rust,no_run
+//! use roboplc::comm::serial;
+//! use roboplc::io::dlms::{obis, DlmsClient};
+//! use std::time::Duration;
+//!
+//! let client = serial::connect(
+//!     "/dev/ttyS0:9600:8:N:1",
+//!     Duration::from_secs(2),
+//!     Duration::from_millis(20),
+//! )
+//! .unwrap();
+//! let mut meter = DlmsClient::new(&client, 17);
+//! let energy = meter.read_register(obis::ACTIVE_ENERGY_IMPORT).unwrap();
+//! println!("{} {}", energy.value, energy.unit_name().unwrap_or_default());
+//! ```
+use std::{fmt, str::FromStr};
+
+use serde::{Serialize, Serializer};
+
+use crate::comm::Client;
+use crate::{Error, Result};
+
+mod data;
+mod hdlc;
+
+pub use data::DlmsValue;
+
+use hdlc::Addresses;
+
+/// COSEM interface classes
+pub mod class {
+    pub const DATA: u16 = 1;
+    pub const REGISTER: u16 = 3;
+    pub const EXTENDED_REGISTER: u16 = 4;
+    pub const DEMAND_REGISTER: u16 = 5;
+    pub const CLOCK: u16 = 8;
+}
+
+/// Common OBIS codes
+pub mod obis {
+    use super::ObisCode;
+
+    pub const CLOCK: ObisCode = ObisCode([0, 0, 1, 0, 0, 255]);
+    pub const ACTIVE_ENERGY_IMPORT: ObisCode = ObisCode([1, 0, 1, 8, 0, 255]);
+    pub const ACTIVE_ENERGY_EXPORT: ObisCode = ObisCode([1, 0, 2, 8, 0, 255]);
+    pub const REACTIVE_ENERGY_IMPORT: ObisCode = ObisCode([1, 0, 3, 8, 0, 255]);
+    pub const REACTIVE_ENERGY_EXPORT: ObisCode = ObisCode([1, 0, 4, 8, 0, 255]);
+    pub const ACTIVE_POWER_IMPORT: ObisCode = ObisCode([1, 0, 1, 7, 0, 255]);
+    pub const ACTIVE_POWER_EXPORT: ObisCode = ObisCode([1, 0, 2, 7, 0, 255]);
+    pub const FREQUENCY: ObisCode = ObisCode([1, 0, 14, 7, 0, 255]);
+    pub const CURRENT_L1: ObisCode = ObisCode([1, 0, 31, 7, 0, 255]);
+    pub const VOLTAGE_L1: ObisCode = ObisCode([1, 0, 32, 7, 0, 255]);
+    pub const CURRENT_L2: ObisCode = ObisCode([1, 0, 51, 7, 0, 255]);
+    pub const VOLTAGE_L2: ObisCode = ObisCode([1, 0, 52, 7, 0, 255]);
+    pub const CURRENT_L3: ObisCode = ObisCode([1, 0, 71, 7, 0, 255]);
+    pub const VOLTAGE_L3: ObisCode = ObisCode([1, 0, 72, 7, 0, 255]);
+}
+
+// public client, no authentication
+const DEFAULT_CLIENT_ADDRESS: u8 = 16;
+const DEFAULT_LOGICAL_DEVICE: u16 = 1;
+
+const GET_REQUEST: u8 = 0xc0;
+const GET_RESPONSE: u8 = 0xc4;
+const GET_NORMAL: u8 = 0x01;
+const GET_NEXT: u8 = 0x02;
+const GET_WITH_BLOCK: u8 = 0x02;
+const AARE: u8 = 0x61;
+const EXCEPTION_RESPONSE: u8 = 0xd8;
+// invoke id 1, high priority, confirmed service
+const INVOKE_ID_AND_PRIORITY: u8 = 0xc1;
+
+/// OBIS code (object identifier), e.g. `1.0.1.8.0.255` (active energy import)
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct ObisCode(pub [u8; 6]);
+
+impl fmt::Display for ObisCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let c = self.0;
+        write!(f, "{}.{}.{}.{}.{}.{}", c[0], c[1], c[2], c[3], c[4], c[5])
+    }
+}
+
+impl FromStr for ObisCode {
+    type Err = Error;
+    /// Parses dotted (`1.0.1.8.0.255`) and IEC (`1-0:1.8.0*255`) notations, the last group
+    /// defaults to 255 if omitted
+    fn from_str(s: &str) -> Result<Self> {
+        let groups: Vec<&str> = s.split(['.', '-', ':', '*']).collect();
+        if groups.len() != 5 && groups.len() != 6 {
+            return Err(Error::invalid_data(format!("invalid OBIS code: {}", s)));
+        }
+        let mut code = [255u8; 6];
+        for (c, g) in code.iter_mut().zip(groups) {
+            *c = g
+                .trim()
+                .parse()
+                .map_err(|_| Error::invalid_data(format!("invalid OBIS code: {}", s)))?;
+        }
+        Ok(Self(code))
+    }
+}
+
+impl Serialize for ObisCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// A register value with the scaler applied
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RegisterValue {
+    pub obis: ObisCode,
+    pub value: f64,
+    /// COSEM unit code (e.g. 30 for Wh)
+    pub unit: u8,
+}
+
+impl RegisterValue {
+    /// Returns the unit name for common unit codes
+    pub fn unit_name(&self) -> Option<&'static str> {
+        unit_name(self.unit)
+    }
+}
+
+/// Returns names for common COSEM unit codes
+pub fn unit_name(unit: u8) -> Option<&'static str> {
+    Some(match unit {
+        7 => "s",
+        9 => "°C",
+        27 => "W",
+        28 => "VA",
+        29 => "var",
+        30 => "Wh",
+        31 => "VAh",
+        32 => "varh",
+        33 => "A",
+        35 => "V",
+        44 => "Hz",
+        255 => "",
+        _ => return None,
+    })
+}
+
+/// DLMS/COSEM client. The association is established automatically on the first request and
+/// re-established after communication errors
+pub struct DlmsClient {
+    client: Client,
+    addresses: Addresses,
+    password: Option<Vec<u8>>,
+    max_pdu_size: u16,
+    send_seq: u8,
+    recv_seq: u8,
+    connected: bool,
+}
+
+impl DlmsClient {
+    /// Creates a new client. `physical_address` is the meter HDLC address (lower server address,
+    /// usually 17 + the last digits of the meter serial number for serial meters, 1 for TCP ones)
+    pub fn new(client: &Client, physical_address: u16) -> Self {
+        Self {
+            client: client.clone(),
+            addresses: Addresses {
+                client: DEFAULT_CLIENT_ADDRESS,
+                server_upper: DEFAULT_LOGICAL_DEVICE,
+                server_lower: physical_address,
+            },
+            password: None,
+            max_pdu_size: 0xffff,
+            send_seq: 0,
+            recv_seq: 0,
+            connected: false,
+        }
+    }
+    /// Sets the client address (the default is 16, the public client)
+    pub fn client_address(mut self, address: u8) -> Self {
+        self.addresses.client = address;
+        self
+    }
+    /// Sets the logical device address (upper server address, the default is 1, the management
+    /// logical device)
+    pub fn logical_device(mut self, address: u16) -> Self {
+        self.addresses.server_upper = address;
+        self
+    }
+    /// Sets the password for low-level security authentication (usually required with client
+    /// address 17 and higher)
+    pub fn password(mut self, password: &[u8]) -> Self {
+        self.password = Some(password.to_vec());
+        self
+    }
+    /// Sets the maximum APDU size, proposed to the meter (the default is 65535)
+    pub fn max_pdu_size(mut self, size: u16) -> Self {
+        self.max_pdu_size = size;
+        self
+    }
+    /// Establishes the HDLC connection and the application association
+    pub fn connect(&mut self) -> Result<()> {
+        let client = self.client.clone();
+        let _lock = client.lock();
+        self.connected = false;
+        self.send_seq = 0;
+        self.recv_seq = 0;
+        self.client
+            .write(&hdlc::build_frame(&self.addresses, hdlc::SNRM, &[]))?;
+        let frame = hdlc::read_frame(&self.client)?;
+        if frame.control != hdlc::UA {
+            return Err(Error::io(format!(
+                "HDLC connection refused (control {:#x})",
+                frame.control
+            )));
+        }
+        let aarq = self.aarq();
+        let response = self.transact(&aarq)?;
+        check_aare(&response)?;
+        self.connected = true;
+        Ok(())
+    }
+    /// Releases the connection
+    pub fn disconnect(&mut self) -> Result<()> {
+        if !self.connected {
+            return Ok(());
+        }
+        let _lock = self.client.lock();
+        self.connected = false;
+        self.client
+            .write(&hdlc::build_frame(&self.addresses, hdlc::DISC, &[]))?;
+        let frame = hdlc::read_frame(&self.client)?;
+        if frame.control != hdlc::UA && frame.control != hdlc::DM {
+            return Err(Error::io("unexpected HDLC disconnect response"));
+        }
+        Ok(())
+    }
+    /// Reads an attribute of a COSEM object
+    pub fn get(&mut self, class_id: u16, obis: ObisCode, attribute: i8) -> Result<DlmsValue> {
+        if !self.connected {
+            self.connect()?;
+        }
+        let result = self.get_attribute(class_id, obis, attribute);
+        if let Err(ref e) = result {
+            if !matches!(e, Error::API(..)) {
+                // the association state is unknown after communication errors
+                self.connected = false;
+                self.client.reconnect();
+            }
+        }
+        result
+    }
+    /// Reads a register (class 3) value and applies its scaler
+    pub fn read_register(&mut self, obis: ObisCode) -> Result<RegisterValue> {
+        let value = self.get(class::REGISTER, obis, 2)?;
+        let scaler_unit = self.get(class::REGISTER, obis, 3)?;
+        let (scaler, unit) = scaler_unit
+            .as_scaler_unit()
+            .ok_or_else(|| Error::invalid_data(format!("invalid scaler_unit of {}", obis)))?;
+        let value = value
+            .as_f64()
+            .ok_or_else(|| Error::invalid_data(format!("{} is not a numeric register", obis)))?;
+        Ok(RegisterValue {
+            obis,
+            value: value * 10f64.powi(i32::from(scaler)),
+            unit,
+        })
+    }
+    /// Reads several registers
+    pub fn read_registers(&mut self, codes: &[ObisCode]) -> Result<Vec<RegisterValue>> {
+        codes.iter().map(|obis| self.read_register(*obis)).collect()
+    }
+    /// Creates a device for [`crate::io::poll::Poller`], which reads the registers and converts
+    /// them into a hub message
+    #[cfg(target_os = "linux")]
+    pub fn into_poll_device<D, F>(
+        mut self,
+        name: &str,
+        interval: std::time::Duration,
+        codes: Vec<ObisCode>,
+        mut f: F,
+    ) -> crate::io::poll::Device<D>
+    where
+        F: FnMut(Vec<RegisterValue>) -> D + Send + 'static,
+    {
+        crate::io::poll::Device::new(name, interval, move || {
+            self.read_registers(&codes).map(&mut f)
+        })
+    }
+    fn get_attribute(&mut self, class_id: u16, obis: ObisCode, attribute: i8) -> Result<DlmsValue> {
+        let client = self.client.clone();
+        let _lock = client.lock();
+        let mut request = vec![GET_REQUEST, GET_NORMAL, INVOKE_ID_AND_PRIORITY];
+        request.extend(class_id.to_be_bytes());
+        request.extend(obis.0);
+        request.extend(attribute.to_be_bytes());
+        // no selective access
+        request.push(0);
+        let mut response = self.transact(&request)?;
+        let mut raw = Vec::new();
+        loop {
+            check_exception(&response)?;
+            if response.len() < 4 || response[0] != GET_RESPONSE {
+                return Err(Error::invalid_data("invalid GET response"));
+            }
+            match response[1] {
+                GET_NORMAL => {
+                    return match response[3] {
+                        0 => DlmsValue::decode(&response[4..]),
+                        _ => Err(data_access_error(response.get(4).copied())),
+                    };
+                }
+                GET_WITH_BLOCK => {
+                    // invoke id, last block flag, block number (u32), result choice
+                    if response.len() < 9 {
+                        return Err(Error::invalid_data("invalid GET block response"));
+                    }
+                    let last = response[3] != 0;
+                    let block = [response[4], response[5], response[6], response[7]];
+                    if response[8] != 0 {
+                        return Err(data_access_error(response.get(9).copied()));
+                    }
+                    let (len, pos) = data::decode_length(&response[9..])?;
+                    let chunk = response
+                        .get(9 + pos..9 + pos + len)
+                        .ok_or_else(|| Error::invalid_data("GET block data truncated"))?;
+                    raw.extend(chunk);
+                    if last {
+                        return DlmsValue::decode(&raw);
+                    }
+                    let mut next = vec![GET_REQUEST, GET_NEXT, INVOKE_ID_AND_PRIORITY];
+                    next.extend(block);
+                    response = self.transact(&next)?;
+                }
+                _ => return Err(Error::invalid_data("unsupported GET response type")),
+            }
+        }
+    }
+    // sends an APDU in an I-frame and returns the response APDU, reassembling segments
+    fn transact(&mut self, apdu: &[u8]) -> Result<Vec<u8>> {
+        let mut info = hdlc::LLC_REQUEST.to_vec();
+        info.extend(apdu);
+        let control = (self.recv_seq << 5) | 0x10 | (self.send_seq << 1);
+        self.send_seq = (self.send_seq + 1) % 8;
+        self.client
+            .write(&hdlc::build_frame(&self.addresses, control, &info))?;
+        let mut response = Vec::new();
+        loop {
+            let frame = hdlc::read_frame(&self.client)?;
+            if !frame.is_information() {
+                return Err(Error::io(format!(
+                    "unexpected HDLC frame (control {:#x})",
+                    frame.control
+                )));
+            }
+            self.recv_seq = (self.recv_seq + 1) % 8;
+            response.extend(frame.info);
+            if !frame.segmented {
+                break;
+            }
+            // receive ready, requests the next segment
+            let rr = (self.recv_seq << 5) | 0x11;
+            self.client
+                .write(&hdlc::build_frame(&self.addresses, rr, &[]))?;
+        }
+        Ok(response)
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    fn aarq(&self) -> Vec<u8> {
+        // application context name: logical name referencing, no ciphering
+        let mut body = vec![
+            0xa1, 0x09, 0x06, 0x07, 0x60, 0x85, 0x74, 0x05, 0x08, 0x01, 0x01,
+        ];
+        if let Some(ref password) = self.password {
+            // ACSE requirements: authentication
+            body.extend([0x8a, 0x02, 0x07, 0x80]);
+            // mechanism name: low-level security
+            body.extend([0x8b, 0x07, 0x60, 0x85, 0x74, 0x05, 0x08, 0x02, 0x01]);
+            body.extend([0xac, (password.len() + 2) as u8, 0x80, password.len() as u8]);
+            body.extend(password);
+        }
+        // xDLMS initiate request: DLMS version 6, conformance (get, block transfer with get,
+        // selective access, set, action), max PDU size
+        let mut initiate = vec![
+            0x01, 0x00, 0x00, 0x00, 0x06, 0x5f, 0x1f, 0x04, 0x00, 0x00, 0x7e, 0x1f,
+        ];
+        initiate.extend(self.max_pdu_size.to_be_bytes());
+        body.extend([0xbe, (initiate.len() + 2) as u8, 0x04, initiate.len() as u8]);
+        body.extend(initiate);
+        let mut aarq = vec![0x60, body.len() as u8];
+        aarq.extend(body);
+        aarq
+    }
+}
+
+impl Drop for DlmsClient {
+    fn drop(&mut self) {
+        let _r = self.disconnect();
+    }
+}
+
+fn check_exception(response: &[u8]) -> Result<()> {
+    if response.first() == Some(&EXCEPTION_RESPONSE) {
+        return Err(Error::API(
+            "DLMS exception response".to_owned(),
+            i64::from(response.get(2).copied().unwrap_or_default()),
+        ));
+    }
+    Ok(())
+}
+
+fn data_access_error(code: Option<u8>) -> Error {
+    let code = code.unwrap_or(250);
+    let msg = match code {
+        1 => "hardware fault",
+        2 => "temporary failure",
+        3 => "read-write denied",
+        4 => "object undefined",
+        9 => "object class inconsistent",
+        11 => "object unavailable",
+        12 => "type unmatched",
+        13 => "scope of access violated",
+        _ => "data access error",
+    };
+    Error::API(msg.to_owned(), i64::from(code))
+}
+
+fn check_aare(response: &[u8]) -> Result<()> {
+    check_exception(response)?;
+    if response.len() < 2 || response[0] != AARE {
+        return Err(Error::invalid_data("invalid AARE"));
+    }
+    let (len, mut pos) = data::decode_length(&response[1..])?;
+    pos += 1;
+    let end = (pos + len).min(response.len());
+    while pos + 2 <= end {
+        let tag = response[pos];
+        let (len, n) = data::decode_length(&response[pos + 1..])?;
+        let value = response
+            .get(pos + 1 + n..pos + 1 + n + len)
+            .ok_or_else(|| Error::invalid_data("AARE truncated"))?;
+        // association result: integer 0 = accepted
+        if tag == 0xa2 {
+            return match value {
+                [0x02, 0x01, 0] => Ok(()),
+                [0x02, 0x01, result] => Err(Error::API(
+                    "DLMS association rejected".to_owned(),
+                    i64::from(*result),
+                )),
+                _ => Err(Error::invalid_data("invalid AARE result")),
+            };
+        }
+        pos += 1 + n + len;
+    }
+    Err(Error::invalid_data("AARE result missing"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::hdlc::{build_frame, parse_frame, Addresses, SNRM};
+    use super::{check_aare, DlmsValue, ObisCode};
+
+    #[test]
+    fn test_hdlc_frame() {
+        let addresses = Addresses {
+            client: 16,
+            server_upper: 1,
+            server_lower: 0,
+        };
+        let frame = build_frame(&addresses, SNRM, &[]);
+        assert_eq!(
+            frame,
+            [0x7e, 0xa0, 0x07, 0x03, 0x21, 0x93, 0x0f, 0x01, 0x7e]
+        );
+        let frame = build_frame(&addresses, 0x10, &[0xe6, 0xe7, 0x00, 0x01, 0x02]);
+        let parsed = parse_frame(&frame[1..frame.len() - 1]).unwrap();
+        assert_eq!(parsed.info, [0x01, 0x02]);
+        let mut broken = frame.clone();
+        broken[10] ^= 0xff;
+        assert!(parse_frame(&broken[1..broken.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_obis_and_data() {
+        let code: ObisCode = "1-0:1.8.0*255".parse().unwrap();
+        assert_eq!(code, "1.0.1.8.0".parse().unwrap());
+        assert_eq!(code.to_string(), "1.0.1.8.0.255");
+        // scaler_unit: structure { integer -3, enum 30 (Wh) }
+        let scaler_unit = DlmsValue::decode(&[0x02, 0x02, 0x0f, 0xfd, 0x16, 0x1e]).unwrap();
+        assert_eq!(scaler_unit.as_scaler_unit(), Some((-3, 30)));
+        let value = DlmsValue::decode(&[0x06, 0x00, 0x01, 0xe2, 0x40]).unwrap();
+        assert_eq!(value.as_f64(), Some(123_456.0));
+        let aare = [
+            0x61, 0x29, 0xa1, 0x09, 0x06, 0x07, 0x60, 0x85, 0x74, 0x05, 0x08, 0x01, 0x01, 0xa2,
+            0x03, 0x02, 0x01, 0x00, 0xa3, 0x05, 0xa1, 0x03, 0x02, 0x01, 0x00, 0xbe, 0x10, 0x04,
+            0x0e, 0x08, 0x00, 0x06, 0x5f, 0x1f, 0x04, 0x00, 0x00, 0x10, 0x14, 0x00, 0x80, 0x00,
+            0x07,
+        ];
+        assert!(check_aare(&aare).is_ok());
+    }
+}
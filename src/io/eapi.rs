@@ -16,10 +16,12 @@
 use std::collections::BTreeMap;
 use std::io::Cursor;
 use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::controller::{Context, SLEEP_STEP};
+use crate::controller::{Context, OperationMode, SLEEP_STEP};
+use crate::deadband::{Deadband, DeadbandSet};
 use crate::{pchannel_async, DataDeliveryPolicy, DeliveryPolicy};
 use crate::{
     pchannel_async::{Receiver as ReceiverAsync, Sender as SenderAsync},
@@ -29,8 +31,16 @@
     ipc::Client,
     rpc::{Rpc, RpcClient},
 };
+use parking_lot_rt::Mutex;
 use tracing::{error, info, warn};
 
+// max client name suffix on broker-side name collisions
+const MAX_RENAME_SUFFIX: usize = 16;
+
+fn default_auto_rename() -> bool {
+    true
+}
+
 enum PushPayload {
     State {
         oid: Arc<OID>,
@@ -76,10 +86,14 @@ pub struct EAPIConfig<D, V>
     queue_size: Option<usize>,
     buf_ttl: Option<u64>,
     reconnect_delay: f64,
+    #[serde(default = "default_auto_rename")]
+    auto_rename: bool,
     #[serde(skip)]
-    action_handlers: BTreeMap<OID, ActionHandlerFn<D, V>>,
+    action_handlers: BTreeMap<OID, ActionHandler<D, V>>,
     #[serde(skip)]
-    bulk_action_handlers: Vec<(OIDMask, ActionHandlerFn<D, V>)>,
+    bulk_action_handlers: Vec<(OIDMask, ActionHandler<D, V>)>,
+    #[serde(skip)]
+    state_deadband: Option<Deadband>,
 }
 
 impl<D, V> EAPIConfig<D, V>
@@ -111,8 +125,10 @@ pub fn new(path: &str) -> Self {
             queue_size: None,
             buf_ttl: None,
             reconnect_delay: 2.0,
+            auto_rename: true,
             action_handlers: <_>::default(),
             bulk_action_handlers: <_>::default(),
+            state_deadband: None,
         }
     }
     /// Set timeout in seconds
@@ -140,23 +156,159 @@ pub fn reconnect_delay(mut self, reconnect_delay: f64) -> Self {
         self.reconnect_delay = reconnect_delay;
         self
     }
+    /// If the client name is already registered on the broker, automatically suffix it (`NAME.2`,
+    /// `NAME.3` etc.) instead of failing (the default is true)
+    pub fn auto_rename(mut self, auto_rename: bool) -> Self {
+        self.auto_rename = auto_rename;
+        self
+    }
     pub fn action_handler(mut self, oid: OID, handler: ActionHandlerFn<D, V>) -> Self {
-        self.action_handlers.insert(oid, handler);
+        self.action_handlers
+            .insert(oid, ActionHandler::Simple(handler));
+        self
+    }
+    /// Sets a handler for long-running actions, which can report progress and observe
+    /// cancellation (see [`ActionControl`])
+    pub fn controlled_action_handler(
+        mut self,
+        oid: OID,
+        handler: ControlledActionHandlerFn<D, V>,
+    ) -> Self {
+        self.action_handlers
+            .insert(oid, ActionHandler::Controlled(handler));
+        self
+    }
+    /// Sets the deadband filter for analog states, pushed with [`EAPI::state_push_analog()`]
+    /// (each OID is filtered separately). Without a filter, all analog states are pushed
+    pub fn state_deadband(mut self, deadband: Deadband) -> Self {
+        self.state_deadband = Some(deadband);
         self
     }
     pub fn bulk_action_handler(mut self, mask: OIDMask, handler: ActionHandlerFn<D, V>) -> Self {
-        self.bulk_action_handlers.push((mask, handler));
+        self.bulk_action_handlers
+            .push((mask, ActionHandler::Simple(handler)));
+        self
+    }
+    /// Sets a bulk handler for long-running actions (see
+    /// [`EAPIConfig::controlled_action_handler()`])
+    pub fn bulk_controlled_action_handler(
+        mut self,
+        mask: OIDMask,
+        handler: ControlledActionHandlerFn<D, V>,
+    ) -> Self {
+        self.bulk_action_handlers
+            .push((mask, ActionHandler::Controlled(handler)));
         self
     }
 }
 
 /// Action handler functions type
 pub type ActionHandlerFn<D, V> = fn(&mut Action, context: &Context<D, V>) -> ActionResult;
+/// Long-running action handler functions type
+pub type ControlledActionHandlerFn<D, V> =
+    fn(&mut Action, control: &ActionControl, context: &Context<D, V>) -> ActionResult;
 /// The result type of action handler functions
 pub type ActionResult = std::result::Result<(), Box<dyn std::error::Error>>;
 
-type ActionHandlers<D, V> = Arc<BTreeMap<OID, ActionHandlerFn<D, V>>>;
-type BulkActionHandlers<D, V> = Arc<Vec<(OIDMask, ActionHandlerFn<D, V>)>>;
+enum ActionHandler<D, V> {
+    Simple(ActionHandlerFn<D, V>),
+    Controlled(ControlledActionHandlerFn<D, V>),
+}
+
+// derive adds unnecessary bounds for D and V
+impl<D, V> Clone for ActionHandler<D, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<D, V> Copy for ActionHandler<D, V> {}
+
+type ActionHandlers<D, V> = Arc<BTreeMap<OID, ActionHandler<D, V>>>;
+type BulkActionHandlers<D, V> = Arc<Vec<(OIDMask, ActionHandler<D, V>)>>;
+// running actions by UUID, used to process terminate/kill requests
+type RunningActions = Arc<Mutex<BTreeMap<Value, (OID, Arc<AtomicBool>)>>>;
+
+/// Controls a long-running action: reports the progress to EVA ICS and observes cancellation
+/// (the action has been terminated or killed)
+///
+/// ```rust,ignore
+/// .controlled_action_handler("unit:axes/x".parse().unwrap(), |action, control, context| {
+///     for step in 0..100 {
+///         control.check_canceled()?;
+///         move_axis_step(context, step)?;
+///         control.report_progress(f64::from(step + 1), None);
+///     }
+///     Ok(())
+/// })
+/// ```
+#[derive(Clone)]
+pub struct ActionControl {
+    topic: Arc<String>,
+    tx: SenderAsync<PushPayload>,
+    running_event: Value,
+    canceled: Arc<AtomicBool>,
+}
+
+impl ActionControl {
+    /// Returns true if the action has been terminated/killed. The handler should stop as soon as
+    /// possible, the action is marked as terminated regardless of the handler result
+    pub fn is_canceled(&self) -> bool {
+        self.canceled.load(Ordering::Acquire)
+    }
+    /// Returns an error if the action has been canceled, to be used with `?` in handlers
+    pub fn check_canceled(&self) -> ActionResult {
+        if self.is_canceled() {
+            Err(Box::new(Error::failed("action canceled")))
+        } else {
+            Ok(())
+        }
+    }
+    /// Sleeps for the given duration or until the action is canceled. Returns false if canceled
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let until = Instant::now() + duration;
+        loop {
+            if self.is_canceled() {
+                return false;
+            }
+            let now = Instant::now();
+            if now >= until {
+                return true;
+            }
+            std::thread::sleep(SLEEP_STEP.min(until - now));
+        }
+    }
+    /// Reports the progress (percents) with an optional message. Progress reports are
+    /// informational, so the method never fails (reports are dropped if the bus is not
+    /// connected)
+    pub fn report_progress(&self, percent: f64, message: Option<&str>) {
+        #[derive(Serialize)]
+        struct Progress<'a> {
+            progress: f64,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            message: Option<&'a str>,
+        }
+        if let Err(error) = self.report_state(Progress {
+            progress: percent.clamp(0.0, 100.0),
+            message,
+        }) {
+            warn!(topic = %self.topic, %error, "failed to report action progress");
+        }
+    }
+    /// Reports a custom state of the running action (sent as the action output)
+    pub fn report_state<S: Serialize>(&self, state: S) -> Result<()> {
+        let out = to_value(state).map_err(Error::invalid_data)?;
+        let mut event = self.running_event.clone();
+        if let Value::Map(ref mut map) = event {
+            map.insert(Value::String("out".to_owned()), out);
+        }
+        let payload = pack(&event).map_err(Error::invalid_data)?;
+        self.tx.try_send(PushPayload::ActionState {
+            topic: self.topic.clone(),
+            payload,
+        })
+    }
+}
 
 struct Handlers<D, V>
 where
@@ -165,41 +317,47 @@ struct Handlers<D, V>
 {
     action_handlers: ActionHandlers<D, V>,
     bulk_action_handlers: BulkActionHandlers<D, V>,
+    running_actions: RunningActions,
     tx: SenderAsync<PushPayload>,
     context: Context<D, V>,
 }
 
 fn handle_action<D, V>(
     action: &mut Action,
-    topic: Arc<String>,
-    tx: SenderAsync<PushPayload>,
-    action_handlers: ActionHandlers<D, V>,
-    bulk_action_handlers: BulkActionHandlers<D, V>,
+    control: &ActionControl,
+    action_handlers: &ActionHandlers<D, V>,
+    bulk_action_handlers: &BulkActionHandlers<D, V>,
     context: &Context<D, V>,
 ) -> ActionResult
 where
     D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
     V: Send,
 {
-    macro_rules! notify_running {
-        () => {
-            if let Ok(payload) = pack(&action.event_running()) {
-                tx.try_send(PushPayload::ActionState { topic, payload })
-                    .ok();
-            }
-        };
-    }
-    if let Some(handler) = action_handlers.get(action.oid()) {
-        notify_running!();
-        return handler(action, context);
+    let handler = action_handlers.get(action.oid()).copied().or_else(|| {
+        bulk_action_handlers
+            .iter()
+            .find(|(mask, _)| mask.matches(action.oid()))
+            .map(|(_, handler)| *handler)
+    });
+    let Some(handler) = handler else {
+        return Err(
+            eva_common::Error::not_found(format!("action handler not found: {}", action.oid()))
+                .into(),
+        );
+    };
+    if let Ok(payload) = pack(&control.running_event) {
+        control
+            .tx
+            .try_send(PushPayload::ActionState {
+                topic: control.topic.clone(),
+                payload,
+            })
+            .ok();
     }
-    for (mask, handler) in bulk_action_handlers.iter() {
-        if mask.matches(action.oid()) {
-            notify_running!();
-            return handler(action, context);
-        }
+    match handler {
+        ActionHandler::Simple(f) => f(action, context),
+        ActionHandler::Controlled(f) => f(action, control, context),
     }
-    Err(eva_common::Error::not_found(format!("action handler not found: {}", action.oid())).into())
 }
 
 #[async_trait]
@@ -243,26 +401,40 @@ struct Payload {
                 let mut action: Action = unpack(payload)?;
                 let action_handlers = self.action_handlers.clone();
                 let bulk_action_handlers = self.bulk_action_handlers.clone();
+                let running_actions = self.running_actions.clone();
                 let tx = self.tx.clone();
                 let context = self.context.clone();
                 tokio::task::spawn_blocking(move || {
-                    let topic = Arc::new(format_action_topic(action.oid()));
-                    let payload = if let Err(e) = handle_action(
+                    let control = ActionControl {
+                        topic: Arc::new(format_action_topic(action.oid())),
+                        tx,
+                        running_event: to_value(action.event_running()).unwrap_or(Value::Unit),
+                        canceled: <_>::default(),
+                    };
+                    let uuid = to_value(action.uuid()).unwrap_or(Value::Unit);
+                    running_actions.lock().insert(
+                        uuid.clone(),
+                        (action.oid().clone(), control.canceled.clone()),
+                    );
+                    let result = handle_action(
                         &mut action,
-                        topic.clone(),
-                        tx.clone(),
-                        action_handlers,
-                        bulk_action_handlers,
+                        &control,
+                        &action_handlers,
+                        &bulk_action_handlers,
                         &context,
-                    ) {
+                    );
+                    running_actions.lock().remove(&uuid);
+                    let payload = if control.is_canceled() {
+                        action.event_terminated()
+                    } else if let Err(e) = result {
                         action.event_failed(1, None, Some(Value::String(e.to_string())))
                     } else {
                         action.event_completed(None)
                     };
                     match pack(&payload) {
                         Ok(packed) => {
-                            if let Err(error) = tx.send_blocking(PushPayload::ActionState {
-                                topic,
+                            if let Err(error) = control.tx.send_blocking(PushPayload::ActionState {
+                                topic: control.topic,
                                 payload: packed,
                             }) {
                                 error!(%error, "failed to send action state");
@@ -275,6 +447,55 @@ struct Payload {
                 .map_err(eva_common::Error::failed)?;
                 Ok(None)
             }
+            "terminate" => {
+                #[derive(Deserialize)]
+                struct ParamsUuid {
+                    u: Value,
+                }
+                let params: ParamsUuid = unpack(payload)?;
+                let Some((_, canceled)) = self.running_actions.lock().get(&params.u).cloned()
+                else {
+                    return Err(eva_common::Error::not_found("action not found").into());
+                };
+                canceled.store(true, Ordering::Release);
+                Ok(None)
+            }
+            "mode.get" => {
+                if payload.is_empty() {
+                    #[derive(Serialize)]
+                    struct Payload {
+                        mode: OperationMode,
+                    }
+                    let payload = Payload {
+                        mode: self.context.operation_mode(),
+                    };
+                    Ok(Some(pack(&payload)?))
+                } else {
+                    Err(RpcError::params(None))
+                }
+            }
+            "mode.set" => {
+                #[derive(Deserialize)]
+                struct ParamsMode {
+                    mode: OperationMode,
+                }
+                let params: ParamsMode = unpack(payload)?;
+                self.context.set_operation_mode(params.mode);
+                Ok(None)
+            }
+            "kill" => {
+                #[derive(Deserialize)]
+                struct ParamsOid {
+                    i: OID,
+                }
+                let params: ParamsOid = unpack(payload)?;
+                for (oid, canceled) in self.running_actions.lock().values() {
+                    if *oid == params.i {
+                        canceled.store(true, Ordering::Release);
+                    }
+                }
+                Ok(None)
+            }
 
             _ => Err(RpcError::method(None)),
         }
@@ -282,6 +503,9 @@ struct Payload {
 }
 
 /// EAPI connector, requires to be run in a separate thread manually
+///
+/// Besides actions, the connector provides RPC methods `mode.get` and `mode.set` (params: `mode`)
+/// to get/switch the controller operation mode remotely
 pub struct EAPI<D, V>
 where
     D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
@@ -308,11 +532,15 @@ struct EAPIInner<D, V>
     V: Send,
 {
     name: String,
+    // the actual client name, may differ from the configured one if auto-renamed
+    client_name: Mutex<String>,
     config: EAPIConfig<D, V>,
     tx: SenderAsync<PushPayload>,
     rx: ReceiverAsync<PushPayload>,
     action_handlers: ActionHandlers<D, V>,
     bulk_action_handlers: BulkActionHandlers<D, V>,
+    running_actions: RunningActions,
+    deadbands: Option<Mutex<DeadbandSet<Arc<OID>>>>,
 }
 
 impl<D, V> EAPI<D, V>
@@ -341,14 +569,21 @@ pub fn new<N: fmt::Display>(name: N, mut config: EAPIConfig<D, V>) -> Self {
             pchannel_async::bounded(config.queue_size.unwrap_or(busrt::DEFAULT_QUEUE_SIZE));
         let action_handlers = mem::take(&mut config.action_handlers);
         let bulk_action_handlers = mem::take(&mut config.bulk_action_handlers);
+        let deadbands = config
+            .state_deadband
+            .take()
+            .map(|d| Mutex::new(DeadbandSet::new(d)));
         Self {
             inner: EAPIInner {
                 name: name.to_string(),
+                client_name: Mutex::new(name.to_string()),
                 config,
                 tx,
                 rx,
                 action_handlers: Arc::new(action_handlers),
                 bulk_action_handlers: Arc::new(bulk_action_handlers),
+                running_actions: <_>::default(),
+                deadbands,
             }
             .into(),
         }
@@ -364,6 +599,11 @@ pub fn run(&self, thread_name: &str, context: &Context<D, V>) {
             .unwrap();
         rt.block_on(self.run_async(context));
     }
+    /// The actual client name, which may differ from the configured one if the client has been
+    /// auto-renamed (see [`EAPIConfig::auto_rename()`])
+    pub fn client_name(&self) -> String {
+        self.inner.client_name.lock().clone()
+    }
     async fn run_async(&self, context: &Context<D, V>) {
         let reconnect_delay = Duration::from_secs_f64(self.inner.config.reconnect_delay);
         loop {
@@ -374,11 +614,37 @@ async fn run_async(&self, context: &Context<D, V>) {
             tokio::time::sleep(reconnect_delay).await;
         }
     }
+    async fn connect(&self) -> Result<(Client, String)> {
+        let mut name = self.inner.name.clone();
+        let mut suffix = 1;
+        loop {
+            let bus_config = self.inner.config.to_busrt_config(&name);
+            match Client::connect(&bus_config).await {
+                Ok(client) => {
+                    self.inner.client_name.lock().clone_from(&name);
+                    return Ok((client, name));
+                }
+                Err(e)
+                    if e.kind() == busrt::ErrorKind::Busy
+                        && self.inner.config.auto_rename
+                        && suffix < MAX_RENAME_SUFFIX =>
+                {
+                    suffix += 1;
+                    let new_name = format!("{}.{}", self.inner.name, suffix);
+                    warn!(
+                        client = name,
+                        new_name, "EAPI client name is already registered, renaming"
+                    );
+                    name = new_name;
+                }
+                Err(e) => return Err(Error::io(e)),
+            }
+        }
+    }
     async fn bus(&self, context: &Context<D, V>) -> Result<()> {
-        let bus_config = self.inner.config.to_busrt_config(&self.inner.name);
-        let client = Client::connect(&bus_config).await.map_err(Error::io)?;
+        let (client, name) = self.connect().await?;
         info!(
-            client = self.inner.name,
+            client = name,
             path = self.inner.config.path,
             "connected to EAPI bus"
         );
@@ -386,6 +652,7 @@ async fn bus(&self, context: &Context<D, V>) -> Result<()> {
             tx: self.inner.tx.clone(),
             action_handlers: self.inner.action_handlers.clone(),
             bulk_action_handlers: self.inner.bulk_action_handlers.clone(),
+            running_actions: self.inner.running_actions.clone(),
             context: context.clone(),
         };
         let rpc = Arc::new(RpcClient::new(client, handlers));
@@ -464,7 +731,7 @@ struct DobjPushPayload<'a> {
             tokio::time::sleep(SLEEP_STEP).await;
         }
         push_worker.abort();
-        warn!(client = self.inner.name, "disconnected from EAPI bus");
+        warn!(client = name, "disconnected from EAPI bus");
         Ok(())
     }
     pub fn dobj_push<T>(&self, name: Arc<String>, value: T) -> Result<()>
@@ -496,7 +763,22 @@ pub fn state_push<T: Serialize>(&self, oid: Arc<OID>, value: T) -> Result<()> {
             })
             .map_err(Into::into)
     }
+    /// Pushes an analog state if it is significant for the state deadband filter (see
+    /// [`EAPIConfig::state_deadband()`]). Returns true if the state has been pushed
+    pub fn state_push_analog(&self, oid: Arc<OID>, value: f64) -> Result<bool> {
+        if let Some(ref deadbands) = self.inner.deadbands {
+            if deadbands.lock().process(oid.clone(), value).is_none() {
+                return Ok(false);
+            }
+        }
+        self.state_push(oid, value)?;
+        Ok(true)
+    }
     pub fn state_error(&self, oid: Arc<OID>) -> Result<()> {
+        if let Some(ref deadbands) = self.inner.deadbands {
+            // the next value must be pushed to clear the error
+            deadbands.lock().remove(&oid);
+        }
         self.inner
             .tx
             .try_send(PushPayload::State {
@@ -506,3 +788,96 @@ pub fn state_error(&self, oid: Arc<OID>) -> Result<()> {
             .map_err(Into::into)
     }
 }
+
+/// Multiple named EAPI connections, e.g. for programs which bridge several EVA ICS nodes. Pushes
+/// are routed to connections by their names
+pub struct EAPIConnections<D, V>
+where
+    D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
+    V: Send,
+{
+    connections: BTreeMap<String, EAPI<D, V>>,
+}
+
+impl<D, V> Default for EAPIConnections<D, V>
+where
+    D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
+    V: Send,
+{
+    fn default() -> Self {
+        Self {
+            connections: <_>::default(),
+        }
+    }
+}
+
+impl<D, V> EAPIConnections<D, V>
+where
+    D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Adds a named connection
+    pub fn add(&mut self, connection: &str, eapi: EAPI<D, V>) -> Result<()> {
+        if self.connections.contains_key(connection) {
+            return Err(Error::invalid_data(format!(
+                "EAPI connection already exists: {}",
+                connection
+            )));
+        }
+        self.connections.insert(connection.to_owned(), eapi);
+        Ok(())
+    }
+    pub fn get(&self, connection: &str) -> Option<&EAPI<D, V>> {
+        self.connections.get(connection)
+    }
+    fn connection(&self, connection: &str) -> Result<&EAPI<D, V>> {
+        self.connections.get(connection).ok_or_else(|| {
+            Error::invalid_data(format!("EAPI connection not found: {}", connection))
+        })
+    }
+    pub fn dobj_push<T>(&self, connection: &str, name: Arc<String>, value: T) -> Result<()>
+    where
+        T: for<'a> BinWrite<Args<'a> = ()>,
+    {
+        self.connection(connection)?.dobj_push(name, value)
+    }
+    pub fn dobj_error(&self, connection: &str, name: Arc<String>) -> Result<()> {
+        self.connection(connection)?.dobj_error(name)
+    }
+    pub fn state_push<T: Serialize>(
+        &self,
+        connection: &str,
+        oid: Arc<OID>,
+        value: T,
+    ) -> Result<()> {
+        self.connection(connection)?.state_push(oid, value)
+    }
+    pub fn state_push_analog(&self, connection: &str, oid: Arc<OID>, value: f64) -> Result<bool> {
+        self.connection(connection)?.state_push_analog(oid, value)
+    }
+    pub fn state_error(&self, connection: &str, oid: Arc<OID>) -> Result<()> {
+        self.connection(connection)?.state_error(oid)
+    }
+    /// Runs all connections in a single thread
+    ///
+    /// # Panics
+    ///
+    /// Will panic if failed to start the tokio runtime
+    pub fn run(&self, thread_name: &str, context: &Context<D, V>) {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .thread_name(thread_name)
+            .build()
+            .unwrap();
+        let local = tokio::task::LocalSet::new();
+        for eapi in self.connections.values() {
+            let eapi = eapi.clone();
+            let context = context.clone();
+            local.spawn_local(async move { eapi.run_async(&context).await });
+        }
+        rt.block_on(local);
+    }
+}
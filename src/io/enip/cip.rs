@@ -0,0 +1,251 @@
+use std::fmt;
+
+use crate::{Error, Result};
+
+/// CIP elementary data types, returned by and required for tag services
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum CipType {
+    Bool,
+    SInt,
+    Int,
+    DInt,
+    LInt,
+    USInt,
+    UInt,
+    UDInt,
+    ULInt,
+    Real,
+    LReal,
+    /// 32-bit boolean array (BOOL[] tags)
+    DWord,
+    /// Structure (UDT) with the structure handle
+    Structure(u16),
+    Other(u16),
+}
+
+const STRUCTURE: u16 = 0x02a0;
+
+impl CipType {
+    /// The element size in bytes, None for structures and unknown types
+    pub fn size(self) -> Option<usize> {
+        Some(match self {
+            CipType::Bool | CipType::SInt | CipType::USInt => 1,
+            CipType::Int | CipType::UInt => 2,
+            CipType::DInt | CipType::UDInt | CipType::Real | CipType::DWord => 4,
+            CipType::LInt | CipType::ULInt | CipType::LReal => 8,
+            CipType::Structure(_) | CipType::Other(_) => return None,
+        })
+    }
+    pub(super) fn encode(self, buf: &mut Vec<u8>) {
+        let code = match self {
+            CipType::Bool => 0xc1,
+            CipType::SInt => 0xc2,
+            CipType::Int => 0xc3,
+            CipType::DInt => 0xc4,
+            CipType::LInt => 0xc5,
+            CipType::USInt => 0xc6,
+            CipType::UInt => 0xc7,
+            CipType::UDInt => 0xc8,
+            CipType::ULInt => 0xc9,
+            CipType::Real => 0xca,
+            CipType::LReal => 0xcb,
+            CipType::DWord => 0xd3,
+            CipType::Structure(handle) => {
+                buf.extend(STRUCTURE.to_le_bytes());
+                buf.extend(handle.to_le_bytes());
+                return;
+            }
+            CipType::Other(code) => code,
+        };
+        buf.extend(u16::to_le_bytes(code));
+    }
+    /// Decodes the type, returns the type and the number of bytes consumed
+    pub(super) fn decode(buf: &[u8]) -> Result<(Self, usize)> {
+        let code = u16::from_le_bytes(
+            buf.get(..2)
+                .ok_or_else(|| Error::invalid_data("CIP data type missing"))?
+                .try_into()
+                .unwrap(),
+        );
+        Ok(match code {
+            0xc1 => (CipType::Bool, 2),
+            0xc2 => (CipType::SInt, 2),
+            0xc3 => (CipType::Int, 2),
+            0xc4 => (CipType::DInt, 2),
+            0xc5 => (CipType::LInt, 2),
+            0xc6 => (CipType::USInt, 2),
+            0xc7 => (CipType::UInt, 2),
+            0xc8 => (CipType::UDInt, 2),
+            0xc9 => (CipType::ULInt, 2),
+            0xca => (CipType::Real, 2),
+            0xcb => (CipType::LReal, 2),
+            0xd3 => (CipType::DWord, 2),
+            STRUCTURE => {
+                let handle = buf
+                    .get(2..4)
+                    .ok_or_else(|| Error::invalid_data("CIP structure handle missing"))?;
+                (
+                    CipType::Structure(u16::from_le_bytes([handle[0], handle[1]])),
+                    4,
+                )
+            }
+            _ => (CipType::Other(code), 2),
+        })
+    }
+}
+
+/// Encodes a Logix tag name into a CIP path (ANSI extended symbolic and element segments), e.g.
+/// `Program:Main.Motors[2].Speed` or `Matrix[1,2]`
+pub(super) fn encode_tag_path(tag: &str) -> Result<Vec<u8>> {
+    let err = || Error::invalid_data(format!("invalid tag name: {}", tag));
+    let mut path = Vec::new();
+    for part in tag.split('.') {
+        let (name, indexes) = if let Some((name, rest)) = part.split_once('[') {
+            (name, Some(rest.strip_suffix(']').ok_or_else(err)?))
+        } else {
+            (part, None)
+        };
+        if name.is_empty() || name.len() > usize::from(u8::MAX) {
+            return Err(err());
+        }
+        path.push(0x91);
+        // the length is checked above
+        path.push(u8::try_from(name.len()).unwrap());
+        path.extend(name.as_bytes());
+        if name.len() % 2 != 0 {
+            path.push(0);
+        }
+        for index in indexes.into_iter().flat_map(|i| i.split(',')) {
+            let index: u32 = index.trim().parse().map_err(|_| err())?;
+            if let Ok(index) = u8::try_from(index) {
+                path.extend([0x28, index]);
+            } else if let Ok(index) = u16::try_from(index) {
+                path.extend([0x29, 0]);
+                path.extend(index.to_le_bytes());
+            } else {
+                path.extend([0x2a, 0]);
+                path.extend(index.to_le_bytes());
+            }
+        }
+    }
+    Ok(path)
+}
+
+/// CIP error status
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct CipStatus {
+    pub general: u8,
+    pub extended: Option<u16>,
+}
+
+impl fmt::Display for CipStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self.general {
+            0x01 => "connection failure",
+            0x02 => "resource unavailable",
+            0x04 => "path segment error (unknown tag)",
+            0x05 => "path destination unknown",
+            0x06 => "partial transfer",
+            0x08 => "service not supported",
+            0x0a => "attribute list error",
+            0x0c => "object state conflict",
+            0x13 => "not enough data",
+            0x15 => "too much data",
+            0x1e => "embedded service error",
+            0x26 => "invalid path size",
+            0xff => match self.extended {
+                Some(0x2104) => "offset out of range",
+                Some(0x2105) => "index out of range",
+                Some(0x2107) => "data type mismatch",
+                _ => "general error",
+            },
+            _ => "error",
+        };
+        write!(f, "CIP {} ({:#04x}", msg, self.general)?;
+        if let Some(extended) = self.extended {
+            write!(f, ", extended {:#06x}", extended)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl From<CipStatus> for Error {
+    fn from(status: CipStatus) -> Self {
+        Error::API(status.to_string(), i64::from(status.general))
+    }
+}
+
+/// Parses a CIP reply, returns the reply data or an error status. Partial transfer (0x06) is
+/// returned as Ok with the flag set
+pub(super) fn parse_reply(reply: &[u8], service: u8) -> Result<(&[u8], bool)> {
+    if reply.len() < 4 {
+        return Err(Error::invalid_data("CIP reply too short"));
+    }
+    if reply[0] != service | 0x80 {
+        return Err(Error::invalid_data(format!(
+            "unexpected CIP reply service: {:#04x}",
+            reply[0]
+        )));
+    }
+    let general = reply[2];
+    let extended_words = usize::from(reply[3]);
+    let data = reply
+        .get(4 + extended_words * 2..)
+        .ok_or_else(|| Error::invalid_data("CIP reply truncated"))?;
+    match general {
+        0 => Ok((data, false)),
+        0x06 => Ok((data, true)),
+        _ => Err(CipStatus {
+            general,
+            extended: (extended_words > 0).then(|| u16::from_le_bytes([reply[4], reply[5]])),
+        }
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode_tag_path, parse_reply, CipType};
+
+    #[test]
+    fn test_tag_path() {
+        assert_eq!(
+            encode_tag_path("Speed").unwrap(),
+            [0x91, 5, b'S', b'p', b'e', b'e', b'd', 0]
+        );
+        assert_eq!(
+            encode_tag_path("Line.Counts[3,300]").unwrap(),
+            [
+                0x91, 4, b'L', b'i', b'n', b'e', 0x91, 6, b'C', b'o', b'u', b'n', b't', b's', 0x28,
+                3, 0x29, 0, 0x2c, 0x01
+            ]
+        );
+        assert_eq!(
+            encode_tag_path("Program:P[70000]").unwrap()[12..],
+            [0x2a, 0, 0x70, 0x11, 0x01, 0x00]
+        );
+        assert!(encode_tag_path("A..B").is_err());
+        assert!(encode_tag_path("A[1").is_err());
+        assert!(encode_tag_path("A[x]").is_err());
+    }
+
+    #[test]
+    fn test_types_and_replies() {
+        for t in [
+            CipType::Real,
+            CipType::Structure(0x0fce),
+            CipType::Other(0xa0),
+        ] {
+            let mut buf = Vec::new();
+            t.encode(&mut buf);
+            assert_eq!(CipType::decode(&buf).unwrap(), (t, buf.len()));
+        }
+        let (data, partial) = parse_reply(&[0xcc, 0, 0, 0, 0xca, 0, 1, 2, 3, 4], 0x4c).unwrap();
+        assert_eq!(data, [0xca, 0, 1, 2, 3, 4]);
+        assert!(!partial);
+        let err = parse_reply(&[0xcd, 0, 0xff, 1, 0x07, 0x21], 0x4d).unwrap_err();
+        assert!(err.to_string().contains("data type mismatch"));
+        assert!(parse_reply(&[0xcc, 0, 0x06, 0], 0x4c).unwrap().1);
+        assert!(parse_reply(&[0xcd, 0, 0, 0], 0x4c).is_err());
+    }
+}
@@ -0,0 +1,494 @@
+//!
+//! EtherNet/IP (CIP) client for Allen-Bradley/Rockwell Logix PLCs (ControlLogix, CompactLogix)
+//! and I/O mapping.
+//!
+//! The client works over a TCP [`Client`] (port 44818), so connections are re-established by the
+//! standard comm machinery. The encapsulation session is registered automatically on the first
+//! request and re-registered after communication errors. Tags are read and written with
+//! unconnected explicit messages (Logix Read/Write Tag services), routed to the CPU slot via the
+//! backplane if required. Large values are transferred with fragmented services.
+//!
+//! Mapped values are little-endian (the native CIP byte order). Structure (UDT) tags are
+//! transferred as-is, so mapped structures must follow the Logix memory layout (including
+//! alignment padding).
+//!
+//! Example:
+//!
+//! ```rust,no_run
+//! use roboplc::comm::tcp;
+//! use roboplc::io::enip::{EnipClient, EnipMapping};
+//! use roboplc::io::prelude::*;
+//! use std::time::Duration;
+//!
+//! #[binrw]
+//! struct Motor {
+//!     speed: f32,
+//!     current: f32,
+//!     faults: u32,
+//! }
+//!
+//! let client = tcp::connect("10.90.34.111:44818", Duration::from_secs(1)).unwrap();
+//! // ControlLogix CPU in slot 0
+//! let plc = EnipClient::new(&client).slot(Some(0));
+//! let mut mapping = EnipMapping::create(&plc, "Program:Main.Motors[2]", 1).unwrap();
+//! let motor: Motor = mapping.read().unwrap();
+//! ```
+use std::{io::Cursor, sync::Arc, time::Duration};
+
+use binrw::{BinRead, BinWrite};
+use parking_lot_rt::Mutex;
+use tracing::trace;
+
+use super::{IoMapping, LockedIoMapping};
+use crate::comm::Client;
+use crate::{Error, Result};
+
+mod cip;
+
+pub use cip::{CipStatus, CipType};
+
+use cip::{encode_tag_path, parse_reply};
+
+const CMD_REGISTER_SESSION: u16 = 0x65;
+const CMD_UNREGISTER_SESSION: u16 = 0x66;
+const CMD_SEND_RR_DATA: u16 = 0x6f;
+
+const ITEM_NULL_ADDRESS: u16 = 0x0000;
+const ITEM_UNCONNECTED_DATA: u16 = 0x00b2;
+
+const SERVICE_READ_TAG: u8 = 0x4c;
+const SERVICE_WRITE_TAG: u8 = 0x4d;
+const SERVICE_READ_TAG_FRAGMENTED: u8 = 0x52;
+const SERVICE_WRITE_TAG_FRAGMENTED: u8 = 0x53;
+const SERVICE_UNCONNECTED_SEND: u8 = 0x52;
+
+// connection manager object (class 6, instance 1)
+const CONNECTION_MANAGER_PATH: [u8; 4] = [0x20, 0x06, 0x24, 0x01];
+// backplane port
+const BACKPLANE_PORT: u8 = 1;
+
+// unconnected messages are limited to ~500 bytes, larger writes are fragmented
+const MAX_WRITE_FRAGMENT: usize = 400;
+// reassembled reads larger than this are rejected
+const MAX_DATA_SIZE: usize = 1024 * 1024;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Tag value (raw little-endian data)
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TagValue {
+    pub data_type: CipType,
+    pub data: Vec<u8>,
+}
+
+struct Inner {
+    client: Client,
+    slot: Option<u8>,
+    timeout: Duration,
+    context: u64,
+    // (session handle, comm client session id)
+    session: Option<(u32, usize)>,
+}
+
+/// EtherNet/IP client. Can be cloned and shared between workers with no limitations, requests
+/// are executed under the comm client lock
+#[derive(Clone)]
+pub struct EnipClient {
+    client: Client,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl EnipClient {
+    /// Creates a new client. Requests are sent directly to the target (no routing) by default
+    pub fn new(client: &Client) -> Self {
+        Self {
+            client: client.clone(),
+            inner: Arc::new(Mutex::new(Inner {
+                client: client.clone(),
+                slot: None,
+                timeout: DEFAULT_TIMEOUT,
+                context: 0,
+                session: None,
+            })),
+        }
+    }
+    /// Sets the CPU slot for backplane routing (required for ControlLogix, where the Ethernet
+    /// module and the CPU are separate modules), None for direct requests (the default, e.g.
+    /// CompactLogix and Micro800)
+    pub fn slot(self, slot: Option<u8>) -> Self {
+        self.inner.lock().slot = slot;
+        self
+    }
+    /// Sets the request timeout, reported to the routing module (the default is 5 seconds)
+    pub fn timeout(self, timeout: Duration) -> Self {
+        self.inner.lock().timeout = timeout;
+        self
+    }
+    /// The communication client
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+    /// Reads a tag (`count` elements for arrays, 1 otherwise)
+    pub fn read_tag(&self, tag: &str, count: u16) -> Result<TagValue> {
+        let _lock = self.client.lock();
+        self.read_tag_locked(&encode_tag_path(tag)?, count)
+    }
+    /// Writes a tag. The data type must match the tag type (the type of structures can be
+    /// obtained by reading the tag)
+    pub fn write_tag(&self, tag: &str, count: u16, value: &TagValue) -> Result<()> {
+        let _lock = self.client.lock();
+        self.write_tag_locked(&encode_tag_path(tag)?, count, value)
+    }
+    fn read_tag_locked(&self, path: &[u8], count: u16) -> Result<TagValue> {
+        self.execute(|inner| inner.read_tag(path, count))
+    }
+    fn write_tag_locked(&self, path: &[u8], count: u16, value: &TagValue) -> Result<()> {
+        self.execute(|inner| inner.write_tag(path, count, value))
+    }
+    // executes a request, the comm client must be locked
+    fn execute<R, F>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut Inner) -> Result<R>,
+    {
+        let mut inner = self.inner.lock();
+        let result = inner.register().and_then(|()| f(&mut inner));
+        if let Err(ref e) = result {
+            if !matches!(e, Error::API(..)) {
+                // the session state is unknown after communication errors
+                inner.session.take();
+                self.client.reconnect();
+            }
+        }
+        result
+    }
+}
+
+impl Inner {
+    fn register(&mut self) -> Result<()> {
+        if let Some((_, comm_session)) = self.session {
+            if comm_session == self.client.session_id() {
+                return Ok(());
+            }
+            self.session.take();
+        }
+        trace!("registering EtherNet/IP session");
+        // protocol version, options
+        let data = [1, 0, 0, 0];
+        let (handle, _) = self.transact(CMD_REGISTER_SESSION, 0, &data)?;
+        self.session = Some((handle, self.client.session_id()));
+        Ok(())
+    }
+    fn read_tag(&mut self, path: &[u8], count: u16) -> Result<TagValue> {
+        let reply = self.request(SERVICE_READ_TAG, path, &count.to_le_bytes())?;
+        let (data, mut partial) = parse_reply(&reply, SERVICE_READ_TAG)?;
+        let (data_type, type_len) = CipType::decode(data)?;
+        let mut value = data[type_len..].to_vec();
+        while partial {
+            let mut request = count.to_le_bytes().to_vec();
+            request.extend(
+                u32::try_from(value.len())
+                    .map_err(Error::invalid_data)?
+                    .to_le_bytes(),
+            );
+            let reply = self.request(SERVICE_READ_TAG_FRAGMENTED, path, &request)?;
+            let (data, p) = parse_reply(&reply, SERVICE_READ_TAG_FRAGMENTED)?;
+            let (_, type_len) = CipType::decode(data)?;
+            if data.len() == type_len {
+                return Err(Error::invalid_data("empty CIP read fragment"));
+            }
+            value.extend(&data[type_len..]);
+            if value.len() > MAX_DATA_SIZE {
+                return Err(Error::invalid_data("CIP tag data too large"));
+            }
+            partial = p;
+        }
+        Ok(TagValue {
+            data_type,
+            data: value,
+        })
+    }
+    fn write_tag(&mut self, path: &[u8], count: u16, value: &TagValue) -> Result<()> {
+        let mut header = Vec::with_capacity(8);
+        value.data_type.encode(&mut header);
+        header.extend(count.to_le_bytes());
+        if value.data.len() <= MAX_WRITE_FRAGMENT {
+            let mut request = header;
+            request.extend(&value.data);
+            let reply = self.request(SERVICE_WRITE_TAG, path, &request)?;
+            parse_reply(&reply, SERVICE_WRITE_TAG)?;
+            return Ok(());
+        }
+        let mut offset = 0;
+        for chunk in value.data.chunks(MAX_WRITE_FRAGMENT) {
+            let mut request = header.clone();
+            request.extend(
+                u32::try_from(offset)
+                    .map_err(Error::invalid_data)?
+                    .to_le_bytes(),
+            );
+            request.extend(chunk);
+            let reply = self.request(SERVICE_WRITE_TAG_FRAGMENTED, path, &request)?;
+            parse_reply(&reply, SERVICE_WRITE_TAG_FRAGMENTED)?;
+            offset += chunk.len();
+        }
+        Ok(())
+    }
+    // sends a CIP request (unconnected), returns the CIP reply
+    fn request(&mut self, service: u8, path: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+        let mut message = vec![service, path_words(path)?];
+        message.extend(path);
+        message.extend(data);
+        if let Some(slot) = self.slot {
+            // wraps the message into an unconnected send to the connection manager
+            let mut request = vec![
+                SERVICE_UNCONNECTED_SEND,
+                path_words(&CONNECTION_MANAGER_PATH)?,
+            ];
+            request.extend(CONNECTION_MANAGER_PATH);
+            let (tick, ticks) = timeout_ticks(self.timeout);
+            request.extend([tick, ticks]);
+            request.extend(
+                u16::try_from(message.len())
+                    .map_err(|_| Error::invalid_data("CIP request too large"))?
+                    .to_le_bytes(),
+            );
+            let odd = message.len() % 2 != 0;
+            request.extend(message);
+            if odd {
+                request.push(0);
+            }
+            // route path: size in words, reserved, backplane port and slot
+            request.extend([1, 0, BACKPLANE_PORT, slot]);
+            message = request;
+        }
+        let mut rr = Vec::with_capacity(message.len() + 16);
+        // interface handle, timeout
+        rr.extend(0u32.to_le_bytes());
+        rr.extend(0u16.to_le_bytes());
+        // item count, null address item, unconnected data item
+        rr.extend(2u16.to_le_bytes());
+        rr.extend(ITEM_NULL_ADDRESS.to_le_bytes());
+        rr.extend(0u16.to_le_bytes());
+        rr.extend(ITEM_UNCONNECTED_DATA.to_le_bytes());
+        rr.extend(
+            u16::try_from(message.len())
+                .map_err(|_| Error::invalid_data("CIP request too large"))?
+                .to_le_bytes(),
+        );
+        rr.extend(message);
+        let handle = self
+            .session
+            .map(|(handle, _)| handle)
+            .ok_or_else(|| Error::io("EtherNet/IP session is not registered"))?;
+        let (_, reply) = self.transact(CMD_SEND_RR_DATA, handle, &rr)?;
+        let reply = unconnected_data(&reply)?;
+        if self.slot.is_some()
+            // fragmented reads share the service code with unconnected send
+            && service != SERVICE_READ_TAG_FRAGMENTED
+            && reply.first() == Some(&(SERVICE_UNCONNECTED_SEND | 0x80))
+        {
+            // routing errors are reported by the connection manager
+            parse_reply(reply, SERVICE_UNCONNECTED_SEND)?;
+            return Err(Error::invalid_data("invalid CIP unconnected send reply"));
+        }
+        Ok(reply.to_vec())
+    }
+    // sends an encapsulation command, returns the session handle and the reply data
+    fn transact(&mut self, command: u16, session: u32, data: &[u8]) -> Result<(u32, Vec<u8>)> {
+        self.context = self.context.wrapping_add(1);
+        let mut frame = Vec::with_capacity(data.len() + 24);
+        frame.extend(command.to_le_bytes());
+        frame.extend(
+            u16::try_from(data.len())
+                .map_err(|_| Error::invalid_data("EtherNet/IP request too large"))?
+                .to_le_bytes(),
+        );
+        frame.extend(session.to_le_bytes());
+        // status
+        frame.extend(0u32.to_le_bytes());
+        frame.extend(self.context.to_le_bytes());
+        // options
+        frame.extend(0u32.to_le_bytes());
+        frame.extend(data);
+        self.client.write(&frame)?;
+        if command == CMD_UNREGISTER_SESSION {
+            // no reply
+            return Ok((session, Vec::new()));
+        }
+        let mut header = [0u8; 24];
+        self.client.read_exact(&mut header)?;
+        let reply_command = u16::from_le_bytes([header[0], header[1]]);
+        let len = usize::from(u16::from_le_bytes([header[2], header[3]]));
+        let handle = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        let status = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+        let mut reply = vec![0; len];
+        self.client.read_exact(&mut reply)?;
+        if reply_command != command || header[12..20] != self.context.to_le_bytes() {
+            return Err(Error::invalid_data("unexpected EtherNet/IP reply"));
+        }
+        if status != 0 {
+            if status == 0x64 {
+                // invalid session handle
+                self.session.take();
+            }
+            return Err(Error::API(
+                format!("EtherNet/IP encapsulation error {:#x}", status),
+                i64::from(status),
+            ));
+        }
+        Ok((handle, reply))
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        if let Some((handle, _)) = self.session.take() {
+            let client = self.client.clone();
+            let _lock = client.lock();
+            let _r = self.transact(CMD_UNREGISTER_SESSION, handle, &[]);
+        }
+    }
+}
+
+fn path_words(path: &[u8]) -> Result<u8> {
+    if path.len() % 2 != 0 {
+        return Err(Error::invalid_data("CIP path must be word-aligned"));
+    }
+    u8::try_from(path.len() / 2).map_err(|_| Error::invalid_data("CIP path too long"))
+}
+
+// converts the timeout into (priority/time tick, timeout ticks) of unconnected send
+fn timeout_ticks(timeout: Duration) -> (u8, u8) {
+    let ms = timeout.as_millis();
+    for tick in 0..16u8 {
+        let ticks = ms >> tick;
+        if ticks <= 255 {
+            // can not overflow, checked above
+            return (tick, u8::try_from(ticks.max(1)).unwrap_or(u8::MAX));
+        }
+    }
+    (15, u8::MAX)
+}
+
+// extracts the unconnected data item from a SendRRData reply
+fn unconnected_data(reply: &[u8]) -> Result<&[u8]> {
+    let err = || Error::invalid_data("invalid EtherNet/IP SendRRData reply");
+    let count = u16::from_le_bytes(reply.get(6..8).ok_or_else(err)?.try_into().unwrap());
+    let mut pos = 8;
+    for _ in 0..count {
+        let item = reply.get(pos..pos + 4).ok_or_else(err)?;
+        let item_type = u16::from_le_bytes([item[0], item[1]]);
+        let len = usize::from(u16::from_le_bytes([item[2], item[3]]));
+        let data = reply.get(pos + 4..pos + 4 + len).ok_or_else(err)?;
+        if item_type == ITEM_UNCONNECTED_DATA {
+            return Ok(data);
+        }
+        pos += 4 + len;
+    }
+    Err(err())
+}
+
+/// Maps a tag (or `count` elements of an array tag) to a structure. The data type, required for
+/// writing, is learned from the first read (a read is performed automatically before the first
+/// write)
+#[allow(clippy::module_name_repetitions)]
+pub struct EnipMapping {
+    client: EnipClient,
+    path: Vec<u8>,
+    count: u16,
+    data_type: Option<CipType>,
+    data_buf: Vec<u8>,
+}
+
+impl EnipMapping {
+    pub fn create(client: &EnipClient, tag: &str, count: u16) -> Result<Self> {
+        Ok(Self {
+            client: client.clone(),
+            path: encode_tag_path(tag)?,
+            count,
+            data_type: None,
+            data_buf: vec![],
+        })
+    }
+    /// Sets the tag data type, so the first write does not require a read
+    pub fn with_data_type(mut self, data_type: CipType) -> Self {
+        self.data_type = Some(data_type);
+        self
+    }
+}
+
+impl IoMapping for EnipMapping {
+    type Options = ();
+    fn read<T>(&mut self) -> Result<T>
+    where
+        T: for<'a> BinRead<Args<'a> = ()>,
+    {
+        let client = self.client.client.clone();
+        let _lock = client.lock();
+        self.read_locked()
+    }
+
+    fn write<T>(&mut self, value: T) -> Result<()>
+    where
+        T: for<'a> BinWrite<Args<'a> = ()>,
+    {
+        let client = self.client.client.clone();
+        let _lock = client.lock();
+        self.write_locked(value)
+    }
+}
+
+impl LockedIoMapping for EnipMapping {
+    fn client(&self) -> &Client {
+        &self.client.client
+    }
+
+    fn read_locked<T>(&mut self) -> Result<T>
+    where
+        T: for<'a> BinRead<Args<'a> = ()>,
+    {
+        let value = self.client.read_tag_locked(&self.path, self.count)?;
+        self.data_type = Some(value.data_type);
+        self.data_buf = value.data;
+        let mut reader = Cursor::new(&self.data_buf);
+        T::read_le(&mut reader).map_err(Into::into)
+    }
+
+    fn write_locked<T>(&mut self, value: T) -> Result<()>
+    where
+        T: for<'a> BinWrite<Args<'a> = ()>,
+    {
+        let data_type = if let Some(data_type) = self.data_type {
+            data_type
+        } else {
+            let value = self.client.read_tag_locked(&self.path, self.count)?;
+            self.data_type = Some(value.data_type);
+            value.data_type
+        };
+        let mut writer = Cursor::new(Vec::with_capacity(self.data_buf.len()));
+        value.write_le(&mut writer)?;
+        let value = TagValue {
+            data_type,
+            data: writer.into_inner(),
+        };
+        self.client.write_tag_locked(&self.path, self.count, &value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{timeout_ticks, unconnected_data};
+
+    #[test]
+    fn test_rr_data() {
+        let reply = [
+            0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0xb2, 0, 6, 0, 0xcc, 0, 0, 0, 0xc3, 0,
+        ];
+        assert_eq!(unconnected_data(&reply).unwrap(), [0xcc, 0, 0, 0, 0xc3, 0]);
+        assert!(unconnected_data(&reply[..20]).is_err());
+        assert_eq!(timeout_ticks(Duration::from_millis(200)), (0, 200));
+        assert_eq!(timeout_ticks(Duration::from_secs(5)), (5, 156));
+    }
+}
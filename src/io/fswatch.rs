@@ -0,0 +1,242 @@
+//!
+//! Watch folder file ingestion (e.g. recipes and orders, dropped by MES).
+//!
+//! [`FolderWatcher`] watches a directory with inotify, parses new files with the parser function
+//! and publishes the parsed messages to the hub. Processed files are moved to the archive folder,
+//! files which can not be parsed are moved to the error folder together with a `.error` file,
+//! which contains the error message. Files are moved with `rename`, so the archive and error
+//! folders must be located on the same file system as the watched one.
+//!
+//! Files are processed when closed after writing or moved into the directory, so the
+//! recommended way for producers is to write a file into a temporary location and move it into
+//! the watched directory. Files, which have been dropped while the watcher has not been running,
+//! are processed at the start.
+//!
+//! ```rust,ignore
+//! let watcher = FolderWatcher::new("/var/roboplc/orders", |_path, data| {
+//!     Ok(Message::Order(serde_json::from_slice(data).map_err(Error::invalid_data)?))
+//! })
+//! .extension("json");
+//! watcher.run(context.hub(), || context.is_online())?;
+//! ```
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use nix::{
+    errno::Errno,
+    sys::inotify::{AddWatchFlags, InitFlags, Inotify},
+};
+use rtsc::data_policy::DataDeliveryPolicy;
+use tracing::{error, info, warn};
+
+use crate::{hub::Hub, Error, Result};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// File parser function, called with the file path and the file contents
+pub type ParserFn<T> = fn(path: &Path, data: &[u8]) -> Result<T>;
+
+/// Watch folder
+pub struct FolderWatcher<T> {
+    dir: PathBuf,
+    archive_dir: PathBuf,
+    error_dir: PathBuf,
+    extensions: Vec<String>,
+    parser: ParserFn<T>,
+}
+
+impl<T> FolderWatcher<T> {
+    /// Creates a new watcher of the directory. Archive and error folders are `archive` and
+    /// `error` sub-directories by default
+    pub fn new<P: AsRef<Path>>(dir: P, parser: ParserFn<T>) -> Self {
+        let dir = dir.as_ref().to_owned();
+        Self {
+            archive_dir: dir.join("archive"),
+            error_dir: dir.join("error"),
+            dir,
+            extensions: Vec::new(),
+            parser,
+        }
+    }
+    /// Sets the archive folder for processed files
+    pub fn archive_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.archive_dir = dir.as_ref().to_owned();
+        self
+    }
+    /// Sets the folder for files which can not be parsed
+    pub fn error_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.error_dir = dir.as_ref().to_owned();
+        self
+    }
+    /// Processes only files with the given extension (can be called multiple times). If no
+    /// extensions are set, all files are processed, except hidden ones
+    pub fn extension(mut self, extension: &str) -> Self {
+        self.extensions
+            .push(extension.trim_start_matches('.').to_lowercase());
+        self
+    }
+    fn is_watched(&self, path: &Path) -> bool {
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            return false;
+        };
+        if name.starts_with('.') || !path.is_file() {
+            return false;
+        }
+        self.extensions.is_empty()
+            || path
+                .extension()
+                .and_then(|e| e.to_str())
+                .map_or(false, |e| self.extensions.contains(&e.to_lowercase()))
+    }
+    /// Parses a single file and moves it to the archive or to the error folder
+    pub fn process_file(&self, path: &Path) -> Result<T> {
+        let result = fs::read(path)
+            .map_err(Into::into)
+            .and_then(|data| (self.parser)(path, &data));
+        match result {
+            Ok(value) => {
+                move_to(path, &self.archive_dir)?;
+                Ok(value)
+            }
+            Err(e) => {
+                let target = move_to(path, &self.error_dir)?;
+                let mut error_file = target.into_os_string();
+                error_file.push(".error");
+                fs::write(error_file, format!("{}\n", e))?;
+                Err(e)
+            }
+        }
+    }
+    fn pending_files(&self) -> Result<Vec<PathBuf>> {
+        let mut files: Vec<PathBuf> = fs::read_dir(&self.dir)?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| self.is_watched(p))
+            .collect();
+        files.sort();
+        Ok(files)
+    }
+    fn ingest<F: FnMut(T)>(&self, path: &Path, handler: &mut F) {
+        match self.process_file(path) {
+            Ok(value) => {
+                info!(path=%path.display(), "file ingested");
+                handler(value);
+            }
+            Err(e) => error!(path=%path.display(), %e, "file ingestion failed"),
+        }
+    }
+    /// Watches the directory while the online function returns true, calls the handler for each
+    /// parsed file
+    pub fn watch<F, O>(&self, mut handler: F, online: O) -> Result<()>
+    where
+        F: FnMut(T),
+        O: Fn() -> bool,
+    {
+        fs::create_dir_all(&self.archive_dir)?;
+        fs::create_dir_all(&self.error_dir)?;
+        let inotify =
+            Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC).map_err(Error::io)?;
+        inotify
+            .add_watch(
+                &self.dir,
+                AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO,
+            )
+            .map_err(|e| Error::io(format!("{}: {}", self.dir.display(), e)))?;
+        // the watch is added first to not miss files, dropped during the scan
+        for path in self.pending_files()? {
+            self.ingest(&path, &mut handler);
+        }
+        while online() {
+            match inotify.read_events() {
+                Ok(events) => {
+                    for event in events {
+                        let Some(name) = event.name else {
+                            continue;
+                        };
+                        let path = self.dir.join(name);
+                        if self.is_watched(&path) {
+                            self.ingest(&path, &mut handler);
+                        }
+                    }
+                }
+                Err(Errno::EAGAIN) => thread::sleep(POLL_INTERVAL),
+                Err(e) => return Err(Error::io(e)),
+            }
+        }
+        Ok(())
+    }
+    /// Watches the directory while the online function returns true, publishes parsed messages
+    /// to the hub
+    pub fn run<O>(&self, hub: &Hub<T>, online: O) -> Result<()>
+    where
+        T: DataDeliveryPolicy + Clone,
+        O: Fn() -> bool,
+    {
+        self.watch(|message| hub.send(message), online)
+    }
+}
+
+// moves a file into the directory, the file gets a timestamp suffix if the name is already used
+fn move_to(path: &Path, dir: &Path) -> Result<PathBuf> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| Error::invalid_data("invalid file name"))?;
+    let mut target = dir.join(name);
+    if target.exists() {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let mut name = name.to_owned();
+        name.push(format!(".{}", ts));
+        warn!(path=%target.display(), "file already exists, renaming");
+        target = dir.join(name);
+    }
+    fs::create_dir_all(dir)?;
+    fs::rename(path, &target)?;
+    Ok(target)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path};
+
+    use super::FolderWatcher;
+    use crate::{Error, Result};
+
+    fn parse(_path: &Path, data: &[u8]) -> Result<u32> {
+        std::str::from_utf8(data)
+            .map_err(Error::invalid_data)?
+            .trim()
+            .parse()
+            .map_err(Into::into)
+    }
+
+    #[test]
+    fn test_process_files() {
+        let dir = std::env::temp_dir().join(format!("roboplc-fswatch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("1.txt"), "42").unwrap();
+        fs::write(dir.join("2.txt"), "bad").unwrap();
+        fs::write(dir.join("3.dat"), "1").unwrap();
+        fs::write(dir.join(".4.txt"), "1").unwrap();
+        let watcher = FolderWatcher::new(&dir, parse).extension("txt");
+        let files = watcher.pending_files().unwrap();
+        assert_eq!(files, [dir.join("1.txt"), dir.join("2.txt")]);
+        assert_eq!(watcher.process_file(&files[0]).unwrap(), 42);
+        assert!(watcher.process_file(&files[1]).is_err());
+        assert!(dir.join("archive/1.txt").exists());
+        assert!(dir.join("error/2.txt").exists());
+        assert!(dir.join("error/2.txt.error").exists());
+        // duplicate names are not overwritten
+        fs::write(dir.join("1.txt"), "43").unwrap();
+        assert_eq!(watcher.process_file(&dir.join("1.txt")).unwrap(), 43);
+        assert_eq!(fs::read_dir(dir.join("archive")).unwrap().count(), 2);
+        assert!(watcher.pending_files().unwrap().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
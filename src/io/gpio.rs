@@ -0,0 +1,325 @@
+//!
+//! PWM outputs and hardware pulse counters of single-board computers, accessed via Linux sysfs.
+//!
+//! * [`Pwm`] - PWM outputs (`/sys/class/pwm/pwmchipN/pwmM`), e.g. for fan/valve modulation
+//!
+//! * [`HwCounter`] - hardware pulse counters of the Linux counter subsystem
+//!   (`/sys/bus/counter/devices/counterN/countM`), e.g. for flow meter inputs
+//!
+//! Attribute files are opened once, so duty/frequency changes and counter reads can be performed
+//! from real-time loops without extra system calls to open/close files.
+//!
+//! Both types implement [`IoMapping`]: PWM mappings read/write the duty ratio (`f32`, 0..=1),
+//! counter mappings read the raw counter value (`u64`), which can be accumulated with
+//! [`crate::encoder::Counter`] if the hardware counter wraps around.
+//!
+//! ```rust,no_run
+//! use roboplc::io::gpio::Pwm;
+//!
+//! let mut fan = Pwm::open(0, 1).unwrap();
+//! fan.set_frequency(25_000.0).unwrap();
+//! fan.set_duty(0.4).unwrap();
+//! fan.enable(true).unwrap();
+//! ```
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Cursor,
+    os::unix::fs::FileExt as _,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use binrw::{BinRead, BinWrite};
+
+use super::IoMapping;
+use crate::{Error, Result};
+
+const PWM_SYSFS: &str = "/sys/class/pwm";
+const COUNTER_SYSFS: &str = "/sys/bus/counter/devices";
+
+// sysfs attributes usually appear with a delay after the channel is exported (udev rules)
+const EXPORT_WAIT: Duration = Duration::from_millis(10);
+const EXPORT_ATTEMPTS: usize = 100;
+
+fn open_attr(path: &Path, write: bool) -> Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(write)
+        .open(path)
+        .map_err(|e| Error::io(format!("{}: {}", path.display(), e)))
+}
+
+fn write_attr(file: &File, value: u64) -> Result<()> {
+    file.write_at(format!("{}\n", value).as_bytes(), 0)?;
+    Ok(())
+}
+
+fn read_attr(file: &File) -> Result<u64> {
+    let mut buf = [0u8; 32];
+    let len = file.read_at(&mut buf, 0)?;
+    std::str::from_utf8(&buf[..len])
+        .map_err(Error::invalid_data)?
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .parse::<u64>()
+        .map_err(Into::into)
+}
+
+/// PWM output (sysfs)
+pub struct Pwm {
+    period: File,
+    duty_cycle: File,
+    enable: File,
+    period_ns: u64,
+    duty_ns: u64,
+}
+
+impl Pwm {
+    /// Opens a PWM channel of the given chip, exports it if necessary
+    pub fn open(chip: u32, channel: u32) -> Result<Self> {
+        Self::open_at(
+            &Path::new(PWM_SYSFS).join(format!("pwmchip{}", chip)),
+            channel,
+        )
+    }
+    /// Opens a PWM channel of the chip at the given sysfs path
+    pub fn open_at(chip_path: &Path, channel: u32) -> Result<Self> {
+        let path = chip_path.join(format!("pwm{}", channel));
+        if !path.exists() {
+            fs::write(chip_path.join("export"), channel.to_string())
+                .map_err(|e| Error::io(format!("unable to export PWM channel: {}", e)))?;
+        }
+        let enable_path = path.join("enable");
+        for _ in 0..EXPORT_ATTEMPTS {
+            if OpenOptions::new().write(true).open(&enable_path).is_ok() {
+                break;
+            }
+            thread::sleep(EXPORT_WAIT);
+        }
+        let period = open_attr(&path.join("period"), true)?;
+        let duty_cycle = open_attr(&path.join("duty_cycle"), true)?;
+        let enable = open_attr(&enable_path, true)?;
+        Ok(Self {
+            period_ns: read_attr(&period)?,
+            duty_ns: read_attr(&duty_cycle)?,
+            period,
+            duty_cycle,
+            enable,
+        })
+    }
+    /// Sets the PWM period, the duty ratio is kept
+    pub fn set_period(&mut self, period: Duration) -> Result<()> {
+        let period_ns = u64::try_from(period.as_nanos()).map_err(Error::invalid_data)?;
+        if period_ns == 0 {
+            return Err(Error::invalid_data("PWM period must be non-zero"));
+        }
+        let ratio = self.duty();
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let duty_ns = (period_ns as f64 * ratio).round() as u64;
+        // the duty cycle must never exceed the period
+        if duty_ns < self.duty_ns {
+            self.write_duty_ns(duty_ns)?;
+            self.write_period_ns(period_ns)?;
+        } else {
+            self.write_period_ns(period_ns)?;
+            self.write_duty_ns(duty_ns)?;
+        }
+        Ok(())
+    }
+    /// Sets the PWM frequency (Hz), the duty ratio is kept
+    pub fn set_frequency(&mut self, frequency: f64) -> Result<()> {
+        if !(frequency > 0.0 && frequency.is_finite()) {
+            return Err(Error::invalid_data("PWM frequency must be positive"));
+        }
+        self.set_period(Duration::from_secs_f64(1.0 / frequency))
+    }
+    /// Sets the duty ratio (0..=1)
+    pub fn set_duty(&mut self, ratio: f64) -> Result<()> {
+        if !(0.0..=1.0).contains(&ratio) {
+            return Err(Error::invalid_data(format!(
+                "PWM duty ratio must be within 0..=1 ({})",
+                ratio
+            )));
+        }
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let duty_ns = (self.period_ns as f64 * ratio).round() as u64;
+        self.write_duty_ns(duty_ns)
+    }
+    /// Sets the active time of the period
+    pub fn set_duty_cycle(&mut self, duty_cycle: Duration) -> Result<()> {
+        let duty_ns = u64::try_from(duty_cycle.as_nanos()).map_err(Error::invalid_data)?;
+        if duty_ns > self.period_ns {
+            return Err(Error::invalid_data("PWM duty cycle exceeds the period"));
+        }
+        self.write_duty_ns(duty_ns)
+    }
+    /// Enables/disables the output
+    pub fn enable(&mut self, enable: bool) -> Result<()> {
+        write_attr(&self.enable, u64::from(enable))
+    }
+    /// The current period
+    pub fn period(&self) -> Duration {
+        Duration::from_nanos(self.period_ns)
+    }
+    /// The current duty ratio (0 if the period is not set)
+    pub fn duty(&self) -> f64 {
+        if self.period_ns == 0 {
+            0.0
+        } else {
+            self.duty_ns as f64 / self.period_ns as f64
+        }
+    }
+    fn write_period_ns(&mut self, period_ns: u64) -> Result<()> {
+        write_attr(&self.period, period_ns)?;
+        self.period_ns = period_ns;
+        Ok(())
+    }
+    fn write_duty_ns(&mut self, duty_ns: u64) -> Result<()> {
+        write_attr(&self.duty_cycle, duty_ns)?;
+        self.duty_ns = duty_ns;
+        Ok(())
+    }
+}
+
+impl IoMapping for Pwm {
+    type Options = ();
+
+    /// Reads the duty ratio as `f32`
+    fn read<T>(&mut self) -> Result<T>
+    where
+        T: for<'a> BinRead<Args<'a> = ()>,
+    {
+        #[allow(clippy::cast_possible_truncation)]
+        let duty = self.duty() as f32;
+        T::read_be(&mut Cursor::new(duty.to_be_bytes())).map_err(Into::into)
+    }
+
+    /// Writes the duty ratio, the value must be `f32`
+    fn write<T>(&mut self, value: T) -> Result<()>
+    where
+        T: for<'a> BinWrite<Args<'a> = ()>,
+    {
+        let mut buf = Cursor::new(Vec::with_capacity(4));
+        value.write_be(&mut buf)?;
+        let ratio: [u8; 4] = buf
+            .into_inner()
+            .try_into()
+            .map_err(|_| Error::invalid_data("PWM mappings accept f32 duty ratio only"))?;
+        self.set_duty(f64::from(f32::from_be_bytes(ratio)))
+    }
+}
+
+/// Hardware pulse counter (Linux counter subsystem)
+pub struct HwCounter {
+    path: PathBuf,
+    count: File,
+}
+
+impl HwCounter {
+    /// Opens a count of the given counter device
+    pub fn open(device: u32, count: u32) -> Result<Self> {
+        Self::open_at(
+            &Path::new(COUNTER_SYSFS)
+                .join(format!("counter{}", device))
+                .join(format!("count{}", count)),
+        )
+    }
+    /// Opens a counter count at the given sysfs path
+    pub fn open_at(path: &Path) -> Result<Self> {
+        Ok(Self {
+            path: path.to_owned(),
+            count: open_attr(&path.join("count"), false)?,
+        })
+    }
+    /// Reads the counter value
+    pub fn value(&self) -> Result<u64> {
+        read_attr(&self.count)
+    }
+    /// Resets the counter to zero (if supported by the hardware)
+    pub fn reset(&self) -> Result<()> {
+        write_attr(&open_attr(&self.path.join("count"), true)?, 0)
+    }
+}
+
+impl IoMapping for HwCounter {
+    type Options = ();
+
+    /// Reads the counter value as `u64`
+    fn read<T>(&mut self) -> Result<T>
+    where
+        T: for<'a> BinRead<Args<'a> = ()>,
+    {
+        T::read_be(&mut Cursor::new(self.value()?.to_be_bytes())).map_err(Into::into)
+    }
+
+    fn write<T>(&mut self, _value: T) -> Result<()>
+    where
+        T: for<'a> BinWrite<Args<'a> = ()>,
+    {
+        Err(Error::invalid_data("hardware counters are read-only"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf, time::Duration};
+
+    use super::{HwCounter, Pwm};
+    use crate::io::IoMapping as _;
+
+    fn sysfs_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("roboplc-gpio-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn attr(path: &PathBuf, name: &str) -> u64 {
+        fs::read_to_string(path.join(name))
+            .unwrap()
+            .lines()
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_pwm() {
+        let chip = sysfs_dir("pwm");
+        let path = chip.join("pwm0");
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("period"), "1000000\n").unwrap();
+        fs::write(path.join("duty_cycle"), "250000\n").unwrap();
+        fs::write(path.join("enable"), "0\n").unwrap();
+        let mut pwm = Pwm::open_at(&chip, 0).unwrap();
+        assert!((pwm.duty() - 0.25).abs() < f64::EPSILON);
+        pwm.set_frequency(2000.0).unwrap();
+        assert_eq!(pwm.period(), Duration::from_micros(500));
+        assert_eq!(attr(&path, "period"), 500_000);
+        assert_eq!(attr(&path, "duty_cycle"), 125_000);
+        pwm.write(0.5f32).unwrap();
+        assert_eq!(attr(&path, "duty_cycle"), 250_000);
+        assert!((pwm.read::<f32>().unwrap() - 0.5).abs() < f32::EPSILON);
+        assert!(pwm.write(1u8).is_err());
+        assert!(pwm.set_duty(1.5).is_err());
+        pwm.enable(true).unwrap();
+        assert_eq!(attr(&path, "enable"), 1);
+        fs::remove_dir_all(chip).unwrap();
+    }
+
+    #[test]
+    fn test_counter() {
+        let path = sysfs_dir("counter");
+        fs::write(path.join("count"), "12345\n").unwrap();
+        let mut counter = HwCounter::open_at(&path).unwrap();
+        assert_eq!(counter.read::<u64>().unwrap(), 12345);
+        counter.reset().unwrap();
+        assert_eq!(counter.value().unwrap(), 0);
+        fs::remove_dir_all(path).unwrap();
+    }
+}
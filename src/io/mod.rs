@@ -4,20 +4,43 @@
 pub use binrw;
 use binrw::{BinRead, BinWrite};
 
+use crate::comm::Client;
 use crate::Result;
 
+pub use roboplc_derive::MappingGroup;
+
+#[cfg(feature = "dlms")]
+/// DLMS/COSEM energy meters
+pub mod dlms;
 #[cfg(feature = "eapi")]
 /// EVA ICS local bus API
 pub mod eapi;
+#[cfg(feature = "enip")]
+/// EtherNet/IP (CIP) Logix PLCs
+pub mod enip;
+/// Watch folder file ingestion
+#[cfg(target_os = "linux")]
+pub mod fswatch;
+/// PWM outputs and hardware pulse counters (sysfs)
+#[cfg(target_os = "linux")]
+pub mod gpio;
 #[cfg(feature = "modbus")]
 /// Modbus communication
 pub mod modbus;
+#[cfg(feature = "opcua")]
+/// OPC UA client
+pub mod opcua;
 /// Linux process communication
 #[cfg(feature = "pipe")]
 /// Subprocess pipes
 pub mod pipe;
+/// Polled devices engine
+#[cfg(target_os = "linux")]
+pub mod poll;
 /// Raw UDP communication
 pub mod raw_udp;
+/// Framework for custom TCP servers
+pub mod server;
 
 #[allow(clippy::module_name_repetitions)]
 pub trait IoMapping {
@@ -30,6 +53,20 @@ fn write<T>(&mut self, value: T) -> Result<()>
         T: for<'a> BinWrite<Args<'a> = ()>;
 }
 
+/// Mappings, which can be executed in groups under a single client lock (see [`MappingGroup`])
+pub trait LockedIoMapping: IoMapping {
+    /// The communication client of the mapping
+    fn client(&self) -> &Client;
+    /// Reads the mapping, the client must be locked by the caller
+    fn read_locked<T>(&mut self) -> Result<T>
+    where
+        T: for<'a> BinRead<Args<'a> = ()>;
+    /// Writes the mapping, the client must be locked by the caller
+    fn write_locked<T>(&mut self, value: T) -> Result<()>
+    where
+        T: for<'a> BinWrite<Args<'a> = ()>;
+}
+
 pub mod prelude {
     pub use super::IoMapping as _;
     pub use binrw::prelude::*;
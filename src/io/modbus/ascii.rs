@@ -0,0 +1,135 @@
+//! Modbus ASCII framing. Frames are converted to/from RTU ones (with CRC), so the RTU request
+//! generation and response processing can be used as-is.
+use crate::comm::Client;
+use crate::{Error, Result};
+
+use super::sniffer::crc16;
+
+// ':' + 255 bytes (hex) + CRLF
+const MAX_FRAME_LEN: usize = 1 + 255 * 2 + 2;
+
+/// Longitudinal redundancy check of the binary frame (unit id + PDU)
+pub(super) fn lrc(data: &[u8]) -> u8 {
+    data.iter()
+        .fold(0u8, |acc, b| acc.wrapping_add(*b))
+        .wrapping_neg()
+}
+
+/// Encodes an RTU frame (the CRC is dropped) as an ASCII one
+pub(super) fn encode(rtu: &[u8], out: &mut Vec<u8>) {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    let data = &rtu[..rtu.len().saturating_sub(2)];
+    out.truncate(0);
+    out.push(b':');
+    for b in data.iter().copied().chain([lrc(data)]) {
+        out.push(HEX[usize::from(b >> 4)]);
+        out.push(HEX[usize::from(b & 0x0f)]);
+    }
+    out.extend(b"\r\n");
+}
+
+/// Decodes an ASCII frame into an RTU one (with CRC), the LRC is verified
+pub(super) fn decode(ascii: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    let start = ascii
+        .iter()
+        .position(|c| *c == b':')
+        .ok_or_else(|| Error::invalid_data("no Modbus ASCII frame start"))?;
+    let hex = ascii[start + 1..]
+        .strip_suffix(b"\r\n")
+        .ok_or_else(|| Error::invalid_data("no Modbus ASCII frame end"))?;
+    if hex.len() % 2 != 0 || hex.len() < 6 {
+        return Err(Error::invalid_data("invalid Modbus ASCII frame length"));
+    }
+    out.truncate(0);
+    for pair in hex.chunks(2) {
+        let s = std::str::from_utf8(pair).map_err(Error::invalid_data)?;
+        out.push(u8::from_str_radix(s, 16)?);
+    }
+    let lrc_received = out.pop().unwrap_or_default();
+    if lrc(out) != lrc_received {
+        return Err(Error::invalid_data("Modbus ASCII LRC mismatch"));
+    }
+    let crc = crc16(out);
+    out.extend(crc.to_le_bytes());
+    Ok(())
+}
+
+/// Collects a frame from ':' to LF, bytes outside of frames are ignored
+#[derive(Default)]
+pub(super) struct FrameCollector {
+    frame: Vec<u8>,
+}
+
+impl FrameCollector {
+    /// Returns true if the frame is complete. Too long frames are dropped
+    pub(super) fn push(&mut self, byte: u8) -> bool {
+        if byte == b':' {
+            // a new frame start, previous garbage is dropped
+            self.frame.truncate(0);
+        } else if self.frame.is_empty() || self.frame.last() == Some(&b'\n') {
+            return false;
+        }
+        self.frame.push(byte);
+        if self.frame.len() > MAX_FRAME_LEN {
+            self.frame.truncate(0);
+            return false;
+        }
+        byte == b'\n'
+    }
+    pub(super) fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+}
+
+/// Sends an RTU request (in `buf`) as ASCII. If a response is expected, it is read and placed
+/// into `buf` as an RTU frame. The client must be locked
+pub(super) fn communicate(
+    client: &Client,
+    buf: &mut Vec<u8>,
+    ascii_buf: &mut Vec<u8>,
+    response: bool,
+) -> Result<()> {
+    encode(buf, ascii_buf);
+    client.write(ascii_buf)?;
+    if !response {
+        return Ok(());
+    }
+    let mut collector = FrameCollector::default();
+    let mut c = [0u8; 1];
+    loop {
+        client.read_exact(&mut c)?;
+        if collector.push(c[0]) {
+            break;
+        }
+    }
+    decode(collector.frame(), buf)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, encode, lrc, FrameCollector};
+
+    #[test]
+    fn test_ascii_framing() {
+        // read 3 holdings from 0x006B, unit 0x11
+        let data = [0x11, 0x03, 0x00, 0x6B, 0x00, 0x03];
+        assert_eq!(lrc(&data), 0x7E);
+        let mut rtu = data.to_vec();
+        rtu.extend([0x00, 0x00]);
+        let mut ascii = Vec::new();
+        encode(&rtu, &mut ascii);
+        assert_eq!(ascii, b":1103006B00037E\r\n");
+        let mut collector = FrameCollector::default();
+        let complete: Vec<bool> = b"\x00:11:1103006B00037E\r\n"
+            .iter()
+            .map(|b| collector.push(*b))
+            .collect();
+        assert_eq!(complete.iter().filter(|c| **c).count(), 1);
+        assert!(complete.last().unwrap());
+        let mut decoded = Vec::new();
+        decode(collector.frame(), &mut decoded).unwrap();
+        assert_eq!(&decoded[..6], data);
+        assert_eq!(decoded[6..], [0x76, 0x87]);
+        assert!(decode(b":1103006B00037F\r\n", &mut decoded).is_err());
+    }
+}
@@ -5,6 +5,8 @@
 //! master(client)](https://github.com/roboplc/roboplc/blob/main/examples/modbus-master.rs),
 //! [modbus slave(server)](https://github.com/roboplc/roboplc/blob/main/examples/modbus-slave.rs)
 use std::io::Cursor;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::comm::{Client, Protocol};
 use crate::{Error, Result};
@@ -15,16 +17,38 @@
 use rmodbus::guess_response_frame_len;
 use rmodbus::{client::ModbusRequest as RModbusRequest, ModbusProto};
 
+#[allow(clippy::module_name_repetitions, clippy::useless_attribute)]
+pub use persistence::{ModbusServerPersistence, ModbusServerPersister};
+#[allow(clippy::module_name_repetitions, clippy::useless_attribute)]
+pub use scanner::ModbusScanner;
 #[allow(clippy::module_name_repetitions, clippy::useless_attribute)]
 pub use server::{
-    AllowFn as ModbusServerAllowFn, ModbusServer, ModbusServerMapping,
-    WritePermission as ModbusServerWritePermission,
+    AllowFn as ModbusServerAllowFn, Deadband, ModbusAccessRule, ModbusArrayElement,
+    ModbusAuditEvent, ModbusServer, ModbusServerHandle, ModbusServerMapping, ModbusServerStopper,
+    ModbusServerStorage, ModbusStorageTransaction, WritePermission as ModbusServerWritePermission,
+    MODBUS_ARRAY_CHUNK_REGISTERS, MODBUS_WRITE_FUNCTIONS,
+};
+#[allow(clippy::module_name_repetitions, clippy::useless_attribute)]
+pub use sniffer::{ModbusFrame, ModbusSniffer, ModbusTransaction, ModbusValues};
+#[allow(clippy::module_name_repetitions, clippy::useless_attribute)]
+pub use subscription::{ModbusChange, ModbusChangeSubscription};
+#[cfg(feature = "modbus-tls")]
+#[allow(clippy::module_name_repetitions, clippy::useless_attribute)]
+pub use tls::{
+    parse_role as parse_tls_role, ModbusTlsConfig, TlsClient, TlsClientValidator, MODBUS_TLS_PORT,
 };
 
-use super::IoMapping;
+use super::{IoMapping, LockedIoMapping};
 
+mod ascii;
+mod persistence;
 mod regs;
+mod scanner;
 mod server;
+mod sniffer;
+mod subscription;
+#[cfg(feature = "modbus-tls")]
+mod tls;
 
 pub mod prelude {
     pub use super::{
@@ -55,36 +79,144 @@ fn to_swapped_modbus_endianness(&self) -> Self {
 impl From<Protocol> for ModbusProto {
     fn from(value: Protocol) -> Self {
         match value {
-            Protocol::Tcp => ModbusProto::TcpUdp,
+            Protocol::Tcp | Protocol::Udp => ModbusProto::TcpUdp,
             Protocol::Serial => ModbusProto::Rtu,
         }
     }
 }
 
+/// Maximum number of registers per read request, allowed by the Modbus specification
+pub const MAX_READ_REGISTERS: u16 = 125;
+/// Maximum number of coils/discretes per read request, allowed by the Modbus specification
+pub const MAX_READ_BITS: u16 = 2000;
+/// Maximum number of holdings per write request, allowed by the Modbus specification
+pub const MAX_WRITE_REGISTERS: u16 = 123;
+/// Maximum number of coils per write request, allowed by the Modbus specification
+pub const MAX_WRITE_BITS: u16 = 1968;
+/// Modbus broadcast unit id (writes to all units on a serial bus, no response is sent)
+pub const BROADCAST_UNIT_ID: u8 = 0;
+
+/// Framing of Modbus client frames
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Framing {
+    /// Derived from the client transport: Modbus TCP (MBAP) for TCP/UDP clients, RTU for serial
+    #[default]
+    Auto,
+    /// RTU frames (with CRC) over a TCP/UDP client, for serial-to-Ethernet converters which
+    /// bridge RTU transparently
+    RtuOverTcp,
+}
+
+/// Write validation hook, called with the written and the read-back raw data (for coils, one
+/// byte per coil). Must return true if the device has accepted the written value
+pub type WriteValidator = fn(written: &[u8], read_back: &[u8]) -> bool;
+
 /// Mapping options for Modbus client
+///
+/// If a mapping contains more registers than allowed per request, reads and bulk writes are
+/// automatically split into several requests. The limits can be lowered for devices and gateways
+/// which do not support the full Modbus PDU size.
 #[allow(clippy::module_name_repetitions)]
 #[derive(Clone)]
 pub struct ModbusMappingOptions {
     bulk_write: bool,
+    max_read_registers: u16,
+    max_read_bits: u16,
+    max_write_registers: u16,
+    max_write_bits: u16,
+    broadcast_delay: Duration,
+    verify_writes: bool,
+    write_validator: Option<WriteValidator>,
+    ascii: bool,
+    framing: Framing,
 }
 
 impl ModbusMappingOptions {
     pub fn new() -> Self {
-        Self { bulk_write: true }
+        Self::default()
     }
     pub fn bulk_write(mut self, value: bool) -> Self {
         self.bulk_write = value;
         self
     }
+    /// Sets the maximum number of input/holding registers per read request (the default is
+    /// [`MAX_READ_REGISTERS`])
+    pub fn max_read_registers(mut self, value: u16) -> Self {
+        self.max_read_registers = value.clamp(1, MAX_READ_REGISTERS);
+        self
+    }
+    /// Sets the maximum number of coils/discretes per read request (the default is
+    /// [`MAX_READ_BITS`])
+    pub fn max_read_bits(mut self, value: u16) -> Self {
+        self.max_read_bits = value.clamp(1, MAX_READ_BITS);
+        self
+    }
+    /// Sets the maximum number of holding registers per bulk write request (the default is
+    /// [`MAX_WRITE_REGISTERS`])
+    pub fn max_write_registers(mut self, value: u16) -> Self {
+        self.max_write_registers = value.clamp(1, MAX_WRITE_REGISTERS);
+        self
+    }
+    /// Sets the maximum number of coils per bulk write request (the default is
+    /// [`MAX_WRITE_BITS`])
+    pub fn max_write_bits(mut self, value: u16) -> Self {
+        self.max_write_bits = value.clamp(1, MAX_WRITE_BITS);
+        self
+    }
+    /// Sets the turnaround delay after broadcast writes, which allows units to process the
+    /// request before the next one (the default is 100ms)
+    pub fn broadcast_delay(mut self, delay: Duration) -> Self {
+        self.broadcast_delay = delay;
+        self
+    }
+    /// Reads the registers back after each write and compares the data with the written one,
+    /// returning [`Error::VerificationFailed`] on mismatch (the default is false). Broadcast
+    /// writes are never verified
+    pub fn verify_writes(mut self, value: bool) -> Self {
+        self.verify_writes = value;
+        self
+    }
+    /// Sets a custom validation hook for written values, which replaces the default byte-to-byte
+    /// compare. Enables write verification
+    pub fn write_validator(mut self, validator: WriteValidator) -> Self {
+        self.verify_writes = true;
+        self.write_validator = Some(validator);
+        self
+    }
+    /// Uses Modbus ASCII framing instead of RTU, for legacy devices (serial clients only, the
+    /// default is false)
+    pub fn ascii(mut self, value: bool) -> Self {
+        self.ascii = value;
+        self
+    }
+    /// Sets the frame format (the default is [`Framing::Auto`])
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
 }
 
 impl Default for ModbusMappingOptions {
     fn default() -> Self {
-        Self { bulk_write: true }
+        Self {
+            bulk_write: true,
+            max_read_registers: MAX_READ_REGISTERS,
+            max_read_bits: MAX_READ_BITS,
+            max_write_registers: MAX_WRITE_REGISTERS,
+            max_write_bits: MAX_WRITE_BITS,
+            broadcast_delay: Duration::from_millis(100),
+            verify_writes: false,
+            write_validator: None,
+            ascii: false,
+            framing: Framing::Auto,
+        }
     }
 }
 
 /// Mapping for Modbus client
+///
+/// Mappings with unit id 0 ([`BROADCAST_UNIT_ID`]) on serial buses are broadcast mappings (e.g.
+/// for time synchronization of all units): they are write-only and responses are not expected.
 #[allow(clippy::module_name_repetitions)]
 pub struct ModbusMapping {
     client: Client,
@@ -121,107 +253,243 @@ pub fn with_options(mut self, options: ModbusMappingOptions) -> Self {
         self.options = options;
         self
     }
+    /// Returns true if the mapping is a broadcast one (unit id 0 on a serial bus, including buses
+    /// behind RTU-over-TCP converters). Modbus TCP devices usually treat unit id 0 as a regular
+    /// address, so there are no TCP broadcasts
+    pub fn is_broadcast(&self) -> bool {
+        self.unit_id == BROADCAST_UNIT_ID && matches!(self.proto(), ModbusProto::Rtu)
+    }
+    // the frame protocol
+    fn proto(&self) -> ModbusProto {
+        match self.options.framing {
+            Framing::Auto => self.client.protocol().into(),
+            Framing::RtuOverTcp => ModbusProto::Rtu,
+        }
+    }
+    fn is_ascii(&self) -> bool {
+        self.options.ascii && matches!(self.client.protocol(), Protocol::Serial)
+    }
+    /// Writes a numeric value, reads it back and compares with the given tolerance (useful for
+    /// floats, which may be rounded by devices). Returns [`Error::VerificationFailed`] on
+    /// mismatch. Not suitable for values with swapped endianness
+    pub fn write_with_tolerance<T>(&mut self, value: T, tolerance: f64) -> Result<()>
+    where
+        T: for<'a> BinRead<Args<'a> = ()> + for<'a> BinWrite<Args<'a> = ()> + Into<f64> + Copy,
+    {
+        if self.is_broadcast() {
+            return Err(Error::invalid_data("broadcast writes can not be verified"));
+        }
+        let client = self.client.clone();
+        let _lock = client.lock();
+        self.serialize(value)?;
+        self.write_data()?;
+        self.read_data()?;
+        let read_back: T = T::read_be(&mut Cursor::new(&self.data_buf))?;
+        let (written, read_back): (f64, f64) = (value.into(), read_back.into());
+        if (written - read_back).abs() > tolerance {
+            return Err(Error::VerificationFailed(format!(
+                "{:?} {}: written {}, read back {}",
+                self.register.kind, self.register.offset, written, read_back
+            )));
+        }
+        Ok(())
+    }
+    fn serialize<T>(&mut self, value: T) -> Result<()>
+    where
+        T: for<'a> BinWrite<Args<'a> = ()>,
+    {
+        self.data_buf.truncate(0);
+        let mut data_buf = Cursor::new(&mut self.data_buf);
+        value.write_be(&mut data_buf)?;
+        Ok(())
+    }
+    // the client must be locked
+    fn verify_written(&mut self) -> Result<()> {
+        if !self.options.verify_writes || self.is_broadcast() {
+            return Ok(());
+        }
+        let written = std::mem::take(&mut self.data_buf);
+        let result = self.read_data();
+        let result = result.and_then(|()| {
+            let read_back = self.data_buf.get(..written.len()).unwrap_or(&self.data_buf);
+            let valid = if let Some(validator) = self.options.write_validator {
+                validator(&written, read_back)
+            } else {
+                data_matches(self.register.kind, &written, read_back)
+            };
+            if valid {
+                Ok(())
+            } else {
+                Err(Error::VerificationFailed(format!(
+                    "{:?} {}: written {:?}, read back {:?}",
+                    self.register.kind, self.register.offset, written, read_back
+                )))
+            }
+        });
+        // keep the pre-allocated buffer
+        self.data_buf = written;
+        result
+    }
+}
+
+fn data_matches(kind: ModbusRegisterKind, written: &[u8], read_back: &[u8]) -> bool {
+    if written.len() != read_back.len() {
+        return false;
+    }
+    if kind == ModbusRegisterKind::Coil {
+        // any non-zero byte is written as "on"
+        written
+            .iter()
+            .zip(read_back)
+            .all(|(w, r)| (*w != 0) == (*r != 0))
+    } else {
+        written == read_back
+    }
 }
 
 macro_rules! prepare_transaction {
     ($self: expr) => {{
-        let mut mreq = RModbusRequest::new($self.unit_id, $self.client.protocol().into());
+        let mut mreq = RModbusRequest::new($self.unit_id, $self.proto());
         mreq.tr_id = $self.request_id;
-        $self.request_id += 1;
+        $self.request_id = $self.request_id.wrapping_add(1);
         $self.buf.truncate(0);
         mreq
     }};
 }
 
 macro_rules! communicate {
-    ($self: expr) => {
-        $self.client.write(&$self.buf)?;
-        let mut buf = [0u8; 6];
-        $self.client.read_exact(&mut buf)?;
-        $self.buf.truncate(0);
-        $self.buf.extend(buf);
-        let len = guess_response_frame_len(&buf, $self.client.protocol().into())?;
-        if len > 6 {
-            $self.rest_buf.resize(usize::from(len - 6), 0);
-            $self.client.read_exact(&mut $self.rest_buf)?;
-            $self.buf.extend(&$self.rest_buf);
+    ($self: expr, $tr_id: expr) => {
+        if $self.is_ascii() {
+            ascii::communicate(&$self.client, &mut $self.buf, &mut $self.rest_buf, true)?;
+        } else {
+            $self.client.write(&$self.buf)?;
+            loop {
+                let mut buf = [0u8; 6];
+                $self.client.read_exact(&mut buf)?;
+                $self.buf.truncate(0);
+                $self.buf.extend(buf);
+                let len = guess_response_frame_len(&buf, $self.proto())?;
+                if len > 6 {
+                    $self.rest_buf.resize(usize::from(len - 6), 0);
+                    $self.client.read_exact(&mut $self.rest_buf)?;
+                    $self.buf.extend(&$self.rest_buf);
+                }
+                if matches!($self.proto(), ModbusProto::TcpUdp) && buf[..2] != $tr_id.to_be_bytes()
+                {
+                    // late UDP responses to previous (or retransmitted) requests are skipped
+                    if matches!($self.client.protocol(), Protocol::Udp) {
+                        continue;
+                    }
+                    // the stream is out of sync
+                    $self.client.reconnect();
+                    return Err(Error::invalid_data(format!(
+                        "modbus response transaction id mismatch: expected {}, received {}",
+                        $tr_id,
+                        u16::from_be_bytes([buf[0], buf[1]])
+                    )));
+                }
+                break;
+            }
         }
     };
 }
 
-impl IoMapping for ModbusMapping {
-    type Options = ModbusMappingOptions;
-    fn read<T>(&mut self) -> Result<T>
-    where
-        T: for<'a> BinRead<Args<'a> = ()>,
-    {
-        let _lock = self.client.lock();
-        let mut mreq = prepare_transaction!(self);
-        match self.register.kind {
-            ModbusRegisterKind::Coil => {
-                mreq.generate_get_coils(self.register.offset, self.count, &mut self.buf)?;
-            }
-            ModbusRegisterKind::Discrete => {
-                mreq.generate_get_discretes(self.register.offset, self.count, &mut self.buf)?;
-            }
-            ModbusRegisterKind::Input => {
-                mreq.generate_get_inputs(self.register.offset, self.count, &mut self.buf)?;
-            }
-            ModbusRegisterKind::Holding => {
-                mreq.generate_get_holdings(self.register.offset, self.count, &mut self.buf)?;
-            }
-        };
-        communicate!(self);
-        match self.register.kind {
-            ModbusRegisterKind::Coil | ModbusRegisterKind::Discrete => {
-                self.data_buf.truncate(0);
-                mreq.parse_bool_u8(&self.buf, &mut self.data_buf)?;
-                let mut reader = Cursor::new(&self.data_buf);
-                T::read_be(&mut reader).map_err(Into::into)
-            }
-            ModbusRegisterKind::Input | ModbusRegisterKind::Holding => {
-                let data = mreq.parse_slice(&self.buf)?;
-                if data.is_empty() {
-                    return Err(Error::invalid_data("invalid modbus response"));
-                }
-                let mut reader = Cursor::new(data);
-                T::read_be(&mut reader).map_err(Into::into)
+// broadcast requests are not responded
+macro_rules! communicate_write {
+    ($self: expr, $mreq: expr) => {
+        if $self.is_broadcast() {
+            if $self.is_ascii() {
+                ascii::communicate(&$self.client, &mut $self.buf, &mut $self.rest_buf, false)?;
+            } else {
+                $self.client.write(&$self.buf)?;
             }
+            thread::sleep($self.options.broadcast_delay);
+        } else {
+            communicate!($self, $mreq.tr_id);
+            $mreq.parse_ok(&$self.buf)?;
         }
-    }
+    };
+}
 
-    fn write<T>(&mut self, value: T) -> Result<()>
-    where
-        T: for<'a> BinWrite<Args<'a> = ()>,
-    {
-        let _lock = self.client.lock();
-        let mut data_buf = Cursor::new(&mut self.data_buf);
-        value.write_be(&mut data_buf)?;
-        if self.options.bulk_write {
+impl ModbusMapping {
+    // reads the mapping data into data_buf, the client must be locked
+    fn read_data(&mut self) -> Result<()> {
+        let max_count = match self.register.kind {
+            ModbusRegisterKind::Coil | ModbusRegisterKind::Discrete => self.options.max_read_bits,
+            ModbusRegisterKind::Input | ModbusRegisterKind::Holding => {
+                self.options.max_read_registers
+            }
+        };
+        self.data_buf.truncate(0);
+        let mut offset = self.register.offset;
+        let mut remaining = self.count;
+        // large mappings are read with several requests, the data is reassembled in data_buf
+        while remaining > 0 {
+            let count = remaining.min(max_count);
             let mut mreq = prepare_transaction!(self);
             match self.register.kind {
                 ModbusRegisterKind::Coil => {
-                    mreq.generate_set_coils_bulk(
-                        self.register.offset,
-                        &self.data_buf,
-                        &mut self.buf,
-                    )?;
+                    mreq.generate_get_coils(offset, count, &mut self.buf)?;
+                }
+                ModbusRegisterKind::Discrete => {
+                    mreq.generate_get_discretes(offset, count, &mut self.buf)?;
+                }
+                ModbusRegisterKind::Input => {
+                    mreq.generate_get_inputs(offset, count, &mut self.buf)?;
                 }
                 ModbusRegisterKind::Holding => {
-                    mreq.generate_set_holdings_bulk_from_slice(
-                        self.register.offset,
-                        &self.data_buf,
-                        &mut self.buf,
-                    )?;
+                    mreq.generate_get_holdings(offset, count, &mut self.buf)?;
                 }
+            };
+            communicate!(self, mreq.tr_id);
+            match self.register.kind {
+                ModbusRegisterKind::Coil | ModbusRegisterKind::Discrete => {
+                    let len = self.data_buf.len();
+                    mreq.parse_bool_u8(&self.buf, &mut self.data_buf)?;
+                    if remaining > count {
+                        // drop padding bits of the response byte
+                        self.data_buf.truncate(len + usize::from(count));
+                    }
+                }
+                ModbusRegisterKind::Input | ModbusRegisterKind::Holding => {
+                    let data = mreq.parse_slice(&self.buf)?;
+                    if data.is_empty() {
+                        return Err(Error::invalid_data("invalid modbus response"));
+                    }
+                    self.data_buf.extend(data);
+                }
+            }
+            offset = offset.wrapping_add(count);
+            remaining -= count;
+        }
+        Ok(())
+    }
+
+    // writes data_buf to the mapping, the client must be locked
+    fn write_data(&mut self) -> Result<()> {
+        if self.options.bulk_write {
+            // (registers per request, bytes per register)
+            let (max_count, width) = match self.register.kind {
+                ModbusRegisterKind::Coil => (self.options.max_write_bits, 1),
+                ModbusRegisterKind::Holding => (self.options.max_write_registers, 2),
                 ModbusRegisterKind::Discrete | ModbusRegisterKind::Input => {
                     return Err(Error::IO(
                         "unsupported modbus register kind for writing".to_owned(),
                     ));
                 }
+            };
+            let mut offset = self.register.offset;
+            // large values are written with several requests
+            for chunk in self.data_buf.chunks(usize::from(max_count) * width) {
+                let mut mreq = prepare_transaction!(self);
+                if self.register.kind == ModbusRegisterKind::Coil {
+                    mreq.generate_set_coils_bulk(offset, chunk, &mut self.buf)?;
+                } else {
+                    mreq.generate_set_holdings_bulk_from_slice(offset, chunk, &mut self.buf)?;
+                }
+                communicate_write!(self, mreq);
+                offset = offset.wrapping_add(max_count);
             }
-            communicate!(self);
-            mreq.parse_ok(&self.buf)?;
         } else {
             let mut i = 0;
             for offset in self.register.offset..self.register.offset + self.count {
@@ -248,10 +516,201 @@ fn write<T>(&mut self, value: T) -> Result<()>
                         ));
                     }
                 }
-                communicate!(self);
-                mreq.parse_ok(&self.buf)?;
+                communicate_write!(self, mreq);
             }
         }
         Ok(())
     }
 }
+
+impl IoMapping for ModbusMapping {
+    type Options = ModbusMappingOptions;
+    fn read<T>(&mut self) -> Result<T>
+    where
+        T: for<'a> BinRead<Args<'a> = ()>,
+    {
+        let client = self.client.clone();
+        let _lock = client.lock();
+        self.read_locked()
+    }
+
+    fn write<T>(&mut self, value: T) -> Result<()>
+    where
+        T: for<'a> BinWrite<Args<'a> = ()>,
+    {
+        let client = self.client.clone();
+        let _lock = client.lock();
+        self.write_locked(value)
+    }
+}
+
+impl LockedIoMapping for ModbusMapping {
+    fn client(&self) -> &Client {
+        &self.client
+    }
+
+    fn read_locked<T>(&mut self) -> Result<T>
+    where
+        T: for<'a> BinRead<Args<'a> = ()>,
+    {
+        if self.is_broadcast() {
+            return Err(Error::invalid_data("broadcast mappings are write-only"));
+        }
+        self.read_data()?;
+        let mut reader = Cursor::new(&self.data_buf);
+        T::read_be(&mut reader).map_err(Into::into)
+    }
+
+    fn write_locked<T>(&mut self, value: T) -> Result<()>
+    where
+        T: for<'a> BinWrite<Args<'a> = ()>,
+    {
+        self.serialize(value)?;
+        self.write_data()?;
+        self.verify_written()
+    }
+}
+
+/// A unit, found by [`scan_units()`]
+#[derive(Debug, Clone, Copy)]
+pub struct UnitScan {
+    pub unit_id: u8,
+    /// Request round-trip time
+    pub rtt: Duration,
+    /// Modbus exception code, if the unit responded with an exception (e.g. the probe register
+    /// does not exist). Such units are present on the bus
+    pub exception: Option<u8>,
+}
+
+/// Probes units on a bus (for commissioning and bus health checks) by reading a single register
+/// from each and returns responding units. Units which respond with Modbus exceptions are
+/// reported as well. The client timeouts should be short, as missing units are detected by
+/// timeouts. The broadcast unit id is skipped.
+pub fn scan_units<I, R>(client: &Client, units: I, probe: R) -> Result<Vec<UnitScan>>
+where
+    I: IntoIterator<Item = u8>,
+    R: TryInto<ModbusRegister>,
+    Error: From<<R as TryInto<ModbusRegister>>::Error>,
+{
+    let probe: ModbusRegister = probe.try_into()?;
+    let proto: ModbusProto = client.protocol().into();
+    // function code offset in response frames
+    let fn_offset = match proto {
+        ModbusProto::TcpUdp => 7,
+        _ => 1,
+    };
+    let _lock = client.lock();
+    let mut buf = Vec::with_capacity(256);
+    let mut result = Vec::new();
+    for (tr_id, unit_id) in (1u16..).zip(units) {
+        if unit_id == BROADCAST_UNIT_ID {
+            continue;
+        }
+        let mut mreq = RModbusRequest::new(unit_id, proto);
+        mreq.tr_id = tr_id;
+        buf.truncate(0);
+        match probe.kind {
+            ModbusRegisterKind::Coil => mreq.generate_get_coils(probe.offset, 1, &mut buf)?,
+            ModbusRegisterKind::Discrete => {
+                mreq.generate_get_discretes(probe.offset, 1, &mut buf)?;
+            }
+            ModbusRegisterKind::Input => mreq.generate_get_inputs(probe.offset, 1, &mut buf)?,
+            ModbusRegisterKind::Holding => {
+                mreq.generate_get_holdings(probe.offset, 1, &mut buf)?;
+            }
+        }
+        let started = Instant::now();
+        match probe_unit(client, proto, &mut buf) {
+            Ok(()) => {
+                let rtt = started.elapsed();
+                let exception = match mreq.parse_ok(&buf) {
+                    Ok(()) => None,
+                    Err(_) => match buf.get(fn_offset..=fn_offset + 1) {
+                        Some(&[func, code]) if func & 0x80 != 0 => Some(code),
+                        // a broken frame, e.g. a collision or a wrong CRC
+                        _ => continue,
+                    },
+                };
+                result.push(UnitScan {
+                    unit_id,
+                    rtt,
+                    exception,
+                });
+            }
+            Err(_) => {
+                // drop a possibly late response
+                client.reconnect();
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn probe_unit(client: &Client, proto: ModbusProto, buf: &mut Vec<u8>) -> Result<()> {
+    client.write(buf)?;
+    let mut head = [0u8; 6];
+    client.read_exact(&mut head)?;
+    buf.truncate(0);
+    buf.extend(head);
+    let len = guess_response_frame_len(&head, proto)?;
+    if len > 6 {
+        let mut rest = vec![0u8; usize::from(len - 6)];
+        client.read_exact(&mut rest)?;
+        buf.extend(rest);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{data_matches, ModbusMapping, ModbusRegisterKind};
+    use crate::comm::udp;
+    use crate::io::IoMapping;
+    use std::net::UdpSocket;
+    use std::thread;
+    use std::time::Duration;
+
+    // a Modbus UDP response to "read holding registers" with a single register
+    fn response(tr_id: u16, value: u16) -> Vec<u8> {
+        let mut frame = tr_id.to_be_bytes().to_vec();
+        frame.extend([0, 0, 0, 5, 1, 3, 2]);
+        frame.extend(value.to_be_bytes());
+        frame
+    }
+
+    #[test]
+    fn test_udp_late_responses() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut buf = [0u8; 256];
+            for value in [100, 200] {
+                let (_, peer) = server.recv_from(&mut buf).unwrap();
+                let tr_id = u16::from_be_bytes([buf[0], buf[1]]);
+                // two late replies to previous requests, then the actual one
+                for late in [tr_id.wrapping_sub(2), tr_id.wrapping_sub(1)] {
+                    server.send_to(&response(late, 0xffff), peer).unwrap();
+                }
+                server.send_to(&response(tr_id, value), peer).unwrap();
+            }
+        });
+        let client = udp::connect(addr, Duration::from_secs(1)).unwrap();
+        let mut mapping = ModbusMapping::create(&client, 1, "h0", 1).unwrap();
+        assert_eq!(mapping.read::<u16>().unwrap(), 100);
+        assert_eq!(mapping.read::<u16>().unwrap(), 200);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_data_matches() {
+        assert!(data_matches(
+            ModbusRegisterKind::Coil,
+            &[1, 0, 2],
+            &[1, 0, 1]
+        ));
+        assert!(!data_matches(ModbusRegisterKind::Coil, &[1, 0], &[0, 0]));
+        assert!(data_matches(ModbusRegisterKind::Holding, &[0, 1], &[0, 1]));
+        assert!(!data_matches(ModbusRegisterKind::Holding, &[0, 1], &[0, 2]));
+        assert!(!data_matches(ModbusRegisterKind::Holding, &[0, 1], &[0]));
+    }
+}
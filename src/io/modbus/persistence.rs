@@ -0,0 +1,309 @@
+use std::{
+    fs,
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use parking_lot_rt::Mutex;
+use rmodbus::server::storage::ModbusStorage;
+use tracing::{error, warn};
+
+use super::{ModbusRegister, ModbusRegisterKind};
+use crate::{Error, Result};
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"RPMS";
+const SNAPSHOT_VERSION: u8 = 1;
+
+/// Persistence of server storage context register ranges. Snapshots are written atomically (to a
+/// temporary file which is renamed) and only after a certain number of changes is accumulated or
+/// the maximum delay has passed, which spares flash wear on embedded systems.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug)]
+pub struct ModbusServerPersistence {
+    path: PathBuf,
+    ranges: Vec<(ModbusRegister, u16)>,
+    min_changes: u64,
+    max_delay: Duration,
+    interval: Duration,
+}
+
+impl ModbusServerPersistence {
+    /// Creates a new persistence config with the snapshot file path
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+            ranges: Vec::new(),
+            min_changes: 100,
+            max_delay: Duration::from_secs(10),
+            interval: Duration::from_secs(1),
+        }
+    }
+    /// Adds a register range to persist
+    pub fn range(mut self, register: ModbusRegister, count: u16) -> Self {
+        self.ranges.push((register, count));
+        self
+    }
+    /// Sets the number of register changes after which a snapshot is written (the default is
+    /// 100)
+    pub fn min_changes(mut self, min_changes: u64) -> Self {
+        self.min_changes = min_changes;
+        self
+    }
+    /// Sets the maximum delay after which pending changes are written regardless of their number
+    /// (the default is 10 seconds)
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+    /// Sets the pending change check interval (the default is 1 second)
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+    pub(super) fn restore<const C: usize, const D: usize, const I: usize, const H: usize>(
+        &self,
+        storage: &mut ModbusStorage<C, D, I, H>,
+    ) -> Result<()> {
+        let data = match fs::read(&self.path) {
+            Ok(v) => v,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        for (register, count, values) in decode_snapshot(&data)? {
+            if !self.ranges.contains(&(register, count)) {
+                warn!(
+                    ?register,
+                    count, "snapshot range is not configured, skipped"
+                );
+                continue;
+            }
+            match register.kind {
+                ModbusRegisterKind::Coil => {
+                    storage.set_coils_from_u8_bytes(register.offset, values)
+                }
+                ModbusRegisterKind::Discrete => {
+                    storage.set_discretes_from_u8_bytes(register.offset, values)
+                }
+                ModbusRegisterKind::Input => storage.set_inputs_from_u8(register.offset, values),
+                ModbusRegisterKind::Holding => {
+                    storage.set_holdings_from_u8(register.offset, values)
+                }
+            }
+            .map_err(Error::io)?;
+        }
+        Ok(())
+    }
+    fn snapshot<const C: usize, const D: usize, const I: usize, const H: usize>(
+        &self,
+        storage: &ModbusStorage<C, D, I, H>,
+    ) -> Result<Vec<u8>> {
+        let mut ranges = Vec::with_capacity(self.ranges.len());
+        for &(register, count) in &self.ranges {
+            let mut values = Vec::new();
+            match register.kind {
+                ModbusRegisterKind::Coil => {
+                    storage.get_coils_as_u8_bytes(register.offset, count, &mut values)
+                }
+                ModbusRegisterKind::Discrete => {
+                    storage.get_discretes_as_u8_bytes(register.offset, count, &mut values)
+                }
+                ModbusRegisterKind::Input => {
+                    storage.get_inputs_as_u8(register.offset, count, &mut values)
+                }
+                ModbusRegisterKind::Holding => {
+                    storage.get_holdings_as_u8(register.offset, count, &mut values)
+                }
+            }
+            .map_err(Error::io)?;
+            ranges.push((register, count, values));
+        }
+        Ok(encode_snapshot(&ranges))
+    }
+}
+
+/// Writes storage context snapshots, created with [`super::ModbusServer::persister()`]. Must be
+/// run in a separate thread, [`ModbusServerPersister::flush()`] should be also called on program
+/// shutdown.
+#[allow(clippy::module_name_repetitions)]
+pub struct ModbusServerPersister<const C: usize, const D: usize, const I: usize, const H: usize> {
+    storage: Arc<Mutex<ModbusStorage<C, D, I, H>>>,
+    persistence: Arc<ModbusServerPersistence>,
+    changes: Arc<AtomicU64>,
+    flushed: u64,
+    last_flush: Instant,
+}
+
+impl<const C: usize, const D: usize, const I: usize, const H: usize>
+    ModbusServerPersister<C, D, I, H>
+{
+    pub(super) fn new(
+        storage: Arc<Mutex<ModbusStorage<C, D, I, H>>>,
+        persistence: Arc<ModbusServerPersistence>,
+        changes: Arc<AtomicU64>,
+    ) -> Self {
+        let flushed = changes.load(Ordering::SeqCst);
+        Self {
+            storage,
+            persistence,
+            changes,
+            flushed,
+            last_flush: Instant::now(),
+        }
+    }
+    /// Number of register changes not written to the snapshot yet
+    pub fn pending_changes(&self) -> u64 {
+        self.changes
+            .load(Ordering::SeqCst)
+            .wrapping_sub(self.flushed)
+    }
+    /// Writes the snapshot if there are pending changes. Returns true if the snapshot has been
+    /// written
+    pub fn flush(&mut self) -> Result<bool> {
+        let changes = self.changes.load(Ordering::SeqCst);
+        if changes == self.flushed {
+            return Ok(false);
+        }
+        let data = self.persistence.snapshot(&*self.storage.lock())?;
+        write_atomic(&self.persistence.path, &data)?;
+        self.flushed = changes;
+        self.last_flush = Instant::now();
+        Ok(true)
+    }
+    /// Writes the snapshot if either the minimum number of changes is accumulated or the maximum
+    /// delay has passed. Returns true if the snapshot has been written
+    pub fn flush_if_required(&mut self) -> Result<bool> {
+        let pending = self.pending_changes();
+        if pending >= self.persistence.min_changes
+            || (pending > 0 && self.last_flush.elapsed() >= self.persistence.max_delay)
+        {
+            self.flush()
+        } else {
+            Ok(false)
+        }
+    }
+    /// Runs the persister loop. Never returns, write errors are logged
+    pub fn run(&mut self) {
+        loop {
+            thread::sleep(self.persistence.interval);
+            if let Err(error) = self.flush_if_required() {
+                error!(
+                    %error,
+                    path=%self.persistence.path.display(),
+                    "unable to write Modbus storage snapshot"
+                );
+            }
+        }
+    }
+}
+
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut f = fs::File::create(&tmp)?;
+    f.write_all(data)?;
+    f.sync_all()?;
+    drop(f);
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn kind_to_u8(kind: ModbusRegisterKind) -> u8 {
+    match kind {
+        ModbusRegisterKind::Coil => 0,
+        ModbusRegisterKind::Discrete => 1,
+        ModbusRegisterKind::Input => 2,
+        ModbusRegisterKind::Holding => 3,
+    }
+}
+
+fn kind_from_u8(v: u8) -> Result<ModbusRegisterKind> {
+    match v {
+        0 => Ok(ModbusRegisterKind::Coil),
+        1 => Ok(ModbusRegisterKind::Discrete),
+        2 => Ok(ModbusRegisterKind::Input),
+        3 => Ok(ModbusRegisterKind::Holding),
+        v => Err(Error::invalid_data(format!("invalid register kind: {}", v))),
+    }
+}
+
+// magic, version, then ranges: kind (u8), offset (u16), count (u16), data len (u32), data
+fn encode_snapshot(ranges: &[(ModbusRegister, u16, Vec<u8>)]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend(SNAPSHOT_MAGIC);
+    buf.push(SNAPSHOT_VERSION);
+    for (register, count, values) in ranges {
+        buf.push(kind_to_u8(register.kind));
+        buf.extend(register.offset.to_be_bytes());
+        buf.extend(count.to_be_bytes());
+        #[allow(clippy::cast_possible_truncation)]
+        buf.extend((values.len() as u32).to_be_bytes());
+        buf.extend(values);
+    }
+    buf
+}
+
+fn decode_snapshot(data: &[u8]) -> Result<Vec<(ModbusRegister, u16, &[u8])>> {
+    let invalid = || Error::invalid_data("invalid Modbus storage snapshot");
+    let mut data = data
+        .strip_prefix(SNAPSHOT_MAGIC)
+        .ok_or_else(invalid)?
+        .strip_prefix(&[SNAPSHOT_VERSION])
+        .ok_or_else(invalid)?;
+    let mut ranges = Vec::new();
+    while !data.is_empty() {
+        if data.len() < 9 {
+            return Err(invalid());
+        }
+        let kind = kind_from_u8(data[0])?;
+        let offset = u16::from_be_bytes([data[1], data[2]]);
+        let count = u16::from_be_bytes([data[3], data[4]]);
+        let len = usize::try_from(u32::from_be_bytes([data[5], data[6], data[7], data[8]]))
+            .map_err(Error::invalid_data)?;
+        data = &data[9..];
+        if data.len() < len {
+            return Err(invalid());
+        }
+        ranges.push((ModbusRegister::new(kind, offset), count, &data[..len]));
+        data = &data[len..];
+    }
+    Ok(ranges)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_snapshot, encode_snapshot};
+    use crate::io::modbus::{ModbusRegister, ModbusRegisterKind};
+
+    #[test]
+    fn test_snapshot_encode_decode() {
+        let ranges = vec![
+            (
+                ModbusRegister::new(ModbusRegisterKind::Holding, 100),
+                2,
+                vec![0, 1, 0, 2],
+            ),
+            (
+                ModbusRegister::new(ModbusRegisterKind::Coil, 5),
+                3,
+                vec![1, 0, 1],
+            ),
+        ];
+        let data = encode_snapshot(&ranges);
+        let decoded = decode_snapshot(&data).unwrap();
+        assert_eq!(decoded.len(), 2);
+        for ((reg, count, values), (d_reg, d_count, d_values)) in ranges.iter().zip(decoded) {
+            assert_eq!(*reg, d_reg);
+            assert_eq!(*count, d_count);
+            assert_eq!(values.as_slice(), d_values);
+        }
+        assert!(decode_snapshot(&data[..data.len() - 1]).is_err());
+        assert!(decode_snapshot(b"XXXX").is_err());
+    }
+}
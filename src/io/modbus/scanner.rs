@@ -0,0 +1,304 @@
+use std::io::Cursor;
+use std::time::Duration;
+
+use binrw::BinRead;
+use rtsc::data_policy::DataDeliveryPolicy;
+use tracing::warn;
+
+use super::{ModbusMapping, ModbusMappingOptions, ModbusRegister, ModbusRegisterKind};
+use crate::comm::Client;
+use crate::{hub::Hub, Error, Result};
+
+type Decoder<D> = Box<dyn Fn(&[u8]) -> Result<D> + Send + Sync>;
+
+struct Block<D> {
+    unit_id: u8,
+    register: ModbusRegister,
+    count: u16,
+    decode: Decoder<D>,
+}
+
+// a coalesced range, read with a single request
+struct Batch {
+    mapping: ModbusMapping,
+    offset: u16,
+    blocks: Vec<usize>,
+}
+
+/// Scan engine for Modbus client mappings. Owns multiple register blocks, coalesces adjacent
+/// (and, optionally, close) ranges of the same unit and register kind into single requests,
+/// polls them on a fixed interval and publishes decoded values to a hub.
+///
+/// Gap registers are not mapped by the scanner, so [`ModbusScanner::max_gap()`] should be set
+/// only if a device allows reading them.
+///
+/// ```rust,no_run
+/// use binrw::BinRead;
+/// use roboplc::comm::tcp;
+/// use roboplc::io::modbus::ModbusScanner;
+/// use roboplc::prelude::*;
+/// use std::time::Duration;
+///
+/// #[derive(BinRead, Clone)]
+/// struct Env {
+///     temp: f32,
+///     hum: f32,
+/// }
+///
+/// #[derive(DataPolicy, Clone)]
+/// enum Message {
+///     Env(Env),
+///     Pressure(u16),
+/// }
+///
+/// let hub: Hub<Message> = Hub::new();
+/// let client = tcp::connect("10.0.0.10:502", Duration::from_secs(1)).unwrap();
+/// let mut scanner = ModbusScanner::new(&client, Duration::from_millis(500));
+/// // both blocks are read with a single request
+/// scanner.add(1, "h0", 4, Message::Env).unwrap();
+/// scanner.add(1, "h4", 1, Message::Pressure).unwrap();
+/// scanner.run(&hub, || true);
+/// ```
+#[allow(clippy::module_name_repetitions)]
+pub struct ModbusScanner<D> {
+    client: Client,
+    period: Duration,
+    options: ModbusMappingOptions,
+    max_gap: u16,
+    blocks: Vec<Block<D>>,
+    batches: Vec<Batch>,
+}
+
+impl<D> ModbusScanner<D>
+where
+    D: DataDeliveryPolicy + Clone,
+{
+    pub fn new(client: &Client, period: Duration) -> Self {
+        Self {
+            client: client.clone(),
+            period,
+            options: <_>::default(),
+            max_gap: 0,
+            blocks: Vec::new(),
+            batches: Vec::new(),
+        }
+    }
+    /// Mapping options, used for all requests. The maximum number of registers/bits per request
+    /// limits coalescing
+    pub fn with_options(mut self, options: ModbusMappingOptions) -> Self {
+        self.options = options;
+        self.plan();
+        self
+    }
+    /// The maximum number of unmapped registers (bits for coils and discretes) between blocks,
+    /// which are read to coalesce the blocks into a single request (the default is 0)
+    pub fn max_gap(mut self, max_gap: u16) -> Self {
+        self.max_gap = max_gap;
+        self.plan();
+        self
+    }
+    /// Adds a register block. The block data is decoded as big-endian (as
+    /// [`ModbusMapping::read()`] does) and converted into a hub message with the function
+    pub fn add<R, T, F>(&mut self, unit_id: u8, register: R, count: u16, f: F) -> Result<()>
+    where
+        R: TryInto<ModbusRegister>,
+        Error: From<<R as TryInto<ModbusRegister>>::Error>,
+        T: for<'a> BinRead<Args<'a> = ()>,
+        F: Fn(T) -> D + Send + Sync + 'static,
+    {
+        let register = register.try_into()?;
+        if count == 0 {
+            return Err(Error::invalid_data("empty modbus scanner block"));
+        }
+        if u32::from(register.offset) + u32::from(count) > u32::from(u16::MAX) + 1 {
+            return Err(Error::invalid_data("modbus scanner block is out of range"));
+        }
+        if ModbusMapping::create(&self.client, unit_id, register, count)?.is_broadcast() {
+            return Err(Error::invalid_data("broadcast mappings are write-only"));
+        }
+        self.blocks.push(Block {
+            unit_id,
+            register,
+            count,
+            decode: Box::new(move |data| {
+                let value = T::read_be(&mut Cursor::new(data))?;
+                Ok(f(value))
+            }),
+        });
+        self.plan();
+        Ok(())
+    }
+    /// The number of requests per scan
+    pub fn requests(&self) -> usize {
+        self.batches.len()
+    }
+    /// Reads all blocks once and publishes decoded values to the hub. Failed requests are logged
+    /// and skipped, returns the number of failed requests
+    pub fn scan(&mut self, hub: &Hub<D>) -> usize {
+        let mut failed = 0;
+        for batch in &mut self.batches {
+            let client = batch.mapping.client.clone();
+            let _lock = client.lock();
+            if let Err(e) = batch.mapping.read_data() {
+                warn!(
+                    unit_id = batch.mapping.unit_id,
+                    register = ?batch.mapping.register,
+                    count = batch.mapping.count,
+                    error = %e,
+                    "modbus scan request failed"
+                );
+                failed += 1;
+                continue;
+            }
+            for &i in &batch.blocks {
+                let block = &self.blocks[i];
+                let width = width(block.register.kind);
+                let start = usize::from(block.register.offset - batch.offset) * width;
+                let end = start + usize::from(block.count) * width;
+                let Some(data) = batch.mapping.data_buf.get(start..end) else {
+                    warn!(register = ?block.register, "modbus scan response is too short");
+                    continue;
+                };
+                match (block.decode)(data) {
+                    Ok(message) => hub.send(message),
+                    Err(e) => {
+                        warn!(register = ?block.register, error = %e, "modbus scan decode error");
+                    }
+                }
+            }
+        }
+        failed
+    }
+    /// Scans blocks on the interval while the online function returns true
+    pub fn run<O>(&mut self, hub: &Hub<D>, online: O)
+    where
+        O: Fn() -> bool,
+    {
+        let mut interval = crate::time::interval(self.period);
+        while online() {
+            interval.tick();
+            if !online() {
+                break;
+            }
+            self.scan(hub);
+        }
+    }
+    // re-creates batches for the current blocks and options
+    fn plan(&mut self) {
+        let ranges: Vec<(u8, ModbusRegister, u16)> = self
+            .blocks
+            .iter()
+            .map(|b| (b.unit_id, b.register, b.count))
+            .collect();
+        self.batches = coalesce(&ranges, &self.options, self.max_gap)
+            .into_iter()
+            .map(|(unit_id, register, count, blocks)| Batch {
+                // never fails as the register is already parsed
+                mapping: ModbusMapping::create(&self.client, unit_id, register, count)
+                    .unwrap()
+                    .with_options(self.options.clone()),
+                offset: register.offset,
+                blocks,
+            })
+            .collect();
+    }
+}
+
+// bytes per register in the read data
+fn width(kind: ModbusRegisterKind) -> usize {
+    match kind {
+        ModbusRegisterKind::Coil | ModbusRegisterKind::Discrete => 1,
+        ModbusRegisterKind::Input | ModbusRegisterKind::Holding => 2,
+    }
+}
+
+fn kind_order(kind: ModbusRegisterKind) -> u8 {
+    match kind {
+        ModbusRegisterKind::Coil => 0,
+        ModbusRegisterKind::Discrete => 1,
+        ModbusRegisterKind::Input => 2,
+        ModbusRegisterKind::Holding => 3,
+    }
+}
+
+// groups ranges by unit and kind and merges adjacent/overlapping ones (or separated by up to
+// max_gap registers), while a merged range fits into a single request. Returns (unit id, start
+// register, count, range indexes)
+fn coalesce(
+    ranges: &[(u8, ModbusRegister, u16)],
+    options: &ModbusMappingOptions,
+    max_gap: u16,
+) -> Vec<(u8, ModbusRegister, u16, Vec<usize>)> {
+    let mut order: Vec<usize> = (0..ranges.len()).collect();
+    order.sort_by_key(|&i| {
+        let (unit_id, register, _) = ranges[i];
+        (unit_id, kind_order(register.kind), register.offset)
+    });
+    let mut result: Vec<(u8, ModbusRegister, u16, Vec<usize>)> = Vec::new();
+    for i in order {
+        let (unit_id, register, count) = ranges[i];
+        let max_count = u32::from(match register.kind {
+            ModbusRegisterKind::Coil | ModbusRegisterKind::Discrete => options.max_read_bits,
+            ModbusRegisterKind::Input | ModbusRegisterKind::Holding => options.max_read_registers,
+        });
+        let start = u32::from(register.offset);
+        let end = start + u32::from(count);
+        if let Some(last) = result.last_mut() {
+            let last_start = u32::from(last.1.offset);
+            let last_end = last_start + u32::from(last.2);
+            let merged_end = last_end.max(end);
+            if last.0 == unit_id
+                && last.1.kind == register.kind
+                && start <= last_end + u32::from(max_gap)
+                && merged_end - last_start <= max_count
+            {
+                // the merged count fits into a single request
+                last.2 = u16::try_from(merged_end - last_start).unwrap();
+                last.3.push(i);
+                continue;
+            }
+        }
+        result.push((unit_id, register, count, vec![i]));
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::coalesce;
+    use crate::io::modbus::{ModbusMappingOptions, ModbusRegister, ModbusRegisterKind};
+
+    #[test]
+    fn test_coalesce() {
+        let h = |offset| ModbusRegister::new(ModbusRegisterKind::Holding, offset);
+        let c = |offset| ModbusRegister::new(ModbusRegisterKind::Coil, offset);
+        let ranges = [
+            (1, h(4), 2),
+            (1, h(0), 4),
+            (1, c(0), 8),
+            (1, h(8), 1),
+            (2, h(6), 1),
+            (1, h(2), 1),
+            (1, h(200), 10),
+        ];
+        let options = ModbusMappingOptions::new().max_read_registers(100);
+        let batches = coalesce(&ranges, &options, 0);
+        assert_eq!(
+            batches,
+            [
+                (1, c(0), 8, vec![2]),
+                (1, h(0), 6, vec![1, 5, 0]),
+                (1, h(8), 1, vec![3]),
+                (1, h(200), 10, vec![6]),
+                (2, h(6), 1, vec![4]),
+            ]
+        );
+        let batches = coalesce(&ranges, &options, 2);
+        assert_eq!(batches[1], (1, h(0), 9, vec![1, 5, 0, 3]));
+        // limited by the request size
+        let options = ModbusMappingOptions::new().max_read_registers(5);
+        let batches = coalesce(&ranges, &options, 0);
+        assert_eq!(batches[1], (1, h(0), 4, vec![1, 5]));
+        assert_eq!(batches[2], (1, h(4), 2, vec![0]));
+    }
+}
@@ -1,3 +1,7 @@
+use crate::io::server::{
+    prepare_tcp_stream, ConnectionLimiter, ServerHandle, ServerStopper, StopSignal,
+    STOP_CHECK_INTERVAL,
+};
 use crate::io::{modbus::ModbusRegister, IoMapping};
 use crate::{
     comm::{self, Protocol},
@@ -11,23 +15,404 @@
 };
 use rtsc::semaphore::Semaphore;
 use serial::SystemPort;
-use std::time::Duration;
+use std::any::Any;
+use std::time::{Duration, Instant};
 use std::{
-    io::{Cursor, Read, Write},
-    net::{TcpListener, TcpStream},
-    sync::Arc,
+    io::{self, Cursor, Read, Write},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    ops::Range,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     thread,
 };
-use tracing::error;
+use tracing::{error, warn};
 
+use super::ascii;
+use super::persistence::{ModbusServerPersistence, ModbusServerPersister};
+use super::subscription::ModbusChangeSubscription;
+#[cfg(feature = "modbus-tls")]
+use super::tls::ModbusTlsConfig;
 use super::ModbusRegisterKind;
 
+/// Stops a running [`ModbusServer`]. Can be cloned and shared with no limitations.
+pub type ModbusServerStopper = ServerStopper;
+
+/// A handle of a server, running in background (see [`ModbusServer::spawn()`])
+pub type ModbusServerHandle = ServerHandle;
+
+// MBAP header without the unit id
+const MBAP_HEADER_LEN: usize = 6;
+const MODBUS_ERROR_ILLEGAL_FUNCTION: u8 = 0x01;
+const MODBUS_ERROR_ILLEGAL_DATA_ADDRESS: u8 = 0x02;
+const MODBUS_ERROR_ILLEGAL_DATA_VALUE: u8 = 0x03;
+
 enum Server {
-    Tcp(TcpListener),
+    // the plain listener is absent for TLS-only servers
+    Tcp(Option<TcpListener>),
+    Udp(UdpSocket),
     Serial(SystemPort),
 }
 
-#[allow(clippy::trivially_copy_pass_by_ref)]
+trait ClientStream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> ClientStream for T {}
+
+// a received UDP request, the response is collected to be sent back
+struct Datagram<'a> {
+    request: &'a [u8],
+    response: &'a mut Vec<u8>,
+}
+
+impl Read for Datagram<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.request.read(buf)
+    }
+}
+
+impl Write for Datagram<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.response.extend(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// a placeholder, so the accept loop is the same with and without TLS support
+#[cfg(not(feature = "modbus-tls"))]
+#[derive(Clone)]
+enum ModbusTlsConfig {}
+
+#[cfg(not(feature = "modbus-tls"))]
+impl ModbusTlsConfig {
+    fn accept(&self, _stream: TcpStream, _addr: SocketAddr) -> Result<TcpStream> {
+        match *self {}
+    }
+}
+
+enum TcpFrame {
+    Request,
+    // the request is larger than the frame buffer, the header, unit id and function code are
+    // kept in the buffer
+    Oversized,
+    Closed,
+}
+
+// reads a single request, framed by its MBAP header. Partial reads (the frame is split into
+// several TCP segments) and pipelined requests are handled correctly
+fn read_tcp_frame<T: Read>(
+    client: &mut T,
+    buf: &mut ModbusFrameBuf,
+    stop: &StopSignal,
+    idle_timeout: Option<Duration>,
+    last_activity: &mut Instant,
+) -> TcpFrame {
+    let mut header = [0u8; MBAP_HEADER_LEN];
+    if !read_full(client, &mut header, stop, idle_timeout, last_activity) {
+        return TcpFrame::Closed;
+    }
+    let protocol_id = u16::from_be_bytes([header[2], header[3]]);
+    let len = usize::from(u16::from_be_bytes([header[4], header[5]]));
+    // unit id + function code at least
+    if protocol_id != 0 || len < 2 {
+        return TcpFrame::Closed;
+    }
+    buf[..MBAP_HEADER_LEN].copy_from_slice(&header);
+    let frame_len = MBAP_HEADER_LEN + len;
+    if frame_len <= buf.len() {
+        if !read_full(
+            client,
+            &mut buf[MBAP_HEADER_LEN..frame_len],
+            stop,
+            idle_timeout,
+            last_activity,
+        ) {
+            return TcpFrame::Closed;
+        }
+        buf[frame_len..].fill(0);
+        return TcpFrame::Request;
+    }
+    // the request is discarded, unit id and function code are kept for the exception response
+    let mut rest = vec![0u8; len];
+    if !read_full(client, &mut rest, stop, idle_timeout, last_activity) {
+        return TcpFrame::Closed;
+    }
+    buf[MBAP_HEADER_LEN] = rest[0];
+    buf[MBAP_HEADER_LEN + 1] = rest[1];
+    TcpFrame::Oversized
+}
+
+// builds an exception response for a request, framed with the MBAP header
+fn tcp_exception(request: &[u8], code: u8) -> [u8; MBAP_HEADER_LEN + 3] {
+    let mut exception = [0u8; MBAP_HEADER_LEN + 3];
+    // transaction and protocol ids
+    exception[..4].copy_from_slice(&request[..4]);
+    exception[5] = 3;
+    // unit id
+    exception[6] = request[MBAP_HEADER_LEN];
+    exception[7] = request[MBAP_HEADER_LEN + 1] | 0x80;
+    exception[8] = code;
+    exception
+}
+
+// validates a request PDU in the order, defined by the specification (function code, quantity,
+// address range), returns an exception code for invalid requests. `sizes` are the storage sizes
+// of coils, discretes, inputs and holdings
+fn validate_request(pdu: &[u8], sizes: [usize; 4]) -> Option<u8> {
+    let func = *pdu.first()?;
+    if !matches!(func, 1..=6 | 15 | 16) {
+        return Some(MODBUS_ERROR_ILLEGAL_FUNCTION);
+    }
+    let word = |pos: usize| {
+        pdu.get(pos..pos + 2)
+            .map(|b| usize::from(u16::from_be_bytes([b[0], b[1]])))
+    };
+    let (Some(addr), Some(value)) = (word(1), word(3)) else {
+        return Some(MODBUS_ERROR_ILLEGAL_DATA_VALUE);
+    };
+    let byte_count = pdu.get(5).copied().map(usize::from);
+    let (valid, size, count) = match func {
+        1 => (
+            (1..=2000).contains(&value) && pdu.len() == 5,
+            sizes[0],
+            value,
+        ),
+        2 => (
+            (1..=2000).contains(&value) && pdu.len() == 5,
+            sizes[1],
+            value,
+        ),
+        3 => (
+            (1..=125).contains(&value) && pdu.len() == 5,
+            sizes[3],
+            value,
+        ),
+        4 => (
+            (1..=125).contains(&value) && pdu.len() == 5,
+            sizes[2],
+            value,
+        ),
+        5 => (
+            (value == 0 || value == 0xff00) && pdu.len() == 5,
+            sizes[0],
+            1,
+        ),
+        6 => (pdu.len() == 5, sizes[3], 1),
+        15 => (
+            (1..=1968).contains(&value)
+                && byte_count == Some((value + 7) / 8)
+                && pdu.len() == 6 + (value + 7) / 8,
+            sizes[0],
+            value,
+        ),
+        _ => (
+            (1..=123).contains(&value)
+                && byte_count == Some(value * 2)
+                && pdu.len() == 6 + value * 2,
+            sizes[3],
+            value,
+        ),
+    };
+    if !valid {
+        return Some(MODBUS_ERROR_ILLEGAL_DATA_VALUE);
+    }
+    if addr + count > size {
+        return Some(MODBUS_ERROR_ILLEGAL_DATA_ADDRESS);
+    }
+    None
+}
+
+/// Modbus write function codes (single/multiple coils and holdings)
+pub const MODBUS_WRITE_FUNCTIONS: [u8; 4] = [5, 6, 15, 16];
+
+/// Access rule of [`ModbusServer`] (see [`ModbusServer::add_access_rule()`])
+#[derive(Debug, Clone)]
+pub struct ModbusAccessRule {
+    functions: Vec<u8>,
+    clients: Vec<IpAddr>,
+    allow: bool,
+}
+
+impl ModbusAccessRule {
+    /// Creates a rule, which allows matching requests
+    pub fn allow() -> Self {
+        Self {
+            functions: Vec::new(),
+            clients: Vec::new(),
+            allow: true,
+        }
+    }
+    /// Creates a rule, which denies matching requests
+    pub fn deny() -> Self {
+        Self {
+            allow: false,
+            ..Self::allow()
+        }
+    }
+    /// Matches the given function codes only (all functions are matched by default), e.g.
+    /// [`MODBUS_WRITE_FUNCTIONS`]
+    pub fn functions(mut self, functions: &[u8]) -> Self {
+        self.functions = functions.to_vec();
+        self
+    }
+    /// Matches requests from the given TCP client IP addresses only (by default all requests are
+    /// matched, including ones received via serial ports)
+    pub fn clients(mut self, clients: &[IpAddr]) -> Self {
+        self.clients = clients.to_vec();
+        self
+    }
+    fn matches(&self, client: Option<IpAddr>, function: u8) -> bool {
+        (self.functions.is_empty() || self.functions.contains(&function))
+            && (self.clients.is_empty() || client.map_or(false, |ip| self.clients.contains(&ip)))
+    }
+}
+
+/// Audit event, passed to the audit function (see [`ModbusServer::set_audit_fn()`]). Produced
+/// for each write request and for each request, denied by the access rules
+#[derive(Debug, Clone)]
+pub struct ModbusAuditEvent<'a> {
+    /// Client address (`None` for serial servers)
+    pub peer: Option<SocketAddr>,
+    pub function: u8,
+    /// Register kind (`None` for unsupported functions)
+    pub kind: Option<ModbusRegisterKind>,
+    pub range: Range<u16>,
+    /// Raw values of write requests (empty for reads)
+    pub values: &'a [u8],
+    pub allowed: bool,
+}
+
+type AuditFn = dyn Fn(&ModbusAuditEvent) + Send + Sync;
+
+#[derive(Clone)]
+struct AccessPolicy {
+    rules: Vec<ModbusAccessRule>,
+    default_allow: bool,
+    audit: Option<Arc<AuditFn>>,
+}
+
+impl Default for AccessPolicy {
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            default_allow: true,
+            audit: None,
+        }
+    }
+}
+
+impl AccessPolicy {
+    // the first matching rule is applied
+    fn is_allowed(&self, client: Option<IpAddr>, function: u8) -> bool {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(client, function))
+            .map_or(self.default_allow, |rule| rule.allow)
+    }
+    fn audit(&self, peer: Option<SocketAddr>, request: &RequestInfo, allowed: bool) {
+        if let Some(ref audit) = self.audit {
+            audit(&ModbusAuditEvent {
+                peer,
+                function: request.function,
+                kind: request.kind,
+                range: request.range.clone(),
+                values: request.values,
+                allowed,
+            });
+        }
+    }
+}
+
+struct RequestInfo<'a> {
+    function: u8,
+    kind: Option<ModbusRegisterKind>,
+    range: Range<u16>,
+    values: &'a [u8],
+}
+
+impl<'a> RequestInfo<'a> {
+    // parses a request PDU, the PDU may contain trailing bytes (the frame buffer)
+    fn parse(pdu: &'a [u8]) -> Self {
+        let function = pdu.first().copied().unwrap_or_default();
+        let word = |pos: usize| {
+            pdu.get(pos..pos + 2)
+                .map_or(0, |b| u16::from_be_bytes([b[0], b[1]]))
+        };
+        let addr = word(1);
+        let (kind, count, values) = match function {
+            1 => (Some(ModbusRegisterKind::Coil), word(3), None),
+            2 => (Some(ModbusRegisterKind::Discrete), word(3), None),
+            3 => (Some(ModbusRegisterKind::Holding), word(3), None),
+            4 => (Some(ModbusRegisterKind::Input), word(3), None),
+            5 => (Some(ModbusRegisterKind::Coil), 1, pdu.get(3..5)),
+            6 => (Some(ModbusRegisterKind::Holding), 1, pdu.get(3..5)),
+            15 | 16 => {
+                let kind = if function == 15 {
+                    ModbusRegisterKind::Coil
+                } else {
+                    ModbusRegisterKind::Holding
+                };
+                let len = usize::from(pdu.get(5).copied().unwrap_or_default());
+                (Some(kind), word(3), pdu.get(6..6 + len))
+            }
+            _ => (None, 0, None),
+        };
+        Self {
+            function,
+            kind,
+            range: if kind.is_some() {
+                addr..addr.saturating_add(count)
+            } else {
+                0..0
+            },
+            values: values.unwrap_or_default(),
+        }
+    }
+    fn is_write(&self) -> bool {
+        MODBUS_WRITE_FUNCTIONS.contains(&self.function)
+    }
+}
+
+// returns false if the connection is closed, timed out or the server is stopped. A partially
+// received frame must be completed within the idle timeout as well
+fn read_full<T: Read>(
+    client: &mut T,
+    buf: &mut [u8],
+    stop: &StopSignal,
+    idle_timeout: Option<Duration>,
+    last_activity: &mut Instant,
+) -> bool {
+    let mut pos = 0;
+    while pos < buf.len() {
+        match client.read(&mut buf[pos..]) {
+            Ok(0) => return false,
+            Ok(n) => {
+                pos += n;
+                *last_activity = Instant::now();
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                if stop.is_set() {
+                    return false;
+                }
+                if idle_timeout.map_or(true, |t| last_activity.elapsed() >= t) {
+                    return false;
+                }
+            }
+            Err(_) => return false,
+        }
+    }
+    true
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref, clippy::too_many_arguments)]
 fn handle_client<
     T: Read + Write,
     const C: usize,
@@ -40,20 +425,97 @@ fn handle_client<
     storage: Arc<Mutex<ModbusStorage<C, D, I, H>>>,
     modbus_proto: ModbusProto,
     allow_write: &AllowFn,
+    access: &AccessPolicy,
+    peer: Option<SocketAddr>,
+    changes: &AtomicU64,
+    stop: &StopSignal,
+    idle_timeout: Option<Duration>,
+    ascii: bool,
 ) -> Result<()> {
+    let pdu_offset = if matches!(modbus_proto, ModbusProto::TcpUdp) {
+        MBAP_HEADER_LEN + 1
+    } else {
+        1
+    };
     let mut buf: ModbusFrameBuf = [0; 256];
     let mut response = Vec::with_capacity(256);
+    let mut last_activity = Instant::now();
+    // Modbus ASCII frames are converted to/from RTU ones
+    let mut ascii_frame = ascii::FrameCollector::default();
+    let mut ascii_buf = Vec::new();
+    let mut byte = [0u8; 1];
     loop {
-        if client.read(&mut buf).unwrap_or(0) == 0 {
-            break;
+        if matches!(modbus_proto, ModbusProto::TcpUdp) {
+            match read_tcp_frame(
+                &mut client,
+                &mut buf,
+                stop,
+                idle_timeout,
+                &mut last_activity,
+            ) {
+                TcpFrame::Request => {
+                    let len = usize::from(u16::from_be_bytes([buf[4], buf[5]]));
+                    // broadcasts and requests for other units are processed as usual
+                    if buf[MBAP_HEADER_LEN] == unit {
+                        let pdu = &buf[MBAP_HEADER_LEN + 1..MBAP_HEADER_LEN + len];
+                        if let Some(code) = validate_request(pdu, [C, D, I, H]) {
+                            client
+                                .write_all(&tcp_exception(&buf, code))
+                                .map_err(Error::io)?;
+                            continue;
+                        }
+                    }
+                }
+                TcpFrame::Oversized => {
+                    client
+                        .write_all(&tcp_exception(&buf, MODBUS_ERROR_ILLEGAL_DATA_VALUE))
+                        .map_err(Error::io)?;
+                    continue;
+                }
+                TcpFrame::Closed => break,
+            }
+        } else {
+            let target: &mut [u8] = if ascii { &mut byte } else { &mut buf };
+            match client.read(target) {
+                Ok(0) => break,
+                Ok(_) => last_activity = Instant::now(),
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    if stop.is_set() || idle_timeout.map_or(true, |t| last_activity.elapsed() >= t)
+                    {
+                        break;
+                    }
+                    continue;
+                }
+                Err(_) => break,
+            }
+            if ascii {
+                if !ascii_frame.push(byte[0]) {
+                    continue;
+                }
+                if let Err(error) = ascii::decode(ascii_frame.frame(), &mut ascii_buf) {
+                    warn!(%error, "invalid Modbus ASCII frame");
+                    continue;
+                }
+                buf[..ascii_buf.len()].copy_from_slice(&ascii_buf);
+            }
         }
         response.truncate(0);
         let mut frame = ModbusFrame::new(unit, &buf, modbus_proto, &mut response);
         frame.parse().map_err(Error::io)?;
         if frame.processing_required {
-            if frame.readonly {
+            let request = RequestInfo::parse(&buf[pdu_offset..]);
+            if !access.is_allowed(peer.map(|p| p.ip()), request.function) {
+                access.audit(peer, &request, false);
+                frame.set_modbus_error_if_unset(&rmodbus::ErrorKind::NegativeAcknowledge)?;
+            } else if frame.readonly {
                 frame.process_read(&*storage.lock()).map_err(Error::io)?;
             } else {
+                let mut changed = 0;
                 let (process, _guard) = if let Some(changes) = frame.changes() {
                     let (kind, range) = match changes {
                         rmodbus::server::Changes::Coils { reg, count } => {
@@ -63,6 +525,7 @@ fn handle_client<
                             (ModbusRegisterKind::Holding, reg..reg + count)
                         }
                     };
+                    changed = u64::from(range.end - range.start);
                     match allow_write(kind, range) {
                         WritePermission::Allow => (true, None),
                         WritePermission::AllowLock(guard) => (true, Some(guard)),
@@ -71,10 +534,14 @@ fn handle_client<
                 } else {
                     (true, None)
                 };
+                if request.is_write() {
+                    access.audit(peer, &request, process);
+                }
                 if process {
                     frame
                         .process_write(&mut *storage.lock())
                         .map_err(Error::io)?;
+                    changes.fetch_add(changed, Ordering::SeqCst);
                 } else {
                     frame.set_modbus_error_if_unset(&rmodbus::ErrorKind::NegativeAcknowledge)?;
                 }
@@ -82,7 +549,12 @@ fn handle_client<
         }
         if frame.response_required {
             frame.finalize_response().map_err(Error::io)?;
-            client.write_all(&response).map_err(Error::io)?;
+            if ascii {
+                ascii::encode(&response, &mut ascii_buf);
+                client.write_all(&ascii_buf).map_err(Error::io)?;
+            } else {
+                client.write_all(&response).map_err(Error::io)?;
+            }
         }
     }
     Ok(())
@@ -124,6 +596,15 @@ pub struct ModbusServer<const C: usize, const D: usize, const I: usize, const H:
     timeout: Duration,
     semaphore: Semaphore,
     allow_external_write_fn: Arc<AllowFn>,
+    access: Arc<AccessPolicy>,
+    changes: Arc<AtomicU64>,
+    persistence: Option<Arc<ModbusServerPersistence>>,
+    stop: StopSignal,
+    idle_timeout: Duration,
+    limiter: ConnectionLimiter,
+    serial_ascii: bool,
+    #[cfg(feature = "modbus-tls")]
+    tls: Option<(TcpListener, ModbusTlsConfig)>,
 }
 impl<const C: usize, const D: usize, const I: usize, const H: usize> ModbusServer<C, D, I, H> {
     pub fn bind(
@@ -134,16 +615,124 @@ pub fn bind(
         max_workers: usize,
     ) -> Result<Self> {
         let server = match protocol {
-            Protocol::Tcp => Server::Tcp(TcpListener::bind(path)?),
+            Protocol::Tcp => Server::Tcp(Some(TcpListener::bind(path)?)),
+            Protocol::Udp => Server::Udp(UdpSocket::bind(path)?),
             Protocol::Serial => Server::Serial(comm::serial::open(&path.parse()?, timeout)?),
         };
-        Ok(Self {
+        Ok(Self::new(unit, server, timeout, max_workers))
+    }
+    /// Binds a Modbus/TCP Security (TLS-only) server, the standard port is
+    /// [`super::MODBUS_TLS_PORT`]
+    #[cfg(feature = "modbus-tls")]
+    pub fn bind_tls(
+        unit: u8,
+        path: &str,
+        config: ModbusTlsConfig,
+        timeout: Duration,
+        max_workers: usize,
+    ) -> Result<Self> {
+        let mut server = Self::new(unit, Server::Tcp(None), timeout, max_workers);
+        server.tls = Some((TcpListener::bind(path)?, config));
+        Ok(server)
+    }
+    fn new(unit: u8, server: Server, timeout: Duration, max_workers: usize) -> Self {
+        Self {
             storage: <_>::default(),
             unit,
             server,
             timeout,
             semaphore: Semaphore::new(max_workers),
             allow_external_write_fn: Arc::new(|_, _| WritePermission::Allow),
+            access: <_>::default(),
+            changes: <_>::default(),
+            persistence: None,
+            stop: <_>::default(),
+            idle_timeout: timeout,
+            limiter: <_>::default(),
+            serial_ascii: false,
+            #[cfg(feature = "modbus-tls")]
+            tls: None,
+        }
+    }
+    /// Returns the local address of a TCP/UDP server (e.g. if bound to port 0)
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        match self.server {
+            Server::Tcp(Some(ref listener)) => listener.local_addr().map_err(Into::into),
+            Server::Udp(ref socket) => socket.local_addr().map_err(Into::into),
+            Server::Tcp(None) | Server::Serial(_) => Err(Error::Unimplemented),
+        }
+    }
+    /// Adds a Modbus/TCP Security (TLS) listener to a TCP server, so both plain and TLS clients
+    /// are served (e.g. during migration). The standard port is [`super::MODBUS_TLS_PORT`]
+    #[cfg(feature = "modbus-tls")]
+    pub fn add_tls_listener(&mut self, path: &str, config: ModbusTlsConfig) -> Result<()> {
+        if !matches!(self.server, Server::Tcp(_)) {
+            return Err(Error::failed("TLS is supported for TCP servers only"));
+        }
+        self.tls = Some((TcpListener::bind(path)?, config));
+        Ok(())
+    }
+    /// Returns the local address of the TLS listener (e.g. if bound to port 0)
+    #[cfg(feature = "modbus-tls")]
+    pub fn tls_local_addr(&self) -> Result<SocketAddr> {
+        self.tls
+            .as_ref()
+            .ok_or(Error::Unimplemented)?
+            .0
+            .local_addr()
+            .map_err(Into::into)
+    }
+    /// Sets the idle timeout for TCP client connections (the default is the server timeout)
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        self.idle_timeout = timeout;
+    }
+    /// Uses Modbus ASCII framing instead of RTU for a serial server (the default is false)
+    pub fn set_serial_ascii(&mut self, ascii: bool) {
+        self.serial_ascii = ascii;
+    }
+    /// Limits the number of simultaneous TCP connections from a single IP address (unlimited by
+    /// default). The total number of connections is limited by `max_workers`
+    pub fn set_max_connections_per_client(&mut self, max: usize) {
+        self.limiter.max_connections_per_client = Some(max);
+    }
+    /// Accepts TCP connections and UDP requests from the given IP addresses only (all are accepted
+    /// by default)
+    pub fn set_allowed_clients(&mut self, clients: Vec<IpAddr>) {
+        self.limiter.allowed_clients = clients;
+    }
+    /// Stops the server when the controller goes offline (shutdown)
+    #[cfg(target_os = "linux")]
+    pub fn set_controller_state(&mut self, state: crate::controller::State) {
+        self.stop.state = Some(state);
+    }
+    /// Returns a stopper, which can be used to stop the server
+    pub fn stopper(&self) -> ModbusServerStopper {
+        self.stop.stopper()
+    }
+    /// Serves the clients in a background thread (not real-time)
+    pub fn spawn(mut self) -> Result<ModbusServerHandle> {
+        let stopper = self.stopper();
+        let thread = thread::Builder::new()
+            .name("modbus-server".to_owned())
+            .spawn(move || self.serve())?;
+        Ok(ModbusServerHandle::new(stopper, thread))
+    }
+    /// Enables storage context persistence. The last snapshot (if exists) is restored
+    /// immediately, so the method should be called right after binding. The snapshots are written
+    /// by [`ModbusServerPersister`] (see [`ModbusServer::persister()`])
+    pub fn persistence(mut self, persistence: ModbusServerPersistence) -> Result<Self> {
+        persistence.restore(&mut *self.storage.lock())?;
+        self.persistence = Some(Arc::new(persistence));
+        Ok(self)
+    }
+    /// Creates a storage context snapshot writer. Returns `None` if the persistence is not enabled
+    pub fn persister(&self) -> Option<ModbusServerPersister<C, D, I, H>> {
+        self.persistence.as_ref().map(|persistence| {
+            ModbusServerPersister::new(
+                self.storage.clone(),
+                persistence.clone(),
+                self.changes.clone(),
+            )
         })
     }
     /// Set a function which checks if an external client write operation is allowed.
@@ -152,6 +741,32 @@ pub fn bind(
     pub fn set_allow_external_write_fn(&mut self, f: AllowFn) {
         self.allow_external_write_fn = f.into();
     }
+    /// Adds an access rule. Rules are checked in the order they have been added, the first
+    /// matching rule is applied. Denied requests get the negative acknowledge exception
+    ///
+    /// ```rust,ignore
+    /// // only the SCADA host may write, everyone may read
+    /// server.add_access_rule(
+    ///     ModbusAccessRule::allow().functions(&MODBUS_WRITE_FUNCTIONS).clients(&[scada_ip]),
+    /// );
+    /// server.add_access_rule(ModbusAccessRule::deny().functions(&MODBUS_WRITE_FUNCTIONS));
+    /// ```
+    pub fn add_access_rule(&mut self, rule: ModbusAccessRule) {
+        Arc::make_mut(&mut self.access).rules.push(rule);
+    }
+    /// Sets the access for requests, which match no rules (the default is true, allowed)
+    pub fn set_default_access(&mut self, allow: bool) {
+        Arc::make_mut(&mut self.access).default_allow = allow;
+    }
+    /// Sets a function which is called for each write request (allowed or denied) and for each
+    /// request, denied by the access rules. Can be used to feed journals and alarms. The function
+    /// is called from client threads and should not block
+    pub fn set_audit_fn<F>(&mut self, f: F)
+    where
+        F: Fn(&ModbusAuditEvent) + Send + Sync + 'static,
+    {
+        Arc::make_mut(&mut self.access).audit = Some(Arc::new(f));
+    }
     pub fn mapping(&self, register: ModbusRegister, count: u16) -> ModbusServerMapping<C, D, I, H> {
         let buf_capacity = match register.kind {
             ModbusRegisterKind::Coil | ModbusRegisterKind::Discrete => usize::from(count),
@@ -162,61 +777,639 @@ pub fn mapping(&self, register: ModbusRegister, count: u16) -> ModbusServerMappi
             register,
             count,
             data_buf: Vec::with_capacity(buf_capacity),
+            changes: self.changes.clone(),
+            last_value: None,
+            writes: 0,
+            skipped_writes: 0,
         }
     }
+    /// Creates a mapping for an array of `N` elements, the number of registers is calculated
+    /// automatically. Arrays which do not fit into any storage area are rejected at compile time.
+    /// The register kind is known at runtime only, so the storage area of the kind and the
+    /// register range are checked at runtime
+    pub fn array_mapping<T, const N: usize>(
+        &self,
+        register: ModbusRegister,
+    ) -> Result<ModbusServerMapping<C, D, I, H>>
+    where
+        T: ModbusArrayElement,
+    {
+        data_registers(register.kind, T::SIZE)?;
+        let registers = data_registers(register.kind, ArraySize::<T, N, C, D, I, H>::BYTES)?;
+        check_storage_range::<C, D, I, H>(register, registers)?;
+        let count = u16::try_from(registers).map_err(Error::invalid_data)?;
+        Ok(self.mapping(register, count))
+    }
     pub fn storage(&self) -> Arc<Mutex<ModbusStorage<C, D, I, H>>> {
         self.storage.clone()
     }
+    /// Returns a storage handle for atomic multi-register writes (see
+    /// [`ModbusServerStorage::transaction()`])
+    pub fn shared_storage(&self) -> ModbusServerStorage<C, D, I, H> {
+        ModbusServerStorage {
+            storage: self.storage.clone(),
+            changes: self.changes.clone(),
+        }
+    }
+    /// Serves the clients until stopped (see [`ModbusServer::stopper()`] and
+    /// [`ModbusServer::set_controller_state()`])
     pub fn serve(&mut self) -> Result<()> {
         let timeout = self.timeout;
         let unit = self.unit;
         match self.server {
-            Server::Tcp(ref server) => loop {
-                let permission = self.semaphore.acquire();
-                let (stream, addr) = server.accept()?;
-                if let Err(e) = prepare_tcp_stream(&stream, timeout) {
-                    error!(%addr, %e, "error preparing tcp stream");
-                    continue;
+            Server::Tcp(ref plain) => {
+                #[allow(unused_mut)]
+                let mut listeners: Vec<(&TcpListener, Option<&ModbusTlsConfig>)> =
+                    plain.iter().map(|l| (l, None)).collect();
+                #[cfg(feature = "modbus-tls")]
+                if let Some((ref listener, ref config)) = self.tls {
+                    listeners.push((listener, Some(config)));
                 }
-                let storage = self.storage.clone();
-                let allow_write = self.allow_external_write_fn.clone();
-                thread::spawn(move || {
-                    let _permission = permission;
-                    if let Err(error) =
-                        handle_client(stream, unit, storage, ModbusProto::TcpUdp, &allow_write)
-                    {
-                        error!(%addr, %error, "error handling Modbus client");
+                for (listener, _) in &listeners {
+                    listener.set_nonblocking(true)?;
+                }
+                loop {
+                    if self.stop.is_set() {
+                        break;
                     }
-                });
-            },
-            Server::Serial(ref mut serial) => loop {
-                if let Err(e) = handle_client(
-                    &mut *serial,
-                    unit,
-                    self.storage.clone(),
-                    ModbusProto::Rtu,
-                    &self.allow_external_write_fn,
-                ) {
-                    error!(%e, "error handling Modbus client");
+                    let permission = self.semaphore.acquire();
+                    let accepted = 'accept: loop {
+                        for (listener, tls) in &listeners {
+                            match listener.accept() {
+                                Ok((stream, addr)) => break 'accept Some((stream, addr, *tls)),
+                                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                                Err(e) => return Err(e.into()),
+                            }
+                        }
+                        if self.stop.is_set() {
+                            break None;
+                        }
+                        thread::sleep(STOP_CHECK_INTERVAL);
+                    };
+                    let Some((stream, addr, tls)) = accepted else {
+                        break;
+                    };
+                    let Some(guard) = self.limiter.register(addr) else {
+                        continue;
+                    };
+                    if let Err(e) = prepare_tcp_stream(&stream, timeout) {
+                        error!(%addr, %e, "error preparing tcp stream");
+                        continue;
+                    }
+                    let tls = tls.cloned();
+                    let storage = self.storage.clone();
+                    let allow_write = self.allow_external_write_fn.clone();
+                    let access = self.access.clone();
+                    let changes = self.changes.clone();
+                    let stop = self.stop.clone();
+                    let idle_timeout = self.idle_timeout;
+                    thread::spawn(move || {
+                        let _permission = permission;
+                        let _guard = guard;
+                        let client: Box<dyn ClientStream> = match tls {
+                            Some(config) => match config.accept(stream, addr) {
+                                Ok(stream) => Box::new(stream),
+                                Err(error) => {
+                                    error!(%addr, %error, "Modbus TLS client rejected");
+                                    return;
+                                }
+                            },
+                            None => Box::new(stream),
+                        };
+                        if let Err(error) = handle_client(
+                            client,
+                            unit,
+                            storage,
+                            ModbusProto::TcpUdp,
+                            &allow_write,
+                            &access,
+                            Some(addr),
+                            &changes,
+                            &stop,
+                            Some(idle_timeout),
+                            false,
+                        ) {
+                            error!(%addr, %error, "error handling Modbus client");
+                        }
+                    });
                 }
-            },
+            }
+            Server::Udp(ref socket) => {
+                // requests are processed sequentially, one response datagram per request
+                socket.set_read_timeout(Some(STOP_CHECK_INTERVAL))?;
+                let mut request = vec![0u8; comm::udp::MAX_DATAGRAM_SIZE];
+                let mut response = Vec::with_capacity(256);
+                while !self.stop.is_set() {
+                    let (len, addr) = match socket.recv_from(&mut request) {
+                        Ok(v) => v,
+                        Err(e)
+                            if matches!(
+                                e.kind(),
+                                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                            ) =>
+                        {
+                            continue;
+                        }
+                        Err(e) => return Err(e.into()),
+                    };
+                    if self.limiter.register(addr).is_none() {
+                        continue;
+                    }
+                    response.truncate(0);
+                    let datagram = Datagram {
+                        request: &request[..len],
+                        response: &mut response,
+                    };
+                    if let Err(error) = handle_client(
+                        datagram,
+                        unit,
+                        self.storage.clone(),
+                        ModbusProto::TcpUdp,
+                        &self.allow_external_write_fn,
+                        &self.access,
+                        Some(addr),
+                        &self.changes,
+                        &self.stop,
+                        None,
+                        false,
+                    ) {
+                        error!(%addr, %error, "error handling Modbus UDP request");
+                        continue;
+                    }
+                    if !response.is_empty() {
+                        if let Err(error) = socket.send_to(&response, addr) {
+                            error!(%addr, %error, "error sending Modbus UDP response");
+                        }
+                    }
+                }
+            }
+            Server::Serial(ref mut serial) => {
+                while !self.stop.is_set() {
+                    if let Err(e) = handle_client(
+                        &mut *serial,
+                        unit,
+                        self.storage.clone(),
+                        ModbusProto::Rtu,
+                        &self.allow_external_write_fn,
+                        &self.access,
+                        None,
+                        &self.changes,
+                        &self.stop,
+                        None,
+                        self.serial_ascii,
+                    ) {
+                        error!(%e, "error handling Modbus client");
+                    }
+                }
+            }
         }
+        Ok(())
     }
 }
 
-fn prepare_tcp_stream(stream: &TcpStream, timeout: Duration) -> Result<()> {
-    stream.set_read_timeout(Some(timeout))?;
-    stream.set_write_timeout(Some(timeout))?;
-    stream.set_nodelay(true)?;
+/// Values which can be compared with a deadband, used by
+/// [`ModbusServerMapping::write_if_changed()`]. Can be implemented for custom structures, e.g.
+/// to compare certain fields only.
+pub trait Deadband {
+    /// Returns true if the value differs from the previous one by more than the deadband. If the
+    /// deadband is zero, any change must be reported
+    fn exceeds_deadband(&self, previous: &Self, deadband: f64) -> bool;
+}
+
+macro_rules! impl_deadband_num {
+    ($($t: ty),*) => {
+        $(
+            impl Deadband for $t {
+                #[allow(clippy::cast_lossless, clippy::cast_precision_loss)]
+                fn exceeds_deadband(&self, previous: &Self, deadband: f64) -> bool {
+                    if deadband > 0.0 {
+                        (*self as f64 - *previous as f64).abs() > deadband
+                    } else {
+                        self != previous
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_deadband_num!(u8, i8, u16, i16, u32, i32, u64, i64);
+
+macro_rules! impl_deadband_float {
+    ($($t: ty),*) => {
+        $(
+            impl Deadband for $t {
+                #[allow(clippy::cast_lossless, clippy::float_cmp)]
+                fn exceeds_deadband(&self, previous: &Self, deadband: f64) -> bool {
+                    if self.is_nan() || previous.is_nan() {
+                        self.is_nan() != previous.is_nan()
+                    } else if deadband > 0.0 {
+                        (*self as f64 - *previous as f64).abs() > deadband
+                    } else {
+                        self != previous
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_deadband_float!(f32, f64);
+
+impl Deadband for bool {
+    fn exceeds_deadband(&self, previous: &Self, _deadband: f64) -> bool {
+        self != previous
+    }
+}
+
+impl<T: Deadband, const N: usize> Deadband for [T; N] {
+    fn exceeds_deadband(&self, previous: &Self, deadband: f64) -> bool {
+        self.iter()
+            .zip(previous.iter())
+            .any(|(v, p)| v.exceeds_deadband(p, deadband))
+    }
+}
+
+/// The max number of registers written/read by [`ModbusServerMapping::write_array()`] and
+/// [`ModbusServerMapping::read_array()`] with a single storage lock (the max number of registers
+/// in a single Modbus read request)
+pub const MODBUS_ARRAY_CHUNK_REGISTERS: usize = 125;
+
+/// Array elements for [`ModbusServerMapping::write_array()`] and
+/// [`ModbusServerMapping::read_array()`]. For coils and discretes each byte of an element occupies
+/// a single register, for inputs and holdings each two bytes do
+pub trait ModbusArrayElement:
+    for<'a> BinRead<Args<'a> = ()> + for<'a> BinWrite<Args<'a> = ()> + Copy + Default
+{
+    /// Element size in bytes
+    const SIZE: usize;
+}
+
+macro_rules! impl_array_element {
+    ($($t: ty),*) => {
+        $(
+            impl ModbusArrayElement for $t {
+                const SIZE: usize = std::mem::size_of::<$t>();
+            }
+        )*
+    };
+}
+
+impl_array_element!(u8, i8, u16, i16, u32, i32, u64, i64, f32, f64);
+
+const fn max_storage_bytes(c: usize, d: usize, i: usize, h: usize) -> usize {
+    let mut max = if c > d { c } else { d };
+    if i * 2 > max {
+        max = i * 2;
+    }
+    if h * 2 > max {
+        max = h * 2;
+    }
+    max
+}
+
+// compile-time array size checks. The register kind is a runtime value, so the array is checked
+// against the largest storage area only, the area of the kind is checked by check_storage_range
+struct ArraySize<T, const N: usize, const C: usize, const D: usize, const I: usize, const H: usize>(
+    std::marker::PhantomData<T>,
+);
+
+impl<T, const N: usize, const C: usize, const D: usize, const I: usize, const H: usize>
+    ArraySize<T, N, C, D, I, H>
+where
+    T: ModbusArrayElement,
+{
+    const BYTES: usize = {
+        let bytes = N * T::SIZE;
+        assert!(
+            bytes <= max_storage_bytes(C, D, I, H),
+            "the array is larger than all Modbus server storage areas"
+        );
+        bytes
+    };
+}
+
+fn storage_size<const C: usize, const D: usize, const I: usize, const H: usize>(
+    kind: ModbusRegisterKind,
+) -> usize {
+    match kind {
+        ModbusRegisterKind::Coil => C,
+        ModbusRegisterKind::Discrete => D,
+        ModbusRegisterKind::Input => I,
+        ModbusRegisterKind::Holding => H,
+    }
+}
+
+// converts the data size into the number of registers
+fn data_registers(kind: ModbusRegisterKind, bytes: usize) -> Result<usize> {
+    match kind {
+        ModbusRegisterKind::Coil | ModbusRegisterKind::Discrete => Ok(bytes),
+        ModbusRegisterKind::Input | ModbusRegisterKind::Holding => {
+            if bytes % 2 == 0 {
+                Ok(bytes / 2)
+            } else {
+                Err(Error::invalid_data(
+                    "odd-sized array elements can not be mapped to 16-bit registers",
+                ))
+            }
+        }
+    }
+}
+
+// checks if the registers fit into the storage
+fn check_storage_range<const C: usize, const D: usize, const I: usize, const H: usize>(
+    register: ModbusRegister,
+    registers: usize,
+) -> Result<()> {
+    let size = storage_size::<C, D, I, H>(register.kind);
+    if usize::from(register.offset) + registers > size {
+        return Err(Error::invalid_data(format!(
+            "{} {:?} registers at offset {} do not fit into the server storage ({} registers), \
+            increase the storage size",
+            registers, register.kind, register.offset, size
+        )));
+    }
     Ok(())
 }
 
+/// Server storage context handle for transactions. Can be cloned and shared between workers
+#[derive(Clone)]
+pub struct ModbusServerStorage<const C: usize, const D: usize, const I: usize, const H: usize> {
+    storage: Arc<Mutex<ModbusStorage<C, D, I, H>>>,
+    changes: Arc<AtomicU64>,
+}
+
+impl<const C: usize, const D: usize, const I: usize, const H: usize>
+    ModbusServerStorage<C, D, I, H>
+{
+    /// Creates a register change subscription (see [`ModbusChangeSubscription`])
+    pub fn subscribe(&self) -> ModbusChangeSubscription<C, D, I, H> {
+        ModbusChangeSubscription::new(self.storage.clone(), self.changes.clone())
+    }
+    /// Performs reads and writes of several registers atomically. The storage is locked for the
+    /// whole transaction, so external clients never see groups of related registers (e.g. a
+    /// 64-bit counter split across 4 holdings) half-updated. If the function returns an error,
+    /// all writes of the transaction are rolled back.
+    ///
+    /// The function is called with the storage locked, so it must be as short as possible.
+    ///
+    /// ```rust,ignore
+    /// storage.transaction(|tx| {
+    ///     tx.write(ModbusRegister::new(ModbusRegisterKind::Holding, 0), counter)?;
+    ///     tx.write(ModbusRegister::new(ModbusRegisterKind::Holding, 4), timestamp)?;
+    ///     Ok(())
+    /// })?;
+    /// ```
+    pub fn transaction<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut ModbusStorageTransaction<C, D, I, H>) -> Result<R>,
+    {
+        let mut tx = ModbusStorageTransaction {
+            storage: self.storage.lock(),
+            undo: Vec::new(),
+            buf: Vec::new(),
+        };
+        match f(&mut tx) {
+            Ok(result) => {
+                let changed = tx.undo.iter().map(|(_, count, _)| u64::from(*count)).sum();
+                drop(tx);
+                self.changes.fetch_add(changed, Ordering::SeqCst);
+                Ok(result)
+            }
+            Err(e) => {
+                tx.rollback();
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Storage transaction (see [`ModbusServerStorage::transaction()`])
+pub struct ModbusStorageTransaction<
+    'a,
+    const C: usize,
+    const D: usize,
+    const I: usize,
+    const H: usize,
+> {
+    storage: MutexGuard<'a, ModbusStorage<C, D, I, H>>,
+    // register, count, previous data
+    undo: Vec<(ModbusRegister, u16, Vec<u8>)>,
+    buf: Vec<u8>,
+}
+
+impl<'a, const C: usize, const D: usize, const I: usize, const H: usize>
+    ModbusStorageTransaction<'a, C, D, I, H>
+{
+    /// Reads a value from the given number of registers
+    pub fn read<T>(&mut self, register: ModbusRegister, count: u16) -> Result<T>
+    where
+        T: for<'b> BinRead<Args<'b> = ()>,
+    {
+        self.buf.truncate(0);
+        get_data(&self.storage, register, count, &mut self.buf)?;
+        T::read_be(&mut Cursor::new(&self.buf)).map_err(Into::into)
+    }
+    /// Writes a value, the number of registers is calculated from the value size
+    pub fn write<T>(&mut self, register: ModbusRegister, value: T) -> Result<()>
+    where
+        T: for<'b> BinWrite<Args<'b> = ()>,
+    {
+        let mut data = Cursor::new(Vec::new());
+        value.write_be(&mut data)?;
+        let data = data.into_inner();
+        let count = match register.kind {
+            ModbusRegisterKind::Coil | ModbusRegisterKind::Discrete => data.len(),
+            ModbusRegisterKind::Input | ModbusRegisterKind::Holding => {
+                if data.len() % 2 != 0 {
+                    return Err(Error::invalid_data("invalid data length"));
+                }
+                data.len() / 2
+            }
+        };
+        let count = u16::try_from(count).map_err(Error::invalid_data)?;
+        let mut previous = Vec::with_capacity(data.len());
+        get_data(&self.storage, register, count, &mut previous)?;
+        set_data(&mut self.storage, register, &data)?;
+        self.undo.push((register, count, previous));
+        Ok(())
+    }
+    /// Direct access to the storage context
+    pub fn storage(&mut self) -> &mut ModbusStorage<C, D, I, H> {
+        &mut self.storage
+    }
+    fn rollback(&mut self) {
+        while let Some((register, _, previous)) = self.undo.pop() {
+            // the data has been read from the same registers, so can not fail
+            let _ = set_data(&mut self.storage, register, &previous);
+        }
+    }
+}
+
+pub(super) fn get_data<const C: usize, const D: usize, const I: usize, const H: usize>(
+    storage: &ModbusStorage<C, D, I, H>,
+    register: ModbusRegister,
+    count: u16,
+    buf: &mut Vec<u8>,
+) -> Result<()> {
+    match register.kind {
+        ModbusRegisterKind::Coil => storage.get_coils_as_u8_bytes(register.offset, count, buf),
+        ModbusRegisterKind::Discrete => {
+            storage.get_discretes_as_u8_bytes(register.offset, count, buf)
+        }
+        ModbusRegisterKind::Input => storage.get_inputs_as_u8(register.offset, count, buf),
+        ModbusRegisterKind::Holding => storage.get_holdings_as_u8(register.offset, count, buf),
+    }
+    .map_err(Error::io)
+}
+
+fn set_data<const C: usize, const D: usize, const I: usize, const H: usize>(
+    storage: &mut ModbusStorage<C, D, I, H>,
+    register: ModbusRegister,
+    data: &[u8],
+) -> Result<()> {
+    match register.kind {
+        ModbusRegisterKind::Coil => storage.set_coils_from_u8_bytes(register.offset, data),
+        ModbusRegisterKind::Discrete => storage.set_discretes_from_u8_bytes(register.offset, data),
+        ModbusRegisterKind::Input => storage.set_inputs_from_u8(register.offset, data),
+        ModbusRegisterKind::Holding => storage.set_holdings_from_u8(register.offset, data),
+    }
+    .map_err(Error::io)
+}
+
 /// Server storage context mapping.
 pub struct ModbusServerMapping<const C: usize, const D: usize, const I: usize, const H: usize> {
     storage: Arc<Mutex<ModbusStorage<C, D, I, H>>>,
     register: ModbusRegister,
     count: u16,
     data_buf: Vec<u8>,
+    // shared storage change counter, used by the persistence
+    changes: Arc<AtomicU64>,
+    // the last value written with write_if_changed
+    last_value: Option<Box<dyn Any + Send>>,
+    writes: u64,
+    skipped_writes: u64,
+}
+
+impl<const C: usize, const D: usize, const I: usize, const H: usize>
+    ModbusServerMapping<C, D, I, H>
+{
+    /// Writes the value to the storage context only if it differs from the value previously
+    /// written with this method by more than the deadband. Allows internal tasks to avoid
+    /// rewriting identical values each cycle and reduces the storage lock contention.
+    ///
+    /// Returns true if the value has been written.
+    ///
+    /// The previous value is cached in the mapping, so if the registers can be modified by
+    /// external clients (coils, holdings), [`ModbusServerMapping::reset_change_cache()`] should be
+    /// called to force the next write.
+    pub fn write_if_changed<T>(&mut self, value: T, deadband: f64) -> Result<bool>
+    where
+        T: for<'a> BinWrite<Args<'a> = ()> + Deadband + Clone + Send + 'static,
+    {
+        if let Some(previous) = self.last_value.as_ref().and_then(|v| v.downcast_ref::<T>()) {
+            if !value.exceeds_deadband(previous, deadband) {
+                self.skipped_writes += 1;
+                return Ok(false);
+            }
+        }
+        self.write(value.clone())?;
+        self.last_value = Some(Box::new(value));
+        Ok(true)
+    }
+    /// Writes an array of values. The data is written in chunks of up to
+    /// [`MODBUS_ARRAY_CHUNK_REGISTERS`] registers, the storage is locked for each chunk separately (use
+    /// [`ModbusServerStorage::transaction()`] if the whole array must be updated atomically).
+    ///
+    /// Arrays which are larger than all storage areas are rejected at compile time, an error is
+    /// returned if the array does not fit into the mapping or into the storage area of the mapped
+    /// register kind
+    ///
+    /// # Panics
+    ///
+    /// Should not panic
+    pub fn write_array<T, const N: usize>(&mut self, values: &[T; N]) -> Result<()>
+    where
+        T: ModbusArrayElement,
+    {
+        let (registers, element_registers) = self.array_registers::<T, N>()?;
+        let chunk_len = (MODBUS_ARRAY_CHUNK_REGISTERS / element_registers).max(1);
+        let mut register = self.register;
+        for chunk in values.chunks(chunk_len) {
+            self.data_buf.truncate(0);
+            let mut cursor = Cursor::new(&mut self.data_buf);
+            for value in chunk {
+                value.write_be(&mut cursor)?;
+            }
+            set_data(&mut self.storage.lock(), register, &self.data_buf)?;
+            // can not overflow, the range is checked
+            register.offset += u16::try_from(chunk.len() * element_registers).unwrap();
+        }
+        self.last_value.take();
+        self.writes += 1;
+        self.changes.fetch_add(registers as u64, Ordering::SeqCst);
+        Ok(())
+    }
+    /// Reads an array of values. The data is read in chunks of up to [`MODBUS_ARRAY_CHUNK_REGISTERS`]
+    /// registers, the storage is locked for each chunk separately.
+    ///
+    /// Arrays which are larger than all storage areas are rejected at compile time, an error is
+    /// returned if the array does not fit into the mapping or into the storage area of the mapped
+    /// register kind
+    ///
+    /// # Panics
+    ///
+    /// Should not panic
+    pub fn read_array<T, const N: usize>(&mut self) -> Result<[T; N]>
+    where
+        T: ModbusArrayElement,
+    {
+        let (_, element_registers) = self.array_registers::<T, N>()?;
+        let chunk_len = (MODBUS_ARRAY_CHUNK_REGISTERS / element_registers).max(1);
+        let mut result = [T::default(); N];
+        let mut register = self.register;
+        for chunk in result.chunks_mut(chunk_len) {
+            let count = u16::try_from(chunk.len() * element_registers).unwrap();
+            self.data_buf.truncate(0);
+            get_data(&self.storage.lock(), register, count, &mut self.data_buf)?;
+            let mut cursor = Cursor::new(&self.data_buf);
+            for value in chunk {
+                *value = T::read_be(&mut cursor)?;
+            }
+            register.offset += count;
+        }
+        Ok(result)
+    }
+    // returns the number of array and element registers
+    fn array_registers<T, const N: usize>(&self) -> Result<(usize, usize)>
+    where
+        T: ModbusArrayElement,
+    {
+        let kind = self.register.kind;
+        let registers = data_registers(kind, ArraySize::<T, N, C, D, I, H>::BYTES)?;
+        if registers > usize::from(self.count) {
+            return Err(Error::invalid_data(format!(
+                "the array requires {} registers, the mapping has {}",
+                registers, self.count
+            )));
+        }
+        check_storage_range::<C, D, I, H>(self.register, registers)?;
+        Ok((registers, data_registers(kind, T::SIZE)?))
+    }
+    /// Resets the cached value, the next [`ModbusServerMapping::write_if_changed()`] call writes
+    /// the value unconditionally
+    pub fn reset_change_cache(&mut self) {
+        self.last_value.take();
+    }
+    /// Number of values written to the storage context with this mapping
+    pub fn writes(&self) -> u64 {
+        self.writes
+    }
+    /// Number of writes skipped by [`ModbusServerMapping::write_if_changed()`] as values have not
+    /// been changed
+    pub fn skipped_writes(&self) -> u64 {
+        self.skipped_writes
+    }
 }
 
 impl<const C: usize, const D: usize, const I: usize, const H: usize> IoMapping
@@ -305,6 +1498,182 @@ macro_rules! check_data_len_u16 {
                     .map_err(Error::io)?;
             }
         };
+        self.last_value.take();
+        self.writes += 1;
+        self.changes
+            .fetch_add(u64::from(self.count), Ordering::SeqCst);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::{
+        AccessPolicy, ModbusAccessRule, ModbusRegisterKind, ModbusServerMapping,
+        ModbusServerStorage, RequestInfo, MODBUS_WRITE_FUNCTIONS,
+    };
+    use crate::{
+        io::modbus::{ModbusChange, ModbusRegister},
+        Error,
+    };
+    use rmodbus::server::context::ModbusContext as _;
+
+    #[test]
+    fn test_access_rules() {
+        let scada = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let mut policy = AccessPolicy::default();
+        policy.rules.push(
+            ModbusAccessRule::allow()
+                .functions(&MODBUS_WRITE_FUNCTIONS)
+                .clients(&[scada]),
+        );
+        policy
+            .rules
+            .push(ModbusAccessRule::deny().functions(&MODBUS_WRITE_FUNCTIONS));
+        assert!(policy.is_allowed(Some(scada), 16));
+        assert!(!policy.is_allowed(Some(other), 16));
+        assert!(!policy.is_allowed(None, 5));
+        assert!(policy.is_allowed(Some(other), 3));
+        policy.default_allow = false;
+        assert!(!policy.is_allowed(Some(other), 3));
+    }
+
+    #[test]
+    fn test_request_info() {
+        let request = RequestInfo::parse(&[16, 0, 10, 0, 2, 4, 0, 1, 0, 2, 0, 0]);
+        assert_eq!(request.kind, Some(ModbusRegisterKind::Holding));
+        assert_eq!(request.range, 10..12);
+        assert_eq!(request.values, [0, 1, 0, 2]);
+        assert!(request.is_write());
+        let request = RequestInfo::parse(&[5, 0, 3, 0xff, 0]);
+        assert_eq!(request.kind, Some(ModbusRegisterKind::Coil));
+        assert_eq!(request.range, 3..4);
+        assert_eq!(request.values, [0xff, 0]);
+        let request = RequestInfo::parse(&[4, 0, 0, 0, 8]);
+        assert_eq!(request.range, 0..8);
+        assert!(request.values.is_empty());
+        assert!(!request.is_write());
+    }
+
+    #[test]
+    fn test_array_mapping() {
+        let mut mapping = ModbusServerMapping::<8, 8, 8, 256> {
+            storage: <_>::default(),
+            register: ModbusRegister::new(ModbusRegisterKind::Holding, 10),
+            count: 128,
+            data_buf: Vec::new(),
+            changes: <_>::default(),
+            last_value: None,
+            writes: 0,
+            skipped_writes: 0,
+        };
+        let mut waveform = [0f32; 64];
+        let mut value = 0.0;
+        for v in &mut waveform {
+            *v = value;
+            value += 0.5;
+        }
+        // 128 registers, written in 2 chunks
+        mapping.write_array(&waveform).unwrap();
+        assert_eq!(mapping.read_array::<f32, 64>().unwrap(), waveform);
+        assert_eq!(
+            mapping.changes.load(std::sync::atomic::Ordering::SeqCst),
+            128
+        );
+        let mut holdings = Vec::new();
+        mapping
+            .storage
+            .lock()
+            .get_holdings_as_u8(74, 2, &mut holdings)
+            .unwrap();
+        assert_eq!(holdings, 16f32.to_be_bytes());
+        // does not fit into the mapping
+        assert!(mapping.write_array(&[0u64; 33]).is_err());
+        // does not fit into the storage
+        mapping.register.offset = 200;
+        let err = mapping.write_array(&waveform).unwrap_err();
+        assert!(err.to_string().contains("increase the storage size"));
+        mapping.register.kind = ModbusRegisterKind::Coil;
+        mapping.register.offset = 0;
+        mapping.write_array(&[1u8, 0, 1]).unwrap();
+        assert_eq!(mapping.read_array::<u8, 3>().unwrap(), [1, 0, 1]);
+        // fits into the holding area but not into the coil one
+        let err = mapping.write_array(&[1u8; 16]).unwrap_err();
+        assert!(err.to_string().contains("increase the storage size"));
+        assert!(mapping.read_array::<u8, 16>().is_err());
+    }
+
+    #[test]
+    fn test_change_subscription() {
+        let storage = ModbusServerStorage::<8, 8, 8, 8> {
+            storage: <_>::default(),
+            changes: <_>::default(),
+        };
+        let reg = |offset| ModbusRegister::new(ModbusRegisterKind::Holding, offset);
+        let mut subscription = storage.subscribe().watch(reg(0), 4, 2).watch(
+            ModbusRegister::new(ModbusRegisterKind::Coil, 0),
+            2,
+            0,
+        );
+        let mut events = Vec::new();
+        assert_eq!(subscription.poll(&mut events).unwrap(), 6);
+        assert_eq!(subscription.poll(&mut events).unwrap(), 0);
+        events.clear();
+        storage
+            .transaction(|tx| {
+                tx.write(reg(1), 2u16)?;
+                tx.write(reg(2), 10u16)?;
+                tx.write(ModbusRegister::new(ModbusRegisterKind::Coil, 1), 1u8)
+            })
+            .unwrap();
+        subscription.poll(&mut events).unwrap();
+        assert_eq!(
+            events,
+            [
+                ModbusChange {
+                    kind: ModbusRegisterKind::Holding,
+                    offset: 2,
+                    value: 10
+                },
+                ModbusChange {
+                    kind: ModbusRegisterKind::Coil,
+                    offset: 1,
+                    value: 1
+                }
+            ]
+        );
+        // the deadband is checked against the last reported value
+        events.clear();
+        storage.transaction(|tx| tx.write(reg(1), 3u16)).unwrap();
+        subscription.poll(&mut events).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].offset, 1);
+    }
+
+    #[test]
+    fn test_transaction() {
+        let storage = ModbusServerStorage::<8, 8, 8, 8> {
+            storage: <_>::default(),
+            changes: <_>::default(),
+        };
+        let reg = |offset| ModbusRegister::new(ModbusRegisterKind::Holding, offset);
+        storage
+            .transaction(|tx| {
+                tx.write(reg(0), 0x0001_0002_0003_0004u64)?;
+                tx.write(reg(4), 5u16)
+            })
+            .unwrap();
+        assert_eq!(storage.changes.load(std::sync::atomic::Ordering::SeqCst), 5);
+        let result: crate::Result<()> = storage.transaction(|tx| {
+            tx.write(reg(0), 0u64)?;
+            Err(Error::failed("aborted"))
+        });
+        assert!(result.is_err());
+        let value = storage.transaction(|tx| tx.read::<u64>(reg(0), 4)).unwrap();
+        assert_eq!(value, 0x0001_0002_0003_0004);
+        assert!(storage.transaction(|tx| tx.write(reg(6), 0u64)).is_err());
+    }
+}
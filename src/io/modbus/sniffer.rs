@@ -0,0 +1,379 @@
+use std::{
+    io::{self, Read as _},
+    time::{Duration, Instant},
+};
+
+use serial::{SerialPort as _, SystemPort};
+use tracing::trace;
+
+use super::{ModbusRegister, ModbusRegisterKind};
+use crate::comm::serial::{open as open_serial, Parameters};
+use crate::{Error, Result};
+
+const DEFAULT_RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Decoded frame values
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ModbusValues {
+    None,
+    Bits(Vec<bool>),
+    Registers(Vec<u16>),
+}
+
+/// A decoded Modbus RTU frame
+#[derive(Debug, Clone)]
+pub struct ModbusFrame {
+    pub unit: u8,
+    /// Function code (without the exception bit)
+    pub function: u8,
+    /// Start register (requests only)
+    pub register: Option<ModbusRegister>,
+    /// Number of registers (requests only)
+    pub count: Option<u16>,
+    pub values: ModbusValues,
+    /// Exception code (responses only)
+    pub exception: Option<u8>,
+    pub crc_ok: bool,
+    /// The frame is not a valid Modbus frame or the function is not supported
+    pub malformed: bool,
+    pub raw: Vec<u8>,
+    pub time: Instant,
+}
+
+/// A sniffed transaction: a request and a response (if received)
+#[derive(Debug, Clone)]
+pub struct ModbusTransaction {
+    pub request: ModbusFrame,
+    pub response: Option<ModbusFrame>,
+}
+
+impl ModbusTransaction {
+    /// Response time
+    pub fn duration(&self) -> Option<Duration> {
+        self.response
+            .as_ref()
+            .map(|r| r.time.saturating_duration_since(self.request.time))
+    }
+}
+
+/// Listen-only Modbus RTU bus sniffer. Passively reads the bus, splits frames by silent intervals
+/// and decodes transactions of other masters, never transmits any data.
+///
+/// Example (sending transactions to the hub):
+///
+/// ```rust,no_run
+/// use roboplc::hub::Hub;
+/// use roboplc::io::modbus::{ModbusSniffer, ModbusTransaction};
+/// use roboplc::DataPolicy;
+///
+/// #[derive(Clone, DataPolicy)]
+/// enum Message {
+///     ModbusTransaction(ModbusTransaction),
+/// }
+///
+/// let hub: Hub<Message> = Hub::new();
+/// let mut sniffer = ModbusSniffer::open("/dev/ttyS0:9600:8:N:1").unwrap();
+/// sniffer.run(|t| { hub.send(Message::ModbusTransaction(t)); }).unwrap();
+/// ```
+#[allow(clippy::module_name_repetitions)]
+pub struct ModbusSniffer {
+    port: SystemPort,
+    decoder: Decoder,
+}
+
+impl ModbusSniffer {
+    /// Opens the serial port (the path format is the same as for [`crate::comm::serial`]
+    /// clients). Frames are split by the Modbus RTU silent interval (T3.5)
+    pub fn open(path: &str) -> Result<Self> {
+        let params: Parameters = path.parse()?;
+        let port = open_serial(&params, params.inter_frame_delay())?;
+        Ok(Self {
+            port,
+            decoder: Decoder::new(DEFAULT_RESPONSE_TIMEOUT),
+        })
+    }
+    /// Sets the silent interval which splits frames (the default is T3.5, calculated from the
+    /// port parameters). Increasing the value may help with slow USB-RS485 converters
+    pub fn frame_gap(mut self, frame_gap: Duration) -> Result<Self> {
+        self.port.set_timeout(frame_gap).map_err(Error::io)?;
+        Ok(self)
+    }
+    /// Sets the maximum time between a request and a response (the default is 1 second)
+    pub fn response_timeout(mut self, response_timeout: Duration) -> Self {
+        self.decoder.response_timeout = response_timeout;
+        self
+    }
+    /// Reads the bus and calls the function for each decoded transaction. Returns on read errors
+    /// only
+    pub fn run<F: FnMut(ModbusTransaction)>(&mut self, mut f: F) -> Result<()> {
+        let mut frame = Vec::new();
+        let mut buf = [0u8; 256];
+        loop {
+            match self.port.read(&mut buf) {
+                Ok(n) if n > 0 => {
+                    frame.extend_from_slice(&buf[..n]);
+                    continue;
+                }
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e.into()),
+            }
+            if !frame.is_empty() {
+                trace!(len = frame.len(), "Modbus frame sniffed");
+                for t in self.decoder.push(&frame, Instant::now()) {
+                    f(t);
+                }
+                frame.clear();
+            }
+        }
+    }
+}
+
+struct Decoder {
+    pending: Option<ModbusFrame>,
+    response_timeout: Duration,
+}
+
+impl Decoder {
+    fn new(response_timeout: Duration) -> Self {
+        Self {
+            pending: None,
+            response_timeout,
+        }
+    }
+    fn is_response(&self, frame: &[u8], now: Instant) -> bool {
+        let Some(ref pending) = self.pending else {
+            return false;
+        };
+        // broadcast requests have no responses
+        pending.unit != 0
+            && frame.len() >= 2
+            && frame[0] == pending.unit
+            && frame[1] & 0x7f == pending.function
+            && now.saturating_duration_since(pending.time) <= self.response_timeout
+    }
+    // a single silent interval may contain several frames if the gap is too short
+    fn push(&mut self, data: &[u8], now: Instant) -> Vec<ModbusTransaction> {
+        let mut result = Vec::new();
+        let mut rest = data;
+        while !rest.is_empty() {
+            let as_response = self.is_response(rest, now);
+            let len = expected_len(rest, as_response)
+                .filter(|len| *len <= rest.len())
+                .unwrap_or(rest.len());
+            let (frame, r) = rest.split_at(len);
+            rest = r;
+            if as_response {
+                let request = self.pending.take().unwrap();
+                let mut response = decode(frame, true, now);
+                if let (ModbusValues::Bits(ref mut bits), Some(count)) =
+                    (&mut response.values, request.count)
+                {
+                    bits.truncate(usize::from(count));
+                }
+                result.push(ModbusTransaction {
+                    request,
+                    response: Some(response),
+                });
+            } else {
+                if let Some(request) = self.pending.take() {
+                    result.push(ModbusTransaction {
+                        request,
+                        response: None,
+                    });
+                }
+                self.pending = Some(decode(frame, false, now));
+            }
+        }
+        result
+    }
+}
+
+fn expected_len(frame: &[u8], as_response: bool) -> Option<usize> {
+    let function = *frame.get(1)?;
+    if as_response && function & 0x80 != 0 {
+        return Some(5);
+    }
+    match (function, as_response) {
+        (1..=4, false) | (5 | 6, _) | (15 | 16, true) => Some(8),
+        (1..=4, true) => frame.get(2).map(|n| 5 + usize::from(*n)),
+        (15 | 16, false) => frame.get(6).map(|n| 9 + usize::from(*n)),
+        _ => None,
+    }
+}
+
+fn register_kind(function: u8) -> Option<ModbusRegisterKind> {
+    match function {
+        1 | 5 | 15 => Some(ModbusRegisterKind::Coil),
+        2 => Some(ModbusRegisterKind::Discrete),
+        3 | 6 | 16 => Some(ModbusRegisterKind::Holding),
+        4 => Some(ModbusRegisterKind::Input),
+        _ => None,
+    }
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*data.get(pos)?, *data.get(pos + 1)?]))
+}
+
+fn bits(data: &[u8]) -> Vec<bool> {
+    data.iter()
+        .flat_map(|byte| (0..8).map(move |i| byte & (1 << i) != 0))
+        .collect()
+}
+
+fn registers(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2)
+        .map(|c| u16::from_be_bytes([c[0], c[1]]))
+        .collect()
+}
+
+fn decode(frame: &[u8], is_response: bool, time: Instant) -> ModbusFrame {
+    let mut result = ModbusFrame {
+        unit: frame.first().copied().unwrap_or_default(),
+        function: frame.get(1).map_or(0, |f| f & 0x7f),
+        register: None,
+        count: None,
+        values: ModbusValues::None,
+        exception: None,
+        crc_ok: check_crc(frame),
+        malformed: false,
+        raw: frame.to_vec(),
+        time,
+    };
+    if frame.len() < 4 {
+        result.malformed = true;
+        return result;
+    }
+    // without the unit, the function code and the CRC
+    let pdu = &frame[2..frame.len() - 2];
+    let function = frame[1];
+    let decoded = if is_response && function & 0x80 != 0 {
+        result.exception = pdu.first().copied();
+        result.exception.is_some()
+    } else if let Some(kind) = register_kind(function) {
+        decode_pdu(&mut result, kind, function, pdu, is_response).is_some()
+    } else {
+        false
+    };
+    result.malformed = !decoded;
+    result
+}
+
+fn decode_pdu(
+    frame: &mut ModbusFrame,
+    kind: ModbusRegisterKind,
+    function: u8,
+    pdu: &[u8],
+    is_response: bool,
+) -> Option<()> {
+    if is_response {
+        match function {
+            1 | 2 => frame.values = ModbusValues::Bits(bits(pdu.get(1..)?)),
+            3 | 4 => frame.values = ModbusValues::Registers(registers(pdu.get(1..)?)),
+            5 => frame.values = ModbusValues::Bits(vec![u16_at(pdu, 2)? == 0xff00]),
+            6 => frame.values = ModbusValues::Registers(vec![u16_at(pdu, 2)?]),
+            _ => {
+                // write multiple confirmation
+                u16_at(pdu, 2)?;
+            }
+        }
+        return Some(());
+    }
+    frame.register = Some(ModbusRegister::new(kind, u16_at(pdu, 0)?));
+    match function {
+        1..=4 => frame.count = Some(u16_at(pdu, 2)?),
+        5 => {
+            frame.count = Some(1);
+            frame.values = ModbusValues::Bits(vec![u16_at(pdu, 2)? == 0xff00]);
+        }
+        6 => {
+            frame.count = Some(1);
+            frame.values = ModbusValues::Registers(vec![u16_at(pdu, 2)?]);
+        }
+        15 => {
+            let count = u16_at(pdu, 2)?;
+            let mut values = bits(pdu.get(5..)?);
+            values.truncate(usize::from(count));
+            frame.count = Some(count);
+            frame.values = ModbusValues::Bits(values);
+        }
+        _ => {
+            frame.count = Some(u16_at(pdu, 2)?);
+            frame.values = ModbusValues::Registers(registers(pdu.get(5..)?));
+        }
+    }
+    Some(())
+}
+
+pub(super) fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for byte in data {
+        crc ^= u16::from(*byte);
+        for _ in 0..8 {
+            if crc & 1 == 0 {
+                crc >>= 1;
+            } else {
+                crc = (crc >> 1) ^ 0xA001;
+            }
+        }
+    }
+    crc
+}
+
+fn check_crc(frame: &[u8]) -> bool {
+    if frame.len() < 4 {
+        return false;
+    }
+    let (data, crc) = frame.split_at(frame.len() - 2);
+    crc16(data).to_le_bytes() == [crc[0], crc[1]]
+}
+
+#[cfg(test)]
+mod test {
+    use super::{crc16, Decoder, ModbusValues};
+    use crate::io::modbus::{ModbusRegister, ModbusRegisterKind};
+    use std::time::{Duration, Instant};
+
+    fn with_crc(data: &[u8]) -> Vec<u8> {
+        let mut frame = data.to_vec();
+        frame.extend(crc16(data).to_le_bytes());
+        frame
+    }
+
+    #[test]
+    fn test_decode_transactions() {
+        let mut decoder = Decoder::new(Duration::from_secs(1));
+        let now = Instant::now();
+        // read holdings 100-101 of unit 1
+        let request = with_crc(&[1, 3, 0, 100, 0, 2]);
+        assert_eq!(request, [1, 3, 0, 100, 0, 2, 0x85, 0xd4]);
+        let response = with_crc(&[1, 3, 4, 0, 1, 0, 2]);
+        assert!(decoder.push(&request, now).is_empty());
+        let t = decoder.push(&response, now);
+        assert_eq!(t.len(), 1);
+        assert_eq!(
+            t[0].request.register,
+            Some(ModbusRegister::new(ModbusRegisterKind::Holding, 100))
+        );
+        assert_eq!(t[0].request.count, Some(2));
+        let r = t[0].response.as_ref().unwrap();
+        assert!(r.crc_ok);
+        assert_eq!(r.values, ModbusValues::Registers(vec![1, 2]));
+        // write coil request and exception response in a single chunk
+        let mut data = with_crc(&[2, 5, 0, 7, 0xff, 0]);
+        data.extend(with_crc(&[2, 0x85, 2]));
+        let t = decoder.push(&data, now);
+        assert_eq!(t.len(), 1);
+        assert_eq!(t[0].request.values, ModbusValues::Bits(vec![true]));
+        assert_eq!(t[0].response.as_ref().unwrap().exception, Some(2));
+        // a corrupted request without a response
+        let mut request = with_crc(&[3, 1, 0, 0, 0, 10]);
+        request[7] ^= 0xff;
+        assert!(decoder.push(&request, now).is_empty());
+        let t = decoder.push(&with_crc(&[1, 4, 0, 0, 0, 1]), now);
+        assert_eq!(t.len(), 1);
+        assert!(!t[0].request.crc_ok);
+        assert!(t[0].response.is_none());
+    }
+}
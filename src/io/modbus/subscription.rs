@@ -0,0 +1,120 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use parking_lot_rt::Mutex;
+use rmodbus::server::storage::ModbusStorage;
+
+use super::server::get_data;
+use super::{ModbusRegister, ModbusRegisterKind};
+use crate::Result;
+
+/// A register change event. Values of coils and discretes are 0/1
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ModbusChange {
+    pub kind: ModbusRegisterKind,
+    pub offset: u16,
+    pub value: u16,
+}
+
+struct Watch {
+    register: ModbusRegister,
+    count: u16,
+    deadband: u16,
+    // the last reported values
+    reported: Vec<Option<u16>>,
+    buf: Vec<u8>,
+}
+
+/// Register change subscription of the server storage (internal and external writes), created
+/// with [`super::ModbusServerStorage::subscribe()`]. Can be used e.g. by HMI workers to avoid
+/// scanning the whole storage context every frame.
+///
+/// Watched ranges are scanned by [`ModbusChangeSubscription::poll()`] only if the storage has been
+/// changed since the previous call. The first poll reports all watched registers.
+pub struct ModbusChangeSubscription<const C: usize, const D: usize, const I: usize, const H: usize>
+{
+    storage: Arc<Mutex<ModbusStorage<C, D, I, H>>>,
+    changes: Arc<AtomicU64>,
+    seen: Option<u64>,
+    watches: Vec<Watch>,
+}
+
+impl<const C: usize, const D: usize, const I: usize, const H: usize>
+    ModbusChangeSubscription<C, D, I, H>
+{
+    pub(super) fn new(
+        storage: Arc<Mutex<ModbusStorage<C, D, I, H>>>,
+        changes: Arc<AtomicU64>,
+    ) -> Self {
+        Self {
+            storage,
+            changes,
+            seen: None,
+            watches: Vec::new(),
+        }
+    }
+    /// Watches a range of registers. A change is reported if the value differs from the
+    /// previously reported one by more than the deadband (in raw register units, ignored for
+    /// coils and discretes)
+    pub fn watch(mut self, register: ModbusRegister, count: u16, deadband: u16) -> Self {
+        self.watches.push(Watch {
+            register,
+            count,
+            deadband,
+            reported: vec![None; usize::from(count)],
+            buf: Vec::new(),
+        });
+        // the new range must be scanned
+        self.seen.take();
+        self
+    }
+    /// Collects changes of the watched registers into the vector, returns the number of changes
+    ///
+    /// # Panics
+    ///
+    /// Should not panic
+    pub fn poll(&mut self, events: &mut Vec<ModbusChange>) -> Result<usize> {
+        let changes = self.changes.load(Ordering::SeqCst);
+        if self.seen == Some(changes) {
+            return Ok(0);
+        }
+        {
+            let storage = self.storage.lock();
+            for watch in &mut self.watches {
+                watch.buf.truncate(0);
+                get_data(&storage, watch.register, watch.count, &mut watch.buf)?;
+            }
+        }
+        self.seen = Some(changes);
+        let len = events.len();
+        for watch in &mut self.watches {
+            let kind = watch.register.kind;
+            let bits = matches!(
+                kind,
+                ModbusRegisterKind::Coil | ModbusRegisterKind::Discrete
+            );
+            let (width, deadband) = if bits { (1, 0) } else { (2, watch.deadband) };
+            for (i, (chunk, reported)) in
+                watch.buf.chunks(width).zip(&mut watch.reported).enumerate()
+            {
+                let value = if bits {
+                    u16::from(chunk[0])
+                } else {
+                    u16::from_be_bytes([chunk[0], chunk[1]])
+                };
+                if reported.map_or(true, |r| value.abs_diff(r) > deadband) {
+                    reported.replace(value);
+                    events.push(ModbusChange {
+                        kind,
+                        // can not overflow, the range has been read
+                        offset: watch.register.offset + u16::try_from(i).unwrap(),
+                        value,
+                    });
+                }
+            }
+        }
+        Ok(events.len() - len)
+    }
+}
@@ -0,0 +1,202 @@
+//! Modbus/TCP Security (TLS) support, see the Modbus/TCP Security protocol specification
+use std::{
+    fs,
+    io::{self, BufReader},
+    net::{SocketAddr, TcpStream},
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use rustls::{
+    pki_types::{CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore, ServerConfig, ServerConnection, StreamOwned,
+};
+
+use crate::{Error, Result};
+
+/// The default Modbus/TCP Security port
+pub const MODBUS_TLS_PORT: u16 = 802;
+
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Modbus/TCP Security role extension, OID 1.3.6.1.4.1.50316.802.1 (DER-encoded)
+const ROLE_OID: [u8; 13] = [
+    0x06, 0x0b, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0x89, 0x0c, 0x86, 0x22, 0x01,
+];
+
+/// A function which validates authenticated TLS clients, returns false to reject the client
+pub type TlsClientValidator = fn(&TlsClient) -> bool;
+
+/// Authenticated TLS client information
+#[derive(Debug, Clone)]
+pub struct TlsClient {
+    pub addr: SocketAddr,
+    /// Client certificate chain (DER), the end-entity certificate first
+    pub certificates: Vec<Vec<u8>>,
+    /// The role from the Modbus/TCP Security role certificate extension
+    pub role: Option<String>,
+}
+
+/// TLS configuration for [`super::ModbusServer`]. Client certificates are mandatory, as
+/// Modbus/TCP Security requires mutual authentication
+#[derive(Clone)]
+pub struct ModbusTlsConfig {
+    config: Arc<ServerConfig>,
+    validator: Option<TlsClientValidator>,
+    handshake_timeout: Duration,
+}
+
+impl ModbusTlsConfig {
+    /// Creates a configuration from PEM files: the server certificate chain, the server private
+    /// key and CA certificates to verify clients
+    pub fn from_pem_files<P: AsRef<Path>>(cert_chain: P, key: P, client_ca: P) -> Result<Self> {
+        let certs = load_certs(cert_chain.as_ref())?;
+        let key = load_key(key.as_ref())?;
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(client_ca.as_ref())? {
+            roots.add(cert).map_err(Error::invalid_data)?;
+        }
+        let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+            .build()
+            .map_err(Error::invalid_data)?;
+        let config = ServerConfig::builder()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(certs, key)
+            .map_err(Error::invalid_data)?;
+        Ok(Self::from_server_config(Arc::new(config)))
+    }
+    /// Creates a configuration from a custom rustls server configuration
+    pub fn from_server_config(config: Arc<ServerConfig>) -> Self {
+        Self {
+            config,
+            validator: None,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+        }
+    }
+    /// Sets a function which validates clients after the handshake (e.g. checks the role or
+    /// the certificate subject)
+    pub fn client_validator(mut self, validator: TlsClientValidator) -> Self {
+        self.validator = Some(validator);
+        self
+    }
+    /// Sets the handshake timeout (the default is 10 seconds)
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+    // performs the handshake and validates the client, the stream must have a read timeout set
+    pub(super) fn accept(
+        &self,
+        mut stream: TcpStream,
+        addr: SocketAddr,
+    ) -> Result<StreamOwned<ServerConnection, TcpStream>> {
+        let mut conn = ServerConnection::new(self.config.clone()).map_err(Error::io)?;
+        let started = Instant::now();
+        while conn.is_handshaking() {
+            match conn.complete_io(&mut stream) {
+                Ok(_) => {}
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    if started.elapsed() >= self.handshake_timeout {
+                        return Err(Error::Timeout);
+                    }
+                }
+                Err(e) => return Err(Error::io(e)),
+            }
+        }
+        let certificates: Vec<Vec<u8>> = conn
+            .peer_certificates()
+            .map(|certs| certs.iter().map(|c| c.as_ref().to_vec()).collect())
+            .unwrap_or_default();
+        let client = TlsClient {
+            addr,
+            role: certificates.first().and_then(|c| parse_role(c)),
+            certificates,
+        };
+        if let Some(validator) = self.validator {
+            if !validator(&client) {
+                return Err(Error::failed(format!("TLS client {} rejected", addr)));
+            }
+        }
+        Ok(StreamOwned::new(conn, stream))
+    }
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    rustls_pemfile::certs(&mut reader)
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(Into::into)
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    rustls_pemfile::private_key(&mut reader)?
+        .ok_or_else(|| Error::invalid_data(format!("no private key found in {}", path.display())))
+}
+
+// returns the DER value length and the length of the length field
+fn der_len(buf: &[u8]) -> Option<(usize, usize)> {
+    let first = *buf.first()?;
+    if first & 0x80 == 0 {
+        return Some((usize::from(first), 1));
+    }
+    let n = usize::from(first & 0x7f);
+    if n == 0 || n > 4 {
+        return None;
+    }
+    let len = buf
+        .get(1..=n)?
+        .iter()
+        .fold(0usize, |acc, &b| (acc << 8) | usize::from(b));
+    Some((len, n + 1))
+}
+
+/// Extracts the role (UTF8String) from the Modbus/TCP Security role extension of a DER-encoded
+/// certificate
+pub fn parse_role(cert: &[u8]) -> Option<String> {
+    let pos = cert.windows(ROLE_OID.len()).position(|w| w == ROLE_OID)? + ROLE_OID.len();
+    let mut rest = &cert[pos..];
+    // the optional "critical" flag
+    if rest.first() == Some(&0x01) {
+        rest = rest.get(3..)?;
+    }
+    // extnValue OCTET STRING, which contains the DER-encoded UTF8String
+    if rest.first() != Some(&0x04) {
+        return None;
+    }
+    let (_, n) = der_len(&rest[1..])?;
+    let value = rest.get(1 + n..)?;
+    if value.first() != Some(&0x0c) {
+        return None;
+    }
+    let (len, n) = der_len(&value[1..])?;
+    let role = value.get(1 + n..1 + n + len)?;
+    String::from_utf8(role.to_vec()).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_role, ROLE_OID};
+
+    #[test]
+    fn test_role() {
+        // SEQUENCE { OID, critical, OCTET STRING { UTF8String "Operator" } }
+        let mut ext = vec![0x30, 0x1c];
+        ext.extend(ROLE_OID);
+        ext.extend([0x01, 0x01, 0x00]);
+        ext.extend([0x04, 0x0a, 0x0c, 0x08]);
+        ext.extend(b"Operator");
+        let mut cert = vec![0x30, 0x82, 0x01, 0x00, 0xa0, 0x03, 0x02, 0x01, 0x02];
+        cert.extend(&ext);
+        assert_eq!(parse_role(&cert).unwrap(), "Operator");
+        assert!(parse_role(&cert[..cert.len() - 3]).is_none());
+        assert!(parse_role(&[0x30, 0x00]).is_none());
+    }
+}
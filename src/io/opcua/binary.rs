@@ -0,0 +1,639 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Serialize, Serializer};
+
+use crate::{Error, Result};
+
+// 100ns intervals between 1601-01-01 and 1970-01-01
+const EPOCH_DIFF: i64 = 116_444_736_000_000_000;
+
+// variants nested deeper are rejected
+const MAX_DEPTH: usize = 8;
+
+/// OPC UA node identifier, e.g. `ns=2;s=Line1.Speed` or `ns=3;i=1001`
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct NodeId {
+    pub namespace: u16,
+    pub identifier: Identifier,
+}
+
+/// Node identifier value
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Identifier {
+    Numeric(u32),
+    String(String),
+    Guid([u8; 16]),
+    Opaque(Vec<u8>),
+}
+
+impl NodeId {
+    pub fn numeric(namespace: u16, id: u32) -> Self {
+        Self {
+            namespace,
+            identifier: Identifier::Numeric(id),
+        }
+    }
+    pub fn string(namespace: u16, id: &str) -> Self {
+        Self {
+            namespace,
+            identifier: Identifier::String(id.to_owned()),
+        }
+    }
+    pub(super) fn null() -> Self {
+        Self::numeric(0, 0)
+    }
+    pub(super) fn encode(&self, buf: &mut Vec<u8>) {
+        match self.identifier {
+            Identifier::Numeric(id) => {
+                if self.namespace == 0 && id <= 0xff {
+                    // two byte encoding
+                    buf.push(0x00);
+                    buf.extend(u8::try_from(id).unwrap_or_default().to_le_bytes());
+                } else if self.namespace <= 0xff && id <= 0xffff {
+                    // four byte encoding
+                    buf.push(0x01);
+                    buf.extend(
+                        u8::try_from(self.namespace)
+                            .unwrap_or_default()
+                            .to_le_bytes(),
+                    );
+                    buf.extend(u16::try_from(id).unwrap_or_default().to_le_bytes());
+                } else {
+                    buf.push(0x02);
+                    buf.extend(self.namespace.to_le_bytes());
+                    buf.extend(id.to_le_bytes());
+                }
+            }
+            Identifier::String(ref id) => {
+                buf.push(0x03);
+                buf.extend(self.namespace.to_le_bytes());
+                put_string(buf, Some(id));
+            }
+            Identifier::Guid(ref id) => {
+                buf.push(0x04);
+                buf.extend(self.namespace.to_le_bytes());
+                buf.extend(id);
+            }
+            Identifier::Opaque(ref id) => {
+                buf.push(0x05);
+                buf.extend(self.namespace.to_le_bytes());
+                put_bytes(buf, Some(id));
+            }
+        }
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.namespace != 0 {
+            write!(f, "ns={};", self.namespace)?;
+        }
+        match self.identifier {
+            Identifier::Numeric(id) => write!(f, "i={}", id),
+            Identifier::String(ref id) => write!(f, "s={}", id),
+            Identifier::Guid(ref id) => {
+                write!(f, "g=")?;
+                // data1-3 are little-endian in the binary encoding
+                for (i, pos) in [3, 2, 1, 0, 5, 4, 7, 6, 8, 9, 10, 11, 12, 13, 14, 15]
+                    .into_iter()
+                    .enumerate()
+                {
+                    if matches!(i, 4 | 6 | 8 | 10) {
+                        write!(f, "-")?;
+                    }
+                    write!(f, "{:02x}", id[pos])?;
+                }
+                Ok(())
+            }
+            Identifier::Opaque(ref id) => {
+                write!(f, "b=")?;
+                for b in id {
+                    write!(f, "{:02x}", b)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FromStr for NodeId {
+    type Err = Error;
+    /// Parses the standard notation (`ns=<namespace>;<i|s|g|b>=<identifier>`, the namespace
+    /// defaults to 0). Opaque identifiers are given in hex
+    fn from_str(s: &str) -> Result<Self> {
+        let err = || Error::invalid_data(format!("invalid node id: {}", s));
+        let (namespace, id) = if let Some(rest) = s.strip_prefix("ns=") {
+            let (ns, id) = rest.split_once(';').ok_or_else(err)?;
+            (ns.parse().map_err(|_| err())?, id)
+        } else {
+            (0, s)
+        };
+        let (kind, value) = id.split_once('=').ok_or_else(err)?;
+        let identifier = match kind {
+            "i" => Identifier::Numeric(value.parse().map_err(|_| err())?),
+            "s" => Identifier::String(value.to_owned()),
+            "g" => {
+                let hex: String = value.chars().filter(|c| *c != '-').collect();
+                let bytes = parse_hex(&hex).ok_or_else(err)?;
+                let bytes: [u8; 16] = bytes.try_into().map_err(|_| err())?;
+                let mut guid = [0u8; 16];
+                for (i, pos) in [3, 2, 1, 0, 5, 4, 7, 6, 8, 9, 10, 11, 12, 13, 14, 15]
+                    .into_iter()
+                    .enumerate()
+                {
+                    guid[pos] = bytes[i];
+                }
+                Identifier::Guid(guid)
+            }
+            "b" => Identifier::Opaque(parse_hex(value).ok_or_else(err)?),
+            _ => return Err(err()),
+        };
+        Ok(Self {
+            namespace,
+            identifier,
+        })
+    }
+}
+
+impl Serialize for NodeId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// OPC UA variant value (built-in types)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum OpcUaValue {
+    Null,
+    Bool(bool),
+    I8(i8),
+    U8(u8),
+    I16(i16),
+    U16(u16),
+    I32(i32),
+    U32(u32),
+    I64(i64),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+    String(String),
+    /// 100 nanosecond intervals since 1601-01-01 UTC
+    DateTime(i64),
+    ByteString(Vec<u8>),
+    Array(Vec<OpcUaValue>),
+}
+
+impl OpcUaValue {
+    /// Returns a numeric (or boolean) value as f64
+    #[allow(clippy::cast_precision_loss)]
+    pub fn as_f64(&self) -> Option<f64> {
+        Some(match *self {
+            OpcUaValue::F32(v) => f64::from(v),
+            OpcUaValue::F64(v) => v,
+            _ => self.as_i128()? as f64,
+        })
+    }
+    /// Returns an integer (or boolean) value as i128
+    pub fn as_i128(&self) -> Option<i128> {
+        Some(match *self {
+            OpcUaValue::Bool(v) => i128::from(v),
+            OpcUaValue::I8(v) => i128::from(v),
+            OpcUaValue::U8(v) => i128::from(v),
+            OpcUaValue::I16(v) => i128::from(v),
+            OpcUaValue::U16(v) => i128::from(v),
+            OpcUaValue::I32(v) => i128::from(v),
+            OpcUaValue::U32(v) => i128::from(v),
+            OpcUaValue::I64(v) | OpcUaValue::DateTime(v) => i128::from(v),
+            OpcUaValue::U64(v) => i128::from(v),
+            _ => return None,
+        })
+    }
+    /// Converts a DateTime value into UNIX time (nanoseconds)
+    pub fn as_unix_nanos(&self) -> Option<i128> {
+        if let OpcUaValue::DateTime(v) = *self {
+            Some(i128::from(v - EPOCH_DIFF) * 100)
+        } else {
+            None
+        }
+    }
+    fn type_id(&self) -> u8 {
+        match self {
+            OpcUaValue::Null => 0,
+            OpcUaValue::Bool(_) => 1,
+            OpcUaValue::I8(_) => 2,
+            OpcUaValue::U8(_) => 3,
+            OpcUaValue::I16(_) => 4,
+            OpcUaValue::U16(_) => 5,
+            OpcUaValue::I32(_) => 6,
+            OpcUaValue::U32(_) => 7,
+            OpcUaValue::I64(_) => 8,
+            OpcUaValue::U64(_) => 9,
+            OpcUaValue::F32(_) => 10,
+            OpcUaValue::F64(_) => 11,
+            OpcUaValue::String(_) => 12,
+            OpcUaValue::DateTime(_) => 13,
+            OpcUaValue::ByteString(_) => 15,
+            OpcUaValue::Array(items) => items.first().map_or(0, OpcUaValue::type_id),
+        }
+    }
+    fn encode_scalar(&self, buf: &mut Vec<u8>) -> Result<()> {
+        match self {
+            OpcUaValue::Null => {}
+            OpcUaValue::Bool(v) => buf.push(u8::from(*v)),
+            OpcUaValue::I8(v) => buf.extend(v.to_le_bytes()),
+            OpcUaValue::U8(v) => buf.push(*v),
+            OpcUaValue::I16(v) => buf.extend(v.to_le_bytes()),
+            OpcUaValue::U16(v) => buf.extend(v.to_le_bytes()),
+            OpcUaValue::I32(v) => buf.extend(v.to_le_bytes()),
+            OpcUaValue::U32(v) => buf.extend(v.to_le_bytes()),
+            OpcUaValue::I64(v) | OpcUaValue::DateTime(v) => buf.extend(v.to_le_bytes()),
+            OpcUaValue::U64(v) => buf.extend(v.to_le_bytes()),
+            OpcUaValue::F32(v) => buf.extend(v.to_le_bytes()),
+            OpcUaValue::F64(v) => buf.extend(v.to_le_bytes()),
+            OpcUaValue::String(v) => put_string(buf, Some(v)),
+            OpcUaValue::ByteString(v) => put_bytes(buf, Some(v)),
+            OpcUaValue::Array(_) => {
+                return Err(Error::invalid_data(
+                    "nested OPC UA arrays are not supported",
+                ))
+            }
+        }
+        Ok(())
+    }
+    /// Encodes the value as Variant
+    pub(super) fn encode(&self, buf: &mut Vec<u8>) -> Result<()> {
+        let type_id = self.type_id();
+        if let OpcUaValue::Array(items) = self {
+            if items.iter().any(|v| v.type_id() != type_id) {
+                return Err(Error::invalid_data(
+                    "OPC UA array items must be of the same type",
+                ));
+            }
+            buf.push(type_id | 0x80);
+            put_len(buf, Some(items.len()))?;
+            for item in items {
+                item.encode_scalar(buf)?;
+            }
+        } else {
+            buf.push(type_id);
+            self.encode_scalar(buf)?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts UNIX time (nanoseconds) into OPC UA DateTime
+pub(super) fn date_time(unix_nanos: u128) -> i64 {
+    i64::try_from(unix_nanos / 100).map_or(i64::MAX, |v| v + EPOCH_DIFF)
+}
+
+pub(super) fn put_len(buf: &mut Vec<u8>, len: Option<usize>) -> Result<()> {
+    let len = if let Some(len) = len {
+        i32::try_from(len).map_err(|_| Error::invalid_data("OPC UA value too large"))?
+    } else {
+        -1
+    };
+    buf.extend(len.to_le_bytes());
+    Ok(())
+}
+
+pub(super) fn put_bytes(buf: &mut Vec<u8>, value: Option<&[u8]>) {
+    if let Some(value) = value {
+        // i32 overflow is not possible for messages of the client
+        buf.extend(i32::try_from(value.len()).unwrap_or(i32::MAX).to_le_bytes());
+        buf.extend(value);
+    } else {
+        buf.extend((-1i32).to_le_bytes());
+    }
+}
+
+pub(super) fn put_string(buf: &mut Vec<u8>, value: Option<&str>) {
+    put_bytes(buf, value.map(str::as_bytes));
+}
+
+/// Little-endian decoder of OPC UA binary messages
+pub(super) struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(super) fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+    /// The remaining data
+    pub(super) fn rest(&self) -> &'a [u8] {
+        &self.buf[self.pos..]
+    }
+    pub(super) fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let data = self
+            .buf
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| Error::invalid_data("OPC UA message truncated"))?;
+        self.pos += len;
+        Ok(data)
+    }
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        // the length is checked by take
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+    pub(super) fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+    pub(super) fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take_array()?))
+    }
+    pub(super) fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take_array()?))
+    }
+    pub(super) fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.take_array()?))
+    }
+    pub(super) fn i64(&mut self) -> Result<i64> {
+        Ok(i64::from_le_bytes(self.take_array()?))
+    }
+    pub(super) fn f64(&mut self) -> Result<f64> {
+        Ok(f64::from_le_bytes(self.take_array()?))
+    }
+    /// Array length, None for null arrays
+    pub(super) fn len(&mut self) -> Result<Option<usize>> {
+        let len = self.i32()?;
+        if len < 0 {
+            return Ok(None);
+        }
+        let len = usize::try_from(len).map_err(Error::invalid_data)?;
+        if len > self.buf.len() - self.pos {
+            // every element takes at least one byte
+            return Err(Error::invalid_data("OPC UA message truncated"));
+        }
+        Ok(Some(len))
+    }
+    pub(super) fn bytes(&mut self) -> Result<Option<&'a [u8]>> {
+        self.len()?.map(|len| self.take(len)).transpose()
+    }
+    pub(super) fn string(&mut self) -> Result<Option<String>> {
+        self.bytes()?
+            .map(|b| String::from_utf8(b.to_vec()).map_err(Error::invalid_data))
+            .transpose()
+    }
+    pub(super) fn node_id(&mut self) -> Result<NodeId> {
+        let encoding = self.u8()?;
+        self.node_id_with_encoding(encoding & 0x0f)
+    }
+    fn node_id_with_encoding(&mut self, encoding: u8) -> Result<NodeId> {
+        Ok(match encoding {
+            0x00 => NodeId::numeric(0, u32::from(self.u8()?)),
+            0x01 => {
+                let namespace = u16::from(self.u8()?);
+                NodeId::numeric(namespace, u32::from(self.u16()?))
+            }
+            0x02 => {
+                let namespace = self.u16()?;
+                NodeId::numeric(namespace, self.u32()?)
+            }
+            0x03 => {
+                let namespace = self.u16()?;
+                NodeId {
+                    namespace,
+                    identifier: Identifier::String(self.string()?.unwrap_or_default()),
+                }
+            }
+            0x04 => {
+                let namespace = self.u16()?;
+                NodeId {
+                    namespace,
+                    identifier: Identifier::Guid(self.take_array()?),
+                }
+            }
+            0x05 => {
+                let namespace = self.u16()?;
+                NodeId {
+                    namespace,
+                    identifier: Identifier::Opaque(
+                        self.bytes()?.map(<[u8]>::to_vec).unwrap_or_default(),
+                    ),
+                }
+            }
+            _ => return Err(Error::invalid_data("invalid OPC UA node id encoding")),
+        })
+    }
+    fn localized_text(&mut self) -> Result<Option<String>> {
+        let mask = self.u8()?;
+        if mask & 0x01 != 0 {
+            self.bytes()?;
+        }
+        if mask & 0x02 != 0 {
+            self.string()
+        } else {
+            Ok(None)
+        }
+    }
+    pub(super) fn skip_localized_text(&mut self) -> Result<()> {
+        self.localized_text().map(|_| ())
+    }
+    pub(super) fn skip_string_array(&mut self) -> Result<()> {
+        for _ in 0..self.len()?.unwrap_or_default() {
+            self.bytes()?;
+        }
+        Ok(())
+    }
+    pub(super) fn skip_extension_object(&mut self) -> Result<()> {
+        self.node_id()?;
+        match self.u8()? {
+            0x00 => {}
+            0x01 | 0x02 => {
+                self.bytes()?;
+            }
+            _ => return Err(Error::invalid_data("invalid OPC UA extension object")),
+        }
+        Ok(())
+    }
+    pub(super) fn skip_diagnostic_info(&mut self) -> Result<()> {
+        for _ in 0..MAX_DEPTH {
+            let mask = self.u8()?;
+            // symbolic id, namespace uri, locale, localized text
+            for bit in [0x01, 0x02, 0x08, 0x04] {
+                if mask & bit != 0 {
+                    self.i32()?;
+                }
+            }
+            if mask & 0x10 != 0 {
+                self.bytes()?;
+            }
+            if mask & 0x20 != 0 {
+                self.u32()?;
+            }
+            if mask & 0x40 == 0 {
+                return Ok(());
+            }
+        }
+        Err(Error::invalid_data("OPC UA diagnostic info is too deep"))
+    }
+    fn scalar(&mut self, type_id: u8, depth: usize) -> Result<OpcUaValue> {
+        Ok(match type_id {
+            0 => OpcUaValue::Null,
+            1 => OpcUaValue::Bool(self.u8()? != 0),
+            2 => OpcUaValue::I8(i8::from_le_bytes(self.take_array()?)),
+            3 => OpcUaValue::U8(self.u8()?),
+            4 => OpcUaValue::I16(i16::from_le_bytes(self.take_array()?)),
+            5 => OpcUaValue::U16(self.u16()?),
+            6 => OpcUaValue::I32(self.i32()?),
+            7 => OpcUaValue::U32(self.u32()?),
+            8 => OpcUaValue::I64(self.i64()?),
+            9 => OpcUaValue::U64(u64::from_le_bytes(self.take_array()?)),
+            10 => OpcUaValue::F32(f32::from_le_bytes(self.take_array()?)),
+            11 => OpcUaValue::F64(self.f64()?),
+            12 => self.string()?.map_or(OpcUaValue::Null, OpcUaValue::String),
+            13 => OpcUaValue::DateTime(self.i64()?),
+            // guid
+            14 => OpcUaValue::ByteString(self.take(16)?.to_vec()),
+            15 | 16 => self
+                .bytes()?
+                .map_or(OpcUaValue::Null, |b| OpcUaValue::ByteString(b.to_vec())),
+            // status code
+            19 => OpcUaValue::U32(self.u32()?),
+            21 => self
+                .localized_text()?
+                .map_or(OpcUaValue::Null, OpcUaValue::String),
+            24 => {
+                if depth >= MAX_DEPTH {
+                    return Err(Error::invalid_data("OPC UA variant is too deep"));
+                }
+                self.variant_at(depth + 1)?
+            }
+            _ => {
+                return Err(Error::invalid_data(format!(
+                    "unsupported OPC UA variant type: {}",
+                    type_id
+                )))
+            }
+        })
+    }
+    pub(super) fn variant(&mut self) -> Result<OpcUaValue> {
+        self.variant_at(0)
+    }
+    fn variant_at(&mut self, depth: usize) -> Result<OpcUaValue> {
+        let mask = self.u8()?;
+        let type_id = mask & 0x3f;
+        if mask & 0x80 == 0 {
+            return self.scalar(type_id, depth);
+        }
+        let len = self.len()?.unwrap_or_default();
+        let mut items = Vec::with_capacity(len);
+        for _ in 0..len {
+            items.push(self.scalar(type_id, depth)?);
+        }
+        if mask & 0x40 != 0 {
+            // multi-dimensional arrays are returned flattened
+            for _ in 0..self.len()?.unwrap_or_default() {
+                self.i32()?;
+            }
+        }
+        Ok(OpcUaValue::Array(items))
+    }
+    /// Decodes DataValue, returns the value and the status code
+    pub(super) fn data_value(&mut self) -> Result<(OpcUaValue, u32)> {
+        let mask = self.u8()?;
+        let value = if mask & 0x01 != 0 {
+            self.variant()?
+        } else {
+            OpcUaValue::Null
+        };
+        let status = if mask & 0x02 != 0 { self.u32()? } else { 0 };
+        if mask & 0x04 != 0 {
+            self.i64()?;
+        }
+        if mask & 0x10 != 0 {
+            self.u16()?;
+        }
+        if mask & 0x08 != 0 {
+            self.i64()?;
+        }
+        if mask & 0x20 != 0 {
+            self.u16()?;
+        }
+        Ok((value, status))
+    }
+    /// Decodes ResponseHeader, returns the service result
+    pub(super) fn response_header(&mut self) -> Result<u32> {
+        // timestamp, request handle
+        self.i64()?;
+        self.u32()?;
+        let service_result = self.u32()?;
+        self.skip_diagnostic_info()?;
+        self.skip_string_array()?;
+        self.skip_extension_object()?;
+        Ok(service_result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Identifier, NodeId, OpcUaValue, Reader};
+
+    #[test]
+    fn test_node_id() {
+        for s in [
+            "ns=2;s=Line1.Speed",
+            "ns=3;i=1001",
+            "i=85",
+            "ns=1;g=72962b91-fa75-4ae6-8d28-b404dc7daf63",
+            "ns=4;b=0a0b0c",
+        ] {
+            let node_id: NodeId = s.parse().unwrap();
+            assert_eq!(node_id.to_string(), s);
+            let mut buf = Vec::new();
+            node_id.encode(&mut buf);
+            assert_eq!(Reader::new(&buf).node_id().unwrap(), node_id);
+        }
+        let node_id: NodeId = "ns=2;s=a=b;c".parse().unwrap();
+        assert_eq!(node_id.identifier, Identifier::String("a=b;c".to_owned()));
+        assert!("ns=x;i=1".parse::<NodeId>().is_err());
+        assert!("ns=1;q=1".parse::<NodeId>().is_err());
+        let mut buf = Vec::new();
+        NodeId::numeric(0, 631).encode(&mut buf);
+        assert_eq!(buf, [0x01, 0x00, 0x77, 0x02]);
+    }
+
+    #[test]
+    fn test_variant() {
+        for value in [
+            OpcUaValue::Bool(true),
+            OpcUaValue::I16(-2),
+            OpcUaValue::U32(100_000),
+            OpcUaValue::F64(1.5),
+            OpcUaValue::String("RoboPLC".to_owned()),
+            OpcUaValue::Array(vec![OpcUaValue::F32(1.0), OpcUaValue::F32(-2.5)]),
+        ] {
+            let mut buf = Vec::new();
+            value.encode(&mut buf).unwrap();
+            let mut reader = Reader::new(&buf);
+            assert_eq!(reader.variant().unwrap(), value);
+            assert!(reader.take(1).is_err());
+        }
+        let mut buf = Vec::new();
+        assert!(
+            OpcUaValue::Array(vec![OpcUaValue::U8(1), OpcUaValue::I8(1)])
+                .encode(&mut buf)
+                .is_err()
+        );
+        // DataValue: value, status code, source timestamp
+        let buf = [
+            0x07, 0x06, 0x2a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 1, 2, 3, 4, 5, 6, 7, 8,
+        ];
+        let (value, status) = Reader::new(&buf).data_value().unwrap();
+        assert_eq!(value, OpcUaValue::I32(42));
+        assert_eq!(status, 0);
+    }
+}
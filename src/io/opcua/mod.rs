@@ -0,0 +1,918 @@
+//!
+//! OPC UA client (binary protocol over TCP) and I/O mapping.
+//!
+//! The client works over a TCP [`Client`], so connections are re-established by the standard
+//! comm machinery. The secure channel and the session are created automatically on the first
+//! request and re-created after communication errors. Only the "None" security policy is
+//! supported, with anonymous or user name (plain text) authentication. Subscriptions are not
+//! supported, values are polled with the Read service.
+//!
+//! Example:
+//!
+//! ```rust,no_run
+//! use roboplc::comm::tcp;
+//! use roboplc::io::opcua::{OpcUaClient, OpcUaMapping, OpcUaType};
+//! use roboplc::io::prelude::*;
+//! use std::time::Duration;
+//!
+//! #[binrw]
+//! struct Line {
+//!     running: u8,
+//!     speed: f32,
+//!     counter: u32,
+//! }
+//!
+//! let client = tcp::connect("10.90.34.111:4840", Duration::from_secs(1)).unwrap();
+//! let opc = OpcUaClient::new(&client, "opc.tcp://10.90.34.111:4840");
+//! let mut mapping = OpcUaMapping::create(
+//!     &opc,
+//!     [
+//!         ("ns=3;s=\"Line\".\"Running\"".parse().unwrap(), OpcUaType::Bool),
+//!         ("ns=3;s=\"Line\".\"Speed\"".parse().unwrap(), OpcUaType::F32),
+//!         ("ns=3;s=\"Line\".\"Counter\"".parse().unwrap(), OpcUaType::U32),
+//!     ],
+//! );
+//! let line: Line = mapping.read().unwrap();
+//! ```
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io::Cursor,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use binrw::{BinRead, BinWrite};
+use parking_lot_rt::Mutex;
+use tracing::trace;
+
+use super::{IoMapping, LockedIoMapping};
+use crate::comm::Client;
+use crate::{Error, Result};
+
+mod binary;
+
+pub use binary::{Identifier, NodeId, OpcUaValue};
+
+use binary::{date_time, put_bytes, put_len, put_string, Reader};
+
+const SECURITY_POLICY_NONE: &str = "http://opcfoundation.org/UA/SecurityPolicy#None";
+
+// the receive buffer (max chunk size), proposed to the server
+const BUFFER_SIZE: u32 = 65535;
+// reassembled messages larger than this are rejected
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+const CHANNEL_LIFETIME_MS: u32 = 3_600_000;
+const SESSION_TIMEOUT_MS: f64 = 3_600_000.0;
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// value attribute
+const ATTRIBUTE_VALUE: u32 = 13;
+// message security mode None
+const SECURITY_MODE_NONE: u32 = 1;
+const TOKEN_TYPE_ANONYMOUS: u32 = 0;
+const TOKEN_TYPE_USER_NAME: u32 = 1;
+
+// binary encoding ids of service messages
+mod service {
+    pub const SERVICE_FAULT: u32 = 397;
+    pub const ANONYMOUS_IDENTITY_TOKEN: u32 = 321;
+    pub const USER_NAME_IDENTITY_TOKEN: u32 = 324;
+    pub const OPEN_SECURE_CHANNEL_REQUEST: u32 = 446;
+    pub const OPEN_SECURE_CHANNEL_RESPONSE: u32 = 449;
+    pub const CLOSE_SECURE_CHANNEL_REQUEST: u32 = 452;
+    pub const CREATE_SESSION_REQUEST: u32 = 461;
+    pub const CREATE_SESSION_RESPONSE: u32 = 464;
+    pub const ACTIVATE_SESSION_REQUEST: u32 = 467;
+    pub const ACTIVATE_SESSION_RESPONSE: u32 = 470;
+    pub const CLOSE_SESSION_REQUEST: u32 = 473;
+    pub const CLOSE_SESSION_RESPONSE: u32 = 476;
+    pub const READ_REQUEST: u32 = 631;
+    pub const READ_RESPONSE: u32 = 634;
+    pub const WRITE_REQUEST: u32 = 673;
+    pub const WRITE_RESPONSE: u32 = 676;
+}
+
+fn is_bad(status: u32) -> bool {
+    status & 0x8000_0000 != 0
+}
+
+struct Channel {
+    id: u32,
+    token_id: u32,
+    // the token is renewed after 75% of its lifetime
+    renew_at: Instant,
+    auth_token: NodeId,
+    // the comm client session the channel belongs to
+    comm_session: usize,
+}
+
+struct Inner {
+    client: Client,
+    endpoint_url: String,
+    credentials: Option<(String, String)>,
+    request_timeout: Duration,
+    // the server receive buffer size
+    send_buffer: usize,
+    seq: u32,
+    request_id: u32,
+    request_handle: u32,
+    channel: Option<Channel>,
+}
+
+/// OPC UA client. Can be cloned and shared between workers with no limitations, requests are
+/// executed under the comm client lock
+#[derive(Clone)]
+pub struct OpcUaClient {
+    client: Client,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl OpcUaClient {
+    /// Creates a new client. The endpoint URL is sent to the server in the handshake (e.g.
+    /// `opc.tcp://10.90.34.111:4840`)
+    pub fn new(client: &Client, endpoint_url: &str) -> Self {
+        Self {
+            client: client.clone(),
+            inner: Arc::new(Mutex::new(Inner {
+                client: client.clone(),
+                endpoint_url: endpoint_url.to_owned(),
+                credentials: None,
+                request_timeout: DEFAULT_REQUEST_TIMEOUT,
+                send_buffer: BUFFER_SIZE as usize,
+                seq: 0,
+                request_id: 0,
+                request_handle: 0,
+                channel: None,
+            })),
+        }
+    }
+    /// Sets user name authentication (anonymous by default). Note that the password is sent in
+    /// plain text
+    pub fn credentials(self, user: &str, password: &str) -> Self {
+        self.inner.lock().credentials = Some((user.to_owned(), password.to_owned()));
+        self
+    }
+    /// Sets the request timeout hint for the server (the default is 10 seconds)
+    pub fn request_timeout(self, timeout: Duration) -> Self {
+        self.inner.lock().request_timeout = timeout;
+        self
+    }
+    /// The communication client
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+    /// Creates the secure channel and the session (called automatically by requests)
+    pub fn connect(&self) -> Result<()> {
+        let _lock = self.client.lock();
+        self.execute(|_| Ok(()))
+    }
+    /// Closes the session and the secure channel
+    pub fn disconnect(&self) -> Result<()> {
+        let _lock = self.client.lock();
+        self.inner.lock().close()
+    }
+    /// Reads values of nodes. Returns an error if any of the nodes can not be read
+    pub fn read(&self, nodes: &[NodeId]) -> Result<Vec<OpcUaValue>> {
+        let _lock = self.client.lock();
+        self.read_locked(nodes)
+    }
+    /// Writes values of nodes. The value types must match the node data types
+    pub fn write(&self, values: &[(NodeId, OpcUaValue)]) -> Result<()> {
+        let _lock = self.client.lock();
+        self.write_locked(values)
+    }
+    fn read_locked(&self, nodes: &[NodeId]) -> Result<Vec<OpcUaValue>> {
+        self.execute(|inner| inner.read(nodes))
+    }
+    fn write_locked(&self, values: &[(NodeId, OpcUaValue)]) -> Result<()> {
+        self.execute(|inner| inner.write(values))
+    }
+    // executes a request, the comm client must be locked
+    fn execute<R, F>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut Inner) -> Result<R>,
+    {
+        let mut inner = self.inner.lock();
+        let result = inner.open().and_then(|()| f(&mut inner));
+        if let Err(ref e) = result {
+            if !matches!(e, Error::API(..)) {
+                // the channel state is unknown after communication errors
+                inner.channel.take();
+                self.client.reconnect();
+            }
+        }
+        result
+    }
+}
+
+impl Inner {
+    // opens or renews the secure channel and the session if required
+    fn open(&mut self) -> Result<()> {
+        if let Some(ref channel) = self.channel {
+            if channel.comm_session != self.client.session_id() {
+                self.channel.take();
+            } else if channel.renew_at <= Instant::now() {
+                let channel_id = channel.id;
+                return self.open_channel(Some(channel_id));
+            } else {
+                return Ok(());
+            }
+        }
+        trace!(endpoint_url = %self.endpoint_url, "opening OPC UA session");
+        self.seq = 0;
+        self.hello()?;
+        self.open_channel(None)?;
+        self.create_session()
+    }
+    fn close(&mut self) -> Result<()> {
+        if self.channel.is_none() {
+            return Ok(());
+        }
+        let mut body = Vec::new();
+        // delete subscriptions
+        body.push(1);
+        let result = self
+            .call(
+                service::CLOSE_SESSION_REQUEST,
+                service::CLOSE_SESSION_RESPONSE,
+                &body,
+            )
+            .and_then(|_| {
+                let mut request = Vec::new();
+                self.request_header(&mut request, service::CLOSE_SECURE_CHANNEL_REQUEST);
+                self.send_message(b"CLO", &request)
+            });
+        self.channel.take();
+        result
+    }
+    fn hello(&mut self) -> Result<()> {
+        let mut body = Vec::new();
+        // protocol version, receive and send buffer sizes, no message size and chunk limits
+        for v in [0, BUFFER_SIZE, BUFFER_SIZE, 0, 0] {
+            body.extend(u32::to_le_bytes(v));
+        }
+        put_string(&mut body, Some(&self.endpoint_url));
+        self.send_chunk(b"HEL", b'F', &body)?;
+        let (kind, _, data) = self.recv_chunk()?;
+        if &kind != b"ACK" {
+            return Err(Error::invalid_data("OPC UA hello not acknowledged"));
+        }
+        let mut r = Reader::new(&data);
+        // protocol version
+        r.u32()?;
+        let receive_buffer = r.u32()?;
+        self.send_buffer = usize::try_from(receive_buffer).map_err(Error::invalid_data)?;
+        Ok(())
+    }
+    fn open_channel(&mut self, renew: Option<u32>) -> Result<()> {
+        let mut body = Vec::new();
+        NodeId::numeric(0, service::OPEN_SECURE_CHANNEL_REQUEST).encode(&mut body);
+        self.request_header(&mut body, 0);
+        // protocol version, request type (issue/renew), security mode
+        for v in [0, u32::from(renew.is_some()), SECURITY_MODE_NONE] {
+            body.extend(u32::to_le_bytes(v));
+        }
+        // client nonce
+        put_bytes(&mut body, None);
+        body.extend(CHANNEL_LIFETIME_MS.to_le_bytes());
+        let mut message = renew.unwrap_or_default().to_le_bytes().to_vec();
+        put_string(&mut message, Some(SECURITY_POLICY_NONE));
+        // sender certificate, receiver certificate thumbprint
+        put_bytes(&mut message, None);
+        put_bytes(&mut message, None);
+        let request_id = self.next_seq(&mut message);
+        message.extend(body);
+        self.send_chunk(b"OPN", b'F', &message)?;
+        let (kind, _, data) = self.recv_chunk()?;
+        if &kind != b"OPN" {
+            return Err(Error::invalid_data("invalid OPC UA open channel response"));
+        }
+        let mut r = Reader::new(&data);
+        r.u32()?;
+        r.bytes()?;
+        r.bytes()?;
+        r.bytes()?;
+        // sequence number
+        r.u32()?;
+        if r.u32()? != request_id {
+            return Err(Error::invalid_data("OPC UA request id mismatch"));
+        }
+        let mut r = Reader::new(check_response(
+            r.rest(),
+            service::OPEN_SECURE_CHANNEL_RESPONSE,
+        )?);
+        // server protocol version
+        r.u32()?;
+        let id = r.u32()?;
+        let token_id = r.u32()?;
+        // created at
+        r.i64()?;
+        let lifetime = r.u32()?;
+        let renew_at = Instant::now() + Duration::from_millis(u64::from(lifetime) * 3 / 4);
+        if let Some(ref mut channel) = self.channel {
+            channel.token_id = token_id;
+            channel.renew_at = renew_at;
+        } else {
+            self.channel = Some(Channel {
+                id,
+                token_id,
+                renew_at,
+                auth_token: NodeId::null(),
+                comm_session: self.client.session_id(),
+            });
+        }
+        Ok(())
+    }
+    fn create_session(&mut self) -> Result<()> {
+        let mut body = Vec::new();
+        // client description: application uri, product uri, name, type (client), gateway uri,
+        // discovery profile uri, discovery urls
+        put_string(&mut body, Some("urn:roboplc:client"));
+        put_string(&mut body, Some("urn:roboplc"));
+        body.push(0x02);
+        put_string(&mut body, Some("RoboPLC"));
+        body.extend(1u32.to_le_bytes());
+        put_string(&mut body, None);
+        put_string(&mut body, None);
+        put_len(&mut body, None)?;
+        // server uri
+        put_string(&mut body, None);
+        put_string(&mut body, Some(&self.endpoint_url));
+        // session name
+        put_string(&mut body, Some("RoboPLC"));
+        put_bytes(&mut body, Some(&nonce()));
+        // client certificate
+        put_bytes(&mut body, None);
+        body.extend(SESSION_TIMEOUT_MS.to_le_bytes());
+        // max response message size
+        body.extend(0u32.to_le_bytes());
+        let response = self.call(
+            service::CREATE_SESSION_REQUEST,
+            service::CREATE_SESSION_RESPONSE,
+            &body,
+        )?;
+        let mut r = Reader::new(&response);
+        // session id
+        r.node_id()?;
+        let auth_token = r.node_id()?;
+        // revised timeout, server nonce, server certificate
+        r.f64()?;
+        r.bytes()?;
+        r.bytes()?;
+        let token_type = if self.credentials.is_some() {
+            TOKEN_TYPE_USER_NAME
+        } else {
+            TOKEN_TYPE_ANONYMOUS
+        };
+        let policy_id = find_token_policy(&mut r, token_type)?.ok_or_else(|| {
+            Error::API(
+                "OPC UA server does not support the authentication method".to_owned(),
+                i64::from(token_type),
+            )
+        })?;
+        if let Some(ref mut channel) = self.channel {
+            channel.auth_token = auth_token;
+        }
+        self.activate_session(&policy_id)
+    }
+    fn activate_session(&mut self, policy_id: &str) -> Result<()> {
+        let mut body = Vec::new();
+        // client signature (algorithm, signature), client software certificates, locale ids
+        put_string(&mut body, None);
+        put_bytes(&mut body, None);
+        put_len(&mut body, Some(0))?;
+        put_len(&mut body, Some(0))?;
+        let mut token = Vec::new();
+        put_string(&mut token, Some(policy_id));
+        let token_type = if let Some((ref user, ref password)) = self.credentials {
+            put_string(&mut token, Some(user));
+            put_bytes(&mut token, Some(password.as_bytes()));
+            // encryption algorithm
+            put_string(&mut token, None);
+            service::USER_NAME_IDENTITY_TOKEN
+        } else {
+            service::ANONYMOUS_IDENTITY_TOKEN
+        };
+        NodeId::numeric(0, token_type).encode(&mut body);
+        body.push(0x01);
+        put_bytes(&mut body, Some(&token));
+        // user token signature
+        put_string(&mut body, None);
+        put_bytes(&mut body, None);
+        self.call(
+            service::ACTIVATE_SESSION_REQUEST,
+            service::ACTIVATE_SESSION_RESPONSE,
+            &body,
+        )?;
+        Ok(())
+    }
+    fn read(&mut self, nodes: &[NodeId]) -> Result<Vec<OpcUaValue>> {
+        let mut body = Vec::new();
+        // max age, timestamps to return (neither)
+        body.extend(0f64.to_le_bytes());
+        body.extend(3u32.to_le_bytes());
+        put_len(&mut body, Some(nodes.len()))?;
+        for node in nodes {
+            node.encode(&mut body);
+            body.extend(ATTRIBUTE_VALUE.to_le_bytes());
+            // index range, data encoding
+            put_string(&mut body, None);
+            body.extend(0u16.to_le_bytes());
+            put_string(&mut body, None);
+        }
+        let response = self.call(service::READ_REQUEST, service::READ_RESPONSE, &body)?;
+        let mut r = Reader::new(&response);
+        let len = r.len()?.unwrap_or_default();
+        if len != nodes.len() {
+            return Err(Error::invalid_data("invalid OPC UA read response"));
+        }
+        let mut values = Vec::with_capacity(len);
+        for node in nodes {
+            let (value, status) = r.data_value()?;
+            if is_bad(status) {
+                return Err(node_error(node, status));
+            }
+            values.push(value);
+        }
+        Ok(values)
+    }
+    fn write(&mut self, values: &[(NodeId, OpcUaValue)]) -> Result<()> {
+        let mut body = Vec::new();
+        put_len(&mut body, Some(values.len()))?;
+        for (node, value) in values {
+            node.encode(&mut body);
+            body.extend(ATTRIBUTE_VALUE.to_le_bytes());
+            // index range
+            put_string(&mut body, None);
+            // data value with the value only
+            body.push(0x01);
+            value.encode(&mut body)?;
+        }
+        let response = self.call(service::WRITE_REQUEST, service::WRITE_RESPONSE, &body)?;
+        let mut r = Reader::new(&response);
+        let len = r.len()?.unwrap_or_default();
+        if len != values.len() {
+            return Err(Error::invalid_data("invalid OPC UA write response"));
+        }
+        for (node, _) in values {
+            let status = r.u32()?;
+            if is_bad(status) {
+                return Err(node_error(node, status));
+            }
+        }
+        Ok(())
+    }
+    fn request_header(&mut self, buf: &mut Vec<u8>, request_type: u32) {
+        if request_type > 0 {
+            NodeId::numeric(0, request_type).encode(buf);
+        }
+        if let Some(ref channel) = self.channel {
+            channel.auth_token.encode(buf);
+        } else {
+            NodeId::null().encode(buf);
+        }
+        buf.extend(date_time(now_ns()).to_le_bytes());
+        self.request_handle = self.request_handle.wrapping_add(1);
+        buf.extend(self.request_handle.to_le_bytes());
+        // return diagnostics, audit entry id
+        buf.extend(0u32.to_le_bytes());
+        put_string(buf, None);
+        let timeout = u32::try_from(self.request_timeout.as_millis()).unwrap_or(u32::MAX);
+        buf.extend(timeout.to_le_bytes());
+        // additional header (null extension object)
+        NodeId::null().encode(buf);
+        buf.push(0x00);
+    }
+    // puts the sequence header, returns the request id
+    fn next_seq(&mut self, buf: &mut Vec<u8>) -> u32 {
+        self.seq = self.seq.wrapping_add(1);
+        self.request_id = self.request_id.wrapping_add(1);
+        buf.extend(self.seq.to_le_bytes());
+        buf.extend(self.request_id.to_le_bytes());
+        self.request_id
+    }
+    // calls a service, returns the response body after the response header
+    fn call(&mut self, request_type: u32, response_type: u32, body: &[u8]) -> Result<Vec<u8>> {
+        let mut request = Vec::with_capacity(body.len() + 64);
+        self.request_header(&mut request, request_type);
+        request.extend(body);
+        let request_id = self.send_message(b"MSG", &request)?;
+        let response = self.recv_message(request_id)?;
+        let result = check_response(&response, response_type);
+        if let Err(Error::API(..)) = result {
+            // the session is usually invalid after service faults
+            self.channel.take();
+        }
+        result.map(<[u8]>::to_vec)
+    }
+    fn send_message(&mut self, kind: &[u8; 3], body: &[u8]) -> Result<u32> {
+        let channel = self
+            .channel
+            .as_ref()
+            .ok_or_else(|| Error::io("OPC UA channel is not open"))?;
+        let mut message = Vec::with_capacity(body.len() + 16);
+        message.extend(channel.id.to_le_bytes());
+        message.extend(channel.token_id.to_le_bytes());
+        let request_id = self.next_seq(&mut message);
+        message.extend(body);
+        self.send_chunk(kind, b'F', &message)?;
+        Ok(request_id)
+    }
+    fn recv_message(&mut self, request_id: u32) -> Result<Vec<u8>> {
+        let mut message = Vec::new();
+        loop {
+            let (kind, chunk_type, data) = self.recv_chunk()?;
+            if &kind != b"MSG" {
+                return Err(Error::invalid_data("unexpected OPC UA message type"));
+            }
+            let mut r = Reader::new(&data);
+            // channel id, token id, sequence number
+            r.u32()?;
+            r.u32()?;
+            r.u32()?;
+            if r.u32()? != request_id {
+                return Err(Error::invalid_data("OPC UA request id mismatch"));
+            }
+            match chunk_type {
+                b'A' => {
+                    let code = r.u32()?;
+                    let reason = r.string()?.unwrap_or_default();
+                    return Err(Error::API(
+                        format!("OPC UA message aborted: {}", reason),
+                        i64::from(code),
+                    ));
+                }
+                b'C' | b'F' => {
+                    message.extend(r.rest());
+                    if message.len() > MAX_MESSAGE_SIZE {
+                        return Err(Error::invalid_data("OPC UA message too large"));
+                    }
+                    if chunk_type == b'F' {
+                        return Ok(message);
+                    }
+                }
+                _ => return Err(Error::invalid_data("invalid OPC UA chunk type")),
+            }
+        }
+    }
+    fn send_chunk(&self, kind: &[u8; 3], chunk_type: u8, body: &[u8]) -> Result<()> {
+        let size = body.len() + 8;
+        if size > self.send_buffer {
+            return Err(Error::invalid_data(
+                "OPC UA request exceeds the server buffer size",
+            ));
+        }
+        let mut buf = Vec::with_capacity(size);
+        buf.extend(kind);
+        buf.push(chunk_type);
+        // can not overflow, the size is limited by the buffer size
+        buf.extend(u32::try_from(size).unwrap_or(u32::MAX).to_le_bytes());
+        buf.extend(body);
+        self.client.write(&buf)
+    }
+    // reads a chunk, returns (message type, chunk type, data)
+    fn recv_chunk(&self) -> Result<([u8; 3], u8, Vec<u8>)> {
+        let mut header = [0u8; 8];
+        self.client.read_exact(&mut header)?;
+        let size = usize::try_from(u32::from_le_bytes([
+            header[4], header[5], header[6], header[7],
+        ]))
+        .map_err(Error::invalid_data)?;
+        if !(8..=BUFFER_SIZE as usize).contains(&size) {
+            return Err(Error::invalid_data("invalid OPC UA chunk size"));
+        }
+        let mut data = vec![0; size - 8];
+        self.client.read_exact(&mut data)?;
+        let kind = [header[0], header[1], header[2]];
+        if &kind == b"ERR" {
+            let mut r = Reader::new(&data);
+            let code = r.u32()?;
+            let reason = r.string()?.unwrap_or_default();
+            return Err(Error::io(format!(
+                "OPC UA error {:#010x}: {}",
+                code, reason
+            )));
+        }
+        Ok((kind, header[3], data))
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        if self.channel.is_some() {
+            let client = self.client.clone();
+            let _lock = client.lock();
+            let _r = self.close();
+        }
+    }
+}
+
+// checks the response type and the service result, returns the data after the response header
+fn check_response(response: &[u8], response_type: u32) -> Result<&[u8]> {
+    let mut r = Reader::new(response);
+    let type_id = r.node_id()?;
+    let service_result = r.response_header()?;
+    if is_bad(service_result) {
+        return Err(Error::API(
+            "OPC UA service fault".to_owned(),
+            i64::from(service_result),
+        ));
+    }
+    if type_id == NodeId::numeric(0, service::SERVICE_FAULT) {
+        return Err(Error::API("OPC UA service fault".to_owned(), 0));
+    }
+    if type_id != NodeId::numeric(0, response_type) {
+        return Err(Error::invalid_data(format!(
+            "unexpected OPC UA response type: {}",
+            type_id
+        )));
+    }
+    Ok(r.rest())
+}
+
+fn node_error(node: &NodeId, status: u32) -> Error {
+    Error::API(format!("OPC UA node {} error", node), i64::from(status))
+}
+
+// finds the user token policy id in server endpoints, endpoints with no security are preferred
+fn find_token_policy(r: &mut Reader, token_type: u32) -> Result<Option<String>> {
+    let mut found = None;
+    for _ in 0..r.len()?.unwrap_or_default() {
+        // endpoint url
+        r.bytes()?;
+        // server description
+        r.bytes()?;
+        r.bytes()?;
+        r.skip_localized_text()?;
+        r.u32()?;
+        r.bytes()?;
+        r.bytes()?;
+        r.skip_string_array()?;
+        // server certificate
+        r.bytes()?;
+        let security_mode = r.u32()?;
+        // security policy uri
+        r.bytes()?;
+        for _ in 0..r.len()?.unwrap_or_default() {
+            let policy_id = r.string()?;
+            let policy_token_type = r.u32()?;
+            // issued token type, issuer endpoint url, security policy uri
+            r.bytes()?;
+            r.bytes()?;
+            r.bytes()?;
+            if policy_token_type == token_type
+                && (found.is_none() || security_mode == SECURITY_MODE_NONE)
+            {
+                found = Some(policy_id.unwrap_or_default());
+            }
+        }
+        // transport profile uri, security level
+        r.bytes()?;
+        r.u8()?;
+    }
+    Ok(found)
+}
+
+fn now_ns() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos())
+}
+
+fn nonce() -> Vec<u8> {
+    let state = RandomState::new();
+    (0..4u64)
+        .flat_map(|i| {
+            let mut hasher = state.build_hasher();
+            hasher.write_u64(i);
+            hasher.write_u128(now_ns());
+            hasher.finish().to_le_bytes()
+        })
+        .collect()
+}
+
+/// Node data types for [`OpcUaMapping`]. Mapped values are represented in the structures as
+/// big-endian numbers of the same size (booleans as `u8`)
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum OpcUaType {
+    Bool,
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    I64,
+    U64,
+    F32,
+    F64,
+}
+
+impl OpcUaType {
+    /// The value size in bytes
+    pub fn size(self) -> usize {
+        match self {
+            OpcUaType::Bool | OpcUaType::I8 | OpcUaType::U8 => 1,
+            OpcUaType::I16 | OpcUaType::U16 => 2,
+            OpcUaType::I32 | OpcUaType::U32 | OpcUaType::F32 => 4,
+            OpcUaType::I64 | OpcUaType::U64 | OpcUaType::F64 => 8,
+        }
+    }
+    // converts a value into big-endian bytes of the type
+    #[allow(clippy::cast_possible_truncation)]
+    fn put_be(self, value: &OpcUaValue, buf: &mut Vec<u8>) -> Result<()> {
+        let mismatch = || Error::invalid_data(format!("OPC UA value type mismatch: {:?}", value));
+        let int = || value.as_i128().ok_or_else(mismatch);
+        let range = |_| Error::invalid_data(format!("OPC UA value out of range: {:?}", value));
+        match self {
+            OpcUaType::Bool => buf.push(u8::from(int()? != 0)),
+            OpcUaType::I8 => buf.extend(i8::try_from(int()?).map_err(range)?.to_be_bytes()),
+            OpcUaType::U8 => buf.extend(u8::try_from(int()?).map_err(range)?.to_be_bytes()),
+            OpcUaType::I16 => buf.extend(i16::try_from(int()?).map_err(range)?.to_be_bytes()),
+            OpcUaType::U16 => buf.extend(u16::try_from(int()?).map_err(range)?.to_be_bytes()),
+            OpcUaType::I32 => buf.extend(i32::try_from(int()?).map_err(range)?.to_be_bytes()),
+            OpcUaType::U32 => buf.extend(u32::try_from(int()?).map_err(range)?.to_be_bytes()),
+            OpcUaType::I64 => buf.extend(i64::try_from(int()?).map_err(range)?.to_be_bytes()),
+            OpcUaType::U64 => buf.extend(u64::try_from(int()?).map_err(range)?.to_be_bytes()),
+            OpcUaType::F32 => {
+                buf.extend((value.as_f64().ok_or_else(mismatch)? as f32).to_be_bytes())
+            }
+            OpcUaType::F64 => buf.extend(value.as_f64().ok_or_else(mismatch)?.to_be_bytes()),
+        }
+        Ok(())
+    }
+    // converts big-endian bytes of the type into a value, the length is checked by the caller
+    fn value_from_be(self, b: &[u8]) -> OpcUaValue {
+        match self {
+            OpcUaType::Bool => OpcUaValue::Bool(b[0] != 0),
+            OpcUaType::I8 => OpcUaValue::I8(i8::from_be_bytes([b[0]])),
+            OpcUaType::U8 => OpcUaValue::U8(b[0]),
+            OpcUaType::I16 => OpcUaValue::I16(i16::from_be_bytes([b[0], b[1]])),
+            OpcUaType::U16 => OpcUaValue::U16(u16::from_be_bytes([b[0], b[1]])),
+            OpcUaType::I32 => OpcUaValue::I32(i32::from_be_bytes([b[0], b[1], b[2], b[3]])),
+            OpcUaType::U32 => OpcUaValue::U32(u32::from_be_bytes([b[0], b[1], b[2], b[3]])),
+            OpcUaType::F32 => OpcUaValue::F32(f32::from_be_bytes([b[0], b[1], b[2], b[3]])),
+            OpcUaType::I64 => OpcUaValue::I64(i64::from_be_bytes([
+                b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7],
+            ])),
+            OpcUaType::U64 => OpcUaValue::U64(u64::from_be_bytes([
+                b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7],
+            ])),
+            OpcUaType::F64 => OpcUaValue::F64(f64::from_be_bytes([
+                b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7],
+            ])),
+        }
+    }
+}
+
+/// Maps a list of nodes to a structure. The structure fields must follow the node order and
+/// match the declared types (see [`OpcUaType`]). All nodes are read and written with a single
+/// request
+#[allow(clippy::module_name_repetitions)]
+pub struct OpcUaMapping {
+    client: OpcUaClient,
+    nodes: Vec<NodeId>,
+    types: Vec<OpcUaType>,
+    data_buf: Vec<u8>,
+}
+
+impl OpcUaMapping {
+    pub fn create<I>(client: &OpcUaClient, nodes: I) -> Self
+    where
+        I: IntoIterator<Item = (NodeId, OpcUaType)>,
+    {
+        let (nodes, types) = nodes.into_iter().unzip();
+        Self {
+            client: client.clone(),
+            nodes,
+            types,
+            data_buf: vec![],
+        }
+    }
+    fn decode_values(&mut self, values: &[OpcUaValue]) -> Result<()> {
+        if values.len() != self.types.len() {
+            return Err(Error::invalid_data("OPC UA mapping value count mismatch"));
+        }
+        self.data_buf.truncate(0);
+        for (value, kind) in values.iter().zip(&self.types) {
+            kind.put_be(value, &mut self.data_buf)?;
+        }
+        Ok(())
+    }
+    fn encode_values(&self) -> Result<Vec<(NodeId, OpcUaValue)>> {
+        let size: usize = self.types.iter().map(|t| t.size()).sum();
+        if self.data_buf.len() != size {
+            return Err(Error::invalid_data(format!(
+                "OPC UA mapping data size mismatch: {} bytes expected, {} provided",
+                size,
+                self.data_buf.len()
+            )));
+        }
+        let mut pos = 0;
+        let mut values = Vec::with_capacity(self.nodes.len());
+        for (node, kind) in self.nodes.iter().zip(&self.types) {
+            let value = kind.value_from_be(&self.data_buf[pos..pos + kind.size()]);
+            pos += kind.size();
+            values.push((node.clone(), value));
+        }
+        Ok(values)
+    }
+}
+
+impl IoMapping for OpcUaMapping {
+    type Options = ();
+    fn read<T>(&mut self) -> Result<T>
+    where
+        T: for<'a> BinRead<Args<'a> = ()>,
+    {
+        let client = self.client.client.clone();
+        let _lock = client.lock();
+        self.read_locked()
+    }
+
+    fn write<T>(&mut self, value: T) -> Result<()>
+    where
+        T: for<'a> BinWrite<Args<'a> = ()>,
+    {
+        let client = self.client.client.clone();
+        let _lock = client.lock();
+        self.write_locked(value)
+    }
+}
+
+impl LockedIoMapping for OpcUaMapping {
+    fn client(&self) -> &Client {
+        &self.client.client
+    }
+
+    fn read_locked<T>(&mut self) -> Result<T>
+    where
+        T: for<'a> BinRead<Args<'a> = ()>,
+    {
+        let values = self.client.read_locked(&self.nodes)?;
+        self.decode_values(&values)?;
+        let mut reader = Cursor::new(&self.data_buf);
+        T::read_be(&mut reader).map_err(Into::into)
+    }
+
+    fn write_locked<T>(&mut self, value: T) -> Result<()>
+    where
+        T: for<'a> BinWrite<Args<'a> = ()>,
+    {
+        let mut writer = Cursor::new(std::mem::take(&mut self.data_buf));
+        writer.get_mut().truncate(0);
+        let result = value.write_be(&mut writer);
+        self.data_buf = writer.into_inner();
+        result?;
+        let values = self.encode_values()?;
+        self.client.write_locked(&values)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{OpcUaClient, OpcUaMapping, OpcUaType, OpcUaValue};
+    use crate::comm::tcp;
+
+    #[test]
+    fn test_mapping_conversion() {
+        // the client connects on the first request
+        let client = tcp::connect("127.0.0.1:4840", Duration::from_secs(1)).unwrap();
+        let opc = OpcUaClient::new(&client, "opc.tcp://127.0.0.1:4840");
+        let mut mapping = OpcUaMapping::create(
+            &opc,
+            [
+                ("ns=2;s=Running".parse().unwrap(), OpcUaType::Bool),
+                ("ns=2;s=Speed".parse().unwrap(), OpcUaType::F32),
+                ("ns=2;s=Counter".parse().unwrap(), OpcUaType::I16),
+            ],
+        );
+        mapping
+            .decode_values(&[
+                OpcUaValue::Bool(true),
+                OpcUaValue::F64(1.5),
+                OpcUaValue::I32(-3),
+            ])
+            .unwrap();
+        assert_eq!(mapping.data_buf, [1, 0x3f, 0xc0, 0, 0, 0xff, 0xfd]);
+        let values = mapping.encode_values().unwrap();
+        assert_eq!(values[1].1, OpcUaValue::F32(1.5));
+        assert_eq!(values[2].1, OpcUaValue::I16(-3));
+        assert!(mapping
+            .decode_values(&[
+                OpcUaValue::Bool(false),
+                OpcUaValue::F32(0.0),
+                OpcUaValue::U32(70_000)
+            ])
+            .is_err());
+        assert!(mapping
+            .decode_values(&[OpcUaValue::String("x".to_owned())])
+            .is_err());
+        mapping.data_buf.pop();
+        assert!(mapping.encode_values().is_err());
+    }
+}
@@ -1,22 +1,29 @@
 use std::{
     collections::BTreeMap,
-    ffi::{OsStr, OsString},
-    io,
+    ffi::{CString, OsStr, OsString},
+    fs, io,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
     process::Stdio,
     time::Duration,
 };
 
+use serde::Serialize;
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter},
     process::Command,
 };
-use tracing::error;
+use tracing::{error, warn};
 
 use crate::{
     pchannel_async::{self, Receiver},
     DataDeliveryPolicy, Result,
 };
 
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+type UsageFn = dyn Fn(ChildUsage) + Send + Sync;
+
 pub struct Reader {
     rx: Receiver<String>,
 }
@@ -34,6 +41,147 @@ pub struct Pipe {
     input_data: Option<Vec<u8>>,
     tx: pchannel_async::Sender<String>,
     restart_delay: Duration,
+    cgroup: Option<CGroup>,
+    nice: Option<i32>,
+    usage_interval: Duration,
+    on_usage: Option<Box<UsageFn>>,
+}
+
+/// A cgroup (v2) to confine a subprocess with. The cgroup is created if does not exist, the
+/// process must have write access to the cgroup hierarchy
+#[derive(Clone, Debug)]
+pub struct CGroup {
+    path: PathBuf,
+    cpu_max: Option<(Duration, Duration)>,
+    cpus: Option<Vec<usize>>,
+    memory_max: Option<u64>,
+}
+
+impl CGroup {
+    /// Relative paths are created under `/sys/fs/cgroup`
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: Path::new(CGROUP_ROOT).join(path),
+            cpu_max: None,
+            cpus: None,
+            memory_max: None,
+        }
+    }
+    /// CPU quota: the subprocess may use `quota` CPU time every `period` (e.g. 50ms of 100ms for
+    /// half of a CPU core)
+    pub fn cpu_quota(mut self, quota: Duration, period: Duration) -> Self {
+        self.cpu_max = Some((quota, period));
+        self
+    }
+    /// CPUs the subprocess is allowed to run on
+    pub fn cpus(mut self, cpus: &[usize]) -> Self {
+        self.cpus = Some(cpus.to_vec());
+        self
+    }
+    /// Memory limit in bytes
+    pub fn memory_max(mut self, bytes: u64) -> Self {
+        self.memory_max = Some(bytes);
+        self
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    // creates the cgroup, applies limits and returns the cgroup.procs path
+    fn prepare(&self) -> io::Result<CString> {
+        fs::create_dir_all(&self.path)?;
+        if let Some(parent) = self.path.parent() {
+            // the controllers may be already enabled or not be available
+            let _r = fs::write(
+                parent.join("cgroup.subtree_control"),
+                "+cpu +cpuset +memory",
+            );
+        }
+        if let Some((quota, period)) = self.cpu_max {
+            fs::write(
+                self.path.join("cpu.max"),
+                format!("{} {}", quota.as_micros(), period.as_micros()),
+            )?;
+        }
+        if let Some(ref cpus) = self.cpus {
+            let cpus: Vec<String> = cpus.iter().map(ToString::to_string).collect();
+            fs::write(self.path.join("cpuset.cpus"), cpus.join(","))?;
+        }
+        if let Some(memory_max) = self.memory_max {
+            fs::write(self.path.join("memory.max"), memory_max.to_string())?;
+        }
+        CString::new(self.path.join("cgroup.procs").as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+    fn usage(&self, pid: u32) -> io::Result<ChildUsage> {
+        let cpu_stat = fs::read_to_string(self.path.join("cpu.stat"))?;
+        let usage_usec = cpu_stat
+            .lines()
+            .find_map(|line| line.strip_prefix("usage_usec "))
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid cpu.stat"))?;
+        let memory = fs::read_to_string(self.path.join("memory.current"))?
+            .trim()
+            .parse::<u64>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(ChildUsage {
+            pid,
+            cpu_time: Duration::from_micros(usage_usec),
+            memory,
+        })
+    }
+}
+
+/// Subprocess resource usage. If the subprocess is confined with a [`CGroup`], the usage of the
+/// whole cgroup is reported (including child processes of the subprocess)
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct ChildUsage {
+    pub pid: u32,
+    /// Total CPU time
+    pub cpu_time: Duration,
+    /// Resident memory in bytes
+    pub memory: u64,
+}
+
+impl DataDeliveryPolicy for ChildUsage {}
+
+impl ChildUsage {
+    fn from_proc(pid: u32) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid /proc data");
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid))?;
+        // fields after the command name, starting from the process state
+        let fields: Vec<&str> = stat
+            .rsplit_once(')')
+            .ok_or_else(invalid)?
+            .1
+            .split_whitespace()
+            .collect();
+        let ticks = |n: usize| -> io::Result<u64> {
+            fields
+                .get(n)
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(invalid)
+        };
+        let cpu_ticks = ticks(11)? + ticks(12)?;
+        let statm = fs::read_to_string(format!("/proc/{}/statm", pid))?;
+        let rss_pages: u64 = statm
+            .split_whitespace()
+            .nth(1)
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(invalid)?;
+        let (clk_tck, page_size) = unsafe {
+            (
+                libc::sysconf(libc::_SC_CLK_TCK),
+                libc::sysconf(libc::_SC_PAGESIZE),
+            )
+        };
+        let clk_tck = u64::try_from(clk_tck).map_err(|_| invalid())?.max(1);
+        let page_size = u64::try_from(page_size).map_err(|_| invalid())?;
+        Ok(Self {
+            pid,
+            cpu_time: Duration::from_micros(cpu_ticks * 1_000_000 / clk_tck),
+            memory: rss_pages * page_size,
+        })
+    }
 }
 
 impl Pipe {
@@ -47,6 +195,10 @@ pub fn new<P: AsRef<OsStr>>(program: P) -> (Self, Reader) {
                 input_data: None,
                 tx,
                 restart_delay: Duration::from_secs(1),
+                cgroup: None,
+                nice: None,
+                usage_interval: Duration::from_secs(5),
+                on_usage: None,
             },
             Reader { rx },
         )
@@ -81,8 +233,30 @@ pub fn restart_delay(&mut self, delay: Duration) -> &mut Self {
         self.restart_delay = delay;
         self
     }
+    /// Confines the subprocess with a cgroup (CPU quota, cpuset, memory limit)
+    pub fn cgroup(&mut self, cgroup: CGroup) -> &mut Self {
+        self.cgroup = Some(cgroup);
+        self
+    }
+    /// Runs the subprocess with SCHED_OTHER scheduling and the given nice value (-20..19) instead
+    /// of inheriting the real-time policy of the parent thread
+    pub fn nice(&mut self, nice: i32) -> &mut Self {
+        self.nice = Some(nice);
+        self
+    }
+    /// Calls the function with the subprocess resource usage every `interval`, e.g. to report the
+    /// usage via the hub
+    pub fn on_usage<F>(&mut self, interval: Duration, f: F) -> &mut Self
+    where
+        F: Fn(ChildUsage) + Send + Sync + 'static,
+    {
+        self.usage_interval = interval;
+        self.on_usage = Some(Box::new(f));
+        self
+    }
     /// Launches a subprocess pipe. The subprocess is restarted automatically if it terminates. The
-    /// subprocess inherits sheduling policy and priority of the parent thread.
+    /// subprocess inherits sheduling policy and priority of the parent thread, unless
+    /// [`Pipe::nice()`] is set.
     ///
     /// # Panics
     ///
@@ -96,16 +270,38 @@ pub fn run(&self) {
     }
     async fn run_async(&self) {
         loop {
+            let cgroup_procs = match self.cgroup.as_ref().map(CGroup::prepare).transpose() {
+                Ok(v) => v,
+                Err(error) => {
+                    error!(program=%self.program.to_string_lossy(), %error, "Failed to prepare cgroup");
+                    tokio::time::sleep(self.restart_delay).await;
+                    continue;
+                }
+            };
             match command_pipe(
                 &self.program,
                 &self.args,
                 &Options {
                     environment: self.environment.clone(),
                     input_data: self.input_data.clone(),
+                    cgroup_procs,
+                    nice: self.nice,
                 },
             ) {
-                Ok(rx) => {
-                    while let Ok(v) = rx.recv().await {
+                Ok((pid, rx)) => {
+                    let mut usage_timer =
+                        tokio::time::interval(self.usage_interval.max(Duration::from_millis(1)));
+                    loop {
+                        let v = tokio::select! {
+                            v = rx.recv() => {
+                                let Ok(v) = v else { break };
+                                v
+                            }
+                            _ = usage_timer.tick(), if self.on_usage.is_some() => {
+                                self.report_usage(pid);
+                                continue;
+                            }
+                        };
                         match v {
                             CommandPipeOutput::Stdout(line) => {
                                 if self.tx.send(line).await.is_err() {
@@ -132,12 +328,30 @@ async fn run_async(&self) {
             tokio::time::sleep(self.restart_delay).await;
         }
     }
+    fn report_usage(&self, pid: Option<u32>) {
+        let (Some(pid), Some(f)) = (pid, &self.on_usage) else {
+            return;
+        };
+        let usage = if let Some(ref cgroup) = self.cgroup {
+            cgroup.usage(pid)
+        } else {
+            ChildUsage::from_proc(pid)
+        };
+        match usage {
+            Ok(usage) => f(usage),
+            Err(error) => {
+                warn!(program=%self.program.to_string_lossy(), %error, "Unable to get subprocess resource usage");
+            }
+        }
+    }
 }
 
 #[derive(Default, Clone)]
 struct Options {
     environment: BTreeMap<String, String>,
     input_data: Option<Vec<u8>>,
+    cgroup_procs: Option<CString>,
+    nice: Option<i32>,
 }
 
 #[derive(Debug)]
@@ -153,7 +367,7 @@ fn command_pipe<P, I, S>(
     program: P,
     args: I,
     opts: &Options,
-) -> io::Result<Receiver<CommandPipeOutput>>
+) -> io::Result<(Option<u32>, Receiver<CommandPipeOutput>)>
 where
     P: AsRef<OsStr>,
     I: IntoIterator<Item = S>,
@@ -161,14 +375,46 @@ fn command_pipe<P, I, S>(
 {
     let (output_tx, output_rx) = pchannel_async::bounded(10);
 
-    let mut child = Command::new(program)
+    let mut command = Command::new(program);
+    command
         .args(args)
         .stdin(Stdio::piped())
         .stderr(Stdio::piped())
         .stdout(Stdio::piped())
         .kill_on_drop(true)
-        .envs(&opts.environment)
-        .spawn()?;
+        .envs(&opts.environment);
+    if opts.cgroup_procs.is_some() || opts.nice.is_some() {
+        let cgroup_procs = opts.cgroup_procs.clone();
+        let nice = opts.nice;
+        // only async-signal-safe calls are allowed in the child process before exec
+        unsafe {
+            command.pre_exec(move || {
+                if let Some(ref procs) = cgroup_procs {
+                    let fd = libc::open(procs.as_ptr(), libc::O_WRONLY);
+                    if fd < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    // "0" moves the writing process
+                    let res = libc::write(fd, b"0".as_ptr().cast(), 1);
+                    libc::close(fd);
+                    if res < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                if let Some(nice) = nice {
+                    let param = libc::sched_param { sched_priority: 0 };
+                    if libc::sched_setscheduler(0, libc::SCHED_OTHER, &param) != 0
+                        || libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0
+                    {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+    let mut child = command.spawn()?;
+    let pid = child.id();
     let stdin = if opts.input_data.is_some() {
         match child.stdin.take() {
             Some(v) => Some(v),
@@ -261,5 +507,5 @@ fn command_pipe<P, I, S>(
             .await;
     });
 
-    Ok(output_rx)
+    Ok((pid, output_rx))
 }
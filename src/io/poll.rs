@@ -0,0 +1,469 @@
+//!
+//! Polled devices engine. Devices (usually a client mapping with a parsing function) are
+//! registered with own polling intervals and priorities and are polled by a small set of
+//! real-time threads. Poll results are published to the controller hub.
+//!
+//! Devices are isolated from each other: a failing device does not block others and is polled
+//! less frequently (see [`Poller::failure_backoff()`]) until it recovers. Panics in poll
+//! functions are counted as device errors (unless the process panic handler is set with
+//! `setup_panic()`, which terminates the process on any panic).
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use parking_lot_rt::{Condvar, Mutex};
+use serde::Serialize;
+use tracing::{error, warn};
+
+use crate::controller::{Context, SLEEP_STEP};
+use crate::hub::Hub;
+use crate::thread_rt::{Builder, RTParams};
+use crate::{DataDeliveryPolicy, Error, Result};
+
+type PollFn<D> = dyn FnMut() -> Result<D> + Send;
+
+pub const DEFAULT_PRIORITY: usize = 100;
+
+pub const DEFAULT_WORKERS: usize = 2;
+
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+
+pub const DEFAULT_FAILURE_BACKOFF: Duration = Duration::from_secs(5);
+
+/// A polled device
+pub struct Device<D> {
+    name: String,
+    interval: Duration,
+    priority: usize,
+    poll_fn: Box<PollFn<D>>,
+}
+
+impl<D> Device<D> {
+    /// Creates a new device. The poll function usually owns a client mapping, reads it and
+    /// returns a hub message
+    pub fn new<F>(name: &str, interval: Duration, poll_fn: F) -> Self
+    where
+        F: FnMut() -> Result<D> + Send + 'static,
+    {
+        Self {
+            name: name.to_owned(),
+            interval,
+            priority: DEFAULT_PRIORITY,
+            poll_fn: Box::new(poll_fn),
+        }
+    }
+    /// Sets the device priority (the default is 100, lower is more important). If several devices
+    /// are due at the same time, ones with the higher priority are polled first
+    pub fn priority(mut self, priority: usize) -> Self {
+        self.priority = priority;
+        self
+    }
+}
+
+/// Device polling statistics
+#[derive(Serialize, Default, Clone, Debug)]
+pub struct DeviceStats {
+    /// Total number of polls
+    pub polls: u64,
+    /// Total number of failed polls
+    pub errors: u64,
+    /// Number of failed polls in a row
+    pub consecutive_errors: u32,
+    /// Number of polls started later than one full interval after the deadline
+    pub missed_deadlines: u64,
+    /// Number of polls which took longer than the device interval
+    pub overruns: u64,
+    /// The last poll duration
+    pub last_duration: Option<Duration>,
+    /// The last poll error
+    pub last_error: Option<String>,
+}
+
+struct Slot<D> {
+    name: String,
+    interval: Duration,
+    priority: usize,
+    next_due: Instant,
+    // taken by a worker thread while the device is being polled
+    poll_fn: Option<Box<PollFn<D>>>,
+    stats: DeviceStats,
+}
+
+struct Shared<D> {
+    slots: Mutex<Vec<Slot<D>>>,
+    cv: Condvar,
+    stop: AtomicBool,
+}
+
+/// Polled devices engine, requires to be run in a separate thread manually
+pub struct Poller<D> {
+    name: String,
+    workers: usize,
+    rt_params: RTParams,
+    failure_threshold: u32,
+    failure_backoff: Duration,
+    shared: Arc<Shared<D>>,
+}
+
+impl<D> Poller<D>
+where
+    D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
+{
+    /// Creates a new poller. The name is used as a prefix for worker thread names and SHOULD be
+    /// 13 characters or less
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            workers: DEFAULT_WORKERS,
+            rt_params: RTParams::new(),
+            failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+            failure_backoff: DEFAULT_FAILURE_BACKOFF,
+            shared: Arc::new(Shared {
+                slots: <_>::default(),
+                cv: Condvar::new(),
+                stop: AtomicBool::new(false),
+            }),
+        }
+    }
+    /// Sets the number of polling threads (the default is 2)
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+    /// Sets real-time parameters for polling threads
+    pub fn rt_params(mut self, rt_params: RTParams) -> Self {
+        self.rt_params = rt_params;
+        self
+    }
+    /// Sets the number of failed polls in a row after which the device is polled with
+    /// [`Poller::failure_backoff()`] interval (the default is 3)
+    pub fn failure_threshold(mut self, threshold: u32) -> Self {
+        self.failure_threshold = threshold;
+        self
+    }
+    /// Sets the polling interval for failed devices (the default is 5 seconds). If the device
+    /// own interval is larger, it is used instead
+    pub fn failure_backoff(mut self, backoff: Duration) -> Self {
+        self.failure_backoff = backoff;
+        self
+    }
+    /// Registers a device. The device name MUST be unique
+    pub fn add_device(&self, device: Device<D>) -> Result<()> {
+        let mut slots = self.shared.slots.lock();
+        if slots.iter().any(|s| s.name == device.name) {
+            return Err(Error::invalid_data(format!(
+                "device already registered: {}",
+                device.name
+            )));
+        }
+        slots.push(Slot {
+            name: device.name,
+            interval: device.interval,
+            priority: device.priority,
+            next_due: Instant::now(),
+            poll_fn: Some(device.poll_fn),
+            stats: <_>::default(),
+        });
+        self.shared.cv.notify_one();
+        Ok(())
+    }
+    /// Returns a handle which can be used to get device statistics while the poller is running
+    pub fn handle(&self) -> PollerHandle<D> {
+        PollerHandle {
+            shared: self.shared.clone(),
+        }
+    }
+    /// Runs the poller until the controller goes offline. Blocks the current thread
+    pub fn run<V: Send + Sync>(&self, context: &Context<D, V>) -> Result<()> {
+        self.run_while(context.hub(), || context.is_online())
+    }
+    // runs the poller while the online function returns true
+    fn run_while<O>(&self, hub: &Hub<D>, online: O) -> Result<()>
+    where
+        O: Fn() -> bool + Sync,
+    {
+        self.stagger();
+        thread::scope(|scope| {
+            for i in 0..self.workers {
+                let builder = Builder::new()
+                    .name(format!("{}{}", self.name, i))
+                    .rt_params(self.rt_params.clone());
+                if let Err(e) = builder.spawn_scoped(scope, || self.worker_loop(hub, &online)) {
+                    self.shared.stop.store(true, Ordering::SeqCst);
+                    self.shared.cv.notify_all();
+                    return Err(e);
+                }
+            }
+            Ok(())
+        })
+    }
+    // spreads initial deadlines of the devices over their intervals to avoid load bursts
+    fn stagger(&self) {
+        let mut slots = self.shared.slots.lock();
+        let now = Instant::now();
+        let Ok(count) = u32::try_from(slots.len()) else {
+            return;
+        };
+        for (i, slot) in (0..count).zip(slots.iter_mut()) {
+            slot.next_due = now + slot.interval / count * i;
+        }
+    }
+    fn worker_loop<O>(&self, hub: &Hub<D>, online: &O)
+    where
+        O: Fn() -> bool,
+    {
+        loop {
+            if !online() || self.shared.stop.load(Ordering::SeqCst) {
+                break;
+            }
+            let (idx, mut poll_fn) = {
+                let mut slots = self.shared.slots.lock();
+                let now = Instant::now();
+                match pick(&slots, now) {
+                    Ok(idx) => (idx, slots[idx].poll_fn.take().unwrap()),
+                    Err(earliest) => {
+                        let max_wake = now + SLEEP_STEP;
+                        let wake = earliest.map_or(max_wake, |e| e.min(max_wake));
+                        self.shared.cv.wait_until(&mut slots, wake);
+                        continue;
+                    }
+                }
+            };
+            let started = Instant::now();
+            let result = poll(&mut poll_fn).map(|message| hub.send(message));
+            let elapsed = started.elapsed();
+            let mut slots = self.shared.slots.lock();
+            let slot = &mut slots[idx];
+            // the poll function is returned back even if it has panicked
+            slot.poll_fn.replace(poll_fn);
+            self.complete(slot, started, elapsed, result);
+            self.shared.cv.notify_one();
+        }
+    }
+    // updates the device statistics and schedules the next poll
+    fn complete(
+        &self,
+        slot: &mut Slot<D>,
+        started: Instant,
+        elapsed: Duration,
+        result: Result<()>,
+    ) {
+        slot.stats.polls += 1;
+        slot.stats.last_duration = Some(elapsed);
+        if elapsed > slot.interval {
+            slot.stats.overruns += 1;
+        }
+        let missed = started.saturating_duration_since(slot.next_due) > slot.interval;
+        if missed {
+            slot.stats.missed_deadlines += 1;
+        }
+        match result {
+            Ok(()) => {
+                if slot.stats.consecutive_errors >= self.failure_threshold {
+                    warn!(device = slot.name, "device recovered");
+                }
+                slot.stats.consecutive_errors = 0;
+                slot.next_due = if missed {
+                    // do not try to catch up, keep the phase from now
+                    started + slot.interval
+                } else {
+                    slot.next_due + slot.interval
+                };
+            }
+            Err(e) => {
+                slot.stats.errors += 1;
+                slot.stats.consecutive_errors += 1;
+                if slot.stats.consecutive_errors == 1 {
+                    error!(device = slot.name, error=%e, "device poll failed");
+                } else if slot.stats.consecutive_errors == self.failure_threshold {
+                    error!(device = slot.name, error=%e, "device failed, backing off");
+                }
+                slot.stats.last_error = Some(e.to_string());
+                let interval = if slot.stats.consecutive_errors >= self.failure_threshold {
+                    slot.interval.max(self.failure_backoff)
+                } else {
+                    slot.interval
+                };
+                slot.next_due = Instant::now() + interval;
+            }
+        }
+    }
+}
+
+// selects a due device with the highest priority (the earliest deadline first if priorities are
+// equal), returns the earliest deadline of other devices if no device is due
+fn pick<D>(slots: &[Slot<D>], now: Instant) -> std::result::Result<usize, Option<Instant>> {
+    let mut candidate: Option<usize> = None;
+    let mut earliest: Option<Instant> = None;
+    for (i, slot) in slots.iter().enumerate() {
+        if slot.poll_fn.is_none() {
+            continue;
+        }
+        if slot.next_due <= now {
+            if candidate.map_or(true, |c| {
+                let c = &slots[c];
+                (slot.priority, slot.next_due) < (c.priority, c.next_due)
+            }) {
+                candidate = Some(i);
+            }
+        } else if earliest.map_or(true, |e| slot.next_due < e) {
+            earliest = Some(slot.next_due);
+        }
+    }
+    candidate.ok_or(earliest)
+}
+
+// calls the poll function, a panic is turned into a device error
+fn poll<D>(poll_fn: &mut Box<PollFn<D>>) -> Result<D> {
+    match panic::catch_unwind(AssertUnwindSafe(|| poll_fn())) {
+        Ok(result) => result,
+        Err(e) => {
+            let message = e
+                .downcast_ref::<&str>()
+                .map(|s| (*s).to_owned())
+                .or_else(|| e.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Err(Error::failed(format!("device poll panicked: {}", message)))
+        }
+    }
+}
+
+/// Poller handle, can be used to get device statistics
+pub struct PollerHandle<D> {
+    shared: Arc<Shared<D>>,
+}
+
+impl<D> Clone for PollerHandle<D> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<D> PollerHandle<D> {
+    /// Returns statistics for all registered devices
+    pub fn stats(&self) -> BTreeMap<String, DeviceStats> {
+        self.shared
+            .slots
+            .lock()
+            .iter()
+            .map(|s| (s.name.clone(), s.stats.clone()))
+            .collect()
+    }
+    /// Returns statistics for a device
+    pub fn device_stats(&self, name: &str) -> Option<DeviceStats> {
+        self.shared
+            .slots
+            .lock()
+            .iter()
+            .find(|s| s.name == name)
+            .map(|s| s.stats.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::{pick, Device, Poller, Slot};
+    use crate::hub::Hub;
+    use crate::{DataDeliveryPolicy, Error};
+
+    #[derive(Clone, Debug)]
+    struct Message(u32);
+
+    impl DataDeliveryPolicy for Message {}
+
+    fn slot(name: &str, priority: usize, next_due: Instant) -> Slot<Message> {
+        Slot {
+            name: name.to_owned(),
+            interval: Duration::from_millis(100),
+            priority,
+            next_due,
+            poll_fn: Some(Box::new(|| Ok(Message(0)))),
+            stats: <_>::default(),
+        }
+    }
+
+    #[test]
+    fn test_pick() {
+        let now = Instant::now();
+        let ms = Duration::from_millis;
+        let mut slots = vec![
+            slot("late", 100, now - ms(20)),
+            slot("later", 100, now - ms(50)),
+            slot("important", 10, now - ms(1)),
+            slot("future", 1, now + ms(30)),
+            slot("future2", 1, now + ms(10)),
+        ];
+        // the highest priority first, then the earliest deadline
+        assert_eq!(pick(&slots, now), Ok(2));
+        slots[2].poll_fn.take();
+        assert_eq!(pick(&slots, now), Ok(1));
+        slots[1].poll_fn.take();
+        assert_eq!(pick(&slots, now), Ok(0));
+        slots[0].poll_fn.take();
+        assert_eq!(pick(&slots, now), Err(Some(now + ms(10))));
+        assert_eq!(pick(&slots, now + ms(30)), Ok(3));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let poller: Poller<Message> = Poller::new("test")
+            .failure_threshold(2)
+            .failure_backoff(Duration::from_secs(10));
+        let started = Instant::now();
+        let mut slot = slot("dev", 100, started);
+        let elapsed = Duration::from_millis(1);
+        poller.complete(&mut slot, started, elapsed, Err(Error::io("e")));
+        assert_eq!(slot.stats.consecutive_errors, 1);
+        assert!(slot.next_due < started + Duration::from_secs(1));
+        poller.complete(&mut slot, started, elapsed, Err(Error::io("e")));
+        assert_eq!(slot.stats.consecutive_errors, 2);
+        assert!(slot.next_due >= started + Duration::from_secs(10));
+        let started = slot.next_due;
+        poller.complete(&mut slot, started, elapsed, Ok(()));
+        assert_eq!(slot.stats.consecutive_errors, 0);
+        assert_eq!(slot.stats.errors, 2);
+        assert_eq!(slot.stats.polls, 3);
+        assert_eq!(slot.next_due, started + slot.interval);
+    }
+
+    #[test]
+    fn test_poll_panic() {
+        let hub: Hub<Message> = Hub::new();
+        let client = hub.register("test", |_| true).unwrap();
+        let poller: Poller<Message> = Poller::new("test").workers(1);
+        let calls = AtomicU32::new(0);
+        poller
+            .add_device(Device::new("dev", Duration::from_millis(1), move || {
+                if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                    panic!("device failure");
+                }
+                Ok(Message(1))
+            }))
+            .unwrap();
+        let handle = poller.handle();
+        let done = AtomicBool::new(false);
+        thread::scope(|scope| {
+            let t = scope.spawn(|| poller.run_while(&hub, || !done.load(Ordering::SeqCst)));
+            // the device keeps being polled after the panic
+            assert_eq!(client.recv().unwrap().0, 1);
+            done.store(true, Ordering::SeqCst);
+            t.join().unwrap().unwrap();
+        });
+        let stats = handle.device_stats("dev").unwrap();
+        assert_eq!(stats.errors, 1);
+        assert!(stats.polls >= 2);
+        assert_eq!(
+            stats.last_error.unwrap(),
+            "operation failed: device poll panicked: device failure"
+        );
+    }
+}
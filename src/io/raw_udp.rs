@@ -3,7 +3,15 @@
 //! devices and software, such as Matlab, LabView, etc.
 //!
 //! [Raw UDP example](https://github.com/roboplc/roboplc/blob/main/examples/raw-udp.rs)
+//!
+//! On Linux the receiver can deliver per-packet reception timestamps (IEEE 1588 hardware
+//! timestamps if supported by NIC and enabled for the interface, kernel software timestamps or
+//! monotonic timestamps captured by the receiver as a fallback).
 use binrw::{BinRead, BinWrite};
+#[cfg(target_os = "linux")]
+use bma_ts::Monotonic;
+#[cfg(target_os = "linux")]
+use std::{io, mem, os::unix::io::AsRawFd, ptr, time::Duration};
 use std::{
     io::Cursor,
     marker::PhantomData,
@@ -12,6 +20,50 @@
 
 use crate::{Error, Result};
 
+/// Packet reception timestamp
+#[cfg(target_os = "linux")]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RxTimestamp {
+    /// Raw NIC hardware (PTP clock) timestamp
+    Hardware(Duration),
+    /// Kernel software timestamp (CLOCK_REALTIME), since UNIX epoch
+    Software(Duration),
+    /// Monotonic timestamp, captured by the receiver after the packet has been received
+    Monotonic(Monotonic),
+}
+
+/// Reception timestamping capability, detected by [`UdpReceiver::enable_timestamping()`]
+#[cfg(target_os = "linux")]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TimestampingCapability {
+    /// Hardware and software timestamps are requested. Packets are marked with hardware
+    /// timestamps if the NIC driver provides them, otherwise software timestamps are used
+    Hardware,
+    /// Software timestamps only
+    Software,
+    /// Kernel timestamping is not available, monotonic timestamps are captured
+    Monotonic,
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct ScmTimestamping {
+    // 0 - software, 1 - deprecated, 2 - raw hardware
+    ts: [libc::timespec; 3],
+}
+
+#[cfg(target_os = "linux")]
+fn timespec_to_duration(ts: &libc::timespec) -> Option<Duration> {
+    if ts.tv_sec == 0 && ts.tv_nsec == 0 {
+        return None;
+    }
+    Some(Duration::new(
+        u64::try_from(ts.tv_sec).ok()?,
+        u32::try_from(ts.tv_nsec).ok()?,
+    ))
+}
+
 /// Raw UDP receiver
 pub struct UdpReceiver<T>
 where
@@ -19,6 +71,8 @@ pub struct UdpReceiver<T>
 {
     server: UdpSocket,
     buffer: Vec<u8>,
+    #[cfg(target_os = "linux")]
+    timestamping: Option<TimestampingCapability>,
     _phantom: PhantomData<T>,
 }
 
@@ -31,9 +85,104 @@ pub fn bind<A: ToSocketAddrs>(addr: A, buf_size: usize) -> Result<Self> {
         Ok(Self {
             server,
             buffer: vec![0; buf_size],
+            #[cfg(target_os = "linux")]
+            timestamping: None,
             _phantom: PhantomData,
         })
     }
+    /// Probes and enables kernel reception timestamping (SO_TIMESTAMPING). If `hardware` is
+    /// true, hardware timestamps are requested as well (note that hardware timestamping must be
+    /// enabled for the network interface, e.g. with `hwstamp_ctl`). Falls back to software and
+    /// then to monotonic timestamps if not supported
+    #[cfg(target_os = "linux")]
+    pub fn enable_timestamping(&mut self, hardware: bool) -> TimestampingCapability {
+        let software_flags = libc::SOF_TIMESTAMPING_RX_SOFTWARE | libc::SOF_TIMESTAMPING_SOFTWARE;
+        let hardware_flags =
+            libc::SOF_TIMESTAMPING_RX_HARDWARE | libc::SOF_TIMESTAMPING_RAW_HARDWARE;
+        let capability = if hardware
+            && self
+                .set_timestamping_flags(software_flags | hardware_flags)
+                .is_ok()
+        {
+            TimestampingCapability::Hardware
+        } else if self.set_timestamping_flags(software_flags).is_ok() {
+            TimestampingCapability::Software
+        } else {
+            TimestampingCapability::Monotonic
+        };
+        self.timestamping = Some(capability);
+        capability
+    }
+    /// Returns the timestamping capability if enabled
+    #[cfg(target_os = "linux")]
+    pub fn timestamping(&self) -> Option<TimestampingCapability> {
+        self.timestamping
+    }
+    #[cfg(target_os = "linux")]
+    fn set_timestamping_flags(&self, flags: libc::c_uint) -> io::Result<()> {
+        let res = unsafe {
+            libc::setsockopt(
+                self.server.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_TIMESTAMPING,
+                ptr::addr_of!(flags).cast(),
+                libc::socklen_t::try_from(mem::size_of_val(&flags)).unwrap_or_default(),
+            )
+        };
+        if res == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+    /// Receives a frame with its reception timestamp. If kernel timestamping is not enabled (see
+    /// [`UdpReceiver::enable_timestamping()`]) or the kernel has not provided a timestamp,
+    /// [`RxTimestamp::Monotonic`] is returned
+    #[cfg(target_os = "linux")]
+    pub fn recv_timestamped(&mut self) -> Result<(T, RxTimestamp)> {
+        // u64 array to keep cmsghdr alignment
+        let mut control = [0u64; 64];
+        let mut iov = libc::iovec {
+            iov_base: self.buffer.as_mut_ptr().cast(),
+            iov_len: self.buffer.len(),
+        };
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = mem::size_of_val(&control) as _;
+        let res = unsafe { libc::recvmsg(self.server.as_raw_fd(), &mut msg, 0) };
+        let captured = Monotonic::now();
+        let size = usize::try_from(res).map_err(|_| Error::from(io::Error::last_os_error()))?;
+        let mut timestamp = None;
+        if self.timestamping.is_some() {
+            unsafe {
+                let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+                while !cmsg.is_null() {
+                    if (*cmsg).cmsg_level == libc::SOL_SOCKET
+                        && (*cmsg).cmsg_type == libc::SCM_TIMESTAMPING
+                    {
+                        let data: ScmTimestamping =
+                            ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast());
+                        timestamp = timespec_to_duration(&data.ts[2])
+                            .map(RxTimestamp::Hardware)
+                            .or_else(|| {
+                                timespec_to_duration(&data.ts[0]).map(RxTimestamp::Software)
+                            });
+                    }
+                    cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+                }
+            }
+        }
+        let mut cursor = Cursor::new(&self.buffer[..size]);
+        let value = T::read_le(&mut cursor)?;
+        Ok((value, timestamp.unwrap_or(RxTimestamp::Monotonic(captured))))
+    }
+    /// Returns an iterator over timestamped frames (see [`UdpReceiver::recv_timestamped()`])
+    #[cfg(target_os = "linux")]
+    pub fn iter_timestamped(&mut self) -> impl Iterator<Item = Result<(T, RxTimestamp)>> + '_ {
+        std::iter::from_fn(move || Some(self.recv_timestamped()))
+    }
 }
 
 impl<T> Iterator for UdpReceiver<T>
@@ -0,0 +1,580 @@
+//!
+//! Framework for custom TCP servers (slaves) of vendor protocols.
+//!
+//! The protocol is implemented with [`FrameHandler`], which detects request frames and produces
+//! responses. The listener, the connection worker limit, idle timeouts, client allow-lists and
+//! connection limits, request filters and statistics are provided by [`FrameServer`].
+//!
+//! If requests must be processed by a worker (e.g. a real-time one), [`channel_handler()`]
+//! forwards request frames into a data channel and waits for the worker to respond.
+//!
+//! Example:
+//!
+//! ```rust,no_run
+//! use roboplc::io::server::{FrameHandler, FrameServer, Session};
+//! use roboplc::Result;
+//! use std::time::Duration;
+//!
+//! // a line-based echo protocol
+//! struct Echo;
+//!
+//! impl FrameHandler for Echo {
+//!     fn frame_len(&self, buf: &[u8]) -> Result<Option<usize>> {
+//!         Ok(buf.iter().position(|&b| b == b'\n').map(|pos| pos + 1))
+//!     }
+//!     fn handle(&self, request: &[u8], response: &mut Vec<u8>, _session: &Session) -> Result<()> {
+//!         response.extend(request);
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let server = FrameServer::bind("0.0.0.0:9000", Echo, Duration::from_secs(1), 8).unwrap();
+//! let handle = server.spawn().unwrap();
+//! ```
+use std::{
+    collections::BTreeMap,
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use parking_lot_rt::Mutex;
+use rtsc::semaphore::Semaphore;
+use serde::Serialize;
+use tracing::{error, warn};
+
+use crate::{pchannel, DataDeliveryPolicy, Error, Result};
+
+// stop check interval for the TCP listener and client connections
+pub(crate) const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+const DEFAULT_MAX_FRAME_SIZE: usize = 65536;
+
+#[derive(Clone, Default)]
+pub(crate) struct StopSignal {
+    pub(crate) flag: Arc<AtomicBool>,
+    #[cfg(target_os = "linux")]
+    pub(crate) state: Option<crate::controller::State>,
+}
+
+impl StopSignal {
+    pub(crate) fn is_set(&self) -> bool {
+        if self.flag.load(Ordering::SeqCst) {
+            return true;
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(ref state) = self.state {
+            return !state.is_online();
+        }
+        false
+    }
+    pub(crate) fn stopper(&self) -> ServerStopper {
+        ServerStopper {
+            flag: self.flag.clone(),
+        }
+    }
+}
+
+/// Stops a running server. Can be cloned and shared with no limitations.
+#[derive(Clone)]
+#[allow(clippy::module_name_repetitions)]
+pub struct ServerStopper {
+    flag: Arc<AtomicBool>,
+}
+
+impl ServerStopper {
+    /// Stops the server. The server stops accepting new connections and closes all client
+    /// connections within 100ms
+    pub fn stop(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+    }
+}
+
+/// A handle of a server, running in background
+#[allow(clippy::module_name_repetitions)]
+pub struct ServerHandle {
+    stopper: ServerStopper,
+    thread: thread::JoinHandle<Result<()>>,
+}
+
+impl ServerHandle {
+    pub(crate) fn new(stopper: ServerStopper, thread: thread::JoinHandle<Result<()>>) -> Self {
+        Self { stopper, thread }
+    }
+    pub fn stop(&self) {
+        self.stopper.stop();
+    }
+    pub fn stopper(&self) -> ServerStopper {
+        self.stopper.clone()
+    }
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+    /// Stops the server and waits for its thread to finish
+    pub fn join(self) -> Result<()> {
+        self.stop();
+        self.thread
+            .join()
+            .map_err(|_| Error::failed("server thread panicked"))?
+    }
+}
+
+/// Client allow-list and per-client connection limits
+#[derive(Default)]
+pub(crate) struct ConnectionLimiter {
+    pub(crate) allowed_clients: Vec<IpAddr>,
+    pub(crate) max_connections_per_client: Option<usize>,
+    connections: Arc<Mutex<BTreeMap<IpAddr, usize>>>,
+}
+
+impl ConnectionLimiter {
+    // checks the allow-list and the connection limit
+    pub(crate) fn register(&self, addr: SocketAddr) -> Option<ConnectionGuard> {
+        let ip = addr.ip();
+        if !self.allowed_clients.is_empty() && !self.allowed_clients.contains(&ip) {
+            warn!(%addr, "client is not allowed, connection refused");
+            return None;
+        }
+        let mut conns = self.connections.lock();
+        if let Some(max) = self.max_connections_per_client {
+            if conns.get(&ip).copied().unwrap_or_default() >= max {
+                warn!(%addr, max, "too many client connections, connection refused");
+                return None;
+            }
+        }
+        *conns.entry(ip).or_default() += 1;
+        Some(ConnectionGuard {
+            ip,
+            connections: self.connections.clone(),
+        })
+    }
+}
+
+// per-client connection counter, the connection is unregistered on drop
+pub(crate) struct ConnectionGuard {
+    ip: IpAddr,
+    connections: Arc<Mutex<BTreeMap<IpAddr, usize>>>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut connections = self.connections.lock();
+        if let Some(count) = connections.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&self.ip);
+            }
+        }
+    }
+}
+
+pub(crate) fn prepare_tcp_stream(stream: &TcpStream, timeout: Duration) -> Result<()> {
+    // accepted streams may inherit the non-blocking mode of the listener
+    stream.set_nonblocking(false)?;
+    // the idle timeout is checked by the client handler
+    stream.set_read_timeout(Some(timeout.min(STOP_CHECK_INTERVAL)))?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.set_nodelay(true)?;
+    Ok(())
+}
+
+/// Client session information
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// Connection id, unique for the server
+    pub id: usize,
+    pub peer: SocketAddr,
+    pub connected: Instant,
+}
+
+/// Protocol implementation for [`FrameServer`]
+pub trait FrameHandler: Send + Sync + 'static {
+    /// Detects a request frame at the beginning of the buffer. Returns the frame length if the
+    /// buffer contains a complete frame or `None` if more data is required. Errors close the
+    /// client connection
+    fn frame_len(&self, buf: &[u8]) -> Result<Option<usize>>;
+    /// Processes a complete request frame. The response is sent to the client if not empty.
+    /// Errors close the client connection
+    fn handle(&self, request: &[u8], response: &mut Vec<u8>, session: &Session) -> Result<()>;
+    /// Called for requests, denied by the request filter (see
+    /// [`FrameServer::set_request_filter()`]). The response is sent to the client if not empty.
+    /// The default implementation sends no response
+    #[allow(unused_variables)]
+    fn deny(&self, request: &[u8], response: &mut Vec<u8>, session: &Session) -> Result<()> {
+        Ok(())
+    }
+    /// Called when a client is connected, e.g. to send a greeting
+    #[allow(unused_variables)]
+    fn connected(&self, response: &mut Vec<u8>, session: &Session) -> Result<()> {
+        Ok(())
+    }
+}
+
+type RequestFilterFn = dyn Fn(&Session, &[u8]) -> bool + Send + Sync;
+
+/// Server statistics
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+#[allow(clippy::module_name_repetitions)]
+pub struct ServerStats {
+    pub connections_accepted: u64,
+    pub connections_refused: u64,
+    pub connections_active: u64,
+    pub requests: u64,
+    pub requests_denied: u64,
+    pub errors: u64,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+}
+
+#[derive(Default)]
+struct Stats {
+    connections_accepted: AtomicU64,
+    connections_refused: AtomicU64,
+    connections_active: AtomicU64,
+    requests: AtomicU64,
+    requests_denied: AtomicU64,
+    errors: AtomicU64,
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+}
+
+impl Stats {
+    fn snapshot(&self) -> ServerStats {
+        ServerStats {
+            connections_accepted: self.connections_accepted.load(Ordering::Relaxed),
+            connections_refused: self.connections_refused.load(Ordering::Relaxed),
+            connections_active: self.connections_active.load(Ordering::Relaxed),
+            requests: self.requests.load(Ordering::Relaxed),
+            requests_denied: self.requests_denied.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Statistics reader of a running server. Can be cloned and shared with no limitations.
+#[derive(Clone)]
+#[allow(clippy::module_name_repetitions)]
+pub struct ServerStatsReader {
+    stats: Arc<Stats>,
+}
+
+impl ServerStatsReader {
+    pub fn get(&self) -> ServerStats {
+        self.stats.snapshot()
+    }
+}
+
+/// TCP server for custom frame-based protocols. Requires to be run in a separate thread manually
+/// or with [`FrameServer::spawn()`]
+#[allow(clippy::module_name_repetitions)]
+pub struct FrameServer<H: FrameHandler> {
+    handler: Arc<H>,
+    listener: TcpListener,
+    timeout: Duration,
+    idle_timeout: Duration,
+    max_frame_size: usize,
+    semaphore: Semaphore,
+    limiter: ConnectionLimiter,
+    request_filter: Option<Arc<RequestFilterFn>>,
+    stop: StopSignal,
+    stats: Arc<Stats>,
+    next_session_id: AtomicUsize,
+}
+
+impl<H: FrameHandler> FrameServer<H> {
+    /// Binds the server. `max_workers` limits the number of simultaneous client connections
+    pub fn bind<A: ToSocketAddrs>(
+        addr: A,
+        handler: H,
+        timeout: Duration,
+        max_workers: usize,
+    ) -> Result<Self> {
+        Ok(Self {
+            handler: Arc::new(handler),
+            listener: TcpListener::bind(addr)?,
+            timeout,
+            idle_timeout: timeout,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            semaphore: Semaphore::new(max_workers),
+            limiter: <_>::default(),
+            request_filter: None,
+            stop: <_>::default(),
+            stats: <_>::default(),
+            next_session_id: AtomicUsize::new(1),
+        })
+    }
+    /// Sets the idle timeout for client connections (the default is the server timeout)
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        self.idle_timeout = timeout;
+    }
+    /// Sets the maximum size of incomplete request frames, the client is disconnected if exceeded
+    /// (the default is 64KB)
+    pub fn set_max_frame_size(&mut self, size: usize) {
+        self.max_frame_size = size;
+    }
+    /// Limits the number of simultaneous connections from a single IP address (unlimited by
+    /// default). The total number of connections is limited by `max_workers`
+    pub fn set_max_connections_per_client(&mut self, max: usize) {
+        self.limiter.max_connections_per_client = Some(max);
+    }
+    /// Accepts connections from the given IP addresses only (all are accepted by default)
+    pub fn set_allowed_clients(&mut self, clients: Vec<IpAddr>) {
+        self.limiter.allowed_clients = clients;
+    }
+    /// Sets a function which checks if a request is allowed to be processed. Denied requests are
+    /// passed to [`FrameHandler::deny()`]
+    pub fn set_request_filter<F>(&mut self, f: F)
+    where
+        F: Fn(&Session, &[u8]) -> bool + Send + Sync + 'static,
+    {
+        self.request_filter = Some(Arc::new(f));
+    }
+    /// Stops the server when the controller goes offline (shutdown)
+    #[cfg(target_os = "linux")]
+    pub fn set_controller_state(&mut self, state: crate::controller::State) {
+        self.stop.state = Some(state);
+    }
+    /// Returns a stopper, which can be used to stop the server
+    pub fn stopper(&self) -> ServerStopper {
+        self.stop.stopper()
+    }
+    /// Returns a statistics reader
+    pub fn stats(&self) -> ServerStatsReader {
+        ServerStatsReader {
+            stats: self.stats.clone(),
+        }
+    }
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener.local_addr().map_err(Into::into)
+    }
+    /// Serves the clients in a background thread (not real-time)
+    pub fn spawn(self) -> Result<ServerHandle> {
+        let stopper = self.stopper();
+        let thread = thread::Builder::new()
+            .name("frame-server".to_owned())
+            .spawn(move || self.serve())?;
+        Ok(ServerHandle::new(stopper, thread))
+    }
+    /// Serves the clients until stopped (see [`FrameServer::stopper()`] and
+    /// [`FrameServer::set_controller_state()`])
+    pub fn serve(&self) -> Result<()> {
+        self.listener.set_nonblocking(true)?;
+        loop {
+            if self.stop.is_set() {
+                break;
+            }
+            let permission = self.semaphore.acquire();
+            let accepted = loop {
+                match self.listener.accept() {
+                    Ok(v) => break Some(v),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        if self.stop.is_set() {
+                            break None;
+                        }
+                        thread::sleep(STOP_CHECK_INTERVAL);
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            };
+            let Some((stream, addr)) = accepted else {
+                break;
+            };
+            let Some(guard) = self.limiter.register(addr) else {
+                self.stats
+                    .connections_refused
+                    .fetch_add(1, Ordering::Relaxed);
+                continue;
+            };
+            if let Err(e) = prepare_tcp_stream(&stream, self.timeout) {
+                error!(%addr, %e, "error preparing tcp stream");
+                continue;
+            }
+            self.stats
+                .connections_accepted
+                .fetch_add(1, Ordering::Relaxed);
+            let connection = Connection {
+                handler: self.handler.clone(),
+                session: Session {
+                    id: self.next_session_id.fetch_add(1, Ordering::Relaxed),
+                    peer: addr,
+                    connected: Instant::now(),
+                },
+                request_filter: self.request_filter.clone(),
+                stop: self.stop.clone(),
+                stats: self.stats.clone(),
+                idle_timeout: self.idle_timeout,
+                max_frame_size: self.max_frame_size,
+            };
+            thread::spawn(move || {
+                let _permission = permission;
+                let _guard = guard;
+                connection
+                    .stats
+                    .connections_active
+                    .fetch_add(1, Ordering::Relaxed);
+                if let Err(error) = connection.handle(stream) {
+                    connection.stats.errors.fetch_add(1, Ordering::Relaxed);
+                    error!(%addr, %error, "error handling client");
+                }
+                connection
+                    .stats
+                    .connections_active
+                    .fetch_sub(1, Ordering::Relaxed);
+            });
+        }
+        Ok(())
+    }
+}
+
+struct Connection<H: FrameHandler> {
+    handler: Arc<H>,
+    session: Session,
+    request_filter: Option<Arc<RequestFilterFn>>,
+    stop: StopSignal,
+    stats: Arc<Stats>,
+    idle_timeout: Duration,
+    max_frame_size: usize,
+}
+
+impl<H: FrameHandler> Connection<H> {
+    fn handle<T: Read + Write>(&self, mut client: T) -> Result<()> {
+        let mut buf = Vec::with_capacity(1024);
+        let mut chunk = [0u8; 1024];
+        let mut response = Vec::with_capacity(1024);
+        self.handler.connected(&mut response, &self.session)?;
+        self.send(&mut client, &response)?;
+        let mut last_activity = Instant::now();
+        loop {
+            match client.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => {
+                    last_activity = Instant::now();
+                    self.stats
+                        .bytes_received
+                        .fetch_add(n as u64, Ordering::Relaxed);
+                    buf.extend(&chunk[..n]);
+                }
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    if self.stop.is_set() || last_activity.elapsed() >= self.idle_timeout {
+                        break;
+                    }
+                    continue;
+                }
+                Err(_) => break,
+            }
+            while let Some(len) = self.handler.frame_len(&buf)? {
+                if len == 0 || len > buf.len() {
+                    return Err(Error::invalid_data("invalid frame length"));
+                }
+                response.truncate(0);
+                let request = &buf[..len];
+                self.stats.requests.fetch_add(1, Ordering::Relaxed);
+                if self
+                    .request_filter
+                    .as_ref()
+                    .map_or(true, |f| f(&self.session, request))
+                {
+                    self.handler.handle(request, &mut response, &self.session)?;
+                } else {
+                    self.stats.requests_denied.fetch_add(1, Ordering::Relaxed);
+                    self.handler.deny(request, &mut response, &self.session)?;
+                }
+                buf.drain(..len);
+                self.send(&mut client, &response)?;
+            }
+            if buf.len() > self.max_frame_size {
+                return Err(Error::invalid_data("request frame too large"));
+            }
+        }
+        Ok(())
+    }
+    fn send<T: Write>(&self, client: &mut T, response: &[u8]) -> Result<()> {
+        if !response.is_empty() {
+            client.write_all(response)?;
+            self.stats
+                .bytes_sent
+                .fetch_add(response.len() as u64, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+/// A request, forwarded by [`channel_handler()`]
+pub struct ServerRequest {
+    pub request: Vec<u8>,
+    pub session: Session,
+    reply: oneshot::Sender<Vec<u8>>,
+}
+
+impl ServerRequest {
+    /// Sends a response to the client (an empty response is not sent)
+    pub fn respond(self, response: Vec<u8>) {
+        let _r = self.reply.send(response);
+    }
+}
+
+impl DataDeliveryPolicy for ServerRequest {}
+
+/// A frame handler which forwards requests into a data channel
+pub struct ChannelHandler<F> {
+    frame_len: F,
+    tx: pchannel::Sender<ServerRequest>,
+    timeout: Duration,
+}
+
+/// Creates a frame handler which forwards requests into a data channel, so they can be
+/// processed by a worker. `frame_len` detects request frames (see [`FrameHandler::frame_len()`]).
+/// If the worker does not respond within the timeout, the client connection is closed
+pub fn channel_handler<F>(
+    frame_len: F,
+    capacity: usize,
+    timeout: Duration,
+) -> (ChannelHandler<F>, pchannel::Receiver<ServerRequest>)
+where
+    F: Fn(&[u8]) -> Result<Option<usize>> + Send + Sync + 'static,
+{
+    let (tx, rx) = pchannel::bounded(capacity);
+    (
+        ChannelHandler {
+            frame_len,
+            tx,
+            timeout,
+        },
+        rx,
+    )
+}
+
+impl<F> FrameHandler for ChannelHandler<F>
+where
+    F: Fn(&[u8]) -> Result<Option<usize>> + Send + Sync + 'static,
+{
+    fn frame_len(&self, buf: &[u8]) -> Result<Option<usize>> {
+        (self.frame_len)(buf)
+    }
+    fn handle(&self, request: &[u8], response: &mut Vec<u8>, session: &Session) -> Result<()> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.tx.send(ServerRequest {
+            request: request.to_vec(),
+            session: session.clone(),
+            reply,
+        })?;
+        let data = reply_rx.recv_timeout(self.timeout).map_err(|e| match e {
+            oneshot::RecvTimeoutError::Timeout => Error::Timeout,
+            oneshot::RecvTimeoutError::Disconnected => Error::ChannelClosed,
+        })?;
+        response.extend(data);
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+//!
+//! Typed persistent key-value store for small program data (counters, maintenance dates,
+//! calibration factors etc.).
+//!
+//! The store is kept in memory and saved into a single file. The file contains a header with a
+//! CRC32 checksum of the data and is replaced atomically (written to a temporary file which is
+//! renamed), the previous version is kept as a backup and is loaded if the main file is missing
+//! or corrupted.
+//!
+//! Values are saved either manually with [`KvStore::flush()`] or by a background thread (see
+//! [`KvStore::background_flush()`]), so writing values does not block real-time threads with disk
+//! I/O.
+//!
+//! Example:
+//!
+//! ```rust,no_run
+//! use roboplc::kv::KvStore;
+//! use std::time::Duration;
+//!
+//! let store = KvStore::open("/var/roboplc/data/kv.dat")
+//!     .unwrap()
+//!     .background_flush(Duration::from_secs(1))
+//!     .unwrap();
+//! let cycles: u64 = store.get("cycles").unwrap().unwrap_or_default();
+//! store.set("cycles", &(cycles + 1)).unwrap();
+//! ```
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+    sync::{Arc, Weak},
+    thread,
+    time::Duration,
+};
+
+use parking_lot_rt::{Condvar, Mutex};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use tracing::{error, warn};
+
+use crate::shutdown::{self, Flush};
+use crate::{Error, Result};
+
+const HEADER_MAGIC: &str = "ROBOPLC-KV";
+const FORMAT_VERSION: u32 = 1;
+
+struct Inner {
+    path: PathBuf,
+    data: Mutex<Data>,
+    changed: Condvar,
+    // serializes file writes
+    write_lock: Mutex<()>,
+}
+
+#[derive(Default)]
+struct Data {
+    values: BTreeMap<String, Value>,
+    generation: u64,
+    flushed_generation: u64,
+}
+
+/// Persistent key-value store. Can be cloned and shared between workers with no limitations
+#[derive(Clone)]
+pub struct KvStore {
+    inner: Arc<Inner>,
+}
+
+impl KvStore {
+    /// Opens the store file. If the file does not exist, an empty store is created
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_owned();
+        let values = match load(&path) {
+            Ok(Some(v)) => v,
+            Ok(None) => load(&backup_path(&path))?.unwrap_or_default(),
+            Err(error) => {
+                error!(
+                    %error,
+                    path=%path.display(),
+                    "key-value store is corrupted, trying backup"
+                );
+                load(&backup_path(&path))?
+                    .ok_or_else(|| Error::invalid_data("key-value store is corrupted"))?
+            }
+        };
+        let inner = Arc::new(Inner {
+            path,
+            data: Mutex::new(Data {
+                values,
+                ..Data::default()
+            }),
+            changed: Condvar::new(),
+            write_lock: <_>::default(),
+        });
+        // unsaved changes are flushed before the process is terminated
+        shutdown::register_flush_hook(&format!("kv:{}", inner.path.display()), &inner);
+        Ok(Self { inner })
+    }
+    /// Starts a background thread, which saves the store after changes. The delay allows to
+    /// combine multiple changes into a single write. The thread is stopped when all store
+    /// instances are dropped
+    pub fn background_flush(self, delay: Duration) -> Result<Self> {
+        let weak = Arc::downgrade(&self.inner);
+        thread::Builder::new()
+            .name("kv-flush".to_owned())
+            .spawn(move || flusher(&weak, delay))?;
+        Ok(self)
+    }
+    /// Gets a value
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        let data = self.inner.data.lock();
+        data.values
+            .get(key)
+            .map(|v| T::deserialize(v).map_err(Error::invalid_data))
+            .transpose()
+    }
+    /// Sets a value
+    pub fn set<T: Serialize>(&self, key: &str, value: &T) -> Result<()> {
+        let value = serde_json::to_value(value).map_err(Error::invalid_data)?;
+        let mut data = self.inner.data.lock();
+        if data.values.get(key) == Some(&value) {
+            return Ok(());
+        }
+        data.values.insert(key.to_owned(), value);
+        data.generation += 1;
+        self.inner.changed.notify_all();
+        Ok(())
+    }
+    /// Removes a value, returns true if the value existed
+    pub fn remove(&self, key: &str) -> bool {
+        let mut data = self.inner.data.lock();
+        if data.values.remove(key).is_some() {
+            data.generation += 1;
+            self.inner.changed.notify_all();
+            true
+        } else {
+            false
+        }
+    }
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.inner.data.lock().values.contains_key(key)
+    }
+    pub fn keys(&self) -> Vec<String> {
+        self.inner.data.lock().values.keys().cloned().collect()
+    }
+    /// Returns true if there are changes which are not saved yet
+    pub fn is_dirty(&self) -> bool {
+        let data = self.inner.data.lock();
+        data.generation != data.flushed_generation
+    }
+    /// Saves the store if changed
+    pub fn flush(&self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Flush for Inner {
+    fn flush(&self) -> Result<()> {
+        let _write_lock = self.write_lock.lock();
+        let (contents, generation) = {
+            let data = self.data.lock();
+            if data.generation == data.flushed_generation {
+                return Ok(());
+            }
+            (
+                serde_json::to_vec(&data.values).map_err(Error::invalid_data)?,
+                data.generation,
+            )
+        };
+        write(&self.path, &contents)?;
+        self.data.lock().flushed_generation = generation;
+        Ok(())
+    }
+}
+
+fn flusher(weak: &Weak<Inner>, delay: Duration) {
+    loop {
+        let Some(inner) = weak.upgrade() else {
+            break;
+        };
+        {
+            let mut data = inner.data.lock();
+            if data.generation == data.flushed_generation {
+                // wake up periodically to check if the store is dropped
+                inner.changed.wait_for(&mut data, Duration::from_secs(1));
+                continue;
+            }
+        }
+        thread::sleep(delay);
+        if let Err(error) = inner.flush() {
+            error!(%error, path=%inner.path.display(), "unable to save key-value store");
+            thread::sleep(delay.max(Duration::from_secs(1)));
+        }
+    }
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut p = path.as_os_str().to_owned();
+    p.push(".bak");
+    p.into()
+}
+
+// header: magic, version, data length, crc32 (hex)
+fn encode(contents: &[u8]) -> Vec<u8> {
+    let mut buf = format!(
+        "{} {} {} {:08x}\n",
+        HEADER_MAGIC,
+        FORMAT_VERSION,
+        contents.len(),
+        crc32(contents)
+    )
+    .into_bytes();
+    buf.extend(contents);
+    buf
+}
+
+fn decode(buf: &[u8]) -> Result<&[u8]> {
+    let invalid = || Error::invalid_data("invalid key-value store file");
+    let pos = buf.iter().position(|v| *v == b'\n').ok_or_else(invalid)?;
+    let header = std::str::from_utf8(&buf[..pos]).map_err(|_| invalid())?;
+    let contents = &buf[pos + 1..];
+    let mut sp = header.split(' ');
+    if sp.next() != Some(HEADER_MAGIC) {
+        return Err(invalid());
+    }
+    let version: u32 = sp.next().and_then(|v| v.parse().ok()).ok_or_else(invalid)?;
+    if version != FORMAT_VERSION {
+        return Err(Error::invalid_data(format!(
+            "unsupported key-value store format: {}",
+            version
+        )));
+    }
+    let len: usize = sp.next().and_then(|v| v.parse().ok()).ok_or_else(invalid)?;
+    let crc = sp
+        .next()
+        .and_then(|v| u32::from_str_radix(v, 16).ok())
+        .ok_or_else(invalid)?;
+    if contents.len() != len || crc32(contents) != crc {
+        return Err(Error::invalid_data("key-value store checksum mismatch"));
+    }
+    Ok(contents)
+}
+
+fn load(path: &Path) -> Result<Option<BTreeMap<String, Value>>> {
+    let buf = match fs::read(path) {
+        Ok(v) => v,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let contents = decode(&buf)?;
+    serde_json::from_slice(contents)
+        .map(Some)
+        .map_err(Error::invalid_data)
+}
+
+fn write(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut f = fs::File::create(&tmp)?;
+    f.write_all(&encode(contents))?;
+    f.sync_all()?;
+    drop(f);
+    // keep the previous version as a backup
+    if let Err(e) = fs::rename(path, backup_path(path)) {
+        if e.kind() != io::ErrorKind::NotFound {
+            warn!(error=%e, path=%path.display(), "unable to back up key-value store");
+        }
+    }
+    fs::rename(&tmp, path)?;
+    if let Some(dir) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        if let Ok(dir) = fs::File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+    Ok(())
+}
+
+// CRC-32 (IEEE)
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::{crc32, decode, encode};
+
+    #[test]
+    fn test_encode_decode() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        let data = br#"{"cycles":42}"#;
+        let encoded = encode(data);
+        assert_eq!(decode(&encoded).unwrap(), data);
+        let mut corrupted = encoded.clone();
+        *corrupted.last_mut().unwrap() = b']';
+        assert!(decode(&corrupted).is_err());
+        assert!(decode(&encoded[..encoded.len() - 1]).is_err());
+    }
+}
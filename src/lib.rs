@@ -17,29 +17,113 @@
 pub use metrics;
 
 pub use rtsc::buf;
-pub use rtsc::pchannel;
-pub use rtsc::pchannel_async;
-pub use rtsc::time;
 
 pub use rtsc::data_policy::{DataDeliveryPolicy, DeliveryPolicy};
 
+/// Build script helpers
+pub mod build;
+/// Linearization and calibration of analog values
+pub mod calibration;
 /// Reliable TCP/Serial communications
 pub mod comm;
 /// Controller and workers
 #[cfg(target_os = "linux")]
 pub mod controller;
+/// Cycle phase synchronization across nodes
+pub mod cycle_sync;
+/// Report-by-exception (deadband) filters for analog values
+pub mod deadband;
+/// Multi-channel analog input filtering
+pub mod dsp;
+/// Encoder and pulse-counting utilities
+pub mod encoder;
+/// Startup banner and environment report
+#[cfg(target_os = "linux")]
+pub mod environment;
+/// C ABI for in-process data exchange
+#[cfg(all(target_os = "linux", feature = "ffi"))]
+pub mod ffi;
+/// Per-deployment feature flags
+pub mod flags;
+/// Forcing of process values for commissioning
+pub mod forcing;
+/// Binary frame specifications for custom protocols
+pub mod frame;
 /// In-process data communication pub/sub hub, synchronous edition
 pub mod hub;
 /// In-process data communication pub/sub hub, asynchronous edition
 pub mod hub_async;
+/// Priority-aware executor for async hub consumers
+#[cfg(all(target_os = "linux", feature = "hub-executor"))]
+pub mod hub_executor;
+/// Persistence of undelivered critical hub messages across restarts
+#[cfg(feature = "kv")]
+pub mod hub_persistence;
 /// I/O
 pub mod io;
+/// Typed persistent key-value store
+#[cfg(feature = "kv")]
+pub mod kv;
+/// Load shedding under sustained overload
+#[cfg(target_os = "linux")]
+pub mod load_shedding;
+/// Logic tools
+pub mod logic;
+/// Manager API client for the running program (status annotations)
+#[cfg(feature = "manager-api")]
+pub mod manager;
+/// Per-worker heap allocation tracking
+pub mod memory;
+/// Motion profile generators
+pub mod motion;
+/// Outbox pattern for outbound integrations (persistent sequence ids, dedup helpers)
+#[cfg(feature = "outbox")]
+pub mod outbox;
+/// Policy-based channels
+pub mod pchannel;
+/// Async policy-based channels
+pub mod pchannel_async;
+/// Task CPU placement validation
+#[cfg(target_os = "linux")]
+pub mod placement;
+/// PLC process image (consistent input/output snapshots)
+pub mod process_image;
+/// Worker/cycle span profiling with flamegraph and Chrome trace export
+pub mod profiling;
+/// Redundant controller pairs (hot standby)
+pub mod redundancy;
+/// Safety-related helpers (watchdog heartbeats)
+pub mod safety;
+/// Time-based scheduling for non-real-time tasks
+#[cfg(all(target_os = "linux", feature = "scheduler"))]
+pub mod scheduler;
+/// Controller variables and hub messages schema export
+#[cfg(feature = "schema")]
+pub mod schema;
+/// Step sequence (recipe) executor
+pub mod sequence;
+/// Flush hooks for critical buffers, called before the process is terminated
+pub mod shutdown;
+/// Error budgets and SLO tracking for I/O subsystems
+pub mod slo;
+/// State snapshots and snapshot diffs for troubleshooting
+#[cfg(feature = "snapshot")]
+pub mod snapshot;
+/// Memory growth monitoring for soak tests
+#[cfg(all(target_os = "linux", feature = "soak"))]
+pub mod soak;
 /// Task supervisor to manage real-time threads
 #[cfg(target_os = "linux")]
 pub mod supervisor;
+/// Timing assertion helpers for integration tests
+pub mod testing;
 /// Real-time thread functions to work with [`supervisor::Supervisor`] and standalone
 #[cfg(target_os = "linux")]
 pub mod thread_rt;
+/// Time tools and periodic intervals
+pub mod time;
+/// Wall-clock reconciliation of monotonic timestamps (time anchors)
+pub mod wallclock;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -92,6 +176,18 @@ pub enum Error {
     /// Supervisor error: task with the given name is not found
     #[error("Task not found")]
     SupervisorTaskNotFound,
+    /// Controller service of the given type is not registered
+    #[error("service not registered: {0} (must be added with Controller::add_service before workers are spawned)")]
+    ServiceNotRegistered(&'static str),
+    /// Controller service of the given type is already registered
+    #[error("service already registered: {0}")]
+    ServiceAlreadyRegistered(&'static str),
+    /// Message schema of a peer or a recording is incompatible with the local one
+    #[error("schema mismatch: {0}")]
+    SchemaMismatch(String),
+    /// The value, read back after a verified write, does not match the written one
+    #[error("write verification failed: {0}")]
+    VerificationFailed(String),
     /// Invalid data receied / parameters provided
     #[error("Invalid data")]
     InvalidData(String),
@@ -204,6 +300,27 @@ pub fn suicide(delay: Duration, warn: bool) {
     };
 }
 
+/// Two-phase variant of [`suicide()`]: after the delay, calls flush hooks (see [`shutdown`]) for
+/// at most `flush_timeout`, then kills the process and its subprocesses with SIGKILL
+#[cfg(target_os = "linux")]
+pub fn suicide_with_flush(delay: Duration, flush_timeout: Duration, warn: bool) {
+    let f = move || {
+        std::thread::sleep(delay);
+        let _ = shutdown::flush_all(flush_timeout);
+        thread_rt::suicide_myself(Duration::from_secs(0), warn);
+    };
+    let mut builder = thread_rt::Builder::new().name("suicide").rt_params(
+        RTParams::new()
+            .set_priority(99)
+            .set_scheduling(Scheduling::FIFO)
+            .set_cpu_ids(&[0]),
+    );
+    builder.park_on_errors = true;
+    if builder.spawn(f).is_err() {
+        std::thread::spawn(f);
+    };
+}
+
 #[cfg(feature = "rvideo")]
 pub use rvideo;
 
@@ -247,6 +364,14 @@ fn panic(info: &PanicInfo) -> ! {
     }
 }
 
+/// Logs the startup banner: crate/build info, run mode, real-time preflight summary and CPU
+/// topology (see [`environment::EnvironmentReport`]). Should be called at the beginning of the
+/// program, after the logger is configured
+#[cfg(target_os = "linux")]
+pub fn announce() {
+    environment::EnvironmentReport::collect().log();
+}
+
 /// Returns true if started in production mode (as a systemd unit)
 pub fn is_production() -> bool {
     env::var("INVOCATION_ID").map_or(false, |v| !v.is_empty())
@@ -266,7 +391,7 @@ pub fn configure_logger(filter: LevelFilter) {
 
 pub mod prelude {
     #[cfg(target_os = "linux")]
-    pub use super::suicide;
+    pub use super::{suicide, suicide_with_flush};
     #[cfg(target_os = "linux")]
     pub use crate::controller::*;
     pub use crate::hub::prelude::*;
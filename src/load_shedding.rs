@@ -0,0 +1,171 @@
+//!
+//! Load shedding under sustained overload.
+//!
+//! Workers declare their importance classes (see
+//! [`crate::controller::WorkerOptions::worker_importance()`]). A [`LoadMonitor`] samples the
+//! system load and, if it stays above the high watermark for the sustain period, instructs
+//! workers of the lowest importance class to reduce their load. If the overload persists, the
+//! next class is shed, and so on (critical workers are never shed). When the load stays below the
+//! low watermark for the sustain period, the classes are restored one by one in the reverse order.
+//!
+//! Workers follow the instructions by checking [`crate::controller::Context::is_load_shed()`] or
+//! pausing with [`crate::controller::Context::wait_while_shed()`].
+//!
+//! ```rust,ignore
+//! let mut monitor = LoadMonitor::new(controller.state().clone()).sustain(Duration::from_secs(10));
+//! controller.spawn_task("loadmon", move || monitor.run(cpu_load()))?;
+//! ```
+use std::time::{Duration, Instant};
+
+use sysinfo::{CpuExt, System, SystemExt};
+
+use crate::controller::{Importance, State};
+
+/// Load shedding monitor
+pub struct LoadMonitor {
+    state: State,
+    high_watermark: f64,
+    low_watermark: f64,
+    sustain: Duration,
+    interval: Duration,
+    level: Option<Importance>,
+    // the time since the load is above the high / below the low watermark
+    since: Option<(bool, Instant)>,
+}
+
+impl LoadMonitor {
+    /// Creates a new monitor, which controls load shedding of the controller with the given state
+    pub fn new(state: State) -> Self {
+        Self {
+            state,
+            high_watermark: 90.0,
+            low_watermark: 70.0,
+            sustain: Duration::from_secs(5),
+            interval: Duration::from_secs(1),
+            level: None,
+            since: None,
+        }
+    }
+    /// The load (in percents), above which the system is considered as overloaded (the default is
+    /// 90)
+    pub fn high_watermark(mut self, value: f64) -> Self {
+        self.high_watermark = value;
+        self
+    }
+    /// The load (in percents), below which load shedding is lifted (the default is 70)
+    pub fn low_watermark(mut self, value: f64) -> Self {
+        self.low_watermark = value;
+        self
+    }
+    /// How long the load must stay above/below a watermark to shed/restore the next importance
+    /// class (the default is 5 seconds)
+    pub fn sustain(mut self, sustain: Duration) -> Self {
+        self.sustain = sustain;
+        self
+    }
+    /// Load sampling interval (the default is 1 second)
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+    /// The current load shedding level
+    pub fn level(&self) -> Option<Importance> {
+        self.level
+    }
+    /// Processes a load sample. Returns true if the load shedding level has been changed
+    pub fn step(&mut self, load: f64, now: Instant) -> bool {
+        let overloaded = if load >= self.high_watermark {
+            true
+        } else if load <= self.low_watermark {
+            false
+        } else {
+            self.since = None;
+            return false;
+        };
+        let since = match self.since {
+            Some((o, t)) if o == overloaded => t,
+            _ => {
+                self.since = Some((overloaded, now));
+                now
+            }
+        };
+        if now.saturating_duration_since(since) < self.sustain {
+            return false;
+        }
+        let level = if overloaded {
+            match self.level {
+                None => Some(Importance::Low),
+                Some(Importance::Low) => Some(Importance::Normal),
+                Some(_) => Some(Importance::High),
+            }
+        } else {
+            match self.level {
+                None | Some(Importance::Low) => None,
+                Some(Importance::Normal) => Some(Importance::Low),
+                Some(_) => Some(Importance::Normal),
+            }
+        };
+        // the next class is shed/restored after another sustain period
+        self.since = Some((overloaded, now));
+        if level == self.level {
+            return false;
+        }
+        self.level = level;
+        self.state.set_load_shedding(level);
+        true
+    }
+    /// Runs the monitor in the current thread while the controller is online. The source
+    /// function must return the current load in percents. Load shedding is lifted on exit
+    pub fn run<F: FnMut() -> f64>(&mut self, mut load: F) {
+        let mut interval = crate::time::interval(self.interval);
+        while self.state.is_online() {
+            interval.tick();
+            self.step(load(), Instant::now());
+        }
+        self.level = None;
+        self.state.set_load_shedding(None);
+    }
+}
+
+/// Returns a load source, which reports the global CPU usage of the system
+pub fn cpu_load() -> impl FnMut() -> f64 {
+    let mut sys = System::new();
+    sys.refresh_cpu();
+    move || {
+        sys.refresh_cpu();
+        f64::from(sys.global_cpu_info().cpu_usage())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::LoadMonitor;
+    use crate::controller::{Importance, State};
+
+    #[test]
+    fn test_load_monitor() {
+        let state = State::new();
+        let mut monitor = LoadMonitor::new(state.clone()).sustain(Duration::from_secs(2));
+        let t = Instant::now();
+        let s = Duration::from_secs(1);
+        assert!(!monitor.step(95.0, t));
+        // a short dip resets the sustain period
+        assert!(!monitor.step(80.0, t + s));
+        assert!(!monitor.step(95.0, t + s * 2));
+        assert!(!monitor.step(95.0, t + s * 3));
+        assert!(monitor.step(95.0, t + s * 4));
+        assert_eq!(state.load_shedding(), Some(Importance::Low));
+        assert!(monitor.step(95.0, t + s * 6));
+        assert!(monitor.step(95.0, t + s * 8));
+        assert!(!monitor.step(95.0, t + s * 10));
+        assert_eq!(state.load_shedding(), Some(Importance::High));
+        assert!(!monitor.step(50.0, t + s * 11));
+        assert!(monitor.step(50.0, t + s * 13));
+        assert_eq!(state.load_shedding(), Some(Importance::Normal));
+        assert!(monitor.step(50.0, t + s * 15));
+        assert!(monitor.step(50.0, t + s * 17));
+        assert_eq!(state.load_shedding(), None);
+    }
+}
@@ -0,0 +1,539 @@
+//!
+//! Tiny boolean/arithmetic expression engine for interlock equations, which can be tweaked by
+//! commissioning staff without recompiling the program.
+//!
+//! Expressions are parsed at startup into a flat operation list and evaluated with a fixed-size
+//! stack, no allocations are performed during evaluation, so it is safe to evaluate expressions in
+//! real-time threads. The expression size and the evaluation stack depth are strictly limited.
+//!
+//! All values are `f64`, booleans are represented as 1.0 (true) and 0.0 (false), any non-zero
+//! value is considered as true.
+//!
+//! Supported syntax (in the order of precedence, from the lowest):
+//!
+//! * `||`, `or`
+//! * `^`, `xor`
+//! * `&&`, `and`
+//! * `==`, `!=`, `<`, `<=`, `>`, `>=`
+//! * `+`, `-`
+//! * `*`, `/`
+//! * unary `!`, `not`, `-`
+//! * numbers, `true`, `false`, variables and parentheses
+//!
+//! Variable names may contain letters, digits, `_`, `.` and `/`, so hub topics can be used as
+//! names directly. As the result, the division operator must be separated with spaces.
+//!
+//! Example:
+//!
+//! ```rust
+//! use roboplc::logic::expr::{Program, Symbols};
+//!
+//! let mut symbols = Symbols::new();
+//! let program = Program::parse(
+//!     "# interlocks
+//!      pump.enable = level > 10 && !alarm
+//!      valve.open = pump.enable and pressure < 4.5",
+//!     &mut symbols,
+//! )
+//! .unwrap();
+//! let level = symbols.index("level").unwrap();
+//! let pressure = symbols.index("pressure").unwrap();
+//! // in the cycle
+//! symbols.set(level, 12.0);
+//! symbols.set(pressure, 3.0);
+//! program.eval(&mut symbols);
+//! assert!(symbols.get_bool(symbols.index("valve.open").unwrap()));
+//! ```
+use crate::{Error, Result};
+
+/// Maximum expression source length
+pub const MAX_SOURCE_LEN: usize = 1024;
+/// Maximum number of operations in a compiled expression
+pub const MAX_OPS: usize = 256;
+/// Maximum evaluation stack depth
+pub const MAX_STACK: usize = 32;
+/// Maximum number of symbols
+pub const MAX_SYMBOLS: usize = 1024;
+
+const MAX_NESTING: usize = 32;
+
+/// Expression variables. Symbols are registered while expressions are parsed, the values should
+/// be set by their indexes in the program cycle
+#[derive(Default, Clone, Debug)]
+pub struct Symbols {
+    names: Vec<String>,
+    values: Vec<f64>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Registers a symbol (if not registered yet) and returns its index
+    pub fn register(&mut self, name: &str) -> Result<usize> {
+        if let Some(index) = self.index(name) {
+            return Ok(index);
+        }
+        if self.names.len() >= MAX_SYMBOLS {
+            return Err(Error::invalid_data("too many symbols"));
+        }
+        self.names.push(name.to_owned());
+        self.values.push(0.0);
+        Ok(self.names.len() - 1)
+    }
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+    pub fn name(&self, index: usize) -> Option<&str> {
+        self.names.get(index).map(String::as_str)
+    }
+    /// # Panics
+    ///
+    /// Will panic if the index is out of bounds
+    pub fn get(&self, index: usize) -> f64 {
+        self.values[index]
+    }
+    /// # Panics
+    ///
+    /// Will panic if the index is out of bounds
+    #[allow(clippy::float_cmp)]
+    pub fn get_bool(&self, index: usize) -> bool {
+        self.values[index] != 0.0
+    }
+    /// # Panics
+    ///
+    /// Will panic if the index is out of bounds
+    pub fn set(&mut self, index: usize, value: f64) {
+        self.values[index] = value;
+    }
+    /// # Panics
+    ///
+    /// Will panic if the index is out of bounds
+    pub fn set_bool(&mut self, index: usize, value: bool) {
+        self.values[index] = from_bool(value);
+    }
+    /// Iterates over symbol names and values
+    pub fn iter(&self) -> impl Iterator<Item = (&str, f64)> {
+        self.names
+            .iter()
+            .map(String::as_str)
+            .zip(self.values.iter().copied())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Const(f64),
+    Var(usize),
+    Not,
+    Neg,
+    Or,
+    Xor,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+fn from_bool(v: bool) -> f64 {
+    if v {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// Compiled expression
+#[derive(Clone, Debug)]
+pub struct Expr {
+    ops: Vec<Op>,
+}
+
+impl Expr {
+    /// Parses an expression, registering variables in the symbol table
+    pub fn parse(source: &str, symbols: &mut Symbols) -> Result<Self> {
+        if source.len() > MAX_SOURCE_LEN {
+            return Err(Error::invalid_data("expression is too long"));
+        }
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            pos: 0,
+            ops: Vec::new(),
+            symbols,
+            depth: 0,
+        };
+        parser.parse_or()?;
+        if parser.pos < parser.tokens.len() {
+            return Err(Error::invalid_data(format!(
+                "unexpected token: {:?}",
+                parser.tokens[parser.pos]
+            )));
+        }
+        let ops = parser.ops;
+        if ops.len() > MAX_OPS {
+            return Err(Error::invalid_data("expression is too complex"));
+        }
+        if stack_depth(&ops) > MAX_STACK {
+            return Err(Error::invalid_data("expression is too deep"));
+        }
+        Ok(Self { ops })
+    }
+    /// Evaluates the expression
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the symbol table is not the one the expression has been parsed with
+    #[allow(clippy::float_cmp)]
+    pub fn eval(&self, symbols: &Symbols) -> f64 {
+        let mut stack = [0.0f64; MAX_STACK];
+        let mut sp = 0;
+        for op in &self.ops {
+            match *op {
+                Op::Const(v) => {
+                    stack[sp] = v;
+                    sp += 1;
+                }
+                Op::Var(i) => {
+                    stack[sp] = symbols.values[i];
+                    sp += 1;
+                }
+                Op::Not => stack[sp - 1] = from_bool(stack[sp - 1] == 0.0),
+                Op::Neg => stack[sp - 1] = -stack[sp - 1],
+                _ => {
+                    let b = stack[sp - 1];
+                    let a = stack[sp - 2];
+                    sp -= 1;
+                    stack[sp - 1] = match *op {
+                        Op::Or => from_bool(a != 0.0 || b != 0.0),
+                        Op::Xor => from_bool((a != 0.0) ^ (b != 0.0)),
+                        Op::And => from_bool(a != 0.0 && b != 0.0),
+                        Op::Eq => from_bool(a == b),
+                        Op::Ne => from_bool(a != b),
+                        Op::Lt => from_bool(a < b),
+                        Op::Le => from_bool(a <= b),
+                        Op::Gt => from_bool(a > b),
+                        Op::Ge => from_bool(a >= b),
+                        Op::Add => a + b,
+                        Op::Sub => a - b,
+                        Op::Mul => a * b,
+                        Op::Div => a / b,
+                        Op::Const(_) | Op::Var(_) | Op::Not | Op::Neg => unreachable!(),
+                    };
+                }
+            }
+        }
+        stack[0]
+    }
+    /// Evaluates the expression as boolean
+    #[allow(clippy::float_cmp)]
+    pub fn eval_bool(&self, symbols: &Symbols) -> bool {
+        self.eval(symbols) != 0.0
+    }
+}
+
+/// A list of assignments (`output = expression`), one per line. Empty lines and lines starting
+/// with `#` are ignored. Assignments are evaluated in order, so an output can be used in the
+/// following expressions
+#[derive(Clone, Debug, Default)]
+pub struct Program {
+    assignments: Vec<(usize, Expr)>,
+}
+
+impl Program {
+    pub fn parse(source: &str, symbols: &mut Symbols) -> Result<Self> {
+        let mut assignments = Vec::new();
+        for (n, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (output, expr) = line
+                .split_once('=')
+                .filter(|(_, e)| !e.starts_with('='))
+                .ok_or_else(|| {
+                    Error::invalid_data(format!("line {}: assignment expected", n + 1))
+                })?;
+            let output = output.trim();
+            if output.is_empty() || !output.chars().all(is_ident_char) {
+                return Err(Error::invalid_data(format!(
+                    "line {}: invalid output name",
+                    n + 1
+                )));
+            }
+            let expr = Expr::parse(expr, symbols)
+                .map_err(|e| Error::invalid_data(format!("line {}: {}", n + 1, e)))?;
+            assignments.push((symbols.register(output)?, expr));
+        }
+        Ok(Self { assignments })
+    }
+    /// Evaluates all assignments and stores the results in the symbol table
+    pub fn eval(&self, symbols: &mut Symbols) {
+        for (output, expr) in &self.assignments {
+            let value = expr.eval(symbols);
+            symbols.values[*output] = value;
+        }
+    }
+    /// Output symbol indexes, e.g. to publish the results
+    pub fn outputs(&self) -> impl Iterator<Item = usize> + '_ {
+        self.assignments.iter().map(|(output, _)| *output)
+    }
+}
+
+fn stack_depth(ops: &[Op]) -> usize {
+    let mut depth: usize = 0;
+    let mut max = 0;
+    for op in ops {
+        match op {
+            Op::Const(_) | Op::Var(_) => {
+                depth += 1;
+                max = max.max(depth);
+            }
+            Op::Not | Op::Neg => {}
+            _ => depth -= 1,
+        }
+    }
+    max
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '/'
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    const OPS: &[&str] = &[
+        "||", "&&", "==", "!=", "<=", ">=", "<", ">", "!", "^", "+", "-", "*", "/",
+    ];
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+    while !rest.is_empty() {
+        let c = rest.chars().next().unwrap();
+        if c == '(' {
+            tokens.push(Token::LParen);
+            rest = &rest[1..];
+        } else if c == ')' {
+            tokens.push(Token::RParen);
+            rest = &rest[1..];
+        } else if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(rest.len());
+            let value = rest[..len]
+                .parse()
+                .map_err(|_| Error::invalid_data(format!("invalid number: {}", &rest[..len])))?;
+            tokens.push(Token::Number(value));
+            rest = &rest[len..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest.find(|c| !is_ident_char(c)).unwrap_or(rest.len());
+            let ident = &rest[..len];
+            tokens.push(match ident {
+                "true" => Token::Number(1.0),
+                "false" => Token::Number(0.0),
+                "or" => Token::Op("||"),
+                "xor" => Token::Op("^"),
+                "and" => Token::Op("&&"),
+                "not" => Token::Op("!"),
+                _ => Token::Ident(ident.to_owned()),
+            });
+            rest = &rest[len..];
+        } else if let Some(op) = OPS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            return Err(Error::invalid_data(format!("unexpected character: {}", c)));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    ops: Vec<Op>,
+    symbols: &'a mut Symbols,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn peek_op(&self) -> Option<&'static str> {
+        if let Some(Token::Op(op)) = self.tokens.get(self.pos) {
+            Some(op)
+        } else {
+            None
+        }
+    }
+    fn binary(
+        &mut self,
+        table: &[(&str, Op)],
+        next: fn(&mut Self) -> Result<()>,
+        chain: bool,
+    ) -> Result<()> {
+        next(self)?;
+        while let Some(op) = self
+            .peek_op()
+            .and_then(|o| table.iter().find(|(s, _)| *s == o))
+            .map(|(_, op)| *op)
+        {
+            self.pos += 1;
+            next(self)?;
+            self.ops.push(op);
+            if !chain {
+                break;
+            }
+        }
+        Ok(())
+    }
+    fn parse_or(&mut self) -> Result<()> {
+        self.binary(&[("||", Op::Or)], Self::parse_xor, true)
+    }
+    fn parse_xor(&mut self) -> Result<()> {
+        self.binary(&[("^", Op::Xor)], Self::parse_and, true)
+    }
+    fn parse_and(&mut self) -> Result<()> {
+        self.binary(&[("&&", Op::And)], Self::parse_cmp, true)
+    }
+    fn parse_cmp(&mut self) -> Result<()> {
+        self.binary(
+            &[
+                ("==", Op::Eq),
+                ("!=", Op::Ne),
+                ("<", Op::Lt),
+                ("<=", Op::Le),
+                (">", Op::Gt),
+                (">=", Op::Ge),
+            ],
+            Self::parse_add,
+            false,
+        )
+    }
+    fn parse_add(&mut self) -> Result<()> {
+        self.binary(&[("+", Op::Add), ("-", Op::Sub)], Self::parse_mul, true)
+    }
+    fn parse_mul(&mut self) -> Result<()> {
+        self.binary(&[("*", Op::Mul), ("/", Op::Div)], Self::parse_unary, true)
+    }
+    fn parse_unary(&mut self) -> Result<()> {
+        let op = match self.peek_op() {
+            Some("!") => Op::Not,
+            Some("-") => Op::Neg,
+            _ => return self.parse_primary(),
+        };
+        self.pos += 1;
+        self.nested(Self::parse_unary)?;
+        self.ops.push(op);
+        Ok(())
+    }
+    fn parse_primary(&mut self) -> Result<()> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| Error::invalid_data("unexpected end of expression"))?;
+        self.pos += 1;
+        match token {
+            Token::Number(v) => self.ops.push(Op::Const(v)),
+            Token::Ident(name) => {
+                let index = self.symbols.register(&name)?;
+                self.ops.push(Op::Var(index));
+            }
+            Token::LParen => {
+                self.nested(Self::parse_or)?;
+                if self.tokens.get(self.pos) != Some(&Token::RParen) {
+                    return Err(Error::invalid_data("closing parenthesis expected"));
+                }
+                self.pos += 1;
+            }
+            Token::RParen | Token::Op(_) => {
+                return Err(Error::invalid_data(format!(
+                    "unexpected token: {:?}",
+                    token
+                )));
+            }
+        }
+        if self.ops.len() > MAX_OPS {
+            return Err(Error::invalid_data("expression is too complex"));
+        }
+        Ok(())
+    }
+    fn nested(&mut self, f: fn(&mut Self) -> Result<()>) -> Result<()> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING {
+            return Err(Error::invalid_data("expression nesting is too deep"));
+        }
+        f(self)?;
+        self.depth -= 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Expr, Program, Symbols};
+
+    #[test]
+    fn test_expr_eval() {
+        let mut symbols = Symbols::new();
+        let expr = Expr::parse("(a > 10 && !b) || c", &mut symbols).unwrap();
+        let (a, b, c) = (
+            symbols.index("a").unwrap(),
+            symbols.index("b").unwrap(),
+            symbols.index("c").unwrap(),
+        );
+        symbols.set(a, 11.0);
+        assert!(expr.eval_bool(&symbols));
+        symbols.set_bool(b, true);
+        assert!(!expr.eval_bool(&symbols));
+        symbols.set_bool(c, true);
+        assert!(expr.eval_bool(&symbols));
+        let expr = Expr::parse("2 + 3 * -x / 2 >= 1 xor true", &mut symbols).unwrap();
+        let x = symbols.index("x").unwrap();
+        symbols.set(x, 2.0);
+        // 2 + (3 * -2 / 2) = -1, -1 >= 1 is false, false xor true
+        assert!(expr.eval_bool(&symbols));
+    }
+
+    #[test]
+    fn test_expr_errors() {
+        let mut symbols = Symbols::new();
+        assert!(Expr::parse("a &&", &mut symbols).is_err());
+        assert!(Expr::parse("(a || b", &mut symbols).is_err());
+        assert!(Expr::parse("a b", &mut symbols).is_err());
+        assert!(Expr::parse("a $ b", &mut symbols).is_err());
+        let deep = format!("{}1{}", "(".repeat(40), ")".repeat(40));
+        assert!(Expr::parse(&deep, &mut symbols).is_err());
+        let wide = (0..40).map(|_| "1").collect::<Vec<_>>().join(" + (");
+        assert!(Expr::parse(&format!("{}{}", wide, ")".repeat(39)), &mut symbols).is_err());
+    }
+
+    #[test]
+    fn test_program() {
+        let mut symbols = Symbols::new();
+        let program = Program::parse(
+            "# comment\n\nout1 = in1 > 5\nout2 = out1 and in2 == 3",
+            &mut symbols,
+        )
+        .unwrap();
+        symbols.set(symbols.index("in1").unwrap(), 6.0);
+        symbols.set(symbols.index("in2").unwrap(), 3.0);
+        program.eval(&mut symbols);
+        assert!(symbols.get_bool(symbols.index("out2").unwrap()));
+        assert_eq!(program.outputs().count(), 2);
+        assert!(Program::parse("out == 1", &mut symbols).is_err());
+    }
+}
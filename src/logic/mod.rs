@@ -0,0 +1,4 @@
+//!
+//! Logic tools for commissioning-time configurable interlocks.
+/// Runtime-loaded boolean/arithmetic expressions
+pub mod expr;
@@ -0,0 +1,143 @@
+//!
+//! RoboPLC manager API client for the running program.
+//!
+//! Allows the program to push human-readable status annotations and progress (e.g. "homing axis
+//! 2", "batch 1234 running") to the manager. Annotations are displayed by `roboplc stat` next to
+//! the program mode.
+//!
+//! The manager passes its API URL and the program key in `ROBOPLC_MANAGER_URL` and
+//! `ROBOPLC_MANAGER_KEY` environment variables when the program is started.
+//!
+//! ```rust,no_run
+//! use roboplc::manager::Manager;
+//!
+//! let manager = Manager::from_env().unwrap();
+//! manager.set_status("homing axis 2").unwrap();
+//! manager.set_progress("batch 1234 running", 42.0).unwrap();
+//! manager.clear_status().unwrap();
+//! ```
+use std::{env, time::Duration};
+
+use serde::Serialize;
+
+use crate::{Error, Result};
+
+/// Environment variable with the manager API URL
+pub const ENV_URL: &str = "ROBOPLC_MANAGER_URL";
+/// Environment variable with the program API key
+pub const ENV_KEY: &str = "ROBOPLC_MANAGER_KEY";
+
+const API_PREFIX: &str = "/roboplc/api";
+/// Status lines longer than the limit are truncated
+pub const MAX_STATUS_LEN: usize = 256;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Manager API client. Calls are blocking, so they should not be performed from real-time
+/// workers directly
+#[derive(Clone)]
+pub struct Manager {
+    url: String,
+    key: String,
+    agent: ureq::Agent,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct StatusParams<'a> {
+    status: Option<&'a str>,
+    progress: Option<f32>,
+}
+
+impl<'a> StatusParams<'a> {
+    fn new(status: Option<&'a str>, progress: Option<f32>) -> Result<Self> {
+        if let Some(p) = progress {
+            if !(0.0..=100.0).contains(&p) {
+                return Err(Error::invalid_data(format!(
+                    "progress must be within 0..=100 ({})",
+                    p
+                )));
+            }
+        }
+        let status = status.map(|s| {
+            if s.len() > MAX_STATUS_LEN {
+                let mut end = MAX_STATUS_LEN;
+                while !s.is_char_boundary(end) {
+                    end -= 1;
+                }
+                &s[..end]
+            } else {
+                s
+            }
+        });
+        Ok(Self { status, progress })
+    }
+}
+
+impl Manager {
+    /// Creates a client from the environment variables, set by the manager
+    pub fn from_env() -> Result<Self> {
+        let url = env::var(ENV_URL).map_err(|_| {
+            Error::failed(format!("{} is not set, not started by manager?", ENV_URL))
+        })?;
+        let key = env::var(ENV_KEY).unwrap_or_default();
+        Ok(Self::new(&url, &key))
+    }
+    /// Creates a new client
+    pub fn new(url: &str, key: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_owned(),
+            key: key.to_owned(),
+            agent: agent(DEFAULT_TIMEOUT),
+        }
+    }
+    /// Sets API timeout (the default is 1 second)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.agent = agent(timeout);
+        self
+    }
+    /// Sets the status line, clears progress
+    pub fn set_status(&self, status: &str) -> Result<()> {
+        self.annotate(&StatusParams::new(Some(status), None)?)
+    }
+    /// Sets the status line and progress (percents, 0..=100)
+    pub fn set_progress(&self, status: &str, progress: f32) -> Result<()> {
+        self.annotate(&StatusParams::new(Some(status), Some(progress))?)
+    }
+    /// Clears the status line and progress
+    pub fn clear_status(&self) -> Result<()> {
+        self.annotate(&StatusParams::new(None, None)?)
+    }
+    fn annotate(&self, params: &StatusParams) -> Result<()> {
+        self.agent
+            .post(&format!("{}{}/set.program.status", self.url, API_PREFIX))
+            .set("x-auth-key", &self.key)
+            .send_json(params)
+            .map_err(|e| match e {
+                ureq::Error::Status(status, response) => {
+                    Error::API(response.into_string().unwrap_or_default(), status.into())
+                }
+                ureq::Error::Transport(e) => Error::io(e),
+            })?;
+        Ok(())
+    }
+}
+
+fn agent(timeout: Duration) -> ureq::Agent {
+    ureq::AgentBuilder::new().timeout(timeout).build()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{StatusParams, MAX_STATUS_LEN};
+
+    #[test]
+    fn test_status_params() {
+        assert!(StatusParams::new(Some("homing"), Some(101.0)).is_err());
+        assert!(StatusParams::new(Some("homing"), Some(-1.0)).is_err());
+        let params = StatusParams::new(Some("homing"), Some(50.0)).unwrap();
+        assert_eq!(params.status, Some("homing"));
+        let long = "ж".repeat(MAX_STATUS_LEN);
+        let params = StatusParams::new(Some(&long), None).unwrap();
+        assert_eq!(params.status.unwrap().len(), MAX_STATUS_LEN);
+    }
+}
@@ -0,0 +1,286 @@
+//!
+//! Per-worker heap allocation tracking. Opt-in, requires [`TrackingAllocator`] to be set as the
+//! global allocator:
+//!
+//! ```rust,no_run
+//! use roboplc::memory::TrackingAllocator;
+//!
+//! #[global_allocator]
+//! static ALLOCATOR: TrackingAllocator = TrackingAllocator::new();
+//! ```
+//!
+//! Workers spawned by the controller are attached automatically, other threads can be attached
+//! with [`attach_current_thread()`]. Allocations of threads which are not attached are accounted
+//! as "other".
+//!
+//! Deallocations are accounted to the thread which frees the memory, so live bytes of workers
+//! which pass heap data to each other are approximate (and may be even negative). The tracking is
+//! intended to detect memory creep, not to provide exact numbers.
+//!
+//! Budgets are checked by [`check_budgets()`], which should be called periodically, e.g. by a
+//! task, running [`run_reporter()`].
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    sync::{
+        atomic::{AtomicBool, AtomicIsize, AtomicU64, AtomicUsize, Ordering},
+        Mutex, RwLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use tracing::error;
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+static OTHER: Stats = Stats::new(String::new());
+
+static REGISTRY: Mutex<Vec<&'static Stats>> = Mutex::new(Vec::new());
+
+static BUDGET_ALARM: RwLock<Option<fn(&WorkerMemory)>> = RwLock::new(None);
+
+thread_local! {
+    static CURRENT: Cell<Option<&'static Stats>> = const { Cell::new(None) };
+}
+
+struct Stats {
+    name: String,
+    live: AtomicIsize,
+    allocations: AtomicU64,
+    deallocations: AtomicU64,
+    // zero if not set
+    budget: AtomicUsize,
+    exceeded: AtomicBool,
+    reported: AtomicBool,
+}
+
+impl Stats {
+    const fn new(name: String) -> Self {
+        Self {
+            name,
+            live: AtomicIsize::new(0),
+            allocations: AtomicU64::new(0),
+            deallocations: AtomicU64::new(0),
+            budget: AtomicUsize::new(0),
+            exceeded: AtomicBool::new(false),
+            reported: AtomicBool::new(false),
+        }
+    }
+    #[allow(clippy::cast_possible_wrap)]
+    fn allocated(&self, size: usize) {
+        let live = self.live.fetch_add(size as isize, Ordering::Relaxed) + size as isize;
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        let budget = self.budget.load(Ordering::Relaxed);
+        if budget > 0 && live > budget as isize {
+            self.exceeded.store(true, Ordering::Relaxed);
+        }
+    }
+    #[allow(clippy::cast_possible_wrap)]
+    fn deallocated(&self, size: usize) {
+        self.live.fetch_sub(size as isize, Ordering::Relaxed);
+        self.deallocations.fetch_add(1, Ordering::Relaxed);
+    }
+    fn snapshot(&self) -> WorkerMemory {
+        let budget = self.budget.load(Ordering::Relaxed);
+        WorkerMemory {
+            name: if self.name.is_empty() {
+                "other".to_owned()
+            } else {
+                self.name.clone()
+            },
+            live_bytes: self.live.load(Ordering::Relaxed),
+            allocations: self.allocations.load(Ordering::Relaxed),
+            deallocations: self.deallocations.load(Ordering::Relaxed),
+            budget: (budget > 0).then_some(budget),
+        }
+    }
+}
+
+fn current() -> &'static Stats {
+    CURRENT.try_with(Cell::get).ok().flatten().unwrap_or(&OTHER)
+}
+
+fn get_or_register(name: &str) -> &'static Stats {
+    let mut registry = REGISTRY.lock().unwrap();
+    if let Some(stats) = registry.iter().find(|s| s.name == name) {
+        return stats;
+    }
+    // the number of workers is limited, leaking is fine
+    let stats: &'static Stats = Box::leak(Box::new(Stats::new(name.to_owned())));
+    registry.push(stats);
+    stats
+}
+
+/// Global allocator wrapper, which attributes heap allocations to workers
+pub struct TrackingAllocator<A = System> {
+    inner: A,
+}
+
+impl TrackingAllocator<System> {
+    pub const fn new() -> Self {
+        Self { inner: System }
+    }
+}
+
+impl Default for TrackingAllocator<System> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A> TrackingAllocator<A> {
+    /// Wraps a custom allocator
+    pub const fn with_allocator(inner: A) -> Self {
+        Self { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            if !INSTALLED.load(Ordering::Relaxed) {
+                INSTALLED.store(true, Ordering::Relaxed);
+            }
+            current().allocated(layout.size());
+        }
+        ptr
+    }
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            current().allocated(layout.size());
+        }
+        ptr
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        current().deallocated(layout.size());
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            let stats = current();
+            stats.deallocated(layout.size());
+            stats.allocated(new_size);
+        }
+        new_ptr
+    }
+}
+
+/// Returns true if [`TrackingAllocator`] is set as the global allocator
+pub fn is_installed() -> bool {
+    INSTALLED.load(Ordering::Relaxed)
+}
+
+/// Attributes further allocations of the current thread to the worker with the given name. Does
+/// nothing if [`TrackingAllocator`] is not installed
+pub fn attach_current_thread(name: &str) {
+    if !is_installed() {
+        return;
+    }
+    let stats = get_or_register(name);
+    let _ = CURRENT.try_with(|c| c.set(Some(stats)));
+}
+
+/// Sets the live bytes budget for a worker. Can be set either before or after the worker is
+/// started
+pub fn set_budget(name: &str, bytes: usize) {
+    let stats = get_or_register(name);
+    stats.budget.store(bytes, Ordering::Relaxed);
+    stats.exceeded.store(false, Ordering::Relaxed);
+    stats.reported.store(false, Ordering::Relaxed);
+}
+
+/// Sets a function, which is called (in addition to logging) by [`check_budgets()`] when a worker
+/// exceeds its budget
+pub fn set_budget_alarm(f: fn(&WorkerMemory)) {
+    BUDGET_ALARM.write().unwrap().replace(f);
+}
+
+/// Worker memory usage
+#[derive(Debug, Clone)]
+pub struct WorkerMemory {
+    pub name: String,
+    /// Live heap bytes
+    pub live_bytes: isize,
+    pub allocations: u64,
+    pub deallocations: u64,
+    pub budget: Option<usize>,
+}
+
+/// Returns memory usage of all tracked workers and other threads
+pub fn stats() -> Vec<WorkerMemory> {
+    let registry = REGISTRY.lock().unwrap();
+    let mut result: Vec<WorkerMemory> = registry.iter().map(|s| s.snapshot()).collect();
+    result.push(OTHER.snapshot());
+    result
+}
+
+/// Checks worker budgets, logs and calls the alarm function (see [`set_budget_alarm()`]) once per
+/// an excess. Returns memory usage of workers, which are over budget
+#[allow(clippy::cast_possible_wrap)]
+pub fn check_budgets() -> Vec<WorkerMemory> {
+    let mut exceeded = Vec::new();
+    let mut to_report = Vec::new();
+    for stats in REGISTRY.lock().unwrap().iter() {
+        if !stats.exceeded.load(Ordering::Relaxed) {
+            continue;
+        }
+        let budget = stats.budget.load(Ordering::Relaxed);
+        if budget == 0 || stats.live.load(Ordering::Relaxed) <= budget as isize {
+            // back to normal
+            stats.exceeded.store(false, Ordering::Relaxed);
+            stats.reported.store(false, Ordering::Relaxed);
+            continue;
+        }
+        let snapshot = stats.snapshot();
+        if !stats.reported.swap(true, Ordering::Relaxed) {
+            to_report.push(snapshot.clone());
+        }
+        exceeded.push(snapshot);
+    }
+    // reported with the registry unlocked, as the alarm function may query stats
+    let alarm = *BUDGET_ALARM.read().unwrap();
+    for snapshot in to_report {
+        error!(
+            worker = %snapshot.name,
+            live_bytes = snapshot.live_bytes,
+            budget = ?snapshot.budget,
+            "worker memory budget exceeded"
+        );
+        if let Some(f) = alarm {
+            f(&snapshot);
+        }
+    }
+    exceeded
+}
+
+#[cfg(feature = "metrics")]
+#[allow(clippy::cast_precision_loss)]
+fn export_metrics(stats: &[WorkerMemory]) {
+    for s in stats {
+        metrics::gauge!("roboplc_worker_heap_live_bytes", "worker" => s.name.clone())
+            .set(s.live_bytes as f64);
+        metrics::counter!("roboplc_worker_heap_allocations", "worker" => s.name.clone())
+            .absolute(s.allocations);
+        metrics::counter!("roboplc_worker_heap_deallocations", "worker" => s.name.clone())
+            .absolute(s.deallocations);
+        if let Some(budget) = s.budget {
+            metrics::gauge!("roboplc_worker_heap_budget_bytes", "worker" => s.name.clone())
+                .set(budget as f64);
+        }
+    }
+}
+
+/// Periodically checks worker budgets and exports memory usage metrics (if `metrics` feature is
+/// enabled). Never returns, should be run in a separate non-real-time task
+pub fn run_reporter(interval: Duration) -> ! {
+    loop {
+        thread::sleep(interval);
+        check_budgets();
+        #[cfg(feature = "metrics")]
+        export_metrics(&stats());
+    }
+}
@@ -0,0 +1,585 @@
+//!
+//! Motion profile generators. Profiles produce per-cycle setpoints (position, velocity,
+//! acceleration), which are written to drives over field bus mappings.
+//!
+//! * [`Profile::trapezoidal()`] - velocity and acceleration limited point-to-point moves
+//!
+//! * [`Profile::s_curve()`] - jerk limited point-to-point moves (smooth acceleration)
+//!
+//! * [`Jog`] - velocity-controlled manual moves with acceleration ramps and optional soft limits
+//!
+//! * [`synchronize()`] and [`LinearMove`] - multi-axis moves which start and finish at the same
+//! time
+//!
+//! Profiles are computed once when created, sampling performs no allocations and can be done in
+//! real-time threads.
+//!
+//! Example:
+//!
+//! ```rust
+//! use roboplc::motion::{Limits, Profile};
+//! use std::time::Duration;
+//!
+//! let limits = Limits::new(100.0, 500.0).jerk(5000.0);
+//! let profile = Profile::s_curve(0.0, 250.0, &limits).unwrap();
+//! for setpoint in profile.setpoints(Duration::from_millis(1)) {
+//!     // write setpoint.position to the drive
+//! }
+//! ```
+use std::time::Duration;
+
+use crate::{Error, Result};
+
+// bisection steps to find the reachable peak velocity of short s-curve moves
+const PEAK_SEARCH_STEPS: usize = 64;
+
+/// Axis (or path) motion limits. Units are arbitrary but must be consistent (e.g. mm, mm/s,
+/// mm/s², mm/s³)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limits {
+    pub max_velocity: f64,
+    pub max_acceleration: f64,
+    /// Required for s-curve profiles only
+    pub max_jerk: Option<f64>,
+}
+
+impl Limits {
+    pub fn new(max_velocity: f64, max_acceleration: f64) -> Self {
+        Self {
+            max_velocity,
+            max_acceleration,
+            max_jerk: None,
+        }
+    }
+    /// Sets the jerk limit (required for s-curve profiles)
+    pub fn jerk(mut self, max_jerk: f64) -> Self {
+        self.max_jerk = Some(max_jerk);
+        self
+    }
+    fn validate(&self) -> Result<()> {
+        if !is_positive(self.max_velocity) || !is_positive(self.max_acceleration) {
+            return Err(Error::invalid_data(
+                "velocity and acceleration limits must be positive",
+            ));
+        }
+        if let Some(jerk) = self.max_jerk {
+            if !is_positive(jerk) {
+                return Err(Error::invalid_data("jerk limit must be positive"));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_positive(v: f64) -> bool {
+    v.is_finite() && v > 0.0
+}
+
+/// Motion setpoint
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Setpoint {
+    pub position: f64,
+    pub velocity: f64,
+    pub acceleration: f64,
+}
+
+// a profile segment with constant jerk
+#[derive(Clone, Copy, Debug, Default)]
+struct Segment {
+    duration: f64,
+    jerk: f64,
+    // state at the segment start, relative to the profile start, positive direction
+    acceleration: f64,
+    velocity: f64,
+    position: f64,
+}
+
+impl Segment {
+    fn sample(&self, t: f64) -> Setpoint {
+        Setpoint {
+            position: self.position
+                + self.velocity * t
+                + self.acceleration * t * t / 2.0
+                + self.jerk * t * t * t / 6.0,
+            velocity: self.velocity + self.acceleration * t + self.jerk * t * t / 2.0,
+            acceleration: self.acceleration + self.jerk * t,
+        }
+    }
+}
+
+/// Point-to-point motion profile. The axis starts and stops at rest
+#[derive(Clone, Debug)]
+pub struct Profile {
+    start: f64,
+    target: f64,
+    // 1.0 or -1.0
+    direction: f64,
+    segments: [Segment; 7],
+    len: usize,
+    // natural duration
+    duration: f64,
+    // time stretch factor (>= 1.0), see synchronize()
+    scale: f64,
+}
+
+impl Profile {
+    /// Creates a trapezoidal (velocity and acceleration limited) profile. If the distance is too
+    /// short to reach the maximum velocity, the profile is triangular
+    pub fn trapezoidal(start: f64, target: f64, limits: &Limits) -> Result<Self> {
+        limits.validate()?;
+        let distance = (target - start).abs();
+        let accel = limits.max_acceleration;
+        let mut velocity = limits.max_velocity;
+        if velocity * velocity / accel > distance {
+            velocity = (distance * accel).sqrt();
+        }
+        let t_acc = velocity / accel;
+        let t_const = if velocity > 0.0 {
+            (distance - velocity * velocity / accel) / velocity
+        } else {
+            0.0
+        };
+        Ok(Self::from_segments(
+            start,
+            target,
+            &[
+                (t_acc, accel, 0.0),
+                (t_const, 0.0, 0.0),
+                (t_acc, -accel, 0.0),
+            ],
+        ))
+    }
+    /// Creates an s-curve (jerk limited) profile. The jerk limit must be set. If the distance is
+    /// too short to reach the maximum velocity and/or acceleration, lower peak values are used
+    pub fn s_curve(start: f64, target: f64, limits: &Limits) -> Result<Self> {
+        limits.validate()?;
+        let jerk = limits
+            .max_jerk
+            .ok_or_else(|| Error::invalid_data("s-curve profiles require the jerk limit"))?;
+        let distance = (target - start).abs();
+        let accel = limits.max_acceleration;
+        // accelerating from zero to v (and decelerating back) takes v * t_acc(v) distance
+        let phase = |v: f64| -> (f64, f64) {
+            if v * jerk >= accel * accel {
+                let t_jerk = accel / jerk;
+                (t_jerk, t_jerk + v / accel)
+            } else {
+                let t_jerk = (v / jerk).sqrt();
+                (t_jerk, 2.0 * t_jerk)
+            }
+        };
+        let mut velocity = limits.max_velocity;
+        if velocity * phase(velocity).1 > distance {
+            let (mut low, mut high) = (0.0, velocity);
+            for _ in 0..PEAK_SEARCH_STEPS {
+                let mid = (low + high) / 2.0;
+                if mid * phase(mid).1 > distance {
+                    high = mid;
+                } else {
+                    low = mid;
+                }
+            }
+            velocity = low;
+        }
+        let (t_jerk, t_acc) = phase(velocity);
+        let peak_accel = jerk * t_jerk;
+        let t_const_accel = (t_acc - 2.0 * t_jerk).max(0.0);
+        let t_const = if velocity > 0.0 {
+            ((distance - velocity * t_acc) / velocity).max(0.0)
+        } else {
+            0.0
+        };
+        Ok(Self::from_segments(
+            start,
+            target,
+            &[
+                (t_jerk, 0.0, jerk),
+                (t_const_accel, peak_accel, 0.0),
+                (t_jerk, peak_accel, -jerk),
+                (t_const, 0.0, 0.0),
+                (t_jerk, 0.0, -jerk),
+                (t_const_accel, -peak_accel, 0.0),
+                (t_jerk, -peak_accel, jerk),
+            ],
+        ))
+    }
+    // segments: (duration, acceleration at start, jerk)
+    fn from_segments(start: f64, target: f64, specs: &[(f64, f64, f64)]) -> Self {
+        let mut segments = [Segment::default(); 7];
+        let mut len = 0;
+        let mut state = Setpoint::default();
+        let mut duration = 0.0;
+        for &(d, acceleration, jerk) in specs {
+            if d <= 0.0 {
+                continue;
+            }
+            let segment = Segment {
+                duration: d,
+                jerk,
+                acceleration,
+                velocity: state.velocity,
+                position: state.position,
+            };
+            state = segment.sample(d);
+            segments[len] = segment;
+            len += 1;
+            duration += d;
+        }
+        Self {
+            start,
+            target,
+            direction: if target < start { -1.0 } else { 1.0 },
+            segments,
+            len,
+            duration,
+            scale: 1.0,
+        }
+    }
+    /// Start position
+    pub fn start(&self) -> f64 {
+        self.start
+    }
+    /// Target position
+    pub fn target(&self) -> f64 {
+        self.target
+    }
+    /// Profile duration in seconds
+    pub fn duration(&self) -> f64 {
+        self.duration * self.scale
+    }
+    /// Stretches the profile in time to finish in the given duration (seconds). Velocities and
+    /// accelerations are reduced proportionally, so the limits are kept. Durations shorter than
+    /// the natural one are ignored
+    pub fn stretch(&mut self, duration: f64) {
+        self.scale = if self.duration > 0.0 && duration > self.duration {
+            duration / self.duration
+        } else {
+            1.0
+        };
+    }
+    /// Returns true if the profile is finished at the given time (seconds since the start)
+    pub fn is_finished(&self, t: f64) -> bool {
+        t >= self.duration()
+    }
+    /// Returns the setpoint at the given time (seconds since the start). The result is clamped
+    /// to the start/target positions outside of the profile time range
+    pub fn sample(&self, t: f64) -> Setpoint {
+        if t >= self.duration() {
+            return Setpoint {
+                position: self.target,
+                ..Setpoint::default()
+            };
+        }
+        let mut local = t.max(0.0) / self.scale;
+        let mut sp = Setpoint::default();
+        for segment in &self.segments[..self.len] {
+            if local <= segment.duration {
+                sp = segment.sample(local);
+                break;
+            }
+            local -= segment.duration;
+        }
+        Setpoint {
+            position: self.start + sp.position * self.direction,
+            velocity: sp.velocity * self.direction / self.scale,
+            acceleration: sp.acceleration * self.direction / (self.scale * self.scale),
+        }
+    }
+    /// Returns per-cycle setpoints, the last one is always the target position
+    pub fn setpoints(&self, cycle: Duration) -> Setpoints<'_> {
+        Setpoints {
+            profile: self,
+            cycle: cycle.as_secs_f64(),
+            n: 0,
+            finished: false,
+        }
+    }
+}
+
+/// Iterator over per-cycle profile setpoints, see [`Profile::setpoints()`]
+pub struct Setpoints<'a> {
+    profile: &'a Profile,
+    cycle: f64,
+    n: u32,
+    finished: bool,
+}
+
+impl Iterator for Setpoints<'_> {
+    type Item = Setpoint;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let t = f64::from(self.n) * self.cycle;
+        if self.profile.is_finished(t) || self.cycle <= 0.0 {
+            self.finished = true;
+        }
+        self.n += 1;
+        Some(self.profile.sample(t))
+    }
+}
+
+/// Stretches profiles of several axes to finish at the same time (the duration of the slowest
+/// one). Returns the common duration in seconds
+pub fn synchronize(profiles: &mut [Profile]) -> f64 {
+    let duration = profiles.iter().map(|p| p.duration).fold(0.0, f64::max);
+    for profile in profiles {
+        profile.stretch(duration);
+    }
+    duration
+}
+
+/// Coordinated straight-line move of several axes. The limits are applied to the path (vector)
+/// velocity/acceleration/jerk
+#[derive(Clone, Debug)]
+pub struct LinearMove {
+    start: Vec<f64>,
+    // unit direction vector
+    direction: Vec<f64>,
+    path: Profile,
+}
+
+impl LinearMove {
+    /// Creates a trapezoidal linear move
+    pub fn trapezoidal(start: &[f64], target: &[f64], limits: &Limits) -> Result<Self> {
+        Self::create(start, target, limits, Profile::trapezoidal)
+    }
+    /// Creates an s-curve linear move (the jerk limit must be set)
+    pub fn s_curve(start: &[f64], target: &[f64], limits: &Limits) -> Result<Self> {
+        Self::create(start, target, limits, Profile::s_curve)
+    }
+    fn create<F>(start: &[f64], target: &[f64], limits: &Limits, f: F) -> Result<Self>
+    where
+        F: FnOnce(f64, f64, &Limits) -> Result<Profile>,
+    {
+        if start.len() != target.len() {
+            return Err(Error::invalid_data("start and target axis count mismatch"));
+        }
+        let delta: Vec<f64> = target.iter().zip(start).map(|(t, s)| t - s).collect();
+        let length = delta.iter().map(|d| d * d).sum::<f64>().sqrt();
+        let direction = if length > 0.0 {
+            delta.iter().map(|d| d / length).collect()
+        } else {
+            vec![0.0; delta.len()]
+        };
+        Ok(Self {
+            start: start.to_vec(),
+            direction,
+            path: f(0.0, length, limits)?,
+        })
+    }
+    /// Number of axes
+    pub fn axes(&self) -> usize {
+        self.start.len()
+    }
+    /// Move duration in seconds
+    pub fn duration(&self) -> f64 {
+        self.path.duration()
+    }
+    /// The path profile
+    pub fn path(&self) -> &Profile {
+        &self.path
+    }
+    /// Writes per-axis setpoints at the given time (seconds since the start) into the output
+    /// slice, which must have the same length as the number of axes
+    pub fn sample(&self, t: f64, out: &mut [Setpoint]) -> Result<()> {
+        if out.len() != self.start.len() {
+            return Err(Error::invalid_data("output slice length mismatch"));
+        }
+        let sp = self.path.sample(t);
+        for ((o, start), dir) in out.iter_mut().zip(&self.start).zip(&self.direction) {
+            *o = Setpoint {
+                position: start + sp.position * dir,
+                velocity: sp.velocity * dir,
+                acceleration: sp.acceleration * dir,
+            };
+        }
+        Ok(())
+    }
+}
+
+/// Velocity-controlled manual move (jog). Velocity changes are ramped with the acceleration
+/// limit, if soft limits are set, the axis is decelerated to stop at them
+#[derive(Clone, Debug)]
+pub struct Jog {
+    limits: Limits,
+    soft_limits: Option<(f64, f64)>,
+    position: f64,
+    velocity: f64,
+    target_velocity: f64,
+}
+
+impl Jog {
+    /// Creates a new jog generator at the given position
+    pub fn new(position: f64, limits: &Limits) -> Result<Self> {
+        limits.validate()?;
+        Ok(Self {
+            limits: *limits,
+            soft_limits: None,
+            position,
+            velocity: 0.0,
+            target_velocity: 0.0,
+        })
+    }
+    /// Sets soft position limits (min, max)
+    pub fn soft_limits(mut self, min: f64, max: f64) -> Self {
+        self.soft_limits = Some((min, max));
+        self
+    }
+    /// Starts moving with the given velocity (negative for the reverse direction). The velocity
+    /// is clamped to the limit
+    pub fn start(&mut self, velocity: f64) {
+        let max = self.limits.max_velocity;
+        self.target_velocity = velocity.clamp(-max, max);
+    }
+    /// Decelerates to stop
+    pub fn stop(&mut self) {
+        self.target_velocity = 0.0;
+    }
+    /// Stops immediately (e.g. on emergency), the current position is kept
+    pub fn halt(&mut self) {
+        self.target_velocity = 0.0;
+        self.velocity = 0.0;
+    }
+    /// Resets the position (e.g. after homing). The axis must be stopped
+    pub fn set_position(&mut self, position: f64) {
+        self.position = position;
+    }
+    pub fn position(&self) -> f64 {
+        self.position
+    }
+    pub fn velocity(&self) -> f64 {
+        self.velocity
+    }
+    /// Returns true if the axis is moving or a move is requested
+    #[allow(clippy::float_cmp)]
+    pub fn is_moving(&self) -> bool {
+        self.velocity != 0.0 || self.target_velocity != 0.0
+    }
+    /// Calculates the next setpoint, must be called every cycle
+    pub fn step(&mut self, cycle: Duration) -> Setpoint {
+        let dt = cycle.as_secs_f64();
+        let accel = self.limits.max_acceleration;
+        let mut target = self.target_velocity;
+        if let Some((min, max)) = self.soft_limits {
+            // the distance required to stop from the current velocity
+            let braking = self.velocity * self.velocity / (2.0 * accel);
+            if (self.velocity > 0.0 && self.position + braking >= max)
+                || (self.velocity < 0.0 && self.position - braking <= min)
+                || (target > 0.0 && self.position >= max)
+                || (target < 0.0 && self.position <= min)
+            {
+                target = 0.0;
+            }
+        }
+        let prev_velocity = self.velocity;
+        let dv = (target - self.velocity).clamp(-accel * dt, accel * dt);
+        self.velocity += dv;
+        self.position += (prev_velocity + self.velocity) / 2.0 * dt;
+        if let Some((min, max)) = self.soft_limits {
+            if self.position > max || self.position < min {
+                self.position = self.position.clamp(min, max);
+                self.velocity = 0.0;
+            }
+        }
+        Setpoint {
+            position: self.position,
+            velocity: self.velocity,
+            acceleration: if dt > 0.0 { dv / dt } else { 0.0 },
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::float_cmp)]
+mod test {
+    use super::{synchronize, Jog, Limits, LinearMove, Profile, Setpoint};
+    use std::time::Duration;
+
+    const EPS: f64 = 1e-6;
+
+    fn check_limits(profile: &Profile, limits: &Limits) {
+        let mut prev = profile.sample(0.0);
+        let mut t = 0.0;
+        while t <= profile.duration() {
+            let sp = profile.sample(t);
+            assert!(sp.velocity.abs() <= limits.max_velocity + EPS);
+            assert!(sp.acceleration.abs() <= limits.max_acceleration + EPS);
+            // continuous position
+            assert!((sp.position - prev.position).abs() <= limits.max_velocity * 1e-3 + EPS);
+            prev = sp;
+            t += 1e-3;
+        }
+    }
+
+    #[test]
+    fn test_trapezoidal() {
+        let limits = Limits::new(100.0, 500.0);
+        let profile = Profile::trapezoidal(10.0, 260.0, &limits).unwrap();
+        // 0.2s accel, 2.3s constant, 0.2s decel
+        assert!((profile.duration() - 2.7).abs() < EPS);
+        assert!((profile.sample(1.0).velocity - 100.0).abs() < EPS);
+        assert!((profile.sample(0.1).acceleration - 500.0).abs() < EPS);
+        assert_eq!(profile.sample(3.0).position, 260.0);
+        check_limits(&profile, &limits);
+        // triangular, reverse
+        let profile = Profile::trapezoidal(0.0, -5.0, &limits).unwrap();
+        assert!((profile.duration() - 0.2).abs() < EPS);
+        assert!((profile.sample(0.1).position + 2.5).abs() < EPS);
+        assert!((profile.sample(0.1).velocity + 50.0).abs() < EPS);
+        let last = profile.setpoints(Duration::from_millis(3)).last().unwrap();
+        assert_eq!(last.position, -5.0);
+        assert!(Profile::trapezoidal(0.0, 1.0, &Limits::new(0.0, 1.0)).is_err());
+    }
+
+    #[test]
+    fn test_s_curve() {
+        let limits = Limits::new(100.0, 500.0).jerk(5000.0);
+        for distance in [0.01, 1.0, 10.0, 250.0] {
+            let profile = Profile::s_curve(0.0, distance, &limits).unwrap();
+            let end = profile.sample(profile.duration() - 1e-9);
+            assert!((end.position - distance).abs() < 1e-4, "{}", distance);
+            assert!(end.velocity.abs() < 1e-3);
+            check_limits(&profile, &limits);
+        }
+        assert!(Profile::s_curve(0.0, 1.0, &Limits::new(1.0, 1.0)).is_err());
+    }
+
+    #[test]
+    fn test_multi_axis() {
+        let limits = Limits::new(100.0, 500.0);
+        let mut profiles = [
+            Profile::trapezoidal(0.0, 250.0, &limits).unwrap(),
+            Profile::trapezoidal(0.0, 50.0, &limits).unwrap(),
+        ];
+        let duration = synchronize(&mut profiles);
+        assert!((profiles[1].duration() - duration).abs() < EPS);
+        check_limits(&profiles[1], &limits);
+        let m = LinearMove::trapezoidal(&[0.0, 0.0], &[30.0, 40.0], &limits).unwrap();
+        let mut out = [Setpoint::default(); 2];
+        m.sample(m.duration() / 2.0, &mut out).unwrap();
+        assert!((out[0].position - 15.0).abs() < EPS);
+        assert!((out[1].position - 20.0).abs() < EPS);
+    }
+
+    #[test]
+    fn test_jog() {
+        let limits = Limits::new(10.0, 100.0);
+        let mut jog = Jog::new(0.0, &limits).unwrap().soft_limits(-5.0, 5.0);
+        let cycle = Duration::from_millis(1);
+        jog.start(20.0);
+        for _ in 0..50 {
+            jog.step(cycle);
+        }
+        assert!((jog.velocity() - 5.0).abs() < EPS);
+        for _ in 0..2000 {
+            jog.step(cycle);
+        }
+        assert!(jog.position() <= 5.0);
+        assert!(jog.position() > 4.9);
+        assert_eq!(jog.velocity(), 0.0);
+        jog.start(-10.0);
+        jog.step(cycle);
+        assert!(jog.velocity() < 0.0);
+    }
+}
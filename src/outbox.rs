@@ -0,0 +1,338 @@
+//!
+//! Outbox pattern for outbound integrations (MQTT, EAPI pushes, cloud uploads).
+//!
+//! Every message gets a persistent monotonically increasing sequence id and is appended to a
+//! spool file before it is sent (write-ahead), so messages survive uplink outages and program
+//! restarts. The uplink worker sends pending messages in order and acknowledges them after the
+//! consumer confirms the delivery, unacknowledged messages are sent again after reconnects and
+//! restarts. This gives at-least-once delivery, duplicates are dropped on the consumer side with
+//! [`Deduplicator`], which makes the delivery effectively exactly-once.
+//!
+//! The spool keeps unacknowledged messages only, acknowledged ones are compacted away. Pending
+//! messages are also kept in memory, so the outbox is designed for outages of hours rather than
+//! months.
+//!
+//! ```rust,no_run
+//! use roboplc::outbox::{Deduplicator, Outbox};
+//!
+//! let outbox: Outbox<f64> = Outbox::open("/var/roboplc/data/outbox/telemetry").unwrap();
+//! // producer
+//! outbox.push(21.5).unwrap();
+//! // uplink worker
+//! for envelope in outbox.pending(100) {
+//!     // send envelope.seq and envelope.message, stop on errors
+//!     outbox.ack(envelope.seq).unwrap();
+//! }
+//! // consumer
+//! let mut dedup = Deduplicator::new();
+//! assert!(dedup.accept("plc1.telemetry", 1));
+//! assert!(!dedup.accept("plc1.telemetry", 1));
+//! ```
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write as _},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use parking_lot_rt::Mutex;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{Error, Result};
+
+const SPOOL_FILE: &str = "spool.jsonl";
+const STATE_FILE: &str = "state.json";
+// the spool is rewritten when it contains more acknowledged entries
+const COMPACT_ENTRIES: usize = 10_000;
+
+/// An outbox message with its sequence id
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Envelope<T> {
+    pub seq: u64,
+    pub message: T,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct SavedState {
+    acked: u64,
+}
+
+struct State<T> {
+    spool: File,
+    pending: VecDeque<Envelope<T>>,
+    last_seq: u64,
+    acked: u64,
+    // acknowledged entries, which are still in the spool file
+    spooled_acked: usize,
+}
+
+struct Inner<T> {
+    dir: PathBuf,
+    sync: bool,
+    state: Mutex<State<T>>,
+}
+
+/// Persistent outbox. Can be cloned and shared between workers with no limitations
+pub struct Outbox<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Clone for Outbox<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Outbox<T>
+where
+    T: Serialize + DeserializeOwned + Clone,
+{
+    /// Opens an outbox in the directory (created if missing). Messages, which have not been
+    /// acknowledged by the previous process instance, are restored. A broken last spool entry
+    /// (e.g. after a power loss) is dropped
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref().to_owned();
+        fs::create_dir_all(&dir)?;
+        let saved: SavedState = match fs::read(dir.join(STATE_FILE)) {
+            Ok(data) => serde_json::from_slice(&data).map_err(Error::invalid_data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => SavedState::default(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut pending = VecDeque::new();
+        let mut last_seq = saved.acked;
+        let mut broken = false;
+        match File::open(dir.join(SPOOL_FILE)) {
+            Ok(f) => {
+                for line in BufReader::new(f).lines() {
+                    let Ok(envelope) = serde_json::from_str::<Envelope<T>>(&line?) else {
+                        warn!(dir = %dir.display(), "broken outbox spool entry dropped");
+                        broken = true;
+                        break;
+                    };
+                    last_seq = last_seq.max(envelope.seq);
+                    if envelope.seq > saved.acked {
+                        pending.push_back(envelope);
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        let spool = write_spool(&dir, &pending)?;
+        if broken {
+            warn!(dir = %dir.display(), pending = pending.len(), "outbox spool repaired");
+        }
+        Ok(Self {
+            inner: Arc::new(Inner {
+                dir,
+                sync: false,
+                state: Mutex::new(State {
+                    spool,
+                    pending,
+                    last_seq,
+                    acked: saved.acked,
+                    spooled_acked: 0,
+                }),
+            }),
+        })
+    }
+    /// Syncs the spool file to the disk after each message (the default is false). Must be called
+    /// before the outbox is cloned
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the outbox is already cloned
+    pub fn sync(mut self, sync: bool) -> Self {
+        Arc::get_mut(&mut self.inner)
+            .expect("the outbox is already cloned")
+            .sync = sync;
+        self
+    }
+    /// Adds a message to the outbox. Returns the message sequence id
+    pub fn push(&self, message: T) -> Result<u64> {
+        let mut state = self.inner.state.lock();
+        let envelope = Envelope {
+            seq: state.last_seq + 1,
+            message,
+        };
+        let mut line = serde_json::to_vec(&envelope).map_err(Error::invalid_data)?;
+        line.push(b'\n');
+        state.spool.write_all(&line)?;
+        if self.inner.sync {
+            state.spool.sync_data()?;
+        }
+        state.last_seq = envelope.seq;
+        state.pending.push_back(envelope);
+        Ok(state.last_seq)
+    }
+    /// Returns up to `max` first pending (not acknowledged) messages in order
+    pub fn pending(&self, max: usize) -> Vec<Envelope<T>> {
+        self.inner
+            .state
+            .lock()
+            .pending
+            .iter()
+            .take(max)
+            .cloned()
+            .collect()
+    }
+    /// Acknowledges all messages up to the sequence id (inclusive). Returns the number of
+    /// messages acknowledged
+    pub fn ack(&self, seq: u64) -> Result<usize> {
+        let mut state = self.inner.state.lock();
+        if seq <= state.acked {
+            return Ok(0);
+        }
+        if seq > state.last_seq {
+            return Err(Error::invalid_data(format!(
+                "outbox sequence id {} has not been issued yet",
+                seq
+            )));
+        }
+        write_atomic(
+            &self.inner.dir.join(STATE_FILE),
+            &serde_json::to_vec(&SavedState { acked: seq }).map_err(Error::invalid_data)?,
+        )?;
+        state.acked = seq;
+        let before = state.pending.len();
+        while state.pending.front().map_or(false, |e| e.seq <= seq) {
+            state.pending.pop_front();
+        }
+        let acked = before - state.pending.len();
+        state.spooled_acked += acked;
+        if state.pending.is_empty() {
+            state.spool.set_len(0)?;
+            state.spooled_acked = 0;
+        } else if state.spooled_acked >= COMPACT_ENTRIES {
+            state.spool = write_spool(&self.inner.dir, &state.pending)?;
+            state.spooled_acked = 0;
+        }
+        Ok(acked)
+    }
+    /// The number of pending messages
+    pub fn len(&self) -> usize {
+        self.inner.state.lock().pending.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// The sequence id of the last pushed message
+    pub fn last_seq(&self) -> u64 {
+        self.inner.state.lock().last_seq
+    }
+    /// The sequence id of the last acknowledged message
+    pub fn acked_seq(&self) -> u64 {
+        self.inner.state.lock().acked
+    }
+}
+
+// rewrites the spool with the pending entries and returns the file, opened for appending
+fn write_spool<T: Serialize>(dir: &Path, pending: &VecDeque<Envelope<T>>) -> Result<File> {
+    let path = dir.join(SPOOL_FILE);
+    let tmp = path.with_extension("tmp");
+    {
+        let mut f = BufWriter::new(File::create(&tmp)?);
+        for envelope in pending {
+            serde_json::to_writer(&mut f, envelope).map_err(Error::invalid_data)?;
+            f.write_all(b"\n")?;
+        }
+        f.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    }
+    fs::rename(&tmp, &path)?;
+    Ok(OpenOptions::new().append(true).open(path)?)
+}
+
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    {
+        let mut f = File::create(&tmp)?;
+        f.write_all(data)?;
+        f.sync_all()?;
+    }
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Consumer-side deduplication by sequence ids of [`Outbox`] messages, tracked per source (e.g.
+/// a program or an outbox name). The state is serializable and should be persisted together
+/// with the processed data (e.g. in the same transaction or in [`crate::kv::KvStore`])
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Deduplicator {
+    last: BTreeMap<String, u64>,
+}
+
+impl Deduplicator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Returns true if the message must be processed, false for duplicates (sequence ids which
+    /// are not greater than the last accepted one)
+    pub fn accept(&mut self, source: &str, seq: u64) -> bool {
+        if let Some(last) = self.last.get_mut(source) {
+            if seq <= *last {
+                return false;
+            }
+            *last = seq;
+        } else {
+            self.last.insert(source.to_owned(), seq);
+        }
+        true
+    }
+    /// The last accepted sequence id of the source, which should be reported to the producer as
+    /// acknowledged
+    pub fn last_seq(&self, source: &str) -> Option<u64> {
+        self.last.get(source).copied()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Deduplicator, Envelope, Outbox, SPOOL_FILE};
+    use std::io::Write as _;
+
+    #[test]
+    fn test_outbox() {
+        let dir = std::env::temp_dir().join(format!("roboplc-test-outbox-{}", std::process::id()));
+        {
+            let outbox: Outbox<String> = Outbox::open(&dir).unwrap();
+            for n in 1..=3 {
+                assert_eq!(outbox.push(format!("m{}", n)).unwrap(), n);
+            }
+            assert_eq!(outbox.ack(1).unwrap(), 1);
+            assert!(outbox.ack(10).is_err());
+        }
+        // a broken entry, written during a power loss
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.join(SPOOL_FILE))
+            .unwrap()
+            .write_all(b"{\"seq\":4,\"mess")
+            .unwrap();
+        let outbox: Outbox<String> = Outbox::open(&dir).unwrap();
+        assert_eq!(
+            outbox.pending(1),
+            [Envelope {
+                seq: 2,
+                message: "m2".to_owned()
+            }]
+        );
+        assert_eq!(outbox.push("m4".to_owned()).unwrap(), 4);
+        assert_eq!(outbox.ack(4).unwrap(), 3);
+        assert!(outbox.is_empty());
+        drop(outbox);
+        let outbox: Outbox<String> = Outbox::open(&dir).unwrap();
+        assert!(outbox.is_empty());
+        assert_eq!(outbox.push("m5".to_owned()).unwrap(), 5);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let mut dedup = Deduplicator::new();
+        assert!(dedup.accept("a", 1));
+        assert!(dedup.accept("a", 3));
+        assert!(!dedup.accept("a", 2));
+        assert!(dedup.accept("b", 1));
+        assert_eq!(dedup.last_seq("a"), Some(3));
+    }
+}
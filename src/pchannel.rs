@@ -0,0 +1,605 @@
+//!
+//! Policy-based channels. Extends [`rtsc::pchannel`] with an optional fairness mode: with many
+//! producers, a fast sender may starve others when the channel frees a single slot. Fair senders
+//! (see [`bounded_fair()`], [`ordered_fair()`]) are granted the channel in round-robin order by
+//! client id, senders of the same client are served in FIFO order.
+//!
+//! The fairness mode applies to blocking sends only, [`FairSender::try_send()`] bypasses the
+//! queue.
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::ops::Bound;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+use parking_lot_rt::{Condvar, Mutex, MutexGuard};
+
+use crate::{DataDeliveryPolicy, DeliveryPolicy, Error, Result};
+
+pub use rtsc::pchannel::*;
+
+/// Creates a bounded channel with a fair sender
+pub fn bounded_fair<T: DataDeliveryPolicy>(capacity: usize) -> (FairSender<T>, Receiver<T>) {
+    let (tx, rx) = bounded(capacity);
+    (tx.into(), rx)
+}
+
+/// Creates an ordered channel with a fair sender
+pub fn ordered_fair<T: DataDeliveryPolicy>(capacity: usize) -> (FairSender<T>, Receiver<T>) {
+    let (tx, rx) = ordered(capacity);
+    (tx.into(), rx)
+}
+
+/// A sender with fair (round-robin) access to the channel. Each clone gets its own client id
+pub struct FairSender<T>
+where
+    T: DataDeliveryPolicy,
+{
+    tx: Sender<T>,
+    turnstile: Arc<Turnstile>,
+    client_id: u64,
+}
+
+impl<T> From<Sender<T>> for FairSender<T>
+where
+    T: DataDeliveryPolicy,
+{
+    fn from(tx: Sender<T>) -> Self {
+        let turnstile = Arc::new(Turnstile::new());
+        Self {
+            tx,
+            client_id: turnstile.register_client(),
+            turnstile,
+        }
+    }
+}
+
+impl<T> Clone for FairSender<T>
+where
+    T: DataDeliveryPolicy,
+{
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            turnstile: self.turnstile.clone(),
+            client_id: self.turnstile.register_client(),
+        }
+    }
+}
+
+impl<T> FairSender<T>
+where
+    T: DataDeliveryPolicy,
+{
+    /// Sends a value, waits for own turn if other senders are blocked
+    pub fn send(&self, value: T) -> Result<()> {
+        let _permit = self.turnstile.acquire(self.client_id);
+        self.tx.send(value).map_err(Into::into)
+    }
+    /// Tries to send a value immediately, the fairness queue is bypassed
+    pub fn try_send(&self, value: T) -> Result<()> {
+        self.tx.try_send(value).map_err(Into::into)
+    }
+    /// The sender client id
+    pub fn client_id(&self) -> u64 {
+        self.client_id
+    }
+    /// The underlying sender
+    pub fn sender(&self) -> &Sender<T> {
+        &self.tx
+    }
+}
+
+#[derive(Default)]
+struct TurnstileState {
+    active: bool,
+    last_client: Option<u64>,
+    next_ticket: u64,
+    // client id -> waiting tickets (FIFO)
+    waiting: BTreeMap<u64, VecDeque<u64>>,
+    // async waiters
+    wakers: BTreeMap<u64, Waker>,
+}
+
+impl TurnstileState {
+    // the ticket of the next client in round-robin order
+    fn next_turn(&self) -> Option<u64> {
+        let next = self.last_client.map_or_else(
+            || self.waiting.iter().next(),
+            |last| {
+                self.waiting
+                    .range((Bound::Excluded(last), Bound::Unbounded))
+                    .next()
+                    .or_else(|| self.waiting.iter().next())
+            },
+        );
+        next.and_then(|(_, tickets)| tickets.front().copied())
+    }
+    fn enqueue(&mut self, client: u64) -> u64 {
+        let ticket = self.next_ticket;
+        self.next_ticket = self.next_ticket.wrapping_add(1);
+        self.waiting.entry(client).or_default().push_back(ticket);
+        ticket
+    }
+    fn remove(&mut self, client: u64, ticket: u64) {
+        if let Some(tickets) = self.waiting.get_mut(&client) {
+            tickets.retain(|t| *t != ticket);
+            if tickets.is_empty() {
+                self.waiting.remove(&client);
+            }
+        }
+        self.wakers.remove(&ticket);
+    }
+    fn try_grant(&mut self, client: u64, ticket: u64) -> bool {
+        if !self.active && self.next_turn() == Some(ticket) {
+            self.remove(client, ticket);
+            self.active = true;
+            self.last_client = Some(client);
+            true
+        } else {
+            false
+        }
+    }
+    fn next_waker(&self) -> Option<Waker> {
+        self.next_turn()
+            .and_then(|ticket| self.wakers.get(&ticket).cloned())
+    }
+}
+
+/// Grants channel access to senders one by one in round-robin order by client id
+pub(crate) struct Turnstile {
+    state: Mutex<TurnstileState>,
+    cv: Condvar,
+    next_client: AtomicU64,
+}
+
+impl Turnstile {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: <_>::default(),
+            cv: Condvar::new(),
+            next_client: AtomicU64::new(0),
+        }
+    }
+    pub(crate) fn register_client(&self) -> u64 {
+        self.next_client.fetch_add(1, Ordering::Relaxed)
+    }
+    pub(crate) fn acquire(self: &Arc<Self>, client: u64) -> TurnstilePermit {
+        let mut state = self.state.lock();
+        let ticket = state.enqueue(client);
+        while !state.try_grant(client, ticket) {
+            self.cv.wait(&mut state);
+        }
+        TurnstilePermit {
+            turnstile: self.clone(),
+        }
+    }
+    pub(crate) fn acquire_async(self: &Arc<Self>, client: u64) -> TurnstileAcquire {
+        TurnstileAcquire {
+            turnstile: self.clone(),
+            client,
+            ticket: None,
+            granted: false,
+        }
+    }
+    fn release(&self) {
+        let mut state = self.state.lock();
+        state.active = false;
+        let waker = state.next_waker();
+        drop(state);
+        self.cv.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Channel access permit, released on drop
+pub(crate) struct TurnstilePermit {
+    turnstile: Arc<Turnstile>,
+}
+
+impl Drop for TurnstilePermit {
+    fn drop(&mut self) {
+        self.turnstile.release();
+    }
+}
+
+/// Async channel access request
+pub(crate) struct TurnstileAcquire {
+    turnstile: Arc<Turnstile>,
+    client: u64,
+    ticket: Option<u64>,
+    granted: bool,
+}
+
+impl Future for TurnstileAcquire {
+    type Output = TurnstilePermit;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut state = this.turnstile.state.lock();
+        let ticket = if let Some(ticket) = this.ticket {
+            ticket
+        } else {
+            let ticket = state.enqueue(this.client);
+            this.ticket = Some(ticket);
+            ticket
+        };
+        if state.try_grant(this.client, ticket) {
+            this.granted = true;
+            Poll::Ready(TurnstilePermit {
+                turnstile: this.turnstile.clone(),
+            })
+        } else {
+            state.wakers.insert(ticket, cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for TurnstileAcquire {
+    fn drop(&mut self) {
+        if self.granted {
+            return;
+        }
+        // cancelled, pass the turn to the next waiter if required
+        if let Some(ticket) = self.ticket {
+            let mut state = self.turnstile.state.lock();
+            state.remove(self.client, ticket);
+            let waker = if state.active {
+                None
+            } else {
+                state.next_waker()
+            };
+            drop(state);
+            self.turnstile.cv.notify_all();
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// Creates a rendezvous (zero-capacity) channel: a sender is blocked until a receiver takes the
+/// value. Senders are served one by one.
+///
+/// Delivery policies: values with [`DeliveryPolicy::Optional`] and
+/// [`DeliveryPolicy::SingleOptional`] are skipped (`Error::ChannelSkipped`) if no receiver is
+/// waiting at the moment, expired values are dropped by the receiver (the sender is released).
+/// As no more than one value is in flight, the rest of policies work the same way as
+/// [`DeliveryPolicy::Always`].
+pub fn rendezvous<T: DataDeliveryPolicy>() -> (RendezvousSender<T>, RendezvousReceiver<T>) {
+    let channel = Arc::new(RendezvousChannel::new());
+    (
+        RendezvousSender {
+            channel: channel.clone(),
+        },
+        RendezvousReceiver { channel },
+    )
+}
+
+pub(crate) enum Step<R> {
+    Ready(R),
+    Wait,
+}
+
+pub(crate) struct RendezvousState<T> {
+    slot: Option<T>,
+    put_seq: u64,
+    taken_seq: u64,
+    senders: usize,
+    receivers: usize,
+    pub(crate) waiting_receivers: usize,
+    // async waiters
+    pub(crate) wakers: Vec<Waker>,
+}
+
+pub(crate) struct RendezvousChannel<T> {
+    pub(crate) state: Mutex<RendezvousState<T>>,
+    cv: Condvar,
+}
+
+fn is_optional<T: DataDeliveryPolicy>(value: &T) -> bool {
+    matches!(
+        value.delivery_policy(),
+        DeliveryPolicy::Optional | DeliveryPolicy::SingleOptional
+    )
+}
+
+impl<T: DataDeliveryPolicy> RendezvousChannel<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: Mutex::new(RendezvousState {
+                slot: None,
+                put_seq: 0,
+                taken_seq: 0,
+                senders: 1,
+                receivers: 1,
+                waiting_receivers: 0,
+                wakers: Vec::new(),
+            }),
+            cv: Condvar::new(),
+        }
+    }
+    pub(crate) fn notify(&self, state: &mut RendezvousState<T>) {
+        self.cv.notify_all();
+        for waker in state.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+    pub(crate) fn wait(&self, state: &mut MutexGuard<RendezvousState<T>>) {
+        self.cv.wait(state);
+    }
+    // puts the value into the free slot, returns its sequence number
+    pub(crate) fn start_send(
+        &self,
+        state: &mut RendezvousState<T>,
+        value: &mut Option<T>,
+    ) -> Step<Result<u64>> {
+        if state.receivers == 0 {
+            return Step::Ready(Err(Error::ChannelClosed));
+        }
+        if value.as_ref().map_or(false, is_optional) && state.waiting_receivers == 0 {
+            return Step::Ready(Err(Error::ChannelSkipped));
+        }
+        if state.slot.is_some() {
+            return Step::Wait;
+        }
+        state.slot = value.take();
+        state.put_seq += 1;
+        self.notify(state);
+        Step::Ready(Ok(state.put_seq))
+    }
+    // waits until the value with the sequence number is taken
+    pub(crate) fn finish_send(&self, state: &mut RendezvousState<T>, seq: u64) -> Step<Result<()>> {
+        if state.taken_seq >= seq {
+            Step::Ready(Ok(()))
+        } else if state.receivers == 0 {
+            self.cancel_send(state, seq);
+            Step::Ready(Err(Error::ChannelClosed))
+        } else {
+            Step::Wait
+        }
+    }
+    // removes the value if it has not been taken yet
+    pub(crate) fn cancel_send(&self, state: &mut RendezvousState<T>, seq: u64) {
+        if state.put_seq == seq && state.taken_seq < seq && state.slot.take().is_some() {
+            self.notify(state);
+        }
+    }
+    pub(crate) fn try_take(&self, state: &mut RendezvousState<T>) -> Step<Result<T>> {
+        if let Some(value) = state.slot.take() {
+            state.taken_seq = state.put_seq;
+            self.notify(state);
+            if !value.is_expired() {
+                return Step::Ready(Ok(value));
+            }
+        }
+        if state.senders == 0 {
+            Step::Ready(Err(Error::ChannelClosed))
+        } else {
+            Step::Wait
+        }
+    }
+    pub(crate) fn try_send(&self, value: T) -> Result<()> {
+        let mut state = self.state.lock();
+        if state.receivers == 0 {
+            return Err(Error::ChannelClosed);
+        }
+        if state.waiting_receivers == 0 || state.slot.is_some() {
+            return Err(if is_optional(&value) {
+                Error::ChannelSkipped
+            } else {
+                Error::ChannelFull
+            });
+        }
+        let mut value = Some(value);
+        match self.start_send(&mut state, &mut value) {
+            Step::Ready(result) => result.map(|_| ()),
+            Step::Wait => Err(Error::ChannelFull),
+        }
+    }
+    pub(crate) fn try_recv(&self) -> Result<T> {
+        let mut state = self.state.lock();
+        match self.try_take(&mut state) {
+            Step::Ready(result) => result,
+            Step::Wait => Err(Error::ChannelEmpty),
+        }
+    }
+    pub(crate) fn add_sender(&self) {
+        self.state.lock().senders += 1;
+    }
+    pub(crate) fn add_receiver(&self) {
+        self.state.lock().receivers += 1;
+    }
+    pub(crate) fn remove_sender(&self) {
+        let mut state = self.state.lock();
+        state.senders -= 1;
+        self.notify(&mut state);
+    }
+    pub(crate) fn remove_receiver(&self) {
+        let mut state = self.state.lock();
+        state.receivers -= 1;
+        self.notify(&mut state);
+    }
+}
+
+/// Rendezvous channel sender
+pub struct RendezvousSender<T: DataDeliveryPolicy> {
+    channel: Arc<RendezvousChannel<T>>,
+}
+
+impl<T: DataDeliveryPolicy> RendezvousSender<T> {
+    /// Sends a value and waits until a receiver takes it
+    pub fn send(&self, value: T) -> Result<()> {
+        let channel = &self.channel;
+        let mut state = channel.state.lock();
+        let mut value = Some(value);
+        let seq = loop {
+            match channel.start_send(&mut state, &mut value) {
+                Step::Ready(result) => break result?,
+                Step::Wait => channel.wait(&mut state),
+            }
+        };
+        loop {
+            match channel.finish_send(&mut state, seq) {
+                Step::Ready(result) => return result,
+                Step::Wait => channel.wait(&mut state),
+            }
+        }
+    }
+    /// Sends a value only if a receiver is waiting at the moment, does not wait until the value
+    /// is taken
+    pub fn try_send(&self, value: T) -> Result<()> {
+        self.channel.try_send(value)
+    }
+}
+
+impl<T: DataDeliveryPolicy> Clone for RendezvousSender<T> {
+    fn clone(&self) -> Self {
+        self.channel.add_sender();
+        Self {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<T: DataDeliveryPolicy> Drop for RendezvousSender<T> {
+    fn drop(&mut self) {
+        self.channel.remove_sender();
+    }
+}
+
+/// Rendezvous channel receiver
+pub struct RendezvousReceiver<T: DataDeliveryPolicy> {
+    channel: Arc<RendezvousChannel<T>>,
+}
+
+impl<T: DataDeliveryPolicy> RendezvousReceiver<T> {
+    /// Waits for a value
+    pub fn recv(&self) -> Result<T> {
+        let channel = &self.channel;
+        let mut state = channel.state.lock();
+        state.waiting_receivers += 1;
+        let result = loop {
+            match channel.try_take(&mut state) {
+                Step::Ready(result) => break result,
+                Step::Wait => channel.wait(&mut state),
+            }
+        };
+        state.waiting_receivers -= 1;
+        result
+    }
+    /// Takes a value if a sender is waiting
+    pub fn try_recv(&self) -> Result<T> {
+        self.channel.try_recv()
+    }
+}
+
+impl<T: DataDeliveryPolicy> Clone for RendezvousReceiver<T> {
+    fn clone(&self) -> Self {
+        self.channel.add_receiver();
+        Self {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<T: DataDeliveryPolicy> Drop for RendezvousReceiver<T> {
+    fn drop(&mut self) {
+        self.channel.remove_receiver();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bounded_fair, rendezvous};
+    use crate::{DataDeliveryPolicy, DeliveryPolicy, Error};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[derive(Debug)]
+    struct Message(usize);
+
+    impl DataDeliveryPolicy for Message {}
+
+    struct OptionalMessage;
+
+    impl DataDeliveryPolicy for OptionalMessage {
+        fn delivery_policy(&self) -> DeliveryPolicy {
+            DeliveryPolicy::Optional
+        }
+    }
+
+    #[test]
+    fn test_rendezvous() {
+        let (tx, rx) = rendezvous::<Message>();
+        let taken = AtomicBool::new(false);
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                tx.send(Message(1)).unwrap();
+                // the sender is released only after the value is taken
+                assert!(taken.load(Ordering::SeqCst));
+            });
+            thread::sleep(Duration::from_millis(50));
+            taken.store(true, Ordering::SeqCst);
+            assert_eq!(rx.recv().unwrap().0, 1);
+        });
+        // no receiver is waiting
+        assert_eq!(tx.try_send(Message(2)), Err(Error::ChannelFull));
+        assert_eq!(rx.try_recv().unwrap_err(), Error::ChannelEmpty);
+    }
+
+    #[test]
+    fn test_rendezvous_shutdown() {
+        // the receiver is dropped while the sender is blocked
+        let (tx, rx) = rendezvous::<Message>();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                assert_eq!(tx.send(Message(1)), Err(Error::ChannelClosed));
+            });
+            thread::sleep(Duration::from_millis(50));
+            drop(rx);
+        });
+        // the sender is dropped while the receiver is blocked
+        let (tx, rx) = rendezvous::<Message>();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                assert_eq!(rx.recv().unwrap_err(), Error::ChannelClosed);
+            });
+            thread::sleep(Duration::from_millis(50));
+            drop(tx);
+        });
+        // optional values are skipped if no receiver is waiting
+        let (tx, _rx) = rendezvous::<OptionalMessage>();
+        assert_eq!(tx.send(OptionalMessage), Err(Error::ChannelSkipped));
+    }
+
+    #[test]
+    fn test_fair_no_starvation() {
+        const PRODUCERS: usize = 3;
+        const MESSAGES: usize = 300;
+        let (tx, rx) = bounded_fair::<Message>(1);
+        let mut counts = [0usize; PRODUCERS];
+        thread::scope(|scope| {
+            for id in 0..PRODUCERS {
+                let tx = tx.clone();
+                scope.spawn(move || while tx.send(Message(id)).is_ok() {});
+            }
+            drop(tx);
+            for _ in 0..MESSAGES {
+                let msg = rx.recv().unwrap();
+                counts[msg.0] += 1;
+                thread::sleep(Duration::from_micros(100));
+            }
+            drop(rx);
+        });
+        for count in counts {
+            assert!(count >= MESSAGES / PRODUCERS / 2, "{:?}", counts);
+        }
+    }
+}
@@ -0,0 +1,424 @@
+//!
+//! Async policy-based channels. Extends [`rtsc::pchannel_async`] with a poll-based receiver
+//! API and a [`Stream`] implementation which allow to use receivers in `select!` loops without
+//! creating and dropping receive futures on each iteration.
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+
+use crate::pchannel::{RendezvousChannel, Step, Turnstile};
+use crate::{DataDeliveryPolicy, Error, Result};
+
+pub use rtsc::pchannel_async::*;
+
+/// Creates a bounded channel with a fair sender (see [`crate::pchannel`] fairness mode)
+pub fn bounded_fair<T: DataDeliveryPolicy>(capacity: usize) -> (FairSender<T>, Receiver<T>) {
+    let (tx, rx) = bounded(capacity);
+    (tx.into(), rx)
+}
+
+/// Creates an ordered channel with a fair sender (see [`crate::pchannel`] fairness mode)
+pub fn ordered_fair<T: DataDeliveryPolicy>(capacity: usize) -> (FairSender<T>, Receiver<T>) {
+    let (tx, rx) = ordered(capacity);
+    (tx.into(), rx)
+}
+
+/// A sender with fair (round-robin) access to the channel. Each clone gets its own client id
+pub struct FairSender<T>
+where
+    T: DataDeliveryPolicy,
+{
+    tx: Sender<T>,
+    turnstile: Arc<Turnstile>,
+    client_id: u64,
+}
+
+impl<T> From<Sender<T>> for FairSender<T>
+where
+    T: DataDeliveryPolicy,
+{
+    fn from(tx: Sender<T>) -> Self {
+        let turnstile = Arc::new(Turnstile::new());
+        Self {
+            tx,
+            client_id: turnstile.register_client(),
+            turnstile,
+        }
+    }
+}
+
+impl<T> Clone for FairSender<T>
+where
+    T: DataDeliveryPolicy,
+{
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            turnstile: self.turnstile.clone(),
+            client_id: self.turnstile.register_client(),
+        }
+    }
+}
+
+impl<T> FairSender<T>
+where
+    T: DataDeliveryPolicy,
+{
+    /// Sends a value, waits for own turn if other senders are pending. Cancellation-safe: if
+    /// the future is dropped while waiting, the turn is passed to the next sender
+    pub async fn send(&self, value: T) -> Result<()> {
+        let _permit = self.turnstile.acquire_async(self.client_id).await;
+        self.tx.send(value).await.map_err(Into::into)
+    }
+    /// Tries to send a value immediately, the fairness queue is bypassed
+    pub fn try_send(&self, value: T) -> Result<()> {
+        self.tx.try_send(value).map_err(Into::into)
+    }
+    /// The sender client id
+    pub fn client_id(&self) -> u64 {
+        self.client_id
+    }
+    /// The underlying sender
+    pub fn sender(&self) -> &Sender<T> {
+        &self.tx
+    }
+}
+
+type RecvFuture<T> = Pin<Box<dyn Future<Output = rtsc::Result<T>> + Send>>;
+
+/// A receiver wrapper which keeps a pending receive operation between polls. Receiving is
+/// cancellation-safe: if the caller stops polling (e.g. another `select!` branch is completed),
+/// the pending operation is kept and the value is returned by the next poll.
+pub struct RecvStream<T>
+where
+    T: DataDeliveryPolicy,
+{
+    rx: Arc<Receiver<T>>,
+    pending: Option<RecvFuture<T>>,
+    // a value received by recv_ready
+    ready: Option<T>,
+}
+
+impl<T> RecvStream<T>
+where
+    T: DataDeliveryPolicy + Send + 'static,
+{
+    pub fn new(rx: Receiver<T>) -> Self {
+        Self {
+            rx: Arc::new(rx),
+            pending: None,
+            ready: None,
+        }
+    }
+    /// Polls the channel for a value. Values which are already in the channel are returned
+    /// without allocating a receive future
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Result<T>> {
+        if let Some(value) = self.ready.take() {
+            return Poll::Ready(Ok(value));
+        }
+        if self.pending.is_none() {
+            match self.rx.try_recv() {
+                Ok(value) => return Poll::Ready(Ok(value)),
+                Err(rtsc::Error::ChannelEmpty) => {
+                    let rx = self.rx.clone();
+                    self.pending = Some(Box::pin(async move { rx.recv().await }));
+                }
+                Err(e) => return Poll::Ready(Err(e.into())),
+            }
+        }
+        let pending = self.pending.as_mut().expect("BUG: no pending future");
+        match pending.as_mut().poll(cx) {
+            Poll::Ready(result) => {
+                self.pending.take();
+                Poll::Ready(result.map_err(Into::into))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+    /// Polls the channel readiness. If the result is `Ready(Ok(()))`, the next
+    /// [`RecvStream::poll_recv()`] or [`RecvStream::recv()`] call returns a value immediately
+    pub fn poll_recv_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if self.ready.is_some() {
+            return Poll::Ready(Ok(()));
+        }
+        match self.poll_recv(cx) {
+            Poll::Ready(Ok(value)) => {
+                self.ready = Some(value);
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+    /// Waits until a value is available without taking it
+    pub fn recv_ready(&mut self) -> impl Future<Output = Result<()>> + '_ {
+        std::future::poll_fn(move |cx| self.poll_recv_ready(cx))
+    }
+    /// Receives a value, can be used directly in `select!` loops
+    pub fn recv(&mut self) -> impl Future<Output = Result<T>> + '_ {
+        std::future::poll_fn(move |cx| self.poll_recv(cx))
+    }
+    /// Returns a reference to the underlying receiver
+    pub fn receiver(&self) -> &Receiver<T> {
+        &self.rx
+    }
+}
+
+impl<T> From<Receiver<T>> for RecvStream<T>
+where
+    T: DataDeliveryPolicy + Send + 'static,
+{
+    fn from(rx: Receiver<T>) -> Self {
+        Self::new(rx)
+    }
+}
+
+impl<T> Stream for RecvStream<T>
+where
+    T: DataDeliveryPolicy + Send + 'static,
+{
+    type Item = T;
+
+    /// The stream is finished when the channel is closed
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        match self.get_mut().poll_recv(cx) {
+            Poll::Ready(Ok(value)) => Poll::Ready(Some(value)),
+            Poll::Ready(Err(Error::ChannelClosed)) => Poll::Ready(None),
+            Poll::Ready(Err(e)) => {
+                tracing::error!(error=%e, "channel receive error");
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+// the stored value is never pinned
+impl<T> Unpin for RecvStream<T> where T: DataDeliveryPolicy {}
+
+/// Creates a rendezvous (zero-capacity) channel: a sender is blocked until a receiver takes the
+/// value (see [`crate::pchannel::rendezvous()`] for the delivery policy handling)
+pub fn rendezvous<T: DataDeliveryPolicy>() -> (RendezvousSender<T>, RendezvousReceiver<T>) {
+    let channel = Arc::new(RendezvousChannel::new());
+    (
+        RendezvousSender {
+            channel: channel.clone(),
+        },
+        RendezvousReceiver { channel },
+    )
+}
+
+/// Rendezvous channel sender
+pub struct RendezvousSender<T: DataDeliveryPolicy> {
+    channel: Arc<RendezvousChannel<T>>,
+}
+
+impl<T: DataDeliveryPolicy> RendezvousSender<T> {
+    /// Sends a value and waits until a receiver takes it. Cancellation-safe: if the future is
+    /// dropped before the value is taken, the value is removed from the channel
+    pub fn send(&self, value: T) -> RendezvousSend<'_, T> {
+        RendezvousSend {
+            channel: &self.channel,
+            value: Some(value),
+            seq: None,
+            done: false,
+        }
+    }
+    /// Sends a value only if a receiver is waiting at the moment, does not wait until the value
+    /// is taken
+    pub fn try_send(&self, value: T) -> Result<()> {
+        self.channel.try_send(value)
+    }
+}
+
+impl<T: DataDeliveryPolicy> Clone for RendezvousSender<T> {
+    fn clone(&self) -> Self {
+        self.channel.add_sender();
+        Self {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<T: DataDeliveryPolicy> Drop for RendezvousSender<T> {
+    fn drop(&mut self) {
+        self.channel.remove_sender();
+    }
+}
+
+/// Rendezvous send operation
+pub struct RendezvousSend<'a, T: DataDeliveryPolicy> {
+    channel: &'a RendezvousChannel<T>,
+    value: Option<T>,
+    seq: Option<u64>,
+    done: bool,
+}
+
+impl<T: DataDeliveryPolicy> Future for RendezvousSend<'_, T> {
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut state = this.channel.state.lock();
+        let seq = if let Some(seq) = this.seq {
+            seq
+        } else {
+            match this.channel.start_send(&mut state, &mut this.value) {
+                Step::Ready(Ok(seq)) => {
+                    this.seq = Some(seq);
+                    seq
+                }
+                Step::Ready(Err(e)) => {
+                    this.done = true;
+                    return Poll::Ready(Err(e));
+                }
+                Step::Wait => {
+                    state.wakers.push(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+        };
+        match this.channel.finish_send(&mut state, seq) {
+            Step::Ready(result) => {
+                this.done = true;
+                Poll::Ready(result)
+            }
+            Step::Wait => {
+                state.wakers.push(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T: DataDeliveryPolicy> Drop for RendezvousSend<'_, T> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        if let Some(seq) = self.seq {
+            let mut state = self.channel.state.lock();
+            self.channel.cancel_send(&mut state, seq);
+        }
+    }
+}
+
+// the stored value is never pinned
+impl<T> Unpin for RendezvousSend<'_, T> where T: DataDeliveryPolicy {}
+
+/// Rendezvous channel receiver
+pub struct RendezvousReceiver<T: DataDeliveryPolicy> {
+    channel: Arc<RendezvousChannel<T>>,
+}
+
+impl<T: DataDeliveryPolicy> RendezvousReceiver<T> {
+    /// Waits for a value. Cancellation-safe
+    pub fn recv(&self) -> RendezvousRecv<'_, T> {
+        RendezvousRecv {
+            channel: &self.channel,
+            waiting: false,
+        }
+    }
+    /// Takes a value if a sender is waiting
+    pub fn try_recv(&self) -> Result<T> {
+        self.channel.try_recv()
+    }
+}
+
+impl<T: DataDeliveryPolicy> Clone for RendezvousReceiver<T> {
+    fn clone(&self) -> Self {
+        self.channel.add_receiver();
+        Self {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<T: DataDeliveryPolicy> Drop for RendezvousReceiver<T> {
+    fn drop(&mut self) {
+        self.channel.remove_receiver();
+    }
+}
+
+/// Rendezvous receive operation
+pub struct RendezvousRecv<'a, T: DataDeliveryPolicy> {
+    channel: &'a RendezvousChannel<T>,
+    // registered as a waiting receiver
+    waiting: bool,
+}
+
+impl<T: DataDeliveryPolicy> Future for RendezvousRecv<'_, T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut state = this.channel.state.lock();
+        if !this.waiting {
+            this.waiting = true;
+            state.waiting_receivers += 1;
+        }
+        match this.channel.try_take(&mut state) {
+            Step::Ready(result) => {
+                this.waiting = false;
+                state.waiting_receivers -= 1;
+                Poll::Ready(result)
+            }
+            Step::Wait => {
+                state.wakers.push(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T: DataDeliveryPolicy> Drop for RendezvousRecv<'_, T> {
+    fn drop(&mut self) {
+        if self.waiting {
+            self.channel.state.lock().waiting_receivers -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::rendezvous;
+    use crate::{DataDeliveryPolicy, Error};
+    use std::time::Duration;
+
+    #[derive(Debug)]
+    struct Message(usize);
+
+    impl DataDeliveryPolicy for Message {}
+
+    #[tokio::test]
+    async fn test_rendezvous() {
+        let (tx, rx) = rendezvous::<Message>();
+        let receiver = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let value = rx.recv().await.unwrap().0;
+            // the sender is dropped, the channel is closed
+            assert_eq!(rx.recv().await.unwrap_err(), Error::ChannelClosed);
+            value
+        });
+        tx.send(Message(1)).await.unwrap();
+        drop(tx);
+        assert_eq!(receiver.await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rendezvous_cancel() {
+        let (tx, rx) = rendezvous::<Message>();
+        // a cancelled send removes the value from the channel
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), tx.send(Message(1)))
+                .await
+                .is_err()
+        );
+        assert_eq!(rx.try_recv().unwrap_err(), Error::ChannelEmpty);
+        drop(rx);
+        assert_eq!(tx.send(Message(2)).await, Err(Error::ChannelClosed));
+    }
+}
@@ -0,0 +1,288 @@
+//!
+//! Task placement validation: cross-checks CPU affinities and priorities of real-time tasks
+//! against each other and against isolated CPUs.
+//!
+//! The report is usually produced with [`crate::controller::Controller::validate_placement()`]
+//! right after all workers have been spawned. Conflicts are logged as warnings, the placement map
+//! can be exported as JSON (the report is serializable) or as a CSV matrix (tasks x CPUs) with
+//! [`PlacementReport::to_csv()`], which can be loaded into spreadsheets as a heatmap.
+use std::{collections::BTreeMap, fmt, fs};
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::{
+    thread_rt::{num_cpus, RTParams, Scheduling},
+    Error, Result,
+};
+
+const ISOLATED_CPUS_PATH: &str = "/sys/devices/system/cpu/isolated";
+
+/// Placement of a single task
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskPlacement {
+    pub name: String,
+    pub scheduling: Scheduling,
+    pub priority: Option<i32>,
+    /// Empty if the task is not pinned
+    pub cpu_ids: Vec<usize>,
+}
+
+impl TaskPlacement {
+    pub fn new(name: &str, params: &RTParams) -> Self {
+        Self {
+            name: name.to_owned(),
+            scheduling: params.scheduling(),
+            priority: params.priority(),
+            cpu_ids: params.cpu_ids().to_vec(),
+        }
+    }
+    fn is_realtime(&self) -> bool {
+        matches!(
+            self.scheduling,
+            Scheduling::FIFO | Scheduling::RoundRobin | Scheduling::DeadLine
+        )
+    }
+}
+
+/// Placement conflict
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PlacementConflict {
+    /// Several real-time tasks with the same priority are pinned to the same single CPU, so they
+    /// can not preempt each other
+    SharedCpu {
+        cpu: usize,
+        priority: i32,
+        tasks: Vec<String>,
+    },
+    /// A real-time task is pinned to a CPU, which is not isolated
+    NonIsolatedCpu { task: String, cpu: usize },
+    /// A task is pinned to a CPU, which does not exist
+    InvalidCpu { task: String, cpu: usize },
+    /// Real-time tasks are pinned, but the system has no isolated CPUs
+    NoIsolatedCpus,
+}
+
+impl fmt::Display for PlacementConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlacementConflict::SharedCpu {
+                cpu,
+                priority,
+                tasks,
+            } => write!(
+                f,
+                "tasks {} are pinned to CPU {} with the same priority {}",
+                tasks.join(", "),
+                cpu,
+                priority
+            ),
+            PlacementConflict::NonIsolatedCpu { task, cpu } => write!(
+                f,
+                "real-time task {} is pinned to non-isolated CPU {}",
+                task, cpu
+            ),
+            PlacementConflict::InvalidCpu { task, cpu } => {
+                write!(f, "task {} is pinned to non-existing CPU {}", task, cpu)
+            }
+            PlacementConflict::NoIsolatedCpus => {
+                write!(f, "real-time tasks are pinned, but no CPUs are isolated")
+            }
+        }
+    }
+}
+
+/// Placement report
+#[derive(Debug, Clone, Serialize)]
+pub struct PlacementReport {
+    pub cpus: usize,
+    pub isolated_cpus: Vec<usize>,
+    pub tasks: Vec<TaskPlacement>,
+    pub conflicts: Vec<PlacementConflict>,
+}
+
+impl PlacementReport {
+    /// Validates task placements for the given system configuration
+    pub fn new(tasks: Vec<TaskPlacement>, cpus: usize, isolated_cpus: Vec<usize>) -> Self {
+        let mut conflicts = Vec::new();
+        // cpu, priority -> tasks, pinned to the single cpu
+        let mut exclusive: BTreeMap<(usize, i32), Vec<String>> = BTreeMap::new();
+        let mut rt_pinned = false;
+        for task in &tasks {
+            for &cpu in &task.cpu_ids {
+                if cpu >= cpus {
+                    conflicts.push(PlacementConflict::InvalidCpu {
+                        task: task.name.clone(),
+                        cpu,
+                    });
+                }
+            }
+            if !task.is_realtime() || task.cpu_ids.is_empty() {
+                continue;
+            }
+            rt_pinned = true;
+            for &cpu in &task.cpu_ids {
+                if cpu < cpus && !isolated_cpus.is_empty() && !isolated_cpus.contains(&cpu) {
+                    conflicts.push(PlacementConflict::NonIsolatedCpu {
+                        task: task.name.clone(),
+                        cpu,
+                    });
+                }
+            }
+            if let ([cpu], Some(priority)) = (task.cpu_ids.as_slice(), task.priority) {
+                exclusive
+                    .entry((*cpu, priority))
+                    .or_default()
+                    .push(task.name.clone());
+            }
+        }
+        for ((cpu, priority), tasks) in exclusive {
+            if tasks.len() > 1 {
+                conflicts.push(PlacementConflict::SharedCpu {
+                    cpu,
+                    priority,
+                    tasks,
+                });
+            }
+        }
+        if rt_pinned && isolated_cpus.is_empty() {
+            conflicts.push(PlacementConflict::NoIsolatedCpus);
+        }
+        Self {
+            cpus,
+            isolated_cpus,
+            tasks,
+            conflicts,
+        }
+    }
+    /// Validates task placements for the current system
+    pub fn detect(tasks: Vec<TaskPlacement>) -> Result<Self> {
+        Ok(Self::new(tasks, num_cpus()?, isolated_cpus()?))
+    }
+    /// Returns true if there are no conflicts
+    pub fn is_ok(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+    /// Logs the conflicts as warnings
+    pub fn log_conflicts(&self) {
+        for conflict in &self.conflicts {
+            warn!(%conflict, "task placement conflict");
+        }
+    }
+    /// Exports the placement map as CSV: a row per task, a column per CPU. Cells of CPUs, the
+    /// task is pinned to, contain the task priority (0 if not set), other cells are empty
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("task,scheduling,priority");
+        for cpu in 0..self.cpus {
+            out.push_str(&format!(
+                ",cpu{}{}",
+                cpu,
+                if self.isolated_cpus.contains(&cpu) {
+                    "*"
+                } else {
+                    ""
+                }
+            ));
+        }
+        out.push('\n');
+        for task in &self.tasks {
+            let priority = task.priority.unwrap_or_default();
+            out.push_str(&format!("{},{:?},{}", task.name, task.scheduling, priority));
+            for cpu in 0..self.cpus {
+                out.push(',');
+                if task.cpu_ids.contains(&cpu) {
+                    out.push_str(&priority.to_string());
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Returns CPUs, isolated with the `isolcpus` kernel parameter
+pub fn isolated_cpus() -> Result<Vec<usize>> {
+    parse_cpu_list(&fs::read_to_string(ISOLATED_CPUS_PATH)?)
+}
+
+// parses kernel CPU lists, e.g. `1-3,5`
+pub(crate) fn parse_cpu_list(s: &str) -> Result<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for part in s.trim().split(',').filter(|p| !p.is_empty()) {
+        if let Some((from, to)) = part.split_once('-') {
+            let (from, to): (usize, usize) = (from.parse()?, to.parse()?);
+            if from > to {
+                return Err(Error::invalid_data(format!("invalid CPU range: {}", part)));
+            }
+            cpus.extend(from..=to);
+        } else {
+            cpus.push(part.parse()?);
+        }
+    }
+    Ok(cpus)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_cpu_list, PlacementConflict, PlacementReport, TaskPlacement};
+    use crate::thread_rt::{RTParams, Scheduling};
+
+    fn task(name: &str, priority: i32, cpu_ids: &[usize]) -> TaskPlacement {
+        TaskPlacement::new(
+            name,
+            &RTParams::new()
+                .set_scheduling(Scheduling::FIFO)
+                .set_priority(priority)
+                .set_cpu_ids(cpu_ids),
+        )
+    }
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("1-3,5\n").unwrap(), [1, 2, 3, 5]);
+        assert!(parse_cpu_list("\n").unwrap().is_empty());
+        assert!(parse_cpu_list("3-1").is_err());
+    }
+
+    #[test]
+    fn test_placement() {
+        let tasks = vec![
+            task("a", 99, &[2]),
+            task("b", 99, &[2]),
+            task("c", 80, &[2]),
+            task("d", 99, &[1]),
+            task("e", 99, &[4]),
+            TaskPlacement::new("f", &RTParams::new()),
+        ];
+        let report = PlacementReport::new(tasks, 4, vec![2, 3]);
+        assert_eq!(
+            report.conflicts,
+            [
+                PlacementConflict::NonIsolatedCpu {
+                    task: "d".to_owned(),
+                    cpu: 1
+                },
+                PlacementConflict::InvalidCpu {
+                    task: "e".to_owned(),
+                    cpu: 4
+                },
+                PlacementConflict::SharedCpu {
+                    cpu: 2,
+                    priority: 99,
+                    tasks: vec!["a".to_owned(), "b".to_owned()]
+                },
+            ]
+        );
+        let csv = report.to_csv();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "task,scheduling,priority,cpu0,cpu1,cpu2*,cpu3*"
+        );
+        assert_eq!(lines.next().unwrap(), "a,FIFO,99,,,99,");
+        assert_eq!(lines.last().unwrap(), "f,Other,0,,,,");
+        let report = PlacementReport::new(vec![task("a", 99, &[0])], 4, vec![]);
+        assert_eq!(report.conflicts, [PlacementConflict::NoIsolatedCpus]);
+    }
+}
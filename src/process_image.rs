@@ -0,0 +1,223 @@
+//!
+//! Classic PLC process image pattern.
+//!
+//! IO workers update the input image, the logic reads a consistent input snapshot at the
+//! beginning of each cycle, works with a local copy of the output image and publishes it once at
+//! the end of the cycle, so IO workers which write outputs always get a consistent output image.
+//! This avoids torn multi-variable reads when variables are updated by several workers.
+//!
+//! Images are triple-buffered: publishing and taking snapshots are lock-free and allocation-free
+//! (writers are serialized with a mutex, which is held only while the image is updated).
+//!
+//! Image fields can be declared as [`crate::forcing::ProcessValue`]s, so IO workers can force
+//! inputs and outputs during commissioning (see [`crate::forcing`]).
+//!
+//! Example:
+//!
+//! ```rust
+//! use roboplc::process_image::{image, LogicImage};
+//!
+//! #[derive(Clone, Default)]
+//! struct Inputs {
+//!     level: f32,
+//!     pressure: f32,
+//! }
+//!
+//! #[derive(Clone, Default)]
+//! struct Outputs {
+//!     pump: bool,
+//! }
+//!
+//! let (inputs_writer, inputs_reader) = image(Inputs::default());
+//! let (outputs_writer, mut outputs_reader) = image(Outputs::default());
+//! // IO worker
+//! inputs_writer.update(|i| {
+//!     i.level = 42.0;
+//!     i.pressure = 1.2;
+//! });
+//! // logic worker
+//! let mut logic = LogicImage::new(inputs_reader, outputs_writer);
+//! logic.cycle(|i, o| {
+//!     o.pump = i.level < 50.0 && i.pressure > 1.0;
+//! });
+//! // IO worker
+//! assert!(outputs_reader.snapshot().pump);
+//! ```
+use std::{
+    cell::UnsafeCell,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use parking_lot_rt::Mutex;
+
+const INDEX_MASK: usize = 0b11;
+const NEW_DATA: usize = 0b100;
+
+struct Shared<T> {
+    slots: [UnsafeCell<T>; 3],
+    // the back buffer index and the new data flag
+    back: AtomicUsize,
+    writer: Mutex<WriterState<T>>,
+}
+
+// the writer and the reader access different slots only, the slot indices are exchanged atomically
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+struct WriterState<T> {
+    staging: T,
+    index: usize,
+}
+
+/// Creates a new process image with the initial value. The writer can be cloned and shared
+/// between workers, the reader is unique
+pub fn image<T: Clone + Send>(initial: T) -> (ImageWriter<T>, ImageReader<T>) {
+    let shared = Arc::new(Shared {
+        slots: [
+            UnsafeCell::new(initial.clone()),
+            UnsafeCell::new(initial.clone()),
+            UnsafeCell::new(initial.clone()),
+        ],
+        back: AtomicUsize::new(1),
+        writer: Mutex::new(WriterState {
+            staging: initial,
+            index: 0,
+        }),
+    });
+    (
+        ImageWriter {
+            shared: shared.clone(),
+        },
+        ImageReader { shared, index: 2 },
+    )
+}
+
+/// Process image writer
+pub struct ImageWriter<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Clone for ImageWriter<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T: Clone> ImageWriter<T> {
+    /// Updates the image and publishes it. Fields which are not modified keep values, set by
+    /// previous updates (including updates from other workers)
+    pub fn update<F: FnOnce(&mut T)>(&self, f: F) {
+        let mut state = self.shared.writer.lock();
+        f(&mut state.staging);
+        self.publish(&mut state);
+    }
+    /// Replaces the whole image and publishes it
+    pub fn write(&self, value: &T) {
+        let mut state = self.shared.writer.lock();
+        state.staging.clone_from(value);
+        self.publish(&mut state);
+    }
+    fn publish(&self, state: &mut WriterState<T>) {
+        // the slot is owned by the writer until published
+        let slot = unsafe { &mut *self.shared.slots[state.index].get() };
+        slot.clone_from(&state.staging);
+        let prev = self
+            .shared
+            .back
+            .swap(state.index | NEW_DATA, Ordering::AcqRel);
+        state.index = prev & INDEX_MASK;
+    }
+}
+
+/// Process image reader
+pub struct ImageReader<T> {
+    shared: Arc<Shared<T>>,
+    index: usize,
+}
+
+impl<T> ImageReader<T> {
+    /// Returns true if the image has been updated since the last snapshot
+    pub fn has_update(&self) -> bool {
+        self.shared.back.load(Ordering::Acquire) & NEW_DATA != 0
+    }
+    /// Returns a consistent snapshot of the latest published image
+    pub fn snapshot(&mut self) -> &T {
+        if self.has_update() {
+            let prev = self.shared.back.swap(self.index, Ordering::AcqRel);
+            self.index = prev & INDEX_MASK;
+        }
+        // the slot is owned by the reader until swapped
+        unsafe { &*self.shared.slots[self.index].get() }
+    }
+}
+
+/// Logic-side process image: takes an input snapshot at the beginning of a cycle and publishes
+/// outputs once at the end
+pub struct LogicImage<I, O> {
+    inputs: ImageReader<I>,
+    outputs: ImageWriter<O>,
+    local_outputs: O,
+}
+
+impl<I, O: Clone> LogicImage<I, O> {
+    /// The local output image is initialized with the current output image value
+    pub fn new(inputs: ImageReader<I>, outputs: ImageWriter<O>) -> Self {
+        let local_outputs = outputs.shared.writer.lock().staging.clone();
+        Self {
+            inputs,
+            outputs,
+            local_outputs,
+        }
+    }
+    /// Runs a logic cycle. The output image is published when the function returns
+    pub fn cycle<F: FnOnce(&I, &mut O)>(&mut self, f: F) {
+        let inputs = self.inputs.snapshot();
+        f(inputs, &mut self.local_outputs);
+        self.outputs.write(&self.local_outputs);
+    }
+    /// The local output image (as written by the last cycle)
+    pub fn outputs(&self) -> &O {
+        &self.local_outputs
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::image;
+    use std::thread;
+
+    #[derive(Clone, Default)]
+    struct Pair {
+        a: u64,
+        b: u64,
+    }
+
+    #[test]
+    fn test_image_consistency() {
+        let (writer, mut reader) = image(Pair::default());
+        assert!(!reader.has_update());
+        writer.update(|p| p.a = 1);
+        writer.update(|p| p.b = 2);
+        let s = reader.snapshot();
+        assert_eq!((s.a, s.b), (1, 2));
+        let (writer, mut reader) = image(Pair::default());
+        let handle = thread::spawn(move || {
+            for i in 0..100_000 {
+                writer.write(&Pair { a: i, b: i });
+            }
+        });
+        let mut last = 0;
+        while !handle.is_finished() || reader.has_update() {
+            let s = reader.snapshot();
+            assert_eq!(s.a, s.b);
+            assert!(s.a >= last);
+            last = s.a;
+        }
+        handle.join().unwrap();
+        assert_eq!(reader.snapshot().a, 99_999);
+    }
+}
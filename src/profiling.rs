@@ -0,0 +1,364 @@
+//!
+//! Lightweight cycle profiler. Workers spawned by the controller run in `worker` tracing spans
+//! (with the `worker` field), cycles can be wrapped in `cycle` spans (with the `cycle_no` field)
+//! with [`crate::controller::Context::cycle_span()`], code sections with [`span()`].
+//!
+//! If the profiler is enabled with [`enable()`], span timings are collected and can be exported
+//! as folded stacks (for `inferno-flamegraph`/`flamegraph.pl`, self times in microseconds) and as
+//! Chrome trace files (for `chrome://tracing` or Perfetto), either directly or on SIGUSR1 (see
+//! [`export_on_signal()`]). When disabled, spans are regular tracing spans only.
+//!
+//! ```rust,no_run
+//! use roboplc::profiling;
+//!
+//! profiling::enable(100_000);
+//! profiling::export_on_signal("/tmp").unwrap();
+//! // kill -USR1 <pid> writes /tmp/roboplc-<pid>-<time>.folded and .trace.json
+//! ```
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{BTreeMap, VecDeque},
+    fmt::Write as _,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use tracing::{info, info_span, span::EnteredSpan, Span};
+
+use crate::Result;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static PROFILE: Mutex<Option<Profile>> = Mutex::new(None);
+
+static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static THREAD: RefCell<ThreadState> = const {
+        RefCell::new(ThreadState {
+            id: 0,
+            worker: None,
+            stack: Vec::new(),
+        })
+    };
+}
+
+struct ThreadState {
+    // assigned on the first recorded span
+    id: u64,
+    worker: Option<String>,
+    stack: Vec<Frame>,
+}
+
+impl ThreadState {
+    fn label(&self) -> String {
+        self.worker.clone().unwrap_or_else(|| {
+            thread::current()
+                .name()
+                .map_or_else(|| format!("thread-{}", self.id), ToOwned::to_owned)
+        })
+    }
+}
+
+struct Frame {
+    name: &'static str,
+    // worker frames are the stack roots
+    is_worker: bool,
+    cycle_no: Option<u64>,
+    start: Instant,
+    children: Duration,
+}
+
+struct TraceEvent {
+    name: Cow<'static, str>,
+    tid: u64,
+    start: Duration,
+    duration: Duration,
+    cycle_no: Option<u64>,
+}
+
+struct Profile {
+    started: Instant,
+    capacity: usize,
+    // self times
+    folded: BTreeMap<String, Duration>,
+    events: VecDeque<TraceEvent>,
+    threads: BTreeMap<u64, String>,
+}
+
+/// Enables the profiler, previously collected data is cleared. The capacity is the max number of
+/// span records kept for Chrome traces (older ones are dropped), folded stacks are aggregated and
+/// not limited
+///
+/// # Panics
+///
+/// Will panic if the internal lock is poisoned
+pub fn enable(capacity: usize) {
+    PROFILE.lock().unwrap().replace(Profile {
+        started: Instant::now(),
+        capacity,
+        folded: BTreeMap::new(),
+        events: VecDeque::with_capacity(capacity.min(100_000)),
+        threads: BTreeMap::new(),
+    });
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Disables the profiler, the collected data is kept until the profiler is enabled again
+pub fn disable() {
+    ENABLED.store(false, Ordering::SeqCst);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Profiling span guard, the span is exited when the guard is dropped
+pub struct ProfileSpan {
+    _span: EnteredSpan,
+    recording: bool,
+}
+
+impl ProfileSpan {
+    fn new(span: Span, name: &'static str, is_worker: bool, cycle_no: Option<u64>) -> Self {
+        let recording = is_enabled();
+        if recording {
+            THREAD.with(|t| {
+                t.borrow_mut().stack.push(Frame {
+                    name,
+                    is_worker,
+                    cycle_no,
+                    start: Instant::now(),
+                    children: Duration::ZERO,
+                });
+            });
+        }
+        Self {
+            _span: span.entered(),
+            recording,
+        }
+    }
+}
+
+impl Drop for ProfileSpan {
+    fn drop(&mut self) {
+        if self.recording {
+            THREAD.with(|t| record(&mut t.borrow_mut()));
+        }
+    }
+}
+
+/// A span for a code section
+pub fn span(name: &'static str) -> ProfileSpan {
+    ProfileSpan::new(info_span!("section", section = name), name, false, None)
+}
+
+/// A worker span, created automatically for workers spawned by the controller. Other threads
+/// may use it to be labeled in profiles
+pub fn worker_span(worker: &str) -> ProfileSpan {
+    THREAD.with(|t| t.borrow_mut().worker = Some(worker.to_owned()));
+    ProfileSpan::new(info_span!("worker", worker), "worker", true, None)
+}
+
+/// A cycle span
+pub fn cycle_span(cycle_no: u64) -> ProfileSpan {
+    ProfileSpan::new(
+        info_span!("cycle", cycle_no),
+        "cycle",
+        false,
+        Some(cycle_no),
+    )
+}
+
+fn record(state: &mut ThreadState) {
+    let Some(frame) = state.stack.pop() else {
+        return;
+    };
+    let duration = frame.start.elapsed();
+    if let Some(parent) = state.stack.last_mut() {
+        parent.children += duration;
+    }
+    if state.id == 0 {
+        state.id = NEXT_THREAD_ID.fetch_add(1, Ordering::SeqCst);
+    }
+    let label = state.label();
+    let mut path = label.clone();
+    for f in state.stack.iter().chain([&frame]).filter(|f| !f.is_worker) {
+        path.push(';');
+        path.push_str(f.name);
+    }
+    let mut profile = PROFILE.lock().unwrap();
+    let Some(profile) = profile.as_mut() else {
+        return;
+    };
+    *profile.folded.entry(path).or_default() += duration.saturating_sub(frame.children);
+    if profile.capacity == 0 {
+        return;
+    }
+    if profile.events.len() >= profile.capacity {
+        profile.events.pop_front();
+    }
+    profile.events.push_back(TraceEvent {
+        name: if frame.is_worker {
+            label.clone().into()
+        } else {
+            frame.name.into()
+        },
+        tid: state.id,
+        start: frame.start.saturating_duration_since(profile.started),
+        duration,
+        cycle_no: frame.cycle_no,
+    });
+    profile.threads.entry(state.id).or_insert(label);
+}
+
+/// Writes folded stacks (self times in microseconds)
+///
+/// # Panics
+///
+/// Will panic if the internal lock is poisoned
+pub fn write_folded<W: Write>(mut w: W) -> Result<()> {
+    let profile = PROFILE.lock().unwrap();
+    if let Some(ref profile) = *profile {
+        for (path, duration) in &profile.folded {
+            writeln!(w, "{} {}", path, duration.as_micros())?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes a Chrome trace (JSON)
+///
+/// # Panics
+///
+/// Will panic if the internal lock is poisoned
+pub fn write_chrome_trace<W: Write>(mut w: W) -> Result<()> {
+    let profile = PROFILE.lock().unwrap();
+    let pid = std::process::id();
+    let mut events = Vec::new();
+    if let Some(ref profile) = *profile {
+        for (tid, name) in &profile.threads {
+            events.push(format!(
+                r#"{{"name":"thread_name","ph":"M","pid":{},"tid":{},"args":{{"name":{}}}}}"#,
+                pid,
+                tid,
+                json_string(name)
+            ));
+        }
+        for event in &profile.events {
+            let mut s = format!(
+                r#"{{"name":{},"cat":"roboplc","ph":"X","pid":{},"tid":{},"ts":{},"dur":{}"#,
+                json_string(&event.name),
+                pid,
+                event.tid,
+                event.start.as_micros(),
+                event.duration.as_micros()
+            );
+            if let Some(cycle_no) = event.cycle_no {
+                write!(s, r#","args":{{"cycle_no":{}}}"#, cycle_no).unwrap();
+            }
+            s.push('}');
+            events.push(s);
+        }
+    }
+    write!(w, r#"{{"traceEvents":[{}]}}"#, events.join(","))?;
+    Ok(())
+}
+
+/// Exports folded stacks and a Chrome trace into the directory, returns the file paths
+pub fn export_to_dir(dir: &Path) -> Result<(PathBuf, PathBuf)> {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let base = format!("roboplc-{}-{}", std::process::id(), time);
+    let folded = dir.join(format!("{}.folded", base));
+    let trace = dir.join(format!("{}.trace.json", base));
+    write_folded(io::BufWriter::new(fs::File::create(&folded)?))?;
+    write_chrome_trace(io::BufWriter::new(fs::File::create(&trace)?))?;
+    info!(folded = %folded.display(), trace = %trace.display(), "profile exported");
+    Ok((folded, trace))
+}
+
+/// Spawns a thread which exports the profile into the directory on SIGUSR1
+#[cfg(target_os = "linux")]
+pub fn export_on_signal<P: Into<PathBuf>>(dir: P) -> Result<()> {
+    let dir = dir.into();
+    let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGUSR1])?;
+    thread::Builder::new()
+        .name("RoboPLCProfile".to_owned())
+        .spawn(move || {
+            for _ in signals.forever() {
+                if let Err(error) = export_to_dir(&dir) {
+                    tracing::error!(%error, "unable to export the profile");
+                }
+            }
+        })?;
+    Ok(())
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => write!(out, "\\u{:04x}", u32::from(c)).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{cycle_span, enable, span, worker_span, write_chrome_trace, write_folded};
+
+    #[test]
+    fn test_profiling() {
+        enable(100);
+        thread::spawn(|| {
+            let _worker = worker_span("w1");
+            for i in 0..3 {
+                let _cycle = cycle_span(i);
+                let _io = span("io");
+                thread::sleep(Duration::from_millis(1));
+            }
+        })
+        .join()
+        .unwrap();
+        let mut folded = Vec::new();
+        write_folded(&mut folded).unwrap();
+        let folded = String::from_utf8(folded).unwrap();
+        let stacks: Vec<&str> = folded
+            .lines()
+            .map(|l| l.rsplit_once(' ').unwrap().0)
+            .collect();
+        assert_eq!(stacks, ["w1", "w1;cycle", "w1;cycle;io"]);
+        let io_time: u64 = folded
+            .lines()
+            .find_map(|l| l.strip_prefix("w1;cycle;io "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(io_time >= 3000);
+        let mut trace = Vec::new();
+        write_chrome_trace(&mut trace).unwrap();
+        let trace = String::from_utf8(trace).unwrap();
+        assert!(trace.contains(r#""args":{"cycle_no":2}"#));
+        assert!(trace.contains(r#""args":{"name":"w1"}"#));
+        assert_eq!(trace.matches(r#""name":"io""#).count(), 3);
+    }
+}
@@ -0,0 +1,487 @@
+//!
+//! Redundant controller pairs (hot standby). Two nodes exchange heartbeats over a dedicated UDP
+//! link, elect the active node and optionally synchronize the program state from the active node
+//! to the standby one.
+//!
+//! Actuator writes must be gated on the active role, either manually with [`RoleBeacon`] or with
+//! [`GatedMapping`] wrapper for I/O mappings. For bumpless transfer, the active node provides its
+//! state (e.g. integrator states of control loops) with [`Redundancy::state_provider()`], which is
+//! delivered to [`Redundancy::on_state_sync()`] handler of the standby node. The handler should
+//! store the state so the standby node can continue from it after a role change.
+//!
+//! Election rules:
+//!
+//! * if the peer heartbeats are not received within the timeout, the node becomes active
+//!
+//! * if both nodes are active (e.g. after the link recovery), the node with the higher priority
+//!   (the lower node id if priorities are equal) stays active
+//!
+//! * if both nodes are in standby, the node with the higher priority becomes active
+//!
+//! * an active node is never preempted by a standby one to avoid flapping
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use binrw::{BinRead, BinWrite};
+use tracing::{error, info, warn};
+
+use crate::{
+    comm::Client,
+    io::{IoMapping, LockedIoMapping},
+    Error, Result,
+};
+
+const PACKET_MAGIC: &[u8; 4] = b"RPRD";
+const PACKET_VERSION: u8 = 1;
+const HEADER_SIZE: usize = 20;
+
+/// Maximum state sync payload size
+pub const MAX_SYNC_SIZE: usize = 60_000;
+
+/// Node role
+#[derive(Default, Debug, Eq, PartialEq, Clone, Copy)]
+#[repr(u8)]
+pub enum Role {
+    /// The node is in standby, actuator writes must be blocked
+    #[default]
+    Standby = 0,
+    /// The node is active
+    Active = 1,
+}
+
+impl From<u8> for Role {
+    fn from(v: u8) -> Self {
+        if v == Role::Active as u8 {
+            Role::Active
+        } else {
+            Role::Standby
+        }
+    }
+}
+
+/// Node role beacon. Can be cloned and shared with no limitations.
+#[derive(Clone, Default)]
+pub struct RoleBeacon {
+    role: Arc<AtomicU8>,
+}
+
+impl RoleBeacon {
+    pub fn role(&self) -> Role {
+        Role::from(self.role.load(Ordering::SeqCst))
+    }
+    pub fn is_active(&self) -> bool {
+        self.role() == Role::Active
+    }
+    /// Calls the function only if the node is active
+    pub fn if_active<R, F: FnOnce() -> R>(&self, f: F) -> Option<R> {
+        if self.is_active() {
+            Some(f())
+        } else {
+            None
+        }
+    }
+    fn set(&self, role: Role) {
+        self.role.store(role as u8, Ordering::SeqCst);
+    }
+}
+
+/// I/O mapping wrapper, which ignores writes if the node is not active
+pub struct GatedMapping<M: IoMapping> {
+    mapping: M,
+    beacon: RoleBeacon,
+}
+
+impl<M: IoMapping> GatedMapping<M> {
+    pub fn new(mapping: M, beacon: RoleBeacon) -> Self {
+        Self { mapping, beacon }
+    }
+    pub fn into_inner(self) -> M {
+        self.mapping
+    }
+}
+
+impl<M: IoMapping> IoMapping for GatedMapping<M> {
+    type Options = M::Options;
+
+    fn read<T>(&mut self) -> Result<T>
+    where
+        T: for<'a> BinRead<Args<'a> = ()>,
+    {
+        self.mapping.read()
+    }
+
+    fn write<T>(&mut self, value: T) -> Result<()>
+    where
+        T: for<'a> BinWrite<Args<'a> = ()>,
+    {
+        if self.beacon.is_active() {
+            self.mapping.write(value)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<M: LockedIoMapping> LockedIoMapping for GatedMapping<M> {
+    fn client(&self) -> &Client {
+        self.mapping.client()
+    }
+
+    fn read_locked<T>(&mut self) -> Result<T>
+    where
+        T: for<'a> BinRead<Args<'a> = ()>,
+    {
+        self.mapping.read_locked()
+    }
+
+    fn write_locked<T>(&mut self, value: T) -> Result<()>
+    where
+        T: for<'a> BinWrite<Args<'a> = ()>,
+    {
+        if self.beacon.is_active() {
+            self.mapping.write_locked(value)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct NodeInfo {
+    node_id: u8,
+    priority: u8,
+    role: Role,
+}
+
+impl NodeInfo {
+    fn outranks(&self, other: &NodeInfo) -> bool {
+        self.priority > other.priority
+            || (self.priority == other.priority && self.node_id < other.node_id)
+    }
+}
+
+// elects the local node role, the peer is None if lost
+fn elect(local: NodeInfo, peer: Option<NodeInfo>) -> Role {
+    let Some(peer) = peer else {
+        return Role::Active;
+    };
+    match (local.role, peer.role) {
+        (Role::Active, Role::Standby) => Role::Active,
+        (Role::Standby, Role::Active) => Role::Standby,
+        (Role::Active, Role::Active) | (Role::Standby, Role::Standby) => {
+            if local.outranks(&peer) {
+                Role::Active
+            } else {
+                Role::Standby
+            }
+        }
+    }
+}
+
+// magic, version, node id, priority, role, sequence (u64), payload len (u32), payload
+fn encode_packet(node: NodeInfo, seq: u64, payload: &[u8], buf: &mut Vec<u8>) {
+    buf.clear();
+    buf.extend(PACKET_MAGIC);
+    buf.push(PACKET_VERSION);
+    buf.push(node.node_id);
+    buf.push(node.priority);
+    buf.push(node.role as u8);
+    buf.extend(seq.to_be_bytes());
+    #[allow(clippy::cast_possible_truncation)]
+    buf.extend((payload.len() as u32).to_be_bytes());
+    buf.extend(payload);
+}
+
+fn decode_packet(buf: &[u8]) -> Result<(NodeInfo, u64, &[u8])> {
+    if buf.len() < HEADER_SIZE || &buf[..4] != PACKET_MAGIC || buf[4] != PACKET_VERSION {
+        return Err(Error::invalid_data("invalid redundancy packet"));
+    }
+    let node = NodeInfo {
+        node_id: buf[5],
+        priority: buf[6],
+        role: Role::from(buf[7]),
+    };
+    let seq = u64::from_be_bytes(buf[8..16].try_into().unwrap());
+    let len = usize::try_from(u32::from_be_bytes(buf[16..20].try_into().unwrap()))
+        .map_err(Error::invalid_data)?;
+    let payload = buf
+        .get(HEADER_SIZE..HEADER_SIZE + len)
+        .ok_or_else(|| Error::invalid_data("invalid redundancy packet payload"))?;
+    Ok((node, seq, payload))
+}
+
+type StateProviderFn = Box<dyn Fn() -> Vec<u8> + Send>;
+type StateSyncFn = Box<dyn FnMut(&[u8]) + Send>;
+type RoleChangeFn = Box<dyn FnMut(Role, Role) + Send>;
+
+/// Redundancy node. Must be run in a separate thread with [`Redundancy::run()`].
+pub struct Redundancy {
+    node_id: u8,
+    priority: u8,
+    socket: UdpSocket,
+    peer_addr: SocketAddr,
+    heartbeat_interval: Duration,
+    peer_timeout: Duration,
+    beacon: RoleBeacon,
+    state_provider: Option<StateProviderFn>,
+    state_sync_handler: Option<StateSyncFn>,
+    role_change_handler: Option<RoleChangeFn>,
+    peer: Option<(NodeInfo, Instant)>,
+    peer_seq: Option<u64>,
+    seq: u64,
+}
+
+impl Redundancy {
+    /// Binds the heartbeat socket to the local address. The node id must be unique within the pair
+    pub fn bind<A: ToSocketAddrs, P: ToSocketAddrs>(
+        node_id: u8,
+        local_addr: A,
+        peer_addr: P,
+    ) -> Result<Self> {
+        let peer_addr = peer_addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| Error::invalid_data("invalid peer address"))?;
+        Ok(Self {
+            node_id,
+            priority: 0,
+            socket: UdpSocket::bind(local_addr)?,
+            peer_addr,
+            heartbeat_interval: Duration::from_millis(100),
+            peer_timeout: Duration::from_millis(500),
+            beacon: RoleBeacon::default(),
+            state_provider: None,
+            state_sync_handler: None,
+            role_change_handler: None,
+            peer: None,
+            peer_seq: None,
+            seq: 0,
+        })
+    }
+    /// Sets the node priority, the node with the higher priority wins the election (the default is
+    /// 0)
+    pub fn priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+    /// Sets the heartbeat interval (the default is 100ms)
+    pub fn heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = interval;
+        self
+    }
+    /// Sets the peer timeout, after which the peer is considered as lost (the default is 500ms)
+    pub fn peer_timeout(mut self, timeout: Duration) -> Self {
+        self.peer_timeout = timeout;
+        self
+    }
+    /// Sets a function which provides the program state on the active node. The state is sent to
+    /// the standby node with every heartbeat, so it must be kept small (see [`MAX_SYNC_SIZE`])
+    pub fn state_provider<F>(mut self, f: F) -> Self
+    where
+        F: Fn() -> Vec<u8> + Send + 'static,
+    {
+        self.state_provider = Some(Box::new(f));
+        self
+    }
+    /// Sets a handler, which is called on the standby node when the state is received from the
+    /// active one
+    pub fn on_state_sync<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        self.state_sync_handler = Some(Box::new(f));
+        self
+    }
+    /// Sets a handler, which is called on role changes with the previous and the new role
+    pub fn on_role_change<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(Role, Role) + Send + 'static,
+    {
+        self.role_change_handler = Some(Box::new(f));
+        self
+    }
+    /// Role beacon, which can be used to gate actuator writes
+    pub fn role_beacon(&self) -> RoleBeacon {
+        self.beacon.clone()
+    }
+    pub fn role(&self) -> Role {
+        self.beacon.role()
+    }
+    fn info(&self) -> NodeInfo {
+        NodeInfo {
+            node_id: self.node_id,
+            priority: self.priority,
+            role: self.beacon.role(),
+        }
+    }
+    fn send_heartbeat(&mut self, buf: &mut Vec<u8>) {
+        let node = self.info();
+        let payload = if node.role == Role::Active {
+            self.state_provider
+                .as_ref()
+                .map(|f| f())
+                .unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        let payload = if payload.len() > MAX_SYNC_SIZE {
+            error!(
+                size = payload.len(),
+                "redundancy state is too large, not synced"
+            );
+            &[][..]
+        } else {
+            &payload[..]
+        };
+        self.seq = self.seq.wrapping_add(1);
+        encode_packet(node, self.seq, payload, buf);
+        if let Err(error) = self.socket.send_to(buf, self.peer_addr) {
+            warn!(%error, "unable to send redundancy heartbeat");
+        }
+    }
+    fn process_packet(&mut self, data: &[u8]) {
+        let (node, seq, payload) = match decode_packet(data) {
+            Ok(v) => v,
+            Err(error) => {
+                warn!(%error, "invalid redundancy packet");
+                return;
+            }
+        };
+        if node.node_id == self.node_id {
+            error!(node_id = node.node_id, "peer has got the same node id");
+            return;
+        }
+        // drop reordered packets, a restarted peer starts the sequence from the beginning
+        if let Some(prev) = self.peer_seq {
+            if seq <= prev && seq > 1 {
+                return;
+            }
+        }
+        self.peer_seq = Some(seq);
+        self.peer = Some((node, Instant::now()));
+        if node.role == Role::Active && self.beacon.role() == Role::Standby && !payload.is_empty() {
+            if let Some(ref mut f) = self.state_sync_handler {
+                f(payload);
+            }
+        }
+    }
+    fn update_role(&mut self, started: Instant) {
+        let peer = match self.peer {
+            Some((peer, last_seen)) if last_seen.elapsed() <= self.peer_timeout => Some(peer),
+            // give the peer a chance to announce itself after start
+            None if started.elapsed() <= self.peer_timeout => return,
+            _ => None,
+        };
+        let local = self.info();
+        let role = elect(local, peer);
+        if role != local.role {
+            info!(
+                from = ?local.role,
+                to = ?role,
+                peer_online = peer.is_some(),
+                "redundancy role changed"
+            );
+            self.beacon.set(role);
+            if let Some(ref mut f) = self.role_change_handler {
+                f(local.role, role);
+            }
+        }
+    }
+    /// Runs the node loop: sends heartbeats, receives peer packets and elects the role
+    pub fn run(&mut self) -> Result<()> {
+        let started = Instant::now();
+        let mut buf = vec![0; HEADER_SIZE + MAX_SYNC_SIZE];
+        let mut out_buf = Vec::with_capacity(HEADER_SIZE);
+        let mut next_heartbeat = Instant::now();
+        loop {
+            let now = Instant::now();
+            if now >= next_heartbeat {
+                self.send_heartbeat(&mut out_buf);
+                next_heartbeat = now + self.heartbeat_interval;
+            }
+            let wait = next_heartbeat
+                .saturating_duration_since(Instant::now())
+                .max(Duration::from_millis(1));
+            self.socket.set_read_timeout(Some(wait))?;
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, addr)) => {
+                    if addr.ip() == self.peer_addr.ip() {
+                        self.process_packet(&buf[..len]);
+                    } else {
+                        warn!(%addr, "redundancy packet from unknown address");
+                    }
+                }
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e.into()),
+            }
+            self.update_role(started);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_packet, elect, encode_packet, NodeInfo, Role};
+
+    fn node(node_id: u8, priority: u8, role: Role) -> NodeInfo {
+        NodeInfo {
+            node_id,
+            priority,
+            role,
+        }
+    }
+
+    #[test]
+    fn test_election() {
+        // peer lost
+        assert_eq!(elect(node(1, 0, Role::Standby), None), Role::Active);
+        // no preemption
+        assert_eq!(
+            elect(node(1, 10, Role::Standby), Some(node(2, 0, Role::Active))),
+            Role::Standby
+        );
+        assert_eq!(
+            elect(node(2, 0, Role::Active), Some(node(1, 10, Role::Standby))),
+            Role::Active
+        );
+        // split brain, the higher priority wins
+        assert_eq!(
+            elect(node(1, 0, Role::Active), Some(node(2, 10, Role::Active))),
+            Role::Standby
+        );
+        assert_eq!(
+            elect(node(2, 10, Role::Active), Some(node(1, 0, Role::Active))),
+            Role::Active
+        );
+        // both in standby, equal priorities, the lower node id wins
+        assert_eq!(
+            elect(node(1, 0, Role::Standby), Some(node(2, 0, Role::Standby))),
+            Role::Active
+        );
+        assert_eq!(
+            elect(node(2, 0, Role::Standby), Some(node(1, 0, Role::Standby))),
+            Role::Standby
+        );
+    }
+
+    #[test]
+    fn test_packet() {
+        let mut buf = Vec::new();
+        let n = node(3, 7, Role::Active);
+        encode_packet(n, 42, b"state", &mut buf);
+        let (decoded, seq, payload) = decode_packet(&buf).unwrap();
+        assert_eq!(decoded, n);
+        assert_eq!(seq, 42);
+        assert_eq!(payload, b"state");
+        assert!(decode_packet(&buf[..buf.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,244 @@
+//!
+//! Toggles a designated output at the configured rate while all registered health sources (e.g.
+//! workers) are healthy. An external hardware watchdog relay, wired to the output, trips if the
+//! output stops toggling. When a source is stalled, the toggling is stopped and the output is set
+//! to the "off" value (fail-safe).
+//!
+//! ```rust,ignore
+//! let mut heartbeat = Heartbeat::new(mapping, Duration::from_millis(250))
+//!     .stall_timeout(Duration::from_secs(1));
+//! heartbeat.register_worker(controller.worker_handle("motion").unwrap());
+//! heartbeat.run(|| !controller.state().is_online())?;
+//! ```
+use std::time::{Duration, Instant};
+
+use binrw::BinWrite;
+use tracing::{error, info};
+
+use crate::{io::IoMapping, Result};
+
+const DEFAULT_STALL_TIMEOUT: Duration = Duration::from_secs(1);
+
+type HealthSource = Box<dyn Fn() -> Option<Instant> + Send>;
+
+/// Heartbeat output pattern generator
+pub struct Heartbeat<M: IoMapping, T = u8> {
+    mapping: M,
+    on: T,
+    off: T,
+    period: Duration,
+    stall_timeout: Duration,
+    sources: Vec<(String, HealthSource)>,
+    output: bool,
+    last_toggle: Option<Instant>,
+    stalled: bool,
+}
+
+impl<M: IoMapping> Heartbeat<M, u8> {
+    /// Creates a new heartbeat generator which writes `1`/`0` bytes (e.g. a coil) and toggles the
+    /// output every `period`
+    pub fn new(mapping: M, period: Duration) -> Self {
+        Self::with_values(mapping, period, 1, 0)
+    }
+}
+
+impl<M, T> Heartbeat<M, T>
+where
+    M: IoMapping,
+    T: for<'a> BinWrite<Args<'a> = ()> + Clone,
+{
+    /// Creates a new heartbeat generator with custom output values (e.g. `1u16`/`0u16` for a
+    /// holding register)
+    pub fn with_values(mapping: M, period: Duration, on: T, off: T) -> Self {
+        Self {
+            mapping,
+            on,
+            off,
+            period,
+            stall_timeout: DEFAULT_STALL_TIMEOUT,
+            sources: Vec::new(),
+            output: false,
+            last_toggle: None,
+            stalled: false,
+        }
+    }
+    /// A source is considered as stalled if it has not reported a heartbeat within the timeout
+    /// (the default is 1 second)
+    pub fn stall_timeout(mut self, timeout: Duration) -> Self {
+        self.stall_timeout = timeout;
+        self
+    }
+    /// Registers a health source. The function must return the time of the last heartbeat of the
+    /// source, `None` means the source is not healthy (e.g. has not been started yet)
+    pub fn register<F>(&mut self, name: &str, last_heartbeat: F)
+    where
+        F: Fn() -> Option<Instant> + Send + 'static,
+    {
+        self.sources
+            .push((name.to_owned(), Box::new(last_heartbeat)));
+    }
+    /// Registers a worker as a health source. The worker must call
+    /// [`crate::controller::Context::heartbeat()`] more often than the stall timeout
+    #[cfg(target_os = "linux")]
+    pub fn register_worker<D>(&mut self, handle: crate::controller::WorkerHandle<D>)
+    where
+        D: rtsc::data_policy::DataDeliveryPolicy + Clone + Send + Sync + 'static,
+    {
+        let name = handle.name().to_owned();
+        self.register(&name, move || {
+            if handle.is_finished() {
+                None
+            } else {
+                handle.last_heartbeat()
+            }
+        });
+    }
+    /// Returns names of the stalled sources
+    pub fn stalled_sources(&self, now: Instant) -> Vec<&str> {
+        self.sources
+            .iter()
+            .filter(|(_, f)| !self.is_fresh(f(), now))
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+    /// Returns true if all registered sources are healthy (also if there are no sources)
+    pub fn is_healthy(&self, now: Instant) -> bool {
+        self.sources.iter().all(|(_, f)| self.is_fresh(f(), now))
+    }
+    fn is_fresh(&self, last_heartbeat: Option<Instant>, now: Instant) -> bool {
+        last_heartbeat.map_or(false, |t| {
+            now.saturating_duration_since(t) <= self.stall_timeout
+        })
+    }
+    /// Returns true if the toggling is stopped because of a stalled source
+    pub fn is_stalled(&self) -> bool {
+        self.stalled
+    }
+    /// Processes a single step: toggles the output if the period is elapsed and all sources are
+    /// healthy, otherwise writes the "off" value once. Returns the health status. Should be
+    /// called at least twice per the period
+    pub fn step(&mut self, now: Instant) -> Result<bool> {
+        if !self.is_healthy(now) {
+            if !self.stalled {
+                error!(stalled = ?self.stalled_sources(now), "heartbeat stopped");
+                self.stalled = true;
+                self.output = false;
+                self.mapping.write(self.off.clone())?;
+            }
+            return Ok(false);
+        }
+        if self.stalled {
+            info!("heartbeat resumed");
+            self.stalled = false;
+            self.last_toggle = None;
+        }
+        if self
+            .last_toggle
+            .map_or(true, |t| now.saturating_duration_since(t) >= self.period)
+        {
+            self.output = !self.output;
+            self.last_toggle = Some(now);
+            let value = if self.output {
+                self.on.clone()
+            } else {
+                self.off.clone()
+            };
+            self.mapping.write(value)?;
+        }
+        Ok(true)
+    }
+    /// Runs the heartbeat loop in the current thread until the stop function returns true. The
+    /// output is set to the "off" value on exit
+    pub fn run<F: Fn() -> bool>(&mut self, stop: F) -> Result<()> {
+        let mut interval = crate::time::interval(self.period / 2);
+        while !stop() {
+            interval.tick();
+            if let Err(e) = self.step(Instant::now()) {
+                error!(%e, "heartbeat output write error");
+            }
+        }
+        self.output = false;
+        self.mapping.write(self.off.clone())
+    }
+    pub fn into_inner(self) -> M {
+        self.mapping
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use binrw::{BinRead, BinWrite};
+
+    use super::Heartbeat;
+    use crate::{io::IoMapping, Result};
+
+    #[derive(Default)]
+    struct SimulatedMapping {
+        writes: Vec<Vec<u8>>,
+    }
+
+    impl IoMapping for SimulatedMapping {
+        type Options = ();
+
+        fn read<T>(&mut self) -> Result<T>
+        where
+            T: for<'a> BinRead<Args<'a> = ()>,
+        {
+            unimplemented!()
+        }
+
+        fn write<T>(&mut self, value: T) -> Result<()>
+        where
+            T: for<'a> BinWrite<Args<'a> = ()>,
+        {
+            let mut buf = Cursor::new(Vec::new());
+            value.write_be(&mut buf)?;
+            self.writes.push(buf.into_inner());
+            Ok(())
+        }
+    }
+
+    const PERIOD: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn test_toggle_and_stall() {
+        let start = Instant::now();
+        let last_heartbeat = Arc::new(Mutex::new(Some(start)));
+        let mut heartbeat = Heartbeat::new(SimulatedMapping::default(), PERIOD)
+            .stall_timeout(Duration::from_millis(300));
+        let lh = last_heartbeat.clone();
+        heartbeat.register("worker", move || *lh.lock().unwrap());
+        for i in 0..7 {
+            assert!(heartbeat.step(start + PERIOD / 2 * i).unwrap());
+        }
+        // stalled
+        assert!(!heartbeat.step(start + Duration::from_millis(400)).unwrap());
+        assert!(!heartbeat.step(start + Duration::from_millis(500)).unwrap());
+        assert!(heartbeat.is_stalled());
+        assert_eq!(
+            heartbeat.stalled_sources(start + Duration::from_millis(500)),
+            ["worker"]
+        );
+        // recovered
+        let now = start + Duration::from_millis(600);
+        *last_heartbeat.lock().unwrap() = Some(now);
+        assert!(heartbeat.step(now).unwrap());
+        let writes: Vec<u8> = heartbeat.into_inner().writes.concat();
+        assert_eq!(writes, [1, 0, 1, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_custom_values() {
+        let mut heartbeat =
+            Heartbeat::with_values(SimulatedMapping::default(), PERIOD, 0xffffu16, 0u16);
+        heartbeat.register("never started", || None);
+        assert!(!heartbeat.step(Instant::now()).unwrap());
+        assert_eq!(heartbeat.into_inner().writes, [vec![0, 0]]);
+    }
+}
@@ -0,0 +1,7 @@
+//!
+//! Safety-related helpers. The helpers do not replace certified safety equipment, they are
+//! designed to feed it (e.g. external hardware watchdog relays).
+/// Heartbeat output pattern for external watchdog relays
+pub mod heartbeat;
+/// Safe-state routine: prioritized actions, executed within a time budget
+pub mod safe_state;
@@ -0,0 +1,283 @@
+//!
+//! Safe-state routine. Workers register safe-state actions (e.g. outputs to write, sequences to
+//! abort) with priorities, the routine is entered with [`SafeState::enter()`] (also available as
+//! [`crate::controller::Controller::enter_safe_state()`] and
+//! [`crate::controller::Context::enter_safe_state()`]) from signal handlers, watchdogs, alarms or
+//! remote commands.
+//!
+//! Actions are executed in the priority order (higher first) within a bounded time budget, the
+//! outcome is logged and recorded (see [`SafeState::last_report()`]).
+//!
+//! ```rust,no_run
+//! use roboplc::safety::safe_state::SafeState;
+//!
+//! let safe_state = SafeState::new();
+//! safe_state.register("valves", 100, || {
+//!     // close valves
+//!     Ok(())
+//! });
+//! safe_state.register("conveyor", 50, || {
+//!     // stop the conveyor
+//!     Ok(())
+//! });
+//! let report = safe_state.enter("emergency stop button");
+//! assert!(report.completed);
+//! ```
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc, Arc,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bma_ts::Timestamp;
+use parking_lot_rt::Mutex;
+use serde::Serialize;
+use tracing::{error, info, warn};
+
+use crate::Result;
+
+type ActionFn = Arc<dyn Fn() -> Result<()> + Send + Sync>;
+
+struct Action {
+    name: String,
+    priority: u8,
+    f: ActionFn,
+}
+
+/// Safe-state action outcome
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "status", content = "error")]
+pub enum SafeStateOutcome {
+    Ok,
+    Failed(String),
+    /// The action has not been completed within the time budget
+    NotCompleted,
+}
+
+/// Safe-state action result
+#[derive(Debug, Clone, Serialize)]
+pub struct SafeStateActionReport {
+    pub name: String,
+    pub priority: u8,
+    pub outcome: SafeStateOutcome,
+}
+
+/// Safe-state routine report
+#[derive(Debug, Clone, Serialize)]
+pub struct SafeStateReport {
+    pub reason: String,
+    pub time: Timestamp,
+    pub elapsed: Duration,
+    /// All actions have been completed within the time budget (some might have failed)
+    pub completed: bool,
+    pub actions: Vec<SafeStateActionReport>,
+}
+
+impl SafeStateReport {
+    /// Returns true if all actions have been completed successfully
+    pub fn is_ok(&self) -> bool {
+        self.completed
+            && self
+                .actions
+                .iter()
+                .all(|a| a.outcome == SafeStateOutcome::Ok)
+    }
+}
+
+struct Inner {
+    actions: Mutex<Vec<Action>>,
+    budget: Mutex<Duration>,
+    active: AtomicBool,
+    // serializes the routine executions
+    executing: Mutex<()>,
+    last_report: Mutex<Option<SafeStateReport>>,
+}
+
+/// Safe-state action registry. Can be cloned and shared with no limitations
+#[derive(Clone)]
+pub struct SafeState {
+    inner: Arc<Inner>,
+}
+
+impl Default for SafeState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SafeState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                actions: <_>::default(),
+                budget: Mutex::new(Duration::from_secs(1)),
+                active: <_>::default(),
+                executing: <_>::default(),
+                last_report: <_>::default(),
+            }),
+        }
+    }
+    /// Sets the total time budget for all actions (the default is 1 second)
+    pub fn set_budget(&self, budget: Duration) {
+        *self.inner.budget.lock() = budget;
+    }
+    /// Registers a safe-state action. Actions with higher priorities are executed first, actions
+    /// with equal priorities are executed in the registration order
+    pub fn register<F>(&self, name: &str, priority: u8, f: F)
+    where
+        F: Fn() -> Result<()> + Send + Sync + 'static,
+    {
+        let mut actions = self.inner.actions.lock();
+        actions.push(Action {
+            name: name.to_owned(),
+            priority,
+            f: Arc::new(f),
+        });
+        // stable sort keeps the registration order for equal priorities
+        actions.sort_by(|a, b| b.priority.cmp(&a.priority));
+    }
+    /// Executes safe-state actions. Returns when all actions are finished or the time budget is
+    /// exceeded (the remaining actions are still executed in background but reported as not
+    /// completed). Concurrent calls are executed one after another
+    pub fn enter(&self, reason: &str) -> SafeStateReport {
+        let _executing = self.inner.executing.lock();
+        self.inner.active.store(true, Ordering::SeqCst);
+        warn!(reason, "entering safe state");
+        let actions: Vec<(String, u8, ActionFn)> = self
+            .inner
+            .actions
+            .lock()
+            .iter()
+            .map(|a| (a.name.clone(), a.priority, a.f.clone()))
+            .collect();
+        let budget = *self.inner.budget.lock();
+        let time = Timestamp::now();
+        let started = Instant::now();
+        let mut reports: Vec<SafeStateActionReport> = actions
+            .iter()
+            .map(|(name, priority, _)| SafeStateActionReport {
+                name: name.clone(),
+                priority: *priority,
+                outcome: SafeStateOutcome::NotCompleted,
+            })
+            .collect();
+        let (tx, rx) = mpsc::channel();
+        let spawned = thread::Builder::new()
+            .name("safe_state".to_owned())
+            .spawn(move || {
+                for (i, (_, _, f)) in actions.into_iter().enumerate() {
+                    let outcome = match f() {
+                        Ok(()) => SafeStateOutcome::Ok,
+                        Err(e) => SafeStateOutcome::Failed(e.to_string()),
+                    };
+                    if tx.send((i, outcome)).is_err() {
+                        // the budget is exceeded, the results are no longer collected
+                        break;
+                    }
+                }
+            });
+        if let Err(error) = spawned {
+            error!(%error, "unable to spawn the safe-state routine");
+        } else {
+            while let Some(remaining) = budget.checked_sub(started.elapsed()) {
+                match rx.recv_timeout(remaining) {
+                    Ok((i, outcome)) => reports[i].outcome = outcome,
+                    Err(_) => break,
+                }
+            }
+        }
+        let report = SafeStateReport {
+            reason: reason.to_owned(),
+            time,
+            elapsed: started.elapsed(),
+            completed: !reports
+                .iter()
+                .any(|r| r.outcome == SafeStateOutcome::NotCompleted),
+            actions: reports,
+        };
+        for action in &report.actions {
+            match action.outcome {
+                SafeStateOutcome::Ok => {}
+                SafeStateOutcome::Failed(ref e) => {
+                    error!(action = %action.name, error = %e, "safe-state action failed");
+                }
+                SafeStateOutcome::NotCompleted => {
+                    error!(
+                        action = %action.name,
+                        "safe-state action not completed in time"
+                    );
+                }
+            }
+        }
+        if report.is_ok() {
+            info!(reason, elapsed = ?report.elapsed, "safe state entered");
+        } else {
+            error!(reason, elapsed = ?report.elapsed, "safe state entered with errors");
+        }
+        self.inner.last_report.lock().replace(report.clone());
+        report
+    }
+    /// Returns true if the safe state has been entered and not reset
+    pub fn is_active(&self) -> bool {
+        self.inner.active.load(Ordering::SeqCst)
+    }
+    /// Resets the active flag (e.g. after the cause has been eliminated by an operator)
+    pub fn reset(&self) {
+        if self.inner.active.swap(false, Ordering::SeqCst) {
+            info!("safe state reset");
+        }
+    }
+    /// The report of the last safe-state routine execution
+    pub fn last_report(&self) -> Option<SafeStateReport> {
+        self.inner.last_report.lock().clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{SafeState, SafeStateOutcome};
+    use crate::Error;
+
+    #[test]
+    fn test_safe_state() {
+        let safe_state = SafeState::new();
+        safe_state.set_budget(Duration::from_millis(200));
+        let order: Arc<Mutex<Vec<&str>>> = <_>::default();
+        for (name, priority) in [("low", 1), ("high", 10), ("high2", 10)] {
+            let o = order.clone();
+            safe_state.register(name, priority, move || {
+                o.lock().unwrap().push(name);
+                Ok(())
+            });
+        }
+        safe_state.register("failing", 5, || Err(Error::failed("output error")));
+        let report = safe_state.enter("test");
+        assert!(report.completed);
+        assert!(!report.is_ok());
+        assert_eq!(*order.lock().unwrap(), ["high", "high2", "low"]);
+        assert_eq!(
+            report.actions[2].outcome,
+            SafeStateOutcome::Failed("operation failed: output error".to_owned())
+        );
+        assert!(safe_state.is_active());
+        safe_state.reset();
+        assert!(!safe_state.is_active());
+        safe_state.register("slow", 0, || {
+            thread::sleep(Duration::from_secs(1));
+            Ok(())
+        });
+        let report = safe_state.enter("test timeout");
+        assert!(!report.completed);
+        assert!(report.elapsed < Duration::from_secs(1));
+        assert_eq!(
+            report.actions.last().unwrap().outcome,
+            SafeStateOutcome::NotCompleted
+        );
+        assert_eq!(safe_state.last_report().unwrap().reason, "test timeout");
+    }
+}
@@ -0,0 +1,549 @@
+//!
+//! Time-based scheduling for non-real-time housekeeping tasks (totalizer resets, reports,
+//! maintenance windows etc.). Jobs are defined with cron-style or interval schedules and either
+//! send hub messages or call functions.
+//!
+//! The scheduler is timezone-aware and can optionally persist the last fire times of the jobs to
+//! catch up missed runs after a downtime (see [`CatchUp`]).
+//!
+//! The scheduler MUST NOT be run in real-time threads as job callbacks may block and the
+//! scheduler itself relies on the system (wall) clock.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Local, LocalResult, NaiveDate, TimeZone, Timelike, Utc};
+use tracing::{error, warn};
+
+use crate::controller::{Context, SLEEP_STEP};
+use crate::{DataDeliveryPolicy, Error, Result};
+
+pub use chrono_tz::Tz;
+
+/// Maximum number of missed runs fired with [`CatchUp::All`] policy
+pub const MAX_CATCH_UP: usize = 1000;
+
+// the maximum period to look for the next cron schedule match
+const CRON_LOOKUP_DAYS: i64 = 366 * 5;
+
+// the current program time (accelerated in simulated runs, see crate::time::set_time_scale())
+fn utc_now() -> DateTime<Utc> {
+    let ts = crate::time::timestamp_now();
+    Utc.timestamp_nanos(i64::try_from(ts.as_nanos()).unwrap_or(i64::MAX))
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct BitSet(u64);
+
+impl BitSet {
+    fn contains(&self, value: u32) -> bool {
+        value < 64 && self.0 & (1 << value) != 0
+    }
+    fn is_full(&self, min: u32, max: u32) -> bool {
+        (min..=max).all(|v| self.contains(v))
+    }
+}
+
+/// Cron-style schedule. Standard 5-field format is supported: minute (0-59), hour (0-23), day of
+/// month (1-31), month (1-12), day of week (0-7, both 0 and 7 are Sunday). Fields may contain
+/// lists (`1,15`), ranges (`1-5`), steps (`*/15`, `0-30/10`) and wildcards.
+///
+/// The following macros are supported as well: `@yearly` (`@annually`), `@monthly`, `@weekly`,
+/// `@daily` (`@midnight`), `@hourly`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CronSchedule {
+    minutes: BitSet,
+    hours: BitSet,
+    days: BitSet,
+    months: BitSet,
+    weekdays: BitSet,
+    // true if the day-of-month field is restricted
+    days_restricted: bool,
+    // true if the day-of-week field is restricted
+    weekdays_restricted: bool,
+}
+
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Result<BitSet> {
+    let mut result = 0u64;
+    for part in field.split(',') {
+        let (range, step) = if let Some((range, step)) = part.split_once('/') {
+            let step: u32 = step.parse()?;
+            if step == 0 {
+                return Err(Error::invalid_data(format!("invalid step: {}", part)));
+            }
+            (range, step)
+        } else {
+            (part, 1)
+        };
+        let (from, to) = if range == "*" {
+            (min, max)
+        } else if let Some((from, to)) = range.split_once('-') {
+            (from.parse()?, to.parse()?)
+        } else {
+            let value: u32 = range.parse()?;
+            // "5/10" means "from 5 to max with step 10"
+            (value, if step > 1 { max } else { value })
+        };
+        if from < min || to > max || from > to {
+            return Err(Error::invalid_data(format!(
+                "value out of range {}-{}: {}",
+                min, max, part
+            )));
+        }
+        for value in (from..=to).step_by(step as usize) {
+            result |= 1 << value;
+        }
+    }
+    Ok(BitSet(result))
+}
+
+impl FromStr for CronSchedule {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let s = match s.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            v => v,
+        };
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(Error::invalid_data(format!(
+                "invalid cron expression (5 fields expected): {}",
+                s
+            )));
+        }
+        let mut weekdays = parse_cron_field(fields[4], 0, 7)?;
+        if weekdays.contains(7) {
+            weekdays.0 |= 1;
+        }
+        let days = parse_cron_field(fields[2], 1, 31)?;
+        Ok(Self {
+            minutes: parse_cron_field(fields[0], 0, 59)?,
+            hours: parse_cron_field(fields[1], 0, 23)?,
+            days_restricted: !days.is_full(1, 31),
+            weekdays_restricted: !weekdays.is_full(0, 6),
+            days,
+            months: parse_cron_field(fields[3], 1, 12)?,
+            weekdays,
+        })
+    }
+}
+
+impl CronSchedule {
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day = self.days.contains(date.day());
+        let weekday = self
+            .weekdays
+            .contains(date.weekday().num_days_from_sunday());
+        // the standard cron behavior: if both fields are restricted, either one must match
+        if self.days_restricted && self.weekdays_restricted {
+            day || weekday
+        } else {
+            day && weekday
+        }
+    }
+    /// Returns the next fire time after the given one in the given timezone. Local times which
+    /// do not exist (DST gaps) are skipped, ambiguous ones (DST overlaps) are fired once
+    pub fn next_after<T: TimeZone>(&self, after: &DateTime<Utc>, tz: &T) -> Option<DateTime<Utc>> {
+        let local = after.with_timezone(tz).naive_local();
+        let mut t = local.date().and_hms_opt(local.hour(), local.minute(), 0)?
+            + chrono::Duration::minutes(1);
+        let limit = t + chrono::Duration::days(CRON_LOOKUP_DAYS);
+        while t < limit {
+            let date = t.date();
+            if !self.months.contains(date.month()) {
+                let (year, month) = if date.month() == 12 {
+                    (date.year() + 1, 1)
+                } else {
+                    (date.year(), date.month() + 1)
+                };
+                t = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if !self.day_matches(date) {
+                t = date.succ_opt()?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if !self.hours.contains(t.hour()) {
+                t = date.and_hms_opt(t.hour(), 0, 0)? + chrono::Duration::hours(1);
+                continue;
+            }
+            if self.minutes.contains(t.minute()) {
+                match tz.from_local_datetime(&t) {
+                    LocalResult::Single(dt) | LocalResult::Ambiguous(dt, _) => {
+                        let dt = dt.with_timezone(&Utc);
+                        if dt > *after {
+                            return Some(dt);
+                        }
+                    }
+                    LocalResult::None => {}
+                }
+            }
+            t += chrono::Duration::minutes(1);
+        }
+        None
+    }
+}
+
+/// Job schedule
+#[derive(Clone, Debug)]
+pub enum Schedule {
+    /// Cron-style schedule
+    Cron(CronSchedule),
+    /// Fixed interval, the first run is performed one interval after the scheduler start
+    Interval(Duration),
+}
+
+impl Schedule {
+    /// Parses a cron-style schedule
+    pub fn cron(expr: &str) -> Result<Self> {
+        expr.parse().map(Schedule::Cron)
+    }
+    /// Creates an interval schedule
+    pub fn interval(interval: Duration) -> Self {
+        Schedule::Interval(interval)
+    }
+    fn next_after(&self, after: &DateTime<Utc>, timezone: Timezone) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Cron(cron) => match timezone {
+                Timezone::Local => cron.next_after(after, &Local),
+                Timezone::Utc => cron.next_after(after, &Utc),
+                Timezone::Tz(tz) => cron.next_after(after, &tz),
+            },
+            Schedule::Interval(interval) => {
+                let interval = chrono::Duration::from_std(*interval).ok()?;
+                if interval <= chrono::Duration::zero() {
+                    return None;
+                }
+                Some(*after + interval)
+            }
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Schedule::cron(s)
+    }
+}
+
+/// Timezone cron schedules are evaluated in
+#[derive(Default, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Timezone {
+    /// The system local timezone
+    #[default]
+    Local,
+    /// UTC
+    Utc,
+    /// A named timezone (e.g. `Europe/Berlin`)
+    Tz(Tz),
+}
+
+impl FromStr for Timezone {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "local" => Ok(Timezone::Local),
+            "UTC" | "utc" => Ok(Timezone::Utc),
+            v => v.parse().map(Timezone::Tz).map_err(Error::invalid_data),
+        }
+    }
+}
+
+/// Catch-up policy for runs missed while the program was not running. Requires the scheduler
+/// state file to be set (see [`Scheduler::state_file()`])
+#[derive(Default, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CatchUp {
+    /// Missed runs are skipped
+    #[default]
+    Skip,
+    /// If there are missed runs, the job is fired once
+    Once,
+    /// The job is fired for every missed run (up to [`MAX_CATCH_UP`])
+    All,
+}
+
+enum Action<D> {
+    Message(D),
+    Callback(Box<dyn FnMut() + Send>),
+}
+
+/// A scheduled job
+pub struct Job<D> {
+    name: String,
+    schedule: Schedule,
+    timezone: Timezone,
+    catch_up: CatchUp,
+    action: Action<D>,
+}
+
+impl<D> Job<D> {
+    /// Creates a job which sends a hub message
+    pub fn message(name: &str, schedule: Schedule, message: D) -> Self {
+        Self::create(name, schedule, Action::Message(message))
+    }
+    /// Creates a job which calls a function. The function is called in the scheduler thread so it
+    /// SHOULD NOT block for a long time
+    pub fn callback<F>(name: &str, schedule: Schedule, f: F) -> Self
+    where
+        F: FnMut() + Send + 'static,
+    {
+        Self::create(name, schedule, Action::Callback(Box::new(f)))
+    }
+    fn create(name: &str, schedule: Schedule, action: Action<D>) -> Self {
+        Self {
+            name: name.to_owned(),
+            schedule,
+            timezone: Timezone::default(),
+            catch_up: CatchUp::default(),
+            action,
+        }
+    }
+    /// Sets the timezone (the default is the system local one)
+    pub fn timezone(mut self, timezone: Timezone) -> Self {
+        self.timezone = timezone;
+        self
+    }
+    /// Sets the catch-up policy (the default is [`CatchUp::Skip`])
+    pub fn catch_up(mut self, catch_up: CatchUp) -> Self {
+        self.catch_up = catch_up;
+        self
+    }
+}
+
+impl<D> fmt::Debug for Job<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Job")
+            .field("name", &self.name)
+            .field("schedule", &self.schedule)
+            .field("timezone", &self.timezone)
+            .field("catch_up", &self.catch_up)
+            .finish_non_exhaustive()
+    }
+}
+
+struct Entry<D> {
+    job: Job<D>,
+    next: Option<DateTime<Utc>>,
+}
+
+/// Jobs scheduler, requires to be run in a separate (non-real-time) thread manually
+pub struct Scheduler<D> {
+    entries: Vec<Entry<D>>,
+    state_file: Option<PathBuf>,
+    last_runs: BTreeMap<String, i64>,
+}
+
+impl<D> Default for Scheduler<D> {
+    fn default() -> Self {
+        Self {
+            entries: <_>::default(),
+            state_file: None,
+            last_runs: <_>::default(),
+        }
+    }
+}
+
+impl<D> Scheduler<D>
+where
+    D: DataDeliveryPolicy + Clone + Send + Sync + 'static,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets the state file to persist the last fire times of the jobs. Required for catch-up
+    /// policies to work
+    pub fn state_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.state_file = Some(path.as_ref().to_owned());
+        self
+    }
+    /// Registers a job. The job name MUST be unique
+    pub fn add_job(&mut self, job: Job<D>) -> Result<()> {
+        if self.entries.iter().any(|e| e.job.name == job.name) {
+            return Err(Error::invalid_data(format!(
+                "job already registered: {}",
+                job.name
+            )));
+        }
+        self.entries.push(Entry { job, next: None });
+        Ok(())
+    }
+    /// Returns the next fire time of a job
+    pub fn next_run(&self, name: &str) -> Option<DateTime<Utc>> {
+        self.entries
+            .iter()
+            .find(|e| e.job.name == name)
+            .and_then(|e| e.next)
+    }
+    /// Runs the scheduler until the controller goes offline. Blocks the current thread
+    pub fn run<V: Send>(&mut self, context: &Context<D, V>) -> Result<()> {
+        self.load_state()?;
+        let now = utc_now();
+        for i in 0..self.entries.len() {
+            self.catch_up(i, &now, context);
+            let entry = &mut self.entries[i];
+            entry.next = entry.job.schedule.next_after(&now, entry.job.timezone);
+        }
+        while context.is_online() {
+            let now = utc_now();
+            let mut fired = false;
+            for i in 0..self.entries.len() {
+                let Some(due) = self.entries[i].next else {
+                    continue;
+                };
+                if due > now {
+                    continue;
+                }
+                self.fire(i, context);
+                self.last_runs
+                    .insert(self.entries[i].job.name.clone(), due.timestamp());
+                fired = true;
+                let entry = &mut self.entries[i];
+                let mut next = entry.job.schedule.next_after(&due, entry.job.timezone);
+                if next.map_or(false, |n| n <= now) {
+                    // the scheduler is late (e.g. the system clock jumped), do not try to catch up
+                    next = entry.job.schedule.next_after(&now, entry.job.timezone);
+                }
+                entry.next = next;
+            }
+            if fired {
+                self.save_state();
+            }
+            let sleep = self
+                .entries
+                .iter()
+                .filter_map(|e| e.next)
+                .min()
+                .and_then(|next| (next - utc_now()).to_std().ok())
+                .map_or(SLEEP_STEP, |d| d.min(SLEEP_STEP));
+            crate::time::sleep(sleep);
+        }
+        Ok(())
+    }
+    fn catch_up<V: Send>(&mut self, i: usize, now: &DateTime<Utc>, context: &Context<D, V>) {
+        let entry = &self.entries[i];
+        let Some(last) = self
+            .last_runs
+            .get(&entry.job.name)
+            .and_then(|ts| Utc.timestamp_opt(*ts, 0).single())
+        else {
+            return;
+        };
+        let mut missed = 0;
+        let mut t = last;
+        while let Some(next) = entry.job.schedule.next_after(&t, entry.job.timezone) {
+            if next > *now || missed >= MAX_CATCH_UP {
+                break;
+            }
+            missed += 1;
+            t = next;
+        }
+        if missed == 0 {
+            return;
+        }
+        let runs = match entry.job.catch_up {
+            CatchUp::Skip => 0,
+            CatchUp::Once => 1,
+            CatchUp::All => missed,
+        };
+        warn!(
+            job = entry.job.name,
+            missed, runs, "scheduled job runs missed"
+        );
+        for _ in 0..runs {
+            self.fire(i, context);
+        }
+        if runs > 0 {
+            self.last_runs
+                .insert(self.entries[i].job.name.clone(), t.timestamp());
+            self.save_state();
+        }
+    }
+    fn fire<V: Send>(&mut self, i: usize, context: &Context<D, V>) {
+        match self.entries[i].job.action {
+            Action::Message(ref message) => context.hub().send(message.clone()),
+            Action::Callback(ref mut f) => f(),
+        }
+    }
+    fn load_state(&mut self) -> Result<()> {
+        let Some(ref path) = self.state_file else {
+            return Ok(());
+        };
+        let contents = match fs::read_to_string(path) {
+            Ok(v) => v,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        for line in contents.lines() {
+            let Some((name, ts)) = line.rsplit_once(' ') else {
+                continue;
+            };
+            match ts.parse() {
+                Ok(ts) => {
+                    self.last_runs.insert(name.to_owned(), ts);
+                }
+                Err(e) => warn!(job = name, error=%e, "invalid scheduler state record"),
+            }
+        }
+        Ok(())
+    }
+    fn save_state(&self) {
+        let Some(ref path) = self.state_file else {
+            return;
+        };
+        let contents: String = self
+            .last_runs
+            .iter()
+            .map(|(name, ts)| format!("{} {}\n", name, ts))
+            .collect();
+        // write to a temporary file first to keep the state consistent on power loss
+        let tmp = path.with_extension("tmp");
+        if let Err(e) = fs::write(&tmp, contents).and_then(|()| fs::rename(&tmp, path)) {
+            error!(path=%path.display(), error=%e, "unable to save scheduler state");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CronSchedule, Schedule, Timezone, Tz};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_cron_next() {
+        let cron: CronSchedule = "*/15 9-17 * * 1-5".parse().unwrap();
+        // Friday
+        let after = Utc.with_ymd_and_hms(2024, 3, 15, 17, 50, 0).unwrap();
+        assert_eq!(
+            cron.next_after(&after, &Utc),
+            Some(Utc.with_ymd_and_hms(2024, 3, 18, 9, 0, 0).unwrap())
+        );
+        let daily = Schedule::cron("@daily").unwrap();
+        let after = Utc.with_ymd_and_hms(2024, 12, 31, 23, 0, 0).unwrap();
+        assert_eq!(
+            daily.next_after(&after, Timezone::Utc),
+            Some(Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap())
+        );
+        assert!("60 * * * *".parse::<CronSchedule>().is_err());
+        assert!("* * *".parse::<CronSchedule>().is_err());
+    }
+
+    #[test]
+    fn test_cron_dst() {
+        // 02:30 does not exist in Europe/Berlin on 2024-03-31
+        let cron: CronSchedule = "30 2 * * *".parse().unwrap();
+        let tz: Tz = "Europe/Berlin".parse().unwrap();
+        let after = Utc.with_ymd_and_hms(2024, 3, 30, 12, 0, 0).unwrap();
+        assert_eq!(
+            cron.next_after(&after, &tz),
+            Some(Utc.with_ymd_and_hms(2024, 4, 1, 0, 30, 0).unwrap())
+        );
+    }
+}
@@ -0,0 +1,411 @@
+//!
+//! Schema export of controller variables and hub message types. The schema contains field names,
+//! types, units and access modes and is used by the RoboPLC manager and HMI builders to generate
+//! value browsers automatically.
+//!
+//! Example:
+//!
+//! ```rust,no_run
+//! use roboplc::schema::{Registry, Schema};
+//!
+//! #[derive(Schema, Default)]
+//! struct Variables {
+//!     /// Oven temperature
+//!     #[schema(unit = "°C")]
+//!     temperature: f32,
+//!     #[schema(access = "rw")]
+//!     setpoint: f32,
+//!     #[schema(skip)]
+//!     internal_counter: u64,
+//! }
+//!
+//! #[derive(Schema, Clone)]
+//! enum Message {
+//!     Temperature(f32),
+//!     Alarm { code: u16 },
+//! }
+//!
+//! Registry::new()
+//!     .variables::<Variables>()
+//!     .messages::<Message>()
+//!     .save_to_data_dir()
+//!     .unwrap();
+//! ```
+//!
+//! Hub bridges and message recordings should be protected against schema drift with
+//! [`SchemaVersion`]: a type name and a hash of the message type schema. Bridge peers exchange
+//! versions with [`SchemaVersion::negotiate()`] on connect, recordings start with a header,
+//! written by [`SchemaVersion::write_header()`] and checked by [`SchemaVersion::check_header()`].
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+
+use crate::{Error, Result};
+
+pub use roboplc_derive::Schema;
+
+/// Default program data directory
+pub const DEFAULT_DATA_DIR: &str = "/var/roboplc/data";
+
+/// Schema file name in the program data directory
+pub const SCHEMA_FILE_NAME: &str = "schema.json";
+
+/// Returns the program data directory (`ROBOPLC_DATA_DIR` environment variable or
+/// [`DEFAULT_DATA_DIR`])
+pub fn data_dir() -> PathBuf {
+    std::env::var_os("ROBOPLC_DATA_DIR").map_or_else(|| DEFAULT_DATA_DIR.into(), PathBuf::from)
+}
+
+/// Types which provide own schema, usually implemented with the derive macro
+pub trait Schema {
+    fn schema() -> TypeSchema;
+}
+
+/// Field access mode
+#[derive(Serialize, Default, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Access {
+    /// Read-only
+    #[default]
+    #[serde(rename = "r")]
+    Read,
+    /// Write-only
+    #[serde(rename = "w")]
+    Write,
+    /// Read-write
+    #[serde(rename = "rw")]
+    ReadWrite,
+}
+
+/// Field schema
+#[derive(Serialize, Clone, Debug)]
+pub struct Field {
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit: Option<String>,
+    access: Access,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+}
+
+impl Field {
+    pub fn new(name: &str, kind: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            kind: kind.to_owned(),
+            unit: None,
+            access: Access::default(),
+            description: None,
+        }
+    }
+    pub fn unit(mut self, unit: &str) -> Self {
+        self.unit = Some(unit.to_owned());
+        self
+    }
+    pub fn access(mut self, access: Access) -> Self {
+        self.access = access;
+        self
+    }
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_owned());
+        self
+    }
+}
+
+/// Enum variant schema
+#[derive(Serialize, Clone, Debug)]
+pub struct Variant {
+    name: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<Field>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+}
+
+impl Variant {
+    pub fn new(name: &str, fields: Vec<Field>) -> Self {
+        Self {
+            name: name.to_owned(),
+            fields,
+            description: None,
+        }
+    }
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_owned());
+        self
+    }
+}
+
+/// Type schema
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum TypeSchema {
+    Struct {
+        name: String,
+        fields: Vec<Field>,
+    },
+    Enum {
+        name: String,
+        variants: Vec<Variant>,
+    },
+}
+
+impl TypeSchema {
+    pub fn name(&self) -> &str {
+        match self {
+            TypeSchema::Struct { name, .. } | TypeSchema::Enum { name, .. } => name,
+        }
+    }
+}
+
+/// Schema registry
+#[derive(Serialize, Default, Clone, Debug)]
+pub struct Registry {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    program: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    variables: Option<TypeSchema>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    messages: Option<TypeSchema>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    types: BTreeMap<String, TypeSchema>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets the program name and version
+    pub fn program(mut self, name: &str, version: &str) -> Self {
+        self.program = Some(name.to_owned());
+        self.version = Some(version.to_owned());
+        self
+    }
+    /// Sets the controller variables type
+    pub fn variables<V: Schema>(mut self) -> Self {
+        self.variables = Some(V::schema());
+        self
+    }
+    /// Sets the hub message type
+    pub fn messages<D: Schema>(mut self) -> Self {
+        self.messages = Some(D::schema());
+        self
+    }
+    /// Adds a custom type, e.g. a structure used in variable fields or messages
+    pub fn add_type<T: Schema>(mut self) -> Self {
+        let schema = T::schema();
+        self.types.insert(schema.name().to_owned(), schema);
+        self
+    }
+    /// Serializes the schema to JSON
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).map_err(crate::Error::invalid_data)
+    }
+    /// Saves the schema to a file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+    /// Saves the schema to the program data directory (see [`data_dir()`]). Returns the file path
+    pub fn save_to_data_dir(&self) -> Result<PathBuf> {
+        let dir = data_dir();
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(SCHEMA_FILE_NAME);
+        self.save(&path)?;
+        Ok(path)
+    }
+}
+
+const HEADER_MAGIC: &[u8; 4] = b"RPSV";
+const HEADER_VERSION: u8 = 1;
+
+static SCHEMA_MISMATCHES: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the number of schema mismatches, detected by [`SchemaVersion`] checks since the
+/// program start
+pub fn schema_mismatches() -> u64 {
+    SCHEMA_MISMATCHES.load(Ordering::Relaxed)
+}
+
+/// Message schema version: the type name and a hash of the type schema. The hash covers type,
+/// field and variant names and field types only, descriptions, units and access modes do not
+/// affect compatibility
+#[derive(Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct SchemaVersion {
+    type_name: String,
+    hash: u64,
+}
+
+impl SchemaVersion {
+    pub fn new(type_name: &str, hash: u64) -> Self {
+        Self {
+            type_name: type_name.to_owned(),
+            hash,
+        }
+    }
+    /// Schema version of a type
+    pub fn of<T: Schema>() -> Self {
+        Self::from_schema(&T::schema())
+    }
+    pub fn from_schema(schema: &TypeSchema) -> Self {
+        let mut hasher = Fnv1a::new();
+        match schema {
+            TypeSchema::Struct { name, fields } => {
+                hasher.write(b"struct");
+                hasher.write(name.as_bytes());
+                hash_fields(&mut hasher, fields);
+            }
+            TypeSchema::Enum { name, variants } => {
+                hasher.write(b"enum");
+                hasher.write(name.as_bytes());
+                for variant in variants {
+                    hasher.write(b"variant");
+                    hasher.write(variant.name.as_bytes());
+                    hash_fields(&mut hasher, &variant.fields);
+                }
+            }
+        }
+        Self {
+            type_name: schema.name().to_owned(),
+            hash: hasher.finish(),
+        }
+    }
+    pub fn type_name(&self) -> &str {
+        &self.type_name
+    }
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+    /// Checks if the peer/recording schema version is compatible with the local one
+    pub fn check(&self, other: &SchemaVersion) -> Result<()> {
+        if self == other {
+            Ok(())
+        } else {
+            SCHEMA_MISMATCHES.fetch_add(1, Ordering::Relaxed);
+            Err(Error::SchemaMismatch(format!(
+                "local {}, remote {}",
+                self, other
+            )))
+        }
+    }
+    /// Writes a binary schema header (used for recordings and bridge negotiation)
+    pub fn write_header<W: Write>(&self, mut writer: W) -> Result<()> {
+        let name_len = u16::try_from(self.type_name.len())
+            .map_err(|_| Error::invalid_data("type name too long"))?;
+        let mut buf = Vec::with_capacity(15 + self.type_name.len());
+        buf.extend(HEADER_MAGIC);
+        buf.push(HEADER_VERSION);
+        buf.extend(self.hash.to_le_bytes());
+        buf.extend(name_len.to_le_bytes());
+        buf.extend(self.type_name.as_bytes());
+        writer.write_all(&buf)?;
+        writer.flush()?;
+        Ok(())
+    }
+    /// Reads a binary schema header
+    pub fn read_header<R: Read>(mut reader: R) -> Result<Self> {
+        let mut buf = [0u8; 15];
+        reader.read_exact(&mut buf)?;
+        if &buf[..4] != HEADER_MAGIC {
+            return Err(Error::SchemaMismatch(
+                "no schema header (unversioned peer or recording)".to_owned(),
+            ));
+        }
+        if buf[4] != HEADER_VERSION {
+            return Err(Error::SchemaMismatch(format!(
+                "unsupported schema header version: {}",
+                buf[4]
+            )));
+        }
+        let hash = u64::from_le_bytes(buf[5..13].try_into().unwrap());
+        let name_len = u16::from_le_bytes(buf[13..15].try_into().unwrap());
+        let mut name = vec![0u8; usize::from(name_len)];
+        reader.read_exact(&mut name)?;
+        let type_name = String::from_utf8(name).map_err(Error::invalid_data)?;
+        Ok(Self { type_name, hash })
+    }
+    /// Reads a schema header (e.g. from a recording) and checks if it is compatible with the local
+    /// schema version
+    pub fn check_header<R: Read>(&self, reader: R) -> Result<()> {
+        let other = Self::read_header(reader)?;
+        self.check(&other)
+    }
+    /// Exchanges schema versions with a bridge peer on connect. Both peers must call the method,
+    /// the stream must not be used if an error is returned
+    pub fn negotiate<S: Read + Write>(&self, stream: &mut S) -> Result<()> {
+        self.write_header(&mut *stream)?;
+        self.check_header(stream)
+    }
+}
+
+impl fmt::Display for SchemaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}#{:016x}", self.type_name, self.hash)
+    }
+}
+
+fn hash_fields(hasher: &mut Fnv1a, fields: &[Field]) {
+    for field in fields {
+        hasher.write(b"field");
+        hasher.write(field.name.as_bytes());
+        hasher.write(field.kind.as_bytes());
+    }
+}
+
+// a stable hash, std hashers are not guaranteed to be stable between Rust releases
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+    fn write(&mut self, data: &[u8]) {
+        for b in data.iter().chain(&[0xff]) {
+            self.0 ^= u64::from(*b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Field, SchemaVersion, TypeSchema, Variant};
+
+    fn message(kind: &str, description: &str) -> TypeSchema {
+        TypeSchema::Enum {
+            name: "Message".to_owned(),
+            variants: vec![
+                Variant::new("Temperature", vec![Field::new("0", kind)]),
+                Variant::new(
+                    "Alarm",
+                    vec![Field::new("code", "u16").description(description)],
+                ),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_schema_version() {
+        let v1 = SchemaVersion::from_schema(&message("f32", "alarm code"));
+        let v2 = SchemaVersion::from_schema(&message("f32", "the alarm code"));
+        let v3 = SchemaVersion::from_schema(&message("f64", "alarm code"));
+        assert_eq!(v1, v2);
+        assert!(v1.check(&v3).is_err());
+        let mut buf = Vec::new();
+        v1.write_header(&mut buf).unwrap();
+        assert_eq!(SchemaVersion::read_header(buf.as_slice()).unwrap(), v1);
+        assert!(v3.check_header(buf.as_slice()).is_err());
+        assert!(SchemaVersion::read_header(&b"garbage data"[..]).is_err());
+    }
+}
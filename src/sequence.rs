@@ -0,0 +1,548 @@
+//!
+//! Step sequence (recipe) executor for batch-style machines (fill → heat → hold → drain).
+//!
+//! A sequence is a list of declarative steps. Each step has an optional entry action, a
+//! completion condition, an optional minimum duration and an optional timeout. Conditions and
+//! actions work with controller variables (or any other state type), so steps can react on data
+//! received from hub messages as soon as the data is stored in the variables.
+//!
+//! The sequence is executed by calling [`Sequence::tick()`] periodically, e.g. from a worker
+//! interval loop. The sequence is controlled (start/pause/resume/abort) with
+//! [`SequenceControl`], which can be cloned and passed to other workers (e.g. an HMI/API handler).
+//! When the sequence is aborted (by a command or a step timeout), the safe-state function is
+//! called.
+//!
+//! Example:
+//!
+//! ```rust
+//! use roboplc::sequence::{Sequence, Step};
+//! use std::time::Duration;
+//!
+//! #[derive(Default)]
+//! struct Variables {
+//!     fill_valve: bool,
+//!     heater: bool,
+//!     level: f32,
+//!     temperature: f32,
+//! }
+//!
+//! let mut seq = Sequence::new("batch")
+//!     .step(
+//!         Step::new("fill")
+//!             .action(|v: &mut Variables| v.fill_valve = true)
+//!             .until(|v| v.level >= 80.0)
+//!             .timeout(Duration::from_secs(120)),
+//!     )
+//!     .step(
+//!         Step::new("heat")
+//!             .action(|v: &mut Variables| {
+//!                 v.fill_valve = false;
+//!                 v.heater = true;
+//!             })
+//!             .until(|v| v.temperature >= 60.0)
+//!             .timeout(Duration::from_secs(600)),
+//!     )
+//!     .step(Step::new("hold").min_duration(Duration::from_secs(300)))
+//!     .safe_state(|v| {
+//!         v.fill_valve = false;
+//!         v.heater = false;
+//!     });
+//! let control = seq.control();
+//! control.start();
+//! let mut vars = Variables::default();
+//! seq.tick(&mut vars);
+//! assert!(vars.fill_valve);
+//! ```
+//!
+//! The current step can be persisted across restarts (see [`Sequence::persistence()`]). A
+//! sequence, which has been running or paused before the restart, is restored as paused at the
+//! same step and must be resumed explicitly. The step entry action is executed again on resume.
+use std::{
+    fmt, fs,
+    io::Write as _,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::{Error, Result};
+
+type ActionFn<V> = Box<dyn Fn(&mut V) + Send + Sync>;
+type ConditionFn<V> = Box<dyn Fn(&V) -> bool + Send + Sync>;
+type StatusFn = Box<dyn FnMut(&SequenceStatus) + Send>;
+
+/// Sequence step
+pub struct Step<V> {
+    name: String,
+    action: Option<ActionFn<V>>,
+    until: Option<ConditionFn<V>>,
+    min_duration: Duration,
+    timeout: Option<Duration>,
+}
+
+impl<V> Step<V> {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            action: None,
+            until: None,
+            min_duration: Duration::ZERO,
+            timeout: None,
+        }
+    }
+    /// Sets the entry action, which is executed once when the step is entered
+    pub fn action<F>(mut self, action: F) -> Self
+    where
+        F: Fn(&mut V) + Send + Sync + 'static,
+    {
+        self.action = Some(Box::new(action));
+        self
+    }
+    /// Sets the completion condition. A step without a condition is completed as soon as its
+    /// minimum duration has passed
+    pub fn until<F>(mut self, condition: F) -> Self
+    where
+        F: Fn(&V) -> bool + Send + Sync + 'static,
+    {
+        self.until = Some(Box::new(condition));
+        self
+    }
+    /// Sets the minimum step duration (the default is zero)
+    pub fn min_duration(mut self, min_duration: Duration) -> Self {
+        self.min_duration = min_duration;
+        self
+    }
+    /// Sets the step timeout. If the step is not completed in time, the sequence is aborted (the
+    /// default is no timeout)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Sequence state
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SequenceState {
+    #[default]
+    Idle,
+    Running,
+    Paused,
+    Completed,
+    Aborted,
+}
+
+impl SequenceState {
+    /// Returns true if the sequence is active (running or paused)
+    pub fn is_active(self) -> bool {
+        matches!(self, SequenceState::Running | SequenceState::Paused)
+    }
+}
+
+impl fmt::Display for SequenceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                SequenceState::Idle => "idle",
+                SequenceState::Running => "running",
+                SequenceState::Paused => "paused",
+                SequenceState::Completed => "completed",
+                SequenceState::Aborted => "aborted",
+            }
+        )
+    }
+}
+
+impl FromStr for SequenceState {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "idle" => Ok(SequenceState::Idle),
+            "running" => Ok(SequenceState::Running),
+            "paused" => Ok(SequenceState::Paused),
+            "completed" => Ok(SequenceState::Completed),
+            "aborted" => Ok(SequenceState::Aborted),
+            _ => Err(Error::invalid_data(format!(
+                "invalid sequence state: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// Abort reason
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AbortReason {
+    /// Aborted by [`SequenceControl::abort()`]
+    Command,
+    /// A step timeout has been reached
+    Timeout,
+}
+
+/// Sequence status, passed to the status function (see [`Sequence::on_status()`]) on each
+/// change. Can be serialized or converted into a hub message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SequenceStatus {
+    pub sequence: String,
+    pub state: SequenceState,
+    /// The current step (the last one for completed/aborted sequences)
+    pub step: Option<String>,
+    pub step_index: Option<usize>,
+    pub abort_reason: Option<AbortReason>,
+}
+
+const CMD_NONE: u8 = 0;
+const CMD_START: u8 = 1;
+const CMD_PAUSE: u8 = 2;
+const CMD_RESUME: u8 = 3;
+const CMD_ABORT: u8 = 4;
+
+/// Sequence control handle. Commands are processed at the next sequence tick, if several
+/// commands are sent between ticks, the last one is processed
+#[derive(Clone, Default)]
+pub struct SequenceControl {
+    command: Arc<AtomicU8>,
+}
+
+impl SequenceControl {
+    /// Starts the sequence from the first step. Ignored if the sequence is active
+    pub fn start(&self) {
+        self.command.store(CMD_START, Ordering::SeqCst);
+    }
+    /// Pauses the running sequence. Step timers are stopped while the sequence is paused
+    pub fn pause(&self) {
+        self.command.store(CMD_PAUSE, Ordering::SeqCst);
+    }
+    /// Resumes the paused sequence
+    pub fn resume(&self) {
+        self.command.store(CMD_RESUME, Ordering::SeqCst);
+    }
+    /// Aborts the active sequence and calls the safe-state function
+    pub fn abort(&self) {
+        self.command.store(CMD_ABORT, Ordering::SeqCst);
+    }
+    fn take(&self) -> u8 {
+        self.command.swap(CMD_NONE, Ordering::SeqCst)
+    }
+}
+
+/// Step sequence executor
+pub struct Sequence<V> {
+    name: String,
+    steps: Vec<Step<V>>,
+    safe_state: Option<ActionFn<V>>,
+    on_status: Option<StatusFn>,
+    persistence: Option<PathBuf>,
+    control: SequenceControl,
+    state: SequenceState,
+    step: usize,
+    // entry action has been executed
+    entered: bool,
+    step_elapsed: Duration,
+    last_tick: Option<Instant>,
+    abort_reason: Option<AbortReason>,
+}
+
+impl<V> Sequence<V> {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            steps: Vec::new(),
+            safe_state: None,
+            on_status: None,
+            persistence: None,
+            control: SequenceControl::default(),
+            state: SequenceState::Idle,
+            step: 0,
+            entered: false,
+            step_elapsed: Duration::ZERO,
+            last_tick: None,
+            abort_reason: None,
+        }
+    }
+    /// Adds a step
+    pub fn step(mut self, step: Step<V>) -> Self {
+        self.steps.push(step);
+        self
+    }
+    /// Sets the safe-state function, which is called when the sequence is aborted
+    pub fn safe_state<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&mut V) + Send + Sync + 'static,
+    {
+        self.safe_state = Some(Box::new(f));
+        self
+    }
+    /// Sets the status function, which is called on each state or step change, e.g. to send the
+    /// status to the hub
+    pub fn on_status<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(&SequenceStatus) + Send + 'static,
+    {
+        self.on_status = Some(Box::new(f));
+        self
+    }
+    /// Persists the current state and step in a file and restores them. Active sequences are
+    /// restored as paused
+    pub fn persistence<P: AsRef<Path>>(mut self, path: P) -> Result<Self> {
+        let path = path.as_ref().to_owned();
+        match fs::read_to_string(&path) {
+            Ok(s) => {
+                let (state, step) = decode_persistent(&s)?;
+                if step >= self.steps.len() {
+                    return Err(Error::invalid_data(format!(
+                        "persisted sequence step out of range: {}",
+                        step
+                    )));
+                }
+                self.state = if state.is_active() {
+                    SequenceState::Paused
+                } else {
+                    state
+                };
+                self.step = step;
+                info!(
+                    sequence = self.name,
+                    state = %self.state,
+                    step = self.steps[step].name,
+                    "sequence restored"
+                );
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        self.persistence = Some(path);
+        Ok(self)
+    }
+    /// Returns the control handle
+    pub fn control(&self) -> SequenceControl {
+        self.control.clone()
+    }
+    pub fn state(&self) -> SequenceState {
+        self.state
+    }
+    /// The current step (the last one for completed/aborted sequences)
+    pub fn current_step(&self) -> Option<&Step<V>> {
+        if self.state == SequenceState::Idle {
+            None
+        } else {
+            self.steps.get(self.step)
+        }
+    }
+    pub fn status(&self) -> SequenceStatus {
+        let step = self.current_step();
+        SequenceStatus {
+            sequence: self.name.clone(),
+            state: self.state,
+            step: step.map(|s| s.name.clone()),
+            step_index: step.map(|_| self.step),
+            abort_reason: self.abort_reason,
+        }
+    }
+    /// Processes pending control commands and executes the current step. Must be called
+    /// periodically
+    pub fn tick(&mut self, vars: &mut V) -> SequenceState {
+        let now = Instant::now();
+        match self.control.take() {
+            CMD_START if !self.state.is_active() => {
+                if self.steps.is_empty() {
+                    warn!(sequence = self.name, "sequence has no steps");
+                } else {
+                    self.abort_reason = None;
+                    self.enter_step(0);
+                    self.set_state(SequenceState::Running);
+                }
+            }
+            CMD_PAUSE if self.state == SequenceState::Running => {
+                self.set_state(SequenceState::Paused);
+            }
+            CMD_RESUME if self.state == SequenceState::Paused => {
+                self.set_state(SequenceState::Running);
+            }
+            CMD_ABORT if self.state.is_active() => {
+                self.abort(vars, AbortReason::Command);
+            }
+            _ => {}
+        }
+        if self.state == SequenceState::Running {
+            if let Some(last_tick) = self.last_tick {
+                self.step_elapsed += now.saturating_duration_since(last_tick);
+            }
+            self.last_tick = Some(now);
+            self.run_step(vars);
+        } else {
+            self.last_tick = None;
+        }
+        self.state
+    }
+    fn run_step(&mut self, vars: &mut V) {
+        let step = &self.steps[self.step];
+        if !self.entered {
+            if let Some(ref action) = step.action {
+                action(vars);
+            }
+            self.entered = true;
+        }
+        if step.timeout.map_or(false, |t| self.step_elapsed > t) {
+            error!(
+                sequence = self.name,
+                step = step.name,
+                "sequence step timeout"
+            );
+            self.abort(vars, AbortReason::Timeout);
+            return;
+        }
+        if self.step_elapsed >= step.min_duration && step.until.as_ref().map_or(true, |f| f(vars)) {
+            if self.step + 1 < self.steps.len() {
+                self.enter_step(self.step + 1);
+                self.report();
+                // the next step is executed at the next tick
+            } else {
+                self.set_state(SequenceState::Completed);
+            }
+        }
+    }
+    fn enter_step(&mut self, step: usize) {
+        self.step = step;
+        self.entered = false;
+        self.step_elapsed = Duration::ZERO;
+        self.last_tick = None;
+    }
+    fn abort(&mut self, vars: &mut V, reason: AbortReason) {
+        if let Some(ref safe_state) = self.safe_state {
+            safe_state(vars);
+        }
+        self.abort_reason = Some(reason);
+        self.set_state(SequenceState::Aborted);
+    }
+    fn set_state(&mut self, state: SequenceState) {
+        self.state = state;
+        self.report();
+    }
+    fn report(&mut self) {
+        if let Some(ref path) = self.persistence {
+            if let Err(error) = write_atomic(path, &encode_persistent(self.state, self.step)) {
+                error!(%error, path=%path.display(), "unable to persist sequence state");
+            }
+        }
+        let status = self.status();
+        if let Some(ref mut f) = self.on_status {
+            f(&status);
+        }
+    }
+}
+
+fn encode_persistent(state: SequenceState, step: usize) -> String {
+    format!("{} {}\n", state, step)
+}
+
+fn decode_persistent(s: &str) -> Result<(SequenceState, usize)> {
+    let mut sp = s.trim().split(' ');
+    let state = sp
+        .next()
+        .ok_or_else(|| Error::invalid_data("sequence state missing"))?
+        .parse()?;
+    let step = sp
+        .next()
+        .ok_or_else(|| Error::invalid_data("sequence step missing"))?
+        .parse()
+        .map_err(Error::invalid_data)?;
+    Ok((state, step))
+}
+
+fn write_atomic(path: &Path, data: &str) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut f = fs::File::create(&tmp)?;
+    f.write_all(data.as_bytes())?;
+    f.sync_all()?;
+    drop(f);
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_persistent, encode_persistent, AbortReason, Sequence, SequenceState, Step};
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct Vars {
+        level: u32,
+        valve: bool,
+        heater: bool,
+    }
+
+    fn sequence() -> Sequence<Vars> {
+        Sequence::new("test")
+            .step(
+                Step::new("fill")
+                    .action(|v: &mut Vars| v.valve = true)
+                    .until(|v| v.level >= 10),
+            )
+            .step(Step::new("heat").action(|v: &mut Vars| {
+                v.valve = false;
+                v.heater = true;
+            }))
+            .safe_state(|v| {
+                v.valve = false;
+                v.heater = false;
+            })
+    }
+
+    #[test]
+    fn test_sequence_steps() {
+        let mut seq = sequence();
+        let control = seq.control();
+        let mut vars = Vars::default();
+        assert_eq!(seq.tick(&mut vars), SequenceState::Idle);
+        control.start();
+        assert_eq!(seq.tick(&mut vars), SequenceState::Running);
+        assert!(vars.valve);
+        control.pause();
+        vars.level = 10;
+        assert_eq!(seq.tick(&mut vars), SequenceState::Paused);
+        assert_eq!(seq.current_step().unwrap().name(), "fill");
+        control.resume();
+        assert_eq!(seq.tick(&mut vars), SequenceState::Running);
+        assert_eq!(seq.current_step().unwrap().name(), "heat");
+        assert_eq!(seq.tick(&mut vars), SequenceState::Completed);
+        assert!(vars.heater);
+        assert!(!vars.valve);
+    }
+
+    #[test]
+    fn test_sequence_abort() {
+        let mut seq = sequence();
+        seq.steps[0].timeout = Some(Duration::ZERO);
+        let mut vars = Vars::default();
+        seq.control().start();
+        seq.tick(&mut vars);
+        std::thread::sleep(Duration::from_millis(1));
+        assert_eq!(seq.tick(&mut vars), SequenceState::Aborted);
+        assert_eq!(seq.status().abort_reason, Some(AbortReason::Timeout));
+        assert!(!vars.valve);
+    }
+
+    #[test]
+    fn test_persistent_encode_decode() {
+        let s = encode_persistent(SequenceState::Paused, 3);
+        assert_eq!(decode_persistent(&s).unwrap(), (SequenceState::Paused, 3));
+        assert!(decode_persistent("invalid 1").is_err());
+        assert!(decode_persistent("running").is_err());
+    }
+}
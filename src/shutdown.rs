@@ -0,0 +1,160 @@
+//!
+//! Flush hooks for critical buffers (persistent stores, recorders etc.), which are called before
+//! the process is terminated.
+//!
+//! Subsystems register their buffers automatically (e.g. [`crate::kv::KvStore`]), custom ones can
+//! be registered with [`register_flush_hook()`] or [`register_flush_fn()`]. Hooks are called by
+//! [`flush_all()`] when the controller is stopped and by [`crate::suicide_with_flush()`] (used by
+//! the controller signal handlers) before the process is killed.
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use tracing::{error, warn};
+
+use crate::{Error, Result};
+
+/// The default total time for all flush hooks
+pub const DEFAULT_FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+static HOOKS: Mutex<Vec<FlushHook>> = Mutex::new(Vec::new());
+
+/// Objects with critical buffers, which must be flushed before the process is terminated
+pub trait Flush: Send + Sync {
+    fn flush(&self) -> Result<()>;
+}
+
+struct FnHook<F>(F);
+
+impl<F> Flush for FnHook<F>
+where
+    F: Fn() -> Result<()> + Send + Sync,
+{
+    fn flush(&self) -> Result<()> {
+        (self.0)()
+    }
+}
+
+enum Target {
+    // removed automatically when the object is dropped
+    Weak(Weak<dyn Flush>),
+    Permanent(Arc<dyn Flush>),
+}
+
+struct FlushHook {
+    name: String,
+    target: Target,
+}
+
+impl FlushHook {
+    fn get(&self) -> Option<Arc<dyn Flush>> {
+        match self.target {
+            Target::Weak(ref weak) => weak.upgrade(),
+            Target::Permanent(ref target) => Some(target.clone()),
+        }
+    }
+}
+
+/// Registers a flush hook for an object. The object is not kept alive by the registry, the hook
+/// is removed automatically when the object is dropped
+///
+/// # Panics
+///
+/// Will panic if the internal lock is poisoned
+pub fn register_flush_hook<T>(name: &str, target: &Arc<T>)
+where
+    T: Flush + 'static,
+{
+    let weak: Weak<dyn Flush> = Arc::downgrade(target) as Weak<dyn Flush>;
+    HOOKS.lock().unwrap().push(FlushHook {
+        name: name.to_owned(),
+        target: Target::Weak(weak),
+    });
+}
+
+/// Registers a flush function
+///
+/// # Panics
+///
+/// Will panic if the internal lock is poisoned
+pub fn register_flush_fn<F>(name: &str, f: F)
+where
+    F: Fn() -> Result<()> + Send + Sync + 'static,
+{
+    HOOKS.lock().unwrap().push(FlushHook {
+        name: name.to_owned(),
+        target: Target::Permanent(Arc::new(FnHook(f))),
+    });
+}
+
+/// Calls all registered flush hooks in the registration order. The total time is limited with
+/// the timeout, [`Error::Timeout`] is returned if the hooks have not been completed in time (the
+/// remaining ones are still called in background). Errors of individual hooks are logged
+///
+/// # Panics
+///
+/// Will panic if the internal lock is poisoned
+pub fn flush_all(timeout: Duration) -> Result<()> {
+    let hooks: Vec<(String, Arc<dyn Flush>)> = {
+        let mut registry = HOOKS.lock().unwrap();
+        registry.retain(|h| h.get().is_some());
+        registry
+            .iter()
+            .filter_map(|h| Some((h.name.clone(), h.get()?)))
+            .collect()
+    };
+    if hooks.is_empty() {
+        return Ok(());
+    }
+    let current: Arc<Mutex<Option<String>>> = <_>::default();
+    let (tx, rx) = mpsc::channel();
+    let c = current.clone();
+    thread::Builder::new()
+        .name("flush".to_owned())
+        .spawn(move || {
+            for (name, hook) in hooks {
+                c.lock().unwrap().replace(name.clone());
+                if let Err(error) = hook.flush() {
+                    error!(%name, %error, "flush hook failed");
+                }
+            }
+            let _ = tx.send(());
+        })?;
+    if rx.recv_timeout(timeout).is_err() {
+        let name = current.lock().unwrap().clone().unwrap_or_default();
+        warn!(%name, "flush hooks have not been completed in time");
+        return Err(Error::Timeout);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{flush_all, register_flush_hook, Flush, HOOKS};
+    use crate::Result;
+
+    struct Buffer(AtomicUsize);
+
+    impl Flush for Buffer {
+        fn flush(&self) -> Result<()> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_flush_hooks() {
+        let buf = Arc::new(Buffer(AtomicUsize::new(0)));
+        register_flush_hook("test", &buf);
+        flush_all(Duration::from_secs(5)).unwrap();
+        assert_eq!(buf.0.load(Ordering::SeqCst), 1);
+        drop(buf);
+        flush_all(Duration::from_secs(5)).unwrap();
+        assert!(!HOOKS.lock().unwrap().iter().any(|h| h.name == "test"));
+    }
+}
@@ -0,0 +1,395 @@
+//!
+//! Error budgets and SLO (service level objective) tracking for I/O subsystems.
+//!
+//! I/O mappings (devices, links) are registered in [`SloTracker`] with target success rates and
+//! optional max latencies. The tracker keeps rolling compliance over a sliding window and
+//! calculates burn rates: how fast the error budget (`1 - success rate`) is consumed. A burn rate
+//! of 1.0 means that the budget is consumed exactly at the end of the window.
+//!
+//! An alert is raised when both the long (the whole window) and the short (1/12 of the window)
+//! burn rates exceed the threshold, which filters out short error bursts and clears alerts
+//! quickly after recovery. Alerts are logged and passed to the alert function, which can e.g.
+//! send a hub message.
+//!
+//! ```rust,no_run
+//! use roboplc::slo::{SloTarget, SloTracker};
+//! use std::time::Duration;
+//!
+//! let tracker = SloTracker::new().on_alert(|status| println!("{:?}", status));
+//! let plc1 = tracker.register(
+//!     "plc1",
+//!     SloTarget::new(0.999).max_latency(Duration::from_millis(50)),
+//! );
+//! let result: Result<(), roboplc::Error> = plc1.measure(|| {
+//!     // read the device
+//!     Ok(())
+//! });
+//! std::thread::spawn(move || {
+//!     tracker.run(Duration::from_secs(10));
+//! });
+//! ```
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use parking_lot_rt::Mutex;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::time::now;
+
+const BUCKETS: u32 = 60;
+
+const SHORT_WINDOW_DIVIDER: u32 = 12;
+
+type AlertFn = dyn Fn(&SloStatus) + Send + Sync;
+
+/// SLO target
+#[derive(Debug, Clone, Copy)]
+pub struct SloTarget {
+    success_rate: f64,
+    max_latency: Option<Duration>,
+    window: Duration,
+    burn_rate_threshold: f64,
+}
+
+impl SloTarget {
+    /// Target success rate (0.0-1.0, e.g. 0.999)
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the success rate is not in range 0.0..1.0
+    pub fn new(success_rate: f64) -> Self {
+        assert!(
+            (0.0..1.0).contains(&success_rate),
+            "success rate must be in range 0.0..1.0"
+        );
+        Self {
+            success_rate,
+            max_latency: None,
+            window: Duration::from_secs(3600),
+            burn_rate_threshold: 2.0,
+        }
+    }
+    /// Successful operations, which took longer than the max latency, are counted as failed
+    /// (the default is no limit)
+    pub fn max_latency(mut self, max_latency: Duration) -> Self {
+        self.max_latency = Some(max_latency);
+        self
+    }
+    /// Compliance window (the default is 1 hour)
+    ///
+    /// # Panics
+    ///
+    /// Will panic if the window is zero
+    pub fn window(mut self, window: Duration) -> Self {
+        assert!(!window.is_zero(), "window must be non-zero");
+        self.window = window;
+        self
+    }
+    /// Burn rate alert threshold (the default is 2.0, the budget is consumed twice faster than
+    /// allowed)
+    pub fn burn_rate_threshold(mut self, threshold: f64) -> Self {
+        self.burn_rate_threshold = threshold;
+        self
+    }
+}
+
+/// SLO status
+#[derive(Debug, Clone, Serialize)]
+pub struct SloStatus {
+    pub name: String,
+    pub target: f64,
+    /// Operations in the window
+    pub total: u64,
+    /// Failed (or too slow) operations in the window
+    pub failed: u64,
+    /// Actual success rate in the window, 1.0 if there were no operations
+    pub compliance: f64,
+    /// Burn rate over the whole window
+    pub burn_rate: f64,
+    /// Burn rate over the short window
+    pub short_burn_rate: f64,
+    /// Remaining error budget in the window (1.0 = untouched, negative if exceeded)
+    pub budget_remaining: f64,
+    pub alerting: bool,
+}
+
+#[derive(Default, Clone, Copy)]
+struct Bucket {
+    total: u64,
+    failed: u64,
+}
+
+struct Objective {
+    name: String,
+    target: SloTarget,
+    bucket_size: Duration,
+    // time of the current bucket start
+    head: Option<Instant>,
+    buckets: VecDeque<Bucket>,
+    alerting: bool,
+}
+
+impl Objective {
+    fn new(name: &str, target: SloTarget) -> Self {
+        Self {
+            name: name.to_owned(),
+            target,
+            bucket_size: (target.window / BUCKETS).max(Duration::from_millis(1)),
+            head: None,
+            buckets: VecDeque::with_capacity(BUCKETS as usize + 1),
+            alerting: false,
+        }
+    }
+    // rotates buckets to the given time
+    fn advance(&mut self, now: Instant) {
+        let Some(mut head) = self.head else {
+            self.head = Some(now);
+            self.buckets.push_back(Bucket::default());
+            return;
+        };
+        while now >= head + self.bucket_size {
+            head += self.bucket_size;
+            self.buckets.push_back(Bucket::default());
+            if self.buckets.len() > BUCKETS as usize {
+                self.buckets.pop_front();
+            }
+            if self.buckets.len() == BUCKETS as usize
+                && self.buckets.iter().all(|b| b.total == 0)
+                && now >= head + self.bucket_size
+            {
+                // idle for the whole window, skip the rest
+                head = now;
+            }
+        }
+        self.head = Some(head);
+    }
+    fn record(&mut self, ok: bool, latency: Duration, now: Instant) {
+        self.advance(now);
+        let good = ok && self.target.max_latency.map_or(true, |max| latency <= max);
+        let bucket = self.buckets.back_mut().unwrap();
+        bucket.total += 1;
+        if !good {
+            bucket.failed += 1;
+        }
+    }
+    #[allow(clippy::cast_precision_loss)]
+    fn burn_rate(&self, buckets: usize) -> (u64, u64, f64) {
+        let (total, failed) = self
+            .buckets
+            .iter()
+            .rev()
+            .take(buckets)
+            .fold((0, 0), |(t, f), b| (t + b.total, f + b.failed));
+        let error_rate = if total == 0 {
+            0.0
+        } else {
+            failed as f64 / total as f64
+        };
+        (total, failed, error_rate / (1.0 - self.target.success_rate))
+    }
+    fn status(&mut self, now: Instant) -> SloStatus {
+        self.advance(now);
+        let (total, failed, burn_rate) = self.burn_rate(BUCKETS as usize);
+        let (_, _, short_burn_rate) =
+            self.burn_rate((BUCKETS / SHORT_WINDOW_DIVIDER).max(1) as usize);
+        #[allow(clippy::cast_precision_loss)]
+        let compliance = if total == 0 {
+            1.0
+        } else {
+            1.0 - failed as f64 / total as f64
+        };
+        SloStatus {
+            name: self.name.clone(),
+            target: self.target.success_rate,
+            total,
+            failed,
+            compliance,
+            burn_rate,
+            short_burn_rate,
+            budget_remaining: 1.0 - burn_rate,
+            alerting: burn_rate > self.target.burn_rate_threshold
+                && short_burn_rate > self.target.burn_rate_threshold,
+        }
+    }
+}
+
+/// Registers I/O objectives and tracks their compliance
+#[derive(Clone)]
+pub struct SloTracker {
+    objectives: Arc<Mutex<Vec<Arc<Mutex<Objective>>>>>,
+    alert_fn: Option<Arc<AlertFn>>,
+}
+
+impl Default for SloTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SloTracker {
+    pub fn new() -> Self {
+        Self {
+            objectives: <_>::default(),
+            alert_fn: None,
+        }
+    }
+    /// A function, which is called (in addition to logging) by [`SloTracker::check()`] when an
+    /// objective starts alerting
+    pub fn on_alert<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&SloStatus) + Send + Sync + 'static,
+    {
+        self.alert_fn = Some(Arc::new(f));
+        self
+    }
+    /// Registers an objective. Results should be recorded with the returned handle
+    pub fn register(&self, name: &str, target: SloTarget) -> SloHandle {
+        let objective = Arc::new(Mutex::new(Objective::new(name, target)));
+        self.objectives.lock().push(objective.clone());
+        SloHandle { objective }
+    }
+    /// Returns the current status of all objectives
+    pub fn status(&self) -> Vec<SloStatus> {
+        let now = now();
+        self.objectives
+            .lock()
+            .iter()
+            .map(|o| o.lock().status(now))
+            .collect()
+    }
+    /// Checks all objectives, logs alert changes and calls the alert function for new alerts.
+    /// Returns the status of all objectives
+    pub fn check(&self) -> Vec<SloStatus> {
+        let now = now();
+        let mut result = Vec::new();
+        let mut to_report = Vec::new();
+        for objective in self.objectives.lock().iter() {
+            let mut objective = objective.lock();
+            let status = objective.status(now);
+            if status.alerting && !objective.alerting {
+                to_report.push(status.clone());
+            } else if !status.alerting && objective.alerting {
+                info!(name = %status.name, compliance = status.compliance, "SLO alert cleared");
+            }
+            objective.alerting = status.alerting;
+            result.push(status);
+        }
+        for status in to_report {
+            warn!(
+                name = %status.name,
+                compliance = status.compliance,
+                burn_rate = status.burn_rate,
+                short_burn_rate = status.short_burn_rate,
+                "SLO error budget burn rate alert"
+            );
+            if let Some(ref f) = self.alert_fn {
+                f(&status);
+            }
+        }
+        #[cfg(feature = "metrics")]
+        export_metrics(&result);
+        result
+    }
+    /// Periodically checks objectives (and exports metrics if `metrics` feature is enabled).
+    /// Never returns, should be run in a separate non-real-time task
+    pub fn run(&self, interval: Duration) -> ! {
+        loop {
+            thread::sleep(interval);
+            self.check();
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn export_metrics(statuses: &[SloStatus]) {
+    for s in statuses {
+        metrics::gauge!("roboplc_slo_compliance", "name" => s.name.clone()).set(s.compliance);
+        metrics::gauge!("roboplc_slo_burn_rate", "name" => s.name.clone()).set(s.burn_rate);
+        metrics::gauge!("roboplc_slo_budget_remaining", "name" => s.name.clone())
+            .set(s.budget_remaining);
+        metrics::gauge!("roboplc_slo_alerting", "name" => s.name.clone()).set(if s.alerting {
+            1.0
+        } else {
+            0.0
+        });
+    }
+}
+
+/// Objective handle, used to record operation results
+#[derive(Clone)]
+pub struct SloHandle {
+    objective: Arc<Mutex<Objective>>,
+}
+
+impl SloHandle {
+    /// Records an operation result
+    pub fn record(&self, ok: bool, latency: Duration) {
+        self.objective.lock().record(ok, latency, now());
+    }
+    /// Runs an operation, measures its latency and records the result
+    pub fn measure<T, E, F>(&self, f: F) -> std::result::Result<T, E>
+    where
+        F: FnOnce() -> std::result::Result<T, E>,
+    {
+        let start = now();
+        let result = f();
+        self.record(result.is_ok(), now().saturating_duration_since(start));
+        result
+    }
+    /// Returns the current objective status
+    pub fn status(&self) -> SloStatus {
+        self.objective.lock().status(now())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{Objective, SloTarget};
+
+    #[test]
+    fn test_slo_burn_rate() {
+        let target = SloTarget::new(0.9)
+            .max_latency(Duration::from_millis(10))
+            .window(Duration::from_secs(60));
+        let mut objective = Objective::new("test", target);
+        let start = Instant::now();
+        for i in 0..60 {
+            let t = start + Duration::from_secs(i);
+            objective.record(true, Duration::from_millis(1), t);
+            objective.record(i % 10 != 0, Duration::from_millis(1), t);
+        }
+        // 6 errors of 120
+        let status = objective.status(start + Duration::from_secs(59));
+        assert_eq!(status.total, 120);
+        assert_eq!(status.failed, 6);
+        assert!((status.burn_rate - 0.5).abs() < 1e-9);
+        assert!(!status.alerting);
+        // a burst of slow responses
+        let t = start + Duration::from_secs(60);
+        for _ in 0..10 {
+            objective.record(true, Duration::from_millis(20), t);
+        }
+        let status = objective.status(t);
+        assert!(status.short_burn_rate > 2.0);
+        assert!(status.burn_rate < 2.0);
+        assert!(!status.alerting);
+        for i in 61..70 {
+            let t = start + Duration::from_secs(i);
+            for _ in 0..10 {
+                objective.record(false, Duration::from_millis(1), t);
+            }
+        }
+        let status = objective.status(start + Duration::from_secs(69));
+        assert!(status.alerting);
+        assert!(status.budget_remaining < 0.0);
+        // the window has passed
+        let status = objective.status(start + Duration::from_secs(200));
+        assert_eq!(status.total, 0);
+        assert!((status.compliance - 1.0).abs() < f64::EPSILON);
+    }
+}
@@ -0,0 +1,351 @@
+//!
+//! State snapshots for troubleshooting. Snapshot sources (process images, variables, alarm lists
+//! etc.) are registered in [`SnapshotSources`], the whole state is captured into a JSON file on
+//! demand (with [`SnapshotSources::capture_to_dir()`] or on SIGUSR2, see
+//! [`SnapshotSources::capture_on_signal()`]).
+//!
+//! Two snapshots (e.g. of a good and a bad run) can be compared with [`Snapshot::diff()`] or with
+//! `robo snapshot-diff`. Values are compared by their paths (`section.field.subfield`).
+//!
+//! ```rust,no_run
+//! use parking_lot_rt::Mutex;
+//! use roboplc::snapshot::{Snapshot, SnapshotSources};
+//! use serde::Serialize;
+//! use std::sync::Arc;
+//!
+//! #[derive(Serialize, Clone, Default)]
+//! struct Variables {
+//!     level: f32,
+//!     alarms: Vec<u16>,
+//! }
+//!
+//! let variables: Arc<Mutex<Variables>> = <_>::default();
+//! let sources = SnapshotSources::new();
+//! let vars = variables.clone();
+//! sources.add("variables", move || vars.lock().clone());
+//! sources.capture_on_signal("/var/roboplc/data/snapshots").unwrap();
+//! // kill -USR2 <pid>
+//! let good = Snapshot::load("good.json").unwrap();
+//! let bad = Snapshot::load("bad.json").unwrap();
+//! println!("{}", good.diff(&bad));
+//! ```
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot_rt::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::info;
+
+use crate::{Error, Result};
+
+/// Snapshot file format version
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+type SourceFn = Box<dyn Fn() -> Result<Value> + Send + Sync>;
+
+/// Snapshot sources. Can be cloned and shared between workers with no limitations
+#[derive(Clone, Default)]
+pub struct SnapshotSources {
+    sources: Arc<Mutex<Vec<(String, SourceFn)>>>,
+}
+
+impl SnapshotSources {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Registers a snapshot source. The function is called when a snapshot is captured, so it
+    /// should return a copy of the current state. A source with the same name is replaced
+    pub fn add<T, F>(&self, name: &str, f: F)
+    where
+        T: Serialize,
+        F: Fn() -> T + Send + Sync + 'static,
+    {
+        let source: SourceFn =
+            Box::new(move || serde_json::to_value(f()).map_err(Error::invalid_data));
+        let mut sources = self.sources.lock();
+        if let Some(s) = sources.iter_mut().find(|(n, _)| n == name) {
+            s.1 = source;
+        } else {
+            sources.push((name.to_owned(), source));
+        }
+    }
+    /// Captures a snapshot of all sources
+    pub fn capture(&self, label: Option<&str>) -> Result<Snapshot> {
+        let sources = self.sources.lock();
+        let mut sections = BTreeMap::new();
+        for (name, source) in sources.iter() {
+            let data = source()?;
+            sections.insert(
+                name.clone(),
+                Section {
+                    time: format_time(SystemTime::now()),
+                    data,
+                },
+            );
+        }
+        Ok(Snapshot {
+            version: SNAPSHOT_VERSION,
+            label: label.map(ToOwned::to_owned),
+            time: format_time(SystemTime::now()),
+            sections,
+        })
+    }
+    /// Captures a snapshot and saves it into the directory, returns the file path
+    pub fn capture_to_dir<P: AsRef<Path>>(&self, dir: P, label: Option<&str>) -> Result<PathBuf> {
+        let snapshot = self.capture(label)?;
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+        fs::create_dir_all(dir.as_ref())?;
+        let path = dir
+            .as_ref()
+            .join(format!("snapshot-{}-{}.json", std::process::id(), time));
+        snapshot.save(&path)?;
+        info!(path = %path.display(), "snapshot saved");
+        Ok(path)
+    }
+    /// Spawns a thread which captures snapshots into the directory on SIGUSR2
+    #[cfg(target_os = "linux")]
+    pub fn capture_on_signal<P: Into<PathBuf>>(&self, dir: P) -> Result<()> {
+        let dir = dir.into();
+        let sources = self.clone();
+        let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGUSR2])?;
+        std::thread::Builder::new()
+            .name("RoboPLCSnapshot".to_owned())
+            .spawn(move || {
+                for _ in signals.forever() {
+                    if let Err(error) = sources.capture_to_dir(&dir, None) {
+                        tracing::error!(%error, "unable to capture a snapshot");
+                    }
+                }
+            })?;
+        Ok(())
+    }
+}
+
+/// Snapshot section (a single source)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Section {
+    /// Capture time (RFC 3339, UTC)
+    pub time: String,
+    pub data: Value,
+}
+
+/// A captured state snapshot
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Snapshot {
+    version: u32,
+    pub label: Option<String>,
+    /// Capture time (RFC 3339, UTC)
+    pub time: String,
+    pub sections: BTreeMap<String, Section>,
+}
+
+impl Snapshot {
+    /// Loads a snapshot file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let snapshot: Snapshot =
+            serde_json::from_slice(&fs::read(path)?).map_err(Error::invalid_data)?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(Error::invalid_data(format!(
+                "unsupported snapshot version: {}",
+                snapshot.version
+            )));
+        }
+        Ok(snapshot)
+    }
+    /// Saves the snapshot into a file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(
+            path,
+            serde_json::to_vec_pretty(self).map_err(Error::invalid_data)?,
+        )?;
+        Ok(())
+    }
+    /// Compares the snapshot with another (newer) one
+    pub fn diff(&self, other: &Snapshot) -> SnapshotDiff {
+        let mut before = BTreeMap::new();
+        let mut after = BTreeMap::new();
+        for (name, section) in &self.sections {
+            flatten(name, &section.data, &section.time, &mut before);
+        }
+        for (name, section) in &other.sections {
+            flatten(name, &section.data, &section.time, &mut after);
+        }
+        let mut changes = Vec::new();
+        for (path, (value, time)) in &before {
+            match after.get(path) {
+                Some((v, _)) if v == value => {}
+                Some((v, t)) => changes.push(SnapshotChange {
+                    path: path.clone(),
+                    before: Some(((*value).clone(), (*time).to_owned())),
+                    after: Some(((*v).clone(), (*t).to_owned())),
+                }),
+                None => changes.push(SnapshotChange {
+                    path: path.clone(),
+                    before: Some(((*value).clone(), (*time).to_owned())),
+                    after: None,
+                }),
+            }
+        }
+        for (path, (value, time)) in &after {
+            if !before.contains_key(path) {
+                changes.push(SnapshotChange {
+                    path: path.clone(),
+                    before: None,
+                    after: Some(((*value).clone(), (*time).to_owned())),
+                });
+            }
+        }
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+        SnapshotDiff {
+            before: (self.label.clone(), self.time.clone()),
+            after: (other.label.clone(), other.time.clone()),
+            changes,
+        }
+    }
+}
+
+/// A changed value. Values are absent if the path does not exist in a snapshot
+#[derive(Clone, Debug)]
+pub struct SnapshotChange {
+    pub path: String,
+    /// The value and the section capture time
+    pub before: Option<(Value, String)>,
+    /// The value and the section capture time
+    pub after: Option<(Value, String)>,
+}
+
+/// Snapshot comparison result
+#[derive(Clone, Debug)]
+pub struct SnapshotDiff {
+    /// Label and time of the first snapshot
+    pub before: (Option<String>, String),
+    /// Label and time of the second snapshot
+    pub after: (Option<String>, String),
+    pub changes: Vec<SnapshotChange>,
+}
+
+impl SnapshotDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (sign, (label, time)) in [("-", &self.before), ("+", &self.after)] {
+            writeln!(f, "{} {} {}", sign, label.as_deref().unwrap_or("-"), time)?;
+        }
+        for change in &self.changes {
+            writeln!(f, "{}", change.path)?;
+            if let Some((ref value, ref time)) = change.before {
+                writeln!(f, "  - {} ({})", value, time)?;
+            }
+            if let Some((ref value, ref time)) = change.after {
+                writeln!(f, "  + {} ({})", value, time)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// objects are flattened, other values (including arrays) are compared as-is
+fn flatten<'a>(
+    path: &str,
+    value: &'a Value,
+    time: &'a str,
+    out: &mut BTreeMap<String, (&'a Value, &'a str)>,
+) {
+    if let Value::Object(map) = value {
+        for (k, v) in map {
+            flatten(&format!("{}.{}", path, k), v, time, out);
+        }
+    } else {
+        out.insert(path.to_owned(), (value, time));
+    }
+}
+
+// RFC 3339, UTC, milliseconds
+fn format_time(time: SystemTime) -> String {
+    let d = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = d.as_secs();
+    let days = i64::try_from(secs / 86400).unwrap_or_default();
+    let rem = secs % 86400;
+    // civil from days (H. Hinnant)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        d.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use serde::Serialize;
+
+    use super::{format_time, SnapshotSources};
+
+    #[derive(Serialize)]
+    struct Inputs {
+        level: f32,
+        pump: bool,
+        alarms: Vec<u16>,
+    }
+
+    #[test]
+    fn test_format_time() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_709_251_199_123);
+        assert_eq!(format_time(time), "2024-02-29T23:59:59.123Z");
+        assert_eq!(format_time(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let good = SnapshotSources::new();
+        good.add("inputs", || Inputs {
+            level: 10.0,
+            pump: true,
+            alarms: vec![],
+        });
+        good.add("mode", || "auto");
+        let bad = SnapshotSources::new();
+        bad.add("inputs", || Inputs {
+            level: 10.0,
+            pump: false,
+            alarms: vec![7],
+        });
+        bad.add("counter", || 1);
+        let good = good.capture(Some("good")).unwrap();
+        let bad = bad.capture(Some("bad")).unwrap();
+        let diff = good.diff(&bad);
+        let paths: Vec<&str> = diff.changes.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(paths, ["counter", "inputs.alarms", "inputs.pump", "mode"]);
+        assert!(diff.changes[0].before.is_none());
+        assert!(diff.changes[3].after.is_none());
+        assert!(diff.to_string().contains("inputs.pump\n  - true"));
+        assert!(good.diff(&good).is_empty());
+    }
+}
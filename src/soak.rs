@@ -0,0 +1,315 @@
+//!
+//! Memory growth monitoring for long-run (soak) tests, e.g. during factory acceptance.
+//!
+//! [`SoakMonitor`] periodically samples the process resident set size (`/proc/self/statm`) and,
+//! if [`crate::memory::TrackingAllocator`] is installed, live heap bytes. Growth trends (least
+//! squares slope over a sliding window, 24 hours by default) are logged and an alarm is raised
+//! when the growth rate exceeds the configured limit, which helps to catch slow leaks.
+//!
+//! ```rust,no_run
+//! use roboplc::soak::SoakMonitor;
+//!
+//! std::thread::spawn(move || {
+//!     SoakMonitor::new()
+//!         // alert if RSS grows faster than 1 MiB per hour
+//!         .max_growth_per_hour(1024 * 1024)
+//!         .run();
+//! });
+//! ```
+use std::{collections::VecDeque, fs, thread, time::Duration};
+
+use bma_ts::Monotonic;
+use serde::Serialize;
+use tracing::{error, info, warn};
+
+use crate::{memory, Error, Result};
+
+const HOUR_SECS: f64 = 3600.0;
+
+/// Memory usage sample
+#[derive(Debug, Copy, Clone, Serialize)]
+pub struct MemorySample {
+    pub time: Monotonic,
+    /// Resident set size
+    pub rss_bytes: u64,
+    /// Live heap bytes, if [`crate::memory::TrackingAllocator`] is installed
+    pub heap_bytes: Option<i64>,
+}
+
+impl MemorySample {
+    /// Takes a sample for the current process
+    pub fn take() -> Result<Self> {
+        let heap_bytes = if memory::is_installed() {
+            Some(
+                memory::stats()
+                    .iter()
+                    .map(|s| i64::try_from(s.live_bytes).unwrap_or(i64::MAX))
+                    .sum(),
+            )
+        } else {
+            None
+        };
+        Ok(Self {
+            time: Monotonic::now(),
+            rss_bytes: rss_bytes()?,
+            heap_bytes,
+        })
+    }
+}
+
+/// Returns the resident set size of the current process
+pub fn rss_bytes() -> Result<u64> {
+    let statm = fs::read_to_string("/proc/self/statm")?;
+    let rss_pages: u64 = statm
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| Error::invalid_data("invalid statm"))?
+        .parse()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    let page_size = u64::try_from(page_size).map_err(Error::invalid_data)?;
+    Ok(rss_pages * page_size)
+}
+
+/// Memory growth trend
+#[derive(Debug, Copy, Clone, Serialize)]
+pub struct MemoryTrend {
+    /// Time span covered by samples
+    pub span: Duration,
+    pub samples: usize,
+    pub rss_bytes: u64,
+    /// RSS growth rate, bytes per hour
+    pub rss_slope: f64,
+    /// Live heap growth rate, bytes per hour
+    pub heap_slope: Option<f64>,
+}
+
+/// Soak test memory monitor
+pub struct SoakMonitor {
+    interval: Duration,
+    window: Duration,
+    min_span: Duration,
+    max_growth_per_hour: Option<u64>,
+    alarm: Option<fn(&MemoryTrend)>,
+    samples: VecDeque<MemorySample>,
+    alarmed: bool,
+}
+
+impl Default for SoakMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SoakMonitor {
+    pub fn new() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            window: Duration::from_secs(86400),
+            min_span: Duration::from_secs(3600),
+            max_growth_per_hour: None,
+            alarm: None,
+            samples: VecDeque::new(),
+            alarmed: false,
+        }
+    }
+    /// Sampling interval for [`SoakMonitor::run()`] (the default is 1 minute)
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+    /// Sliding window, trends are calculated for (the default is 24 hours)
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+    /// Min time span of samples before growth alarms are raised, to ignore start-up allocations
+    /// (the default is 1 hour)
+    pub fn min_span(mut self, min_span: Duration) -> Self {
+        self.min_span = min_span;
+        self
+    }
+    /// Max allowed RSS (and live heap) growth rate, bytes per hour (the default is no limit)
+    pub fn max_growth_per_hour(mut self, bytes: u64) -> Self {
+        self.max_growth_per_hour = Some(bytes);
+        self
+    }
+    /// A function, which is called (in addition to logging) once the growth limit is exceeded.
+    /// Called again only after the growth rate has been back to normal
+    pub fn alarm_fn(mut self, f: fn(&MemoryTrend)) -> Self {
+        self.alarm = Some(f);
+        self
+    }
+    /// Takes a sample of the current process and checks the trend
+    pub fn sample(&mut self) -> Result<MemorySample> {
+        let sample = MemorySample::take()?;
+        self.push(sample);
+        Ok(sample)
+    }
+    /// Appends a sample (e.g. taken externally) and checks the trend
+    pub fn push(&mut self, sample: MemorySample) {
+        while let Some(first) = self.samples.front() {
+            if elapsed(first.time, sample.time) > self.window {
+                self.samples.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.samples.push_back(sample);
+        self.check();
+    }
+    /// Collected samples
+    pub fn samples(&self) -> &VecDeque<MemorySample> {
+        &self.samples
+    }
+    /// Current trend, `None` if there are less than two samples
+    pub fn trend(&self) -> Option<MemoryTrend> {
+        let first = self.samples.front()?;
+        let last = self.samples.back()?;
+        if self.samples.len() < 2 {
+            return None;
+        }
+        let points = |f: fn(&MemorySample) -> Option<f64>| -> Option<Vec<(f64, f64)>> {
+            self.samples
+                .iter()
+                .map(|s| Some((elapsed(first.time, s.time).as_secs_f64(), f(s)?)))
+                .collect()
+        };
+        #[allow(clippy::cast_precision_loss)]
+        let rss = points(|s| Some(s.rss_bytes as f64))?;
+        #[allow(clippy::cast_precision_loss)]
+        let heap = points(|s| s.heap_bytes.map(|v| v as f64));
+        Some(MemoryTrend {
+            span: elapsed(first.time, last.time),
+            samples: self.samples.len(),
+            rss_bytes: last.rss_bytes,
+            rss_slope: slope(&rss)? * HOUR_SECS,
+            heap_slope: heap.as_deref().and_then(slope).map(|v| v * HOUR_SECS),
+        })
+    }
+    fn check(&mut self) {
+        let Some(max) = self.max_growth_per_hour else {
+            return;
+        };
+        let Some(trend) = self.trend() else {
+            return;
+        };
+        if trend.span < self.min_span {
+            return;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let max = max as f64;
+        let exceeded = trend.rss_slope > max || trend.heap_slope.map_or(false, |v| v > max);
+        if !exceeded {
+            self.alarmed = false;
+            return;
+        }
+        if self.alarmed {
+            return;
+        }
+        self.alarmed = true;
+        error!(
+            rss_bytes = trend.rss_bytes,
+            rss_slope = trend.rss_slope,
+            heap_slope = ?trend.heap_slope,
+            span = ?trend.span,
+            "memory growth limit exceeded"
+        );
+        if let Some(f) = self.alarm {
+            f(&trend);
+        }
+    }
+    /// Samples memory usage with the configured interval and logs the trend once per hour. Never
+    /// returns, should be run in a separate non-real-time task
+    pub fn run(mut self) -> ! {
+        let mut last_logged: Option<Monotonic> = None;
+        loop {
+            if let Err(e) = self.sample() {
+                warn!(error = %e, "unable to sample memory usage");
+            }
+            let now = Monotonic::now();
+            if last_logged.map_or(true, |t| elapsed(t, now) >= Duration::from_secs(3600)) {
+                if let Some(trend) = self.trend() {
+                    info!(
+                        rss_bytes = trend.rss_bytes,
+                        rss_slope = trend.rss_slope,
+                        heap_slope = ?trend.heap_slope,
+                        span = ?trend.span,
+                        "memory growth trend"
+                    );
+                    last_logged = Some(now);
+                }
+            }
+            thread::sleep(self.interval);
+        }
+    }
+}
+
+fn elapsed(from: Monotonic, to: Monotonic) -> Duration {
+    let nanos = |m: Monotonic| -> u128 { m.as_nanos().into() };
+    Duration::from_nanos(u64::try_from(nanos(to).saturating_sub(nanos(from))).unwrap_or(u64::MAX))
+}
+
+// least squares slope, None if all x are equal
+fn slope(points: &[(f64, f64)]) -> Option<f64> {
+    #[allow(clippy::cast_precision_loss)]
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let (mut num, mut den) = (0.0, 0.0);
+    for (x, y) in points {
+        num += (x - mean_x) * (y - mean_y);
+        den += (x - mean_x) * (x - mean_x);
+    }
+    if den == 0.0 {
+        None
+    } else {
+        Some(num / den)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use bma_ts::Monotonic;
+
+    use super::{MemorySample, MemoryTrend, SoakMonitor};
+
+    static ALARMS: AtomicUsize = AtomicUsize::new(0);
+
+    fn sample(secs: u64, rss_bytes: u64) -> MemorySample {
+        MemorySample {
+            time: Monotonic::from_nanos(secs * 1_000_000_000),
+            rss_bytes,
+            heap_bytes: None,
+        }
+    }
+
+    #[test]
+    fn test_soak_monitor() {
+        fn alarm(_trend: &MemoryTrend) {
+            ALARMS.fetch_add(1, Ordering::SeqCst);
+        }
+        let mut monitor = SoakMonitor::new()
+            .window(Duration::from_secs(4 * 3600))
+            .max_growth_per_hour(1000)
+            .alarm_fn(alarm);
+        assert!(monitor.trend().is_none());
+        // 500 bytes per hour
+        for h in 0..3 {
+            monitor.push(sample(h * 3600, 10_000 + h * 500));
+        }
+        let trend = monitor.trend().unwrap();
+        assert!((trend.rss_slope - 500.0).abs() < 1e-6);
+        assert!(trend.heap_slope.is_none());
+        assert_eq!(ALARMS.load(Ordering::SeqCst), 0);
+        // 5000 bytes per hour
+        for h in 3..10 {
+            monitor.push(sample(h * 3600, 11_000 + (h - 2) * 5_000));
+        }
+        assert_eq!(ALARMS.load(Ordering::SeqCst), 1);
+        assert_eq!(monitor.samples().len(), 5);
+        assert!((monitor.trend().unwrap().rss_slope - 5000.0).abs() < 1e-6);
+    }
+}
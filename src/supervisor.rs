@@ -1,9 +1,11 @@
 use std::collections::{btree_map, BTreeMap};
+use std::ops::{Deref, DerefMut};
+use std::time::{Duration, Instant};
 use std::{mem, thread};
 
 use serde::Serialize;
 
-use crate::thread_rt::{Builder, ScopedTask, Task};
+use crate::thread_rt::{Builder, RTParams, ScopedTask, Task};
 use crate::time::Interval;
 use crate::{Error, Result};
 
@@ -12,68 +14,92 @@ pub mod prelude {
     pub use crate::thread_rt::{Builder, Scheduling};
 }
 
-/// A supervisor object used to manage tasks spawned with [`Builder`]
+/// A task handle which can be managed by a supervisor. Implemented for both [`Task`] and
+/// [`ScopedTask`]
+pub trait SupervisedTask {
+    /// Task result type
+    type Output;
+    fn name(&self) -> &str;
+    fn is_finished(&self) -> bool;
+    fn is_blocking(&self) -> bool;
+    /// Returns duration since the task was started
+    fn elapsed(&self) -> Duration;
+    /// Returns current real-time params
+    fn rt_params(&self) -> &RTParams;
+    /// Applies new real-time params
+    fn apply_rt_params(&mut self, rt_params: RTParams) -> Result<()>;
+    fn join(self) -> thread::Result<Self::Output>;
+}
+
+macro_rules! impl_supervised_task {
+    ($task:ty, $($lt:lifetime)?) => {
+        impl<$($lt,)? T> SupervisedTask for $task {
+            type Output = T;
+            fn name(&self) -> &str {
+                self.name()
+            }
+            fn is_finished(&self) -> bool {
+                self.is_finished()
+            }
+            fn is_blocking(&self) -> bool {
+                self.is_blocking()
+            }
+            fn elapsed(&self) -> Duration {
+                self.elapsed()
+            }
+            fn rt_params(&self) -> &RTParams {
+                self.rt_params()
+            }
+            fn apply_rt_params(&mut self, rt_params: RTParams) -> Result<()> {
+                self.apply_rt_params(rt_params)
+            }
+            fn join(self) -> thread::Result<T> {
+                self.join()
+            }
+        }
+    };
+}
+
+impl_supervised_task!(Task<T>,);
+impl_supervised_task!(ScopedTask<'scope, T>, 'scope);
+
+/// Task registry, shared by [`Supervisor`] and [`ScopedSupervisor`]. All registry methods are
+/// available for both supervisor kinds
 #[derive(Serialize)]
-pub struct Supervisor<T> {
-    tasks: BTreeMap<String, Task<T>>,
+pub struct TaskRegistry<H> {
+    tasks: BTreeMap<String, H>,
+    #[serde(skip_serializing)]
+    purge_interval: Option<Duration>,
+    #[serde(skip_serializing)]
+    last_purge: Instant,
 }
 
-impl<T> Default for Supervisor<T> {
+impl<H> Default for TaskRegistry<H> {
     fn default() -> Self {
         Self {
             tasks: <_>::default(),
+            purge_interval: None,
+            last_purge: Instant::now(),
         }
     }
 }
 
-macro_rules! vacant_entry {
-    ($self:ident, $builder:ident) => {{
-        let Some(name) = $builder.name.clone() else { return Err(Error::SupervisorNameNotSpecified); };
-        let btree_map::Entry::Vacant(entry) = $self.tasks.entry(name.clone()) else { return Err(Error::SupervisorDuplicateTask(name)); };
-        entry
-    }};
-}
-
-impl<T> Supervisor<T> {
-    pub fn new() -> Self {
-        Self::default()
-    }
-    /// Spawns a new task using a [`Builder`] object and registers it. The task name MUST be unique
-    /// and SHOULD be 15 characters or less to set a proper thread name
-    pub fn spawn<F, B>(&mut self, builder: B, f: F) -> Result<&Task<T>>
-    where
-        B: Into<Builder>,
-        F: FnOnce() -> T + Send + 'static,
-        T: Send + 'static,
-    {
-        let builder = builder.into();
-        let entry = vacant_entry!(self, builder);
-        let task = builder.spawn(f)?;
-        Ok(entry.insert(task))
-    }
-    /// Spawns a new periodic task using a [`Builder`] object and registers it. The task name MUST
-    /// be unique and SHOULD be 15 characters or less to set a proper thread name
-    pub fn spawn_periodic<F, B>(&mut self, builder: B, f: F, interval: Interval) -> Result<&Task<T>>
-    where
-        F: Fn() -> T + Send + 'static,
-        T: Send + 'static,
-        B: Into<Builder>,
-    {
-        let builder = builder.into();
-        let entry = vacant_entry!(self, builder);
-        let task = builder.spawn_periodic(f, interval)?;
-        Ok(entry.insert(task))
+impl<H: SupervisedTask> TaskRegistry<H> {
+    /// Enables periodic purge: finished tasks are removed from the registry when a new task is
+    /// spawned and the interval has passed since the last purge (the default is disabled)
+    pub fn set_purge_interval(&mut self, interval: Option<Duration>) {
+        self.purge_interval = interval;
     }
     /// Gets a task by its name
-    pub fn get_task(&self, name: &str) -> Option<&Task<T>> {
+    pub fn get_task(&self, name: &str) -> Option<&H> {
         self.tasks.get(name)
     }
     /// Gets a task by its name as a mutable object
-    pub fn get_task_mut(&mut self, name: &str) -> Option<&mut Task<T>> {
+    pub fn get_task_mut(&mut self, name: &str) -> Option<&mut H> {
         self.tasks.get_mut(name)
     }
     /// Takes a task by its name and removes it from the internal registry
-    pub fn take_task(&mut self, name: &str) -> Option<Task<T>> {
+    pub fn take_task(&mut self, name: &str) -> Option<H> {
         self.tasks.remove(name)
     }
     /// Removes a task from the internal registry
@@ -84,13 +110,29 @@ pub fn forget_task(&mut self, name: &str) -> Result<()> {
             Err(Error::SupervisorTaskNotFound)
         }
     }
+    /// Iterates over registered tasks
+    pub fn tasks(&self) -> impl Iterator<Item = &H> {
+        self.tasks.values()
+    }
+    /// Names of registered tasks
+    pub fn task_names(&self) -> impl Iterator<Item = &str> {
+        self.tasks.keys().map(String::as_str)
+    }
+    /// Number of registered tasks
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
     /// Removes all finished tasks from the internal registry
     pub fn purge(&mut self) {
         self.tasks.retain(|_, task| !task.is_finished());
+        self.last_purge = Instant::now();
     }
     /// Joins all tasks in the internal registry and returns a map with their results. After the
     /// operation the registry is cleared
-    pub fn join_all(&mut self) -> BTreeMap<String, thread::Result<T>> {
+    pub fn join_all(&mut self) -> BTreeMap<String, thread::Result<H::Output>> {
         let mut result = BTreeMap::new();
         for (name, task) in mem::take(&mut self.tasks) {
             if !task.is_blocking() {
@@ -99,33 +141,120 @@ pub fn join_all(&mut self) -> BTreeMap<String, thread::Result<T>> {
         }
         result
     }
+    fn purge_if_required(&mut self) {
+        if let Some(interval) = self.purge_interval {
+            if self.last_purge.elapsed() >= interval {
+                self.purge();
+            }
+        }
+    }
+    fn vacant_entry(&mut self, builder: &Builder) -> Result<btree_map::VacantEntry<'_, String, H>> {
+        self.purge_if_required();
+        let Some(name) = builder.name.clone() else { return Err(Error::SupervisorNameNotSpecified); };
+        let btree_map::Entry::Vacant(entry) = self.tasks.entry(name.clone()) else { return Err(Error::SupervisorDuplicateTask(name)); };
+        Ok(entry)
+    }
+}
+
+/// A supervisor object used to manage tasks spawned with [`Builder`]
+#[derive(Serialize)]
+pub struct Supervisor<T> {
+    #[serde(flatten)]
+    registry: TaskRegistry<Task<T>>,
+}
+
+impl<T> Default for Supervisor<T> {
+    fn default() -> Self {
+        Self {
+            registry: <_>::default(),
+        }
+    }
+}
+
+impl<T> Deref for Supervisor<T> {
+    type Target = TaskRegistry<Task<T>>;
+    fn deref(&self) -> &Self::Target {
+        &self.registry
+    }
+}
+
+impl<T> DerefMut for Supervisor<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.registry
+    }
+}
+
+impl<T> Supervisor<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Spawns a new task using a [`Builder`] object and registers it. The task name MUST be unique
+    /// and SHOULD be 15 characters or less to set a proper thread name
+    pub fn spawn<F, B>(&mut self, builder: B, f: F) -> Result<&Task<T>>
+    where
+        B: Into<Builder>,
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let builder = builder.into();
+        let entry = self.registry.vacant_entry(&builder)?;
+        let task = builder.spawn(f)?;
+        Ok(entry.insert(task))
+    }
+    /// Spawns a new periodic task using a [`Builder`] object and registers it. The task name MUST
+    /// be unique and SHOULD be 15 characters or less to set a proper thread name
+    pub fn spawn_periodic<F, B>(&mut self, builder: B, f: F, interval: Interval) -> Result<&Task<T>>
+    where
+        F: Fn() -> T + Send + 'static,
+        T: Send + 'static,
+        B: Into<Builder>,
+    {
+        let builder = builder.into();
+        let entry = self.registry.vacant_entry(&builder)?;
+        let task = builder.spawn_periodic(f, interval)?;
+        Ok(entry.insert(task))
+    }
 }
 
 #[allow(clippy::module_name_repetitions)]
 #[derive(Serialize)]
 pub struct ScopedSupervisor<'a, 'env: 'a, T> {
-    tasks: BTreeMap<String, ScopedTask<'a, T>>,
+    #[serde(flatten)]
+    registry: TaskRegistry<ScopedTask<'a, T>>,
     #[serde(skip_serializing)]
     scope: &'a thread::Scope<'a, 'env>,
 }
 
+impl<'a, 'env, T> Deref for ScopedSupervisor<'a, 'env, T> {
+    type Target = TaskRegistry<ScopedTask<'a, T>>;
+    fn deref(&self) -> &Self::Target {
+        &self.registry
+    }
+}
+
+impl<'a, 'env, T> DerefMut for ScopedSupervisor<'a, 'env, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.registry
+    }
+}
+
 impl<'a, 'env, T> ScopedSupervisor<'a, 'env, T> {
     pub fn new(scope: &'a thread::Scope<'a, 'env>) -> Self {
         Self {
-            tasks: <_>::default(),
+            registry: <_>::default(),
             scope,
         }
     }
     /// Spawns a new task using a [`Builder`] object and registers it. The task name MUST be unique
     /// and SHOULD be 15 characters or less to set a proper thread name
-    pub fn spawn<F, B>(&mut self, builder: B, f: F) -> Result<&ScopedTask<T>>
+    pub fn spawn<F, B>(&mut self, builder: B, f: F) -> Result<&ScopedTask<'a, T>>
     where
         B: Into<Builder>,
         F: FnOnce() -> T + Send + 'a,
         T: Send + 'a,
     {
         let builder = builder.into();
-        let entry = vacant_entry!(self, builder);
+        let entry = self.registry.vacant_entry(&builder)?;
         let task = builder.spawn_scoped(self.scope, f)?;
         Ok(entry.insert(task))
     }
@@ -136,50 +265,15 @@ pub fn spawn_periodic<F, B>(
         builder: B,
         f: F,
         interval: Interval,
-    ) -> Result<&ScopedTask<T>>
+    ) -> Result<&ScopedTask<'a, T>>
     where
         F: Fn() -> T + Send + 'a,
         T: Send + 'a,
         B: Into<Builder>,
     {
         let builder = builder.into();
-        let entry = vacant_entry!(self, builder);
+        let entry = self.registry.vacant_entry(&builder)?;
         let task = builder.spawn_scoped_periodic(self.scope, f, interval)?;
         Ok(entry.insert(task))
     }
-    /// Gets a task by its name
-    pub fn get_task(&self, name: &str) -> Option<&ScopedTask<T>> {
-        self.tasks.get(name)
-    }
-    /// Gets a task by its name as a mutable object
-    pub fn get_task_mut(&mut self, name: &str) -> Option<&mut ScopedTask<'a, T>> {
-        self.tasks.get_mut(name)
-    }
-    /// Takes a task by its name and removes it from the internal registry
-    pub fn take_task(&mut self, name: &str) -> Option<ScopedTask<T>> {
-        self.tasks.remove(name)
-    }
-    /// Removes a task from the internal registry
-    pub fn forget_task(&mut self, name: &str) -> Result<()> {
-        if self.tasks.remove(name).is_some() {
-            Ok(())
-        } else {
-            Err(Error::SupervisorTaskNotFound)
-        }
-    }
-    /// Removes all finished tasks from the internal registry
-    pub fn purge(&mut self) {
-        self.tasks.retain(|_, task| !task.is_finished());
-    }
-    /// Joins all tasks in the internal registry and returns a map with their results. After the
-    /// operation the registry is cleared
-    pub fn join_all(&mut self) -> BTreeMap<String, thread::Result<T>> {
-        let mut result = BTreeMap::new();
-        for (name, task) in mem::take(&mut self.tasks) {
-            if !task.is_blocking() {
-                result.insert(name, task.join());
-            }
-        }
-        result
-    }
 }
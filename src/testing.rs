@@ -0,0 +1,714 @@
+//!
+//! Helpers to assert timing properties in integration tests, which allow to gate timing
+//! regressions in CI.
+//!
+//! [`assert_period!`](crate::assert_period) receives messages from a channel (anything with a
+//! blocking `recv()` method which returns [`Result`]) and checks intervals between them,
+//! [`assert_throughput!`](crate::assert_throughput) checks the channel message rate. [`Harness`]
+//! runs a cycle function or a worker in simulated mode and collects its timing distribution.
+//! [`WorkerHarness`] unit-tests worker logic: it runs a worker for a given number of cycles with
+//! scripted hub traffic and collects published messages and shared variable changes.
+//!
+//! Example:
+//!
+//! ```rust,no_run
+//! use roboplc::{assert_period, pchannel};
+//! use std::time::Duration;
+//!
+//! let (tx, rx) = pchannel::bounded::<u32>(10);
+//! std::thread::spawn(move || {
+//!     for i in 0.. {
+//!         if tx.send(i).is_err() {
+//!             break;
+//!         }
+//!         std::thread::sleep(Duration::from_millis(1));
+//!     }
+//! });
+//! assert_period!(
+//!     rx,
+//!     period = Duration::from_millis(1),
+//!     tolerance = Duration::from_micros(100),
+//!     n = 1000
+//! );
+//! ```
+//!
+//! The default tolerance policy requires all samples to be within the tolerance. To ignore rare
+//! outliers on shared CI runners, use `quantile = 0.99` which requires 99% of samples to fit.
+#[cfg(target_os = "linux")]
+use std::{
+    collections::VecDeque,
+    mem,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+#[cfg(target_os = "linux")]
+const COLLECT_INTERVAL: Duration = Duration::from_micros(100);
+
+/// Asserts that messages are received from a channel with the given period. Panics with the
+/// timing statistics and a histogram if the assertion fails
+///
+/// Syntax: `assert_period!(rx, period = .., tolerance = .., n = .. [, quantile = ..])`, where
+/// `n` is the number of intervals measured
+#[macro_export]
+macro_rules! assert_period {
+    ($rx: expr, period = $period: expr, tolerance = $tolerance: expr, n = $n: expr) => {
+        $crate::assert_period!(
+            $rx,
+            period = $period,
+            tolerance = $tolerance,
+            n = $n,
+            quantile = 1.0
+        )
+    };
+    ($rx: expr, period = $period: expr, tolerance = $tolerance: expr, n = $n: expr,
+        quantile = $quantile: expr) => {{
+        let stats = $crate::testing::collect_intervals(|| $rx.recv().is_ok(), $n);
+        if let Err(e) = $crate::testing::check_period(&stats, $period, $tolerance, $n, $quantile) {
+            panic!("{}", e);
+        }
+    }};
+}
+
+/// Asserts that a channel delivers at least the given number of messages per second
+///
+/// Syntax: `assert_throughput!(rx, n = .., min_rate = ..)`, where `n` is the number of messages
+/// measured
+#[macro_export]
+macro_rules! assert_throughput {
+    ($rx: expr, n = $n: expr, min_rate = $min_rate: expr) => {{
+        let throughput = $crate::testing::measure_throughput(|| $rx.recv().is_ok(), $n);
+        if let Err(e) = throughput.check($n, $min_rate) {
+            panic!("{}", e);
+        }
+    }};
+}
+
+/// Timing distribution of collected samples
+#[derive(Clone, Debug, Default)]
+pub struct TimingStats {
+    // sorted
+    samples: Vec<Duration>,
+}
+
+impl From<Vec<Duration>> for TimingStats {
+    fn from(mut samples: Vec<Duration>) -> Self {
+        samples.sort_unstable();
+        Self { samples }
+    }
+}
+
+impl TimingStats {
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+    /// Sorted samples
+    pub fn samples(&self) -> &[Duration] {
+        &self.samples
+    }
+    pub fn min(&self) -> Duration {
+        self.samples.first().copied().unwrap_or_default()
+    }
+    pub fn max(&self) -> Duration {
+        self.samples.last().copied().unwrap_or_default()
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn mean(&self) -> Duration {
+        if self.samples.is_empty() {
+            return Duration::ZERO;
+        }
+        let total: Duration = self.samples.iter().sum();
+        total / self.samples.len() as u32
+    }
+    /// Returns the sample at the given quantile (0.0..=1.0)
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    pub fn quantile(&self, q: f64) -> Duration {
+        if self.samples.is_empty() {
+            return Duration::ZERO;
+        }
+        let pos = ((self.samples.len() - 1) as f64 * q.clamp(0.0, 1.0)).round() as usize;
+        self.samples[pos]
+    }
+    /// Maximum absolute deviation from the expected value
+    pub fn jitter(&self, expected: Duration) -> Duration {
+        abs_diff(self.min(), expected).max(abs_diff(self.max(), expected))
+    }
+    /// Number of samples within the tolerance of the expected value
+    pub fn count_within(&self, expected: Duration, tolerance: Duration) -> usize {
+        self.samples
+            .iter()
+            .filter(|s| abs_diff(**s, expected) <= tolerance)
+            .count()
+    }
+    /// Builds a histogram with the given number of equal buckets between min and max
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn histogram(&self, buckets: usize) -> Histogram {
+        let buckets = buckets.max(1);
+        let start = self.min();
+        let range = self.max() - start;
+        let width = (range / buckets as u32).max(Duration::from_nanos(1));
+        let mut counts = vec![0; buckets];
+        for s in &self.samples {
+            let n = ((*s - start).as_nanos() / width.as_nanos()) as usize;
+            counts[n.min(buckets - 1)] += 1;
+        }
+        Histogram {
+            start,
+            width,
+            counts,
+        }
+    }
+}
+
+impl fmt::Display for TimingStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "n={} min={:?} mean={:?} p50={:?} p99={:?} max={:?}",
+            self.len(),
+            self.min(),
+            self.mean(),
+            self.quantile(0.5),
+            self.quantile(0.99),
+            self.max()
+        )
+    }
+}
+
+/// Timing histogram
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    /// The lower bound of the first bucket
+    pub start: Duration,
+    /// Bucket width
+    pub width: Duration,
+    pub counts: Vec<usize>,
+}
+
+impl fmt::Display for Histogram {
+    #[allow(clippy::cast_possible_truncation)]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const BAR_WIDTH: usize = 50;
+        let max = self.counts.iter().copied().max().unwrap_or_default().max(1);
+        for (i, count) in self.counts.iter().enumerate() {
+            let from = self.start + self.width * i as u32;
+            writeln!(
+                f,
+                "{:>12?} {:>8} {}",
+                from,
+                count,
+                "#".repeat(count * BAR_WIDTH / max)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Message throughput
+#[derive(Clone, Copy, Debug)]
+pub struct Throughput {
+    /// Messages received
+    pub count: usize,
+    pub elapsed: Duration,
+}
+
+impl Throughput {
+    /// Messages per second
+    #[allow(clippy::cast_precision_loss)]
+    pub fn rate(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return f64::INFINITY;
+        }
+        self.count as f64 / secs
+    }
+    /// Checks that the expected number of messages has been received with at least the given
+    /// rate
+    pub fn check(&self, expected: usize, min_rate: f64) -> Result<(), String> {
+        if self.count < expected {
+            return Err(format!(
+                "channel closed after {} of {} messages",
+                self.count, expected
+            ));
+        }
+        let rate = self.rate();
+        if rate < min_rate {
+            return Err(format!(
+                "throughput {:.1} msg/s is below {:.1} msg/s ({} messages in {:?})",
+                rate, min_rate, self.count, self.elapsed
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Collects `n` intervals between `n + 1` calls of the receive function. Collection stops earlier
+/// if the function returns false (e.g. the channel is closed)
+pub fn collect_intervals<F: FnMut() -> bool>(mut recv: F, n: usize) -> TimingStats {
+    let mut samples = Vec::with_capacity(n);
+    if !recv() {
+        return TimingStats::default();
+    }
+    let mut prev = Instant::now();
+    while samples.len() < n && recv() {
+        let now = Instant::now();
+        samples.push(now - prev);
+        prev = now;
+    }
+    samples.into()
+}
+
+/// Measures the time required to receive `n` messages. The measurement starts after the first
+/// message is received and stops earlier if the receive function returns false
+pub fn measure_throughput<F: FnMut() -> bool>(mut recv: F, n: usize) -> Throughput {
+    if !recv() {
+        return Throughput {
+            count: 0,
+            elapsed: Duration::ZERO,
+        };
+    }
+    let start = Instant::now();
+    let mut count = 1;
+    while count < n && recv() {
+        count += 1;
+    }
+    Throughput {
+        count,
+        elapsed: start.elapsed(),
+    }
+}
+
+/// Checks that at least the given quantile of `n` expected samples is within the tolerance of
+/// the period. The error contains the statistics and a histogram
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss
+)]
+pub fn check_period(
+    stats: &TimingStats,
+    period: Duration,
+    tolerance: Duration,
+    n: usize,
+    quantile: f64,
+) -> Result<(), String> {
+    if stats.len() < n {
+        return Err(format!(
+            "channel closed after {} of {} intervals",
+            stats.len(),
+            n
+        ));
+    }
+    let required = (n as f64 * quantile.clamp(0.0, 1.0)).ceil() as usize;
+    let within = stats.count_within(period, tolerance);
+    if within < required {
+        return Err(format!(
+            "{} of {} intervals are outside of {:?} ± {:?} (allowed: {})\n{}\n{}",
+            stats.len() - within,
+            stats.len(),
+            period,
+            tolerance,
+            n - required,
+            stats,
+            stats.histogram(10)
+        ));
+    }
+    Ok(())
+}
+
+/// Timing report of [`Harness::run_periodic()`]
+#[derive(Clone, Debug)]
+pub struct TimingReport {
+    /// Intervals between cycle starts
+    pub intervals: TimingStats,
+    /// Cycle execution times
+    pub execution: TimingStats,
+    /// Number of cycles, which have not been finished within the period
+    pub overruns: usize,
+}
+
+impl fmt::Display for TimingReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "intervals: {}", self.intervals)?;
+        writeln!(f, "execution: {}", self.execution)?;
+        write!(f, "overruns: {}", self.overruns)
+    }
+}
+
+/// Runs cycle functions and workers in simulated mode (see
+/// [`crate::thread_rt::set_simulated()`]) and collects their timing distributions
+#[cfg(target_os = "linux")]
+#[derive(Clone, Debug)]
+pub struct Harness {
+    cycles: usize,
+    warmup: usize,
+}
+
+#[cfg(target_os = "linux")]
+impl Harness {
+    /// Creates a new harness which measures the given number of cycles
+    pub fn new(cycles: usize) -> Self {
+        Self { cycles, warmup: 0 }
+    }
+    /// Sets the number of cycles to skip before measuring (the default is 0)
+    pub fn warmup(mut self, cycles: usize) -> Self {
+        self.warmup = cycles;
+        self
+    }
+    /// Runs the cycle function in the current thread with the given period
+    pub fn run_periodic<F: FnMut()>(&self, period: Duration, mut f: F) -> TimingReport {
+        crate::thread_rt::set_simulated();
+        let mut interval = crate::time::interval(period);
+        let mut intervals = Vec::with_capacity(self.cycles);
+        let mut execution = Vec::with_capacity(self.cycles);
+        let mut overruns = 0;
+        let mut prev: Option<Instant> = None;
+        for n in 0..self.warmup + self.cycles {
+            let tick = interval.tick_info();
+            f();
+            if n < self.warmup {
+                continue;
+            }
+            let elapsed = tick.actual.elapsed();
+            if elapsed > period {
+                overruns += 1;
+            }
+            execution.push(elapsed);
+            if let Some(prev) = prev {
+                intervals.push(tick.actual - prev);
+            }
+            prev = Some(tick.actual);
+        }
+        TimingReport {
+            intervals: intervals.into(),
+            execution: execution.into(),
+            overruns,
+        }
+    }
+    /// Spawns the worker in a new controller and collects intervals between hub messages which
+    /// match the condition. The controller is terminated after the measurement, the worker must
+    /// exit its loop when [`crate::controller::Context::is_online()`] returns false
+    pub fn run_worker<W, D, V, C>(
+        &self,
+        worker: W,
+        variables: V,
+        condition: C,
+    ) -> crate::Result<TimingStats>
+    where
+        W: crate::controller::Worker<D, V> + crate::controller::WorkerOptions + 'static,
+        D: crate::DataDeliveryPolicy + Clone + Send + Sync + 'static,
+        V: Send + Sync + 'static,
+        C: Fn(&D) -> bool + Send + Sync + 'static,
+    {
+        crate::thread_rt::set_simulated();
+        let mut controller = crate::controller::Controller::<D, V>::new_with_variables(variables);
+        let client = controller.hub().register("testing.harness", condition)?;
+        controller.spawn_worker(worker)?;
+        for _ in 0..self.warmup {
+            client.recv()?;
+        }
+        let stats = collect_intervals(|| client.recv().is_ok(), self.cycles);
+        controller.terminate();
+        drop(client);
+        controller.block();
+        Ok(stats)
+    }
+}
+
+/// Scripted hub traffic and the cycle limit of [`WorkerHarness::run()`], executed by
+/// [`crate::controller::Context::cycle_span()`]
+#[cfg(target_os = "linux")]
+pub(crate) struct Script<D> {
+    limit: u64,
+    // sorted by cycle
+    messages: parking_lot_rt::Mutex<VecDeque<(u64, D)>>,
+    injecting: Arc<AtomicBool>,
+}
+
+#[cfg(target_os = "linux")]
+impl<D> Script<D>
+where
+    D: crate::DataDeliveryPolicy + Clone,
+{
+    // sends messages scheduled up to the cycle, returns true if the cycle limit is reached
+    pub(crate) fn on_cycle(&self, cycle: u64, hub: &crate::hub::Hub<D>) -> bool {
+        let mut messages = self.messages.lock();
+        self.injecting.store(true, Ordering::SeqCst);
+        while messages.front().map_or(false, |(c, _)| *c <= cycle) {
+            let (_, message) = messages.pop_front().unwrap();
+            hub.send(message);
+        }
+        self.injecting.store(false, Ordering::SeqCst);
+        cycle + 1 >= self.limit
+    }
+}
+
+/// Runs a worker for a given number of cycles in the current test, with scripted hub traffic
+/// and inspectable results: messages published to the hub and changes of shared variables.
+/// Simulated mode (see [`crate::thread_rt::set_simulated()`]) is enabled automatically, use
+/// [`crate::time::set_time_scale()`] to accelerate workers which wait for intervals or timers.
+///
+/// Cycles are counted by [`crate::controller::Context::cycle_span()`], the worker must call it
+/// at the beginning of each cycle and exit its loop when
+/// [`crate::controller::Context::is_online()`] returns false.
+///
+/// ```rust,no_run
+/// use roboplc::prelude::*;
+/// use roboplc::testing::WorkerHarness;
+///
+/// #[derive(DataPolicy, Clone, Debug, PartialEq)]
+/// enum Message {
+///     Setpoint(u32),
+///     Output(u32),
+/// }
+///
+/// #[derive(Default, Clone)]
+/// struct Variables {
+///     setpoint: u32,
+/// }
+///
+/// #[derive(WorkerOpts)]
+/// struct Regulator {}
+///
+/// impl Worker<Message, Variables> for Regulator {
+///     fn run(&mut self, context: &Context<Message, Variables>) -> WResult {
+///         let client = context
+///             .hub()
+///             .register("regulator", event_matches!(Message::Setpoint(_)))?;
+///         while context.is_online() {
+///             let _span = context.cycle_span();
+///             while let Ok(Message::Setpoint(v)) = client.try_recv() {
+///                 context.variables().write().setpoint = v;
+///             }
+///             let setpoint = context.variables().read().setpoint;
+///             context.hub().send(Message::Output(setpoint / 2));
+///         }
+///         Ok(())
+///     }
+/// }
+///
+/// let mut harness = WorkerHarness::<Message, Variables>::new(Variables::default()).unwrap();
+/// harness.inject(1, Message::Setpoint(100));
+/// assert_eq!(harness.run(&mut Regulator {}, 3).unwrap(), 3);
+/// assert_eq!(
+///     harness.published(),
+///     [Message::Output(0), Message::Output(50), Message::Output(50)]
+/// );
+/// assert_eq!(harness.changed(|v| v.setpoint), Some((0, 100)));
+/// ```
+#[cfg(target_os = "linux")]
+pub struct WorkerHarness<D, V>
+where
+    D: crate::DataDeliveryPolicy + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    controller: crate::controller::Controller<D, V>,
+    spy: crate::hub::Client<D>,
+    injecting: Arc<AtomicBool>,
+    script: Vec<(u64, D)>,
+    published: Vec<D>,
+    before: Option<V>,
+}
+
+#[cfg(target_os = "linux")]
+impl<D, V> WorkerHarness<D, V>
+where
+    D: crate::DataDeliveryPolicy + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    /// Creates a new harness with a dedicated controller and the given shared variables
+    pub fn new(variables: V) -> crate::Result<Self> {
+        crate::thread_rt::set_simulated();
+        let controller = crate::controller::Controller::new_with_variables(variables);
+        let injecting: Arc<AtomicBool> = <_>::default();
+        let spy_injecting = injecting.clone();
+        let spy = controller
+            .hub()
+            .register("testing.worker_harness", move |_| {
+                !spy_injecting.load(Ordering::SeqCst)
+            })?;
+        Ok(Self {
+            controller,
+            spy,
+            injecting,
+            script: Vec::new(),
+            published: Vec::new(),
+            before: None,
+        })
+    }
+    /// The hub of the harness controller, e.g. to register additional clients
+    pub fn hub(&self) -> &crate::hub::Hub<D> {
+        self.controller.hub()
+    }
+    /// Shared variables (locked)
+    pub fn variables(&self) -> &Arc<parking_lot_rt::RwLock<V>> {
+        self.controller.variables()
+    }
+    /// Modifies shared variables before the next run
+    pub fn set_variables<F: FnOnce(&mut V)>(&self, f: F) {
+        f(&mut self.controller.variables().write());
+    }
+    /// Schedules a hub message to be sent at the beginning of the given cycle (zero-based) of the
+    /// next run, after the worker has registered its hub clients. Injected messages are not
+    /// collected as published ones
+    pub fn inject(&mut self, cycle: u64, message: D) {
+        self.script.push((cycle, message));
+    }
+    /// Runs the worker until the given number of cycles is started (at least one) or the worker
+    /// exits. Returns the number of cycles started. The worker is executed in a scoped thread,
+    /// messages it publishes are collected meanwhile
+    ///
+    /// # Panics
+    ///
+    /// Panics if the worker panics
+    pub fn run<W>(&mut self, worker: &mut W, cycles: u64) -> crate::Result<u64>
+    where
+        W: crate::controller::Worker<D, V>,
+    {
+        self.before = Some(self.controller.variables().read().clone());
+        let mut messages = mem::take(&mut self.script);
+        messages.sort_by_key(|(cycle, _)| *cycle);
+        let script = Arc::new(Script {
+            limit: cycles,
+            messages: parking_lot_rt::Mutex::new(messages.into()),
+            injecting: self.injecting.clone(),
+        });
+        let context = self.controller.scripted_context(script);
+        let result = thread::scope(|scope| {
+            let handle = scope.spawn(|| worker.run(&context).map_err(crate::Error::failed));
+            while !handle.is_finished() {
+                self.collect();
+                thread::sleep(COLLECT_INTERVAL);
+            }
+            handle.join()
+        });
+        self.collect();
+        match result {
+            Ok(result) => result.map(|()| context.cycles()),
+            Err(e) => std::panic::resume_unwind(e),
+        }
+    }
+    /// Messages published to the hub since the harness creation or the last
+    /// [`WorkerHarness::take_published()`] call
+    pub fn published(&self) -> &[D] {
+        &self.published
+    }
+    /// Takes collected published messages
+    pub fn take_published(&mut self) -> Vec<D> {
+        mem::take(&mut self.published)
+    }
+    /// Returns the value before and after the last run if it has been changed
+    pub fn changed<T, F>(&self, f: F) -> Option<(T, T)>
+    where
+        T: PartialEq,
+        F: Fn(&V) -> T,
+    {
+        let before = f(self.before.as_ref()?);
+        let after = f(&self.controller.variables().read());
+        (before != after).then_some((before, after))
+    }
+    fn collect(&mut self) {
+        while let Ok(message) = self.spy.try_recv() {
+            self.published.push(message);
+        }
+    }
+}
+
+fn abs_diff(a: Duration, b: Duration) -> Duration {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_period, TimingStats};
+    use std::time::Duration;
+
+    #[cfg(target_os = "linux")]
+    mod worker {
+        use super::super::WorkerHarness;
+        use crate::controller::{Context, WResult, Worker};
+        use crate::DataDeliveryPolicy;
+
+        #[derive(Clone, Debug, PartialEq)]
+        enum Message {
+            Add(u32),
+            Total(u32),
+        }
+
+        impl DataDeliveryPolicy for Message {}
+
+        struct Adder;
+
+        impl Worker<Message, u32> for Adder {
+            fn run(&mut self, context: &Context<Message, u32>) -> WResult {
+                let client = context
+                    .hub()
+                    .register("adder", |m| matches!(m, Message::Add(_)))?;
+                while context.is_online() {
+                    let _span = context.cycle_span();
+                    while let Ok(Message::Add(v)) = client.try_recv() {
+                        *context.variables().write() += v;
+                    }
+                    context
+                        .hub()
+                        .send(Message::Total(*context.variables().read()));
+                }
+                Ok(())
+            }
+        }
+
+        #[test]
+        fn test_worker_harness() {
+            let mut harness = WorkerHarness::new(1).unwrap();
+            harness.inject(1, Message::Add(2));
+            harness.inject(1, Message::Add(3));
+            assert_eq!(harness.run(&mut Adder, 3).unwrap(), 3);
+            assert_eq!(
+                harness.take_published(),
+                [Message::Total(1), Message::Total(6), Message::Total(6)]
+            );
+            assert_eq!(harness.changed(|v| *v), Some((1, 6)));
+            assert_eq!(harness.run(&mut Adder, 1).unwrap(), 1);
+            assert_eq!(harness.published(), [Message::Total(6)]);
+            assert_eq!(harness.changed(|v| *v), None);
+        }
+    }
+
+    #[test]
+    fn test_timing_stats() {
+        let stats: TimingStats = (1..=100)
+            .map(|n| Duration::from_micros(950 + n))
+            .collect::<Vec<_>>()
+            .into();
+        assert_eq!(stats.min(), Duration::from_micros(951));
+        assert_eq!(stats.max(), Duration::from_micros(1050));
+        assert_eq!(
+            stats.jitter(Duration::from_millis(1)),
+            Duration::from_micros(50)
+        );
+        assert_eq!(stats.histogram(10).counts, vec![10; 10]);
+        let period = Duration::from_millis(1);
+        assert!(check_period(&stats, period, Duration::from_micros(50), 100, 1.0).is_ok());
+        assert!(check_period(&stats, period, Duration::from_micros(40), 100, 1.0).is_err());
+        assert!(check_period(&stats, period, Duration::from_micros(40), 100, 0.8).is_ok());
+        assert!(check_period(&stats, period, Duration::from_micros(50), 101, 1.0).is_err());
+    }
+}
@@ -25,7 +25,7 @@ pub fn set_simulated() {
     REALTIME_MODE.store(false, Ordering::Relaxed);
 }
 
-fn is_realtime() -> bool {
+pub(crate) fn is_realtime() -> bool {
     REALTIME_MODE.load(Ordering::Relaxed)
 }
 
@@ -0,0 +1,408 @@
+//!
+//! Time tools. Extends [`rtsc::time`] with a drift-tolerant [`Interval`] which has got a
+//! configurable catch-up policy for overrun cycles and reports scheduled-vs-actual tick times.
+//!
+//! For integration tests of slow processes (e.g. day-long batches) the program time can be
+//! accelerated with [`set_time_scale()`] in simulated mode. [`Interval`], the scheduler and code
+//! which uses [`now()`], [`sleep()`], [`monotonic_now()`] and [`timestamp_now()`] consume the
+//! scaled clock. Note that [`rtsc`] primitives (e.g. `TtlCell`) and [`Instant::now()`] calls
+//! outside of this module keep using the real clock.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bma_ts::{Monotonic, Timestamp};
+
+use crate::{Error, Result};
+
+pub use rtsc::time::*;
+
+static SCALED: AtomicBool = AtomicBool::new(false);
+
+static SCALED_CLOCK: RwLock<Option<ScaledClock>> = RwLock::new(None);
+
+#[derive(Clone, Copy, Debug)]
+struct ScaledClock {
+    scale: f64,
+    // the real time of the anchor
+    real: Instant,
+    // scaled times at the anchor
+    instant: Instant,
+    monotonic: Monotonic,
+    timestamp: Timestamp,
+}
+
+impl ScaledClock {
+    fn elapsed(&self, real_now: Instant) -> Duration {
+        real_now
+            .saturating_duration_since(self.real)
+            .mul_f64(self.scale)
+    }
+    fn instant(&self, real_now: Instant) -> Instant {
+        self.instant + self.elapsed(real_now)
+    }
+    fn monotonic(&self, real_now: Instant) -> Monotonic {
+        Monotonic::from_nanos(add_nanos(self.monotonic.as_nanos(), self.elapsed(real_now)))
+    }
+    fn timestamp(&self, real_now: Instant) -> Timestamp {
+        Timestamp::from_nanos(add_nanos(self.timestamp.as_nanos(), self.elapsed(real_now)))
+    }
+    // re-anchors the clock at the current scaled time, so the time stays continuous
+    fn rescale(prev: Option<&ScaledClock>, scale: f64, real_now: Instant) -> Self {
+        if let Some(prev) = prev {
+            Self {
+                scale,
+                real: real_now,
+                instant: prev.instant(real_now),
+                monotonic: prev.monotonic(real_now),
+                timestamp: prev.timestamp(real_now),
+            }
+        } else {
+            Self {
+                scale,
+                real: real_now,
+                instant: real_now,
+                monotonic: Monotonic::now(),
+                timestamp: Timestamp::now(),
+            }
+        }
+    }
+}
+
+fn add_nanos<N: Into<u128>>(nanos: N, elapsed: Duration) -> u64 {
+    u64::try_from(nanos.into() + elapsed.as_nanos()).unwrap_or(u64::MAX)
+}
+
+fn scaled_clock() -> Option<ScaledClock> {
+    if SCALED.load(Ordering::Relaxed) {
+        *SCALED_CLOCK.read().unwrap()
+    } else {
+        None
+    }
+}
+
+/// Sets the program time scale (e.g. 60.0 to run one program hour per real minute), 1.0 returns
+/// to the real time. The time stays continuous when the scale is changed. Should be called at
+/// the program start, before workers are spawned.
+///
+/// Available in simulated mode only (see [`crate::thread_rt::set_simulated()`])
+///
+/// # Panics
+///
+/// Will panic if the internal lock is poisoned
+pub fn set_time_scale(scale: f64) -> Result<()> {
+    if !(scale.is_finite() && scale > 0.0) {
+        return Err(Error::invalid_data(format!(
+            "invalid time scale: {}",
+            scale
+        )));
+    }
+    if !is_simulated() {
+        return Err(Error::failed(
+            "time acceleration is available in simulated mode only",
+        ));
+    }
+    let mut clock = SCALED_CLOCK.write().unwrap();
+    #[allow(clippy::float_cmp)]
+    let real_time = scale == 1.0 && clock.is_none();
+    if !real_time {
+        clock.replace(ScaledClock::rescale(clock.as_ref(), scale, Instant::now()));
+        SCALED.store(true, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn is_simulated() -> bool {
+    !crate::thread_rt::is_realtime()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_simulated() -> bool {
+    true
+}
+
+/// Returns the program time scale (1.0 for the real time)
+pub fn time_scale() -> f64 {
+    scaled_clock().map_or(1.0, |c| c.scale)
+}
+
+/// Returns the current program time instant (scaled if the time is accelerated)
+pub fn now() -> Instant {
+    let real_now = Instant::now();
+    scaled_clock().map_or(real_now, |c| c.instant(real_now))
+}
+
+/// Returns the current program monotonic time (scaled if the time is accelerated)
+pub fn monotonic_now() -> Monotonic {
+    scaled_clock().map_or_else(Monotonic::now, |c| c.monotonic(Instant::now()))
+}
+
+/// Returns the current program wall-clock time (scaled if the time is accelerated), should be
+/// used for timestamps of recorded data
+pub fn timestamp_now() -> Timestamp {
+    scaled_clock().map_or_else(Timestamp::now, |c| c.timestamp(Instant::now()))
+}
+
+/// Sleeps for the given program time duration (shortened if the time is accelerated)
+pub fn sleep(duration: Duration) {
+    match scaled_clock() {
+        Some(c) => thread::sleep(duration.div_f64(c.scale)),
+        None => thread::sleep(duration),
+    }
+}
+
+/// Creates a new interval with the default [`CatchUpPolicy::Burst`] policy
+pub fn interval(period: Duration) -> Interval {
+    Interval::new(period)
+}
+
+/// Behavior of [`Interval`] when one or more ticks are missed (e.g. a cycle overruns or the
+/// thread is stalled)
+#[derive(Default, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CatchUpPolicy {
+    /// Missed ticks are fired immediately one by one until the interval catches up (the
+    /// default). Keeps the number of ticks but may cause bursts of short cycles
+    #[default]
+    Burst,
+    /// Missed ticks are skipped, the next tick is scheduled one period after the current time.
+    /// The phase is shifted
+    Skip,
+    /// Missed ticks are skipped, the next tick is scheduled at the nearest point of the original
+    /// time grid. Keeps the phase, recommended for control loops
+    PhaseLock,
+}
+
+/// Tick information
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Tick {
+    /// The time the tick has been scheduled at
+    pub scheduled: Instant,
+    /// The time the tick has actually happened
+    pub actual: Instant,
+    /// Number of ticks skipped before this one ([`CatchUpPolicy::Skip`] and
+    /// [`CatchUpPolicy::PhaseLock`] only)
+    pub skipped: u32,
+}
+
+impl Tick {
+    /// The tick delay (actual - scheduled)
+    pub fn lag(&self) -> Duration {
+        self.actual.saturating_duration_since(self.scheduled)
+    }
+    /// Returns true if the tick is late for more than the given tolerance or ticks have been
+    /// skipped
+    pub fn is_late(&self, tolerance: Duration) -> bool {
+        self.skipped > 0 || self.lag() > tolerance
+    }
+}
+
+/// Periodic interval
+#[derive(Clone, Debug)]
+pub struct Interval {
+    period: Duration,
+    policy: CatchUpPolicy,
+    // the time grid origin, used by the phase-lock policy
+    origin: Option<Instant>,
+    next: Option<Instant>,
+    last_tick: Option<Tick>,
+}
+
+impl Interval {
+    /// # Panics
+    ///
+    /// Will panic if the period is zero
+    pub fn new(period: Duration) -> Self {
+        assert!(!period.is_zero(), "interval period must be non-zero");
+        Self {
+            period,
+            policy: CatchUpPolicy::default(),
+            origin: None,
+            next: None,
+            last_tick: None,
+        }
+    }
+    /// Sets the catch-up policy (the default is [`CatchUpPolicy::Burst`])
+    pub fn catch_up_policy(mut self, policy: CatchUpPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+    /// Changes the catch-up policy of an existing interval
+    pub fn set_catch_up_policy(&mut self, policy: CatchUpPolicy) {
+        self.policy = policy;
+    }
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+    /// Resets the interval, the next tick happens immediately and starts a new time grid
+    pub fn reset(&mut self) {
+        self.origin = None;
+        self.next = None;
+        self.last_tick = None;
+    }
+    /// Waits for the next tick. Returns false if the tick is late for more than one period or
+    /// ticks have been skipped. The first tick happens immediately
+    pub fn tick(&mut self) -> bool {
+        let tick = self.tick_info();
+        !tick.is_late(self.period)
+    }
+    /// Waits for the next tick and returns its scheduled and actual times, which can be used by
+    /// loops to compensate integrators etc.
+    pub fn tick_info(&mut self) -> Tick {
+        let now = self::now();
+        let Some(mut scheduled) = self.next else {
+            self.origin = Some(now);
+            self.next = Some(now + self.period);
+            let tick = Tick {
+                scheduled: now,
+                actual: now,
+                skipped: 0,
+            };
+            self.last_tick = Some(tick);
+            return tick;
+        };
+        let mut skipped = 0;
+        if now > scheduled + self.period {
+            // at least one tick missed
+            match self.policy {
+                CatchUpPolicy::Burst => {}
+                CatchUpPolicy::Skip => {
+                    skipped = missed_ticks(now - scheduled, self.period);
+                    scheduled = now;
+                }
+                CatchUpPolicy::PhaseLock => {
+                    let n = missed_ticks(now - scheduled, self.period);
+                    skipped = n;
+                    scheduled += self.period * n;
+                }
+            }
+        }
+        let now = self::now();
+        if scheduled > now {
+            sleep(scheduled - now);
+        }
+        let actual = self::now();
+        self.next = Some(scheduled + self.period);
+        let tick = Tick {
+            scheduled,
+            actual,
+            skipped,
+        };
+        self.last_tick = Some(tick);
+        tick
+    }
+    /// Information about the last tick
+    pub fn last_tick(&self) -> Option<Tick> {
+        self.last_tick
+    }
+    /// The time grid origin (the first tick time)
+    pub fn origin(&self) -> Option<Instant> {
+        self.origin
+    }
+    /// Shifts the time grid (the origin and the next tick) by the given number of nanoseconds
+    /// (negative values shift it backward). Used to align the phase with an external reference
+    /// (see [`crate::cycle_sync`])
+    pub fn shift_phase(&mut self, nanos: i64) {
+        let shift = |t: Instant| {
+            let d = Duration::from_nanos(nanos.unsigned_abs());
+            if nanos >= 0 {
+                t + d
+            } else {
+                t.checked_sub(d).unwrap_or(t)
+            }
+        };
+        self.origin = self.origin.map(shift);
+        self.next = self.next.map(shift);
+    }
+}
+
+/// Iterating an interval waits for the next tick, the items are [`Interval::tick()`] results
+impl Iterator for Interval {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<bool> {
+        Some(self.tick())
+    }
+}
+
+// number of whole periods in the lag
+fn missed_ticks(lag: Duration, period: Duration) -> u32 {
+    u32::try_from(lag.as_nanos() / period.as_nanos()).unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{interval, CatchUpPolicy, ScaledClock};
+    use bma_ts::{Monotonic, Timestamp};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_interval_phase_lock() {
+        let period = Duration::from_millis(10);
+        let mut int = interval(period).catch_up_policy(CatchUpPolicy::PhaseLock);
+        let first = int.tick_info();
+        thread::sleep(Duration::from_millis(35));
+        let tick = int.tick_info();
+        assert!(tick.skipped >= 2);
+        // the tick stays on the original time grid
+        let offset = tick.scheduled - first.scheduled;
+        assert_eq!(offset.as_nanos() % period.as_nanos(), 0);
+        assert!(tick.actual >= tick.scheduled);
+        let next = int.tick_info();
+        assert_eq!(next.scheduled - tick.scheduled, period);
+    }
+
+    #[test]
+    fn test_interval_burst() {
+        let period = Duration::from_millis(10);
+        let mut int = interval(period);
+        int.tick();
+        thread::sleep(Duration::from_millis(35));
+        // missed ticks are fired immediately
+        assert!(!int.tick());
+        let tick = int.tick_info();
+        assert_eq!(tick.skipped, 0);
+        assert!(tick.lag() > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_interval_iter() {
+        let period = Duration::from_millis(5);
+        let started = Instant::now();
+        let mut ticks = 0;
+        for _ in interval(period).take(3) {
+            ticks += 1;
+        }
+        assert_eq!(ticks, 3);
+        // the first tick happens immediately
+        assert!(started.elapsed() >= period * 2);
+    }
+
+    #[test]
+    fn test_scaled_clock() {
+        let real = Instant::now();
+        let clock = ScaledClock {
+            scale: 60.0,
+            real,
+            instant: real,
+            monotonic: Monotonic::from_nanos(1_000),
+            timestamp: Timestamp::from_nanos(2_000),
+        };
+        let real_now = real + Duration::from_secs(2);
+        assert_eq!(clock.instant(real_now) - real, Duration::from_secs(120));
+        assert_eq!(
+            clock.monotonic(real_now),
+            Monotonic::from_nanos(120_000_001_000)
+        );
+        // the time stays continuous when the scale is changed
+        let clock = ScaledClock::rescale(Some(&clock), 2.0, real_now);
+        let real_now = real_now + Duration::from_secs(1);
+        assert_eq!(clock.instant(real_now) - real, Duration::from_secs(122));
+        assert_eq!(
+            clock.timestamp(real_now),
+            Timestamp::from_nanos(122_000_002_000)
+        );
+    }
+}
@@ -0,0 +1,288 @@
+//!
+//! Wall-clock reconciliation of monotonic timestamps.
+//!
+//! Machines without NTP record events with [`Monotonic`] timestamps, which can not be correlated
+//! with the wall time (and with other nodes) later. [`AnchorLog`] keeps periodic anchor points
+//! `(Monotonic, Timestamp, quality)`, which are stored together with recorded data and used to
+//! convert monotonic timestamps into wall-clock ones during export/post-hoc analysis.
+//!
+//! Monotonic timestamps are reset on reboot, so an anchor log is valid for a single boot only.
+//!
+//! ```rust
+//! use roboplc::wallclock::{AnchorLog, TimeQuality};
+//! use roboplc::prelude::Monotonic;
+//!
+//! let mut anchors = AnchorLog::new();
+//! anchors.record(TimeQuality::Rtc);
+//! let event_time = Monotonic::now();
+//! let wall_time = anchors.to_timestamp(event_time).unwrap();
+//! ```
+use bma_ts::{Monotonic, Timestamp};
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+const DEFAULT_MAX_ANCHORS: usize = 10_000;
+
+/// Quality of the wall-clock source at the moment an anchor has been recorded
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeQuality {
+    /// The system clock is not synchronized and has not been set
+    Unsynchronized = 0,
+    /// The system clock has been set from the hardware real-time clock
+    Rtc = 1,
+    /// The system clock has been set manually (e.g. by an operator)
+    Manual = 2,
+    /// The system clock is synchronized with NTP
+    Ntp = 3,
+    /// The system clock is synchronized with PTP or GNSS
+    Precise = 4,
+}
+
+/// Anchor point
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TimeAnchor {
+    pub monotonic: Monotonic,
+    pub timestamp: Timestamp,
+    pub quality: TimeQuality,
+}
+
+impl TimeAnchor {
+    /// Creates an anchor for the current time
+    pub fn now(quality: TimeQuality) -> Self {
+        Self {
+            monotonic: Monotonic::now(),
+            timestamp: Timestamp::now(),
+            quality,
+        }
+    }
+    fn mono_nanos(&self) -> i128 {
+        nanos(self.monotonic.as_nanos())
+    }
+    fn ts_nanos(&self) -> i128 {
+        nanos(self.timestamp.as_nanos())
+    }
+}
+
+fn nanos<N: Into<u128>>(value: N) -> i128 {
+    i128::try_from(value.into()).unwrap_or(i128::MAX)
+}
+
+/// Anchor log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnchorLog {
+    anchors: Vec<TimeAnchor>,
+    #[serde(skip, default = "default_max_anchors")]
+    max_anchors: usize,
+    #[serde(skip, default = "default_min_quality")]
+    min_quality: TimeQuality,
+}
+
+fn default_max_anchors() -> usize {
+    DEFAULT_MAX_ANCHORS
+}
+
+fn default_min_quality() -> TimeQuality {
+    TimeQuality::Unsynchronized
+}
+
+impl Default for AnchorLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AnchorLog {
+    pub fn new() -> Self {
+        Self {
+            anchors: Vec::new(),
+            max_anchors: DEFAULT_MAX_ANCHORS,
+            min_quality: TimeQuality::Unsynchronized,
+        }
+    }
+    /// Max number of anchors kept, the oldest ones are removed (the default is 10000)
+    pub fn max_anchors(mut self, max_anchors: usize) -> Self {
+        self.max_anchors = max_anchors.max(1);
+        self
+    }
+    /// Anchors of lower quality are ignored during conversions (the default is
+    /// [`TimeQuality::Unsynchronized`], all anchors are used)
+    pub fn min_quality(mut self, quality: TimeQuality) -> Self {
+        self.min_quality = quality;
+        self
+    }
+    /// Records an anchor for the current time
+    pub fn record(&mut self, quality: TimeQuality) -> TimeAnchor {
+        let anchor = TimeAnchor::now(quality);
+        // the current time is always the latest one, the error may be ignored
+        let _ = self.push(anchor);
+        anchor
+    }
+    /// Appends an anchor (e.g. received from another node). Anchors must be appended in the
+    /// monotonic order
+    pub fn push(&mut self, anchor: TimeAnchor) -> Result<()> {
+        if let Some(last) = self.anchors.last() {
+            if anchor.monotonic < last.monotonic {
+                return Err(Error::invalid_data("time anchors must be monotonic"));
+            }
+        }
+        if self.anchors.len() >= self.max_anchors {
+            let excess = self.anchors.len() + 1 - self.max_anchors;
+            self.anchors.drain(..excess);
+        }
+        self.anchors.push(anchor);
+        Ok(())
+    }
+    /// Recorded anchors
+    pub fn anchors(&self) -> &[TimeAnchor] {
+        &self.anchors
+    }
+    /// Removes all anchors
+    pub fn clear(&mut self) {
+        self.anchors.clear();
+    }
+    fn usable(&self) -> impl Iterator<Item = &TimeAnchor> {
+        let min_quality = self.min_quality;
+        self.anchors
+            .iter()
+            .filter(move |a| a.quality >= min_quality)
+    }
+    /// Converts a monotonic timestamp into the wall-clock one. Between two anchors the time is
+    /// interpolated linearly (so clock steps and drift are spread across the segment), outside of
+    /// the anchor range the offset of the nearest anchor is used. Returns `None` if there are no
+    /// usable anchors
+    pub fn to_timestamp(&self, monotonic: Monotonic) -> Option<Timestamp> {
+        let m = nanos(monotonic.as_nanos());
+        let mut before: Option<&TimeAnchor> = None;
+        let mut after: Option<&TimeAnchor> = None;
+        for anchor in self.usable() {
+            if anchor.mono_nanos() <= m {
+                before = Some(anchor);
+            } else {
+                after = Some(anchor);
+                break;
+            }
+        }
+        let t = match (before, after) {
+            (Some(a), Some(b)) => {
+                let (m0, m1) = (a.mono_nanos(), b.mono_nanos());
+                let (t0, t1) = (a.ts_nanos(), b.ts_nanos());
+                t0 + (m - m0) * (t1 - t0) / (m1 - m0)
+            }
+            (Some(a), None) | (None, Some(a)) => a.ts_nanos() + m - a.mono_nanos(),
+            (None, None) => return None,
+        };
+        Some(Timestamp::from_nanos(u64::try_from(t).ok()?))
+    }
+    /// Converts a wall-clock timestamp back into the monotonic one (e.g. to find recorded data
+    /// for a wall-clock time range)
+    pub fn to_monotonic(&self, timestamp: Timestamp) -> Option<Monotonic> {
+        let t = nanos(timestamp.as_nanos());
+        let mut before: Option<&TimeAnchor> = None;
+        let mut after: Option<&TimeAnchor> = None;
+        for anchor in self.usable() {
+            if anchor.ts_nanos() <= t {
+                before = Some(anchor);
+            } else {
+                after = Some(anchor);
+                break;
+            }
+        }
+        let m = match (before, after) {
+            (Some(a), Some(b)) if b.ts_nanos() > a.ts_nanos() => {
+                let (m0, m1) = (a.mono_nanos(), b.mono_nanos());
+                let (t0, t1) = (a.ts_nanos(), b.ts_nanos());
+                m0 + (t - t0) * (m1 - m0) / (t1 - t0)
+            }
+            (Some(a), _) | (None, Some(a)) => a.mono_nanos() + t - a.ts_nanos(),
+            (None, None) => return None,
+        };
+        Some(Monotonic::from_nanos(u64::try_from(m).ok()?))
+    }
+    /// Converts monotonic timestamps of exported records, the quality of the nearest anchor is
+    /// returned together with each timestamp
+    pub fn convert<'a, I>(
+        &'a self,
+        times: I,
+    ) -> impl Iterator<Item = Option<(Timestamp, TimeQuality)>> + 'a
+    where
+        I: IntoIterator<Item = Monotonic> + 'a,
+    {
+        times.into_iter().map(move |m| {
+            let ts = self.to_timestamp(m)?;
+            Some((ts, self.nearest_quality(m)?))
+        })
+    }
+    fn nearest_quality(&self, monotonic: Monotonic) -> Option<TimeQuality> {
+        let m = nanos(monotonic.as_nanos());
+        self.usable()
+            .min_by_key(|a| (a.mono_nanos() - m).abs())
+            .map(|a| a.quality)
+    }
+}
+
+/// Returns the quality of the system clock: [`TimeQuality::Ntp`] if the kernel reports the clock
+/// as synchronized (by NTP, chrony etc.), [`TimeQuality::Unsynchronized`] otherwise
+#[cfg(target_os = "linux")]
+pub fn system_clock_quality() -> TimeQuality {
+    // SAFETY: timex is a plain C struct, modes = 0 means read-only
+    let mut tx: libc::timex = unsafe { std::mem::zeroed() };
+    let state = unsafe { libc::adjtimex(&mut tx) };
+    if state < 0 || state == libc::TIME_ERROR {
+        TimeQuality::Unsynchronized
+    } else {
+        TimeQuality::Ntp
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bma_ts::{Monotonic, Timestamp};
+
+    use super::{AnchorLog, TimeAnchor, TimeQuality};
+
+    fn anchor(m: u64, t: u64, quality: TimeQuality) -> TimeAnchor {
+        TimeAnchor {
+            monotonic: Monotonic::from_nanos(m),
+            timestamp: Timestamp::from_nanos(t),
+            quality,
+        }
+    }
+
+    #[test]
+    fn test_anchors() {
+        let mut log = AnchorLog::new().max_anchors(3);
+        assert!(log.to_timestamp(Monotonic::from_nanos(1)).is_none());
+        log.push(anchor(1_000, 1_000_000, TimeQuality::Rtc))
+            .unwrap();
+        // the clock has been stepped by NTP
+        log.push(anchor(2_000, 1_002_000, TimeQuality::Ntp))
+            .unwrap();
+        assert!(log.push(anchor(1_500, 0, TimeQuality::Ntp)).is_err());
+        let ts = |m| log.to_timestamp(Monotonic::from_nanos(m)).unwrap();
+        assert_eq!(ts(500), Timestamp::from_nanos(999_500));
+        assert_eq!(ts(1_500), Timestamp::from_nanos(1_001_000));
+        assert_eq!(ts(3_000), Timestamp::from_nanos(1_003_000));
+        assert_eq!(
+            log.to_monotonic(Timestamp::from_nanos(1_001_000)),
+            Some(Monotonic::from_nanos(1_500))
+        );
+        let converted: Vec<_> = log
+            .convert([Monotonic::from_nanos(1_100), Monotonic::from_nanos(1_900)])
+            .map(|v| v.unwrap().1)
+            .collect();
+        assert_eq!(converted, [TimeQuality::Rtc, TimeQuality::Ntp]);
+        let mut log = log.min_quality(TimeQuality::Ntp);
+        assert_eq!(
+            log.to_timestamp(Monotonic::from_nanos(1_500)),
+            Some(Timestamp::from_nanos(1_001_500))
+        );
+        log.push(anchor(3_000, 1_003_000, TimeQuality::Ntp))
+            .unwrap();
+        log.push(anchor(4_000, 1_004_000, TimeQuality::Ntp))
+            .unwrap();
+        assert_eq!(log.anchors().len(), 3);
+        assert_eq!(log.anchors()[0].monotonic, Monotonic::from_nanos(2_000));
+    }
+}
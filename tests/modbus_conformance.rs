@@ -0,0 +1,206 @@
+//! Modbus TCP server protocol conformance tests. Run with:
+//!
+//! ```shell
+//! cargo test --features modbus-conformance --test modbus_conformance
+//! ```
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use roboplc::comm::Protocol;
+use roboplc::io::modbus::{ModbusServer, ModbusServerHandle};
+
+const UNIT: u8 = 1;
+const COILS: usize = 64;
+const HOLDINGS: usize = 100;
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+const ILLEGAL_FUNCTION: u8 = 0x01;
+const ILLEGAL_DATA_ADDRESS: u8 = 0x02;
+const ILLEGAL_DATA_VALUE: u8 = 0x03;
+
+fn start_server() -> (SocketAddr, ModbusServerHandle) {
+    let server = ModbusServer::<COILS, 16, 16, HOLDINGS>::bind(
+        Protocol::Tcp,
+        UNIT,
+        "127.0.0.1:0",
+        TIMEOUT,
+        16,
+    )
+    .unwrap();
+    let addr = server.local_addr().unwrap();
+    (addr, server.spawn().unwrap())
+}
+
+fn connect(addr: SocketAddr) -> TcpStream {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    stream.set_nodelay(true).unwrap();
+    stream
+}
+
+fn frame(tid: u16, pdu: &[u8]) -> Vec<u8> {
+    let mut frame = tid.to_be_bytes().to_vec();
+    frame.extend([0, 0]);
+    frame.extend(u16::try_from(pdu.len() + 1).unwrap().to_be_bytes());
+    frame.push(UNIT);
+    frame.extend(pdu);
+    frame
+}
+
+fn read_response(stream: &mut TcpStream) -> (u16, Vec<u8>) {
+    let mut header = [0u8; 7];
+    stream.read_exact(&mut header).unwrap();
+    assert_eq!(header[2..4], [0, 0], "invalid protocol id");
+    assert_eq!(header[6], UNIT, "invalid unit id");
+    let len = usize::from(u16::from_be_bytes([header[4], header[5]]));
+    let mut pdu = vec![0u8; len - 1];
+    stream.read_exact(&mut pdu).unwrap();
+    (u16::from_be_bytes([header[0], header[1]]), pdu)
+}
+
+fn request(stream: &mut TcpStream, pdu: &[u8]) -> Vec<u8> {
+    stream.write_all(&frame(0x1234, pdu)).unwrap();
+    let (tid, response) = read_response(stream);
+    assert_eq!(tid, 0x1234, "transaction id mismatch");
+    response
+}
+
+fn assert_exception(response: &[u8], func: u8, code: u8) {
+    assert_eq!(
+        response,
+        [func | 0x80, code],
+        "expected exception {:#x}",
+        code
+    );
+}
+
+fn read_holdings(reg: u16, count: u16) -> Vec<u8> {
+    let mut pdu = vec![0x03];
+    pdu.extend(reg.to_be_bytes());
+    pdu.extend(count.to_be_bytes());
+    pdu
+}
+
+#[test]
+fn illegal_function() {
+    let (addr, server) = start_server();
+    let mut stream = connect(addr);
+    for func in [0x07, 0x08, 0x11, 0x2b, 0x41] {
+        let response = request(&mut stream, &[func, 0, 0, 0, 1]);
+        assert_exception(&response, func, ILLEGAL_FUNCTION);
+    }
+    // the connection is still usable
+    assert_eq!(request(&mut stream, &read_holdings(0, 1))[..2], [0x03, 2]);
+    server.join().unwrap();
+}
+
+#[test]
+fn boundary_addresses() {
+    let (addr, server) = start_server();
+    let mut stream = connect(addr);
+    let last = u16::try_from(HOLDINGS - 1).unwrap();
+    assert_eq!(
+        request(&mut stream, &read_holdings(last, 1))[..2],
+        [0x03, 2]
+    );
+    let response = request(&mut stream, &read_holdings(last, 2));
+    assert_exception(&response, 0x03, ILLEGAL_DATA_ADDRESS);
+    let response = request(&mut stream, &read_holdings(0xffff, 1));
+    assert_exception(&response, 0x03, ILLEGAL_DATA_ADDRESS);
+    let response = request(&mut stream, &[0x06, 0, 100, 0, 1]);
+    assert_exception(&response, 0x06, ILLEGAL_DATA_ADDRESS);
+    let response = request(&mut stream, &[0x01, 0, 63, 0, 2]);
+    assert_exception(&response, 0x01, ILLEGAL_DATA_ADDRESS);
+    server.join().unwrap();
+}
+
+#[test]
+fn illegal_values() {
+    let (addr, server) = start_server();
+    let mut stream = connect(addr);
+    // zero and too large quantities
+    for count in [0, 126] {
+        let response = request(&mut stream, &read_holdings(0, count));
+        assert_exception(&response, 0x03, ILLEGAL_DATA_VALUE);
+    }
+    let response = request(&mut stream, &[0x01, 0, 0, 0x07, 0xd1]);
+    assert_exception(&response, 0x01, ILLEGAL_DATA_VALUE);
+    // invalid coil value
+    let response = request(&mut stream, &[0x05, 0, 0, 0x12, 0x34]);
+    assert_exception(&response, 0x05, ILLEGAL_DATA_VALUE);
+    // byte count mismatch
+    let response = request(&mut stream, &[0x10, 0, 0, 0, 2, 2, 0, 1, 0, 2]);
+    assert_exception(&response, 0x10, ILLEGAL_DATA_VALUE);
+    // truncated PDU
+    let response = request(&mut stream, &[0x03, 0]);
+    assert_exception(&response, 0x03, ILLEGAL_DATA_VALUE);
+    server.join().unwrap();
+}
+
+#[test]
+fn oversized_request() {
+    let (addr, server) = start_server();
+    let mut stream = connect(addr);
+    // write 125 holdings (more than allowed)
+    let mut pdu = vec![0x10, 0, 0, 0, 125, 250];
+    pdu.extend([0u8; 250]);
+    let response = request(&mut stream, &pdu);
+    assert_exception(&response, 0x10, ILLEGAL_DATA_VALUE);
+    assert_eq!(request(&mut stream, &read_holdings(0, 1))[..2], [0x03, 2]);
+    server.join().unwrap();
+}
+
+#[test]
+fn malformed_frames() {
+    let (addr, server) = start_server();
+    // invalid protocol id, the connection is closed
+    let mut stream = connect(addr);
+    let mut invalid = frame(1, &read_holdings(0, 1));
+    invalid[3] = 1;
+    stream.write_all(&invalid).unwrap();
+    let mut buf = [0u8; 1];
+    assert!(matches!(stream.read(&mut buf), Ok(0) | Err(_)));
+    // a request split into several segments
+    let mut stream = connect(addr);
+    let request = frame(2, &read_holdings(0, 2));
+    for chunk in request.chunks(3) {
+        stream.write_all(chunk).unwrap();
+        thread::sleep(Duration::from_millis(20));
+    }
+    let (tid, response) = read_response(&mut stream);
+    assert_eq!(tid, 2);
+    assert_eq!(response[..2], [0x03, 4]);
+    // pipelined requests in a single segment
+    let mut pipelined = frame(3, &read_holdings(0, 1));
+    pipelined.extend(frame(4, &read_holdings(1, 1)));
+    stream.write_all(&pipelined).unwrap();
+    assert_eq!(read_response(&mut stream).0, 3);
+    assert_eq!(read_response(&mut stream).0, 4);
+    server.join().unwrap();
+}
+
+#[test]
+fn concurrent_clients() {
+    let (addr, server) = start_server();
+    let clients: Vec<_> = (0..8u16)
+        .map(|n| {
+            thread::spawn(move || {
+                let mut stream = connect(addr);
+                for i in 0..100u16 {
+                    let value = n * 1000 + i;
+                    let mut write = vec![0x06, 0, u8::try_from(n).unwrap()];
+                    write.extend(value.to_be_bytes());
+                    assert_eq!(request(&mut stream, &write), write);
+                    let response = request(&mut stream, &read_holdings(n, 1));
+                    assert_eq!(response[2..], value.to_be_bytes());
+                }
+            })
+        })
+        .collect();
+    for client in clients {
+        client.join().unwrap();
+    }
+    server.join().unwrap();
+}